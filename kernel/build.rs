@@ -1,5 +1,78 @@
 //! Build script for `kernel`.
 
+use std::process::Command;
+
+/// The physical address at which the kernel is loaded when booting using Multiboot2.
+const MULTIBOOT2_KERNEL_BASE: u64 = 0x20_0000;
+
 fn main() {
+    emit_build_info();
+
+    // Host builds, such as `cargo test`, keep the host's default linker script and only need the
+    // symbols that the kernel's linker script would have defined.
+    if std::env::var("CARGO_CFG_TARGET_OS").as_deref() != Ok("none") {
+        println!("cargo::rustc-link-arg=-Wl,kernel/host_symbols.ld");
+        return;
+    }
+
     println!("cargo::rustc-link-arg=-Tkernel/linker_script.ld");
+    // Identifies the image so that addresses in backtraces can be symbolized offline.
+    println!("cargo::rustc-link-arg=--build-id=sha1");
+
+    if std::env::var_os("CARGO_FEATURE_MULTIBOOT2_BOOT_API").is_some() {
+        // Multiboot2 loads the kernel at its link address and stores the entry point as a 32-bit
+        // physical address, so the kernel cannot be position independent.
+        println!("cargo::rustc-link-arg=--no-pie");
+        println!("cargo::rustc-link-arg=--defsym=KERNEL_BASE={MULTIBOOT2_KERNEL_BASE:#x}");
+    }
+}
+
+/// Exposes the git commit and dirty flag, the build profile, and the rustc version of this build
+/// to the kernel's `build_info` module, using `unknown` for any that cannot be determined.
+fn emit_build_info() {
+    let commit = git(&["rev-parse", "HEAD"]);
+    let dirty = git(&["status", "--porcelain"])
+        .map(|status| if status.is_empty() { "false" } else { "true" }.to_owned());
+    let profile = std::env::var("PROFILE").ok();
+    let rustc_version = std::env::var("RUSTC")
+        .ok()
+        .and_then(|rustc| command_output(Command::new(rustc).arg("-V")));
+
+    for (name, value) in [
+        ("CAPORA_GIT_COMMIT", commit),
+        ("CAPORA_GIT_DIRTY", dirty),
+        ("CAPORA_BUILD_PROFILE", profile),
+        ("CAPORA_RUSTC_VERSION", rustc_version),
+    ] {
+        println!(
+            "cargo::rustc-env={name}={}",
+            value.as_deref().unwrap_or("unknown")
+        );
+    }
+
+    if let Some(git_dir) = git(&["rev-parse", "--absolute-git-dir"]) {
+        for path in ["HEAD", "index", "refs"] {
+            println!("cargo::rerun-if-changed={git_dir}/{path}");
+        }
+        // Naming any file disables cargo's default of rerunning whenever the package changes,
+        // which the dirty flag still depends on.
+        println!("cargo::rerun-if-changed=.");
+    }
+}
+
+/// Runs `git` with `args`, returning its trimmed output if it succeeds.
+fn git(args: &[&str]) -> Option<String> {
+    command_output(Command::new("git").args(args))
+}
+
+/// Runs `cmd`, returning its trimmed standard output if it succeeds.
+fn command_output(cmd: &mut Command) -> Option<String> {
+    let output = cmd.output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+
+    String::from_utf8(output.stdout)
+        .ok()
+        .map(|output| output.trim().to_owned())
 }
@@ -0,0 +1,537 @@
+//! Parsing of the Multiple APIC Description Table, which lists the processors and I/O APICs of
+//! the system and how legacy interrupts are routed to them.
+
+use core::fmt;
+
+use crate::acpi::{read_u32, AcpiError, Sdt};
+
+/// The signature of the MADT.
+pub const SIGNATURE: [u8; 4] = *b"APIC";
+
+/// The offset of the first entry from the start of the MADT's body.
+const ENTRIES_OFFSET: usize = 8;
+
+/// The Multiple APIC Description Table.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub struct Madt<'a> {
+    /// The physical address of the local APIC of every processor.
+    local_apic_address: u32,
+    /// The MADT flags.
+    flags: u32,
+    /// The bytes of the entries.
+    entries: &'a [u8],
+}
+
+impl<'a> Madt<'a> {
+    /// Set in [`Madt::flags()`] if the system also has legacy 8259 PICs.
+    pub const PCAT_COMPAT: u32 = 1 << 0;
+
+    /// Parses the MADT in `sdt`.
+    ///
+    /// # Errors
+    /// - [`AcpiError::UnexpectedSignature`] if `sdt` is not a MADT.
+    /// - [`AcpiError::Truncated`] if `sdt` is too short to hold the fixed fields of a MADT.
+    pub fn parse(sdt: Sdt<'a>) -> Result<Self, AcpiError> {
+        if sdt.header().signature() != SIGNATURE {
+            return Err(AcpiError::UnexpectedSignature {
+                expected: SIGNATURE,
+                found: sdt.header().signature(),
+            });
+        }
+
+        let body = sdt.body();
+        let truncated = AcpiError::Truncated {
+            signature: SIGNATURE,
+            length: (sdt.bytes().len() - body.len() + ENTRIES_OFFSET) as u32,
+            available: sdt.bytes().len(),
+        };
+        Ok(Self {
+            local_apic_address: read_u32(body, 0).ok_or(truncated)?,
+            flags: read_u32(body, 4).ok_or(truncated)?,
+            entries: body.get(ENTRIES_OFFSET..).ok_or(truncated)?,
+        })
+    }
+
+    /// Returns the physical address of the local APIC of every processor.
+    pub const fn local_apic_address(&self) -> u32 {
+        self.local_apic_address
+    }
+
+    /// Returns the MADT flags.
+    pub const fn flags(&self) -> u32 {
+        self.flags
+    }
+
+    /// Returns an [`Iterator`] over the entries of this [`Madt`].
+    ///
+    /// An entry too short for its type yields an error and is skipped. An entry whose length runs
+    /// past the end of the table, or is too short to hold its own header, yields an error and ends
+    /// the iteration, since the entries following it cannot be located.
+    pub fn entries(&self) -> MadtEntries<'a> {
+        MadtEntries {
+            bytes: self.entries,
+        }
+    }
+
+    /// Returns an [`Iterator`] over the processors listed by this [`Madt`], skipping invalid
+    /// entries.
+    pub fn local_apics(&self) -> impl Iterator<Item = LocalApic> + 'a {
+        self.entries().filter_map(|entry| match entry {
+            Ok(MadtEntry::LocalApic(local_apic)) => Some(local_apic),
+            _ => None,
+        })
+    }
+
+    /// Returns an [`Iterator`] over the I/O APICs listed by this [`Madt`], skipping invalid
+    /// entries.
+    pub fn io_apics(&self) -> impl Iterator<Item = IoApic> + 'a {
+        self.entries().filter_map(|entry| match entry {
+            Ok(MadtEntry::IoApic(io_apic)) => Some(io_apic),
+            _ => None,
+        })
+    }
+
+    /// Returns an [`Iterator`] over the interrupt source overrides listed by this [`Madt`],
+    /// skipping invalid entries.
+    pub fn interrupt_source_overrides(&self) -> impl Iterator<Item = InterruptSourceOverride> + 'a {
+        self.entries().filter_map(|entry| match entry {
+            Ok(MadtEntry::InterruptSourceOverride(iso)) => Some(iso),
+            _ => None,
+        })
+    }
+}
+
+/// An [`Iterator`] over the entries of a [`Madt`].
+#[derive(Clone, Debug)]
+pub struct MadtEntries<'a> {
+    /// The bytes of the entries that have not been yielded yet.
+    bytes: &'a [u8],
+}
+
+impl Iterator for MadtEntries<'_> {
+    type Item = Result<MadtEntry, MadtEntryError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let &[kind, length, ..] = self.bytes else {
+            if self.bytes.is_empty() {
+                return None;
+            }
+
+            let remaining = self.bytes.len();
+            self.bytes = &[];
+            return Some(Err(MadtEntryError::Truncated {
+                kind: None,
+                remaining,
+            }));
+        };
+
+        let length = usize::from(length);
+        if length < 2 || length > self.bytes.len() {
+            let remaining = self.bytes.len();
+            self.bytes = &[];
+            return Some(Err(MadtEntryError::Truncated {
+                kind: Some(kind),
+                remaining,
+            }));
+        }
+
+        let (entry, rest) = self.bytes.split_at(length);
+        self.bytes = rest;
+        Some(MadtEntry::parse(kind, entry))
+    }
+}
+
+/// An entry of a [`Madt`].
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum MadtEntry {
+    /// A processor and its local APIC, from either a local APIC or a local x2APIC entry.
+    LocalApic(LocalApic),
+    /// An I/O APIC.
+    IoApic(IoApic),
+    /// A legacy ISA interrupt that is not identity mapped to a global system interrupt.
+    InterruptSourceOverride(InterruptSourceOverride),
+    /// An entry of a type that is not interpreted.
+    Other {
+        /// The type of the entry.
+        kind: u8,
+    },
+}
+
+impl MadtEntry {
+    /// The type of a processor local APIC entry.
+    pub const LOCAL_APIC: u8 = 0;
+    /// The type of an I/O APIC entry.
+    pub const IO_APIC: u8 = 1;
+    /// The type of an interrupt source override entry.
+    pub const INTERRUPT_SOURCE_OVERRIDE: u8 = 2;
+    /// The type of a processor local x2APIC entry.
+    pub const LOCAL_X2APIC: u8 = 9;
+
+    /// Parses `entry`, the complete bytes of an entry of type `kind`.
+    fn parse(kind: u8, entry: &[u8]) -> Result<Self, MadtEntryError> {
+        let (needed, parsed) = match kind {
+            Self::LOCAL_APIC => (
+                8,
+                entry.get(..8).map(|entry| {
+                    Self::LocalApic(LocalApic {
+                        processor_id: u32::from(entry[2]),
+                        apic_id: u32::from(entry[3]),
+                        flags: read_u32(entry, 4).unwrap_or(0),
+                    })
+                }),
+            ),
+            Self::IO_APIC => (
+                12,
+                entry.get(..12).map(|entry| {
+                    Self::IoApic(IoApic {
+                        id: entry[2],
+                        address: read_u32(entry, 4).unwrap_or(0),
+                        gsi_base: read_u32(entry, 8).unwrap_or(0),
+                    })
+                }),
+            ),
+            Self::INTERRUPT_SOURCE_OVERRIDE => (
+                10,
+                entry.get(..10).map(|entry| {
+                    Self::InterruptSourceOverride(InterruptSourceOverride {
+                        bus: entry[2],
+                        source: entry[3],
+                        gsi: read_u32(entry, 4).unwrap_or(0),
+                        flags: u16::from_le_bytes([entry[8], entry[9]]),
+                    })
+                }),
+            ),
+            Self::LOCAL_X2APIC => (
+                16,
+                entry.get(..16).map(|entry| {
+                    Self::LocalApic(LocalApic {
+                        processor_id: read_u32(entry, 12).unwrap_or(0),
+                        apic_id: read_u32(entry, 4).unwrap_or(0),
+                        flags: read_u32(entry, 8).unwrap_or(0),
+                    })
+                }),
+            ),
+            kind => (2, Some(Self::Other { kind })),
+        };
+
+        parsed.ok_or(MadtEntryError::TooShort {
+            kind,
+            length: entry.len(),
+            needed,
+        })
+    }
+}
+
+/// A processor and its local APIC.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub struct LocalApic {
+    /// The ACPI processor UID.
+    pub processor_id: u32,
+    /// The ID of the processor's local APIC.
+    pub apic_id: u32,
+    /// The local APIC flags.
+    pub flags: u32,
+}
+
+impl LocalApic {
+    /// Set in [`LocalApic::flags`] if the processor is ready for use.
+    pub const ENABLED: u32 = 1 << 0;
+    /// Set in [`LocalApic::flags`] if a disabled processor can be enabled by the operating system.
+    pub const ONLINE_CAPABLE: u32 = 1 << 1;
+
+    /// Returns `true` if the processor can be started.
+    pub const fn is_usable(&self) -> bool {
+        self.flags & (Self::ENABLED | Self::ONLINE_CAPABLE) != 0
+    }
+}
+
+/// An I/O APIC.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub struct IoApic {
+    /// The ID of the I/O APIC.
+    pub id: u8,
+    /// The physical address of the I/O APIC's registers.
+    pub address: u32,
+    /// The first global system interrupt handled by the I/O APIC.
+    pub gsi_base: u32,
+}
+
+/// A legacy ISA interrupt that is delivered on a different global system interrupt, or with
+/// different polarity or trigger mode, than an identity mapping implies.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub struct InterruptSourceOverride {
+    /// The bus the interrupt originates on, which is always ISA.
+    pub bus: u8,
+    /// The ISA interrupt being overridden.
+    pub source: u8,
+    /// The global system interrupt the ISA interrupt is delivered on.
+    pub gsi: u32,
+    /// The polarity and trigger mode flags.
+    pub flags: u16,
+}
+
+/// Various errors that can occur while parsing the entries of a [`Madt`].
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum MadtEntryError {
+    /// An entry runs past the end of the table, or is too short to hold its own header, so no
+    /// further entries can be located.
+    Truncated {
+        /// The type of the entry, if its header is present.
+        kind: Option<u8>,
+        /// The number of bytes left in the table.
+        remaining: usize,
+    },
+    /// An entry is too short for its type.
+    TooShort {
+        /// The type of the entry.
+        kind: u8,
+        /// The length of the entry.
+        length: usize,
+        /// The length needed for an entry of its type.
+        needed: usize,
+    },
+}
+
+impl fmt::Display for MadtEntryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::Truncated {
+                kind: Some(kind),
+                remaining,
+            } => write!(
+                f,
+                "MADT entry of type {kind} is truncated with {remaining} bytes left"
+            ),
+            Self::Truncated {
+                kind: None,
+                remaining,
+            } => write!(f, "MADT ends with {remaining} stray bytes"),
+            Self::TooShort {
+                kind,
+                length,
+                needed,
+            } => write!(
+                f,
+                "MADT entry of type {kind} is {length} bytes long, needs {needed}"
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "ktest")]
+crate::kernel_test! {
+    fn madt_parsing() {
+        use crate::acpi::build_test_table;
+
+        #[rustfmt::skip]
+        const BODY: [u8; 58] = [
+            0x00, 0x00, 0xE0, 0xFE, // local APIC address
+            0x01, 0x00, 0x00, 0x00, // flags
+            0, 8, 0, 0, 0x01, 0, 0, 0, // local APIC: processor 0, APIC ID 0, enabled
+            0, 8, 1, 2, 0x00, 0, 0, 0, // local APIC: processor 1, APIC ID 2, disabled
+            1, 12, 3, 0, 0x00, 0x00, 0xC0, 0xFE, 0, 0, 0, 0, // I/O APIC 3 at 0xFEC00000
+            2, 10, 0, 0, 2, 0, 0, 0, 0, 0, // ISA IRQ 0 -> GSI 2
+            4, 6, 0xFF, 0, 0, 1, // local APIC NMI, not interpreted
+            1, 6, 0, 0, 0, 0, // I/O APIC entry that is too short
+        ];
+        let table = build_test_table::<128>(&SIGNATURE, &BODY);
+        let madt = Madt::parse(Sdt::parse(&table).unwrap()).unwrap();
+
+        assert_eq!(madt.local_apic_address(), 0xFEE0_0000);
+        assert_eq!(madt.flags(), Madt::PCAT_COMPAT);
+
+        let mut local_apics = madt.local_apics();
+        let first = local_apics.next().unwrap();
+        assert_eq!((first.processor_id, first.apic_id, first.is_usable()), (0, 0, true));
+        let second = local_apics.next().unwrap();
+        assert_eq!((second.processor_id, second.apic_id, second.is_usable()), (1, 2, false));
+        assert_eq!(local_apics.next(), None);
+
+        let io_apic = madt.io_apics().next().unwrap();
+        assert_eq!((io_apic.id, io_apic.address, io_apic.gsi_base), (3, 0xFEC0_0000, 0));
+        let iso = madt.interrupt_source_overrides().next().unwrap();
+        assert_eq!((iso.source, iso.gsi), (0, 2));
+
+        let mut entries = madt.entries().skip(4);
+        assert_eq!(entries.next(), Some(Ok(MadtEntry::Other { kind: 4 })));
+        assert_eq!(
+            entries.next(),
+            Some(Err(MadtEntryError::TooShort { kind: 1, length: 6, needed: 12 }))
+        );
+        assert_eq!(entries.next(), None);
+    }
+
+    fn madt_truncated_entries() {
+        use crate::acpi::build_test_table;
+
+        #[rustfmt::skip]
+        const BODY: [u8; 30] = [
+            0x00, 0x00, 0xE0, 0xFE, 0x00, 0x00, 0x00, 0x00,
+            9, 16, 0, 0, 0x00, 0x01, 0, 0, 0x01, 0, 0, 0, 0x07, 0, 0, 0, // x2APIC 0x100, UID 7
+            0, 8, 1, 1, 0x01, 0, // local APIC running past the end
+        ];
+        let table = build_test_table::<80>(&SIGNATURE, &BODY);
+        let madt = Madt::parse(Sdt::parse(&table).unwrap()).unwrap();
+
+        let mut entries = madt.entries();
+        assert_eq!(
+            entries.next(),
+            Some(Ok(MadtEntry::LocalApic(LocalApic { processor_id: 7, apic_id: 0x100, flags: 1 })))
+        );
+        assert_eq!(
+            entries.next(),
+            Some(Err(MadtEntryError::Truncated { kind: Some(0), remaining: 6 }))
+        );
+        assert_eq!(entries.next(), None);
+
+        let table = build_test_table::<40>(&SIGNATURE, &[0; 4]);
+        assert!(matches!(
+            Madt::parse(Sdt::parse(&table).unwrap()),
+            Err(AcpiError::Truncated { length: 44, available: 40, .. })
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::vec::Vec;
+
+    use super::*;
+    use crate::acpi::{build_test_table, SdtHeader};
+
+    /// The MADT of a single processor Firecracker guest.
+    #[rustfmt::skip]
+    const MADT: [u8; 64] = [
+        0x41, 0x50, 0x49, 0x43, 0x40, 0x00, 0x00, 0x00, 0x06, 0x69, 0x46, 0x49,
+        0x52, 0x45, 0x43, 0x4B, 0x46, 0x43, 0x56, 0x4D, 0x4D, 0x41, 0x44, 0x54,
+        0x00, 0x00, 0x00, 0x00, 0x46, 0x43, 0x41, 0x54, 0x19, 0x01, 0x24, 0x20,
+        0x00, 0x00, 0xE0, 0xFE, // local APIC address
+        0x00, 0x00, 0x00, 0x00, // flags
+        0x01, 0x0C, 0x00, 0x00, 0x00, 0x00, 0xC0, 0xFE, 0x00, 0x00, 0x00, 0x00, // I/O APIC 0
+        0x00, 0x08, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, // local APIC: processor 0, enabled
+    ];
+
+    #[test]
+    fn captured_madt_lists_processor_and_io_apic() {
+        let madt = Madt::parse(Sdt::parse(&MADT).unwrap()).unwrap();
+
+        assert_eq!(madt.local_apic_address(), 0xFEE0_0000);
+        assert_eq!(madt.flags() & Madt::PCAT_COMPAT, 0);
+        assert_eq!(
+            madt.entries().collect::<Vec<_>>(),
+            [
+                Ok(MadtEntry::IoApic(IoApic {
+                    id: 0,
+                    address: 0xFEC0_0000,
+                    gsi_base: 0,
+                })),
+                Ok(MadtEntry::LocalApic(LocalApic {
+                    processor_id: 0,
+                    apic_id: 0,
+                    flags: LocalApic::ENABLED,
+                })),
+            ]
+        );
+        assert!(madt.local_apics().all(|local_apic| local_apic.is_usable()));
+        assert_eq!(madt.io_apics().count(), 1);
+        assert_eq!(madt.interrupt_source_overrides().count(), 0);
+    }
+
+    #[test]
+    fn corrupt_madt_is_rejected() {
+        let mut corrupt = MADT;
+        corrupt[SdtHeader::SIZE + 8 + 2] = 1;
+
+        assert_eq!(
+            Sdt::parse(&corrupt),
+            Err(AcpiError::ChecksumMismatch(SIGNATURE))
+        );
+    }
+
+    #[test]
+    fn truncated_madt_is_rejected() {
+        assert_eq!(
+            Sdt::parse(&MADT[..MADT.len() - 8]),
+            Err(AcpiError::Truncated {
+                signature: SIGNATURE,
+                length: MADT.len() as u32,
+                available: MADT.len() - 8,
+            })
+        );
+
+        let table = build_test_table::<42>(&SIGNATURE, &MADT[SdtHeader::SIZE..42]);
+        assert_eq!(
+            Madt::parse(Sdt::parse(&table).unwrap()),
+            Err(AcpiError::Truncated {
+                signature: SIGNATURE,
+                length: (SdtHeader::SIZE + ENTRIES_OFFSET) as u32,
+                available: 42,
+            })
+        );
+    }
+
+    #[test]
+    fn truncated_entry_ends_iteration() {
+        // Drop the last byte of the local APIC entry, which then runs past the end of the table.
+        let table = build_test_table::<63>(&SIGNATURE, &MADT[SdtHeader::SIZE..63]);
+        let madt = Madt::parse(Sdt::parse(&table).unwrap()).unwrap();
+
+        let mut entries = madt.entries();
+        assert!(matches!(entries.next(), Some(Ok(MadtEntry::IoApic(_)))));
+        assert_eq!(
+            entries.next(),
+            Some(Err(MadtEntryError::Truncated {
+                kind: Some(MadtEntry::LOCAL_APIC),
+                remaining: 7,
+            }))
+        );
+        assert_eq!(entries.next(), None);
+        assert_eq!(madt.local_apics().count(), 0);
+    }
+
+    #[test]
+    fn short_entry_is_skipped() {
+        // Shrink the I/O APIC entry to 6 bytes, leaving its other 6 bytes as a stray entry.
+        let mut body = [0; 28];
+        body.copy_from_slice(&MADT[SdtHeader::SIZE..]);
+        body[9] = 6;
+        body[14] = MadtEntry::LOCAL_APIC;
+        body[15] = 6;
+        let table = build_test_table::<64>(&SIGNATURE, &body);
+        let madt = Madt::parse(Sdt::parse(&table).unwrap()).unwrap();
+
+        assert_eq!(
+            madt.entries().collect::<Vec<_>>(),
+            [
+                Err(MadtEntryError::TooShort {
+                    kind: MadtEntry::IO_APIC,
+                    length: 6,
+                    needed: 12,
+                }),
+                Err(MadtEntryError::TooShort {
+                    kind: MadtEntry::LOCAL_APIC,
+                    length: 6,
+                    needed: 8,
+                }),
+                Ok(MadtEntry::LocalApic(LocalApic {
+                    processor_id: 0,
+                    apic_id: 0,
+                    flags: LocalApic::ENABLED,
+                })),
+            ]
+        );
+    }
+
+    #[test]
+    fn other_tables_are_not_madts() {
+        let table = build_test_table::<44>(b"MCFG", &[0; 8]);
+
+        assert_eq!(
+            Madt::parse(Sdt::parse(&table).unwrap()),
+            Err(AcpiError::UnexpectedSignature {
+                expected: SIGNATURE,
+                found: *b"MCFG",
+            })
+        );
+    }
+}
@@ -0,0 +1,776 @@
+//! Discovery of the ACPI tables describing the platform.
+//!
+//! [`init()`] follows the RSDP provided by the bootloader to the XSDT, or the RSDT on ACPI 1.0
+//! systems, and validates it. Afterwards, [`find_table()`] locates other tables by their
+//! signature. Tables are read in place through the direct map, and every table is checked against
+//! its length and checksum before it is handed out, so that a truncated or corrupt table is
+//! skipped rather than read past its end.
+
+use core::{fmt, slice};
+
+use crate::{
+    arch::{direct_map, PhysicalAddress},
+    sync::Once,
+};
+
+pub mod madt;
+
+/// The largest table, in bytes, that is read. Anything longer is assumed to be corrupt.
+pub const MAX_TABLE_LENGTH: u32 = 1 << 20;
+
+/// The root table located by [`init()`].
+static ROOT: Once<RootTable> = Once::new();
+
+/// Locates and validates the root table referenced by the RSDP at `rsdp_address`.
+///
+/// Only the first successful call has any effect.
+///
+/// # Errors
+/// Returns [`AcpiError`] if the RSDP or the root table is invalid, or the direct map has not been
+/// initialized.
+pub fn init(rsdp_address: PhysicalAddress) -> Result<&'static RootTable, AcpiError> {
+    if let Some(root) = ROOT.get() {
+        return Ok(root);
+    }
+
+    let rsdp = Rsdp::parse(physical_bytes(rsdp_address.value(), Rsdp::V2_LENGTH)?)?;
+    let (address, kind) = rsdp.root_table();
+    let sdt = read_table(address)?;
+    if sdt.header().signature() != kind.signature() {
+        return Err(AcpiError::UnexpectedSignature {
+            expected: kind.signature(),
+            found: sdt.header().signature(),
+        });
+    }
+
+    Ok(ROOT.call_once(|| RootTable { sdt, kind }))
+}
+
+/// Returns the root table located by [`init()`], or [`None`] if it has not been located.
+pub fn root() -> Option<&'static RootTable> {
+    ROOT.get()
+}
+
+/// Returns the first valid table with the given `signature` referenced by the root table, or
+/// [`None`] if there is none or [`init()`] has not succeeded.
+pub fn find_table(signature: &[u8; 4]) -> Option<Sdt<'static>> {
+    root()?.find_table(signature)
+}
+
+/// Reads and validates the table at physical `address`.
+fn read_table(address: u64) -> Result<Sdt<'static>, AcpiError> {
+    let header = SdtHeader::parse(physical_bytes(address, SdtHeader::SIZE)?)?;
+    if header.length() > MAX_TABLE_LENGTH {
+        return Err(AcpiError::TableTooLarge {
+            signature: header.signature(),
+            length: header.length(),
+        });
+    }
+
+    Sdt::parse(physical_bytes(address, header.length() as usize)?)
+}
+
+/// Returns the `length` bytes of physical memory starting at `address`.
+fn physical_bytes(address: u64, length: usize) -> Result<&'static [u8], AcpiError> {
+    direct_map::offset().ok_or(AcpiError::DirectMapUnavailable)?;
+    address
+        .checked_add(length as u64)
+        .and_then(PhysicalAddress::new)
+        .ok_or(AcpiError::AddressOutOfRange(address))?;
+    let start = direct_map::phys_to_ptr::<u8>(PhysicalAddress::new_masked(address));
+
+    // SAFETY:
+    // The direct map covers all of physical memory, and the firmware places ACPI tables in memory
+    // that is never handed out by the frame allocator or written by the kernel.
+    Ok(unsafe { slice::from_raw_parts(start, length) })
+}
+
+/// Returns `true` if the bytes of `bytes` sum to zero, modulo 256.
+fn checksum_valid(bytes: &[u8]) -> bool {
+    bytes.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte)) == 0
+}
+
+/// Reads the little endian `u32` at `offset` in `bytes`, if it is in bounds.
+fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    let bytes = bytes.get(offset..offset.checked_add(4)?)?;
+    Some(u32::from_le_bytes(bytes.try_into().ok()?))
+}
+
+/// Reads the little endian `u64` at `offset` in `bytes`, if it is in bounds.
+fn read_u64(bytes: &[u8], offset: usize) -> Option<u64> {
+    let bytes = bytes.get(offset..offset.checked_add(8)?)?;
+    Some(u64::from_le_bytes(bytes.try_into().ok()?))
+}
+
+/// The Root System Description Pointer, which locates the root table.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub struct Rsdp {
+    /// The ACPI revision of the RSDP.
+    revision: u8,
+    /// The physical address of the RSDT.
+    rsdt_address: u32,
+    /// The physical address of the XSDT, if the RSDP is ACPI 2.0 or later and provides one.
+    xsdt_address: Option<u64>,
+}
+
+impl Rsdp {
+    /// The signature at the start of the RSDP.
+    pub const SIGNATURE: [u8; 8] = *b"RSD PTR ";
+    /// The length, in bytes, of an ACPI 1.0 RSDP.
+    pub const V1_LENGTH: usize = 20;
+    /// The length, in bytes, of an ACPI 2.0+ RSDP.
+    pub const V2_LENGTH: usize = 36;
+
+    /// Parses and validates the RSDP at the start of `bytes`.
+    ///
+    /// # Errors
+    /// Returns [`AcpiError`] if the signature or a checksum is wrong, or `bytes` is too short.
+    pub fn parse(bytes: &[u8]) -> Result<Self, AcpiError> {
+        let truncated = AcpiError::Truncated {
+            signature: *b"RSDP",
+            length: Self::V1_LENGTH as u32,
+            available: bytes.len(),
+        };
+        let v1 = bytes.get(..Self::V1_LENGTH).ok_or(truncated)?;
+        if v1[..8] != Self::SIGNATURE {
+            return Err(AcpiError::InvalidRsdpSignature);
+        }
+        if !checksum_valid(v1) {
+            return Err(AcpiError::ChecksumMismatch(*b"RSDP"));
+        }
+
+        let revision = v1[15];
+        let rsdt_address = read_u32(v1, 16).ok_or(truncated)?;
+        if revision < 2 {
+            return Ok(Self {
+                revision,
+                rsdt_address,
+                xsdt_address: None,
+            });
+        }
+
+        let length = read_u32(bytes, 20).ok_or(truncated)?;
+        let v2 = bytes
+            .get(..length as usize)
+            .filter(|v2| v2.len() >= Self::V2_LENGTH)
+            .ok_or(AcpiError::Truncated {
+                signature: *b"RSDP",
+                length,
+                available: bytes.len(),
+            })?;
+        if !checksum_valid(v2) {
+            return Err(AcpiError::ChecksumMismatch(*b"RSDP"));
+        }
+
+        Ok(Self {
+            revision,
+            rsdt_address,
+            xsdt_address: read_u64(v2, 24).filter(|&address| address != 0),
+        })
+    }
+
+    /// Returns the ACPI revision of this [`Rsdp`].
+    pub const fn revision(&self) -> u8 {
+        self.revision
+    }
+
+    /// Returns the physical address and kind of the root table, preferring the XSDT.
+    pub const fn root_table(&self) -> (u64, RootKind) {
+        match self.xsdt_address {
+            Some(address) => (address, RootKind::Xsdt),
+            None => (self.rsdt_address as u64, RootKind::Rsdt),
+        }
+    }
+}
+
+/// The kind of root table, which determines the size of its entries.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum RootKind {
+    /// The Root System Description Table, whose entries are 32-bit addresses.
+    Rsdt,
+    /// The Extended System Description Table, whose entries are 64-bit addresses.
+    Xsdt,
+}
+
+impl RootKind {
+    /// Returns the signature of this kind of root table.
+    pub const fn signature(self) -> [u8; 4] {
+        match self {
+            Self::Rsdt => *b"RSDT",
+            Self::Xsdt => *b"XSDT",
+        }
+    }
+
+    /// Returns the size, in bytes, of an entry of this kind of root table.
+    pub const fn entry_size(self) -> usize {
+        match self {
+            Self::Rsdt => 4,
+            Self::Xsdt => 8,
+        }
+    }
+}
+
+/// A validated root table.
+#[derive(Clone, Copy, Debug)]
+pub struct RootTable {
+    /// The table itself.
+    sdt: Sdt<'static>,
+    /// The kind of the table.
+    kind: RootKind,
+}
+
+impl RootTable {
+    /// Returns the underlying [`Sdt`].
+    pub const fn sdt(&self) -> Sdt<'static> {
+        self.sdt
+    }
+
+    /// Returns the kind of this [`RootTable`].
+    pub const fn kind(&self) -> RootKind {
+        self.kind
+    }
+
+    /// Returns an [`Iterator`] over the physical addresses of the tables referenced by this
+    /// [`RootTable`].
+    pub fn entries(&self) -> impl Iterator<Item = u64> + 'static {
+        root_entries(self.sdt.body(), self.kind)
+    }
+
+    /// Returns the first valid table with the given `signature`, logging and skipping any
+    /// referenced table that is invalid.
+    pub fn find_table(&self, signature: &[u8; 4]) -> Option<Sdt<'static>> {
+        self.entries().find_map(|address| {
+            let header = physical_bytes(address, SdtHeader::SIZE)
+                .and_then(SdtHeader::parse)
+                .ok()?;
+            if header.signature() != *signature {
+                return None;
+            }
+
+            read_table(address)
+                .inspect_err(|_error| {
+                    #[cfg(feature = "logging")]
+                    log::warn!("skipping ACPI table at {address:#x}: {_error}");
+                })
+                .ok()
+        })
+    }
+}
+
+/// Returns an [`Iterator`] over the addresses in `body`, the entries of a root table of `kind`.
+///
+/// A trailing partial entry is ignored.
+pub fn root_entries(body: &[u8], kind: RootKind) -> impl Iterator<Item = u64> + '_ {
+    body.chunks_exact(kind.entry_size())
+        .map(|entry| match *entry {
+            [a, b, c, d] => u64::from(u32::from_le_bytes([a, b, c, d])),
+            [a, b, c, d, e, f, g, h] => u64::from_le_bytes([a, b, c, d, e, f, g, h]),
+            _ => 0,
+        })
+        .filter(|&address| address != 0)
+}
+
+/// The header shared by every System Description Table.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub struct SdtHeader {
+    /// The signature identifying the table.
+    signature: [u8; 4],
+    /// The length, in bytes, of the table including its header.
+    length: u32,
+    /// The revision of the table's structure.
+    revision: u8,
+    /// The OEM that supplied the table.
+    oem_id: [u8; 6],
+    /// The OEM's name for the table.
+    oem_table_id: [u8; 8],
+}
+
+impl SdtHeader {
+    /// The size, in bytes, of an [`SdtHeader`].
+    pub const SIZE: usize = 36;
+
+    /// Parses the [`SdtHeader`] at the start of `bytes`.
+    ///
+    /// # Errors
+    /// Returns [`AcpiError::Truncated`] if `bytes` is shorter than a header.
+    pub fn parse(bytes: &[u8]) -> Result<Self, AcpiError> {
+        let Some(header) = bytes.first_chunk::<{ Self::SIZE }>() else {
+            return Err(AcpiError::Truncated {
+                signature: bytes.first_chunk::<4>().copied().unwrap_or(*b"????"),
+                length: Self::SIZE as u32,
+                available: bytes.len(),
+            });
+        };
+
+        let mut signature = [0; 4];
+        signature.copy_from_slice(&header[..4]);
+        let mut oem_id = [0; 6];
+        oem_id.copy_from_slice(&header[10..16]);
+        let mut oem_table_id = [0; 8];
+        oem_table_id.copy_from_slice(&header[16..24]);
+
+        Ok(Self {
+            signature,
+            length: u32::from_le_bytes([header[4], header[5], header[6], header[7]]),
+            revision: header[8],
+            oem_id,
+            oem_table_id,
+        })
+    }
+
+    /// Returns the signature identifying the table.
+    pub const fn signature(&self) -> [u8; 4] {
+        self.signature
+    }
+
+    /// Returns the length, in bytes, of the table including its header.
+    pub const fn length(&self) -> u32 {
+        self.length
+    }
+
+    /// Returns the revision of the table's structure.
+    pub const fn revision(&self) -> u8 {
+        self.revision
+    }
+
+    /// Returns the OEM that supplied the table.
+    pub const fn oem_id(&self) -> [u8; 6] {
+        self.oem_id
+    }
+
+    /// Returns the OEM's name for the table.
+    pub const fn oem_table_id(&self) -> [u8; 8] {
+        self.oem_table_id
+    }
+}
+
+/// A System Description Table whose length and checksum have been validated.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub struct Sdt<'a> {
+    /// The parsed header of the table.
+    header: SdtHeader,
+    /// The bytes of the table, including its header.
+    bytes: &'a [u8],
+}
+
+impl<'a> Sdt<'a> {
+    /// Parses and validates the table at the start of `bytes`.
+    ///
+    /// # Errors
+    /// Returns [`AcpiError`] if `bytes` is shorter than the length of the table, the length is
+    /// shorter than the header, or the checksum is wrong.
+    pub fn parse(bytes: &'a [u8]) -> Result<Self, AcpiError> {
+        let header = SdtHeader::parse(bytes)?;
+        let bytes = bytes
+            .get(..header.length() as usize)
+            .filter(|bytes| bytes.len() >= SdtHeader::SIZE)
+            .ok_or(AcpiError::Truncated {
+                signature: header.signature(),
+                length: header.length(),
+                available: bytes.len(),
+            })?;
+        if !checksum_valid(bytes) {
+            return Err(AcpiError::ChecksumMismatch(header.signature()));
+        }
+
+        Ok(Self { header, bytes })
+    }
+
+    /// Returns the header of this [`Sdt`].
+    pub const fn header(&self) -> &SdtHeader {
+        &self.header
+    }
+
+    /// Returns the bytes of this [`Sdt`], including its header.
+    pub const fn bytes(&self) -> &'a [u8] {
+        self.bytes
+    }
+
+    /// Returns the bytes of this [`Sdt`] that follow its header.
+    pub fn body(&self) -> &'a [u8] {
+        &self.bytes[SdtHeader::SIZE..]
+    }
+}
+
+/// Formats a table signature, replacing bytes that are not printable ASCII.
+struct Signature([u8; 4]);
+
+impl fmt::Display for Signature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for &byte in &self.0 {
+            let c = if byte.is_ascii_graphic() {
+                byte as char
+            } else {
+                '?'
+            };
+            fmt::Write::write_char(f, c)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Various errors that can occur while reading ACPI tables.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum AcpiError {
+    /// The direct map has not been initialized, so physical memory cannot be read.
+    DirectMapUnavailable,
+    /// A table would extend past the end of the physical address space.
+    AddressOutOfRange(u64),
+    /// The RSDP does not start with [`Rsdp::SIGNATURE`].
+    InvalidRsdpSignature,
+    /// The bytes of the table with the given signature do not sum to zero.
+    ChecksumMismatch([u8; 4]),
+    /// Fewer bytes are available than the length of a table or structure.
+    Truncated {
+        /// The signature of the table.
+        signature: [u8; 4],
+        /// The length, in bytes, that was needed.
+        length: u32,
+        /// The number of bytes available.
+        available: usize,
+    },
+    /// A table claims a length larger than [`MAX_TABLE_LENGTH`].
+    TableTooLarge {
+        /// The signature of the table.
+        signature: [u8; 4],
+        /// The length, in bytes, claimed by the table.
+        length: u32,
+    },
+    /// A table does not have the signature it was expected to have.
+    UnexpectedSignature {
+        /// The signature that was expected.
+        expected: [u8; 4],
+        /// The signature that was found.
+        found: [u8; 4],
+    },
+}
+
+impl fmt::Display for AcpiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::DirectMapUnavailable => f.write_str("direct map is not available"),
+            Self::AddressOutOfRange(address) => {
+                write!(f, "table at {address:#x} extends past physical memory")
+            }
+            Self::InvalidRsdpSignature => f.write_str("invalid RSDP signature"),
+            Self::ChecksumMismatch(signature) => {
+                write!(f, "{} checksum mismatch", Signature(signature))
+            }
+            Self::Truncated {
+                signature,
+                length,
+                available,
+            } => write!(
+                f,
+                "{} truncated: needs {length} bytes, {available} available",
+                Signature(signature)
+            ),
+            Self::TableTooLarge { signature, length } => write!(
+                f,
+                "{} claims a length of {length} bytes, more than {MAX_TABLE_LENGTH}",
+                Signature(signature)
+            ),
+            Self::UnexpectedSignature { expected, found } => write!(
+                f,
+                "expected {} but found {}",
+                Signature(expected),
+                Signature(found)
+            ),
+        }
+    }
+}
+
+/// Builds a table with the given `signature` and `body`, fixing up its length and checksum.
+#[cfg(any(test, feature = "ktest"))]
+pub(crate) fn build_test_table<const N: usize>(signature: &[u8; 4], body: &[u8]) -> [u8; N] {
+    let mut table = [0; N];
+    let length = SdtHeader::SIZE + body.len();
+    table[..4].copy_from_slice(signature);
+    table[4..8].copy_from_slice(&(length as u32).to_le_bytes());
+    table[8] = 1;
+    table[10..16].copy_from_slice(b"CAPORA");
+    table[SdtHeader::SIZE..length].copy_from_slice(body);
+    table[9] = 0u8.wrapping_sub(table.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte)));
+    table
+}
+
+#[cfg(feature = "ktest")]
+crate::kernel_test! {
+    fn acpi_rsdp_parsing() {
+        let mut rsdp = [0u8; Rsdp::V2_LENGTH];
+        rsdp[..8].copy_from_slice(&Rsdp::SIGNATURE);
+        rsdp[9..15].copy_from_slice(b"CAPORA");
+        rsdp[15] = 2;
+        rsdp[16..20].copy_from_slice(&0x1000u32.to_le_bytes());
+        rsdp[20..24].copy_from_slice(&(Rsdp::V2_LENGTH as u32).to_le_bytes());
+        rsdp[24..32].copy_from_slice(&0x2000u64.to_le_bytes());
+        rsdp[8] = 0u8.wrapping_sub(rsdp[..20].iter().fold(0u8, |sum, &b| sum.wrapping_add(b)));
+        rsdp[32] = 0u8.wrapping_sub(rsdp.iter().fold(0u8, |sum, &b| sum.wrapping_add(b)));
+
+        let parsed = Rsdp::parse(&rsdp).unwrap();
+        assert_eq!(parsed.revision(), 2);
+        assert_eq!(parsed.root_table(), (0x2000, RootKind::Xsdt));
+        assert_eq!(
+            Rsdp::parse(&rsdp[..Rsdp::V1_LENGTH]),
+            Err(AcpiError::Truncated {
+                signature: *b"RSDP",
+                length: Rsdp::V1_LENGTH as u32,
+                available: Rsdp::V1_LENGTH,
+            })
+        );
+
+        rsdp[15] = 0;
+        rsdp[8] = rsdp[8].wrapping_add(2);
+        let v1 = Rsdp::parse(&rsdp[..Rsdp::V1_LENGTH]).unwrap();
+        assert_eq!(v1.root_table(), (0x1000, RootKind::Rsdt));
+
+        rsdp[0] = b'X';
+        assert_eq!(Rsdp::parse(&rsdp), Err(AcpiError::InvalidRsdpSignature));
+    }
+
+    fn acpi_sdt_validation() {
+        let mut body = [0u8; 20];
+        body[..8].copy_from_slice(&0x1000u64.to_le_bytes());
+        body[8..16].copy_from_slice(&0x2000u64.to_le_bytes());
+        body[16..20].copy_from_slice(&0x3000u32.to_le_bytes());
+        let table = build_test_table::<64>(b"XSDT", &body);
+
+        let sdt = Sdt::parse(&table).unwrap();
+        assert_eq!(sdt.header().signature(), *b"XSDT");
+        assert_eq!(sdt.header().length() as usize, SdtHeader::SIZE + body.len());
+        assert_eq!(sdt.header().oem_id(), *b"CAPORA");
+        assert_eq!(sdt.body(), &body);
+
+        let mut entries = root_entries(sdt.body(), RootKind::Xsdt);
+        assert_eq!(entries.next(), Some(0x1000));
+        assert_eq!(entries.next(), Some(0x2000));
+        assert_eq!(entries.next(), None);
+        assert_eq!(root_entries(sdt.body(), RootKind::Rsdt).count(), 3);
+
+        assert!(matches!(
+            Sdt::parse(&table[..40]),
+            Err(AcpiError::Truncated { signature: [b'X', b'S', b'D', b'T'], length: 56, .. })
+        ));
+        assert!(matches!(Sdt::parse(&table[..20]), Err(AcpiError::Truncated { .. })));
+
+        let mut corrupt = table;
+        corrupt[40] ^= 1;
+        assert_eq!(Sdt::parse(&corrupt), Err(AcpiError::ChecksumMismatch(*b"XSDT")));
+
+        let mut short = table;
+        short[4..8].copy_from_slice(&8u32.to_le_bytes());
+        assert!(matches!(Sdt::parse(&short), Err(AcpiError::Truncated { length: 8, .. })));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The RSDP of a Firecracker guest, which locates the XSDT at `0xA0E13`.
+    #[rustfmt::skip]
+    const RSDP: [u8; 36] = [
+        0x52, 0x53, 0x44, 0x20, 0x50, 0x54, 0x52, 0x20, // "RSD PTR "
+        0x2B, // checksum
+        0x46, 0x49, 0x52, 0x45, 0x43, 0x4B, // "FIRECK"
+        0x02, // revision
+        0x00, 0x00, 0x00, 0x00, // RSDT address
+        0x24, 0x00, 0x00, 0x00, // length
+        0x13, 0x0E, 0x0A, 0x00, 0x00, 0x00, 0x00, 0x00, // XSDT address
+        0xB1, // extended checksum
+        0x00, 0x00, 0x00, // reserved
+    ];
+
+    /// The XSDT of the same Firecracker guest, referencing its FADT, MADT and MCFG.
+    #[rustfmt::skip]
+    const XSDT: [u8; 60] = [
+        0x58, 0x53, 0x44, 0x54, 0x3C, 0x00, 0x00, 0x00, 0x01, 0xAC, 0x46, 0x49,
+        0x52, 0x45, 0x43, 0x4B, 0x46, 0x43, 0x4D, 0x56, 0x58, 0x53, 0x44, 0x54,
+        0x00, 0x00, 0x00, 0x00, 0x46, 0x43, 0x41, 0x54, 0x19, 0x01, 0x24, 0x20,
+        0x83, 0x0C, 0x0A, 0x00, 0x00, 0x00, 0x00, 0x00, // FADT
+        0x97, 0x0D, 0x0A, 0x00, 0x00, 0x00, 0x00, 0x00, // MADT
+        0xD7, 0x0D, 0x0A, 0x00, 0x00, 0x00, 0x00, 0x00, // MCFG
+    ];
+
+    /// The addresses of the tables referenced by [`XSDT`].
+    const TABLES: [u64; 3] = [0xA0C83, 0xA0D97, 0xA0DD7];
+
+    /// Returns an ACPI 1.0 RSDP locating an RSDT at `rsdt_address`.
+    fn rsdp_v1(rsdt_address: u32) -> [u8; Rsdp::V1_LENGTH] {
+        let mut rsdp = [0; Rsdp::V1_LENGTH];
+        rsdp.copy_from_slice(&RSDP[..Rsdp::V1_LENGTH]);
+        rsdp[15] = 0;
+        rsdp[16..20].copy_from_slice(&rsdt_address.to_le_bytes());
+        rsdp[8] = 0;
+        rsdp[8] = 0u8.wrapping_sub(rsdp.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte)));
+        rsdp
+    }
+
+    #[test]
+    fn rsdp_prefers_the_xsdt() {
+        let rsdp = Rsdp::parse(&RSDP).unwrap();
+
+        assert_eq!(rsdp.revision(), 2);
+        assert_eq!(rsdp.root_table(), (0xA0E13, RootKind::Xsdt));
+    }
+
+    #[test]
+    fn acpi_1_rsdp_locates_the_rsdt() {
+        let rsdp = Rsdp::parse(&rsdp_v1(0x000E_1000)).unwrap();
+
+        assert_eq!(rsdp.revision(), 0);
+        assert_eq!(rsdp.root_table(), (0x000E_1000, RootKind::Rsdt));
+    }
+
+    #[test]
+    fn rsdp_without_xsdt_address_uses_the_rsdt() {
+        let mut rsdp = RSDP;
+        rsdp[16..20].copy_from_slice(&0x1000u32.to_le_bytes());
+        rsdp[24..32].fill(0);
+        rsdp[8] = rsdp[8].wrapping_sub(0x10);
+        rsdp[32] = rsdp[32].wrapping_add(0x13 + 0x0E + 0x0A);
+
+        assert_eq!(
+            Rsdp::parse(&rsdp).map(|rsdp| rsdp.root_table()),
+            Ok((0x1000, RootKind::Rsdt))
+        );
+    }
+
+    #[test]
+    fn rsdp_rejects_bad_checksums() {
+        let mut rsdp = RSDP;
+        rsdp[9] ^= 0x20;
+        assert_eq!(
+            Rsdp::parse(&rsdp),
+            Err(AcpiError::ChecksumMismatch(*b"RSDP"))
+        );
+
+        // Only the extended checksum covers the XSDT address.
+        let mut rsdp = RSDP;
+        rsdp[24] ^= 0x01;
+        assert_eq!(
+            Rsdp::parse(&rsdp),
+            Err(AcpiError::ChecksumMismatch(*b"RSDP"))
+        );
+
+        let mut rsdp = RSDP;
+        rsdp[0] = b'r';
+        assert_eq!(Rsdp::parse(&rsdp), Err(AcpiError::InvalidRsdpSignature));
+    }
+
+    #[test]
+    fn rsdp_rejects_truncation() {
+        assert_eq!(
+            Rsdp::parse(&RSDP[..19]),
+            Err(AcpiError::Truncated {
+                signature: *b"RSDP",
+                length: Rsdp::V1_LENGTH as u32,
+                available: 19,
+            })
+        );
+        assert_eq!(
+            Rsdp::parse(&RSDP[..30]),
+            Err(AcpiError::Truncated {
+                signature: *b"RSDP",
+                length: Rsdp::V2_LENGTH as u32,
+                available: 30,
+            })
+        );
+    }
+
+    #[test]
+    fn xsdt_lists_every_table() {
+        let sdt = Sdt::parse(&XSDT).unwrap();
+        let header = sdt.header();
+
+        assert_eq!(header.signature(), RootKind::Xsdt.signature());
+        assert_eq!(header.length(), 60);
+        assert_eq!(header.revision(), 1);
+        assert_eq!(header.oem_id(), *b"FIRECK");
+        assert_eq!(header.oem_table_id(), *b"FCMVXSDT");
+        assert!(root_entries(sdt.body(), RootKind::Xsdt).eq(TABLES));
+    }
+
+    #[test]
+    fn rsdt_lists_every_table() {
+        let mut body = [0; 12];
+        for (entry, address) in body.chunks_exact_mut(4).zip(TABLES) {
+            entry.copy_from_slice(&(address as u32).to_le_bytes());
+        }
+        let table = build_test_table::<48>(b"RSDT", &body);
+
+        let sdt = Sdt::parse(&table).unwrap();
+        assert_eq!(sdt.header().signature(), RootKind::Rsdt.signature());
+        assert!(root_entries(sdt.body(), RootKind::Rsdt).eq(TABLES));
+    }
+
+    #[test]
+    fn root_entries_skip_null_and_partial_entries() {
+        let mut body = [0u8; 8 * 3 + 4];
+        body[8..16].copy_from_slice(&TABLES[0].to_le_bytes());
+        body[24..].copy_from_slice(&[0xFF; 4]);
+
+        assert!(root_entries(&body, RootKind::Xsdt).eq([TABLES[0]]));
+    }
+
+    #[test]
+    fn truncated_tables_are_rejected() {
+        assert_eq!(
+            Sdt::parse(&XSDT[..XSDT.len() - 1]),
+            Err(AcpiError::Truncated {
+                signature: *b"XSDT",
+                length: 60,
+                available: 59,
+            })
+        );
+        assert_eq!(
+            Sdt::parse(&XSDT[..20]),
+            Err(AcpiError::Truncated {
+                signature: *b"XSDT",
+                length: SdtHeader::SIZE as u32,
+                available: 20,
+            })
+        );
+
+        // A length too short to cover the header is never valid.
+        let mut short = XSDT;
+        short[4] = 8;
+        assert!(matches!(
+            Sdt::parse(&short),
+            Err(AcpiError::Truncated { length: 8, .. })
+        ));
+    }
+
+    #[test]
+    fn corrupt_tables_are_rejected() {
+        for index in [0, 9, 20, XSDT.len() - 1] {
+            let mut corrupt = XSDT;
+            corrupt[index] = corrupt[index].wrapping_add(1);
+
+            let signature = corrupt[..4].try_into().unwrap();
+            assert_eq!(
+                Sdt::parse(&corrupt),
+                Err(AcpiError::ChecksumMismatch(signature))
+            );
+        }
+    }
+
+    #[test]
+    fn errors_display_signatures() {
+        assert_eq!(
+            std::format!("{}", AcpiError::ChecksumMismatch(*b"XSDT")),
+            "XSDT checksum mismatch"
+        );
+        assert_eq!(
+            std::format!(
+                "{}",
+                AcpiError::UnexpectedSignature {
+                    expected: *b"RSDT",
+                    found: [b'R', 0, b'\n', 0xFF],
+                }
+            ),
+            "expected RSDT but found R???"
+        );
+    }
+}
@@ -3,6 +3,10 @@
 #[cfg(all(feature = "debugcon-logging", not(target_arch = "x86_64")))]
 compile_error!("Feature `debugcon-logging` is not available on non-`x86_64` architectures");
 
+// `xtask` can target `aarch64`, but nothing below has been ported to it yet.
+#[cfg(all(target_os = "none", not(target_arch = "x86_64")))]
+compile_error!("the kernel has not been ported to this architecture yet");
+
 #[cfg(target_arch = "x86_64")]
 mod x86_64;
 #[cfg(target_arch = "x86_64")]
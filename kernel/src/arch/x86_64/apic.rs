@@ -0,0 +1,442 @@
+//! The local APIC of each processor, driven in x2APIC mode, and its periodic timer.
+//!
+//! The kernel cannot map memory yet, so the memory mapped xAPIC registers are out of reach and
+//! the local APIC is only supported in x2APIC mode, where every register is an MSR. The legacy
+//! PICs are remapped out of the way of the exception vectors and masked, until a driver routes one
+//! of their lines with [`enable_legacy_irq()`]. There is no I/O APIC driver yet, so routed lines
+//! reach the bootstrap processor through its LINT0 pin in ExtINT mode.
+//!
+//! The timer's frequency is unknown, so it is calibrated against channel 2 of the legacy PIT,
+//! whose input clock is fixed, before being started in periodic mode at [`TICK_HZ`].
+
+use core::{
+    fmt,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+};
+
+use crate::arch::x86_64::{
+    cpu, interrupt_stats,
+    structures::idt::{InterruptDescriptorTable, InterruptStackFrame},
+};
+
+/// The number of timer interrupts per second.
+pub const TICK_HZ: u32 = 100;
+
+/// The interrupt vector of the local APIC timer.
+pub const TIMER_VECTOR: u8 = 0x40;
+
+/// The interrupt vector of spurious local APIC interrupts.
+pub const SPURIOUS_VECTOR: u8 = 0xFF;
+
+/// The first of the 16 interrupt vectors the legacy PICs are remapped to. Lines are masked until
+/// they are routed with [`enable_legacy_irq()`], so otherwise only their spurious interrupts can
+/// arrive here.
+pub const LEGACY_PIC_VECTOR_BASE: u8 = 0xF0;
+
+/// The model specific register holding the base address and mode of the local APIC.
+const IA32_APIC_BASE: u32 = 0x1B;
+/// The bit of [`IA32_APIC_BASE`] that enables the local APIC.
+const APIC_BASE_ENABLE: u64 = 1 << 11;
+/// The bit of [`IA32_APIC_BASE`] that selects x2APIC mode.
+const APIC_BASE_X2APIC: u64 = 1 << 10;
+
+/// The x2APIC end of interrupt register.
+const X2APIC_EOI: u32 = 0x80B;
+/// The x2APIC spurious interrupt vector register.
+const X2APIC_SPURIOUS: u32 = 0x80F;
+/// The x2APIC interrupt command register.
+const X2APIC_ICR: u32 = 0x830;
+/// The x2APIC timer local vector table entry.
+const X2APIC_LVT_TIMER: u32 = 0x832;
+/// The x2APIC local vector table entry of the LINT0 pin.
+const X2APIC_LVT_LINT0: u32 = 0x835;
+/// The x2APIC timer initial count register.
+const X2APIC_TIMER_INITIAL_COUNT: u32 = 0x838;
+/// The x2APIC timer current count register.
+const X2APIC_TIMER_CURRENT_COUNT: u32 = 0x839;
+/// The x2APIC timer divide configuration register.
+const X2APIC_TIMER_DIVIDE: u32 = 0x83E;
+
+/// The bit of the spurious interrupt vector register that software enables the local APIC.
+const SPURIOUS_APIC_ENABLE: u64 = 1 << 8;
+/// The bit of a local vector table entry that masks it.
+const LVT_MASKED: u64 = 1 << 16;
+/// The delivery mode of a local vector table entry that takes the vector from an external 8259
+/// compatible interrupt controller.
+const LVT_DELIVERY_EXTINT: u64 = 0b111 << 8;
+/// The interrupt command register value that sends a non-maskable interrupt to every processor
+/// except the sender.
+const ICR_NMI_ALL_EXCLUDING_SELF: u64 = (0b11 << 18) | (1 << 14) | (0b100 << 8);
+/// The bit of the timer local vector table entry that selects periodic mode.
+const LVT_TIMER_PERIODIC: u64 = 1 << 17;
+/// The divide configuration value that divides the timer's input clock by 16.
+const TIMER_DIVIDE_BY_16: u64 = 0b0011;
+
+/// The frequency, in hertz, of the legacy PIT's input clock.
+const PIT_FREQUENCY: u32 = 1_193_182;
+/// The length, in milliseconds, of the interval the timer is calibrated over.
+const CALIBRATION_MS: u32 = 10;
+/// The number of times the PIT's output is polled before calibration is abandoned.
+const CALIBRATION_MAX_POLLS: u32 = 10_000_000;
+
+/// The PIT's mode and command register.
+const PIT_COMMAND: u16 = 0x43;
+/// The data port of channel 2 of the PIT.
+const PIT_CHANNEL_2: u16 = 0x42;
+/// The PIT command selecting channel 2, low then high byte access, and mode 0.
+const PIT_CHANNEL_2_ONE_SHOT: u8 = 0b1011_0000;
+/// The system control port that gates PIT channel 2 and reports its output.
+const SYSTEM_CONTROL_PORT_B: u16 = 0x61;
+/// The bit of [`SYSTEM_CONTROL_PORT_B`] that gates PIT channel 2.
+const PIT_CHANNEL_2_GATE: u8 = 1 << 0;
+/// The bit of [`SYSTEM_CONTROL_PORT_B`] that connects PIT channel 2 to the speaker.
+const SPEAKER_ENABLE: u8 = 1 << 1;
+/// The bit of [`SYSTEM_CONTROL_PORT_B`] that reflects the output of PIT channel 2.
+const PIT_CHANNEL_2_OUTPUT: u8 = 1 << 5;
+
+/// The command and data ports of the primary legacy PIC.
+const PIC_PRIMARY: (u16, u16) = (0x20, 0x21);
+/// The command and data ports of the secondary legacy PIC.
+const PIC_SECONDARY: (u16, u16) = (0xA0, 0xA1);
+
+/// Whether the timer of the bootstrap processor has been started.
+static TIMER_RUNNING: AtomicBool = AtomicBool::new(false);
+
+/// The number of timer interrupts handled since boot.
+static TICKS: AtomicU64 = AtomicU64::new(0);
+
+/// Masks the legacy PICs, switches the local APIC of the bootstrap processor to x2APIC mode, and
+/// starts its timer at [`TICK_HZ`], returning the timer's initial count.
+///
+/// The handlers installed by [`install_handlers()`] must be loaded before interrupts are enabled.
+///
+/// # Errors
+/// - [`ApicError::X2ApicUnsupported`] if the processor does not support x2APIC mode.
+/// - [`ApicError::CalibrationFailed`] if the PIT did not count down or the timer is too slow or
+///   too fast for [`TICK_HZ`].
+pub fn init_bootstrap() -> Result<u32, ApicError> {
+    disable_legacy_pic();
+
+    if !cpu::x2apic_supported() {
+        return Err(ApicError::X2ApicUnsupported);
+    }
+
+    // SAFETY:
+    // `IA32_APIC_BASE` exists on every processor with a local APIC, and reading it has no side
+    // effects.
+    let base = unsafe { cpu::read_msr(IA32_APIC_BASE) };
+    // SAFETY:
+    // x2APIC mode is supported, so enabling it only changes how the local APIC is accessed.
+    unsafe { cpu::write_msr(IA32_APIC_BASE, base | APIC_BASE_ENABLE | APIC_BASE_X2APIC) };
+    // SAFETY:
+    // The local APIC is in x2APIC mode, so its registers are MSRs, and the spurious vector has a
+    // handler.
+    unsafe {
+        cpu::write_msr(
+            X2APIC_SPURIOUS,
+            SPURIOUS_APIC_ENABLE | u64::from(SPURIOUS_VECTOR),
+        )
+    };
+
+    let elapsed = calibrate()?;
+    let initial_count =
+        initial_count(elapsed, CALIBRATION_MS, TICK_HZ).ok_or(ApicError::CalibrationFailed)?;
+
+    // SAFETY:
+    // The local APIC is in x2APIC mode and the timer vector has a handler, which is only invoked
+    // once interrupts are enabled.
+    unsafe {
+        cpu::write_msr(
+            X2APIC_LVT_TIMER,
+            LVT_TIMER_PERIODIC | u64::from(TIMER_VECTOR),
+        )
+    };
+    // SAFETY:
+    // Same as above. The divide configuration is still the one used for calibration.
+    unsafe { cpu::write_msr(X2APIC_TIMER_INITIAL_COUNT, u64::from(initial_count)) };
+
+    TIMER_RUNNING.store(true, Ordering::Release);
+    Ok(initial_count)
+}
+
+/// Returns `true` if the local APIC timer has been started.
+pub fn timer_running() -> bool {
+    TIMER_RUNNING.load(Ordering::Acquire)
+}
+
+/// Returns the number of timer interrupts handled since boot.
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+/// Installs the handlers of the non-maskable interrupt, timer, spurious, and legacy PIC vectors in
+/// `idt`.
+pub fn install_handlers(idt: &mut InterruptDescriptorTable) {
+    idt.non_maskable_interrupt.set_handler_fn(nmi_handler);
+    idt.general_interrupts[usize::from(TIMER_VECTOR) - 32].set_handler_fn(timer_handler);
+    for vector in LEGACY_PIC_VECTOR_BASE..=SPURIOUS_VECTOR {
+        idt.general_interrupts[usize::from(vector) - 32].set_handler_fn(spurious_handler);
+    }
+}
+
+/// Unmasks line `irq` of the legacy PICs and routes it to the bootstrap processor, returning the
+/// vector it arrives on, or [`None`] if `irq` is not a legacy line or the local APIC is not
+/// running.
+///
+/// The handler of the returned vector must call [`legacy_end_of_interrupt()`].
+pub fn enable_legacy_irq(irq: u8) -> Option<u8> {
+    if irq >= 16 || !timer_running() {
+        return None;
+    }
+
+    cpu::without_interrupts(|| {
+        // SAFETY:
+        // The local APIC is in x2APIC mode, and LINT0 is only driven by the legacy PICs, which
+        // deliver the remapped vectors that all have handlers.
+        unsafe { cpu::write_msr(X2APIC_LVT_LINT0, LVT_DELIVERY_EXTINT) }
+
+        if irq < 8 {
+            outb(PIC_PRIMARY.1, inb(PIC_PRIMARY.1) & !(1 << irq));
+        } else {
+            outb(PIC_SECONDARY.1, inb(PIC_SECONDARY.1) & !(1 << (irq - 8)));
+            outb(PIC_PRIMARY.1, inb(PIC_PRIMARY.1) & !(1 << 2));
+        }
+    });
+
+    Some(LEGACY_PIC_VECTOR_BASE + irq)
+}
+
+/// Signals the end of an interrupt on line `irq` of the legacy PICs.
+///
+/// Interrupts delivered in ExtINT mode are not acknowledged to the local APIC.
+pub fn legacy_end_of_interrupt(irq: u8) {
+    const END_OF_INTERRUPT: u8 = 0x20;
+
+    if irq >= 8 {
+        outb(PIC_SECONDARY.0, END_OF_INTERRUPT);
+    }
+    outb(PIC_PRIMARY.0, END_OF_INTERRUPT);
+}
+
+/// Sends a non-maskable interrupt to every other processor, which halts them once a panic is in
+/// progress.
+///
+/// Nothing is sent if the local APIC of the current processor is not in x2APIC mode, in which case
+/// no other processor can have been started.
+pub fn halt_other_processors() {
+    // SAFETY:
+    // `IA32_APIC_BASE` exists on every processor with a local APIC, and reading it has no side
+    // effects.
+    let base = unsafe { cpu::read_msr(IA32_APIC_BASE) };
+    if base & (APIC_BASE_ENABLE | APIC_BASE_X2APIC) != APIC_BASE_ENABLE | APIC_BASE_X2APIC {
+        return;
+    }
+
+    // SAFETY:
+    // The local APIC is in x2APIC mode, and the non-maskable interrupt handler halts processors
+    // that receive it during a panic.
+    unsafe { cpu::write_msr(X2APIC_ICR, ICR_NMI_ALL_EXCLUDING_SELF) }
+}
+
+/// Returns the timer count that makes it fire at `tick_hz`, given that it counted down by
+/// `elapsed` in `calibration_ms` milliseconds, or [`None`] if that count is zero or does not fit
+/// the timer.
+pub const fn initial_count(elapsed: u32, calibration_ms: u32, tick_hz: u32) -> Option<u32> {
+    if calibration_ms == 0 || tick_hz == 0 {
+        return None;
+    }
+
+    let count = elapsed as u64 * 1000 / (calibration_ms as u64 * tick_hz as u64);
+    if count == 0 || count > u32::MAX as u64 {
+        return None;
+    }
+
+    Some(count as u32)
+}
+
+/// Returns the number of timer counts, with the input clock divided by 16, that elapse during
+/// [`CALIBRATION_MS`] milliseconds measured by the PIT.
+fn calibrate() -> Result<u32, ApicError> {
+    let pit_count = PIT_FREQUENCY / (1000 / CALIBRATION_MS);
+    let [low, high, ..] = pit_count.to_le_bytes();
+
+    let control = inb(SYSTEM_CONTROL_PORT_B);
+    outb(
+        SYSTEM_CONTROL_PORT_B,
+        (control & !SPEAKER_ENABLE) | PIT_CHANNEL_2_GATE,
+    );
+    outb(PIT_COMMAND, PIT_CHANNEL_2_ONE_SHOT);
+
+    // SAFETY:
+    // The local APIC is in x2APIC mode, and changing the divide configuration of the stopped
+    // timer has no side effects.
+    unsafe { cpu::write_msr(X2APIC_TIMER_DIVIDE, TIMER_DIVIDE_BY_16) };
+    // SAFETY:
+    // Masking the timer prevents it from raising interrupts while it is calibrated.
+    unsafe { cpu::write_msr(X2APIC_LVT_TIMER, LVT_MASKED | u64::from(TIMER_VECTOR)) };
+
+    // Loading the count starts the PIT, so start the timer right after.
+    outb(PIT_CHANNEL_2, low);
+    outb(PIT_CHANNEL_2, high);
+    // SAFETY:
+    // The timer is masked, so starting it raises no interrupts.
+    unsafe { cpu::write_msr(X2APIC_TIMER_INITIAL_COUNT, u64::from(u32::MAX)) };
+
+    let mut polls = 0;
+    while inb(SYSTEM_CONTROL_PORT_B) & PIT_CHANNEL_2_OUTPUT == 0 {
+        polls += 1;
+        if polls == CALIBRATION_MAX_POLLS {
+            break;
+        }
+        core::hint::spin_loop();
+    }
+
+    // SAFETY:
+    // Reading the current count has no side effects.
+    let remaining = unsafe { cpu::read_msr(X2APIC_TIMER_CURRENT_COUNT) } as u32;
+    // SAFETY:
+    // Stopping the masked timer has no side effects.
+    unsafe { cpu::write_msr(X2APIC_TIMER_INITIAL_COUNT, 0) };
+    outb(SYSTEM_CONTROL_PORT_B, control);
+
+    if polls == CALIBRATION_MAX_POLLS {
+        return Err(ApicError::CalibrationFailed);
+    }
+
+    Ok(u32::MAX - remaining)
+}
+
+/// Remaps the legacy PICs to [`LEGACY_PIC_VECTOR_BASE`] and masks all of their lines.
+///
+/// Remapping keeps their spurious interrupts off the exception vectors they are wired to by
+/// default.
+fn disable_legacy_pic() {
+    const ICW1_INIT_WITH_ICW4: u8 = 0x11;
+    const ICW4_8086_MODE: u8 = 0x01;
+    const MASK_ALL: u8 = 0xFF;
+
+    outb(PIC_PRIMARY.0, ICW1_INIT_WITH_ICW4);
+    outb(PIC_SECONDARY.0, ICW1_INIT_WITH_ICW4);
+    outb(PIC_PRIMARY.1, LEGACY_PIC_VECTOR_BASE);
+    outb(PIC_SECONDARY.1, LEGACY_PIC_VECTOR_BASE + 8);
+    // The secondary PIC is cascaded on line 2 of the primary.
+    outb(PIC_PRIMARY.1, 1 << 2);
+    outb(PIC_SECONDARY.1, 2);
+    outb(PIC_PRIMARY.1, ICW4_8086_MODE);
+    outb(PIC_SECONDARY.1, ICW4_8086_MODE);
+
+    outb(PIC_PRIMARY.1, MASK_ALL);
+    outb(PIC_SECONDARY.1, MASK_ALL);
+}
+
+/// Signals the end of the interrupt being handled to the local APIC.
+fn end_of_interrupt() {
+    // SAFETY:
+    // The local APIC is in x2APIC mode whenever its interrupts are handled, and writing zero to
+    // the end of interrupt register only retires the interrupt being handled.
+    unsafe { cpu::write_msr(X2APIC_EOI, 0) }
+}
+
+extern "x86-interrupt" fn timer_handler(_frame: InterruptStackFrame) {
+    interrupt_stats::record(TIMER_VECTOR);
+    let ticks = TICKS.fetch_add(1, Ordering::Relaxed) + 1;
+    end_of_interrupt();
+
+    crate::boot_progress::on_tick(ticks);
+    crate::scheduler::on_tick();
+    // Switching away here resumes this thread on the interrupt return path once it is
+    // rescheduled.
+    crate::scheduler::preempt_if_needed();
+}
+
+/// Halts the current processor if another processor has panicked, as requested by
+/// [`halt_other_processors()`].
+extern "x86-interrupt" fn nmi_handler(_frame: InterruptStackFrame) {
+    if crate::panic_guard::in_progress() {
+        cpu::halt_forever();
+    }
+
+    panic!("unexpected non-maskable interrupt");
+}
+
+/// Handles the spurious interrupts of the local APIC and the legacy PICs, neither of which may be
+/// acknowledged.
+extern "x86-interrupt" fn spurious_handler(_frame: InterruptStackFrame) {
+    interrupt_stats::record(SPURIOUS_VECTOR);
+}
+
+fn outb(port: u16, byte: u8) {
+    // SAFETY:
+    // The ports written by this module belong to the legacy PICs and the PIT, which have no
+    // memory safety implications.
+    unsafe {
+        core::arch::asm!(
+            "out dx, al",
+            in("dx") port,
+            in("al") byte,
+            options(nomem, nostack, preserves_flags)
+        );
+    }
+}
+
+fn inb(port: u16) -> u8 {
+    let byte: u8;
+    // SAFETY:
+    // Reading system control port B or the mask registers of the legacy PICs has no side effects.
+    unsafe {
+        core::arch::asm!(
+            "in al, dx",
+            in("dx") port,
+            out("al") byte,
+            options(nomem, nostack, preserves_flags)
+        );
+    }
+
+    byte
+}
+
+/// Various errors that can occur while setting up the local APIC.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum ApicError {
+    /// The processor does not support x2APIC mode.
+    X2ApicUnsupported,
+    /// The timer could not be calibrated against the PIT.
+    CalibrationFailed,
+}
+
+impl fmt::Display for ApicError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::X2ApicUnsupported => f.write_str("x2APIC mode is not supported"),
+            Self::CalibrationFailed => f.write_str("timer calibration against the PIT failed"),
+        }
+    }
+}
+
+#[cfg(feature = "ktest")]
+crate::kernel_test! {
+    fn timer_initial_count_scales_with_frequency() {
+        assert_eq!(initial_count(1_000_000, 10, 100), Some(1_000_000));
+        assert_eq!(initial_count(1_000_000, 10, 1000), Some(100_000));
+        assert_eq!(initial_count(1_000_000, 20, 100), Some(500_000));
+        assert_eq!(initial_count(5, 10, 1000), None);
+        assert_eq!(initial_count(u32::MAX, 1, 1), None);
+        assert_eq!(initial_count(1_000_000, 0, 100), None);
+    }
+
+    fn timer_ticks_advance() {
+        if !timer_running() {
+            #[cfg(feature = "logging")]
+            log::warn!("local APIC timer is not running, skipping");
+            return;
+        }
+
+        let start = ticks();
+        let mut spins = 0u64;
+        while ticks() < start + 2 {
+            spins += 1;
+            assert!(spins < 1 << 32, "timer did not tick");
+            core::hint::spin_loop();
+        }
+    }
+}
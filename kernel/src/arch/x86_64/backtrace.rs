@@ -0,0 +1,516 @@
+//! Stack traces obtained by following the chain of saved frame pointers.
+//!
+//! The kernel is built with frame pointers, so every function saves the caller's `rbp` at `[rbp]`
+//! and the return address at `[rbp + 8]`. Following this chain yields the return address of every
+//! active call. Return addresses are symbolized through the embedded symbol table when it is
+//! usable, and can otherwise be symbolized offline against the kernel image identified by
+//! [`build_id()`].
+//!
+//! The walk is confined to the stack that contains the stack pointer, which must be a
+//! [`KernelStack`][crate::arch::x86_64::memory::stack::KernelStack] or one of the stacks
+//! registered through [`register_stack()`]: the boot stack, the interrupt and privilege stacks of
+//! the task state segment, and the system call stack. Nothing is walked on any other stack, since
+//! its extent, and so which addresses are safe to read, is unknown.
+
+use core::{
+    fmt, ptr,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::arch::x86_64::{
+    cpu::RegisterSnapshot,
+    memory::{stack, PageRange, VirtualAddress},
+};
+
+/// The maximum number of return addresses recorded in a [`Backtrace`].
+pub const MAX_FRAMES: usize = 32;
+/// The maximum number of stacks that can be registered through [`register_stack()`].
+pub const MAX_REGISTERED_STACKS: usize = 8;
+
+/// The stacks registered through [`register_stack()`].
+static REGISTERED_STACKS: StackRegistry = StackRegistry::new();
+
+/// Registers the stack spanning `bounds`, so that backtraces captured on it are walked.
+///
+/// Stacks beyond the first [`MAX_REGISTERED_STACKS`] are ignored. [`KernelStack`]s need not be
+/// registered.
+///
+/// [`KernelStack`]: crate::arch::x86_64::memory::stack::KernelStack
+pub fn register_stack(bounds: StackBounds) {
+    REGISTERED_STACKS.insert(bounds);
+}
+
+/// Returns the [`StackBounds`] of the known stack that contains `address`, or [`None`] if
+/// `address` lies in no [`KernelStack`] and no stack registered through [`register_stack()`].
+///
+/// This takes no locks, so it can be called while panicking.
+///
+/// [`KernelStack`]: crate::arch::x86_64::memory::stack::KernelStack
+pub fn stack_containing(address: usize) -> Option<StackBounds> {
+    let kernel_stack = VirtualAddress::new(address).and_then(stack::stack_containing);
+
+    kernel_stack
+        .map(StackBounds::from_pages)
+        .or_else(|| REGISTERED_STACKS.containing(address))
+}
+
+/// The range of addresses in which saved frame pointers are followed.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub struct StackBounds {
+    /// The lowest address of the stack.
+    pub start: usize,
+    /// The address one past the highest address of the stack.
+    pub end: usize,
+}
+
+impl StackBounds {
+    /// Returns the [`StackBounds`] spanning `pages`.
+    pub const fn from_pages(pages: PageRange) -> Self {
+        let start = pages.start_address().value();
+
+        Self {
+            start,
+            end: start + pages.size_in_bytes(),
+        }
+    }
+
+    /// Returns `true` if `address` lies within these bounds.
+    pub const fn contains(&self, address: usize) -> bool {
+        address >= self.start && address < self.end
+    }
+
+    /// Returns `true` if the frame record at `frame`, which spans 16 bytes, lies within these
+    /// bounds.
+    pub const fn contains_frame(&self, frame: usize) -> bool {
+        frame >= self.start && frame <= self.end.saturating_sub(16)
+    }
+}
+
+/// Returns an [`Iterator`] over the return addresses found by following the frame pointer chain
+/// starting at `rbp`, reading memory through `read`.
+///
+/// `read` returns the 8-byte value at the given address, or [`None`] if it cannot be read. The walk
+/// ends after [`MAX_FRAMES`] frames or as soon as the chain looks corrupt: a frame pointer that is
+/// null, misaligned, outside `bounds`, or not above the previous one, or a return address of zero.
+pub fn walk<F: Fn(usize) -> Option<u64>>(
+    rbp: usize,
+    bounds: StackBounds,
+    read: F,
+) -> FrameWalker<F> {
+    FrameWalker {
+        rbp,
+        bounds,
+        read,
+        remaining: MAX_FRAMES,
+    }
+}
+
+/// An [`Iterator`] over the return addresses of a frame pointer chain, created by [`walk()`].
+pub struct FrameWalker<F> {
+    /// The frame pointer of the next frame.
+    rbp: usize,
+    /// The range in which frame pointers are followed.
+    bounds: StackBounds,
+    /// Reads the 8-byte value at an address.
+    read: F,
+    /// The number of frames that may still be yielded.
+    remaining: usize,
+}
+
+impl<F: Fn(usize) -> Option<u64>> Iterator for FrameWalker<F> {
+    type Item = u64;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0
+            || self.rbp == 0
+            || !self.rbp.is_multiple_of(8)
+            || !self.bounds.contains_frame(self.rbp)
+        {
+            return None;
+        }
+        self.remaining -= 1;
+
+        let (Some(next_rbp), Some(return_address)) =
+            ((self.read)(self.rbp), (self.read)(self.rbp + 8))
+        else {
+            self.remaining = 0;
+            return None;
+        };
+        if return_address == 0 {
+            self.remaining = 0;
+            return None;
+        }
+
+        // Frames grow downwards, so each caller's frame lies above its callee's. Anything else
+        // means that the chain is corrupt and could loop forever.
+        let next_rbp = next_rbp as usize;
+        self.rbp = if next_rbp > self.rbp { next_rbp } else { 0 };
+
+        Some(return_address)
+    }
+}
+
+/// The return addresses of the calls active when it was captured.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub struct Backtrace {
+    /// The return addresses, innermost first.
+    frames: [u64; MAX_FRAMES],
+    /// The number of valid entries in `frames`.
+    len: usize,
+}
+
+impl Backtrace {
+    /// Captures the backtrace described by the `rsp` and `rbp` of `registers`, which must be
+    /// those of the current stack.
+    ///
+    /// The backtrace is empty if the stack pointer lies in no known stack.
+    pub fn capture(registers: &RegisterSnapshot) -> Self {
+        let mut backtrace = Self {
+            frames: [0; MAX_FRAMES],
+            len: 0,
+        };
+
+        let rsp = registers.rsp as usize;
+        let Some(stack) = stack_containing(rsp) else {
+            return backtrace;
+        };
+        // Frames below the stack pointer are dead, so only the part of the stack above it is
+        // walked.
+        let bounds = StackBounds {
+            start: rsp,
+            end: stack.end,
+        };
+
+        // SAFETY:
+        // `walk()` only reads 8-byte aligned addresses within `bounds`, which lie on the current
+        // stack, whose every page is mapped.
+        let read = |address: usize| Some(unsafe { ptr::read_volatile(address as *const u64) });
+        for (slot, return_address) in
+            backtrace
+                .frames
+                .iter_mut()
+                .zip(walk(registers.rbp as usize, bounds, read))
+        {
+            *slot = return_address;
+            backtrace.len += 1;
+        }
+
+        backtrace
+    }
+
+    /// Returns the return addresses, innermost first.
+    pub fn frames(&self) -> &[u64] {
+        &self.frames[..self.len]
+    }
+}
+
+impl fmt::Display for Backtrace {
+    /// Formats the build ID followed by one `#i 0xADDR name+0xOFFSET` line per frame, without a
+    /// trailing newline.
+    ///
+    /// If the embedded symbol table is unusable, a note saying why precedes the frames, which
+    /// are printed as raw addresses.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match build_id() {
+            Some(build_id) => write!(f, "build id: {}", BuildId(build_id))?,
+            None => f.write_str("build id: unknown")?,
+        }
+
+        if self.frames().is_empty() {
+            return f.write_str("\nno frames");
+        }
+        if let Err(error) = crate::symbols::table() {
+            write!(f, "\n{error}; addresses are not symbolized")?;
+        }
+        for (index, &return_address) in self.frames().iter().enumerate() {
+            write!(f, "\n#{index} {return_address:#018x}")?;
+
+            // A return address follows the call, which may be the last instruction of the
+            // function, so the byte before it is looked up instead.
+            let call_address = VirtualAddress::new_canonical(return_address as usize - 1);
+            if let Some((name, offset)) = crate::symbols::resolve(call_address) {
+                write!(f, " {name}+{:#x}", offset + 1)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// A table of [`StackBounds`] that can be searched without taking a lock.
+struct StackRegistry {
+    /// The start of each stack, or zero for an unused entry.
+    starts: [AtomicUsize; MAX_REGISTERED_STACKS],
+    /// The end of each stack, or zero while its entry is being filled in.
+    ends: [AtomicUsize; MAX_REGISTERED_STACKS],
+}
+
+impl StackRegistry {
+    /// Returns an empty [`StackRegistry`].
+    const fn new() -> Self {
+        Self {
+            starts: [const { AtomicUsize::new(0) }; MAX_REGISTERED_STACKS],
+            ends: [const { AtomicUsize::new(0) }; MAX_REGISTERED_STACKS],
+        }
+    }
+
+    /// Records the stack spanning `bounds`, returning `false` if the table is full or `bounds` is
+    /// empty.
+    fn insert(&self, bounds: StackBounds) -> bool {
+        if bounds.start == 0 || bounds.end <= bounds.start {
+            return false;
+        }
+
+        let Some(slot) = self.starts.iter().position(|entry| {
+            entry
+                .compare_exchange(0, bounds.start, Ordering::AcqRel, Ordering::Relaxed)
+                .is_ok()
+        }) else {
+            return false;
+        };
+        self.ends[slot].store(bounds.end, Ordering::Release);
+
+        true
+    }
+
+    /// Returns the bounds of the recorded stack that contains `address`.
+    fn containing(&self, address: usize) -> Option<StackBounds> {
+        self.starts
+            .iter()
+            .zip(&self.ends)
+            .map(|(start, end)| StackBounds {
+                start: start.load(Ordering::Acquire),
+                end: end.load(Ordering::Acquire),
+            })
+            .find(|bounds| bounds.start != 0 && bounds.contains(address))
+    }
+}
+
+/// Returns `true` unless stack traces are disabled by `backtrace=off` on the kernel command line.
+pub fn enabled() -> bool {
+    crate::options::get::<bool>("backtrace").unwrap_or(true)
+}
+
+/// The type of an ELF note holding the build ID.
+const NT_GNU_BUILD_ID: u32 = 3;
+
+/// Returns the build ID of the running kernel image, or [`None`] if the image has none.
+pub fn build_id() -> Option<&'static [u8]> {
+    extern "C" {
+        #[link_name = "build_id_start"]
+        static BUILD_ID_START: core::ffi::c_void;
+        #[link_name = "build_id_end"]
+        static BUILD_ID_END: core::ffi::c_void;
+    }
+
+    let start = ptr::addr_of!(BUILD_ID_START).cast::<u8>();
+    let end = ptr::addr_of!(BUILD_ID_END).cast::<u8>();
+    // SAFETY:
+    // The linker script places the build ID note between `build_id_start` and `build_id_end`.
+    let note = unsafe { core::slice::from_raw_parts(start, end as usize - start as usize) };
+
+    parse_build_id_note(note)
+}
+
+/// Returns the descriptor of `note` if it is a GNU build ID note.
+fn parse_build_id_note(note: &[u8]) -> Option<&[u8]> {
+    let word = |offset: usize| -> Option<u32> {
+        Some(u32::from_ne_bytes(
+            *note.get(offset..offset + 4)?.first_chunk()?,
+        ))
+    };
+
+    let name_size = word(0)? as usize;
+    let descriptor_size = word(4)? as usize;
+    if word(8)? != NT_GNU_BUILD_ID || note.get(12..12 + name_size)? != b"GNU\0" {
+        return None;
+    }
+
+    let descriptor_start = 12 + name_size.next_multiple_of(4);
+    note.get(descriptor_start..descriptor_start + descriptor_size)
+}
+
+/// Formats a build ID as lowercase hexadecimal.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub struct BuildId<'a>(pub &'a [u8]);
+
+impl fmt::Display for BuildId<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for byte in self.0 {
+            write!(f, "{byte:02x}")?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The address of the first word of a synthetic stack.
+    const BASE: usize = 0x10_0000;
+
+    /// Returns the bounds of a synthetic stack of `len` words.
+    fn bounds(len: usize) -> StackBounds {
+        StackBounds {
+            start: BASE,
+            end: BASE + len * 8,
+        }
+    }
+
+    /// Returns a reader of the synthetic stack `words`, which starts at [`BASE`].
+    fn reader(words: &[u64]) -> impl Fn(usize) -> Option<u64> + '_ {
+        |address| {
+            let offset = address.checked_sub(BASE)?;
+            assert!(offset.is_multiple_of(8), "misaligned read at {address:#x}");
+            words.get(offset / 8).copied()
+        }
+    }
+
+    /// Returns the address of word `index` of a synthetic stack.
+    const fn word(index: usize) -> u64 {
+        (BASE + index * 8) as u64
+    }
+
+    /// Returns the return addresses found by walking the synthetic stack `words` from `rbp`.
+    fn collect(rbp: usize, words: &[u64]) -> Vec<u64> {
+        walk(rbp, bounds(words.len()), reader(words)).collect()
+    }
+
+    #[test]
+    fn follows_a_normal_chain() {
+        let words = [word(2), 0xA, word(6), 0xB, 0, 0, 0, 0xC];
+        assert_eq!(collect(BASE, &words), [0xA, 0xB, 0xC]);
+    }
+
+    #[test]
+    fn stops_at_a_loop() {
+        let words = [word(2), 0xA, word(0), 0xB];
+        assert_eq!(collect(BASE, &words), [0xA, 0xB]);
+
+        let words = [word(0), 0xA];
+        assert_eq!(collect(BASE, &words), [0xA]);
+    }
+
+    #[test]
+    fn stops_at_a_misaligned_frame() {
+        let words = [word(2) + 4, 0xA, word(4), 0xB, 0, 0xC];
+        assert_eq!(collect(BASE, &words), [0xA]);
+        assert!(collect(BASE + 4, &words).is_empty());
+    }
+
+    #[test]
+    fn stops_at_an_out_of_bounds_frame() {
+        let words = [word(4), 0xA, 0, 0xB];
+        assert_eq!(collect(BASE, &words), [0xA]);
+
+        // The frame record starts within the bounds but its return address lies beyond them.
+        let words = [word(3), 0xA, 0, 0];
+        assert_eq!(collect(BASE, &words), [0xA]);
+
+        assert!(collect(BASE - 16, &words).is_empty());
+    }
+
+    #[test]
+    fn stops_at_a_zero_return_address() {
+        let words = [word(2), 0xA, word(4), 0, 0, 0xC];
+        let mut walker = walk(BASE, bounds(words.len()), reader(&words));
+
+        assert_eq!(walker.next(), Some(0xA));
+        assert_eq!(walker.next(), None);
+        assert_eq!(walker.next(), None);
+    }
+
+    #[test]
+    fn stops_at_a_null_frame_pointer() {
+        assert!(collect(0, &[0, 0xA]).is_empty());
+    }
+
+    #[test]
+    fn yields_at_most_max_frames() {
+        let frames = MAX_FRAMES + 8;
+        let words: Vec<u64> = (0..frames)
+            .flat_map(|index| [word(2 * index + 2), 0x1000 + index as u64])
+            .collect();
+
+        let walked = collect(BASE, &words);
+        assert_eq!(walked.len(), MAX_FRAMES);
+        assert_eq!(walked[MAX_FRAMES - 1], 0x1000 + MAX_FRAMES as u64 - 1);
+    }
+
+    #[test]
+    fn stops_when_memory_cannot_be_read() {
+        let words = [word(2), 0xA, word(4), 0xB, 0, 0xC];
+        let read =
+            |address: usize| (address < word(2) as usize).then(|| words[(address - BASE) / 8]);
+
+        assert_eq!(
+            walk(BASE, bounds(words.len()), read).collect::<Vec<_>>(),
+            [0xA]
+        );
+    }
+
+    #[test]
+    fn registry_finds_the_containing_stack() {
+        let registry = StackRegistry::new();
+        let first = StackBounds {
+            start: 0x1000,
+            end: 0x2000,
+        };
+        let second = StackBounds {
+            start: 0x8000,
+            end: 0x9000,
+        };
+        assert!(registry.insert(first));
+        assert!(registry.insert(second));
+
+        assert_eq!(registry.containing(0x1000), Some(first));
+        assert_eq!(registry.containing(0x1FFF), Some(first));
+        assert_eq!(registry.containing(0x8800), Some(second));
+        assert_eq!(registry.containing(0x2000), None);
+        assert_eq!(registry.containing(0x0FFF), None);
+    }
+
+    #[test]
+    fn registry_rejects_empty_bounds_and_fills_up() {
+        let registry = StackRegistry::new();
+        assert!(!registry.insert(StackBounds {
+            start: 0,
+            end: 0x1000
+        }));
+        assert!(!registry.insert(StackBounds {
+            start: 0x1000,
+            end: 0x1000
+        }));
+
+        for index in 0..MAX_REGISTERED_STACKS {
+            let start = 0x1000 * (index + 1);
+            assert!(registry.insert(StackBounds {
+                start,
+                end: start + 0x800
+            }));
+        }
+        assert!(!registry.insert(StackBounds {
+            start: 0x10_0000,
+            end: 0x10_1000
+        }));
+        assert_eq!(registry.containing(0x10_0000), None);
+    }
+
+    #[test]
+    fn capture_skips_unknown_stacks() {
+        let registers = RegisterSnapshot {
+            gprs: [0; 14],
+            rsp: 0x10,
+            rbp: 0x20,
+            rflags: 0,
+            cr0: 0,
+            cr2: 0,
+            cr3: 0,
+            cr4: 0,
+        };
+
+        assert!(Backtrace::capture(&registers).frames().is_empty());
+    }
+}
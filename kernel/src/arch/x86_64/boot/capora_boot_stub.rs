@@ -1,8 +1,19 @@
 //! Module controlling booting using `capora-boot-api`.
 
+use core::{marker::PhantomData, mem};
+
 use boot_api::{BootloaderRequest, BootloaderResponse};
 
-use crate::arch::x86_64::boot::{karchmain, BootloaderMemoryMapIterator, FrameAllocator};
+use crate::{
+    arch::x86_64::{
+        boot::{karchmain, BootloaderData, BootloaderMemoryMapIterator, BootloaderModuleIterator},
+        memory::{
+            reserved::{self, ReservationTag},
+            PhysicalAddress, VirtualAddress,
+        },
+    },
+    boot_progress::{self, BootPhase},
+};
 
 #[used]
 #[link_section = ".bootloader_request"]
@@ -12,21 +23,71 @@ static mut BOOTLOADER_REQUEST: BootloaderRequest = BootloaderRequest {
 };
 
 /// The entry point when booting using `capora-boot-api` protocol.
-#[export_name = "_start"]
+#[cfg_attr(not(test), export_name = "_start")]
 pub unsafe extern "C" fn kbootmain(response: *const BootloaderResponse) -> ! {
+    boot_progress::reach(BootPhase::EntryReached);
+
+    // Booting continues without logging if it cannot be initialized.
     #[cfg(feature = "logging")]
-    crate::logging::init_logging();
+    let _ = crate::logging::init_logging(None);
+    boot_progress::reach(BootPhase::LoggingUp);
 
     let response = unsafe { &*response };
     let memory_map = unsafe {
         core::slice::from_raw_parts(response.memory_map_entries, response.memory_map_entry_count)
     };
 
-    let frame_allocator =
-        FrameAllocator::new(BootloaderMemoryMapIterator::Capora(memory_map.iter()));
+    reserve_boot_structures(response, memory_map);
+
+    let bootloader_data = BootloaderData {
+        memory_map: BootloaderMemoryMapIterator::Capora(memory_map.iter()),
+        modules: BootloaderModuleIterator::None(PhantomData),
+        cmdline: None,
+        // `capora-boot-api` does not describe a direct map, but `capora-boot-stub` leaves physical
+        // memory identity mapped, which acts as a direct map at offset zero.
+        direct_map_offset: VirtualAddress::zero(),
+        framebuffer: None,
+        rsdp: None,
+        kernel_image: None,
+        // `capora-boot-api` only reports usable memory, so none of it is reclaimed.
+        boot_stack: None,
+    };
 
     karchmain(
         response.kernel_virtual_address.cast::<u8>(),
-        frame_allocator,
+        bootloader_data,
     )
 }
+
+/// Registers the frames backing the `capora-boot-api` response block and the memory map it
+/// references as [`ReservationTag::BootStructures`], so that the frame allocator cannot hand them
+/// out while the kernel still references them.
+///
+/// `capora-boot-stub` hands over identity mapped pointers, so their values are their physical
+/// addresses.
+fn reserve_boot_structures(response: &BootloaderResponse, memory_map: &[boot_api::MemoryMapEntry]) {
+    reserve_physical(
+        response as *const BootloaderResponse as u64,
+        mem::size_of::<BootloaderResponse>() as u64,
+    );
+    reserve_physical(
+        memory_map.as_ptr() as u64,
+        mem::size_of_val(memory_map) as u64,
+    );
+}
+
+/// Reserves the `size` bytes located at `address` as [`ReservationTag::BootStructures`].
+fn reserve_physical(address: u64, size: u64) {
+    let Some(range) =
+        PhysicalAddress::new(address).and_then(|address| reserved::frame_range_of(address, size))
+    else {
+        #[cfg(feature = "logging")]
+        log::warn!("Boot structure at {address:#X} ({size:#X} bytes) could not be reserved");
+        return;
+    };
+
+    if let Err(_error) = reserved::reserve(range, ReservationTag::BootStructures) {
+        #[cfg(feature = "logging")]
+        log::warn!("Failed to reserve boot structure {range:?}: {_error}");
+    }
+}
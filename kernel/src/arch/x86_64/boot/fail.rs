@@ -0,0 +1,304 @@
+//! Reporting of failures that occur before the kernel is able to rely on its logging
+//! infrastructure.
+//!
+//! [`boot_fail`] makes a best-effort attempt to get a short message out through every channel that
+//! does not depend on kernel state: the debugcon device, a raw serial port, and a fixed location
+//! in physical memory that can be inspected from a debugger.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::arch::x86_64::{
+    cpu,
+    memory::{direct_map, PhysicalAddress, VirtualAddress},
+};
+
+/// The I/O port of the debugcon device.
+pub const DEBUGCON_PORT: u16 = 0xE9;
+/// The base I/O port of the serial port used to report failures.
+const SERIAL_PORT: u16 = 0x3F8;
+/// The maximum number of times the serial port is polled for each byte before giving up.
+const SERIAL_POLL_LIMIT: u32 = 100_000;
+
+/// The [`PhysicalAddress`] at which the failure code is stored.
+///
+/// This lies in conventional memory below the first MiB that is not used by the firmware after
+/// boot.
+pub const SCRATCH_ADDRESS: PhysicalAddress = PhysicalAddress::new_masked(0x500);
+/// The value stored in the upper 32 bits at [`SCRATCH_ADDRESS`] to mark the lower 32 bits as a
+/// failure code.
+pub const SCRATCH_MAGIC: u64 = 0xB007_FA11;
+
+/// Whether [`boot_fail`] has been entered.
+static FAILING: AtomicBool = AtomicBool::new(false);
+
+/// The various early boot failures.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum BootFailure {
+    /// The Limine bootloader does not support the requested base revision.
+    UnsupportedBaseRevision,
+    /// The bootloader did not provide a memory map.
+    MissingMemoryMap,
+    /// The bootloader did not provide the address at which the kernel was loaded.
+    MissingKernelAddress,
+    /// The bootloader did not provide a valid higher half direct map.
+    MissingDirectMap,
+    /// The kernel was entered with an invalid Multiboot2 magic value.
+    InvalidMultiboot2Magic,
+    /// The Multiboot2 boot information structure was malformed.
+    InvalidMultiboot2Info,
+    /// The boot self-test detected a mismatch between the kernel's mappings and its ELF file.
+    SelfTestFailed,
+    /// A double fault occurred.
+    DoubleFault,
+    /// A double fault occurred while a kernel stack overflowed into its guard page.
+    KernelStackOverflow,
+    /// The processor does not support long mode.
+    LongModeUnsupported,
+    /// Memory that the bootloader reports as available but the kernel keeps using could not be
+    /// reserved.
+    ReservationFailed,
+}
+
+impl BootFailure {
+    /// Returns the numeric code identifying this [`BootFailure`].
+    pub const fn code(self) -> u32 {
+        match self {
+            Self::UnsupportedBaseRevision => 1,
+            Self::MissingMemoryMap => 2,
+            Self::MissingKernelAddress => 3,
+            Self::MissingDirectMap => 4,
+            Self::InvalidMultiboot2Magic => 5,
+            Self::InvalidMultiboot2Info => 6,
+            Self::SelfTestFailed => 7,
+            Self::DoubleFault => 8,
+            Self::KernelStackOverflow => 9,
+            Self::LongModeUnsupported => 10,
+            Self::ReservationFailed => 11,
+        }
+    }
+
+    /// Returns the short message reported for this [`BootFailure`].
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::UnsupportedBaseRevision => "BOOT FAIL 01: unsupported Limine base revision",
+            Self::MissingMemoryMap => "BOOT FAIL 02: missing memory map",
+            Self::MissingKernelAddress => "BOOT FAIL 03: missing kernel address",
+            Self::MissingDirectMap => "BOOT FAIL 04: missing higher half direct map",
+            Self::InvalidMultiboot2Magic => "BOOT FAIL 05: invalid Multiboot2 magic",
+            Self::InvalidMultiboot2Info => "BOOT FAIL 06: invalid Multiboot2 boot information",
+            Self::SelfTestFailed => "BOOT FAIL 07: boot self-test failed",
+            Self::DoubleFault => "BOOT FAIL 08: double fault",
+            Self::KernelStackOverflow => "BOOT FAIL 09: double fault from kernel stack overflow",
+            Self::LongModeUnsupported => "BOOT FAIL 10: processor does not support long mode",
+            Self::ReservationFailed => "BOOT FAIL 11: failed to reserve kernel or module memory",
+        }
+    }
+}
+
+/// Reports `failure` through every available channel and halts.
+///
+/// This does not depend on the logging infrastructure, allocation, or any lock, so it can be used
+/// from any point during boot.
+pub fn boot_fail(failure: BootFailure) -> ! {
+    // SAFETY:
+    // Disabling interrupts has no memory safety implications.
+    unsafe { core::arch::asm!("cli", options(nomem, nostack)) };
+
+    // A failure while reporting a failure only gets to halt, so that a fault in one of the
+    // channels below cannot recurse forever.
+    if !FAILING.swap(true, Ordering::Relaxed) {
+        let message = failure.as_str().as_bytes();
+
+        write_debugcon(message);
+        write_debugcon(b"\n");
+
+        init_serial();
+        write_serial(message);
+        write_serial(b"\r\n");
+
+        store_scratch(failure);
+    }
+
+    cpu::halt_forever()
+}
+
+/// Writes `bytes` to the debugcon device.
+fn write_debugcon(bytes: &[u8]) {
+    for &byte in bytes {
+        outb(DEBUGCON_PORT, byte);
+    }
+}
+
+/// Initializes the serial port at [`SERIAL_PORT`] for 115200 baud, 8 data bits, no parity, and
+/// one stop bit.
+fn init_serial() {
+    outb(SERIAL_PORT + 1, 0x00);
+    outb(SERIAL_PORT + 3, 0x80);
+    outb(SERIAL_PORT, 0x01);
+    outb(SERIAL_PORT + 1, 0x00);
+    outb(SERIAL_PORT + 3, 0x03);
+    outb(SERIAL_PORT + 2, 0xC7);
+}
+
+/// Writes `bytes` to the serial port at [`SERIAL_PORT`], giving up if the port never becomes
+/// ready.
+fn write_serial(bytes: &[u8]) {
+    for &byte in bytes {
+        let mut polls = 0;
+        while inb(SERIAL_PORT + 5) & 0x20 == 0 {
+            polls += 1;
+            if polls >= SERIAL_POLL_LIMIT {
+                return;
+            }
+            core::hint::spin_loop();
+        }
+
+        outb(SERIAL_PORT, byte);
+    }
+}
+
+/// Stores the code of `failure` at [`SCRATCH_ADDRESS`].
+fn store_scratch(failure: BootFailure) {
+    // Before the direct map is known, physical memory is assumed to be identity mapped.
+    let offset = direct_map::offset().unwrap_or(VirtualAddress::zero());
+    let address = offset.value() + SCRATCH_ADDRESS.value() as usize;
+
+    // SAFETY:
+    // `SCRATCH_ADDRESS` is not used by anything else, and the kernel is about to halt.
+    unsafe {
+        core::ptr::write_volatile(
+            address as *mut u64,
+            (SCRATCH_MAGIC << 32) | failure.code() as u64,
+        )
+    }
+}
+
+/// Writes `value` to `port`.
+fn outb(port: u16, value: u8) {
+    // SAFETY:
+    // The ports written by this module are only used to report failures.
+    unsafe {
+        core::arch::asm!("out dx, al", in("dx") port, in("al") value, options(nomem, nostack))
+    }
+}
+
+/// Reads a byte from `port`.
+fn inb(port: u16) -> u8 {
+    let value: u8;
+    // SAFETY:
+    // Reading the serial port's line status register has no side effects.
+    unsafe {
+        core::arch::asm!("in al, dx", in("dx") port, out("al") value, options(nomem, nostack))
+    }
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    /// Every [`BootFailure`] with the code and message it must keep, since both are matched by
+    /// scripts and debuggers that watch for failures.
+    const FAILURES: [(BootFailure, u32, &str); 11] = [
+        (
+            BootFailure::UnsupportedBaseRevision,
+            1,
+            "BOOT FAIL 01: unsupported Limine base revision",
+        ),
+        (
+            BootFailure::MissingMemoryMap,
+            2,
+            "BOOT FAIL 02: missing memory map",
+        ),
+        (
+            BootFailure::MissingKernelAddress,
+            3,
+            "BOOT FAIL 03: missing kernel address",
+        ),
+        (
+            BootFailure::MissingDirectMap,
+            4,
+            "BOOT FAIL 04: missing higher half direct map",
+        ),
+        (
+            BootFailure::InvalidMultiboot2Magic,
+            5,
+            "BOOT FAIL 05: invalid Multiboot2 magic",
+        ),
+        (
+            BootFailure::InvalidMultiboot2Info,
+            6,
+            "BOOT FAIL 06: invalid Multiboot2 boot information",
+        ),
+        (
+            BootFailure::SelfTestFailed,
+            7,
+            "BOOT FAIL 07: boot self-test failed",
+        ),
+        (BootFailure::DoubleFault, 8, "BOOT FAIL 08: double fault"),
+        (
+            BootFailure::KernelStackOverflow,
+            9,
+            "BOOT FAIL 09: double fault from kernel stack overflow",
+        ),
+        (
+            BootFailure::LongModeUnsupported,
+            10,
+            "BOOT FAIL 10: processor does not support long mode",
+        ),
+        (
+            BootFailure::ReservationFailed,
+            11,
+            "BOOT FAIL 11: failed to reserve kernel or module memory",
+        ),
+    ];
+
+    /// Fails to compile when a [`BootFailure`] is added without extending [`FAILURES`].
+    #[allow(dead_code)]
+    const fn listed(failure: BootFailure) {
+        match failure {
+            BootFailure::UnsupportedBaseRevision
+            | BootFailure::MissingMemoryMap
+            | BootFailure::MissingKernelAddress
+            | BootFailure::MissingDirectMap
+            | BootFailure::InvalidMultiboot2Magic
+            | BootFailure::InvalidMultiboot2Info
+            | BootFailure::SelfTestFailed
+            | BootFailure::DoubleFault
+            | BootFailure::KernelStackOverflow
+            | BootFailure::LongModeUnsupported
+            | BootFailure::ReservationFailed => {}
+        }
+    }
+
+    #[test]
+    fn codes_and_messages_are_pinned() {
+        for (failure, code, message) in FAILURES {
+            assert_eq!(failure.code(), code, "{failure:?}");
+            assert_eq!(failure.as_str(), message, "{failure:?}");
+        }
+    }
+
+    #[test]
+    fn codes_are_unique() {
+        let codes: HashSet<_> = FAILURES
+            .iter()
+            .map(|(failure, ..)| failure.code())
+            .collect();
+        assert_eq!(codes.len(), FAILURES.len());
+
+        let failures: HashSet<_> = FAILURES.iter().map(|(failure, ..)| *failure).collect();
+        assert_eq!(failures.len(), FAILURES.len());
+    }
+
+    #[test]
+    fn messages_start_with_their_code() {
+        for (failure, ..) in FAILURES {
+            let prefix = std::format!("BOOT FAIL {:02}: ", failure.code());
+            assert!(failure.as_str().starts_with(&prefix), "{failure:?}");
+            assert!(failure.as_str().is_ascii(), "{failure:?}");
+        }
+    }
+}
@@ -0,0 +1,579 @@
+//! Kernel-owned copies of the information provided by the bootloader.
+//!
+//! Bootloader-provided structures live in memory that is reclaimed once the kernel is done with
+//! them, so everything the kernel needs past early boot is copied into fixed-capacity storage
+//! owned by the kernel before that happens.
+
+use core::fmt;
+
+use crate::{
+    arch::x86_64::{
+        boot::BootloaderData,
+        memory::{Frame, FrameRange, PhysicalAddress, VirtualAddress},
+    },
+    sync::Once,
+};
+
+/// The maximum number of memory map entries that are retained.
+pub const MAX_MEMORY_MAP_ENTRIES: usize = 256;
+/// The maximum number of modules that are retained.
+pub const MAX_MODULES: usize = 16;
+/// The number of bytes available to store strings provided by the bootloader.
+pub const STRING_POOL_SIZE: usize = 4096;
+
+/// The kernel-owned copy of the information provided by the bootloader.
+static BOOT_INFO: Once<BootInfo> = Once::new();
+
+/// Information provided by the bootloader, copied into kernel-owned storage.
+pub struct BootInfo {
+    /// The memory map entries.
+    memory_map: [MemoryMapEntry; MAX_MEMORY_MAP_ENTRIES],
+    /// The number of valid entries in `memory_map`.
+    memory_map_len: usize,
+    /// The modules loaded alongside the kernel.
+    modules: [Module; MAX_MODULES],
+    /// The number of valid entries in `modules`.
+    module_count: usize,
+    /// The kernel command line.
+    cmdline: Option<PooledStr>,
+    /// Storage for the strings referenced by this [`BootInfo`].
+    strings: StringPool,
+    /// The [`VirtualAddress`] at which the direct map of physical memory starts.
+    direct_map_offset: VirtualAddress,
+    /// The framebuffer provided by the bootloader, if any.
+    framebuffer: Option<FramebufferInfo>,
+    /// The [`PhysicalAddress`] of the ACPI RSDP, if the bootloader provided one.
+    rsdp: Option<PhysicalAddress>,
+}
+
+impl BootInfo {
+    /// Creates an empty [`BootInfo`].
+    const fn new() -> Self {
+        Self {
+            memory_map: [MemoryMapEntry::EMPTY; MAX_MEMORY_MAP_ENTRIES],
+            memory_map_len: 0,
+            modules: [Module::EMPTY; MAX_MODULES],
+            module_count: 0,
+            cmdline: None,
+            strings: StringPool::new(),
+            direct_map_offset: VirtualAddress::zero(),
+            framebuffer: None,
+            rsdp: None,
+        }
+    }
+
+    /// Returns the memory map provided by the bootloader.
+    pub fn memory_map(&self) -> &[MemoryMapEntry] {
+        &self.memory_map[..self.memory_map_len]
+    }
+
+    /// Returns an [`Iterator`] over the modules loaded alongside the kernel.
+    pub fn modules(&self) -> impl Iterator<Item = ModuleInfo<'_>> {
+        self.modules[..self.module_count]
+            .iter()
+            .map(|module| ModuleInfo {
+                name: self.strings.get(module.name),
+                address: module.address,
+                size: module.size,
+            })
+    }
+
+    /// Returns the kernel command line, if one was provided.
+    pub fn cmdline(&self) -> Option<&str> {
+        self.cmdline.map(|cmdline| self.strings.get(cmdline))
+    }
+
+    /// Returns the [`VirtualAddress`] at which the direct map of physical memory starts.
+    pub fn direct_map_offset(&self) -> VirtualAddress {
+        self.direct_map_offset
+    }
+
+    /// Returns the framebuffer provided by the bootloader, if any.
+    pub fn framebuffer(&self) -> Option<FramebufferInfo> {
+        self.framebuffer
+    }
+
+    /// Returns the [`PhysicalAddress`] of the ACPI RSDP, if the bootloader provided one.
+    pub fn rsdp(&self) -> Option<PhysicalAddress> {
+        self.rsdp
+    }
+
+    /// Copies the valid `(base, size, kind)` entries of `entries` into the memory map, returning
+    /// the number of valid entries that did not fit.
+    fn copy_memory_map(
+        &mut self,
+        entries: impl IntoIterator<Item = (u64, u64, MemoryKind)>,
+    ) -> usize {
+        let mut dropped = 0;
+        for (base, size, kind) in entries {
+            let Some(entry) = MemoryMapEntry::new(base, size, kind) else {
+                #[cfg(feature = "logging")]
+                log::warn!("Ignoring invalid memory map entry: base {base:#X} size {size:#X}");
+                continue;
+            };
+
+            if !self.push_memory_map_entry(entry) {
+                dropped += 1;
+            }
+        }
+
+        dropped
+    }
+
+    /// Copies the `(name, address, size)` modules of `modules` into the module list, returning
+    /// the number of modules that did not fit.
+    fn copy_modules<'boot>(
+        &mut self,
+        modules: impl IntoIterator<Item = (&'boot [u8], VirtualAddress, u64)>,
+    ) -> usize {
+        let mut dropped = 0;
+        for (name, address, size) in modules {
+            if !self.push_module(name, address, size) {
+                dropped += 1;
+            }
+        }
+
+        dropped
+    }
+
+    /// Appends `entry` to the memory map, returning `false` if the memory map is full.
+    fn push_memory_map_entry(&mut self, entry: MemoryMapEntry) -> bool {
+        let Some(slot) = self.memory_map.get_mut(self.memory_map_len) else {
+            return false;
+        };
+
+        *slot = entry;
+        self.memory_map_len += 1;
+        true
+    }
+
+    /// Appends a module to the list of modules, returning `false` if the module list is full.
+    fn push_module(&mut self, name: &[u8], address: VirtualAddress, size: u64) -> bool {
+        if self.module_count >= MAX_MODULES {
+            return false;
+        }
+
+        let name = self.strings.push(name);
+        self.modules[self.module_count] = Module {
+            name,
+            address,
+            size,
+        };
+        self.module_count += 1;
+        true
+    }
+}
+
+/// An entry in the kernel-owned memory map.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub struct MemoryMapEntry {
+    /// The [`PhysicalAddress`] at which the region starts.
+    pub base: PhysicalAddress,
+    /// The size, in bytes, of the region.
+    pub size: u64,
+    /// The kind of memory that makes up the region.
+    pub kind: MemoryKind,
+}
+
+impl MemoryMapEntry {
+    /// A placeholder [`MemoryMapEntry`] used to fill unused storage.
+    const EMPTY: Self = Self {
+        base: PhysicalAddress::zero(),
+        size: 0,
+        kind: MemoryKind::Reserved,
+    };
+
+    /// Creates a new [`MemoryMapEntry`] from the raw values provided by the bootloader.
+    ///
+    /// If the region is empty or lies outside of the valid physical address range, this function
+    /// returns [`None`].
+    pub fn new(base: u64, size: u64, kind: MemoryKind) -> Option<Self> {
+        if size == 0 {
+            return None;
+        }
+
+        let base = PhysicalAddress::new(base)?;
+        base.value()
+            .checked_add(size - 1)
+            .and_then(PhysicalAddress::new)?;
+
+        Some(Self { base, size, kind })
+    }
+
+    /// Returns the [`FrameRange`] containing this [`MemoryMapEntry`].
+    pub fn frame_range(&self) -> FrameRange {
+        FrameRange::inclusive_range(
+            Frame::containing_address(self.base),
+            Frame::containing_address(PhysicalAddress::new_masked(
+                self.base.value() + (self.size - 1),
+            )),
+        )
+    }
+}
+
+/// The kind of memory described by a [`MemoryMapEntry`].
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum MemoryKind {
+    /// Memory that is free to be used by the kernel.
+    Usable,
+    /// Memory that must not be used.
+    Reserved,
+    /// Memory containing ACPI tables that can be reclaimed once they have been parsed.
+    AcpiReclaimable,
+    /// Memory that must be preserved for ACPI.
+    AcpiNvs,
+    /// Memory that is known to be defective.
+    BadMemory,
+    /// Memory used by the bootloader that can be reclaimed once boot structures are consumed.
+    BootloaderReclaimable,
+    /// Memory containing the kernel and the modules loaded alongside it.
+    KernelAndModules,
+    /// Memory backing a framebuffer.
+    Framebuffer,
+}
+
+impl MemoryKind {
+    /// Every [`MemoryKind`], in declaration order.
+    pub const ALL: [Self; 8] = [
+        Self::Usable,
+        Self::Reserved,
+        Self::AcpiReclaimable,
+        Self::AcpiNvs,
+        Self::BadMemory,
+        Self::BootloaderReclaimable,
+        Self::KernelAndModules,
+        Self::Framebuffer,
+    ];
+
+    /// Returns a human readable name for this [`MemoryKind`].
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Usable => "usable",
+            Self::Reserved => "reserved",
+            Self::AcpiReclaimable => "ACPI reclaimable",
+            Self::AcpiNvs => "ACPI NVS",
+            Self::BadMemory => "bad memory",
+            Self::BootloaderReclaimable => "bootloader reclaimable",
+            Self::KernelAndModules => "kernel and modules",
+            Self::Framebuffer => "framebuffer",
+        }
+    }
+}
+
+/// A linear framebuffer using a direct RGB color model.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub struct FramebufferInfo {
+    /// The [`PhysicalAddress`] at which the framebuffer starts.
+    pub address: PhysicalAddress,
+    /// The number of bytes between the starts of consecutive rows.
+    pub pitch: u32,
+    /// The width, in pixels, of the framebuffer.
+    pub width: u32,
+    /// The height, in pixels, of the framebuffer.
+    pub height: u32,
+    /// The number of bits per pixel.
+    pub bpp: u8,
+    /// The bit offset of the red channel within a pixel.
+    pub red_mask_shift: u8,
+    /// The width, in bits, of the red channel.
+    pub red_mask_size: u8,
+    /// The bit offset of the green channel within a pixel.
+    pub green_mask_shift: u8,
+    /// The width, in bits, of the green channel.
+    pub green_mask_size: u8,
+    /// The bit offset of the blue channel within a pixel.
+    pub blue_mask_shift: u8,
+    /// The width, in bits, of the blue channel.
+    pub blue_mask_size: u8,
+}
+
+/// A module loaded alongside the kernel.
+#[derive(Clone, Copy, Debug)]
+pub struct ModuleInfo<'info> {
+    /// The name of the module.
+    pub name: &'info str,
+    /// The [`VirtualAddress`] at which the module is located.
+    pub address: VirtualAddress,
+    /// The size, in bytes, of the module.
+    pub size: u64,
+}
+
+/// The stored form of a [`ModuleInfo`].
+#[derive(Clone, Copy, Debug)]
+struct Module {
+    /// The name of the module.
+    name: PooledStr,
+    /// The [`VirtualAddress`] at which the module is located.
+    address: VirtualAddress,
+    /// The size, in bytes, of the module.
+    size: u64,
+}
+
+impl Module {
+    /// A placeholder [`Module`] used to fill unused storage.
+    const EMPTY: Self = Self {
+        name: PooledStr { start: 0, len: 0 },
+        address: VirtualAddress::zero(),
+        size: 0,
+    };
+}
+
+/// A reference to a string stored in a [`StringPool`].
+#[derive(Clone, Copy, Debug)]
+struct PooledStr {
+    /// The offset of the string in the [`StringPool`].
+    start: usize,
+    /// The length, in bytes, of the string.
+    len: usize,
+}
+
+/// Bump-allocated storage for strings.
+struct StringPool {
+    /// The storage.
+    bytes: [u8; STRING_POOL_SIZE],
+    /// The number of bytes in use.
+    len: usize,
+}
+
+impl StringPool {
+    /// Creates an empty [`StringPool`].
+    const fn new() -> Self {
+        Self {
+            bytes: [0; STRING_POOL_SIZE],
+            len: 0,
+        }
+    }
+
+    /// Copies the valid UTF-8 prefix of `bytes` into the [`StringPool`].
+    ///
+    /// If there is insufficient space, the string is truncated to fit.
+    fn push(&mut self, bytes: &[u8]) -> PooledStr {
+        let bytes = match core::str::from_utf8(bytes) {
+            Ok(_) => bytes,
+            Err(error) => &bytes[..error.valid_up_to()],
+        };
+
+        let available = STRING_POOL_SIZE - self.len;
+        let mut len = bytes.len().min(available);
+        if len < bytes.len() {
+            #[cfg(feature = "logging")]
+            log::warn!(
+                "String pool exhausted: truncating bootloader string from {} to {len} bytes",
+                bytes.len()
+            );
+
+            while core::str::from_utf8(&bytes[..len]).is_err() {
+                len -= 1;
+            }
+        }
+
+        let start = self.len;
+        self.bytes[start..start + len].copy_from_slice(&bytes[..len]);
+        self.len += len;
+
+        PooledStr { start, len }
+    }
+
+    /// Returns the string referenced by `string`.
+    fn get(&self, string: PooledStr) -> &str {
+        core::str::from_utf8(&self.bytes[string.start..string.start + string.len])
+            .unwrap_or_default()
+    }
+}
+
+impl fmt::Debug for BootInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        struct Modules<'info>(&'info BootInfo);
+
+        impl fmt::Debug for Modules<'_> {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.debug_list().entries(self.0.modules()).finish()
+            }
+        }
+
+        f.debug_struct("BootInfo")
+            .field("memory_map", &self.memory_map())
+            .field("modules", &Modules(self))
+            .field("cmdline", &self.cmdline())
+            .field("direct_map_offset", &self.direct_map_offset)
+            .field("framebuffer", &self.framebuffer)
+            .field("rsdp", &self.rsdp)
+            .finish()
+    }
+}
+
+/// Copies everything referenced by `data` into kernel-owned storage, returning the resulting
+/// [`BootInfo`].
+///
+/// If there is insufficient space to hold the bootloader-provided data, the excess is dropped
+/// with a warning.
+///
+/// # Panics
+/// Panics if called more than once.
+pub(super) fn take_snapshot(data: BootloaderData) -> &'static BootInfo {
+    assert!(!BOOT_INFO.is_initialized(), "boot snapshot already taken");
+
+    let mut boot_info = BootInfo::new();
+
+    let dropped_entries = boot_info.copy_memory_map(data.memory_map);
+    if dropped_entries != 0 {
+        #[cfg(feature = "logging")]
+        log::warn!(
+            "!!! Memory map truncated: {dropped_entries} entries beyond the first \
+            {MAX_MEMORY_MAP_ENTRIES} were dropped !!!"
+        );
+    }
+
+    let dropped_modules = boot_info.copy_modules(data.modules);
+    if dropped_modules != 0 {
+        #[cfg(feature = "logging")]
+        log::warn!(
+            "!!! Module list truncated: {dropped_modules} modules beyond the first \
+            {MAX_MODULES} were dropped !!!"
+        );
+    }
+
+    if let Some(cmdline) = data.cmdline {
+        boot_info.cmdline = Some(boot_info.strings.push(cmdline));
+    }
+
+    boot_info.direct_map_offset = data.direct_map_offset;
+    boot_info.framebuffer = data.framebuffer;
+    boot_info.rsdp = data.rsdp;
+
+    BOOT_INFO.init(boot_info)
+}
+
+/// Returns the kernel-owned [`BootInfo`], if [`take_snapshot`] has completed.
+pub fn boot_info() -> Option<&'static BootInfo> {
+    BOOT_INFO.get()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn memory_map_entries_are_validated() {
+        let entry = MemoryMapEntry::new(0x1000, 0x2000, MemoryKind::Usable).unwrap();
+        assert_eq!(entry.base, PhysicalAddress::new(0x1000).unwrap());
+        assert_eq!(entry.frame_range().size_in_frames(), 2);
+        assert_eq!(
+            MemoryMapEntry::new(0x1800, 0x1000, MemoryKind::Usable)
+                .unwrap()
+                .frame_range()
+                .size_in_frames(),
+            2
+        );
+
+        assert_eq!(MemoryMapEntry::new(0x1000, 0, MemoryKind::Usable), None);
+        assert_eq!(MemoryMapEntry::new(u64::MAX, 1, MemoryKind::Usable), None);
+        assert_eq!(
+            MemoryMapEntry::new(0x1000, u64::MAX, MemoryKind::Usable),
+            None
+        );
+    }
+
+    #[test]
+    fn memory_map_is_copied() {
+        let mut boot_info = BootInfo::new();
+        let dropped = boot_info.copy_memory_map([
+            (0x0, 0x9_F000, MemoryKind::Usable),
+            (0x9_F000, 0, MemoryKind::Reserved),
+            (0x10_0000, 0x10_0000, MemoryKind::BootloaderReclaimable),
+            (u64::MAX, 0x1000, MemoryKind::Reserved),
+        ]);
+
+        assert_eq!(dropped, 0);
+        assert_eq!(
+            boot_info.memory_map(),
+            [
+                MemoryMapEntry::new(0x0, 0x9_F000, MemoryKind::Usable).unwrap(),
+                MemoryMapEntry::new(0x10_0000, 0x10_0000, MemoryKind::BootloaderReclaimable)
+                    .unwrap(),
+            ]
+        );
+    }
+
+    #[test]
+    fn memory_map_is_truncated() {
+        let mut boot_info = BootInfo::new();
+        let entries = (0..MAX_MEMORY_MAP_ENTRIES as u64 + 3)
+            .map(|index| (index * 0x2000, 0x1000, MemoryKind::Usable));
+
+        assert_eq!(boot_info.copy_memory_map(entries), 3);
+        assert_eq!(boot_info.memory_map().len(), MAX_MEMORY_MAP_ENTRIES);
+        assert_eq!(
+            boot_info.memory_map().last().unwrap().base.value(),
+            (MAX_MEMORY_MAP_ENTRIES as u64 - 1) * 0x2000
+        );
+
+        // Invalid entries are not counted as dropped.
+        assert_eq!(boot_info.copy_memory_map([(0, 0, MemoryKind::Usable)]), 0);
+    }
+
+    #[test]
+    fn modules_are_copied() {
+        let mut boot_info = BootInfo::new();
+        let address = VirtualAddress::new_canonical(0xFFFF_8000_0010_0000);
+        let dropped = boot_info.copy_modules([
+            (b"/boot/init".as_slice(), address, 0x3000),
+            (b"".as_slice(), address + 0x3000, 0x10),
+        ]);
+        assert_eq!(dropped, 0);
+
+        let modules = boot_info.modules().collect::<Vec<_>>();
+        assert_eq!(modules.len(), 2);
+        assert_eq!(modules[0].name, "/boot/init");
+        assert_eq!(modules[0].address, address);
+        assert_eq!(modules[0].size, 0x3000);
+        assert_eq!(modules[1].name, "");
+        assert_eq!(modules[1].address, address + 0x3000);
+    }
+
+    #[test]
+    fn modules_are_truncated() {
+        let mut boot_info = BootInfo::new();
+        let modules = (0..MAX_MODULES + 2).map(|index| {
+            (
+                b"module".as_slice(),
+                VirtualAddress::new_canonical(index * 0x1000),
+                0x1000,
+            )
+        });
+
+        assert_eq!(boot_info.copy_modules(modules), 2);
+        assert_eq!(boot_info.modules().count(), MAX_MODULES);
+        assert!(boot_info.modules().all(|module| module.name == "module"));
+    }
+
+    #[test]
+    fn strings_are_copied_up_to_invalid_utf8() {
+        let mut pool = StringPool::new();
+        let hello = pool.push(b"hello");
+        let valid_prefix = pool.push(b"ab\xFFcd");
+        let empty = pool.push(b"");
+
+        assert_eq!(pool.get(hello), "hello");
+        assert_eq!(pool.get(valid_prefix), "ab");
+        assert_eq!(pool.get(empty), "");
+        assert_eq!(pool.len, 7);
+    }
+
+    #[test]
+    fn strings_are_truncated_on_character_boundaries() {
+        let mut pool = StringPool::new();
+        let filler = pool.push(&[b'a'; STRING_POOL_SIZE - 3]);
+        assert_eq!(pool.get(filler).len(), STRING_POOL_SIZE - 3);
+
+        // Only one of the two-byte characters fits in the remaining three bytes.
+        let truncated = pool.push("éé".as_bytes());
+        assert_eq!(pool.get(truncated), "é");
+
+        let full = pool.push(b"x");
+        assert_eq!(pool.get(full), "x");
+        let exhausted = pool.push(b"y");
+        assert_eq!(pool.get(exhausted), "");
+        assert_eq!(pool.len, STRING_POOL_SIZE);
+    }
+}
@@ -1,13 +1,30 @@
 //! Module controlling booting using the Limine boot protocol.
 
+use core::{ffi::CStr, marker::PhantomData, mem};
+
 use crate::{
-    arch::x86_64::boot::{karchmain, BootloaderMemoryMapIterator, FrameAllocator},
+    arch::x86_64::{
+        boot::{
+            fail::{boot_fail, BootFailure},
+            info::FramebufferInfo,
+            karchmain, BootloaderData, BootloaderMemoryMapIterator, BootloaderModuleIterator,
+        },
+        cpu,
+        memory::{
+            reserved::{self, ReservationTag},
+            Page, PageRange, PhysicalAddress, VirtualAddress,
+        },
+    },
+    boot_progress::{self, BootPhase},
     cells::ControlledModificationCell,
 };
 
 /// The base revision of the Limine boot protocol that this kernel supports.
 pub const LIMINE_BASE_REVISION: u64 = 2;
 
+/// The smallest stack that the Limine boot protocol enters the kernel on.
+const LIMINE_MIN_STACK_SIZE: usize = 64 * 1024;
+
 /// The first Limine magic number.
 pub const LIMINE_MAGIC_0: u64 = 0xc7b1dd30df4c8b88;
 /// The second Limine magic number.
@@ -44,39 +61,250 @@ static LIMINE_KERNEL_ADDRESS_REQUEST: ControlledModificationCell<Request<KernelA
 static LIMINE_HIGHER_DIRECT_MAP_REQUEST: ControlledModificationCell<Request<DirectMapRequest>> =
     ControlledModificationCell::new(Request::new(DirectMapRequest::new()));
 
+/// A request for the file from which the kernel was loaded, along with its command line.
+#[used]
+#[link_section = ".limine_requests"]
+static LIMINE_KERNEL_FILE_REQUEST: ControlledModificationCell<Request<KernelFileRequest>> =
+    ControlledModificationCell::new(Request::new(KernelFileRequest::new()));
+
+/// A request for the modules loaded alongside the kernel.
+#[used]
+#[link_section = ".limine_requests"]
+static LIMINE_MODULE_REQUEST: ControlledModificationCell<Request<ModuleRequest>> =
+    ControlledModificationCell::new(Request::new(ModuleRequest::new()));
+
+/// A request for the framebuffers available to the kernel.
+#[used]
+#[link_section = ".limine_requests"]
+static LIMINE_FRAMEBUFFER_REQUEST: ControlledModificationCell<Request<FramebufferRequest>> =
+    ControlledModificationCell::new(Request::new(FramebufferRequest::new()));
+
+/// A request for the location of the ACPI RSDP.
+#[used]
+#[link_section = ".limine_requests"]
+static LIMINE_RSDP_REQUEST: ControlledModificationCell<Request<RsdpRequest>> =
+    ControlledModificationCell::new(Request::new(RsdpRequest::new()));
+
 /// The entry point when using the Limine boot protocol.
-#[cfg_attr(not(feature = "capora-boot-api"), export_name = "_start")]
+#[cfg_attr(not(any(test, feature = "capora-boot-api")), export_name = "_start")]
 pub unsafe extern "C" fn kbootmain() -> ! {
-    #[cfg(feature = "logging")]
-    crate::logging::init_logging();
+    // Taken first, so that it lies close to the top of the stack the bootloader provided.
+    let stack_pointer = cpu::stack_pointer();
 
-    if LIMINE_BASE_REVISION_TAG.get()[2] == LIMINE_BASE_REVISION {
-        loop {}
+    // The bootloader zeroes the revision word if it supports the requested base revision.
+    if LIMINE_BASE_REVISION_TAG.read_volatile()[2] == LIMINE_BASE_REVISION {
+        boot_fail(BootFailure::UnsupportedBaseRevision)
     }
+    boot_progress::reach(BootPhase::EntryReached);
+
+    let kernel_file = LIMINE_KERNEL_FILE_REQUEST
+        .get()
+        .response()
+        .and_then(|response| response.body())
+        .and_then(|response| response.kernel_file());
+    let cmdline = kernel_file.map(File::cmdline);
+
+    // Booting continues without logging if it cannot be initialized.
+    #[cfg(feature = "logging")]
+    let _ = crate::logging::init_logging(
+        cmdline.and_then(|cmdline| core::str::from_utf8(cmdline).ok()),
+    );
+    boot_progress::reach(BootPhase::LoggingUp);
 
     let Some(memory_map) = LIMINE_MEMORY_MAP_REQUEST
         .get()
         .response()
         .and_then(|response| response.body())
     else {
-        loop {}
+        boot_fail(BootFailure::MissingMemoryMap)
     };
     let memory_map: &'static MemoryMapResponse = memory_map;
 
-    let frame_allocator = FrameAllocator::new(BootloaderMemoryMapIterator::Limine(
-        memory_map.as_slice().iter(),
-    ));
+    let Some(kernel_address) = LIMINE_KERNEL_ADDRESS_REQUEST
+        .get()
+        .response()
+        .and_then(|response| response.body())
+    else {
+        boot_fail(BootFailure::MissingKernelAddress)
+    };
 
-    let Some(kernel_virtual_address) = LIMINE_KERNEL_ADDRESS_REQUEST
+    let Some(direct_map_offset) = LIMINE_HIGHER_DIRECT_MAP_REQUEST
         .get()
         .response()
         .and_then(|response| response.body())
+        .and_then(|direct_map| VirtualAddress::new(direct_map.offset() as usize))
     else {
-        loop {}
+        boot_fail(BootFailure::MissingDirectMap)
     };
-    let kernel_virtual_address = kernel_virtual_address.virtual_base;
+    reserve_boot_structures(kernel_address, direct_map_offset.value() as u64);
 
-    karchmain(kernel_virtual_address as *const u8, frame_allocator)
+    let framebuffer = LIMINE_FRAMEBUFFER_REQUEST
+        .get()
+        .response()
+        .and_then(|response| response.body())
+        .and_then(|response| response.as_slice().first())
+        .and_then(|framebuffer| framebuffer.info(direct_map_offset.value() as u64));
+
+    let kernel_image = kernel_file.map(File::as_bytes);
+    let modules = LIMINE_MODULE_REQUEST
+        .get()
+        .response()
+        .and_then(|response| response.body())
+        .map_or(BootloaderModuleIterator::None(PhantomData), |response| {
+            BootloaderModuleIterator::Limine(response.as_slice().iter())
+        });
+
+    let bootloader_data = BootloaderData {
+        memory_map: BootloaderMemoryMapIterator::Limine(memory_map.as_slice().iter()),
+        modules,
+        cmdline,
+        direct_map_offset,
+        framebuffer,
+        rsdp: LIMINE_RSDP_REQUEST
+            .get()
+            .response()
+            .and_then(|response| response.body())
+            .and_then(|response| response.physical_address(direct_map_offset.value() as u64)),
+        kernel_image,
+        boot_stack: boot_stack(stack_pointer),
+    };
+
+    let kernel_virtual_address = kernel_address.virtual_base;
+
+    karchmain(kernel_virtual_address as *const u8, bootloader_data)
+}
+
+/// Returns the [`PageRange`] of the stack the bootloader entered the kernel on, given the
+/// `stack_pointer` at entry.
+///
+/// The bootloader does not report where its stack ends, so the smallest stack it guarantees is
+/// assumed. The [`Page`] above the one containing `stack_pointer` is included as well, since the
+/// frame of [`kbootmain()`] already lies above `stack_pointer` when it is taken.
+fn boot_stack(stack_pointer: VirtualAddress) -> Option<PageRange> {
+    let bottom = stack_pointer.checked_sub(LIMINE_MIN_STACK_SIZE)?;
+    let top = stack_pointer.checked_add(Page::PAGE_SIZE)?;
+
+    PageRange::inclusive_range(
+        Page::containing_address(bottom),
+        Page::containing_address(top),
+    )
+}
+
+/// Registers the frames backing the Limine requests and all non-null responses as
+/// [`ReservationTag::BootStructures`], so that the frame allocator cannot hand them out while the
+/// kernel still references them.
+///
+/// Responses are located in the higher half direct map, which starts at `direct_map_offset`.
+fn reserve_boot_structures(kernel_address: &KernelAddressResponse, direct_map_offset: u64) {
+    extern "C" {
+        #[link_name = "limine_requests_start"]
+        static LIMINE_REQUESTS_START: core::ffi::c_void;
+        #[link_name = "limine_requests_end"]
+        static LIMINE_REQUESTS_END: core::ffi::c_void;
+    }
+
+    let requests_start = core::ptr::addr_of!(LIMINE_REQUESTS_START) as u64;
+    let requests_end = core::ptr::addr_of!(LIMINE_REQUESTS_END) as u64;
+    reserve_physical(
+        PhysicalAddress::new(
+            requests_start
+                .wrapping_sub(kernel_address.virtual_base)
+                .wrapping_add(kernel_address.physical_base),
+        ),
+        requests_end - requests_start,
+    );
+
+    reserve_response(LIMINE_ENTRY_POINT_REQUEST.get(), direct_map_offset);
+    reserve_response(LIMINE_KERNEL_ADDRESS_REQUEST.get(), direct_map_offset);
+    reserve_response(LIMINE_HIGHER_DIRECT_MAP_REQUEST.get(), direct_map_offset);
+
+    if let Some(kernel_file) = reserve_response(LIMINE_KERNEL_FILE_REQUEST.get(), direct_map_offset)
+        .and_then(|response| response.kernel_file())
+    {
+        reserve_file(kernel_file, direct_map_offset);
+    }
+
+    if let Some(modules) = reserve_response(LIMINE_MODULE_REQUEST.get(), direct_map_offset) {
+        reserve_direct_mapped(
+            modules.modules.cast_const(),
+            modules.module_count as usize,
+            direct_map_offset,
+        );
+
+        for &module in modules.as_slice() {
+            reserve_file(module, direct_map_offset);
+        }
+    }
+
+    if let Some(memory_map) = reserve_response(LIMINE_MEMORY_MAP_REQUEST.get(), direct_map_offset) {
+        reserve_direct_mapped(
+            memory_map.entries.cast_const(),
+            memory_map.entry_count as usize,
+            direct_map_offset,
+        );
+
+        for &entry in memory_map.as_slice() {
+            reserve_direct_mapped(entry as *const MemoryMapEntry, 1, direct_map_offset);
+        }
+    }
+}
+
+/// Reserves the [`File`] structure and the strings it references.
+///
+/// The contents of the [`File`] are not reserved, since they are located in memory that is never
+/// reclaimed.
+fn reserve_file(file: &File, direct_map_offset: u64) {
+    reserve_direct_mapped(file as *const File, 1, direct_map_offset);
+    reserve_direct_mapped(
+        file.path.cast::<u8>(),
+        file.path().len() + 1,
+        direct_map_offset,
+    );
+    reserve_direct_mapped(
+        file.cmdline.cast::<u8>(),
+        file.cmdline().len() + 1,
+        direct_map_offset,
+    );
+}
+
+/// Reserves the [`Response`] to `request`, if it exists, returning the body of the [`Response`]
+/// if it is supported.
+fn reserve_response<T: LimineRequest>(
+    request: &Request<T>,
+    direct_map_offset: u64,
+) -> Option<&T::Response> {
+    reserve_direct_mapped(request.response.cast_const(), 1, direct_map_offset);
+
+    request.response().and_then(|response| response.body())
+}
+
+/// Reserves the memory backing `count` contiguous `T`s located at `ptr` in the higher half direct
+/// map.
+///
+/// Null pointers are ignored.
+fn reserve_direct_mapped<T>(ptr: *const T, count: usize, direct_map_offset: u64) {
+    if ptr.is_null() {
+        return;
+    }
+
+    reserve_physical(
+        PhysicalAddress::new((ptr as u64).wrapping_sub(direct_map_offset)),
+        (mem::size_of::<T>() * count) as u64,
+    );
+}
+
+/// Reserves the `size` bytes located at `address` as [`ReservationTag::BootStructures`].
+fn reserve_physical(address: Option<PhysicalAddress>, size: u64) {
+    let Some(range) = address.and_then(|address| reserved::frame_range_of(address, size)) else {
+        #[cfg(feature = "logging")]
+        log::warn!("Limine structure at {address:?} ({size:#X} bytes) could not be reserved");
+        return;
+    };
+
+    if let Err(_error) = reserved::reserve(range, ReservationTag::BootStructures) {
+        #[cfg(feature = "logging")]
+        log::warn!("Failed to reserve Limine structure {range:?}: {_error}");
+    }
 }
 
 /// The base structure of a [`LimineRequest`].
@@ -89,6 +317,10 @@ pub struct Request<T: LimineRequest> {
     body: T,
 }
 
+// SAFETY:
+// The response pointer is only written by the bootloader before the kernel is entered, and the
+// response it points at is never written by the kernel, so sending a [`Request`] to another
+// thread is sound whenever its body can be sent.
 unsafe impl<T: LimineRequest + Send> Send for Request<T> {}
 
 impl<T: LimineRequest> Request<T> {
@@ -104,7 +336,17 @@ impl<T: LimineRequest> Request<T> {
     /// Returns [`&Response<T::Response>`] if the request is supported, otherwise, if the
     /// [`LimineResponse`] is unsupported or was not successfully processed, this returns [`None`].
     pub fn response(&self) -> Option<&Response<T::Response>> {
-        unsafe { self.response.as_ref() }
+        // The response pointer is written by the bootloader, so it must be read as written rather
+        // than assumed to still be null.
+        //
+        // SAFETY:
+        // `self.response` is a valid, aligned field of `self`.
+        let response = unsafe { core::ptr::read_volatile(&self.response) };
+
+        // SAFETY:
+        // The bootloader either leaves the pointer null or points it at a response that lives for
+        // as long as the request.
+        unsafe { response.as_ref() }
     }
 }
 
@@ -198,13 +440,24 @@ impl LimineResponse for MemoryMapResponse {
 }
 
 impl MemoryMapResponse {
-    pub fn as_slice(&self) -> &'static [&'static MemoryMapEntry] {
+    /// Returns the memory map entries provided by the bootloader.
+    ///
+    /// The entries live in bootloader-reclaimable memory, so they must be copied before that memory
+    /// is reclaimed.
+    pub(super) fn as_slice(&self) -> &'static [&'static MemoryMapEntry] {
         assert!(!self.entries.is_null());
+        // SAFETY:
+        // The bootloader points `entries` at an array of `entry_count` entry pointers, which lives
+        // until bootloader-reclaimable memory is reclaimed.
         let slice = unsafe { core::slice::from_raw_parts(self.entries, self.entry_count as usize) };
         for entry in slice {
             assert!(!entry.is_null());
         }
 
+        // SAFETY:
+        // Every pointer in the array was checked to be non-null above, and the bootloader points
+        // each at a valid [`MemoryMapEntry`] that lives as long as the array, so the array can be
+        // viewed as references.
         unsafe {
             core::slice::from_raw_parts(
                 self.entries.cast::<&MemoryMapEntry>(),
@@ -313,3 +566,355 @@ pub struct DirectMapResponse {
 impl LimineResponse for DirectMapResponse {
     const REVISION: u64 = 0;
 }
+
+impl DirectMapResponse {
+    /// Returns the virtual address at which the higher half direct map of physical memory starts.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+}
+
+#[repr(transparent)]
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub struct KernelFileRequest();
+
+impl KernelFileRequest {
+    pub const fn new() -> Self {
+        Self()
+    }
+}
+
+impl LimineRequest for KernelFileRequest {
+    const ID: [u64; 4] = [
+        LIMINE_MAGIC_0,
+        LIMINE_MAGIC_1,
+        0xad97e90e83f1ed67,
+        0x31eb5d1c5ff23b69,
+    ];
+    const REVISION: u64 = 0;
+    type Response = KernelFileResponse;
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub struct KernelFileResponse {
+    kernel_file: *mut File,
+}
+
+impl LimineResponse for KernelFileResponse {
+    const REVISION: u64 = 0;
+}
+
+impl KernelFileResponse {
+    /// Returns the [`File`] from which the kernel was loaded.
+    ///
+    /// The [`File`] lives in bootloader-reclaimable memory, so anything the kernel needs from it
+    /// must be copied before that memory is reclaimed.
+    pub(super) fn kernel_file(&self) -> Option<&'static File> {
+        // SAFETY:
+        // The bootloader either leaves `kernel_file` null or points it at a valid [`File`] that
+        // lives until bootloader-reclaimable memory is reclaimed.
+        unsafe { self.kernel_file.as_ref() }
+    }
+}
+
+#[repr(transparent)]
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ModuleRequest();
+
+impl ModuleRequest {
+    pub const fn new() -> Self {
+        Self()
+    }
+}
+
+impl LimineRequest for ModuleRequest {
+    const ID: [u64; 4] = [
+        LIMINE_MAGIC_0,
+        LIMINE_MAGIC_1,
+        0x3e7e279702be32af,
+        0xca1c4f3bd1280cee,
+    ];
+    const REVISION: u64 = 0;
+    type Response = ModuleResponse;
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ModuleResponse {
+    module_count: u64,
+    modules: *mut *mut File,
+}
+
+impl LimineResponse for ModuleResponse {
+    const REVISION: u64 = 0;
+}
+
+impl ModuleResponse {
+    /// Returns the [`File`]s of the modules loaded alongside the kernel.
+    ///
+    /// The [`File`]s live in bootloader-reclaimable memory, so anything the kernel needs from them
+    /// must be copied before that memory is reclaimed.
+    pub(super) fn as_slice(&self) -> &'static [&'static File] {
+        if self.module_count == 0 {
+            return &[];
+        }
+
+        assert!(!self.modules.is_null());
+        // SAFETY:
+        // The bootloader points `modules` at an array of `module_count` module pointers, which
+        // lives until bootloader-reclaimable memory is reclaimed.
+        let slice =
+            unsafe { core::slice::from_raw_parts(self.modules, self.module_count as usize) };
+        for module in slice {
+            assert!(!module.is_null());
+        }
+
+        // SAFETY:
+        // Every pointer in the array was checked to be non-null above, and the bootloader points
+        // each at a valid [`File`] that lives as long as the array, so the array can be viewed as
+        // references.
+        unsafe {
+            core::slice::from_raw_parts(self.modules.cast::<&File>(), self.module_count as usize)
+        }
+    }
+}
+
+#[repr(transparent)]
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub struct RsdpRequest();
+
+impl RsdpRequest {
+    pub const fn new() -> Self {
+        Self()
+    }
+}
+
+impl LimineRequest for RsdpRequest {
+    const ID: [u64; 4] = [
+        LIMINE_MAGIC_0,
+        LIMINE_MAGIC_1,
+        0xc5e77b6b397e7b43,
+        0x27637845accdcf3c,
+    ];
+    const REVISION: u64 = 0;
+    type Response = RsdpResponse;
+}
+
+#[repr(transparent)]
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub struct RsdpResponse {
+    address: u64,
+}
+
+impl LimineResponse for RsdpResponse {
+    const REVISION: u64 = 0;
+}
+
+impl RsdpResponse {
+    /// Returns the [`PhysicalAddress`] of the RSDP.
+    ///
+    /// Before base revision 3, the bootloader reports the address in the higher half direct map,
+    /// which starts at `direct_map_offset`.
+    pub fn physical_address(&self, direct_map_offset: u64) -> Option<PhysicalAddress> {
+        if self.address == 0 {
+            return None;
+        }
+
+        let address = self
+            .address
+            .checked_sub(direct_map_offset)
+            .unwrap_or(self.address);
+        PhysicalAddress::new(address)
+    }
+}
+
+#[repr(transparent)]
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub struct FramebufferRequest();
+
+impl FramebufferRequest {
+    pub const fn new() -> Self {
+        Self()
+    }
+}
+
+impl LimineRequest for FramebufferRequest {
+    const ID: [u64; 4] = [
+        LIMINE_MAGIC_0,
+        LIMINE_MAGIC_1,
+        0x9d5827dcd881dd75,
+        0xa3148604f6fab11b,
+    ];
+    const REVISION: u64 = 0;
+    type Response = FramebufferResponse;
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub struct FramebufferResponse {
+    framebuffer_count: u64,
+    framebuffers: *mut *mut Framebuffer,
+}
+
+impl LimineResponse for FramebufferResponse {
+    const REVISION: u64 = 0;
+}
+
+impl FramebufferResponse {
+    /// Returns the [`Framebuffer`]s provided by the bootloader.
+    ///
+    /// The [`Framebuffer`]s live in bootloader-reclaimable memory, so anything the kernel needs
+    /// from them must be copied before that memory is reclaimed.
+    pub(super) fn as_slice(&self) -> &'static [&'static Framebuffer] {
+        if self.framebuffer_count == 0 || self.framebuffers.is_null() {
+            return &[];
+        }
+
+        // SAFETY:
+        // The bootloader points `framebuffers` at an array of `framebuffer_count` framebuffer
+        // pointers, which lives until bootloader-reclaimable memory is reclaimed.
+        let slice = unsafe {
+            core::slice::from_raw_parts(self.framebuffers, self.framebuffer_count as usize)
+        };
+        for framebuffer in slice {
+            assert!(!framebuffer.is_null());
+        }
+
+        // SAFETY:
+        // Every pointer in the array was checked to be non-null above, and the bootloader points
+        // each at a valid [`Framebuffer`] that lives as long as the array, so the array can be
+        // viewed as references.
+        unsafe {
+            core::slice::from_raw_parts(
+                self.framebuffers.cast::<&Framebuffer>(),
+                self.framebuffer_count as usize,
+            )
+        }
+    }
+}
+
+/// A framebuffer set up by the bootloader.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Framebuffer {
+    address: *mut u8,
+    width: u64,
+    height: u64,
+    pitch: u64,
+    bpp: u16,
+    memory_model: u8,
+    red_mask_size: u8,
+    red_mask_shift: u8,
+    green_mask_size: u8,
+    green_mask_shift: u8,
+    blue_mask_size: u8,
+    blue_mask_shift: u8,
+    unused: [u8; 7],
+    edid_size: u64,
+    edid: *mut u8,
+}
+
+impl Framebuffer {
+    /// The memory model of framebuffers using a direct RGB color model.
+    pub const MEMORY_MODEL_RGB: u8 = 1;
+
+    /// Converts this [`Framebuffer`], located in the higher half direct map starting at
+    /// `direct_map_offset`, into a [`FramebufferInfo`].
+    ///
+    /// Returns [`None`] if the framebuffer does not use a direct RGB color model or its fields do
+    /// not fit a [`FramebufferInfo`].
+    pub fn info(&self, direct_map_offset: u64) -> Option<FramebufferInfo> {
+        if self.memory_model != Self::MEMORY_MODEL_RGB {
+            return None;
+        }
+
+        Some(FramebufferInfo {
+            address: PhysicalAddress::new((self.address as u64).wrapping_sub(direct_map_offset))?,
+            pitch: self.pitch.try_into().ok()?,
+            width: self.width.try_into().ok()?,
+            height: self.height.try_into().ok()?,
+            bpp: self.bpp.try_into().ok()?,
+            red_mask_shift: self.red_mask_shift,
+            red_mask_size: self.red_mask_size,
+            green_mask_shift: self.green_mask_shift,
+            green_mask_size: self.green_mask_size,
+            blue_mask_shift: self.blue_mask_shift,
+            blue_mask_size: self.blue_mask_size,
+        })
+    }
+}
+
+/// A file loaded by the bootloader.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub struct File {
+    revision: u64,
+    address: *mut u8,
+    size: u64,
+    path: *const core::ffi::c_char,
+    cmdline: *const core::ffi::c_char,
+    media_type: u32,
+    unused: u32,
+    tftp_ip: u32,
+    tftp_port: u32,
+    partition_index: u32,
+    mbr_disk_id: u32,
+    gpt_disk_uuid: [u8; 16],
+    gpt_part_uuid: [u8; 16],
+    part_uuid: [u8; 16],
+}
+
+impl File {
+    /// Returns the address at which the contents of the [`File`] are located.
+    pub fn address(&self) -> *mut u8 {
+        self.address
+    }
+
+    /// Returns the size, in bytes, of the [`File`].
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Returns the contents of the [`File`].
+    pub(super) fn as_bytes(&self) -> &'static [u8] {
+        if self.address.is_null() {
+            return &[];
+        }
+
+        // SAFETY:
+        // The bootloader points `address` at the `size` bytes of the file, which it loads into
+        // memory that is never reclaimed.
+        unsafe { core::slice::from_raw_parts(self.address, self.size as usize) }
+    }
+
+    /// Returns the path of the [`File`], without the null terminator.
+    ///
+    /// The path lives in bootloader-reclaimable memory, so it must be copied before that memory is
+    /// reclaimed.
+    pub(super) fn path(&self) -> &'static [u8] {
+        if self.path.is_null() {
+            return &[];
+        }
+
+        // SAFETY:
+        // The bootloader points `path` at a null-terminated string, which lives until
+        // bootloader-reclaimable memory is reclaimed.
+        unsafe { CStr::from_ptr(self.path) }.to_bytes()
+    }
+
+    /// Returns the command line associated with the [`File`], without the null terminator.
+    ///
+    /// The command line lives in bootloader-reclaimable memory, so it must be copied before that
+    /// memory is reclaimed.
+    pub(super) fn cmdline(&self) -> &'static [u8] {
+        if self.cmdline.is_null() {
+            return &[];
+        }
+
+        // SAFETY:
+        // The bootloader points `cmdline` at a null-terminated string, which lives until
+        // bootloader-reclaimable memory is reclaimed.
+        unsafe { CStr::from_ptr(self.cmdline) }.to_bytes()
+    }
+}
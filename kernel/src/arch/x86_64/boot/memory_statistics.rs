@@ -0,0 +1,403 @@
+//! Statistics about the physical memory described by the bootloader's memory map.
+//!
+//! The statistics are computed once during boot by [`init()`], which also logs the memory map, and
+//! remain available through [`get()`] to subsystems that size themselves after physical memory.
+
+use core::{fmt, ops::Range};
+
+use crate::{
+    arch::x86_64::boot::info::{MemoryKind, MemoryMapEntry},
+    sync::Once,
+};
+
+/// The [`MemoryStatistics`] of the memory map provided by the bootloader.
+static MEMORY_STATISTICS: Once<MemoryStatistics> = Once::new();
+
+/// Computes the [`MemoryStatistics`] of `memory_map`, records them for [`get()`], and logs
+/// `memory_map` along with a summary of the statistics.
+///
+/// # Panics
+/// Panics if the statistics have already been recorded.
+pub(super) fn init(memory_map: &[MemoryMapEntry]) {
+    let _statistics = MEMORY_STATISTICS.init(MemoryStatistics::from_memory_map(memory_map));
+
+    #[cfg(feature = "logging")]
+    {
+        log::info!("Memory map:");
+        for entry in memory_map {
+            log::info!(
+                "  {:#018x}-{:#018x} {:>10} {}",
+                entry.base.value(),
+                entry.base.value() + entry.size.saturating_sub(1),
+                ByteSize(entry.size),
+                entry.kind.name()
+            );
+        }
+        log::info!(
+            "{} usable, {} reserved, largest usable region {}",
+            ByteSize(_statistics.usable_bytes()),
+            ByteSize(_statistics.reserved_bytes()),
+            ByteSize(_statistics.largest_usable_region())
+        );
+        log::info!("Memory map entries: {}", EntryCounts(_statistics));
+    }
+}
+
+/// Returns the [`MemoryStatistics`] of the bootloader's memory map, or [`None`] if they have not
+/// been computed yet.
+pub fn get() -> Option<&'static MemoryStatistics> {
+    MEMORY_STATISTICS.get()
+}
+
+/// Aggregate information about a memory map.
+///
+/// Entries of the same [`MemoryKind`] that overlap are only counted once, and empty entries are
+/// ignored.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub struct MemoryStatistics {
+    /// The number of bytes of each [`MemoryKind`], in the order of [`MemoryKind::ALL`].
+    bytes: [u64; MemoryKind::ALL.len()],
+    /// The number of non-empty entries of each [`MemoryKind`], in the order of
+    /// [`MemoryKind::ALL`].
+    entries: [usize; MemoryKind::ALL.len()],
+    /// The size, in bytes, of the largest contiguous region of usable memory.
+    largest_usable_region: u64,
+}
+
+impl MemoryStatistics {
+    /// Computes the [`MemoryStatistics`] of `memory_map`.
+    ///
+    /// Adjacent usable entries form a single contiguous region.
+    pub fn from_memory_map(memory_map: &[MemoryMapEntry]) -> Self {
+        let mut statistics = Self {
+            bytes: [0; MemoryKind::ALL.len()],
+            entries: [0; MemoryKind::ALL.len()],
+            largest_usable_region: 0,
+        };
+
+        for kind in MemoryKind::ALL {
+            statistics.entries[kind as usize] = memory_map
+                .iter()
+                .filter(|entry| entry.kind == kind && entry.size != 0)
+                .count();
+
+            for run in merged_runs(memory_map, kind) {
+                let size = run.end - run.start;
+                statistics.bytes[kind as usize] += size;
+                if kind == MemoryKind::Usable {
+                    statistics.largest_usable_region = statistics.largest_usable_region.max(size);
+                }
+            }
+        }
+
+        statistics
+    }
+
+    /// Returns the number of bytes of usable memory.
+    pub const fn usable_bytes(&self) -> u64 {
+        self.bytes_of(MemoryKind::Usable)
+    }
+
+    /// Returns the number of bytes of memory of every [`MemoryKind`] other than
+    /// [`MemoryKind::Usable`].
+    pub fn reserved_bytes(&self) -> u64 {
+        MemoryKind::ALL
+            .into_iter()
+            .filter(|&kind| kind != MemoryKind::Usable)
+            .map(|kind| self.bytes_of(kind))
+            .sum()
+    }
+
+    /// Returns the size, in bytes, of the largest contiguous region of usable memory.
+    pub const fn largest_usable_region(&self) -> u64 {
+        self.largest_usable_region
+    }
+
+    /// Returns the number of bytes of memory of the given [`MemoryKind`].
+    pub const fn bytes_of(&self, kind: MemoryKind) -> u64 {
+        self.bytes[kind as usize]
+    }
+
+    /// Returns the number of non-empty entries of the given [`MemoryKind`].
+    pub const fn entries_of(&self, kind: MemoryKind) -> usize {
+        self.entries[kind as usize]
+    }
+}
+
+/// Returns an [`Iterator`] over the maximal runs of contiguous physical addresses covered by the
+/// entries of `memory_map` of the given [`MemoryKind`], in ascending order.
+fn merged_runs(
+    memory_map: &[MemoryMapEntry],
+    kind: MemoryKind,
+) -> impl Iterator<Item = Range<u64>> + '_ {
+    let spans = move || {
+        memory_map
+            .iter()
+            .filter(move |entry| entry.kind == kind && entry.size != 0)
+            .map(|entry| entry.base.value()..entry.base.value() + entry.size)
+    };
+
+    let mut covered = 0;
+    core::iter::from_fn(move || {
+        let start = spans()
+            .filter(|span| span.end > covered)
+            .map(|span| span.start.max(covered))
+            .min()?;
+
+        let mut end = start;
+        while let Some(extended) = spans()
+            .filter(|span| span.start <= end && span.end > end)
+            .map(|span| span.end)
+            .max()
+        {
+            end = extended;
+        }
+
+        covered = end;
+        Some(start..end)
+    })
+}
+
+/// Formats a number of bytes in the largest binary unit that keeps the value at least one, with
+/// at most one decimal place.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub struct ByteSize(pub u64);
+
+impl ByteSize {
+    /// The binary units, from largest to smallest, along with the number of bytes in each.
+    const UNITS: [(u64, &'static str); 4] = [
+        (1 << 40, "TiB"),
+        (1 << 30, "GiB"),
+        (1 << 20, "MiB"),
+        (1 << 10, "KiB"),
+    ];
+}
+
+impl fmt::Display for ByteSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut buffer = FormatBuffer::new();
+        let result = match Self::UNITS.into_iter().find(|&(unit, _)| self.0 >= unit) {
+            Some((unit, name)) => {
+                let whole = self.0 / unit;
+                let tenths = self.0 % unit * 10 / unit;
+                if tenths == 0 {
+                    fmt::Write::write_fmt(&mut buffer, format_args!("{whole} {name}"))
+                } else {
+                    fmt::Write::write_fmt(&mut buffer, format_args!("{whole}.{tenths} {name}"))
+                }
+            }
+            None => fmt::Write::write_fmt(&mut buffer, format_args!("{} B", self.0)),
+        };
+
+        result.and_then(|()| f.pad(buffer.as_str()))
+    }
+}
+
+/// Formats the number of entries of each [`MemoryKind`] present in some [`MemoryStatistics`].
+#[cfg(feature = "logging")]
+struct EntryCounts<'statistics>(&'statistics MemoryStatistics);
+
+#[cfg(feature = "logging")]
+impl fmt::Display for EntryCounts<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut first = true;
+        for kind in MemoryKind::ALL {
+            let count = self.0.entries_of(kind);
+            if count == 0 {
+                continue;
+            }
+
+            if !first {
+                f.write_str(", ")?;
+            }
+            write!(f, "{count} {}", kind.name())?;
+            first = false;
+        }
+
+        Ok(())
+    }
+}
+
+/// A fixed-capacity buffer that text can be formatted into, so that the result can be padded.
+struct FormatBuffer {
+    /// The bytes written so far.
+    bytes: [u8; Self::CAPACITY],
+    /// The number of valid bytes in `bytes`.
+    len: usize,
+}
+
+impl FormatBuffer {
+    /// The number of bytes a [`FormatBuffer`] can hold.
+    const CAPACITY: usize = 32;
+
+    /// Returns an empty [`FormatBuffer`].
+    const fn new() -> Self {
+        Self {
+            bytes: [0; Self::CAPACITY],
+            len: 0,
+        }
+    }
+
+    /// Returns the text written to this [`FormatBuffer`].
+    fn as_str(&self) -> &str {
+        // Only whole `str`s are ever written to the buffer.
+        core::str::from_utf8(&self.bytes[..self.len]).unwrap_or_default()
+    }
+}
+
+impl fmt::Write for FormatBuffer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let end = self.len + s.len();
+        let destination = self.bytes.get_mut(self.len..end).ok_or(fmt::Error)?;
+
+        destination.copy_from_slice(s.as_bytes());
+        self.len = end;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::vec::Vec;
+
+    use super::*;
+    use crate::arch::x86_64::memory::PhysicalAddress;
+
+    /// Returns a [`MemoryMapEntry`] of `kind` covering `size` bytes from `base`.
+    fn entry(base: u64, size: u64, kind: MemoryKind) -> MemoryMapEntry {
+        MemoryMapEntry {
+            base: PhysicalAddress::new_masked(base),
+            size,
+            kind,
+        }
+    }
+
+    #[test]
+    fn memory_statistics_aggregation() {
+        let memory_map = [
+            entry(0x0, 0x9_F000, MemoryKind::Usable),
+            entry(0x9_F000, 0x6_1000, MemoryKind::Reserved),
+            entry(0x10_0000, 0x10_0000, MemoryKind::Usable),
+            // Adjacent to the previous entry, so both form one contiguous region.
+            entry(0x20_0000, 0x20_0000, MemoryKind::Usable),
+            // Overlaps the previous entry, so only its last 0x10_0000 bytes are new.
+            entry(0x30_0000, 0x20_0000, MemoryKind::Usable),
+            entry(0x60_0000, 0, MemoryKind::Usable),
+            entry(0x60_0000, 0x1000, MemoryKind::AcpiNvs),
+            entry(0x60_0000, 0x1000, MemoryKind::AcpiNvs),
+            entry(0x70_0000, 0x8_0000, MemoryKind::Usable),
+        ];
+        let statistics = MemoryStatistics::from_memory_map(&memory_map);
+
+        assert_eq!(statistics.usable_bytes(), 0x9_F000 + 0x40_0000 + 0x8_0000);
+        assert_eq!(statistics.largest_usable_region(), 0x40_0000);
+        assert_eq!(statistics.bytes_of(MemoryKind::Reserved), 0x6_1000);
+        assert_eq!(statistics.bytes_of(MemoryKind::AcpiNvs), 0x1000);
+        assert_eq!(statistics.reserved_bytes(), 0x6_1000 + 0x1000);
+
+        assert_eq!(statistics.entries_of(MemoryKind::Usable), 5);
+        assert_eq!(statistics.entries_of(MemoryKind::AcpiNvs), 2);
+        assert_eq!(statistics.entries_of(MemoryKind::Framebuffer), 0);
+
+        let empty = MemoryStatistics::from_memory_map(&[entry(0x1000, 0, MemoryKind::Usable)]);
+        assert_eq!(empty.usable_bytes(), 0);
+        assert_eq!(empty.largest_usable_region(), 0);
+        assert_eq!(empty.entries_of(MemoryKind::Usable), 0);
+    }
+
+    #[test]
+    fn runs_merge_unsorted_overlapping_and_nested_entries() {
+        let memory_map = [
+            entry(0x8000, 0x1000, MemoryKind::Usable),
+            entry(0x1000, 0x3000, MemoryKind::Usable),
+            entry(0x2000, 0x1000, MemoryKind::Usable),
+            entry(0x5000, 0x1000, MemoryKind::Reserved),
+            entry(0x3000, 0x2000, MemoryKind::Usable),
+            entry(0x9000, 0x1000, MemoryKind::Usable),
+            entry(0xC000, 0, MemoryKind::Usable),
+        ];
+
+        assert_eq!(
+            merged_runs(&memory_map, MemoryKind::Usable).collect::<Vec<_>>(),
+            [0x1000..0x5000, 0x8000..0xA000]
+        );
+        let mut reserved = merged_runs(&memory_map, MemoryKind::Reserved);
+        assert_eq!(reserved.next(), Some(0x5000..0x6000));
+        assert_eq!(reserved.next(), None);
+        assert_eq!(merged_runs(&memory_map, MemoryKind::AcpiNvs).count(), 0);
+        assert_eq!(merged_runs(&[], MemoryKind::Usable).count(), 0);
+    }
+
+    #[test]
+    fn runs_reach_the_top_of_physical_memory() {
+        let top = PhysicalAddress::ADDRESS_MASK + 1;
+        let memory_map = [
+            entry(top - 0x2000, 0x2000, MemoryKind::Usable),
+            entry(0, 0x1000, MemoryKind::Usable),
+        ];
+
+        assert_eq!(
+            merged_runs(&memory_map, MemoryKind::Usable).collect::<Vec<_>>(),
+            [0..0x1000, top - 0x2000..top]
+        );
+        let statistics = MemoryStatistics::from_memory_map(&memory_map);
+        assert_eq!(statistics.usable_bytes(), 0x3000);
+        assert_eq!(statistics.largest_usable_region(), 0x2000);
+    }
+
+    #[test]
+    fn other_kinds_do_not_split_usable_regions() {
+        let memory_map = [
+            entry(0x1000, 0x4000, MemoryKind::Usable),
+            entry(0x2000, 0x1000, MemoryKind::Reserved),
+            entry(0x5000, 0x1000, MemoryKind::Usable),
+        ];
+        let statistics = MemoryStatistics::from_memory_map(&memory_map);
+
+        assert_eq!(statistics.usable_bytes(), 0x5000);
+        assert_eq!(statistics.largest_usable_region(), 0x5000);
+        assert_eq!(statistics.reserved_bytes(), 0x1000);
+    }
+
+    #[test]
+    fn byte_size_formatting() {
+        use core::fmt::Write;
+
+        let mut buffer = FormatBuffer::new();
+        write!(
+            buffer,
+            "{}|{}|{}|{:>8}",
+            ByteSize(512),
+            ByteSize(640 * 1024),
+            ByteSize(0x18_0000),
+            ByteSize(1 << 30)
+        )
+        .unwrap();
+        assert_eq!(buffer.as_str(), "512 B|640 KiB|1.5 MiB|   1 GiB");
+    }
+
+    #[test]
+    fn byte_sizes_truncate_to_one_decimal() {
+        use std::format;
+
+        assert_eq!(format!("{}", ByteSize(0)), "0 B");
+        assert_eq!(format!("{}", ByteSize(1023)), "1023 B");
+        assert_eq!(format!("{}", ByteSize(1024)), "1 KiB");
+        assert_eq!(format!("{}", ByteSize(2047)), "1.9 KiB");
+        assert_eq!(format!("{}", ByteSize(5 << 40)), "5 TiB");
+        assert_eq!(format!("{}", ByteSize(u64::MAX)), "16777215.9 TiB");
+        assert_eq!(format!("{:<7}|", ByteSize(1 << 20)), "1 MiB  |");
+    }
+
+    #[test]
+    fn format_buffers_reject_overflowing_writes() {
+        use core::fmt::Write;
+
+        let mut buffer = FormatBuffer::new();
+        assert!(buffer
+            .write_str(&"x".repeat(FormatBuffer::CAPACITY))
+            .is_ok());
+        assert!(buffer.write_str("y").is_err());
+        assert_eq!(buffer.as_str().len(), FormatBuffer::CAPACITY);
+    }
+}
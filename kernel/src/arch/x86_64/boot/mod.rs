@@ -1,16 +1,34 @@
 //! Module controlling booting for the kernel on `x86_64`, parsing bootloader structures and
 //! transferring to [`kmain`].
 
-use core::{mem, slice};
+use core::{marker::PhantomData, ops::Range, slice};
+
+use fail::{boot_fail, BootFailure};
+use info::{FramebufferInfo, MemoryKind, MemoryMapEntry};
 
 use crate::{
+    acpi::{
+        self,
+        madt::{self, Madt},
+    },
     arch::x86_64::{
+        apic,
+        backtrace::{self, StackBounds},
+        boot_progress, cpu, interrupt_stats,
         memory::{
-            Frame, FrameRange, FrameRangeIter, Page, PageRange, PhysicalAddress, VirtualAddress,
+            direct_map, heap, kernel_memory,
+            mapper::Mapper,
+            reserved::{self, ReservationTag},
+            stack, Frame, FrameRange, FrameRangeIter, PageRange, PhysicalAddress, VirtualAddress,
         },
-        structures::idt::{load_idt, InterruptStackFrame},
-        IDT,
+        per_cpu, ps2, smp,
+        structures::idt::{load_idt, InterruptDescriptorTable, InterruptStackFrame, IstSetting},
+        structures::{gdt::GlobalDescriptorTable, tss::TaskStateSegment},
+        syscall, usermode, DOUBLE_FAULT_STACK, DOUBLE_FAULT_STACK_SIZE, GDT, IDT, PRIVILEGE_STACK,
+        PRIVILEGE_STACK_SIZE, TSS,
     },
+    boot_progress::BootPhase,
+    cells::{capability::CapabilitySlot, untyped::Untyped},
     kmain,
 };
 
@@ -20,64 +38,113 @@ pub mod capora_boot_stub;
 #[cfg(feature = "limine-boot-api")]
 pub mod limine;
 
+#[cfg(feature = "multiboot2-boot-api")]
+pub mod multiboot2;
+
+#[cfg(all(
+    feature = "multiboot2-boot-api",
+    any(feature = "capora-boot-api", feature = "limine-boot-api")
+))]
+compile_error!(
+    "`multiboot2-boot-api` links the kernel at a fixed address and cannot be combined with \
+    `capora-boot-api` or `limine-boot-api`"
+);
+
+pub mod fail;
+pub mod info;
+pub mod memory_statistics;
+#[cfg(feature = "boot-selftest")]
+pub mod selftest;
+
 /// The entry point for bootloader-independent `x86_64` specific setup.
-pub fn karchmain(kernel_address: *const u8, allocator: FrameAllocator) -> ! {
+fn karchmain(_kernel_address: *const u8, bootloader_data: BootloaderData) -> ! {
+    per_cpu::init_bootstrap();
+    setup_gdt();
     setup_idt();
+    syscall::init_bootstrap();
 
-    let mut pml4e_index = 512;
-    let mut pml3e_index = 512;
-    let mut pml2e_index = 512;
+    #[cfg(feature = "boot-selftest")]
+    let kernel_image = bootloader_data.kernel_image;
+    let boot_stack = bootloader_data.boot_stack;
+    if let Some(pages) = boot_stack {
+        backtrace::register_stack(StackBounds::from_pages(pages));
+    }
+
+    let boot_info = info::take_snapshot(bootloader_data);
+    if let Some(cmdline) = boot_info.cmdline() {
+        crate::options::init(cmdline);
+    }
 
-    let mut page_table_page_count: usize = 1;
-    let mut kernel_backing_frame_count: usize = 0;
+    direct_map::init(boot_info.direct_map_offset());
+    #[cfg(feature = "logging")]
+    log::debug!("Direct map located at {:?}", boot_info.direct_map_offset());
+    memory_statistics::init(boot_info.memory_map());
 
-    let program_headers = get_phdrs();
-    for (index, program_header) in program_headers.iter().enumerate() {
+    if let Err(_error) = boot_progress::reserve_scratch() {
         #[cfg(feature = "logging")]
-        log::trace!("Program Header {index}: {:?}", program_header);
+        log::warn!("failed to reserve boot progress scratch frame: {_error}");
+    }
+    crate::boot_progress::reach(BootPhase::MemoryMapParsed);
 
-        if program_header.segment_type() != 1 {
-            continue;
+    discover_acpi(boot_info.rsdp());
+
+    #[cfg(feature = "framebuffer-logging")]
+    if let Some(framebuffer) = boot_info.framebuffer() {
+        match crate::arch::x86_64::logging::init_framebuffer_logging(framebuffer) {
+            Ok(sink) => match crate::logging::register_sink(sink, boot_info.cmdline()) {
+                Ok(()) => log::info!(
+                    "Framebuffer logging on {}x{} framebuffer at {:?}",
+                    framebuffer.width,
+                    framebuffer.height,
+                    framebuffer.address
+                ),
+                Err(error) => log::warn!("framebuffer: {error}, disabled"),
+            },
+            Err(error) => log::warn!("framebuffer: {error}, disabled"),
         }
+    }
 
-        let page = Page::containing_address(VirtualAddress::new_canonical(
-            kernel_address as usize + program_header.virtual_address() as usize,
-        ));
-        let end_page = Page::containing_address(VirtualAddress::new_canonical(
-            (kernel_address as u64
-                + program_header.virtual_address()
-                + (program_header.memory_size() - 1)) as usize,
-        ));
-        let page_range = PageRange::inclusive_range(page, end_page).unwrap();
+    #[cfg(feature = "boot-selftest")]
+    selftest::verify_segments(_kernel_address, get_phdrs(), kernel_image);
 
-        for page in page_range {
-            if page.pml4e_index() != pml4e_index {
-                pml4e_index = page.pml4e_index();
-                page_table_page_count += 1;
+    let _released_frames = reserved::release(ReservationTag::BootStructures);
+    #[cfg(feature = "logging")]
+    log::debug!("Boot snapshot complete: released {_released_frames} reserved frames");
 
-                pml3e_index = 512;
-                pml2e_index = 512;
-            }
-            if page.pml3e_index() != pml3e_index {
-                pml3e_index = page.pml3e_index();
-                page_table_page_count += 1;
+    let mut allocator = FrameAllocator::new(boot_info.memory_map());
+    reclaim_bootloader_memory(&mut allocator, boot_info.memory_map(), boot_stack);
+    setup_heap(&mut allocator);
 
-                pml2e_index = 512;
-            }
-            if page.pml2e_index() != pml2e_index {
-                pml2e_index = page.pml2e_index();
-                page_table_page_count += 1;
-            }
+    match crate::cells::cnode::init_root(|| allocator.allocate_frame()) {
+        #[cfg(feature = "logging")]
+        Ok(()) => log::debug!(
+            "Root CNode created with {} slots",
+            1usize << crate::cells::cnode::ROOT_SIZE_BITS
+        ),
+        #[cfg(not(feature = "logging"))]
+        Ok(()) => {}
+        Err(_error) => {
+            #[cfg(feature = "logging")]
+            log::error!("failed to create root CNode: {_error}");
         }
-        kernel_backing_frame_count += page_range.size_in_pages();
     }
 
-    #[cfg(feature = "logging")]
-    log::trace!("{allocator:#X?}");
+    crate::boot_progress::reach(BootPhase::PageTablesBuilt);
+
+    create_initial_untyped(allocator);
+    setup_scheduling();
+    setup_keyboard();
+    crate::boot_progress::reach(BootPhase::InterruptsEnabled);
+
+    // Application processors are not started yet, so the bootstrap processor is the only one
+    // taking part in bring-up.
+    smp::complete_global_init(1);
+    smp::wait_for_all_online();
 
     kmain()
 }
 
+#[cfg(feature = "boot-selftest")]
 pub fn get_phdrs() -> &'static [ProgramHeader] {
     extern "C" {
         #[link_name = "phdrs_start"]
@@ -96,7 +163,7 @@ pub fn get_phdrs() -> &'static [ProgramHeader] {
     let phdrs = unsafe {
         core::slice::from_raw_parts(
             start_ptr.cast::<ProgramHeader>(),
-            size / mem::size_of::<ProgramHeader>(),
+            size / core::mem::size_of::<ProgramHeader>(),
         )
     };
 
@@ -137,6 +204,11 @@ impl ProgramHeader {
         u64::from_ne_bytes(slice)
     }
 
+    pub fn file_size(&self) -> u64 {
+        let slice = *self.slice[32..40].first_chunk::<8>().unwrap();
+        u64::from_ne_bytes(slice)
+    }
+
     pub fn memory_size(&self) -> u64 {
         let slice = *self.slice[40..48].first_chunk::<8>().unwrap();
         u64::from_ne_bytes(slice)
@@ -151,105 +223,1133 @@ impl core::fmt::Debug for ProgramHeader {
         debug_struct.field("flags", &self.flags());
         debug_struct.field("offset", &self.offset());
         debug_struct.field("virtual_address", &self.virtual_address());
+        debug_struct.field("file_size", &self.file_size());
         debug_struct.field("memory_size", &self.memory_size());
 
         debug_struct.finish()
     }
 }
 
-pub fn setup_idt() {
-    let idt = unsafe { &mut *core::ptr::addr_of_mut!(IDT) };
+/// Loads the kernel's global descriptor table and task state segment, whose kernel stack is
+/// [`PRIVILEGE_STACK`].
+///
+/// The stacks of the task state segment are registered for backtraces.
+pub fn setup_gdt() {
+    let tss = TSS.call_once(|| {
+        let mut tss = TaskStateSegment::new();
+        let stack_bottom = PRIVILEGE_STACK.0.get() as usize;
+        let stack_top = stack_bottom + PRIVILEGE_STACK_SIZE;
+        tss.set_kernel_stack(VirtualAddress::new_canonical(stack_top));
+        backtrace::register_stack(StackBounds {
+            start: stack_bottom,
+            end: stack_top,
+        });
+        let stack_bottom = DOUBLE_FAULT_STACK.0.get() as usize;
+        let stack_top = stack_bottom + DOUBLE_FAULT_STACK_SIZE;
+        tss.set_interrupt_stack(DOUBLE_FAULT_IST, VirtualAddress::new_canonical(stack_top));
+        backtrace::register_stack(StackBounds {
+            start: stack_bottom,
+            end: stack_top,
+        });
+        tss
+    });
+    let gdt = GDT.call_once(|| GlobalDescriptorTable::new(tss));
 
-    idt.double_fault.set_handler_fn(double_fault_handler);
+    // SAFETY:
+    // This runs once, before the IDT is set up, and nothing relies on the segments of the table
+    // left behind by the bootloader.
+    unsafe { gdt.load() }
+}
+
+pub fn setup_idt() {
+    let idt = IDT.call_once(|| {
+        let mut idt = InterruptDescriptorTable::new();
+        idt.double_fault.set_handler_fn(double_fault_handler);
+        // SAFETY:
+        // `setup_gdt()` points the `DOUBLE_FAULT_IST` entry at `DOUBLE_FAULT_STACK`, which
+        // nothing else uses.
+        unsafe { idt.double_fault.set_stack(DOUBLE_FAULT_IST) };
+        apic::install_handlers(&mut idt);
+        ps2::install_handlers(&mut idt);
+        usermode::install_handlers(&mut idt);
+        idt
+    });
 
     unsafe { load_idt(idt) }
 }
 
+/// Locates the ACPI tables from the RSDP at `rsdp`, logging the processors and I/O APICs listed by
+/// the MADT.
+fn discover_acpi(rsdp: Option<PhysicalAddress>) {
+    let Some(rsdp) = rsdp else {
+        #[cfg(feature = "logging")]
+        log::warn!("bootloader did not provide an ACPI RSDP");
+        return;
+    };
+
+    let _root = match acpi::init(rsdp) {
+        Ok(root) => root,
+        Err(_error) => {
+            #[cfg(feature = "logging")]
+            log::warn!("ACPI tables unavailable: {_error}");
+            return;
+        }
+    };
+    #[cfg(feature = "logging")]
+    log::debug!(
+        "ACPI {:?} at {rsdp:?} references {} tables",
+        _root.kind(),
+        _root.entries().count()
+    );
+
+    let madt = match acpi::find_table(&madt::SIGNATURE).map(Madt::parse) {
+        Some(Ok(madt)) => madt,
+        Some(Err(_error)) => {
+            #[cfg(feature = "logging")]
+            log::warn!("invalid MADT: {_error}");
+            return;
+        }
+        None => {
+            #[cfg(feature = "logging")]
+            log::warn!("no MADT found");
+            return;
+        }
+    };
+
+    #[cfg(feature = "logging")]
+    {
+        use crate::acpi::madt::MadtEntry;
+
+        for entry in madt.entries() {
+            match entry {
+                Ok(MadtEntry::LocalApic(local_apic)) => log::info!(
+                    "CPU {}: APIC ID {}{}",
+                    local_apic.processor_id,
+                    local_apic.apic_id,
+                    if local_apic.is_usable() {
+                        ""
+                    } else {
+                        " (disabled)"
+                    }
+                ),
+                Ok(MadtEntry::IoApic(io_apic)) => log::info!(
+                    "I/O APIC {} at {:#x}, GSI base {}",
+                    io_apic.id,
+                    io_apic.address,
+                    io_apic.gsi_base
+                ),
+                Ok(MadtEntry::InterruptSourceOverride(iso)) => log::debug!(
+                    "ISA IRQ {} routed to GSI {} (flags {:#x})",
+                    iso.source,
+                    iso.gsi,
+                    iso.flags
+                ),
+                Ok(MadtEntry::Other { .. }) => {}
+                Err(error) => log::warn!("skipping {error}"),
+            }
+        }
+    }
+    #[cfg(not(feature = "logging"))]
+    let _ = madt;
+}
+
+/// Makes the bootloader-reclaimable memory available to `allocator`, after reserving the parts of
+/// it that the kernel keeps using: the active page tables and `boot_stack`, the stack the
+/// bootloader entered the kernel on.
+///
+/// Nothing is reclaimed unless all of those parts could be reserved.
+fn reclaim_bootloader_memory(
+    allocator: &mut FrameAllocator,
+    memory_map: &[MemoryMapEntry],
+    boot_stack: Option<PageRange>,
+) {
+    let is_reclaimable = |frame: Frame| {
+        memory_map.iter().any(|entry| {
+            entry.kind == MemoryKind::BootloaderReclaimable
+                && entry.frame_range().contains_address(frame.base_address())
+        })
+    };
+    let mut result = Ok(());
+    let mut reserve = |frame: Frame| {
+        if result.is_ok() && is_reclaimable(frame) {
+            result = reserved::reserve(
+                FrameRange::inclusive_range(frame, frame),
+                ReservationTag::LiveBootMemory,
+            );
+        }
+    };
+
+    // SAFETY:
+    // Only the bootstrap processor is running, and nothing modifies the page tables while the
+    // `Mapper` walks them.
+    let Some(mapper) = (unsafe { Mapper::active() }) else {
+        #[cfg(feature = "logging")]
+        log::warn!("bootloader memory not reclaimed: direct map is not initialized");
+        return;
+    };
+    mapper.for_each_table(&mut reserve);
+    for page in boot_stack.into_iter().flatten() {
+        if let Some(address) = mapper.translate(page.base_address()) {
+            reserve(Frame::containing_address(address));
+        }
+    }
+
+    if let Err(_error) = result {
+        #[cfg(feature = "logging")]
+        log::warn!("bootloader memory not reclaimed: {_error}");
+        return;
+    }
+
+    // SAFETY:
+    // Everything the kernel needs from the bootloader's structures was copied into the boot
+    // snapshot, which lives in the kernel image, and the reservations protecting those structures
+    // have been released. The page tables and the boot stack were reserved above.
+    let _reclaimed = unsafe { allocator.reclaim_bootloader_memory() };
+    #[cfg(feature = "logging")]
+    log::info!(
+        "Reclaimed {_reclaimed} frames ({} KiB) of bootloader memory",
+        _reclaimed * Frame::FRAME_SIZE / 1024
+    );
+}
+
+/// The minimum number of bytes of the kernel heap mapped during boot.
+const MIN_INITIAL_HEAP_SIZE: usize = 1024 * 1024;
+/// The maximum number of bytes of the kernel heap mapped during boot.
+const MAX_INITIAL_HEAP_SIZE: usize = 8 * 1024 * 1024;
+
+/// Maps the first part of the kernel heap with frames from `allocator`.
+///
+/// The initial heap covers 1/256th of usable memory, clamped between [`MIN_INITIAL_HEAP_SIZE`]
+/// and [`MAX_INITIAL_HEAP_SIZE`].
+fn setup_heap(allocator: &mut FrameAllocator) {
+    let usable_bytes = memory_statistics::get().map_or(0, |statistics| statistics.usable_bytes());
+    let initial_size = usize::try_from(usable_bytes / 256)
+        .unwrap_or(usize::MAX)
+        .clamp(MIN_INITIAL_HEAP_SIZE, MAX_INITIAL_HEAP_SIZE);
+
+    // SAFETY:
+    // Only the bootstrap processor is running, and nothing else modifies the page tables while it
+    // boots.
+    let Some(mut mapper) = (unsafe { Mapper::active() }) else {
+        #[cfg(feature = "logging")]
+        log::error!("kernel heap unavailable: direct map is not initialized");
+        return;
+    };
+
+    // SAFETY:
+    // `mapper` manages the active page tables, and `allocator` only hands out unused frames.
+    match unsafe { heap::init(&mut mapper, allocator, initial_size) } {
+        #[cfg(feature = "logging")]
+        Ok(()) => log::debug!(
+            "Kernel heap mapped at {:?}, {} KiB",
+            heap::HEAP_START,
+            heap::mapped_size() / 1024
+        ),
+        #[cfg(not(feature = "logging"))]
+        Ok(()) => {}
+        Err(_error) => {
+            #[cfg(feature = "logging")]
+            log::error!("failed to initialize kernel heap: {_error}");
+        }
+    }
+}
+
+/// Creates the idle thread of the bootstrap processor and, if the local APIC timer can be
+/// started, enables interrupts so that its tick preempts threads.
+fn setup_scheduling() {
+    if let Err(_error) = crate::scheduler::init_cpu() {
+        #[cfg(feature = "logging")]
+        log::error!("failed to create idle thread: {_error}");
+    }
+
+    match apic::init_bootstrap() {
+        Ok(_initial_count) => {
+            #[cfg(feature = "logging")]
+            log::info!(
+                "Local APIC timer ticking at {} Hz (initial count {_initial_count})",
+                apic::TICK_HZ
+            );
+
+            crate::scheduler::enable_preemption();
+            crate::boot_progress::start_watchdog(apic::ticks(), u64::from(apic::TICK_HZ));
+            // SAFETY:
+            // Every vector the local APIC and the masked legacy PICs can raise has a handler, and
+            // the timer handler only touches state guarded against interrupts.
+            unsafe { cpu::enable_interrupts() }
+        }
+        Err(_error) => {
+            #[cfg(feature = "logging")]
+            log::warn!("local APIC timer unavailable: {_error}, threads are not preempted");
+        }
+    }
+}
+
+/// Initializes the PS/2 keyboard, if one is present.
+fn setup_keyboard() {
+    match ps2::init() {
+        Ok(_interrupt_driven) => {
+            #[cfg(feature = "logging")]
+            log::info!(
+                "PS/2 keyboard ready ({})",
+                if _interrupt_driven {
+                    "interrupt-driven"
+                } else {
+                    "polled"
+                }
+            );
+        }
+        Err(_error) => {
+            #[cfg(feature = "logging")]
+            log::warn!("PS/2 keyboard unavailable: {_error}");
+        }
+    }
+}
+
+/// The interrupt vector of the double fault exception.
+const DOUBLE_FAULT_VECTOR: u8 = 8;
+/// The interrupt stack table entry the double fault handler runs on.
+const DOUBLE_FAULT_IST: IstSetting = IstSetting::Ist1;
+
 extern "x86-interrupt" fn double_fault_handler(frame: InterruptStackFrame, code: u64) -> ! {
-    loop {}
+    interrupt_stats::record(DOUBLE_FAULT_VECTOR);
+
+    // A kernel stack overflow faults on the stack's guard page, and the processor then faults
+    // again pushing the page fault's frame onto the same stack. `cr2` still holds the address of
+    // the guard page.
+    let fault_address = VirtualAddress::new_canonical(cpu::read_cr2() as usize);
+    if stack::overflowed_stack(fault_address).is_some() {
+        boot_fail(BootFailure::KernelStackOverflow)
+    }
+
+    boot_fail(BootFailure::DoubleFault)
+}
+
+#[cfg(feature = "ktest")]
+crate::kernel_test! {
+    fn idt_is_loaded_with_double_fault_handler() {
+        let idt = IDT.get().expect("IDT has not been set up");
+        assert_eq!(idt.double_fault.func_ptr().value(), double_fault_handler as usize);
+        assert_eq!(idt.double_fault.options().ist(), DOUBLE_FAULT_IST);
+        let stack_top = DOUBLE_FAULT_STACK.0.get() as usize + DOUBLE_FAULT_STACK_SIZE;
+        assert_eq!(
+            TSS.get().and_then(|tss| tss.interrupt_stack(DOUBLE_FAULT_IST)),
+            Some(VirtualAddress::new_canonical(stack_top))
+        );
+
+        let mut idtr = [0u8; 10];
+        // SAFETY:
+        // `sidt` stores the 10 byte IDT register to `idtr`.
+        unsafe {
+            core::arch::asm!(
+                "sidt [{}]",
+                in(reg) idtr.as_mut_ptr(),
+                options(nostack, preserves_flags)
+            )
+        }
+
+        let limit = u16::from_le_bytes([idtr[0], idtr[1]]);
+        let base = u64::from_le_bytes([
+            idtr[2], idtr[3], idtr[4], idtr[5], idtr[6], idtr[7], idtr[8], idtr[9],
+        ]);
+        assert_eq!(base, idt as *const InterruptDescriptorTable as u64);
+        assert_eq!(usize::from(limit), core::mem::size_of::<InterruptDescriptorTable>() - 1);
+    }
+}
+
+/// Sets aside [`KERNEL_FRAME_RESERVE`][kernel_memory::KERNEL_FRAME_RESERVE] frames for the
+/// kernel's own use, and wraps every other frame that `allocator` has not handed out into untyped
+/// capabilities placed in the empty slots of the root [`CNode`][crate::cells::cnode::CNode].
+fn create_initial_untyped(mut allocator: FrameAllocator) {
+    let kernel_frames = allocator.split_off(kernel_memory::KERNEL_FRAME_RESERVE);
+    kernel_memory::init(kernel_frames);
+
+    let Some(root) = crate::cells::cnode::root() else {
+        return;
+    };
+    let mut root = root.lock();
+
+    let mut regions = allocator.into_free_regions();
+    let mut _untyped_count = 0;
+    let mut _untyped_frames = 0;
+    for index in 0..root.size() {
+        if !root.lookup(index).is_ok_and(|slot| slot.is_empty()) {
+            continue;
+        }
+        let Some(frame_range) = regions.next() else {
+            break;
+        };
+
+        let untyped = CapabilitySlot::Untyped(Untyped::new(frame_range));
+        root.insert(index, untyped)
+            .expect("empty slot rejected untyped capability");
+        _untyped_count += 1;
+        _untyped_frames += frame_range.size_in_frames();
+
+        #[cfg(feature = "logging")]
+        log::debug!(
+            "Untyped capability {index}: {:?}, {} KiB",
+            frame_range.start_address(),
+            frame_range.size_in_bytes() / 1024
+        );
+    }
+
+    let _unwrapped_frames: u64 = regions.map(|range| range.size_in_frames()).sum();
+    #[cfg(feature = "logging")]
+    {
+        log::info!(
+            "Created {_untyped_count} untyped capabilities covering {} KiB",
+            _untyped_frames * Frame::FRAME_SIZE / 1024
+        );
+        if _unwrapped_frames != 0 {
+            log::warn!("Root CNode is full: {_unwrapped_frames} frames left unused");
+        }
+    }
 }
 
+/// A bump allocator over the usable regions of the kernel-owned memory map, which reuses
+/// deallocated [`Frame`]s before advancing.
+///
+/// Deallocated [`Frame`]s form an intrusive free list: the first eight bytes of each free
+/// [`Frame`], accessed through the direct map, hold the [`PhysicalAddress`] of the next one.
+/// [`Frame`]s passed over by a contiguous allocation are kept as ranges instead, which are handed
+/// out after the free list and before the bump cursor advances.
 #[derive(Clone, Debug)]
 pub struct FrameAllocator {
-    original: BootloaderMemoryMapIterator,
-    entries: BootloaderMemoryMapIterator,
+    original: UsableRegions,
+    entries: UsableRegions,
     current: FrameRangeIter,
+    /// The most recently deallocated [`Frame`], or [`None`] if the free list is empty.
+    free_list: Option<Frame>,
+    /// The ranges of [`Frame`]s passed over by contiguous allocations, which are empty when
+    /// unused.
+    skipped: [FrameRangeIter; Self::MAX_SKIPPED_RANGES],
+    /// The memory map entries whose bootloader-reclaimable regions are handed out once
+    /// [`FrameAllocator::reclaim_bootloader_memory()`] is called, or [`None`] once it has been.
+    reclaimable: Option<slice::Iter<'static, MemoryMapEntry>>,
 }
 
 impl FrameAllocator {
-    fn new(entries: BootloaderMemoryMapIterator) -> FrameAllocator {
+    /// The value stored in the last [`Frame`] of the free list in place of a [`PhysicalAddress`].
+    const FREE_LIST_END: u64 = u64::MAX;
+    /// The maximum number of ranges of passed over [`Frame`]s kept aside. Further ranges are put on
+    /// the free list one [`Frame`] at a time.
+    const MAX_SKIPPED_RANGES: usize = 8;
+    /// The number of entries at the head of the free list that debug builds search for a
+    /// [`Frame`] being deallocated, which bounds the cost of catching a double free.
+    #[cfg(debug_assertions)]
+    const DOUBLE_FREE_SEARCH_DEPTH: usize = 64;
+
+    fn new(memory_map: &'static [MemoryMapEntry]) -> FrameAllocator {
+        let entries = UsableRegions::new(memory_map);
+
         FrameAllocator {
             original: entries.clone(),
             entries,
             current: FrameRangeIter::empty(),
+            free_list: None,
+            skipped: [const { FrameRangeIter::empty() }; Self::MAX_SKIPPED_RANGES],
+            reclaimable: Some(memory_map.iter()),
         }
     }
 
+    /// Returns a [`Frame`] that is not in use, preferring the most recently deallocated one and
+    /// then those passed over by contiguous allocations, or [`None`] if none are left.
+    ///
+    /// [`Frame`] zero is never handed out, so that a zero physical address can never refer to an
+    /// allocation.
     pub fn allocate_frame(&mut self) -> Option<Frame> {
-        let mut next_frame = self.current.next();
-        while next_frame.is_none() {
+        if let Some(frame) = self.free_list {
+            // SAFETY:
+            // `frame` is on the free list, so it is not in use and its first eight bytes hold the
+            // next free frame. The direct map covers all of physical memory.
+            let next =
+                unsafe { direct_map::phys_to_ptr::<u64>(frame.base_address()).read_volatile() };
+            self.free_list = (next != Self::FREE_LIST_END)
+                .then(|| Frame::containing_address(PhysicalAddress::new_masked(next)));
+
+            return Some(frame);
+        }
+
+        if let Some(frame) = self
+            .skipped
+            .iter_mut()
+            .find_map(|range| range.find(|&frame| Self::is_allocatable(frame)))
+        {
+            return Some(frame);
+        }
+
+        loop {
+            let mut next_frame = self.current.next();
+            while next_frame.is_none() {
+                self.current = self.entries.next()?.into_iter();
+                next_frame = self.current.next();
+            }
+
+            let frame = next_frame?;
+            if Self::is_allocatable(frame) {
+                return Some(frame);
+            }
+        }
+    }
+
+    /// Returns `frame` to this [`FrameAllocator`] so that it can be handed out again.
+    ///
+    /// # Panics
+    /// Panics if `frame` is [`Frame`] zero. In debug builds, also panics if `frame` is among the
+    /// most recently deallocated [`Frame`]s or waits to be handed out ahead of the bump cursor
+    /// or in a passed over range.
+    ///
+    /// # Safety
+    /// `frame` must have been handed out by this [`FrameAllocator`] and must no longer be in use.
+    pub unsafe fn deallocate_frame(&mut self, frame: Frame) {
+        assert!(frame.number() != 0, "frame zero is never allocated");
+        #[cfg(debug_assertions)]
+        assert!(!self.is_free(frame), "double free of {frame:?}");
+
+        // SAFETY:
+        // The caller guarantees that `frame` is no longer in use.
+        unsafe { self.push_free(frame) }
+    }
+
+    /// Returns `count` contiguous [`Frame`]s, the first of which has a number that is a multiple
+    /// of `align_frames`, or [`None`] if no region left to this [`FrameAllocator`] holds such a
+    /// window.
+    ///
+    /// The window lies within a single memory map entry and never includes [`Frame`] zero, a
+    /// reserved [`Frame`], or a [`Frame`] that was already handed out. [`Frame`]s passed over to
+    /// reach it are kept aside as ranges rather than lost.
+    pub fn allocate_contiguous(&mut self, count: u64, align_frames: u64) -> Option<FrameRange> {
+        if count == 0 || !align_frames.is_power_of_two() {
+            return None;
+        }
+
+        let window = core::iter::once(self.current.remaining())
+            .chain(self.entries.clone())
+            .find_map(|range| find_window(range, count, align_frames, Self::is_allocatable))?;
+
+        loop {
+            let remaining = self.current.remaining();
+            let remaining_end = remaining.start().number() + remaining.size_in_frames();
+            if remaining.contains_address(window.start_address()) {
+                let window_end = window.start().number() + window.size_in_frames();
+                self.skip(remaining.start().number()..window.start().number());
+                self.current = frame_range(window_end, remaining_end).into_iter();
+
+                return Some(window);
+            }
+
+            self.skip(remaining.start().number()..remaining_end);
+            // The window was found in one of the remaining entries, so one is left.
             self.current = self.entries.next()?.into_iter();
-            next_frame = self.current.next();
         }
+    }
+
+    /// Makes the bootloader-reclaimable regions of the memory map available to this
+    /// [`FrameAllocator`], returning the number of [`Frame`]s that it can now hand out.
+    ///
+    /// The regions are handed out after the usable ones. Reserved [`Frame`]s within them are
+    /// skipped as usual. Calling this again has no effect.
+    ///
+    /// # Safety
+    /// The kernel must no longer use any bootloader-reclaimable memory outside of reserved
+    /// [`Frame`]s. In particular, everything it needs from the bootloader's structures must have
+    /// been copied elsewhere.
+    pub unsafe fn reclaim_bootloader_memory(&mut self) -> u64 {
+        let Some(reclaimable) = self.reclaimable.take() else {
+            return 0;
+        };
+
+        let frame_count = reclaimable
+            .clone()
+            .filter(|entry| entry.kind == MemoryKind::BootloaderReclaimable)
+            .flat_map(MemoryMapEntry::frame_range)
+            .filter(|&frame| Self::is_allocatable(frame))
+            .count() as u64;
+        self.entries.reclaimed = reclaimable;
+
+        frame_count
+    }
+
+    /// Returns `true` if `frame` may be handed out, when it is not already in use.
+    fn is_allocatable(frame: Frame) -> bool {
+        frame.number() != 0 && !reserved::is_reserved(frame)
+    }
+
+    /// Keeps the [`Frame`]s with a number in `numbers` aside, to be handed out before the bump
+    /// cursor advances.
+    ///
+    /// If every range is taken, the [`Frame`]s that may be handed out are put on the free list
+    /// instead.
+    fn skip(&mut self, numbers: Range<u64>) {
+        if numbers.is_empty() {
+            return;
+        }
+
+        let range = frame_range(numbers.start, numbers.end);
+        if let Some(slot) = self
+            .skipped
+            .iter_mut()
+            .find(|slot| slot.remaining().size_in_frames() == 0)
+        {
+            *slot = range.into_iter();
+            return;
+        }
+
+        for frame in range
+            .into_iter()
+            .filter(|&frame| Self::is_allocatable(frame))
+        {
+            // SAFETY:
+            // `frame` lies ahead of the bump cursor, so it has never been handed out.
+            unsafe { self.push_free(frame) }
+        }
+    }
+
+    /// Puts `frame` on the free list.
+    ///
+    /// # Safety
+    /// `frame` must not be in use.
+    unsafe fn push_free(&mut self, frame: Frame) {
+        let next = match self.free_list {
+            Some(next) => next.base_address().value(),
+            None => Self::FREE_LIST_END,
+        };
+        // SAFETY:
+        // The caller guarantees that `frame` is not in use, and the direct map covers all of
+        // physical memory.
+        unsafe { direct_map::phys_to_ptr::<u64>(frame.base_address()).write_volatile(next) };
+        self.free_list = Some(frame);
+    }
+
+    /// Returns `true` if `frame` is ahead of the bump cursor, in a passed over range, or among the
+    /// first [`Self::DOUBLE_FREE_SEARCH_DEPTH`] entries of the free list.
+    ///
+    /// Only the head of the free list is searched, so that deallocation stays cheap however long
+    /// the free list grows.
+    #[cfg(debug_assertions)]
+    fn is_free(&self, frame: Frame) -> bool {
+        let address = frame.base_address();
+        if self.current.remaining().contains_address(address)
+            || self
+                .skipped
+                .iter()
+                .any(|range| range.remaining().contains_address(address))
+        {
+            return true;
+        }
+
+        let mut current = self.free_list;
+        for _ in 0..Self::DOUBLE_FREE_SEARCH_DEPTH {
+            let Some(free) = current else {
+                break;
+            };
+            if free == frame {
+                return true;
+            }
+
+            // SAFETY:
+            // Every frame on the free list holds the next free frame in its first eight bytes,
+            // and the direct map covers all of physical memory.
+            let next =
+                unsafe { direct_map::phys_to_ptr::<u64>(free.base_address()).read_volatile() };
+            current = (next != Self::FREE_LIST_END)
+                .then(|| Frame::containing_address(PhysicalAddress::new_masked(next)));
+        }
+
+        false
+    }
+
+    /// Moves up to `count` [`Frame`]s that this [`FrameAllocator`] has not handed out into a new
+    /// [`FrameAllocator`], which hands out those [`Frame`]s and any later deallocated to it, but
+    /// nothing else.
+    pub fn split_off(&mut self, count: u64) -> FrameAllocator {
+        let mut split = FrameAllocator {
+            original: UsableRegions::new(&[]),
+            entries: UsableRegions::new(&[]),
+            current: FrameRangeIter::empty(),
+            free_list: None,
+            skipped: [const { FrameRangeIter::empty() }; Self::MAX_SKIPPED_RANGES],
+            reclaimable: None,
+        };
+
+        for frame in core::iter::from_fn(|| self.allocate_frame())
+            .take(usize::try_from(count).unwrap_or(usize::MAX))
+        {
+            // SAFETY:
+            // `frame` was just handed out by this allocator, so nothing uses it.
+            unsafe { split.push_free(frame) }
+        }
+
+        split
+    }
+
+    /// Consumes the [`FrameAllocator`], returning an [`Iterator`] over runs of contiguous
+    /// [`Frame`]s that it has not handed out.
+    ///
+    /// Frames on the free list are handed out in the reverse of the order they were freed in, so
+    /// runs are merged in both directions, as [`coalesce()`] does.
+    pub fn into_free_regions(mut self) -> impl Iterator<Item = FrameRange> {
+        coalesce(core::iter::from_fn(move || self.allocate_frame()))
+    }
+}
+
+/// Merges consecutive [`Frame`]s of `frames` into [`FrameRange`]s, extending the current run
+/// while the next [`Frame`] lies directly above or directly below it.
+fn coalesce(frames: impl Iterator<Item = Frame>) -> impl Iterator<Item = FrameRange> {
+    let mut frames = frames.peekable();
+
+    core::iter::from_fn(move || {
+        let mut start = frames.next()?;
+        let mut end = start;
+        while let Some(frame) = frames.next_if(|frame| {
+            frame.number() == end.number() + 1 || frame.number() + 1 == start.number()
+        }) {
+            if frame.number() > end.number() {
+                end = frame;
+            } else {
+                start = frame;
+            }
+        }
+
+        Some(FrameRange::inclusive_range(start, end))
+    })
+}
+
+/// Returns the first window of `count` [`Frame`]s within `range` whose first [`Frame`] number is
+/// a multiple of `align_frames` and whose [`Frame`]s all satisfy `usable`.
+fn find_window(
+    range: FrameRange,
+    count: u64,
+    align_frames: u64,
+    usable: impl Fn(Frame) -> bool,
+) -> Option<FrameRange> {
+    let end = range.start().number() + range.size_in_frames();
+
+    let mut start = range
+        .start()
+        .number()
+        .checked_next_multiple_of(align_frames)?;
+    while start.checked_add(count)? <= end {
+        match (start..start + count)
+            .map(frame_at)
+            .find(|&frame| !usable(frame))
+        {
+            Some(unusable) => {
+                start = (unusable.number() + 1).checked_next_multiple_of(align_frames)?;
+            }
+            None => return Some(frame_range(start, start + count)),
+        }
+    }
+
+    None
+}
+
+/// Returns the [`Frame`] numbered `number`.
+fn frame_at(number: u64) -> Frame {
+    Frame::containing_address(PhysicalAddress::new_masked(number * Frame::FRAME_SIZE))
+}
+
+/// Returns the [`FrameRange`] of the [`Frame`]s numbered from `start` up to, but excluding, `end`.
+fn frame_range(start: u64, end: u64) -> FrameRange {
+    if end <= start {
+        return FrameRange::empty(frame_at(start));
+    }
+
+    FrameRange::inclusive_range(frame_at(start), frame_at(end - 1))
+}
+
+/// An [`Iterator`] over the [`FrameRange`]s of the usable entries of a memory map, followed by
+/// those of its bootloader-reclaimable entries once they have been reclaimed.
+#[derive(Clone, Debug)]
+struct UsableRegions {
+    /// The memory map entries yet to be searched for usable regions.
+    usable: slice::Iter<'static, MemoryMapEntry>,
+    /// The memory map entries yet to be searched for reclaimed regions, which is empty until
+    /// bootloader-reclaimable memory is reclaimed.
+    reclaimed: slice::Iter<'static, MemoryMapEntry>,
+}
+
+impl UsableRegions {
+    /// Returns the [`UsableRegions`] of `memory_map`, before any memory has been reclaimed.
+    fn new(memory_map: &'static [MemoryMapEntry]) -> Self {
+        Self {
+            usable: memory_map.iter(),
+            reclaimed: [].iter(),
+        }
+    }
+}
+
+impl Iterator for UsableRegions {
+    type Item = FrameRange;
 
-        next_frame
+    fn next(&mut self) -> Option<Self::Item> {
+        self.usable
+            .by_ref()
+            .find(|entry| entry.kind == MemoryKind::Usable)
+            .or_else(|| {
+                self.reclaimed
+                    .by_ref()
+                    .find(|entry| entry.kind == MemoryKind::BootloaderReclaimable)
+            })
+            .map(MemoryMapEntry::frame_range)
     }
 }
 
+#[cfg(feature = "ktest")]
+crate::kernel_test! {
+    fn frame_allocator_skips_frame_zero() {
+        static MEMORY_MAP: crate::sync::Once<[MemoryMapEntry; 1]> = crate::sync::Once::new();
+        let memory_map = MEMORY_MAP.init([MemoryMapEntry::new(
+            0,
+            Frame::FRAME_SIZE,
+            MemoryKind::Usable,
+        )
+        .unwrap()]);
+
+        assert_eq!(FrameAllocator::new(memory_map).allocate_frame(), None);
+    }
+
+    fn frame_allocator_reuses_deallocated_frames() {
+        /// Memory in the kernel image that stands in for two usable frames.
+        #[repr(C, align(4096))]
+        struct Frames([[u8; Frame::FRAME_SIZE as usize]; 2]);
+        static mut FRAMES: Frames = Frames([[0; Frame::FRAME_SIZE as usize]; 2]);
+        static MEMORY_MAP: crate::sync::Once<[MemoryMapEntry; 2]> = crate::sync::Once::new();
+
+        // SAFETY:
+        // Nothing else modifies the active page tables while the test runs.
+        let mapper = unsafe { crate::arch::x86_64::memory::mapper::Mapper::active() }
+            .expect("direct map has not been set up");
+        let memory_map = MEMORY_MAP.init([0, 1].map(|index| {
+            // SAFETY:
+            // Only the address of `FRAMES` is taken.
+            let address = unsafe { core::ptr::addr_of!(FRAMES.0[index]) } as usize;
+            let address = mapper.translate(VirtualAddress::new_canonical(address)).unwrap();
+            MemoryMapEntry::new(address.value(), Frame::FRAME_SIZE, MemoryKind::Usable).unwrap()
+        }));
+        let first = Frame::containing_address(memory_map[0].base);
+        let second = Frame::containing_address(memory_map[1].base);
+        let mut allocator = FrameAllocator::new(memory_map);
+
+        assert_eq!(allocator.allocate_frame(), Some(first));
+        assert_eq!(allocator.allocate_frame(), Some(second));
+        assert_eq!(allocator.allocate_frame(), None);
+
+        // SAFETY:
+        // Both frames were handed out by `allocator` and only back `FRAMES`, which nothing else
+        // uses.
+        unsafe { allocator.deallocate_frame(first) };
+        // SAFETY:
+        // Same as above.
+        unsafe { allocator.deallocate_frame(second) };
+        assert_eq!(allocator.allocate_frame(), Some(second));
+        assert_eq!(allocator.allocate_frame(), Some(first));
+        assert_eq!(allocator.allocate_frame(), None);
+    }
+
+    fn frame_window_alignment_and_holes() {
+        let range = frame_range(3, 20);
+        let usable = |_: Frame| true;
+        assert_eq!(find_window(range, 4, 4, usable), Some(frame_range(4, 8)));
+        assert_eq!(find_window(range, 4, 16, usable), Some(frame_range(16, 20)));
+        assert_eq!(find_window(range, 5, 16, usable), None);
+        assert_eq!(find_window(range, 18, 1, usable), None);
+        assert_eq!(find_window(range, 17, 1, usable), Some(range));
+
+        let without_five = |frame: Frame| frame.number() != 5;
+        assert_eq!(find_window(range, 4, 4, without_five), Some(frame_range(8, 12)));
+        assert_eq!(find_window(range, 2, 1, without_five), Some(frame_range(3, 5)));
+        assert_eq!(find_window(range, 3, 1, without_five), Some(frame_range(6, 9)));
+    }
+
+    fn contiguous_allocation_follows_cursor() {
+        // Nothing below writes to these frames: none are deallocated, and every window starts at
+        // the cursor, so no frames are passed over.
+        const BASE: u64 = 0x10_0000;
+        static MEMORY_MAP: crate::sync::Once<[MemoryMapEntry; 2]> = crate::sync::Once::new();
+        let memory_map = MEMORY_MAP.init([0, 4].map(|offset| {
+            MemoryMapEntry::new(
+                (BASE + offset) * Frame::FRAME_SIZE,
+                4 * Frame::FRAME_SIZE,
+                MemoryKind::Usable,
+            )
+            .unwrap()
+        }));
+        let mut allocator = FrameAllocator::new(memory_map);
+
+        assert_eq!(allocator.allocate_frame(), Some(frame_at(BASE)));
+        assert_eq!(allocator.allocate_contiguous(3, 1), Some(frame_range(BASE + 1, BASE + 4)));
+        // Adjacent memory map entries are not merged.
+        assert_eq!(allocator.allocate_contiguous(5, 1), None);
+        assert_eq!(allocator.allocate_contiguous(2, 4), Some(frame_range(BASE + 4, BASE + 6)));
+        assert_eq!(allocator.allocate_frame(), Some(frame_at(BASE + 6)));
+        assert_eq!(allocator.allocate_contiguous(2, 1), None);
+        assert_eq!(allocator.allocate_contiguous(1, 1), Some(frame_range(BASE + 7, BASE + 8)));
+        assert_eq!(allocator.allocate_frame(), None);
+        assert_eq!(allocator.allocate_contiguous(0, 1), None);
+    }
+
+    fn reclaimed_memory_follows_usable_memory() {
+        // Nothing below writes to these frames, since none are deallocated.
+        const BASE: u64 = 0x10_0000;
+        static MEMORY_MAP: crate::sync::Once<[MemoryMapEntry; 3]> = crate::sync::Once::new();
+        let memory_map = MEMORY_MAP.init(
+            [
+                (0, 2, MemoryKind::BootloaderReclaimable),
+                (2, 1, MemoryKind::Usable),
+                (3, 1, MemoryKind::AcpiReclaimable),
+            ]
+            .map(|(offset, count, kind)| {
+                MemoryMapEntry::new(
+                    (BASE + offset) * Frame::FRAME_SIZE,
+                    count * Frame::FRAME_SIZE,
+                    kind,
+                )
+                .unwrap()
+            }),
+        );
+        let mut allocator = FrameAllocator::new(memory_map);
+
+        assert_eq!(allocator.allocate_frame(), Some(frame_at(BASE + 2)));
+        assert_eq!(allocator.allocate_frame(), None);
+
+        // SAFETY:
+        // The memory map describes memory that nothing uses.
+        let reclaimed = unsafe { allocator.reclaim_bootloader_memory() };
+        assert_eq!(reclaimed, 2);
+        // SAFETY:
+        // Same as above.
+        let reclaimed = unsafe { allocator.reclaim_bootloader_memory() };
+        assert_eq!(reclaimed, 0);
+        assert_eq!(allocator.allocate_contiguous(2, 1), Some(frame_range(BASE, BASE + 2)));
+        assert_eq!(allocator.allocate_frame(), None);
+    }
+}
+
+/// Information provided by the bootloader that is referenced in place, in memory owned by the
+/// bootloader.
+///
+/// This is consumed by [`info::take_snapshot`] before the bootloader's memory is reclaimed.
+struct BootloaderData<'boot> {
+    /// The memory map provided by the bootloader.
+    memory_map: BootloaderMemoryMapIterator<'boot>,
+    /// The modules provided by the bootloader.
+    modules: BootloaderModuleIterator<'boot>,
+    /// The kernel command line, if one was provided.
+    cmdline: Option<&'boot [u8]>,
+    /// The [`VirtualAddress`] at which the direct map of physical memory starts.
+    direct_map_offset: VirtualAddress,
+    /// The framebuffer provided by the bootloader, if any.
+    framebuffer: Option<FramebufferInfo>,
+    /// The [`PhysicalAddress`] of the ACPI RSDP, if the bootloader provided one.
+    rsdp: Option<PhysicalAddress>,
+    /// The kernel's ELF file, if the bootloader provided it.
+    ///
+    /// Boot protocols that load the kernel at its link address without providing the file pass
+    /// the read-only segments of the loaded image instead, which are laid out as in the file.
+    kernel_image: Option<&'boot [u8]>,
+    /// The pages of the stack the bootloader entered the kernel on, if it lies in memory that
+    /// is reclaimed after boot.
+    boot_stack: Option<PageRange>,
+}
+
+/// An [`Iterator`] over the bootloader's memory map, yielding `(base, size, kind)`.
 #[derive(Clone, Debug)]
-enum BootloaderMemoryMapIterator {
+enum BootloaderMemoryMapIterator<'boot> {
     #[cfg(feature = "capora-boot-api")]
-    Capora(slice::Iter<'static, boot_api::MemoryMapEntry>),
+    Capora(slice::Iter<'boot, boot_api::MemoryMapEntry>),
     #[cfg(feature = "limine-boot-api")]
-    Limine(slice::Iter<'static, &'static limine::MemoryMapEntry>),
+    Limine(slice::Iter<'boot, &'boot limine::MemoryMapEntry>),
+    #[cfg(feature = "multiboot2-boot-api")]
+    Multiboot2(multiboot2::MemoryMapIter<'boot>),
 }
 
-impl Iterator for BootloaderMemoryMapIterator {
-    type Item = FrameRange;
+impl Iterator for BootloaderMemoryMapIterator<'_> {
+    type Item = (u64, u64, MemoryKind);
 
     fn next(&mut self) -> Option<Self::Item> {
-        let (base_address, size) = match self {
+        match self {
             #[cfg(feature = "capora-boot-api")]
             Self::Capora(iter) => {
-                let mut entry = iter.next()?;
-                while entry.kind != boot_api::MemoryMapEntryKind::USABLE {
-                    entry = iter.next()?;
-                }
+                let entry = iter.next()?;
+                let kind = if entry.kind == boot_api::MemoryMapEntryKind::USABLE {
+                    MemoryKind::Usable
+                } else {
+                    MemoryKind::Reserved
+                };
 
-                (entry.base, entry.size)
+                Some((entry.base, entry.size, kind))
             }
             #[cfg(feature = "limine-boot-api")]
             Self::Limine(iter) => {
-                let mut entry = iter.next()?;
-                while entry.mem_type != limine::MemoryMapEntryType::USABLE {
-                    entry = iter.next()?;
-                }
+                let entry = iter.next()?;
+                let kind = match entry.mem_type {
+                    limine::MemoryMapEntryType::USABLE => MemoryKind::Usable,
+                    limine::MemoryMapEntryType::ACPI_RECLAIMABLE => MemoryKind::AcpiReclaimable,
+                    limine::MemoryMapEntryType::ACPI_NVS => MemoryKind::AcpiNvs,
+                    limine::MemoryMapEntryType::BAD_MEMORY => MemoryKind::BadMemory,
+                    limine::MemoryMapEntryType::BOOTLOADER_RECLAIMABLE => {
+                        MemoryKind::BootloaderReclaimable
+                    }
+                    limine::MemoryMapEntryType::KERNEL_AND_MODULES => MemoryKind::KernelAndModules,
+                    limine::MemoryMapEntryType::FRAMEBUFFER => MemoryKind::Framebuffer,
+                    _ => MemoryKind::Reserved,
+                };
 
-                (entry.base, entry.length)
+                Some((entry.base, entry.length, kind))
             }
-        };
-        if size == 0 {
-            return self.next();
+            #[cfg(feature = "multiboot2-boot-api")]
+            Self::Multiboot2(iter) => iter.next(),
         }
+    }
+}
 
-        let Some(base_address) = PhysicalAddress::new(base_address) else {
-            #[cfg(feature = "logging")]
-            log::warn!("Memory map entry outside of valid physical address range");
-            return None;
-        };
+/// An [`Iterator`] over the modules provided by the bootloader, yielding `(name, address, size)`.
+#[derive(Clone, Debug)]
+enum BootloaderModuleIterator<'boot> {
+    /// The bootloader did not provide any modules.
+    None(PhantomData<&'boot [u8]>),
+    #[cfg(feature = "limine-boot-api")]
+    Limine(slice::Iter<'boot, &'boot limine::File>),
+    #[cfg(feature = "multiboot2-boot-api")]
+    Multiboot2(multiboot2::ModuleIter<'boot>),
+}
 
-        let Some(end_address) = base_address
-            .value()
-            .checked_add(size)
-            .and_then(|end_address| PhysicalAddress::new(end_address - 1))
-        else {
-            #[cfg(feature = "logging")]
-            log::warn!("Memory map entry outside of valid physical address range");
-            return None;
-        };
-        Some(FrameRange::inclusive_range(
-            Frame::containing_address(base_address),
-            Frame::containing_address(end_address),
-        ))
+impl<'boot> Iterator for BootloaderModuleIterator<'boot> {
+    type Item = (&'boot [u8], VirtualAddress, u64);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            Self::None(_) => None,
+            #[cfg(feature = "limine-boot-api")]
+            Self::Limine(iter) => {
+                let file = iter.next()?;
+
+                Some((
+                    file.path(),
+                    VirtualAddress::new_canonical(file.address() as usize),
+                    file.size(),
+                ))
+            }
+            #[cfg(feature = "multiboot2-boot-api")]
+            Self::Multiboot2(iter) => iter.next(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns the [`FrameRange`]s that [`coalesce()`] merges the [`Frame`]s numbered `numbers`
+    /// into, as pairs of their first and last [`Frame`] numbers.
+    fn coalesced(numbers: &[u64]) -> Vec<(u64, u64)> {
+        coalesce(numbers.iter().copied().map(frame_at))
+            .map(|range| {
+                let start = range.start().number();
+                (start, start + range.size_in_frames() - 1)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn coalesce_merges_ascending_runs() {
+        assert_eq!(coalesced(&[1, 2, 3, 5, 6, 9]), [(1, 3), (5, 6), (9, 9)]);
+    }
+
+    #[test]
+    fn coalesce_merges_descending_runs() {
+        // Frames deallocated in ascending order come off the free list in descending order,
+        // before the bump cursor continues upwards.
+        assert_eq!(coalesced(&[7, 6, 5, 8, 9, 12]), [(5, 9), (12, 12)]);
+        assert_eq!(coalesced(&[4, 3, 10, 9]), [(3, 4), (9, 10)]);
+    }
+
+    /// Returns a [`FrameAllocator`] over usable memory map entries of `(offset, count)` frames
+    /// from frame `base`.
+    ///
+    /// No [`Frame`] may be deallocated, since the frames are not backed by memory.
+    fn allocator(base: u64, entries: &[(u64, u64)]) -> FrameAllocator {
+        let memory_map = entries
+            .iter()
+            .map(|&(offset, count)| {
+                MemoryMapEntry::new(
+                    (base + offset) * Frame::FRAME_SIZE,
+                    count * Frame::FRAME_SIZE,
+                    MemoryKind::Usable,
+                )
+                .unwrap()
+            })
+            .collect::<Vec<_>>();
+
+        FrameAllocator::new(Vec::leak(memory_map))
+    }
+
+    #[test]
+    fn contiguous_allocation_keeps_passed_over_frames_as_ranges() {
+        const BASE: u64 = 0x10_0000;
+        let mut allocator = allocator(BASE, &[(0, 16)]);
+
+        assert_eq!(allocator.allocate_frame(), Some(frame_at(BASE)));
+        assert_eq!(
+            allocator.allocate_contiguous(4, 8),
+            Some(frame_range(BASE + 8, BASE + 12))
+        );
+        assert!(allocator.free_list.is_none());
+        assert_eq!(
+            allocator.skipped[0].remaining(),
+            frame_range(BASE + 1, BASE + 8)
+        );
+
+        for number in (BASE + 1..BASE + 8).chain(BASE + 12..BASE + 16) {
+            assert_eq!(allocator.allocate_frame(), Some(frame_at(number)));
+        }
+        assert_eq!(allocator.allocate_frame(), None);
+    }
+
+    #[test]
+    fn contiguous_allocation_passes_over_whole_entries() {
+        const BASE: u64 = 0x10_0000;
+        let mut allocator = allocator(BASE, &[(0, 4), (8, 2), (16, 8)]);
+
+        assert_eq!(allocator.allocate_frame(), Some(frame_at(BASE)));
+        assert_eq!(
+            allocator.allocate_contiguous(4, 4),
+            Some(frame_range(BASE + 16, BASE + 20))
+        );
+        assert!(allocator.free_list.is_none());
+
+        let regions = allocator.into_free_regions().collect::<Vec<_>>();
+        assert_eq!(
+            regions,
+            [
+                frame_range(BASE + 1, BASE + 4),
+                frame_range(BASE + 8, BASE + 10),
+                frame_range(BASE + 20, BASE + 24),
+            ]
+        );
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    fn frames_not_handed_out_count_as_free() {
+        const BASE: u64 = 0x10_0000;
+        let mut allocator = allocator(BASE, &[(0, 16)]);
+        allocator.allocate_frame();
+        allocator.allocate_contiguous(2, 4).unwrap();
+
+        assert!(!allocator.is_free(frame_at(BASE)));
+        assert!(allocator.is_free(frame_at(BASE + 1)));
+        assert!(allocator.is_free(frame_at(BASE + 3)));
+        assert!(!allocator.is_free(frame_at(BASE + 4)));
+        assert!(allocator.is_free(frame_at(BASE + 6)));
+        assert!(allocator.is_free(frame_at(BASE + 15)));
+    }
+
+    #[test]
+    fn coalesce_keeps_separate_runs_apart() {
+        assert_eq!(coalesced(&[]), []);
+        assert_eq!(coalesced(&[3, 1, 5]), [(3, 3), (1, 1), (5, 5)]);
+        assert_eq!(coalesced(&[2, 2]), [(2, 2), (2, 2)]);
     }
 }
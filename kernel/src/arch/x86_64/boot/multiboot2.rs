@@ -0,0 +1,1009 @@
+//! Module controlling booting using the Multiboot2 boot protocol.
+//!
+//! The bootloader enters the kernel in 32-bit protected mode with paging disabled, on BIOS systems
+//! as well as on UEFI systems, where it exits boot services first. The entry code identity maps
+//! the first [`IDENTITY_MAP_SIZE`] bytes of physical memory using page tables in the kernel image,
+//! switches to long mode, and continues in 64-bit mode. Memory above that limit is left out of the
+//! memory map handed to the rest of the kernel.
+
+// The entry code is only assembled into the kernel itself, so host tests leave it unused.
+#![cfg_attr(test, allow(dead_code))]
+
+use core::mem;
+
+use crate::{
+    arch::x86_64::{
+        backtrace::{self, StackBounds},
+        boot::{
+            fail::{boot_fail, BootFailure},
+            info::{FramebufferInfo, MemoryKind},
+            karchmain, BootloaderData, BootloaderMemoryMapIterator, BootloaderModuleIterator,
+        },
+        memory::{
+            reserved::{self, ReservationTag},
+            FrameRange, PhysicalAddress, VirtualAddress,
+        },
+    },
+    boot_progress::{self, BootPhase},
+    cells::ControlledModificationCell,
+};
+
+/// The value placed in `eax` by a Multiboot2 compliant bootloader.
+pub const BOOTLOADER_MAGIC: u32 = 0x36d76289;
+
+/// The number of GiB of physical memory identity mapped by the entry code.
+const IDENTITY_MAP_GIBS: usize = 16;
+/// The number of bytes of physical memory identity mapped by the entry code.
+pub const IDENTITY_MAP_SIZE: u64 = IDENTITY_MAP_GIBS as u64 * 1024 * 1024 * 1024;
+
+/// The page tables built by the entry code to identity map the first [`IDENTITY_MAP_SIZE`] bytes
+/// of physical memory with 2 MiB pages.
+///
+/// These remain the active page tables after boot. Lying in the kernel image, they are protected
+/// along with it.
+#[repr(C, align(4096))]
+struct BootPageTables {
+    /// The page map level 4 table, of which only the first entry is used.
+    pml4: [u64; 512],
+    /// The page directory pointer table, of which the first [`IDENTITY_MAP_GIBS`] entries are used.
+    pdpt: [u64; 512],
+    /// The page directories, each of which maps 1 GiB.
+    directories: [[u64; 512]; IDENTITY_MAP_GIBS],
+}
+
+/// The page tables built by the entry code.
+static mut BOOT_PAGE_TABLES: BootPageTables = BootPageTables {
+    pml4: [0; 512],
+    pdpt: [0; 512],
+    directories: [[0; 512]; IDENTITY_MAP_GIBS],
+};
+
+/// The message written to the debugcon device by the entry code if the processor does not
+/// support long mode, in which case no 64-bit code can run to call [`boot_fail`].
+static LONG_MODE_MESSAGE: [u8; BootFailure::LongModeUnsupported.as_str().len()] =
+    *BootFailure::LongModeUnsupported
+        .as_str()
+        .as_bytes()
+        .first_chunk()
+        .unwrap();
+
+/// The size of the stack used by the kernel until it sets up its own.
+const STACK_SIZE: usize = 64 * 1024;
+
+/// The stack used by the kernel until it sets up its own.
+#[repr(C, align(16))]
+struct Stack([u8; STACK_SIZE]);
+
+/// The stack used by the kernel until it sets up its own.
+static mut STACK: Stack = Stack([0; STACK_SIZE]);
+
+/// The size of an ACPI 2.0+ RSDP.
+const RSDP_SIZE: usize = 36;
+
+/// Kernel-owned copy of the RSDP.
+///
+/// Multiboot2 only provides a copy of the RSDP inside the boot information structure, which is
+/// reclaimed after boot, so it is copied into the kernel image.
+static RSDP: ControlledModificationCell<[u8; RSDP_SIZE]> =
+    ControlledModificationCell::new([0; RSDP_SIZE]);
+
+#[cfg(not(test))]
+core::arch::global_asm!(
+    ".pushsection .multiboot2_header, \"a\"",
+    ".balign 8",
+    "2:",
+    // Header magic, architecture (i386), header length, and checksum.
+    ".long 0xE85250D6",
+    ".long 0",
+    ".long 3f - 2b",
+    ".long -(0xE85250D6 + (3f - 2b))",
+    // Information request tag: command line, modules, memory map, framebuffer, and both RSDPs.
+    ".balign 8",
+    ".short 1, 0",
+    ".long 8 + 4 * 6",
+    ".long 1, 3, 6, 8, 14, 15",
+    // End tag.
+    ".balign 8",
+    ".short 0, 0",
+    ".long 8",
+    "3:",
+    ".popsection",
+    "",
+    // The descriptor table loaded to enter long mode, holding only the null descriptor and a
+    // 64-bit code segment, and the pointer `lgdt` loads it from.
+    ".pushsection .rodata.multiboot2_gdt, \"a\"",
+    ".balign 8",
+    ".Lmultiboot2_gdt:",
+    ".quad 0",
+    ".quad 0x00AF9A000000FFFF",
+    ".Lmultiboot2_gdt_pointer:",
+    ".short .Lmultiboot2_gdt_pointer - .Lmultiboot2_gdt - 1",
+    ".long .Lmultiboot2_gdt",
+    ".popsection",
+    "",
+    // The first code executed when booting using the Multiboot2 boot protocol, in 32-bit
+    // protected mode with paging disabled. Keeps the magic value in `eax` and the address of the
+    // boot information structure in `ebx` in `edi` and `esi`, which survive the switch to long
+    // mode, where they are passed to `kbootmain`.
+    ".pushsection .text.multiboot2_entry, \"ax\"",
+    ".code32",
+    ".global _start",
+    "_start:",
+    "cli",
+    "mov edi, eax",
+    "mov esi, ebx",
+    // Check that the processor supports long mode, and collect the `IA32_EFER` bits to set in
+    // `ebx`: long mode enable, along with no-execute enable if the processor supports it.
+    "mov eax, 0x80000000",
+    "cpuid",
+    "cmp eax, 0x80000001",
+    "jb .Lmultiboot2_no_long_mode",
+    "mov eax, 0x80000001",
+    "cpuid",
+    "test edx, 1 << 29",
+    "jz .Lmultiboot2_no_long_mode",
+    "mov ebx, 1 << 8",
+    "test edx, 1 << 20",
+    "jz 2f",
+    "or ebx, 1 << 11",
+    "2:",
+    // Point the first PML4 entry at the PDPT, and each used PDPT entry at a page directory, all
+    // present and writable.
+    "lea eax, [{tables} + 4096 + 3]",
+    "mov [{tables}], eax",
+    "xor ecx, ecx",
+    "3:",
+    "mov eax, ecx",
+    "shl eax, 12",
+    "lea eax, [eax + {tables} + 8192 + 3]",
+    "mov [{tables} + 4096 + ecx * 8], eax",
+    "inc ecx",
+    "cmp ecx, {gibs}",
+    "jb 3b",
+    // Fill the page directories with present, writable 2 MiB pages mapping each address to
+    // itself. The page directories are contiguous, so they are filled as a single array.
+    "xor ecx, ecx",
+    "4:",
+    "mov eax, ecx",
+    "shl eax, 21",
+    "or eax, 0x83",
+    "mov edx, ecx",
+    "shr edx, 11",
+    "mov [{tables} + 8192 + ecx * 8], eax",
+    "mov [{tables} + 8192 + ecx * 8 + 4], edx",
+    "inc ecx",
+    "cmp ecx, {directory_entries}",
+    "jb 4b",
+    // Enable physical address extension, load the page tables, enable long mode, and turn on
+    // paging, which activates long mode.
+    "mov eax, cr4",
+    "or eax, 1 << 5",
+    "mov cr4, eax",
+    "lea eax, [{tables}]",
+    "mov cr3, eax",
+    "mov ecx, 0xC0000080",
+    "rdmsr",
+    "or eax, ebx",
+    "wrmsr",
+    "mov eax, cr0",
+    "or eax, (1 << 31) | 1",
+    "mov cr0, eax",
+    // Load the 64-bit code segment to leave compatibility mode.
+    "lgdt [.Lmultiboot2_gdt_pointer]",
+    "push 0x08",
+    "lea eax, [.Lmultiboot2_long_mode]",
+    "push eax",
+    "retf",
+    ".Lmultiboot2_no_long_mode:",
+    "lea esi, [{message}]",
+    "mov ecx, {message_len}",
+    "mov dx, {debugcon}",
+    "rep outsb",
+    "5:",
+    "hlt",
+    "jmp 5b",
+    ".code64",
+    ".Lmultiboot2_long_mode:",
+    "xor eax, eax",
+    "mov ds, ax",
+    "mov es, ax",
+    "mov fs, ax",
+    "mov gs, ax",
+    "mov ss, ax",
+    "lea rsp, [rip + {stack} + {stack_size}]",
+    "call {main}",
+    "ud2",
+    ".popsection",
+    tables = sym BOOT_PAGE_TABLES,
+    gibs = const IDENTITY_MAP_GIBS,
+    directory_entries = const IDENTITY_MAP_GIBS * 512,
+    message = sym LONG_MODE_MESSAGE,
+    message_len = const BootFailure::LongModeUnsupported.as_str().len(),
+    debugcon = const crate::arch::x86_64::boot::fail::DEBUGCON_PORT,
+    stack = sym STACK,
+    stack_size = const STACK_SIZE,
+    main = sym kbootmain,
+);
+
+/// The entry point when using the Multiboot2 boot protocol.
+extern "C" fn kbootmain(magic: u32, info_address: u32) -> ! {
+    if magic != BOOTLOADER_MAGIC {
+        boot_fail(BootFailure::InvalidMultiboot2Magic)
+    }
+    boot_progress::reach(BootPhase::EntryReached);
+
+    let stack_bottom = core::ptr::addr_of!(STACK) as usize;
+    backtrace::register_stack(StackBounds {
+        start: stack_bottom,
+        end: stack_bottom + STACK_SIZE,
+    });
+
+    let info_ptr = info_address as usize as *const u8;
+    // SAFETY:
+    // The bootloader guarantees that a valid boot information structure is located at
+    // `info_address`, which is identity mapped, and the first field is its total size.
+    let total_size = unsafe { info_ptr.cast::<u32>().read_unaligned() };
+    // SAFETY:
+    // The bootloader guarantees that `total_size` bytes of boot information are located at
+    // `info_address`, and nothing modifies them during boot.
+    let bytes = unsafe { core::slice::from_raw_parts(info_ptr, total_size as usize) };
+
+    let Ok(info) = BootInformation::parse(bytes) else {
+        boot_fail(BootFailure::InvalidMultiboot2Info)
+    };
+
+    // Booting continues without logging if it cannot be initialized.
+    #[cfg(feature = "logging")]
+    let _ = crate::logging::init_logging(
+        info.cmdline()
+            .and_then(|cmdline| core::str::from_utf8(cmdline).ok()),
+    );
+    boot_progress::reach(BootPhase::LoggingUp);
+
+    if let Some(range) = PhysicalAddress::new(info_address as u64)
+        .and_then(|address| reserved::frame_range_of(address, total_size as u64))
+    {
+        if let Err(_error) = reserved::reserve(range, ReservationTag::BootStructures) {
+            #[cfg(feature = "logging")]
+            log::warn!("Failed to reserve Multiboot2 boot information {range:?}: {_error}");
+        }
+    }
+
+    // The bootloader reports the memory holding the kernel image and the modules as available, so
+    // the frame allocator would hand it out unless it is reserved.
+    for range in core::iter::once(kernel_image_frames())
+        .chain(info.modules().map(|(_, address, size)| {
+            PhysicalAddress::new(address.value() as u64)
+                .and_then(|address| reserved::frame_range_of(address, size))
+        }))
+        .flatten()
+    {
+        if let Err(_error) = reserved::reserve(range, ReservationTag::KernelAndModules) {
+            #[cfg(feature = "logging")]
+            log::error!("Failed to reserve {range:?}: {_error}");
+            boot_fail(BootFailure::ReservationFailed)
+        }
+    }
+
+    let bootloader_data = BootloaderData {
+        memory_map: BootloaderMemoryMapIterator::Multiboot2(
+            info.memory_map().below(IDENTITY_MAP_SIZE),
+        ),
+        modules: BootloaderModuleIterator::Multiboot2(info.modules()),
+        cmdline: info.cmdline(),
+        // The entry code identity maps physical memory, which acts as a direct map at offset zero.
+        direct_map_offset: VirtualAddress::zero(),
+        framebuffer: info.framebuffer(),
+        rsdp: info.rsdp().and_then(copy_rsdp),
+        kernel_image: Some(kernel_image_read_only()),
+        // The boot stack is part of the kernel image.
+        boot_stack: None,
+    };
+
+    // The kernel is linked at the address at which it is loaded, so no slide is applied.
+    karchmain(core::ptr::null(), bootloader_data)
+}
+
+extern "C" {
+    /// The first byte of the kernel image.
+    #[link_name = "kernel_start"]
+    static KERNEL_START: core::ffi::c_void;
+    /// The first byte of the kernel image after its read-only segments.
+    #[link_name = "kernel_read_only_end"]
+    static KERNEL_READ_ONLY_END: core::ffi::c_void;
+    /// The first byte after the kernel image.
+    #[link_name = "kernel_end"]
+    static KERNEL_END: core::ffi::c_void;
+}
+
+/// Returns the [`FrameRange`] occupied by the loaded kernel image, including its zero-initialized
+/// data.
+fn kernel_image_frames() -> Option<FrameRange> {
+    let start = core::ptr::addr_of!(KERNEL_START) as u64;
+    let end = core::ptr::addr_of!(KERNEL_END) as u64;
+
+    // The kernel is identity mapped, so the addresses of its symbols are physical addresses.
+    reserved::frame_range_of(PhysicalAddress::new(start)?, end.checked_sub(start)?)
+}
+
+/// Returns the read-only segments of the loaded kernel image.
+///
+/// The kernel is loaded at its link address, so every byte of these segments lies at its offset
+/// in the kernel's ELF file from the start of the image.
+fn kernel_image_read_only() -> &'static [u8] {
+    let start = core::ptr::addr_of!(KERNEL_START).cast::<u8>();
+    let end = core::ptr::addr_of!(KERNEL_READ_ONLY_END).cast::<u8>();
+
+    // SAFETY:
+    // Both symbols are defined by the linker script within the kernel image, `end` after `start`.
+    let size = unsafe { end.offset_from(start) } as usize;
+    // SAFETY:
+    // The read-only segments are mapped for the lifetime of the kernel and never modified.
+    unsafe { core::slice::from_raw_parts(start, size) }
+}
+
+/// Copies `rsdp` into [`RSDP`], returning the [`PhysicalAddress`] of the copy.
+fn copy_rsdp(rsdp: &[u8]) -> Option<PhysicalAddress> {
+    let len = rsdp.len().min(RSDP_SIZE);
+
+    // SAFETY:
+    // This is only called once during boot, before any other references to `RSDP` are created.
+    let copy = unsafe { RSDP.get_mut() };
+    copy[..len].copy_from_slice(&rsdp[..len]);
+
+    // The kernel is identity mapped, so the address of the copy is its physical address.
+    PhysicalAddress::new(copy.as_ptr() as u64)
+}
+
+/// The type of the tag that terminates the list of tags.
+const TAG_END: u32 = 0;
+/// The type of the tag containing the kernel command line.
+const TAG_CMDLINE: u32 = 1;
+/// The type of the tag describing a module.
+const TAG_MODULE: u32 = 3;
+/// The type of the tag containing the memory map.
+const TAG_MEMORY_MAP: u32 = 6;
+/// The type of the tag describing the framebuffer.
+const TAG_FRAMEBUFFER: u32 = 8;
+/// The type of the tag containing a copy of the ACPI 1.0 RSDP.
+const TAG_ACPI_OLD: u32 = 14;
+/// The type of the tag containing a copy of the ACPI 2.0+ RSDP.
+const TAG_ACPI_NEW: u32 = 15;
+
+/// The parsed Multiboot2 boot information structure.
+#[derive(Clone, Copy, Debug)]
+pub struct BootInformation<'info> {
+    /// The tags of the boot information structure.
+    tags: &'info [u8],
+}
+
+impl<'info> BootInformation<'info> {
+    /// Validates the fixed header of the boot information structure located in `bytes`.
+    ///
+    /// # Errors
+    /// Returns [`ParseError`] if `bytes` is too small to contain the header or the size reported
+    /// by the header does not match the size of `bytes`.
+    pub fn parse(bytes: &'info [u8]) -> Result<Self, ParseError> {
+        let (Some(total_size), Some(tags)) = (read_u32(bytes, 0), bytes.get(8..)) else {
+            return Err(ParseError::Truncated);
+        };
+        if total_size as usize != bytes.len() {
+            return Err(ParseError::SizeMismatch);
+        }
+
+        Ok(Self { tags })
+    }
+
+    /// Returns an [`Iterator`] over the tags in the boot information structure.
+    pub fn tags(&self) -> TagIter<'info> {
+        TagIter {
+            remaining: self.tags,
+        }
+    }
+
+    /// Returns the kernel command line, if one was provided.
+    pub fn cmdline(&self) -> Option<&'info [u8]> {
+        self.tags()
+            .find(|tag| tag.kind == TAG_CMDLINE)
+            .map(|tag| c_str(tag.body))
+    }
+
+    /// Returns an [`Iterator`] over the memory map entries.
+    pub fn memory_map(&self) -> MemoryMapIter<'info> {
+        let Some(tag) = self.tags().find(|tag| tag.kind == TAG_MEMORY_MAP) else {
+            return MemoryMapIter::empty();
+        };
+
+        let entry_size = read_u32(tag.body, 0).unwrap_or(0) as usize;
+        if entry_size < 24 {
+            return MemoryMapIter::empty();
+        }
+
+        MemoryMapIter {
+            entries: tag.body.get(8..).unwrap_or(&[]),
+            entry_size,
+            limit: u64::MAX,
+        }
+    }
+
+    /// Returns an [`Iterator`] over the modules.
+    pub fn modules(&self) -> ModuleIter<'info> {
+        ModuleIter { tags: self.tags() }
+    }
+
+    /// Returns information about the framebuffer, if it uses a direct RGB color model.
+    pub fn framebuffer(&self) -> Option<FramebufferInfo> {
+        let tag = self.tags().find(|tag| tag.kind == TAG_FRAMEBUFFER)?;
+
+        let address = PhysicalAddress::new(read_u64(tag.body, 0)?)?;
+        let pitch = read_u32(tag.body, 8)?;
+        let width = read_u32(tag.body, 12)?;
+        let height = read_u32(tag.body, 16)?;
+        let bpp = *tag.body.get(20)?;
+        let framebuffer_type = *tag.body.get(21)?;
+        if framebuffer_type != 1 {
+            return None;
+        }
+
+        let color_info = tag.body.get(24..30)?;
+        Some(FramebufferInfo {
+            address,
+            pitch,
+            width,
+            height,
+            bpp,
+            red_mask_shift: color_info[0],
+            red_mask_size: color_info[1],
+            green_mask_shift: color_info[2],
+            green_mask_size: color_info[3],
+            blue_mask_shift: color_info[4],
+            blue_mask_size: color_info[5],
+        })
+    }
+
+    /// Returns the copy of the RSDP provided by the bootloader, preferring the ACPI 2.0+ copy.
+    pub fn rsdp(&self) -> Option<&'info [u8]> {
+        self.tags()
+            .find(|tag| tag.kind == TAG_ACPI_NEW)
+            .or_else(|| self.tags().find(|tag| tag.kind == TAG_ACPI_OLD))
+            .map(|tag| tag.body)
+    }
+}
+
+/// A tag in the Multiboot2 boot information structure.
+#[derive(Clone, Copy, Debug)]
+pub struct Tag<'info> {
+    /// The type of the tag.
+    pub kind: u32,
+    /// The contents of the tag, excluding the type and size fields.
+    pub body: &'info [u8],
+}
+
+/// An [`Iterator`] over the [`Tag`]s of a [`BootInformation`].
+///
+/// Iteration stops at the end tag, or at the first tag that does not fit in the boot information
+/// structure.
+#[derive(Clone, Debug)]
+pub struct TagIter<'info> {
+    /// The unparsed tags.
+    remaining: &'info [u8],
+}
+
+impl<'info> Iterator for TagIter<'info> {
+    type Item = Tag<'info>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let kind = read_u32(self.remaining, 0)?;
+        let size = read_u32(self.remaining, 4)? as usize;
+        if kind == TAG_END || size < 8 || size > self.remaining.len() {
+            self.remaining = &[];
+            return None;
+        }
+
+        let body = &self.remaining[8..size];
+        let aligned_size = size.next_multiple_of(8).min(self.remaining.len());
+        self.remaining = &self.remaining[aligned_size..];
+
+        Some(Tag { kind, body })
+    }
+}
+
+/// An [`Iterator`] over the entries of the Multiboot2 memory map, yielding `(base, size, kind)`.
+#[derive(Clone, Debug)]
+pub struct MemoryMapIter<'info> {
+    /// The unparsed entries.
+    entries: &'info [u8],
+    /// The size of each entry.
+    entry_size: usize,
+    /// The address at which entries are cut off.
+    limit: u64,
+}
+
+impl MemoryMapIter<'_> {
+    /// Returns an empty [`MemoryMapIter`].
+    const fn empty() -> Self {
+        Self {
+            entries: &[],
+            entry_size: 24,
+            limit: u64::MAX,
+        }
+    }
+
+    /// Limits this [`MemoryMapIter`] to the memory below `limit`, skipping entries that start at
+    /// or above it and truncating entries that extend past it.
+    pub fn below(self, limit: u64) -> Self {
+        Self {
+            limit: self.limit.min(limit),
+            ..self
+        }
+    }
+}
+
+impl Iterator for MemoryMapIter<'_> {
+    type Item = (u64, u64, MemoryKind);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if self.entries.len() < self.entry_size {
+                return None;
+            }
+
+            let (entry, remaining) = self.entries.split_at(self.entry_size);
+            self.entries = remaining;
+
+            let base = read_u64(entry, 0)?;
+            let size = read_u64(entry, 8)?;
+            let kind = match read_u32(entry, 16)? {
+                1 => MemoryKind::Usable,
+                3 => MemoryKind::AcpiReclaimable,
+                4 => MemoryKind::AcpiNvs,
+                5 => MemoryKind::BadMemory,
+                _ => MemoryKind::Reserved,
+            };
+
+            if base >= self.limit {
+                continue;
+            }
+
+            return Some((base, size.min(self.limit - base), kind));
+        }
+    }
+}
+
+/// An [`Iterator`] over the modules described by a [`BootInformation`], yielding
+/// `(name, address, size)`.
+#[derive(Clone, Debug)]
+pub struct ModuleIter<'info> {
+    /// The remaining tags.
+    tags: TagIter<'info>,
+}
+
+impl<'info> Iterator for ModuleIter<'info> {
+    type Item = (&'info [u8], VirtualAddress, u64);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let tag = self.tags.next()?;
+            if tag.kind != TAG_MODULE {
+                continue;
+            }
+
+            let (Some(start), Some(end)) = (read_u32(tag.body, 0), read_u32(tag.body, 4)) else {
+                continue;
+            };
+
+            let name = c_str(tag.body.get(8..).unwrap_or(&[]));
+            return Some((
+                name,
+                VirtualAddress::new_canonical(start as usize),
+                end.saturating_sub(start) as u64,
+            ));
+        }
+    }
+}
+
+/// Various errors that can occur while parsing the Multiboot2 boot information structure.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum ParseError {
+    /// The boot information structure is too small to contain its header.
+    Truncated,
+    /// The size reported by the boot information structure does not match its actual size.
+    SizeMismatch,
+}
+
+/// Returns the bytes of `bytes` before the first null byte.
+fn c_str(bytes: &[u8]) -> &[u8] {
+    let len = bytes
+        .iter()
+        .position(|&byte| byte == 0)
+        .unwrap_or(bytes.len());
+    &bytes[..len]
+}
+
+/// Reads the little-endian [`u32`] located at `offset` in `bytes`.
+fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    let bytes = bytes.get(offset..offset.checked_add(mem::size_of::<u32>())?)?;
+    Some(u32::from_le_bytes(bytes.try_into().ok()?))
+}
+
+/// Reads the little-endian [`u64`] located at `offset` in `bytes`.
+fn read_u64(bytes: &[u8], offset: usize) -> Option<u64> {
+    let bytes = bytes.get(offset..offset.checked_add(mem::size_of::<u64>())?)?;
+    Some(u64::from_le_bytes(bytes.try_into().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns a boot information structure holding `tags`, given as `(type, body)` pairs, each
+    /// padded to eight bytes and followed by the end tag.
+    fn boot_info(tags: &[(u32, &[u8])]) -> Vec<u8> {
+        let mut bytes = vec![0; 8];
+        for &(kind, body) in tags.iter().chain([(TAG_END, &[][..])].iter()) {
+            bytes.extend_from_slice(&kind.to_le_bytes());
+            bytes.extend_from_slice(&(8 + body.len() as u32).to_le_bytes());
+            bytes.extend_from_slice(body);
+            bytes.resize(bytes.len().next_multiple_of(8), 0);
+        }
+
+        let total_size = bytes.len() as u32;
+        bytes[..4].copy_from_slice(&total_size.to_le_bytes());
+        bytes
+    }
+
+    /// Returns the body of a memory map tag with `entry_size` byte entries describing `entries`,
+    /// given as `(base, size, type)`.
+    fn memory_map_body(entry_size: u32, entries: &[(u64, u64, u32)]) -> Vec<u8> {
+        let mut body = Vec::new();
+        body.extend_from_slice(&entry_size.to_le_bytes());
+        body.extend_from_slice(&0u32.to_le_bytes());
+        for &(base, size, kind) in entries {
+            let start = body.len();
+            body.extend_from_slice(&base.to_le_bytes());
+            body.extend_from_slice(&size.to_le_bytes());
+            body.extend_from_slice(&kind.to_le_bytes());
+            body.resize(start + entry_size as usize, 0);
+        }
+        body
+    }
+
+    /// Returns the body of a module tag for a module spanning `start..end` named `name`.
+    fn module_body(start: u32, end: u32, name: &[u8]) -> Vec<u8> {
+        let mut body = Vec::new();
+        body.extend_from_slice(&start.to_le_bytes());
+        body.extend_from_slice(&end.to_le_bytes());
+        body.extend_from_slice(name);
+        body.push(0);
+        body
+    }
+
+    /// Returns the kinds of the tags of the boot information structure `bytes`.
+    fn tag_kinds(bytes: &[u8]) -> Vec<u32> {
+        BootInformation::parse(bytes)
+            .unwrap()
+            .tags()
+            .map(|tag| tag.kind)
+            .collect()
+    }
+
+    #[test]
+    fn header_is_validated() {
+        let bytes = boot_info(&[]);
+        assert_eq!(bytes.len(), 16);
+        assert!(BootInformation::parse(&bytes).is_ok());
+
+        assert_eq!(
+            BootInformation::parse(&bytes[..15]).unwrap_err(),
+            ParseError::SizeMismatch
+        );
+        let mut too_long = bytes.clone();
+        too_long.push(0);
+        assert_eq!(
+            BootInformation::parse(&too_long).unwrap_err(),
+            ParseError::SizeMismatch
+        );
+
+        assert_eq!(
+            BootInformation::parse(&[]).unwrap_err(),
+            ParseError::Truncated
+        );
+        assert_eq!(
+            BootInformation::parse(&[8, 0, 0]).unwrap_err(),
+            ParseError::Truncated
+        );
+        // A header that reports a size too small to hold itself.
+        for size in 4..8u8 {
+            let bytes = [size, 0, 0, 0, 0, 0, 0][..usize::from(size)].to_vec();
+            assert_eq!(
+                BootInformation::parse(&bytes).unwrap_err(),
+                ParseError::Truncated
+            );
+        }
+
+        let header_only = [8, 0, 0, 0, 0, 0, 0, 0];
+        assert_eq!(tag_kinds(&header_only), []);
+    }
+
+    #[test]
+    fn parses_the_command_line() {
+        let bytes = boot_info(&[(TAG_CMDLINE, b"log=debug serial=com2\0")]);
+        let info = BootInformation::parse(&bytes).unwrap();
+        assert_eq!(info.cmdline(), Some(&b"log=debug serial=com2"[..]));
+
+        // A command line missing its null terminator ends with the tag.
+        let bytes = boot_info(&[(TAG_CMDLINE, b"quiet")]);
+        let info = BootInformation::parse(&bytes).unwrap();
+        assert_eq!(info.cmdline(), Some(&b"quiet"[..]));
+
+        let bytes = boot_info(&[]);
+        assert_eq!(BootInformation::parse(&bytes).unwrap().cmdline(), None);
+    }
+
+    #[test]
+    fn parses_the_memory_map() {
+        let body = memory_map_body(
+            24,
+            &[
+                (0, 0x9F000, 1),
+                (0x9F000, 0x1000, 2),
+                (0x10_0000, 0x7F0_0000, 1),
+                (0x7FF_0000, 0x1_0000, 3),
+                (0x800_0000, 0x1000, 4),
+                (0x800_1000, 0x1000, 5),
+                (0x800_2000, 0x1000, 17),
+            ],
+        );
+        let bytes = boot_info(&[(TAG_MEMORY_MAP, &body)]);
+        let info = BootInformation::parse(&bytes).unwrap();
+
+        assert_eq!(
+            info.memory_map().collect::<Vec<_>>(),
+            [
+                (0, 0x9F000, MemoryKind::Usable),
+                (0x9F000, 0x1000, MemoryKind::Reserved),
+                (0x10_0000, 0x7F0_0000, MemoryKind::Usable),
+                (0x7FF_0000, 0x1_0000, MemoryKind::AcpiReclaimable),
+                (0x800_0000, 0x1000, MemoryKind::AcpiNvs),
+                (0x800_1000, 0x1000, MemoryKind::BadMemory),
+                (0x800_2000, 0x1000, MemoryKind::Reserved),
+            ]
+        );
+    }
+
+    #[test]
+    fn memory_map_honors_the_entry_size() {
+        // Later versions of the format may append fields to each entry.
+        let body = memory_map_body(32, &[(0x1000, 0x2000, 1), (0x4000, 0x1000, 2)]);
+        let bytes = boot_info(&[(TAG_MEMORY_MAP, &body)]);
+        let info = BootInformation::parse(&bytes).unwrap();
+        assert_eq!(
+            info.memory_map().collect::<Vec<_>>(),
+            [
+                (0x1000, 0x2000, MemoryKind::Usable),
+                (0x4000, 0x1000, MemoryKind::Reserved)
+            ]
+        );
+
+        // Entries too small to hold the defined fields are not parsed at all.
+        let mut body = memory_map_body(24, &[(0x1000, 0x2000, 1)]);
+        body[..4].copy_from_slice(&16u32.to_le_bytes());
+        let bytes = boot_info(&[(TAG_MEMORY_MAP, &body)]);
+        let info = BootInformation::parse(&bytes).unwrap();
+        assert_eq!(info.memory_map().count(), 0);
+
+        // A trailing partial entry is ignored.
+        let mut body = memory_map_body(24, &[(0x1000, 0x2000, 1)]);
+        body.extend_from_slice(&[0xFF; 20]);
+        let bytes = boot_info(&[(TAG_MEMORY_MAP, &body)]);
+        let info = BootInformation::parse(&bytes).unwrap();
+        assert_eq!(info.memory_map().count(), 1);
+
+        let bytes = boot_info(&[(TAG_MEMORY_MAP, &[24, 0, 0, 0])]);
+        let info = BootInformation::parse(&bytes).unwrap();
+        assert_eq!(info.memory_map().count(), 0);
+
+        let bytes = boot_info(&[]);
+        assert_eq!(
+            BootInformation::parse(&bytes).unwrap().memory_map().count(),
+            0
+        );
+    }
+
+    #[test]
+    fn memory_map_is_cut_off_at_the_limit() {
+        let body = memory_map_body(
+            24,
+            &[
+                (0x1000, 0x2000, 1),
+                (0x3000, 0x3000, 1),
+                (0x6000, 0x1000, 1),
+                (0x8000, 0x1000, 3),
+            ],
+        );
+        let bytes = boot_info(&[(TAG_MEMORY_MAP, &body)]);
+        let info = BootInformation::parse(&bytes).unwrap();
+
+        assert_eq!(
+            info.memory_map().below(0x5000).collect::<Vec<_>>(),
+            [
+                (0x1000, 0x2000, MemoryKind::Usable),
+                (0x3000, 0x2000, MemoryKind::Usable),
+            ]
+        );
+        assert_eq!(
+            info.memory_map().below(0x9000).below(0x6000).count(),
+            2,
+            "the lowest limit applies"
+        );
+        assert_eq!(info.memory_map().below(0x1000).count(), 0);
+        assert_eq!(info.memory_map().below(u64::MAX).count(), 4);
+    }
+
+    #[test]
+    fn parses_modules() {
+        let init = module_body(0x40_0000, 0x40_2000, b"init");
+        let empty = module_body(0x50_0000, 0x50_0000, b"");
+        let bytes = boot_info(&[
+            (TAG_MODULE, &init),
+            (TAG_CMDLINE, b"\0"),
+            (TAG_MODULE, &empty),
+            // Too short to hold the module's end address.
+            (TAG_MODULE, &[0, 0, 0x60, 0]),
+        ]);
+        let info = BootInformation::parse(&bytes).unwrap();
+
+        assert_eq!(
+            info.modules().collect::<Vec<_>>(),
+            [
+                (
+                    &b"init"[..],
+                    VirtualAddress::new_canonical(0x40_0000),
+                    0x2000
+                ),
+                (&b""[..], VirtualAddress::new_canonical(0x50_0000), 0),
+            ]
+        );
+
+        // A module whose end lies before its start is treated as empty.
+        let backwards = module_body(0x40_2000, 0x40_0000, b"backwards");
+        let bytes = boot_info(&[(TAG_MODULE, &backwards)]);
+        let info = BootInformation::parse(&bytes).unwrap();
+        assert_eq!(info.modules().next().unwrap().2, 0);
+    }
+
+    #[test]
+    fn parses_direct_color_framebuffers() {
+        let mut body = Vec::new();
+        body.extend_from_slice(&0xFD00_0000u64.to_le_bytes());
+        body.extend_from_slice(&(1024u32 * 4).to_le_bytes());
+        body.extend_from_slice(&1024u32.to_le_bytes());
+        body.extend_from_slice(&768u32.to_le_bytes());
+        body.extend_from_slice(&[32, 1, 0, 0]);
+        body.extend_from_slice(&[16, 8, 8, 8, 0, 8]);
+        let bytes = boot_info(&[(TAG_FRAMEBUFFER, &body)]);
+        let info = BootInformation::parse(&bytes).unwrap();
+
+        assert_eq!(
+            info.framebuffer(),
+            Some(FramebufferInfo {
+                address: PhysicalAddress::new(0xFD00_0000).unwrap(),
+                pitch: 4096,
+                width: 1024,
+                height: 768,
+                bpp: 32,
+                red_mask_shift: 16,
+                red_mask_size: 8,
+                green_mask_shift: 8,
+                green_mask_size: 8,
+                blue_mask_shift: 0,
+                blue_mask_size: 8,
+            })
+        );
+
+        // Indexed color and text mode framebuffers are not supported.
+        for framebuffer_type in [0, 2] {
+            let mut body = body.clone();
+            body[21] = framebuffer_type;
+            let bytes = boot_info(&[(TAG_FRAMEBUFFER, &body)]);
+            let info = BootInformation::parse(&bytes).unwrap();
+            assert_eq!(info.framebuffer(), None);
+        }
+
+        // A tag too short to hold the color information.
+        let bytes = boot_info(&[(TAG_FRAMEBUFFER, &body[..29])]);
+        assert_eq!(BootInformation::parse(&bytes).unwrap().framebuffer(), None);
+        let bytes = boot_info(&[(TAG_FRAMEBUFFER, &body[..16])]);
+        assert_eq!(BootInformation::parse(&bytes).unwrap().framebuffer(), None);
+    }
+
+    #[test]
+    fn prefers_the_acpi_2_rsdp() {
+        let old = [1; 20];
+        let new = [2; RSDP_SIZE];
+
+        let bytes = boot_info(&[(TAG_ACPI_OLD, &old), (TAG_ACPI_NEW, &new)]);
+        assert_eq!(
+            BootInformation::parse(&bytes).unwrap().rsdp(),
+            Some(&new[..])
+        );
+
+        let bytes = boot_info(&[(TAG_ACPI_OLD, &old)]);
+        assert_eq!(
+            BootInformation::parse(&bytes).unwrap().rsdp(),
+            Some(&old[..])
+        );
+
+        let bytes = boot_info(&[]);
+        assert_eq!(BootInformation::parse(&bytes).unwrap().rsdp(), None);
+    }
+
+    #[test]
+    fn tags_are_padded_to_eight_bytes() {
+        // The command line tag is 13 bytes long, so the next tag starts after three bytes of
+        // padding.
+        let bytes = boot_info(&[(TAG_CMDLINE, b"quiet"), (TAG_ACPI_OLD, &[7; 20])]);
+        assert_eq!(&bytes[8 + 13..8 + 16], [0, 0, 0]);
+
+        let info = BootInformation::parse(&bytes).unwrap();
+        let tags = info.tags().collect::<Vec<_>>();
+        assert_eq!(tags.len(), 2);
+        assert_eq!((tags[0].kind, tags[0].body), (TAG_CMDLINE, &b"quiet"[..]));
+        assert_eq!((tags[1].kind, tags[1].body), (TAG_ACPI_OLD, &[7; 20][..]));
+
+        // The padding of the last tag may be cut off by the end of the structure.
+        let mut bytes = vec![0; 8];
+        bytes.extend_from_slice(&TAG_CMDLINE.to_le_bytes());
+        bytes.extend_from_slice(&13u32.to_le_bytes());
+        bytes.extend_from_slice(b"quiet");
+        let total_size = bytes.len() as u32;
+        bytes[..4].copy_from_slice(&total_size.to_le_bytes());
+        let info = BootInformation::parse(&bytes).unwrap();
+        assert_eq!(info.cmdline(), Some(&b"quiet"[..]));
+        assert_eq!(info.tags().count(), 1);
+    }
+
+    #[test]
+    fn iteration_stops_at_malformed_tags() {
+        let mut bytes = boot_info(&[
+            (TAG_CMDLINE, b"quiet\0"),
+            (TAG_ACPI_OLD, &[7; 20]),
+            (TAG_ACPI_NEW, &[8; RSDP_SIZE]),
+        ]);
+        assert_eq!(tag_kinds(&bytes), [TAG_CMDLINE, TAG_ACPI_OLD, TAG_ACPI_NEW]);
+
+        // The second tag starts at offset 24. A size smaller than the tag header stops iteration
+        // there.
+        for size in [0u32, 7] {
+            bytes[28..32].copy_from_slice(&size.to_le_bytes());
+            assert_eq!(tag_kinds(&bytes), [TAG_CMDLINE]);
+        }
+
+        // As does a size reaching past the end of the structure.
+        let remaining = (bytes.len() - 24) as u32;
+        bytes[28..32].copy_from_slice(&(remaining + 1).to_le_bytes());
+        assert_eq!(tag_kinds(&bytes), [TAG_CMDLINE]);
+        bytes[28..32].copy_from_slice(&remaining.to_le_bytes());
+        assert_eq!(tag_kinds(&bytes), [TAG_CMDLINE, TAG_ACPI_OLD]);
+
+        // Tags after an end tag are never visited.
+        let mut bytes = boot_info(&[(TAG_CMDLINE, b"quiet\0"), (TAG_ACPI_OLD, &[7; 20])]);
+        bytes[24..28].copy_from_slice(&TAG_END.to_le_bytes());
+        assert_eq!(tag_kinds(&bytes), [TAG_CMDLINE]);
+    }
+
+    #[test]
+    fn truncated_tags_are_not_parsed() {
+        let bytes = boot_info(&[(TAG_CMDLINE, b"quiet\0")]);
+
+        // Cut the structure off in the middle of the command line tag and again in the middle of
+        // its header, fixing up the total size each time.
+        for len in [20, 12, 9] {
+            let mut truncated = bytes[..len].to_vec();
+            truncated[..4].copy_from_slice(&(len as u32).to_le_bytes());
+            let info = BootInformation::parse(&truncated).unwrap();
+            assert_eq!(info.tags().count(), 0);
+            assert_eq!(info.cmdline(), None);
+        }
+    }
+}
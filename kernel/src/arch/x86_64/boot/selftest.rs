@@ -0,0 +1,527 @@
+//! Boot-time verification that the kernel's loaded segments are mapped as its ELF program headers
+//! expect.
+//!
+//! Mapping bugs such as wrong permissions, missed BSS zeroing, or an off-by-one page tend to only
+//! surface much later as memory corruption, so this pass checks every `PT_LOAD` segment against the
+//! live page tables before the kernel proper starts running.
+
+use core::fmt;
+
+use crate::arch::x86_64::{
+    boot::{
+        fail::{boot_fail, BootFailure},
+        ProgramHeader,
+    },
+    memory::{direct_map, Page, PageRange, PhysicalAddress, VirtualAddress},
+};
+
+/// The `p_type` of a loadable segment.
+const PT_LOAD: u32 = 1;
+/// The bit of `p_flags` marking a segment as executable.
+const PF_X: u32 = 0x1;
+/// The bit of `p_flags` marking a segment as writable.
+const PF_W: u32 = 0x2;
+
+/// The bit of a page table entry marking it as present.
+const ENTRY_PRESENT: u64 = 1 << 0;
+/// The bit of a page table entry marking it as writable.
+const ENTRY_WRITABLE: u64 = 1 << 1;
+/// The bit of a page table entry marking it as mapping a huge page.
+const ENTRY_HUGE: u64 = 1 << 7;
+/// The bit of a page table entry marking it as non-executable.
+const ENTRY_NO_EXECUTE: u64 = 1 << 63;
+/// The bits of a page table entry containing the physical address of the next level.
+const ENTRY_ADDRESS_MASK: u64 = 0x000F_FFFF_FFFF_F000;
+
+/// A zero-initialized object that is never written, used to confirm that the BSS was zeroed.
+///
+/// The rest of the BSS is already in use by the time the self-test runs, so it cannot be checked
+/// directly.
+#[used]
+static mut BSS_CANARY: [u8; 64] = [0; 64];
+
+/// The permissions a segment is expected to be mapped with.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub struct ExpectedFlags {
+    /// Whether the segment should be writable.
+    pub writable: bool,
+    /// Whether the segment should be executable.
+    pub executable: bool,
+}
+
+impl ExpectedFlags {
+    /// Returns the [`ExpectedFlags`] described by the ELF `p_flags` field `flags`.
+    pub const fn from_segment_flags(flags: u32) -> Self {
+        Self {
+            writable: flags & PF_W == PF_W,
+            executable: flags & PF_X == PF_X,
+        }
+    }
+
+    /// Compares these [`ExpectedFlags`] against the effective permissions of a mapping.
+    ///
+    /// # Errors
+    /// Returns a [`FlagMismatch`] describing the difference if the permissions do not match.
+    pub const fn check(self, actual: MappingFlags) -> Result<(), FlagMismatch> {
+        if self.writable != actual.writable || self.executable == actual.no_execute {
+            return Err(FlagMismatch {
+                expected: self,
+                actual,
+            });
+        }
+
+        Ok(())
+    }
+}
+
+/// The effective permissions of a mapping, combined across every level of the page tables.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub struct MappingFlags {
+    /// Whether every level of the walk permits writes.
+    pub writable: bool,
+    /// Whether any level of the walk forbids execution.
+    pub no_execute: bool,
+}
+
+impl MappingFlags {
+    /// The permissions of a walk before any page table entries have been visited.
+    pub const INITIAL: Self = Self {
+        writable: true,
+        no_execute: false,
+    };
+
+    /// Combines these [`MappingFlags`] with the permissions granted by the page table entry
+    /// `entry`.
+    pub const fn combine(self, entry: u64) -> Self {
+        Self {
+            writable: self.writable && entry & ENTRY_WRITABLE == ENTRY_WRITABLE,
+            no_execute: self.no_execute || entry & ENTRY_NO_EXECUTE == ENTRY_NO_EXECUTE,
+        }
+    }
+}
+
+/// The difference between the expected and actual permissions of a mapping.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub struct FlagMismatch {
+    /// The expected permissions.
+    pub expected: ExpectedFlags,
+    /// The actual permissions.
+    pub actual: MappingFlags,
+}
+
+impl fmt::Display for FlagMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "expected writable={} executable={}, found writable={} executable={}",
+            self.expected.writable,
+            self.expected.executable,
+            self.actual.writable,
+            !self.actual.no_execute
+        )
+    }
+}
+
+/// Verifies every `PT_LOAD` segment described by `program_headers`, loaded with a slide of
+/// `kernel_address`, against the live page tables.
+///
+/// If `kernel_image` is provided, the first and last file-backed bytes of each read-only segment
+/// are also compared against the kernel's ELF file.
+///
+/// Any failure is logged and halts the kernel.
+pub fn verify_segments(
+    kernel_address: *const u8,
+    program_headers: &[ProgramHeader],
+    kernel_image: Option<&[u8]>,
+) {
+    let mut failures = 0usize;
+
+    for (index, program_header) in program_headers.iter().enumerate() {
+        if program_header.segment_type() != PT_LOAD || program_header.memory_size() == 0 {
+            continue;
+        }
+
+        let start = kernel_address as usize + program_header.virtual_address() as usize;
+        let expected = ExpectedFlags::from_segment_flags(program_header.flags());
+
+        let Some(pages) = PageRange::inclusive_range(
+            Page::containing_address(VirtualAddress::new_canonical(start)),
+            Page::containing_address(VirtualAddress::new_canonical(
+                start + (program_header.memory_size() as usize - 1),
+            )),
+        ) else {
+            failures += 1;
+            #[cfg(feature = "logging")]
+            log::error!("Segment {index}: invalid address range starting at {start:#X}");
+            continue;
+        };
+
+        for page in pages {
+            match translate(page.base_address()) {
+                None => {
+                    failures += 1;
+                    #[cfg(feature = "logging")]
+                    log::error!("Segment {index}: {page:?} is not mapped");
+                }
+                Some((_, actual)) => {
+                    if let Err(_mismatch) = expected.check(actual) {
+                        failures += 1;
+                        #[cfg(feature = "logging")]
+                        log::error!("Segment {index}: {page:?} has wrong permissions: {_mismatch}");
+                    }
+                }
+            }
+        }
+
+        // Writable segments may already have been modified, by the bootloader filling in request
+        // responses if nothing else, so only read-only segments are compared against the file.
+        if let Some(kernel_image) = kernel_image.filter(|_| !expected.writable) {
+            failures += verify_file_bytes(index, start, program_header, kernel_image);
+        }
+    }
+
+    let canary_ptr = core::ptr::addr_of!(BSS_CANARY);
+    // SAFETY:
+    // `BSS_CANARY` is never written, so reading it cannot race.
+    let canary = unsafe { core::ptr::read_volatile(canary_ptr) };
+    if canary.iter().any(|&byte| byte != 0) {
+        failures += 1;
+        #[cfg(feature = "logging")]
+        log::error!("BSS was not zeroed: canary at {canary_ptr:p} is {canary:X?}");
+    }
+
+    if failures != 0 {
+        #[cfg(feature = "logging")]
+        log::error!("Boot self-test failed with {failures} errors");
+        boot_fail(BootFailure::SelfTestFailed)
+    }
+
+    #[cfg(feature = "logging")]
+    log::info!("Boot self-test passed");
+}
+
+/// Compares the first and last file-backed bytes of the segment described by `program_header`,
+/// loaded at `start`, against `kernel_image`, returning the number of mismatches.
+fn verify_file_bytes(
+    _index: usize,
+    start: usize,
+    program_header: &ProgramHeader,
+    kernel_image: &[u8],
+) -> usize {
+    let file_size = program_header.file_size() as usize;
+    if file_size == 0 {
+        return 0;
+    }
+
+    let offset = program_header.offset() as usize;
+    let mut failures = 0;
+    for position in [0, file_size - 1] {
+        let Some(&expected) = kernel_image.get(offset + position) else {
+            #[cfg(feature = "logging")]
+            log::error!(
+                "Segment {_index}: file offset {:#X} out of bounds",
+                offset + position
+            );
+            failures += 1;
+            continue;
+        };
+
+        // SAFETY:
+        // Every page of the segment was verified to be mapped, and `position` lies within the
+        // segment.
+        let actual = unsafe { core::ptr::read_volatile((start + position) as *const u8) };
+        if actual != expected {
+            #[cfg(feature = "logging")]
+            log::error!(
+                "Segment {_index}: byte at {:#X} is {actual:#04X}, expected {expected:#04X}",
+                start + position
+            );
+            failures += 1;
+        }
+    }
+
+    failures
+}
+
+/// Walks the active page tables, returning the [`PhysicalAddress`] `address` is mapped to along
+/// with the effective permissions of the mapping.
+fn translate(address: VirtualAddress) -> Option<(PhysicalAddress, MappingFlags)> {
+    let offset = direct_map::offset()?;
+
+    let cr3: u64;
+    // SAFETY:
+    // Reading `cr3` has no side effects.
+    unsafe { core::arch::asm!("mov {}, cr3", out(reg) cr3, options(nomem, nostack)) };
+
+    walk(cr3 & ENTRY_ADDRESS_MASK, address, |table, index| {
+        let entry_address = offset.value() + table as usize + index as usize * 8;
+        // SAFETY:
+        // `table` is the physical address of a page table, which is accessible through the direct
+        // map.
+        unsafe { core::ptr::read_volatile(entry_address as *const u64) }
+    })
+}
+
+/// Walks the page tables rooted at the PML4 at physical address `pml4`, reading the entry at
+/// `index` of the table at a physical address with `read_entry`, and returns the
+/// [`PhysicalAddress`] `address` is mapped to along with the effective permissions of the
+/// mapping.
+fn walk(
+    pml4: u64,
+    address: VirtualAddress,
+    mut read_entry: impl FnMut(u64, u16) -> u64,
+) -> Option<(PhysicalAddress, MappingFlags)> {
+    let page = Page::containing_address(address);
+    let indices = [
+        page.pml4e_index(),
+        page.pml3e_index(),
+        page.pml2e_index(),
+        page.pml1e_index(),
+    ];
+
+    let mut table = pml4;
+    let mut flags = MappingFlags::INITIAL;
+    for (level, index) in indices.into_iter().enumerate() {
+        let entry = read_entry(table, index);
+        if entry & ENTRY_PRESENT != ENTRY_PRESENT {
+            return None;
+        }
+
+        flags = flags.combine(entry);
+        table = entry & ENTRY_ADDRESS_MASK;
+
+        // Huge pages can only be mapped by PML3 and PML2 entries.
+        if (level == 1 || level == 2) && entry & ENTRY_HUGE == ENTRY_HUGE {
+            let page_size = 1u64 << (12 + 9 * (3 - level));
+            let physical = (table & !(page_size - 1)) | (address.value() as u64 & (page_size - 1));
+            return Some((PhysicalAddress::new_masked(physical), flags));
+        }
+    }
+
+    Some((
+        PhysicalAddress::new_masked(table | address.page_offset() as u64),
+        flags,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    /// The `p_flags` bit marking a segment as readable.
+    const PF_R: u32 = 0x4;
+
+    /// The address at which the mappings of these tests are placed.
+    const ADDRESS: usize = 0xFFFF_FFFF_8020_1234;
+    /// The physical address of the PML4 of these tests.
+    const PML4: u64 = 0x1000;
+
+    /// A set of page tables, keyed by the physical address of the table and the entry index.
+    struct PageTables(HashMap<(u64, u16), u64>);
+
+    impl PageTables {
+        /// Creates page tables mapping [`ADDRESS`] through PML4, PML3, and PML2 entries with
+        /// `upper` flags, down to a leaf entry at `leaf_level` (3 for a 1 GiB page, 2 for a 2 MiB
+        /// page, and 1 for a 4 KiB page) with `leaf` flags and physical address `physical`.
+        fn new(upper: u64, leaf_level: usize, leaf: u64, physical: u64) -> Self {
+            let address = VirtualAddress::new_canonical(ADDRESS);
+            let indices = [
+                address.pml4e_index(),
+                address.pml3e_index(),
+                address.pml2e_index(),
+                address.pml1e_index(),
+            ];
+
+            let mut entries = HashMap::new();
+            let mut table = PML4;
+            for (level, index) in (1..=4).rev().zip(indices) {
+                if level == leaf_level {
+                    entries.insert((table, index), physical | leaf);
+                    break;
+                }
+
+                let next = table + 0x1000;
+                entries.insert((table, index), next | upper);
+                table = next;
+            }
+
+            Self(entries)
+        }
+
+        /// Translates [`ADDRESS`] through these page tables.
+        fn translate(&self) -> Option<(PhysicalAddress, MappingFlags)> {
+            walk(
+                PML4,
+                VirtualAddress::new_canonical(ADDRESS),
+                |table, index| self.0.get(&(table, index)).copied().unwrap_or(0),
+            )
+        }
+    }
+
+    /// The flags of a present, writable intermediate entry.
+    const TABLE: u64 = ENTRY_PRESENT | ENTRY_WRITABLE;
+    /// The flags of a leaf entry mapping a read-only segment.
+    const READ_ONLY: u64 = ENTRY_PRESENT | ENTRY_NO_EXECUTE;
+    /// The flags of a leaf entry mapping a writable data segment.
+    const READ_WRITE: u64 = ENTRY_PRESENT | ENTRY_WRITABLE | ENTRY_NO_EXECUTE;
+    /// The flags of a leaf entry mapping a code segment.
+    const READ_EXECUTE: u64 = ENTRY_PRESENT;
+
+    #[test]
+    fn segment_flags_map_to_permissions() {
+        let flags = |writable, executable| ExpectedFlags {
+            writable,
+            executable,
+        };
+
+        assert_eq!(ExpectedFlags::from_segment_flags(PF_R), flags(false, false));
+        assert_eq!(
+            ExpectedFlags::from_segment_flags(PF_R | PF_W),
+            flags(true, false)
+        );
+        assert_eq!(
+            ExpectedFlags::from_segment_flags(PF_R | PF_X),
+            flags(false, true)
+        );
+        assert_eq!(
+            ExpectedFlags::from_segment_flags(PF_R | PF_W | PF_X),
+            flags(true, true)
+        );
+    }
+
+    #[test]
+    fn matching_entries_pass() {
+        for (segment, leaf) in [
+            (PF_R, READ_ONLY),
+            (PF_R | PF_W, READ_WRITE),
+            (PF_R | PF_X, READ_EXECUTE),
+        ] {
+            let actual = MappingFlags::INITIAL.combine(TABLE).combine(leaf);
+
+            assert_eq!(
+                ExpectedFlags::from_segment_flags(segment).check(actual),
+                Ok(()),
+                "p_flags {segment:#x} with entry {leaf:#x}"
+            );
+        }
+    }
+
+    #[test]
+    fn mismatched_entries_fail() {
+        for (segment, leaf) in [
+            (PF_R, READ_WRITE),
+            (PF_R, READ_EXECUTE),
+            (PF_R | PF_W, READ_ONLY),
+            (PF_R | PF_W, READ_EXECUTE | ENTRY_WRITABLE),
+            (PF_R | PF_X, READ_ONLY),
+            (PF_R | PF_X, READ_WRITE & !ENTRY_NO_EXECUTE),
+        ] {
+            let expected = ExpectedFlags::from_segment_flags(segment);
+            let actual = MappingFlags::INITIAL.combine(TABLE).combine(leaf);
+
+            assert_eq!(
+                expected.check(actual),
+                Err(FlagMismatch { expected, actual }),
+                "p_flags {segment:#x} with entry {leaf:#x}"
+            );
+        }
+    }
+
+    #[test]
+    fn every_level_restricts_permissions() {
+        // A read-only or non-executable intermediate entry restricts everything below it.
+        let actual = MappingFlags::INITIAL
+            .combine(ENTRY_PRESENT)
+            .combine(READ_WRITE);
+        assert!(!actual.writable);
+
+        let actual = MappingFlags::INITIAL
+            .combine(TABLE | ENTRY_NO_EXECUTE)
+            .combine(READ_EXECUTE);
+        assert!(actual.no_execute);
+
+        // Permissive intermediate entries do not widen a restrictive leaf.
+        let actual = MappingFlags::INITIAL.combine(TABLE).combine(READ_ONLY);
+        assert_eq!(
+            actual,
+            MappingFlags {
+                writable: false,
+                no_execute: true,
+            }
+        );
+    }
+
+    #[test]
+    fn walk_translates_small_pages() {
+        for leaf in [READ_ONLY, READ_WRITE, READ_EXECUTE] {
+            let tables = PageTables::new(TABLE, 1, leaf, 0x0123_4000);
+
+            assert_eq!(
+                tables.translate(),
+                Some((
+                    PhysicalAddress::new_masked(0x0123_4234),
+                    MappingFlags::INITIAL.combine(TABLE).combine(leaf)
+                ))
+            );
+        }
+
+        // Bit 7 of a PML1 entry selects the memory type rather than a huge page.
+        let tables = PageTables::new(TABLE, 1, READ_ONLY | ENTRY_HUGE, 0x0123_4000);
+        assert_eq!(
+            tables.translate().map(|(physical, _)| physical),
+            Some(PhysicalAddress::new_masked(0x0123_4234))
+        );
+    }
+
+    #[test]
+    fn walk_translates_huge_pages() {
+        let tables = PageTables::new(TABLE, 2, READ_EXECUTE | ENTRY_HUGE, 0x4060_0000);
+        assert_eq!(
+            tables.translate(),
+            Some((
+                PhysicalAddress::new_masked(0x4060_0000 | (ADDRESS as u64 & 0x1F_FFFF)),
+                MappingFlags::INITIAL.combine(TABLE).combine(READ_EXECUTE)
+            ))
+        );
+
+        let tables = PageTables::new(TABLE, 3, READ_WRITE | ENTRY_HUGE, 0x8000_0000);
+        let (physical, flags) = tables.translate().unwrap();
+        assert_eq!(
+            physical,
+            PhysicalAddress::new_masked(0x8000_0000 | (ADDRESS as u64 & 0x3FFF_FFFF))
+        );
+        assert_eq!(
+            ExpectedFlags::from_segment_flags(PF_R | PF_W).check(flags),
+            Ok(())
+        );
+        assert!(ExpectedFlags::from_segment_flags(PF_R | PF_X)
+            .check(flags)
+            .is_err());
+    }
+
+    #[test]
+    fn walk_stops_at_missing_entries() {
+        for level in 1..=3 {
+            let tables = PageTables::new(TABLE, level, READ_ONLY & !ENTRY_PRESENT, 0x5000);
+            assert_eq!(tables.translate(), None, "level {level}");
+        }
+
+        let tables = PageTables::new(TABLE & !ENTRY_PRESENT, 1, READ_ONLY, 0x5000);
+        assert_eq!(tables.translate(), None);
+    }
+
+    #[test]
+    fn mismatch_display() {
+        let mismatch = FlagMismatch {
+            expected: ExpectedFlags::from_segment_flags(PF_R | PF_X),
+            actual: MappingFlags::INITIAL.combine(READ_WRITE),
+        };
+
+        assert_eq!(
+            std::format!("{mismatch}"),
+            "expected writable=false executable=true, found writable=true executable=false"
+        );
+    }
+}
@@ -0,0 +1,73 @@
+//! Publication of the current [`BootPhase`] where it can be read from outside the machine.
+//!
+//! Each phase is published in two places:
+//! - With the `debugcon-logging` feature, the byte [`DEBUGCON_CODE_BASE`] plus the phase's code is
+//!   written straight to the debugcon port. These are control characters that never appear in log
+//!   text.
+//! - The phase's code, followed by its bitwise complement, is written to physical address
+//!   [`SCRATCH_ADDRESS`] once the direct map is known, where a hypervisor can read it back from a
+//!   memory dump. The complement distinguishes a published phase from whatever was there before.
+
+use crate::{
+    arch::x86_64::memory::{
+        direct_map,
+        reserved::{self, ReservationError, ReservationTag},
+        Frame, FrameRange, PhysicalAddress,
+    },
+    boot_progress::BootPhase,
+};
+
+/// The physical address of the two bytes holding the most recently completed [`BootPhase`].
+///
+/// This lies in the inter-application communication area of the BIOS data area, which nothing
+/// else uses.
+pub const SCRATCH_ADDRESS: u64 = 0x4F0;
+
+/// The byte added to the code of a [`BootPhase`] when it is written to the debugcon port.
+pub const DEBUGCON_CODE_BASE: u8 = 0x10;
+
+/// Publishes `phase` as the most recently completed [`BootPhase`].
+pub fn publish(phase: BootPhase) {
+    #[cfg(feature = "debugcon-logging")]
+    crate::arch::x86_64::debugcon::acquire_debugcon().write_byte(DEBUGCON_CODE_BASE + phase.code());
+
+    let Some(offset) = direct_map::offset() else {
+        return;
+    };
+    let scratch = (offset.value() + SCRATCH_ADDRESS as usize) as *mut [u8; 2];
+    // SAFETY:
+    // The direct map covers the first frame of physical memory, which is reserved by
+    // `reserve_scratch()` so that nothing else is placed there.
+    unsafe { scratch.write_volatile([phase.code(), !phase.code()]) }
+}
+
+/// Reserves the frame containing [`SCRATCH_ADDRESS`] so that the frame allocator never hands it
+/// out.
+///
+/// # Errors
+/// Returns [`ReservationError`] if the reservation cannot be recorded.
+pub fn reserve_scratch() -> Result<(), ReservationError> {
+    let frame = Frame::containing_address(PhysicalAddress::new_masked(SCRATCH_ADDRESS));
+    reserved::reserve(
+        FrameRange::inclusive_range(frame, frame),
+        ReservationTag::BootProgress,
+    )
+}
+
+#[cfg(feature = "ktest")]
+crate::kernel_test! {
+    fn boot_phase_is_published() {
+        let scratch = direct_map::phys_to_ptr::<[u8; 2]>(PhysicalAddress::new_masked(
+            SCRATCH_ADDRESS,
+        ));
+        // SAFETY:
+        // The direct map covers the first frame of physical memory.
+        let [code, complement] = unsafe { scratch.read_volatile() };
+
+        assert_eq!(code, BootPhase::KmainReached.code());
+        assert_eq!(complement, !code);
+        assert!(reserved::is_reserved(Frame::containing_address(PhysicalAddress::new_masked(
+            SCRATCH_ADDRESS
+        ))));
+    }
+}
@@ -0,0 +1,161 @@
+//! Saving and restoring the execution context of kernel threads.
+//!
+//! A [`Context`] holds the registers that the System V ABI requires a function to preserve, plus
+//! the stack pointer. [`switch_context()`] is an ordinary function call from the point of view of
+//! both threads involved: the outgoing thread's callee-saved registers are stored, the incoming
+//! thread's are loaded, and `ret` returns into wherever the incoming thread last called
+//! [`switch_context()`].
+//!
+//! A new thread has never called [`switch_context()`], so [`Context::seed()`] prepares its stack
+//! to look as if it had: the return address at the top of the stack leads to a trampoline that
+//! calls the thread's entry function with the argument stored in `rbx`. The seeded `rbp` is null,
+//! which ends backtraces at the entry function.
+
+use core::mem;
+
+use crate::arch::x86_64::memory::VirtualAddress;
+
+/// The callee-saved registers and stack pointer of a suspended kernel thread.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, Hash, PartialEq, Eq)]
+pub struct Context {
+    /// The saved `rbx`.
+    pub rbx: u64,
+    /// The saved `rbp`.
+    pub rbp: u64,
+    /// The saved `r12`.
+    pub r12: u64,
+    /// The saved `r13`.
+    pub r13: u64,
+    /// The saved `r14`.
+    pub r14: u64,
+    /// The saved `r15`.
+    pub r15: u64,
+    /// The saved stack pointer, pointing at the address [`switch_context()`] returns to.
+    pub rsp: u64,
+}
+
+/// The number of bytes of a new thread's stack used by [`Context::seed()`].
+pub const SEED_SIZE: usize = 8;
+
+// `switch_context` addresses the fields of `Context` by these offsets.
+const _: () = {
+    assert!(mem::offset_of!(Context, rbx) == 0x00);
+    assert!(mem::offset_of!(Context, rbp) == 0x08);
+    assert!(mem::offset_of!(Context, r12) == 0x10);
+    assert!(mem::offset_of!(Context, r13) == 0x18);
+    assert!(mem::offset_of!(Context, r14) == 0x20);
+    assert!(mem::offset_of!(Context, r15) == 0x28);
+    assert!(mem::offset_of!(Context, rsp) == 0x30);
+    assert!(mem::size_of::<Context>() == 0x38);
+};
+
+// The seed is the trampoline's address alone, so that `ret` pops it to leave the stack 16 byte
+// aligned for the trampoline's call, as the ABI requires.
+const _: () = assert!(SEED_SIZE == mem::size_of::<u64>());
+
+impl Context {
+    /// Prepares the stack ending at `stack_top` so that switching to the returned [`Context`]
+    /// calls `entry(argument)`.
+    ///
+    /// `entry` must not return.
+    ///
+    /// # Safety
+    /// `stack_top` must be the 16 byte aligned end of a stack that is valid for writes of at least
+    /// [`SEED_SIZE`] bytes and is not in use.
+    pub unsafe fn seed(
+        stack_top: VirtualAddress,
+        entry: extern "C" fn(usize) -> !,
+        argument: usize,
+    ) -> Self {
+        let return_address = (stack_top.value() - SEED_SIZE) as *mut u64;
+
+        // SAFETY:
+        // The caller guarantees that the top `SEED_SIZE` bytes of the stack are writable and
+        // unused, and the slot is aligned since `stack_top` is.
+        unsafe { return_address.write(thread_trampoline as *const () as u64) };
+
+        Self {
+            rbx: argument as u64,
+            rbp: 0,
+            r12: entry as *const () as u64,
+            r13: 0,
+            r14: 0,
+            r15: 0,
+            rsp: return_address as u64,
+        }
+    }
+}
+
+core::arch::global_asm!(
+    ".pushsection .text.switch_context, \"ax\"",
+    ".global switch_context",
+    "switch_context:",
+    "mov [rdi + 0x00], rbx",
+    "mov [rdi + 0x08], rbp",
+    "mov [rdi + 0x10], r12",
+    "mov [rdi + 0x18], r13",
+    "mov [rdi + 0x20], r14",
+    "mov [rdi + 0x28], r15",
+    "mov [rdi + 0x30], rsp",
+    "mov rbx, [rsi + 0x00]",
+    "mov rbp, [rsi + 0x08]",
+    "mov r12, [rsi + 0x10]",
+    "mov r13, [rsi + 0x18]",
+    "mov r14, [rsi + 0x20]",
+    "mov r15, [rsi + 0x28]",
+    "mov rsp, [rsi + 0x30]",
+    "ret",
+    "",
+    // The first code run by a new thread. Calls the entry function in `r12` with the argument in
+    // `rbx`.
+    ".global thread_trampoline",
+    "thread_trampoline:",
+    "mov rdi, rbx",
+    "call r12",
+    "ud2",
+    ".popsection",
+);
+
+extern "C" {
+    /// Saves the current context to `old` and resumes the context in `new`.
+    ///
+    /// Returns once another call to [`switch_context()`] resumes `old`.
+    ///
+    /// # Safety
+    /// - `old` must be valid for writes of a [`Context`].
+    /// - `new` must be valid for reads of a [`Context`] that was saved by [`switch_context()`] or
+    ///   created by [`Context::seed()`], and whose stack is not in use.
+    /// - Interrupts should be disabled, since the switch is not atomic.
+    pub fn switch_context(old: *mut Context, new: *const Context);
+
+    /// The first code run by a thread whose [`Context`] was created by [`Context::seed()`].
+    fn thread_trampoline();
+}
+
+#[cfg(feature = "ktest")]
+crate::kernel_test! {
+    fn seed_layout() {
+        /// A stack only large enough for the seed.
+        #[repr(C, align(16))]
+        struct SeedStack([u64; 4]);
+
+        extern "C" fn entry(_: usize) -> ! {
+            unreachable!("seeded context was never switched to")
+        }
+
+        let mut stack = SeedStack([u64::MAX; 4]);
+        let top = VirtualAddress::new_canonical(stack.0.as_mut_ptr_range().end as usize);
+        // SAFETY:
+        // `top` is the 16 byte aligned end of `stack`, which is not otherwise in use.
+        let context = unsafe { Context::seed(top, entry, 0x1234) };
+
+        assert_eq!(context.rsp, top.value() as u64 - SEED_SIZE as u64);
+        assert_eq!((context.rsp + SEED_SIZE as u64) % 16, 0);
+        assert_eq!(context.rbx, 0x1234);
+        assert_eq!(context.r12, entry as *const () as u64);
+        assert_eq!(context.rbp, 0);
+        assert_eq!(stack.0[3], thread_trampoline as *const () as u64);
+        assert_eq!(stack.0[..3], [u64::MAX; 3]);
+    }
+}
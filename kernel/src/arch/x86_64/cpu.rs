@@ -0,0 +1,379 @@
+//! Identification and control of the processor the kernel is running on.
+
+use crate::{arch::x86_64::memory::VirtualAddress, spinlock::InterruptControl};
+
+/// The bit of `ecx` returned by CPUID leaf 1 that is set when running under a hypervisor.
+const HYPERVISOR_PRESENT: u32 = 1 << 31;
+
+/// Returns `true` if the processor reports that the kernel is running under a hypervisor.
+pub fn hypervisor_present() -> bool {
+    let ecx: u32;
+    // SAFETY:
+    // CPUID is available on every `x86_64` processor and has no side effects. `rbx` is reserved
+    // by LLVM, so it is preserved manually.
+    unsafe {
+        core::arch::asm!(
+            "mov {rbx_save}, rbx",
+            "cpuid",
+            "mov rbx, {rbx_save}",
+            rbx_save = out(reg) _,
+            inout("eax") 1 => _,
+            out("ecx") ecx,
+            out("edx") _,
+            options(nomem, nostack, preserves_flags)
+        )
+    }
+
+    ecx & HYPERVISOR_PRESENT == HYPERVISOR_PRESENT
+}
+
+/// The bit of `ecx` returned by CPUID leaf 1 that is set when the local APIC supports x2APIC mode.
+const X2APIC_SUPPORTED: u32 = 1 << 21;
+
+/// Returns `true` if the local APIC of the current processor supports x2APIC mode.
+pub fn x2apic_supported() -> bool {
+    let ecx: u32;
+    // SAFETY:
+    // CPUID is available on every `x86_64` processor and has no side effects. `rbx` is reserved
+    // by LLVM, so it is preserved manually.
+    unsafe {
+        core::arch::asm!(
+            "mov {rbx_save}, rbx",
+            "cpuid",
+            "mov rbx, {rbx_save}",
+            rbx_save = out(reg) _,
+            inout("eax") 1 => _,
+            out("ecx") ecx,
+            out("edx") _,
+            options(nomem, nostack, preserves_flags)
+        )
+    }
+
+    ecx & X2APIC_SUPPORTED == X2APIC_SUPPORTED
+}
+
+/// Returns the initial APIC ID of the current processor, which uniquely identifies it.
+pub fn apic_id() -> u32 {
+    let rbx: u64;
+    // SAFETY:
+    // CPUID is available on every `x86_64` processor and has no side effects. `rbx` is reserved
+    // by LLVM, so it is preserved manually, exchanging it with the result.
+    unsafe {
+        core::arch::asm!(
+            "mov {rbx_save}, rbx",
+            "cpuid",
+            "xchg {rbx_save}, rbx",
+            rbx_save = out(reg) rbx,
+            inout("eax") 1 => _,
+            out("ecx") _,
+            out("edx") _,
+            options(nomem, nostack, preserves_flags)
+        )
+    }
+
+    (rbx as u32) >> 24
+}
+
+/// Reads the model specific register `msr`.
+///
+/// # Safety
+/// `msr` must exist on the current processor and reading it must not have side effects that
+/// violate memory safety.
+pub unsafe fn read_msr(msr: u32) -> u64 {
+    let low: u32;
+    let high: u32;
+    // SAFETY:
+    // The invariants are upheld by the caller.
+    unsafe {
+        core::arch::asm!(
+            "rdmsr",
+            in("ecx") msr,
+            out("eax") low,
+            out("edx") high,
+            options(nomem, nostack, preserves_flags)
+        )
+    };
+
+    (high as u64) << 32 | low as u64
+}
+
+/// Writes `value` to the model specific register `msr`.
+///
+/// # Safety
+/// `msr` must exist on the current processor and writing `value` to it must not violate memory
+/// safety.
+pub unsafe fn write_msr(msr: u32, value: u64) {
+    // SAFETY:
+    // The invariants are upheld by the caller.
+    unsafe {
+        core::arch::asm!(
+            "wrmsr",
+            in("ecx") msr,
+            in("eax") value as u32,
+            in("edx") (value >> 32) as u32,
+            options(nostack, preserves_flags)
+        )
+    };
+}
+
+/// Returns the current value of the stack pointer.
+#[inline(always)]
+pub fn stack_pointer() -> VirtualAddress {
+    let rsp: usize;
+    // SAFETY:
+    // Copying `rsp` has no side effects.
+    unsafe {
+        core::arch::asm!("mov {}, rsp", out(reg) rsp, options(nomem, nostack, preserves_flags))
+    };
+
+    VirtualAddress::new_canonical(rsp)
+}
+
+/// Returns the address whose access caused the last page fault.
+pub fn read_cr2() -> u64 {
+    let address: u64;
+    // SAFETY:
+    // Reading `cr2` has no side effects.
+    unsafe {
+        core::arch::asm!(
+            "mov {}, cr2",
+            out(reg) address,
+            options(nomem, nostack, preserves_flags)
+        )
+    };
+
+    address
+}
+
+/// The bit of `rflags` that is set when maskable interrupts are enabled.
+const RFLAGS_INTERRUPT_ENABLE: u64 = 1 << 9;
+
+/// Returns `true` if maskable interrupts are enabled on the current processor.
+pub fn interrupts_enabled() -> bool {
+    let rflags: u64;
+    // SAFETY:
+    // Reading `rflags` has no side effects.
+    unsafe {
+        core::arch::asm!("pushfq", "pop {}", out(reg) rflags, options(nomem, preserves_flags))
+    };
+
+    rflags & RFLAGS_INTERRUPT_ENABLE == RFLAGS_INTERRUPT_ENABLE
+}
+
+/// Disables maskable interrupts on the current processor.
+pub fn disable_interrupts() {
+    // SAFETY:
+    // Disabling interrupts has no memory safety implications.
+    unsafe { core::arch::asm!("cli", options(nomem, nostack)) };
+}
+
+/// Enables maskable interrupts on the current processor.
+///
+/// # Safety
+/// Interrupt handlers must be able to run without deadlocking or observing broken invariants.
+pub unsafe fn enable_interrupts() {
+    // SAFETY:
+    // The invariants are upheld by the caller.
+    unsafe { core::arch::asm!("sti", options(nomem, nostack)) };
+}
+
+/// Enables maskable interrupts and halts the current processor until the next interrupt arrives.
+///
+/// Enabling interrupts only takes effect after the instruction following `sti`, so an interrupt
+/// that becomes pending in between still ends the halt instead of being handled before it and
+/// leaving the processor halted. Interrupts remain enabled when this returns.
+///
+/// # Safety
+/// Interrupt handlers must be able to run without deadlocking or observing broken invariants.
+pub unsafe fn wait_for_interrupt() {
+    // SAFETY:
+    // The invariants are upheld by the caller.
+    unsafe { core::arch::asm!("sti", "hlt", options(nomem, nostack)) };
+}
+
+/// Halts the current processor until the next interrupt arrives, leaving the interrupt state
+/// unchanged.
+///
+/// With maskable interrupts disabled, only a non-maskable interrupt ends the halt.
+pub fn halt() {
+    // SAFETY:
+    // Halting has no memory safety implications.
+    unsafe { core::arch::asm!("hlt", options(nomem, nostack, preserves_flags)) };
+}
+
+/// Disables maskable interrupts and halts the current processor forever.
+pub fn halt_forever() -> ! {
+    disable_interrupts();
+
+    loop {
+        halt();
+    }
+}
+
+/// Runs `f` with maskable interrupts disabled, restoring their previous state afterwards.
+pub fn without_interrupts<R>(f: impl FnOnce() -> R) -> R {
+    let enabled = interrupts_enabled();
+    if enabled {
+        disable_interrupts();
+    }
+
+    let result = f();
+
+    if enabled {
+        // SAFETY:
+        // Interrupts were enabled before `f` was called, so re-enabling them restores the
+        // previous state.
+        unsafe { enable_interrupts() };
+    }
+
+    result
+}
+
+/// The [`InterruptControl`] implementation for the current processor.
+#[derive(Clone, Copy, Debug, Default, Hash, PartialEq, Eq)]
+pub struct Interrupts;
+
+impl InterruptControl for Interrupts {
+    fn interrupts_enabled() -> bool {
+        interrupts_enabled()
+    }
+
+    fn disable_interrupts() {
+        disable_interrupts()
+    }
+
+    unsafe fn enable_interrupts() {
+        // SAFETY:
+        // The invariants are upheld by the caller.
+        unsafe { enable_interrupts() }
+    }
+}
+
+/// The values of the general purpose and control registers most useful when diagnosing a panic.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub struct RegisterSnapshot {
+    /// The general purpose registers other than `rsp` and `rbp`, in the order `rax`, `rbx`,
+    /// `rcx`, `rdx`, `rsi`, `rdi`, `r8` through `r15`.
+    pub gprs: [u64; 14],
+    /// The stack pointer.
+    pub rsp: u64,
+    /// The frame pointer.
+    pub rbp: u64,
+    /// The flags register.
+    pub rflags: u64,
+    /// The control register holding the protection and paging enables.
+    pub cr0: u64,
+    /// The control register holding the address of the last page fault.
+    pub cr2: u64,
+    /// The control register holding the physical address of the top-level page table.
+    pub cr3: u64,
+    /// The control register holding the architectural extension enables.
+    pub cr4: u64,
+}
+
+impl RegisterSnapshot {
+    /// Captures the registers of the current processor.
+    ///
+    /// The registers are those of the caller, after inlining, at the point of the call. The
+    /// general purpose registers hold whatever the compiler left in them there.
+    #[inline(always)]
+    pub fn capture() -> Self {
+        let (rsp, rbp, rflags, cr0, cr2, cr3, cr4): (u64, u64, u64, u64, u64, u64, u64);
+        let mut gprs = [0u64; 14];
+        // SAFETY:
+        // The empty template only reports the current values of the registers. `rbx` is reserved
+        // by LLVM, so it is copied explicitly.
+        unsafe {
+            core::arch::asm!(
+                "mov {rbx}, rbx",
+                rbx = out(reg) gprs[1],
+                out("rax") gprs[0],
+                out("rcx") gprs[2],
+                out("rdx") gprs[3],
+                out("rsi") gprs[4],
+                out("rdi") gprs[5],
+                out("r8") gprs[6],
+                out("r9") gprs[7],
+                out("r10") gprs[8],
+                out("r11") gprs[9],
+                out("r12") gprs[10],
+                out("r13") gprs[11],
+                out("r14") gprs[12],
+                out("r15") gprs[13],
+                options(nomem, nostack, preserves_flags)
+            )
+        };
+        // SAFETY:
+        // Copying `rsp` has no side effects.
+        unsafe {
+            core::arch::asm!("mov {}, rsp", out(reg) rsp, options(nomem, nostack, preserves_flags))
+        };
+        // SAFETY:
+        // Copying `rbp` has no side effects.
+        unsafe {
+            core::arch::asm!("mov {}, rbp", out(reg) rbp, options(nomem, nostack, preserves_flags))
+        };
+        // SAFETY:
+        // Reading `rflags` has no side effects.
+        unsafe {
+            core::arch::asm!("pushfq", "pop {}", out(reg) rflags, options(nomem, preserves_flags))
+        };
+        // SAFETY:
+        // The kernel runs at CPL 0, where reading control registers has no side effects.
+        unsafe {
+            core::arch::asm!("mov {}, cr0", out(reg) cr0, options(nomem, nostack, preserves_flags))
+        };
+        // SAFETY:
+        // Same as above.
+        unsafe {
+            core::arch::asm!("mov {}, cr2", out(reg) cr2, options(nomem, nostack, preserves_flags))
+        };
+        // SAFETY:
+        // Same as above.
+        unsafe {
+            core::arch::asm!("mov {}, cr3", out(reg) cr3, options(nomem, nostack, preserves_flags))
+        };
+        // SAFETY:
+        // Same as above.
+        unsafe {
+            core::arch::asm!("mov {}, cr4", out(reg) cr4, options(nomem, nostack, preserves_flags))
+        };
+
+        Self {
+            gprs,
+            rsp,
+            rbp,
+            rflags,
+            cr0,
+            cr2,
+            cr3,
+            cr4,
+        }
+    }
+}
+
+/// The names of the registers in [`RegisterSnapshot::gprs`].
+const GPR_NAMES: [&str; 14] = [
+    "rax", "rbx", "rcx", "rdx", "rsi", "rdi", "r8", "r9", "r10", "r11", "r12", "r13", "r14", "r15",
+];
+
+impl core::fmt::Display for RegisterSnapshot {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        for (names, values) in GPR_NAMES.chunks(3).zip(self.gprs.chunks(3)) {
+            for (index, (name, value)) in names.iter().zip(values).enumerate() {
+                if index != 0 {
+                    f.write_str(" ")?;
+                }
+                write!(f, "{name:>3}={value:016x}")?;
+            }
+            f.write_str("\n")?;
+        }
+        writeln!(
+            f,
+            "rsp={:016x} rbp={:016x} rflags={:016x}",
+            self.rsp, self.rbp, self.rflags
+        )?;
+        writeln!(f, "cr0={:016x} cr2={:016x}", self.cr0, self.cr2)?;
+        write!(f, "cr3={:016x} cr4={:016x}", self.cr3, self.cr4)
+    }
+}
@@ -1,43 +1,256 @@
 //! Driver for the debugcon device.
 
-use crate::spinlock::{Spinlock, SpinlockGuard};
+use crate::spinlock::{Acquisition, IrqSpinlock, IrqSpinlockGuard};
 
-static LOCK: Spinlock<Debugcon> = Spinlock::new(Debugcon());
+// The debugcon device is written by the logging path, which may run in interrupt handlers, so it
+// is only held with interrupts disabled.
+static LOCK: IrqSpinlock<Debugcon> = IrqSpinlock::new(Debugcon::new());
 
-/// Acquires the debugcon driver.
-pub fn acquire_debugcon() -> SpinlockGuard<'static, Debugcon> {
+/// The size, in bytes, of the line buffer of the debugcon device.
+pub const LINE_BUFFER_SIZE: usize = 256;
+
+/// Whether a debugcon device was detected, along with the evidence used to decide.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum DebugconDetection {
+    /// The port responded and a hypervisor is present.
+    Present,
+    /// The port responded, but no hypervisor was reported, as is the case under Bochs.
+    PresentWithoutHypervisor,
+    /// The port did not respond, even though a hypervisor is present.
+    AbsentUnderHypervisor,
+    /// The port did not respond and no hypervisor is present, as expected on real hardware.
+    Absent,
+}
+
+impl DebugconDetection {
+    /// Decides whether debugcon is present from the result of probing the port and whether a
+    /// hypervisor is present.
+    pub const fn decide(port_responded: bool, hypervisor_present: bool) -> Self {
+        match (port_responded, hypervisor_present) {
+            (true, true) => Self::Present,
+            (true, false) => Self::PresentWithoutHypervisor,
+            (false, true) => Self::AbsentUnderHypervisor,
+            (false, false) => Self::Absent,
+        }
+    }
+
+    /// Returns `true` if the debugcon device should be used.
+    pub const fn enabled(self) -> bool {
+        matches!(self, Self::Present | Self::PresentWithoutHypervisor)
+    }
+}
+
+/// Acquires the debugcon driver, disabling interrupts until the returned guard is dropped.
+///
+/// This does not check whether a debugcon device is present.
+pub fn acquire_debugcon() -> IrqSpinlockGuard<'static, Debugcon> {
     LOCK.lock()
 }
 
-pub struct Debugcon();
+/// Acquires the debugcon device, forcibly unlocking it after `max_attempts` failed attempts.
+///
+/// # Safety
+/// See [`IrqSpinlock::force_unlock()`].
+pub unsafe fn acquire_debugcon_or_bypass(
+    max_attempts: usize,
+) -> (IrqSpinlockGuard<'static, Debugcon>, Acquisition) {
+    // SAFETY:
+    // The invariants of `IrqSpinlock::lock_or_bypass()` are upheld by the caller.
+    unsafe { LOCK.lock_or_bypass(max_attempts) }
+}
+
+/// A fixed-size buffer accumulating bytes until a full line can be written at once.
+#[derive(Clone, Copy, Debug)]
+pub struct LineBuffer<const N: usize> {
+    /// The buffered bytes, of which the first `len` are in use.
+    data: [u8; N],
+    /// The number of buffered bytes.
+    len: usize,
+}
+
+impl<const N: usize> LineBuffer<N> {
+    /// Creates a new, empty [`LineBuffer`].
+    pub const fn new() -> Self {
+        Self {
+            data: [0; N],
+            len: 0,
+        }
+    }
+
+    /// Returns the buffered bytes.
+    pub fn buffered(&self) -> &[u8] {
+        // `len` is clamped in case a panic interrupted an update.
+        &self.data[..self.len.min(N)]
+    }
+
+    /// Appends `bytes`, passing the buffered bytes to `emit` whenever a newline is appended or
+    /// the buffer fills.
+    pub fn push(&mut self, mut bytes: &[u8], mut emit: impl FnMut(&[u8])) {
+        while !bytes.is_empty() {
+            let len = self.len.min(N);
+            let take = bytes.len().min(N - len);
+            let (chunk, rest) = bytes.split_at(take);
+
+            let newline = chunk.iter().position(|&byte| byte == b'\n');
+            let chunk = newline.map_or(chunk, |index| &chunk[..=index]);
+            self.data[len..len + chunk.len()].copy_from_slice(chunk);
+            self.len = len + chunk.len();
+
+            if newline.is_some() || self.len == N {
+                self.flush(&mut emit);
+            }
+
+            bytes = match newline {
+                Some(index) => &bytes[index + 1..],
+                None => rest,
+            };
+        }
+    }
+
+    /// Passes any buffered bytes to `emit` and empties the buffer.
+    pub fn flush(&mut self, mut emit: impl FnMut(&[u8])) {
+        let buffered = self.buffered();
+        if !buffered.is_empty() {
+            emit(buffered);
+        }
+        self.len = 0;
+    }
+}
+
+impl<const N: usize> Default for LineBuffer<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The debugcon device, buffering output so that each line is written with a single port
+/// operation.
+pub struct Debugcon {
+    /// The output that has not been written to the device yet.
+    buffer: LineBuffer<LINE_BUFFER_SIZE>,
+    /// The number of port operations issued.
+    port_operations: u64,
+}
 
 impl Debugcon {
-    pub fn write_byte(&mut self, byte: u8) {
+    /// The I/O port of the debugcon device.
+    pub const PORT: u16 = 0xe9;
+
+    /// Creates a new [`Debugcon`] with an empty line buffer.
+    pub const fn new() -> Self {
+        Self {
+            buffer: LineBuffer::new(),
+            port_operations: 0,
+        }
+    }
+
+    /// Returns `true` if a debugcon device is present.
+    ///
+    /// QEMU and Bochs return the port number when the debugcon port is read, while the port is
+    /// usually unclaimed on real hardware and reads as `0xFF`.
+    pub fn detect() -> bool {
+        let value: u8;
+        // SAFETY:
+        // Reading from the debugcon port has no side effects.
         unsafe {
             core::arch::asm!(
-                "out dx, al",
-                in("dx") 0xe9,
-                in("al") byte,
+                "in al, dx",
+                in("dx") Self::PORT,
+                out("al") value,
+                options(nomem, nostack, preserves_flags)
             )
         }
+
+        value == Self::PORT as u8
+    }
+
+    /// Returns the number of port operations issued, each of which traps to the hypervisor.
+    pub const fn port_operations(&self) -> u64 {
+        self.port_operations
     }
 
-    pub fn write_bytes(&mut self, bytes: &[u8]) {
+    /// Writes `byte` to the device immediately, bypassing the line buffer.
+    pub fn write_byte(&mut self, byte: u8) {
+        self.port_operations += 1;
         unsafe {
             core::arch::asm!(
-                "rep outsb",
+                "out dx, al",
                 in("dx") 0xe9,
-                inout("rsi") bytes.as_ptr() => _,
-                inout("rcx") bytes.len() => _,
+                in("al") byte,
             )
         }
     }
+
+    /// Appends `bytes` to the line buffer, writing it to the device whenever a line is complete or
+    /// the buffer fills.
+    pub fn write_buffered(&mut self, bytes: &[u8]) {
+        let port_operations = &mut self.port_operations;
+        self.buffer
+            .push(bytes, |line| write_bytes(port_operations, line));
+    }
+
+    /// Writes any buffered output to the device.
+    pub fn flush(&mut self) {
+        let port_operations = &mut self.port_operations;
+        self.buffer.flush(|line| write_bytes(port_operations, line));
+    }
+}
+
+impl Default for Debugcon {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl core::fmt::Write for Debugcon {
     fn write_str(&mut self, s: &str) -> core::fmt::Result {
-        self.write_bytes(s.as_bytes());
+        self.write_buffered(s.as_bytes());
 
         Ok(())
     }
 }
+
+/// Writes `bytes` to the debugcon device with a single `rep outsb`, counting it in
+/// `port_operations`.
+fn write_bytes(port_operations: &mut u64, bytes: &[u8]) {
+    *port_operations += 1;
+    // SAFETY:
+    // `rep outsb` only reads the `bytes.len()` bytes of `bytes`, since the direction flag is clear
+    // on entry to Rust code, and the debugcon port has no effect beyond emitting them.
+    unsafe {
+        core::arch::asm!(
+            "rep outsb",
+            in("dx") 0xe9,
+            inout("rsi") bytes.as_ptr() => _,
+            inout("rcx") bytes.len() => _,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detection_table() {
+        for (port_responded, hypervisor_present, detection, enabled) in [
+            (true, true, DebugconDetection::Present, true),
+            (
+                true,
+                false,
+                DebugconDetection::PresentWithoutHypervisor,
+                true,
+            ),
+            (false, true, DebugconDetection::AbsentUnderHypervisor, false),
+            (false, false, DebugconDetection::Absent, false),
+        ] {
+            let decided = DebugconDetection::decide(port_responded, hypervisor_present);
+
+            assert_eq!(
+                decided, detection,
+                "port_responded={port_responded} hypervisor_present={hypervisor_present}"
+            );
+            assert_eq!(decided.enabled(), enabled, "{decided:?}");
+        }
+    }
+}
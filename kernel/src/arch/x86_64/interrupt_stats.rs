@@ -0,0 +1,29 @@
+//! Counting of the interrupts handled by each processor.
+
+use crate::arch::x86_64::per_cpu::PerCpuAccessError;
+
+/// The number of interrupt vectors.
+pub const VECTOR_COUNT: usize = 256;
+
+crate::per_cpu! {
+    /// The number of times each interrupt vector was handled on the current processor.
+    static COUNTS: [u64; VECTOR_COUNT] = [0; VECTOR_COUNT];
+}
+
+/// Counts an interrupt on `vector` handled by the current processor.
+///
+/// Nothing is counted if per-CPU areas have not been initialized yet, so this is safe to call
+/// from any interrupt handler.
+pub fn record(vector: u8) {
+    let _ = COUNTS.try_with(|counts| {
+        counts[vector as usize] = counts[vector as usize].wrapping_add(1);
+    });
+}
+
+/// Returns the number of times each interrupt vector was handled on the current processor.
+///
+/// # Errors
+/// Returns [`PerCpuAccessError`] if the counts cannot be accessed.
+pub fn counts() -> Result<[u64; VECTOR_COUNT], PerCpuAccessError> {
+    COUNTS.try_with(|counts| *counts)
+}
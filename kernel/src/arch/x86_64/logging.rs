@@ -1,79 +1,441 @@
 //! Driver for `x86_64` logging capabilities.
 
-#[cfg(any(feature = "debugcon-logging", feature = "serial-logging"))]
+#[cfg(any(
+    feature = "debugcon-logging",
+    feature = "serial-logging",
+    feature = "framebuffer-logging"
+))]
 use core::fmt::Write;
+#[cfg(feature = "debugcon-logging")]
+use core::sync::atomic::AtomicBool;
+#[cfg(feature = "serial-logging")]
+use core::sync::atomic::AtomicU16;
+#[cfg(any(feature = "debugcon-logging", feature = "serial-logging"))]
+use core::sync::atomic::Ordering;
+
+#[cfg(feature = "debugcon-logging")]
+use crate::arch::x86_64::{
+    cpu,
+    debugcon::{acquire_debugcon, acquire_debugcon_or_bypass, Debugcon, DebugconDetection},
+};
 
 #[cfg(feature = "serial-logging")]
+use crate::arch::x86_64::serial::{
+    acquire_serial_port, acquire_serial_port_or_bypass, BaudError, BaudRate, DataBits, DmaMode,
+    DmaTriggerLevel, FifoControl, InterruptEnable, Parity, PortSelection, PortSelectionError,
+    SerialPort, SerialProbeError, StopBits, DEFAULT_SERIAL_PORT, STANDARD_PORTS,
+};
+
 use crate::{
-    arch::x86_64::serial::{
-        DmaMode, DmaTriggerLevel, FifoControl, InterruptEnable, LineControl, SerialPort,
-    },
-    spinlock::Spinlock,
+    logging::sink::{write_line, Sink, SinkRegistry},
+    spinlock::Acquisition,
+};
+
+#[cfg(feature = "framebuffer-logging")]
+use crate::{
+    arch::x86_64::{boot::info::FramebufferInfo, memory::direct_map},
+    console::{Console, ConsoleError, FramebufferLayout, PixelFormat},
+    spinlock::IrqSpinlock,
 };
 
-#[cfg(not(any(feature = "debugcon-logging", feature = "serial-logging")))]
+#[cfg(not(any(
+    feature = "debugcon-logging",
+    feature = "serial-logging",
+    feature = "framebuffer-logging"
+)))]
 compile_error!("Kernel logging must have an output method");
 
-/// Initializes architecture specific logging mechanisms.
-pub fn init_arch_logger(logger: &mut ArchitectureLogger) {
+/// The console drawing to the framebuffer, once framebuffer logging has been initialized.
+///
+/// Like every lock on the logging path, this is only held with interrupts disabled.
+#[cfg(feature = "framebuffer-logging")]
+static FRAMEBUFFER_CONSOLE: IrqSpinlock<Option<Console<'static>>> = IrqSpinlock::new(None);
+
+/// Whether the debugcon device is used for logging, as read by [`emergency_write()`].
+#[cfg(feature = "debugcon-logging")]
+static DEBUGCON_IN_USE: AtomicBool = AtomicBool::new(false);
+
+/// The base I/O port of the serial port used for logging, or zero if there is none, as read by
+/// [`emergency_write()`].
+#[cfg(feature = "serial-logging")]
+static SERIAL_IO_PORT: AtomicU16 = AtomicU16::new(0);
+
+/// Initializes architecture specific logging mechanisms, applying the options in `cmdline`, and
+/// registers every working one in `_sinks`.
+///
+/// The returned [`InitReport`] should be logged once logging is functional.
+pub fn init_arch_logger(_sinks: &mut SinkRegistry, _cmdline: Option<&str>) -> InitReport {
+    #[cfg(feature = "debugcon-logging")]
+    let debugcon = {
+        let detection = DebugconDetection::decide(Debugcon::detect(), cpu::hypervisor_present());
+        if detection.enabled() {
+            // The registry has room for every built-in sink.
+            let _ = _sinks.register(&DEBUGCON_SINK);
+            DEBUGCON_IN_USE.store(true, Ordering::Relaxed);
+        }
+
+        detection
+    };
+
     #[cfg(feature = "serial-logging")]
-    {
-        let mut serial_port = logger.serial_port.lock();
-        serial_port.set_interrupt_enable(InterruptEnable::new());
-        serial_port.set_line_control(LineControl::new().set_dlab(true));
-        serial_port.set_divisor(1);
-        serial_port.set_line_control(LineControl::new());
-        serial_port.set_fifo_control(
-            FifoControl::new()
-                .enable_fifo(true)
-                .reset_receive_fifo(true)
-                .reset_transmit_fifo(true)
-                .dma_mode(DmaMode::MultiByte)
-                .trigger_level(DmaTriggerLevel::Bytes14),
-        );
+    let serial = {
+        let (selection, selection_error) =
+            match _cmdline.and_then(|cmdline| crate::cmdline::get_str(cmdline, "serial")) {
+                None => (PortSelection::Port(DEFAULT_SERIAL_PORT), None),
+                Some(value) => match PortSelection::parse(value) {
+                    Ok(selection) => (selection, None),
+                    Err(error) => (PortSelection::Port(DEFAULT_SERIAL_PORT), Some(error)),
+                },
+            };
+
+        let mut serial_port = acquire_serial_port();
+        let result = match selection {
+            PortSelection::Port(port) => {
+                // SAFETY:
+                // The port was selected by the user, who is responsible for it being a serial
+                // port.
+                *serial_port = unsafe { SerialPort::new(port) };
+                init_serial_port(&mut serial_port).map(|()| port)
+            }
+            PortSelection::Auto => STANDARD_PORTS
+                .into_iter()
+                .find(|&port| {
+                    // SAFETY:
+                    // The standard ports are reserved for serial ports, and the self-test rejects
+                    // ports that do not behave like one.
+                    *serial_port = unsafe { SerialPort::new(port) };
+                    init_serial_port(&mut serial_port).is_ok()
+                })
+                .ok_or(SerialInitError::NoPortFound),
+        };
+
+        // Writing to a port without a UART behind it spins forever waiting for the transmitter,
+        // so the port must prove it works before it is used.
+        if let Ok(port) = result {
+            // The registry has room for every built-in sink.
+            let _ = _sinks.register(&SERIAL_SINK);
+            SERIAL_IO_PORT.store(port, Ordering::Relaxed);
+        }
+
+        SerialReport {
+            selection_error,
+            result,
+        }
+    };
+
+    InitReport {
+        #[cfg(feature = "debugcon-logging")]
+        debugcon,
+        #[cfg(feature = "serial-logging")]
+        serial,
     }
 }
 
-/// An architecture specific logger.
-pub struct ArchitectureLogger {
+/// The [`FifoControl`] value programmed into the serial port used for logging.
+///
+/// Nothing drives the UART's DMA signals, so single byte mode is used, giving the conventional
+/// FIFO control value of 0xC7.
+#[cfg(feature = "serial-logging")]
+const SERIAL_FIFO_CONTROL: FifoControl = FifoControl::new()
+    .enable_fifo(true)
+    .reset_receive_fifo(true)
+    .reset_transmit_fifo(true)
+    .set_dma_mode(DmaMode::SingleByte)
+    .set_trigger_level(DmaTriggerLevel::Bytes14);
+
+/// Configures `serial_port` for logging and verifies that it works.
+#[cfg(feature = "serial-logging")]
+fn init_serial_port(serial_port: &mut SerialPort) -> Result<(), SerialInitError> {
+    serial_port.set_interrupt_enable(InterruptEnable::new());
+    serial_port
+        .configure(
+            BaudRate::Baud115200,
+            DataBits::Bits8,
+            StopBits::OneBit,
+            Parity::Disabled,
+        )
+        .map_err(SerialInitError::InvalidBaudRate)?;
+    serial_port.set_fifo_control(SERIAL_FIFO_CONTROL);
+
+    serial_port
+        .self_test()
+        .map_err(|error| SerialInitError::SelfTest {
+            port: serial_port.io_port(),
+            error,
+        })
+}
+
+/// The outcome of [`init_arch_logger`], to be logged once logging is functional.
+#[derive(Clone, Copy, Debug)]
+pub struct InitReport {
+    /// The outcome of detecting the debugcon device.
+    #[cfg(feature = "debugcon-logging")]
+    debugcon: DebugconDetection,
+    /// The outcome of initializing the serial port.
     #[cfg(feature = "serial-logging")]
-    serial_port: Spinlock<SerialPort>,
+    serial: SerialReport,
 }
 
-impl ArchitectureLogger {
-    /// Creates a new uninitialzed [`ArchitectureLogger`].
-    pub const fn new() -> Self {
-        Self {
-            #[cfg(feature = "serial-logging")]
-            serial_port: unsafe {
-                crate::spinlock::Spinlock::new(crate::arch::x86_64::serial::SerialPort::new(0x3f8))
-            },
+impl InitReport {
+    /// Logs the outcome of [`init_arch_logger`].
+    pub fn log(&self) {
+        #[cfg(feature = "debugcon-logging")]
+        match self.debugcon {
+            DebugconDetection::Present => {}
+            DebugconDetection::PresentWithoutHypervisor => {
+                log::info!("debugcon: present without a hypervisor")
+            }
+            DebugconDetection::AbsentUnderHypervisor | DebugconDetection::Absent => {
+                log::info!("debugcon: not present, disabled")
+            }
+        }
+
+        #[cfg(feature = "serial-logging")]
+        {
+            if let Some(error) = self.serial.selection_error {
+                log::warn!(
+                    "Invalid serial port selection: {error}; using {DEFAULT_SERIAL_PORT:#X}"
+                );
+            }
+
+            match self.serial.result {
+                Ok(port) => log::info!("Serial logging on port {port:#X}"),
+                Err(error) => log::warn!("{error}; serial logging disabled"),
+            }
         }
     }
 }
 
-impl log::Log for ArchitectureLogger {
-    fn enabled(&self, _metadata: &log::Metadata) -> bool {
-        true
+/// The outcome of initializing the serial port.
+#[cfg(feature = "serial-logging")]
+#[derive(Clone, Copy, Debug)]
+struct SerialReport {
+    /// The reason the serial port requested by the kernel command line was ignored.
+    selection_error: Option<PortSelectionError>,
+    /// The base I/O port of the serial port in use.
+    result: Result<u16, SerialInitError>,
+}
+
+/// Various errors that can occur while initializing the serial port.
+#[cfg(feature = "serial-logging")]
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+enum SerialInitError {
+    /// The serial port failed its self-test.
+    SelfTest {
+        /// The base I/O port of the serial port.
+        port: u16,
+        /// The reason the self-test failed.
+        error: SerialProbeError,
+    },
+    /// None of the standard serial ports passed the self-test.
+    NoPortFound,
+    /// The serial port cannot be configured for the requested baud rate.
+    InvalidBaudRate(BaudError),
+}
+
+#[cfg(feature = "serial-logging")]
+impl core::fmt::Display for SerialInitError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::SelfTest { port, error } => {
+                write!(f, "serial port {port:#X} failed its self-test: {error}")
+            }
+            Self::NoPortFound => f.pad("no standard serial port passed its self-test"),
+            Self::InvalidBaudRate(error) => write!(f, "invalid serial baud rate: {error}"),
+        }
     }
+}
 
-    fn log(&self, record: &log::Record) {
-        #[cfg(feature = "debugcon-logging")]
-        let _ = writeln!(
-            crate::arch::x86_64::debugcon::acquire_debugcon(),
-            "[{:?}] {}",
-            record.level(),
-            record.args()
-        );
+/// Sets up a console on the framebuffer described by `info`, returning the [`Sink`] that logs to
+/// it.
+///
+/// This must be called after the direct map has been initialized, since the framebuffer is
+/// accessed through it.
+///
+/// # Errors
+/// Returns a [`ConsoleError`] if the framebuffer's pixel format is unsupported or it is too small,
+/// in which case nothing is drawn to it.
+#[cfg(feature = "framebuffer-logging")]
+pub fn init_framebuffer_logging(info: FramebufferInfo) -> Result<&'static dyn Sink, ConsoleError> {
+    let format = PixelFormat::from_masks(
+        info.bpp,
+        (info.red_mask_shift, info.red_mask_size),
+        (info.green_mask_shift, info.green_mask_size),
+        (info.blue_mask_shift, info.blue_mask_size),
+    )?;
+    let layout = FramebufferLayout {
+        width: info.width as usize,
+        height: info.height as usize,
+        pitch: info.pitch as usize,
+        format,
+    };
 
-        #[cfg(feature = "serial-logging")]
-        let _ = writeln!(
-            self.serial_port.lock(),
-            "[{:?}] {}",
-            record.level(),
-            record.args()
-        );
+    let Some(offset) = direct_map::offset() else {
+        return Err(ConsoleError::BufferTooSmall);
+    };
+    let address = offset.value() + info.address.value() as usize;
+    // SAFETY:
+    // The bootloader guarantees that the framebuffer occupies `pitch * height` bytes of physical
+    // memory, which are mapped by the direct map and used by nothing else.
+    let buffer = unsafe {
+        core::slice::from_raw_parts_mut(address as *mut u8, layout.pitch * layout.height)
+    };
+
+    *FRAMEBUFFER_CONSOLE.lock() = Some(Console::new(buffer, layout)?);
+    Ok(&FramebufferSink)
+}
+
+/// Writes `bytes` straight to the debugcon device and serial port used for logging, without taking
+/// their locks.
+///
+/// This is meant for when the logging path can no longer be trusted, such as a panic raised while
+/// handling another. The output may be interleaved with whatever the interrupted code was writing.
+pub fn emergency_write(_bytes: &[u8]) {
+    #[cfg(feature = "debugcon-logging")]
+    if DEBUGCON_IN_USE.load(Ordering::Relaxed) {
+        let mut debugcon = Debugcon::new();
+        for &byte in _bytes {
+            debugcon.write_byte(byte);
+        }
+    }
+
+    #[cfg(feature = "serial-logging")]
+    {
+        let port = SERIAL_IO_PORT.load(Ordering::Relaxed);
+        if port != 0 {
+            // SAFETY:
+            // `port` passed the self-test in `init_arch_logger()`. The transmitter is only polled
+            // a bounded number of times, so this cannot hang on a stalled UART.
+            let mut serial_port = unsafe { SerialPort::new(port) };
+            serial_port.write_bytes(_bytes);
+        }
     }
+}
+
+/// Returns the number of port operations issued to the debugcon device, each of which traps to
+/// the hypervisor.
+#[cfg(feature = "debugcon-logging")]
+pub fn debugcon_port_operations() -> u64 {
+    acquire_debugcon().port_operations()
+}
+
+/// The [`Sink`] writing to the debugcon device.
+#[cfg(feature = "debugcon-logging")]
+static DEBUGCON_SINK: DebugconSink = DebugconSink;
+
+/// A [`Sink`] writing to the debugcon device.
+#[cfg(feature = "debugcon-logging")]
+struct DebugconSink;
+
+#[cfg(feature = "debugcon-logging")]
+impl Sink for DebugconSink {
+    fn name(&self) -> &'static str {
+        "debugcon"
+    }
+
+    fn write_record(&self, record: &log::Record) -> usize {
+        write_line(&mut *acquire_debugcon(), record)
+    }
+
+    fn flush(&self) {
+        acquire_debugcon().flush();
+    }
+
+    unsafe fn panic_write(&self, text: &str, max_attempts: usize) -> Acquisition {
+        // SAFETY:
+        // The invariants of `acquire_debugcon_or_bypass()` are upheld by the caller.
+        let (mut debugcon, acquisition) = unsafe { acquire_debugcon_or_bypass(max_attempts) };
+        // Nothing may flush the buffer after a panic, so it is written out immediately.
+        let _ = debugcon.write_str(text);
+        debugcon.flush();
+        acquisition
+    }
+}
+
+/// The [`Sink`] writing to the serial port.
+#[cfg(feature = "serial-logging")]
+static SERIAL_SINK: SerialSink = SerialSink;
+
+/// A [`Sink`] writing to the serial port.
+#[cfg(feature = "serial-logging")]
+struct SerialSink;
 
-    fn flush(&self) {}
+#[cfg(feature = "serial-logging")]
+impl Sink for SerialSink {
+    fn name(&self) -> &'static str {
+        "serial"
+    }
+
+    fn write_record(&self, record: &log::Record) -> usize {
+        write_line(&mut *acquire_serial_port(), record)
+    }
+
+    fn flush(&self) {
+        // A timeout means the UART is gone, in which case there is nothing left to preserve.
+        let _ = acquire_serial_port().flush();
+    }
+
+    fn dropped_bytes(&self) -> u64 {
+        acquire_serial_port().watchdog().dropped_bytes()
+    }
+
+    fn take_warning(&self) -> Option<&'static str> {
+        acquire_serial_port()
+            .map(SerialPort::watchdog_mut)
+            .take_warning()
+            .then_some("transmitter stalled; dropping output until it recovers")
+    }
+
+    unsafe fn panic_write(&self, text: &str, max_attempts: usize) -> Acquisition {
+        // SAFETY:
+        // The invariants of `acquire_serial_port_or_bypass()` are upheld by the caller.
+        let (mut serial_port, acquisition) = unsafe { acquire_serial_port_or_bypass(max_attempts) };
+        let _ = serial_port.write_str(text);
+        acquisition
+    }
+}
+
+/// A [`Sink`] writing to the framebuffer console.
+#[cfg(feature = "framebuffer-logging")]
+struct FramebufferSink;
+
+#[cfg(feature = "framebuffer-logging")]
+impl Sink for FramebufferSink {
+    fn name(&self) -> &'static str {
+        "framebuffer"
+    }
+
+    fn write_record(&self, record: &log::Record) -> usize {
+        FRAMEBUFFER_CONSOLE
+            .lock()
+            .as_mut()
+            .map_or(0, |console| write_line(console, record))
+    }
+
+    unsafe fn panic_write(&self, text: &str, max_attempts: usize) -> Acquisition {
+        // SAFETY:
+        // The invariants of `IrqSpinlock::lock_or_bypass()` are upheld by the caller.
+        let (mut console, acquisition) =
+            unsafe { FRAMEBUFFER_CONSOLE.lock_or_bypass(max_attempts) };
+        if let Some(console) = console.as_mut() {
+            let _ = console.write_str(text);
+        }
+        acquisition
+    }
+}
+
+#[cfg(all(test, feature = "serial-logging"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serial_fifo_control_is_conventional() {
+        assert_eq!(SERIAL_FIFO_CONTROL.bits(), 0xC7);
+        assert!(SERIAL_FIFO_CONTROL.fifo_enabled());
+        assert_eq!(SERIAL_FIFO_CONTROL.dma_mode(), DmaMode::SingleByte);
+        assert_eq!(
+            SERIAL_FIFO_CONTROL.trigger_level(),
+            DmaTriggerLevel::Bytes14
+        );
+    }
 }
@@ -0,0 +1,50 @@
+//! Tracking of the higher half direct map, the region of virtual memory that maps all of physical
+//! memory at a fixed offset.
+
+use crate::{
+    arch::x86_64::memory::{PhysicalAddress, VirtualAddress},
+    sync::Once,
+};
+
+/// The offset at which physical memory is mapped.
+static OFFSET: Once<VirtualAddress> = Once::new();
+
+/// Records the [`VirtualAddress`] at which the direct map of physical memory starts.
+///
+/// Recording the same offset again has no effect.
+///
+/// # Panics
+/// Panics if the direct map has already been initialized with a different offset.
+#[track_caller]
+pub fn init(offset: VirtualAddress) {
+    let recorded = *OFFSET.call_once(|| offset);
+    assert!(
+        recorded == offset,
+        "direct map already initialized at {recorded:?}, cannot move it to {offset:?}"
+    );
+}
+
+/// Returns the [`VirtualAddress`] at which the direct map of physical memory starts, or [`None`]
+/// if the direct map has not been initialized.
+pub fn offset() -> Option<VirtualAddress> {
+    OFFSET.get().copied()
+}
+
+/// Returns the [`VirtualAddress`] at which the direct map maps `address`.
+///
+/// # Panics
+/// Panics if the direct map has not been initialized.
+#[track_caller]
+pub fn phys_to_virt(address: PhysicalAddress) -> VirtualAddress {
+    let offset = offset().expect("direct map used before it was initialized");
+    offset + address.value() as usize
+}
+
+/// Returns a pointer to the `T` that the direct map maps at `address`.
+///
+/// # Panics
+/// Panics if the direct map has not been initialized.
+#[track_caller]
+pub fn phys_to_ptr<T>(address: PhysicalAddress) -> *mut T {
+    phys_to_virt(address).value() as *mut T
+}
@@ -0,0 +1,165 @@
+//! Mapping of the region of virtual memory that backs the [kernel heap][crate::heap].
+//!
+//! The region is mapped from its start as the heap grows, and each newly mapped part is handed
+//! over to the heap through [`crate::heap::add_region()`].
+
+use core::fmt;
+
+use crate::{
+    arch::x86_64::{
+        boot::FrameAllocator,
+        memory::{
+            mapper::{MapError, Mapper},
+            page_table::PageTableFlags,
+            Page, VirtualAddress,
+        },
+    },
+    spinlock::Spinlock,
+};
+
+/// The start of the region of virtual memory reserved for the kernel heap.
+///
+/// This lies in its own page map level 4 entry, above the direct maps set up by the bootloaders
+/// and below the kernel image.
+pub const HEAP_START: VirtualAddress = VirtualAddress::new_canonical(0xFFFF_E000_0000_0000);
+/// The number of bytes reserved for the kernel heap, which it can never grow beyond.
+pub const HEAP_MAX_SIZE: usize = 1024 * 1024 * 1024;
+
+/// The number of bytes of the heap's region that have been mapped.
+static MAPPED: Spinlock<usize> = Spinlock::new(0);
+
+/// Maps the first `size` bytes of the heap's region, rounded up to whole [`Page`]s, and hands them
+/// over to the kernel heap.
+///
+/// # Errors
+/// - [`HeapError::AlreadyInitialized`] if the heap has already been initialized.
+/// - Any error returned by [`grow()`].
+///
+/// # Safety
+/// `mapper` must manage the active page tables, and every [`Frame`][super::Frame] that
+/// `allocator` hands out must be unused.
+pub unsafe fn init(
+    mapper: &mut Mapper,
+    allocator: &mut FrameAllocator,
+    size: usize,
+) -> Result<(), HeapError> {
+    if *MAPPED.lock() != 0 {
+        return Err(HeapError::AlreadyInitialized);
+    }
+
+    // SAFETY:
+    // The caller's guarantees are forwarded.
+    unsafe { grow(mapper, allocator, size) }
+}
+
+/// Maps the next `additional` bytes of the heap's region, rounded up to whole [`Page`]s, and hands
+/// them over to the kernel heap.
+///
+/// If mapping stops partway through, the [`Page`]s mapped so far are still handed over.
+///
+/// # Errors
+/// - [`HeapError::RegionExhausted`] if the heap would grow beyond [`HEAP_MAX_SIZE`].
+/// - [`HeapError::FrameAllocationFailed`] if a [`Frame`][super::Frame] to back a [`Page`] could
+///   not be allocated.
+/// - [`HeapError::Map`] if a [`Page`] could not be mapped.
+///
+/// # Safety
+/// `mapper` must manage the active page tables, and every [`Frame`][super::Frame] that
+/// `allocator` hands out must be unused.
+pub unsafe fn grow(
+    mapper: &mut Mapper,
+    allocator: &mut FrameAllocator,
+    additional: usize,
+) -> Result<(), HeapError> {
+    let mut mapped = MAPPED.lock();
+    let additional = additional
+        .checked_next_multiple_of(Page::PAGE_SIZE)
+        .filter(|&additional| additional <= HEAP_MAX_SIZE - *mapped)
+        .ok_or(HeapError::RegionExhausted)?;
+
+    let start = HEAP_START + *mapped;
+    let mut result = Ok(());
+    let mut grown = 0;
+    while grown < additional {
+        let Some(frame) = allocator.allocate_frame() else {
+            result = Err(HeapError::FrameAllocationFailed);
+            break;
+        };
+
+        let page = Page::containing_address(start + grown);
+        // SAFETY:
+        // The heap's region is only mapped here, under `MAPPED`, so `page` is unused, and the
+        // caller guarantees that `frame` is unused.
+        let mapping = unsafe { mapper.map_to(page, frame, PageTableFlags::WRITABLE, allocator) };
+        if let Err(error) = mapping {
+            // SAFETY:
+            // `frame` was allocated above and never mapped.
+            unsafe { allocator.deallocate_frame(frame) };
+            result = Err(HeapError::Map(error));
+            break;
+        }
+
+        grown += Page::PAGE_SIZE;
+    }
+
+    if grown != 0 {
+        // SAFETY:
+        // The `grown` bytes at `start` were just mapped to unused frames, and nothing but the heap
+        // uses its region.
+        unsafe { crate::heap::add_region(start.value() as *mut u8, grown) };
+        *mapped += grown;
+    }
+
+    result
+}
+
+/// Returns the number of bytes of the heap's region that have been mapped.
+pub fn mapped_size() -> usize {
+    *MAPPED.lock()
+}
+
+/// Various errors that can occur while mapping the heap's region.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum HeapError {
+    /// The heap has already been initialized.
+    AlreadyInitialized,
+    /// The heap would grow beyond [`HEAP_MAX_SIZE`].
+    RegionExhausted,
+    /// A [`Frame`][super::Frame] to back the heap could not be allocated.
+    FrameAllocationFailed,
+    /// A [`Page`] of the heap could not be mapped.
+    Map(MapError),
+}
+
+impl fmt::Display for HeapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::AlreadyInitialized => f.pad("heap is already initialized"),
+            Self::RegionExhausted => f.pad("heap region is exhausted"),
+            Self::FrameAllocationFailed => f.pad("failed to allocate a frame for the heap"),
+            Self::Map(error) => fmt::Display::fmt(error, f),
+        }
+    }
+}
+
+#[cfg(feature = "ktest")]
+crate::kernel_test! {
+    fn heap_backs_alloc() {
+        use alloc::{boxed::Box, vec::Vec};
+
+        let before = crate::heap::stats();
+        assert!(mapped_size() != 0, "heap was not initialized during boot");
+
+        let boxed = Box::new(0x1234_5678_u64);
+        let address = core::ptr::from_ref(&*boxed).addr();
+        assert!((HEAP_START.value()..HEAP_START.value() + mapped_size()).contains(&address));
+
+        let values = (0..1024_u32).collect::<Vec<_>>();
+        assert_eq!(values.iter().sum::<u32>(), 1023 * 1024 / 2);
+        assert!(crate::heap::stats().used > before.used);
+
+        drop(values);
+        drop(boxed);
+        assert_eq!(crate::heap::stats(), before);
+    }
+}
@@ -0,0 +1,209 @@
+//! Definitions of the 2 MiB and 1 GiB pages and frames that page map level 2 and 3 entries can
+//! map directly.
+
+use core::{fmt, hash::Hash, marker::PhantomData};
+
+use crate::arch::x86_64::memory::{
+    Frame, FrameRange, Page, PageRange, PhysicalAddress, VirtualAddress,
+};
+
+/// A size of huge page supported by `x86_64` processors.
+///
+/// This trait is sealed, since the processor only supports [`Size2MiB`] and [`Size1GiB`].
+pub trait HugePageSize:
+    sealed::Sealed + Clone + Copy + fmt::Debug + Hash + PartialEq + Eq + PartialOrd + Ord
+{
+    /// The number of bytes that make up a huge page of this size.
+    const SIZE: u64;
+    /// The level of the page map tables whose entries map a huge page of this size.
+    const PAGE_MAP_LEVEL: u8;
+}
+
+/// The 2 MiB huge page size, mapped by page map level 2 entries.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Size2MiB {}
+
+impl HugePageSize for Size2MiB {
+    const SIZE: u64 = 2 * 1024 * 1024;
+    const PAGE_MAP_LEVEL: u8 = 2;
+}
+
+/// The 1 GiB huge page size, mapped by page map level 3 entries.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Size1GiB {}
+
+impl HugePageSize for Size1GiB {
+    const SIZE: u64 = 1024 * 1024 * 1024;
+    const PAGE_MAP_LEVEL: u8 = 3;
+}
+
+mod sealed {
+    /// Prevents [`HugePageSize`][super::HugePageSize] from being implemented outside this module.
+    pub trait Sealed {}
+
+    impl Sealed for super::Size2MiB {}
+    impl Sealed for super::Size1GiB {}
+}
+
+/// A region of virtual memory of size `S`, aligned to `S`.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub struct HugePage<S: HugePageSize> {
+    number: usize,
+    size: PhantomData<S>,
+}
+
+impl<S: HugePageSize> HugePage<S> {
+    /// The number of [`Page`]s that make up a [`HugePage`].
+    pub const PAGE_COUNT: usize = S::SIZE as usize / Page::PAGE_SIZE;
+
+    /// Returns the [`HugePage`] that contains the [`VirtualAddress`].
+    pub const fn containing_address(address: VirtualAddress) -> Self {
+        Self {
+            number: address.value() / S::SIZE as usize,
+            size: PhantomData,
+        }
+    }
+
+    /// Returns the [`HugePage`] that starts with `page`, or [`None`] if `page` is not aligned to
+    /// `S`.
+    pub const fn from_start_page(page: Page) -> Option<Self> {
+        if !page.number().is_multiple_of(Self::PAGE_COUNT) {
+            return None;
+        }
+
+        Some(Self::containing_address(page.base_address()))
+    }
+
+    /// Returns the [`HugePage`] number of this [`HugePage`].
+    pub const fn number(&self) -> usize {
+        self.number
+    }
+
+    /// Returns the [`VirtualAddress`] at the base of this [`HugePage`].
+    pub const fn base_address(&self) -> VirtualAddress {
+        VirtualAddress(self.number * S::SIZE as usize)
+    }
+
+    /// Returns the first [`Page`] of this [`HugePage`].
+    pub const fn start_page(&self) -> Page {
+        Page(self.number * Self::PAGE_COUNT)
+    }
+
+    /// Returns the [`PageRange`] of the [`Page`]s that make up this [`HugePage`].
+    pub const fn pages(&self) -> PageRange {
+        PageRange {
+            page: self.start_page(),
+            size: Self::PAGE_COUNT,
+        }
+    }
+}
+
+/// A region of physical memory of size `S`, aligned to `S`.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub struct HugeFrame<S: HugePageSize> {
+    number: u64,
+    size: PhantomData<S>,
+}
+
+impl<S: HugePageSize> HugeFrame<S> {
+    /// The number of [`Frame`]s that make up a [`HugeFrame`].
+    pub const FRAME_COUNT: u64 = S::SIZE / Frame::FRAME_SIZE;
+
+    /// Returns the [`HugeFrame`] that contains the [`PhysicalAddress`].
+    pub const fn containing_address(address: PhysicalAddress) -> Self {
+        Self {
+            number: address.value() / S::SIZE,
+            size: PhantomData,
+        }
+    }
+
+    /// Returns the [`HugeFrame`] that starts with `frame`, or [`None`] if `frame` is not aligned
+    /// to `S`.
+    pub const fn from_start_frame(frame: Frame) -> Option<Self> {
+        if !frame.number().is_multiple_of(Self::FRAME_COUNT) {
+            return None;
+        }
+
+        Some(Self::containing_address(frame.base_address()))
+    }
+
+    /// Returns the [`HugeFrame`] number of this [`HugeFrame`].
+    pub const fn number(&self) -> u64 {
+        self.number
+    }
+
+    /// Returns the [`PhysicalAddress`] at the base of this [`HugeFrame`].
+    pub const fn base_address(&self) -> PhysicalAddress {
+        PhysicalAddress(self.number * S::SIZE)
+    }
+
+    /// Returns the first [`Frame`] of this [`HugeFrame`].
+    pub const fn start_frame(&self) -> Frame {
+        Frame(self.number * Self::FRAME_COUNT)
+    }
+
+    /// Returns the [`FrameRange`] of the [`Frame`]s that make up this [`HugeFrame`].
+    pub const fn frames(&self) -> FrameRange {
+        FrameRange {
+            frame: self.start_frame(),
+            size: Self::FRAME_COUNT,
+        }
+    }
+}
+
+#[cfg(feature = "ktest")]
+crate::kernel_test! {
+    fn huge_page_conversions() {
+        let address = VirtualAddress::new_canonical(0xFFFF_FFFF_8020_1234);
+        let huge_page = HugePage::<Size2MiB>::containing_address(address);
+        assert_eq!(huge_page.base_address().value(), 0xFFFF_FFFF_8020_0000);
+        assert_eq!(huge_page.pages().size_in_pages(), 512);
+        assert_eq!(huge_page.pages().into_iter().count(), 512);
+        assert!(huge_page.pages().contains_address(address));
+        assert_eq!(HugePage::from_start_page(huge_page.start_page()), Some(huge_page));
+        assert_eq!(
+            HugePage::<Size2MiB>::from_start_page(Page::containing_address(address)),
+            None
+        );
+
+        let huge_page = HugePage::<Size1GiB>::containing_address(address);
+        assert_eq!(huge_page.base_address().value(), 0xFFFF_FFFF_8000_0000);
+        assert_eq!(huge_page.start_page().pml4e_index(), 511);
+        assert_eq!(huge_page.start_page().pml3e_index(), 510);
+        assert_eq!(huge_page.start_page().pml2e_index(), 0);
+        assert_eq!(huge_page.pages().size_in_bytes(), Size1GiB::SIZE as usize);
+    }
+
+    fn huge_frame_conversions() {
+        let address = PhysicalAddress::new_masked(0x4060_1234);
+        let huge_frame = HugeFrame::<Size2MiB>::containing_address(address);
+        assert_eq!(huge_frame.base_address().value(), 0x4060_0000);
+        assert_eq!(huge_frame.frames().size_in_frames(), 512);
+        assert!(huge_frame.frames().contains_address(address));
+        assert_eq!(HugeFrame::from_start_frame(huge_frame.start_frame()), Some(huge_frame));
+
+        let huge_frame = HugeFrame::<Size1GiB>::containing_address(address);
+        assert_eq!(huge_frame.base_address().value(), 0x4000_0000);
+        assert_eq!(
+            HugeFrame::<Size1GiB>::from_start_frame(Frame::containing_address(address)),
+            None
+        );
+    }
+
+    fn page_range_huge_eligibility() {
+        let base = HugePage::<Size2MiB>::containing_address(VirtualAddress(0x20_0000)).start_page();
+        let range = |start: usize, count: usize| {
+            PageRange::inclusive_range(Page(start), Page(start + count - 1)).unwrap()
+        };
+
+        assert!(range(base.number(), 512).is_huge_mappable::<Size2MiB>());
+        assert!(range(base.number(), 1024).is_huge_mappable::<Size2MiB>());
+        assert!(!range(base.number(), 513).is_huge_mappable::<Size2MiB>());
+        assert!(!range(base.number() + 1, 512).is_huge_mappable::<Size2MiB>());
+        assert!(!range(base.number(), 512).is_huge_mappable::<Size1GiB>());
+        assert!(range(0, 512 * 512).is_huge_mappable::<Size1GiB>());
+        assert!(!PageRange::inclusive_range(base, Page(0))
+            .unwrap()
+            .is_huge_mappable::<Size2MiB>());
+    }
+}
@@ -0,0 +1,136 @@
+//! The frames and page tables that the kernel keeps for itself once it has booted.
+//!
+//! During boot, every [`Frame`][super::Frame] the kernel does not use is wrapped into untyped
+//! capabilities, except for [`KERNEL_FRAME_RESERVE`] frames held back in the [`FrameAllocator`]
+//! recorded by [`init()`]. That allocator backs the growth of the [kernel heap][super::heap] and
+//! the allocation of [`KernelStack`]s after boot.
+//!
+//! Every change to the active page tables after boot is made under the lock of that allocator,
+//! so that the [`Mapper`]s created for those changes never overlap. The lock does not disable
+//! interrupts, so none of these functions may be called from an interrupt handler.
+
+use crate::{
+    arch::x86_64::{
+        boot::FrameAllocator,
+        memory::{
+            heap::{self, HeapError},
+            mapper::Mapper,
+            stack::{KernelStack, StackError},
+        },
+    },
+    spinlock::TicketSpinlock,
+};
+
+/// The number of frames held back from the untyped capabilities for the kernel's own use.
+pub const KERNEL_FRAME_RESERVE: u64 = 2048;
+
+/// The frames the kernel keeps for itself, once they have been set aside.
+///
+/// The lock is fair, so that no processor waits indefinitely for frames while others allocate.
+static KERNEL_FRAMES: TicketSpinlock<Option<FrameAllocator>> = TicketSpinlock::new(None);
+
+/// Records `allocator` as the source of the frames that the kernel uses after boot.
+///
+/// # Panics
+/// Panics if the kernel's frames have already been recorded.
+pub(in crate::arch::x86_64) fn init(allocator: FrameAllocator) {
+    let mut frames = KERNEL_FRAMES.lock();
+    assert!(frames.is_none(), "kernel frames already recorded");
+    *frames = Some(allocator);
+}
+
+/// Maps the next `additional` bytes of the heap's region with the kernel's frames, as
+/// [`heap::grow()`] does.
+///
+/// # Errors
+/// - [`HeapError::FrameAllocationFailed`] if the kernel's frames have not been recorded yet.
+/// - Any error returned by [`heap::grow()`].
+pub fn grow_heap(additional: usize) -> Result<(), HeapError> {
+    with_mapper(|mapper, allocator| {
+        // SAFETY:
+        // `mapper` manages the active page tables, and `allocator` only hands out unused frames.
+        unsafe { heap::grow(mapper, allocator, additional) }
+    })
+    .unwrap_or(Err(HeapError::FrameAllocationFailed))
+}
+
+/// Allocates a [`KernelStack`] of `size_in_pages` [`Page`][super::Page]s backed by the kernel's
+/// frames.
+///
+/// # Errors
+/// - [`StackError::FrameAllocationFailed`] if the kernel's frames have not been recorded yet.
+/// - Any error returned by [`KernelStack::allocate()`].
+pub fn allocate_stack(size_in_pages: usize) -> Result<KernelStack, StackError> {
+    with_mapper(|mapper, allocator| {
+        // SAFETY:
+        // `mapper` manages the active page tables, and `allocator` only hands out unused frames.
+        unsafe { KernelStack::allocate(mapper, allocator, size_in_pages) }
+    })
+    .unwrap_or(Err(StackError::FrameAllocationFailed))
+}
+
+/// Unmaps `stack` and returns its frames to the kernel's frames.
+///
+/// # Panics
+/// Panics if the kernel's frames have not been recorded, in which case no [`KernelStack`] could
+/// have been allocated by [`allocate_stack()`].
+///
+/// # Safety
+/// `stack` must have been allocated by [`allocate_stack()`], and nothing may use it anymore.
+pub unsafe fn free_stack(stack: KernelStack) {
+    with_mapper(|mapper, allocator| {
+        // SAFETY:
+        // `mapper` manages the active page tables, `stack` was backed by `allocator`, and the
+        // caller guarantees that nothing uses it anymore.
+        unsafe { stack.free(mapper, allocator) }
+    })
+    .expect("kernel stack freed before the kernel frames were recorded");
+}
+
+/// Runs `f` with a [`Mapper`] for the active page tables and the kernel's [`FrameAllocator`],
+/// returning [`None`] if the kernel's frames have not been recorded yet.
+fn with_mapper<R>(f: impl FnOnce(&mut Mapper, &mut FrameAllocator) -> R) -> Option<R> {
+    let mut frames = KERNEL_FRAMES.lock();
+    let allocator = frames.as_mut()?;
+
+    // SAFETY:
+    // Once boot has finished, the active page tables are only modified here, under
+    // `KERNEL_FRAMES`.
+    let mut mapper = unsafe { Mapper::active() }?;
+    Some(f(&mut mapper, allocator))
+}
+
+#[cfg(feature = "ktest")]
+crate::kernel_test! {
+    fn kernel_frames_grow_heap() {
+        use crate::arch::x86_64::memory::Page;
+
+        let mapped = heap::mapped_size();
+        let capacity = crate::heap::stats().capacity;
+        grow_heap(2 * Page::PAGE_SIZE).expect("failed to grow the heap after boot");
+
+        assert_eq!(heap::mapped_size(), mapped + 2 * Page::PAGE_SIZE);
+        assert_eq!(crate::heap::stats().capacity, capacity + 2 * Page::PAGE_SIZE);
+    }
+
+    fn kernel_frames_back_stacks() {
+        use crate::arch::x86_64::memory::stack::overflowed_stack;
+
+        let stack = allocate_stack(2).expect("failed to allocate a stack after boot");
+        let bottom = stack.bottom();
+        assert_eq!(overflowed_stack(bottom - 1), Some(bottom));
+
+        let top_word = (stack.top().value() - 8) as *mut u64;
+        // SAFETY:
+        // The stack is mapped and unused, and its top is aligned.
+        unsafe { top_word.write_volatile(0x5354_4143_4b21) };
+        // SAFETY:
+        // Same as above.
+        assert_eq!(unsafe { top_word.read_volatile() }, 0x5354_4143_4b21);
+
+        // SAFETY:
+        // `stack` came from `allocate_stack()`, and nothing uses it anymore.
+        unsafe { free_stack(stack) };
+        assert_eq!(overflowed_stack(bottom - 1), None);
+    }
+}
@@ -0,0 +1,360 @@
+//! Creation and removal of mappings in a hierarchy of [`PageTable`]s.
+
+use core::fmt;
+
+use crate::arch::x86_64::{
+    boot::FrameAllocator,
+    memory::{
+        direct_map,
+        huge_page::{HugeFrame, HugePage, HugePageSize},
+        page_table::{PageTable, PageTableEntry, PageTableFlags},
+        Frame, Page, PhysicalAddress, VirtualAddress,
+    },
+};
+
+/// Creates, removes, and looks up mappings in the hierarchy of [`PageTable`]s rooted at a page map
+/// level 4 table.
+#[derive(Debug)]
+pub struct Mapper<'table> {
+    /// The page map level 4 table.
+    root: &'table mut PageTable,
+    /// The [`VirtualAddress`] at which physical memory is mapped.
+    offset: VirtualAddress,
+}
+
+impl<'table> Mapper<'table> {
+    /// Returns a [`Mapper`] for the hierarchy rooted at `root`, which accesses each [`PageTable`]
+    /// at `offset` above its [`PhysicalAddress`].
+    ///
+    /// # Safety
+    /// Every [`PageTable`] in the hierarchy, and every [`Frame`] later allocated for a new
+    /// [`PageTable`], must be mapped at `offset` above its [`PhysicalAddress`]. Nothing else may
+    /// modify the hierarchy while the [`Mapper`] exists.
+    pub unsafe fn new(root: &'table mut PageTable, offset: VirtualAddress) -> Self {
+        Self { root, offset }
+    }
+
+    /// Returns a [`Mapper`] for the active page tables, which accesses them through the direct
+    /// map, or [`None`] if the direct map has not been initialized.
+    ///
+    /// # Safety
+    /// Nothing else may modify the active page tables while the [`Mapper`] exists, including
+    /// another [`Mapper`].
+    pub unsafe fn active() -> Option<Mapper<'static>> {
+        let offset = direct_map::offset()?;
+
+        let cr3: u64;
+        // SAFETY:
+        // Reading `cr3` has no side effects.
+        unsafe { core::arch::asm!("mov {}, cr3", out(reg) cr3, options(nomem, nostack)) };
+
+        let root = direct_map::phys_to_ptr::<PageTable>(PhysicalAddress::new_masked(
+            cr3 & PageTableEntry::ADDRESS_MASK,
+        ));
+        // SAFETY:
+        // The active page map level 4 table is covered by the direct map, and the caller
+        // guarantees that nothing else accesses it while the [`Mapper`] exists.
+        let root = unsafe { &mut *root };
+        // SAFETY:
+        // Every table, like every frame, is covered by the direct map, and the caller guarantees
+        // that nothing else modifies the active page tables.
+        Some(unsafe { Mapper::new(root, offset) })
+    }
+
+    /// Maps `page` to `frame` with the given `flags`, allocating any missing intermediate
+    /// [`PageTable`]s from `allocator`.
+    ///
+    /// [`PageTableFlags::PRESENT`] is always set. Intermediate entries are made writable, and user
+    /// accessible if `flags` contains [`PageTableFlags::USER`], so that `flags` alone decides the
+    /// permissions of the mapping.
+    ///
+    /// # Errors
+    /// - [`MapError::FrameAllocationFailed`] if an intermediate [`PageTable`] could not be
+    ///   allocated.
+    /// - [`MapError::HugePage`] if `page` lies within an existing huge page.
+    /// - [`MapError::AlreadyMapped`] if `page` is already mapped.
+    ///
+    /// # Safety
+    /// The new mapping must not break the memory safety of any code that accesses `page`, and
+    /// `frame` must not be in use for anything that `page` could corrupt.
+    pub unsafe fn map_to(
+        &mut self,
+        page: Page,
+        frame: Frame,
+        flags: PageTableFlags,
+        allocator: &mut FrameAllocator,
+    ) -> Result<(), MapError> {
+        let indices = [
+            page.pml4e_index(),
+            page.pml3e_index(),
+            page.pml2e_index(),
+            page.pml1e_index(),
+        ];
+
+        self.map_entry(&indices, frame, flags | PageTableFlags::PRESENT, allocator)
+    }
+
+    /// Maps `page` to `frame` with the given `flags` using a single page map level
+    /// [`HugePageSize::PAGE_MAP_LEVEL`] entry, allocating any missing intermediate [`PageTable`]s
+    /// from `allocator`.
+    ///
+    /// [`PageTableFlags::PRESENT`] and [`PageTableFlags::HUGE`] are always set. Intermediate
+    /// entries are treated as in [`Mapper::map_to`].
+    ///
+    /// # Errors
+    /// - [`MapError::FrameAllocationFailed`] if an intermediate [`PageTable`] could not be
+    ///   allocated.
+    /// - [`MapError::HugePage`] if `page` lies within an existing larger huge page.
+    /// - [`MapError::AlreadyMapped`] if `page` is already mapped, or any part of it is mapped by a
+    ///   lower level [`PageTable`].
+    ///
+    /// # Safety
+    /// The new mapping must not break the memory safety of any code that accesses `page`, and
+    /// `frame` must not be in use for anything that `page` could corrupt.
+    pub unsafe fn map_huge_to<S: HugePageSize>(
+        &mut self,
+        page: HugePage<S>,
+        frame: HugeFrame<S>,
+        flags: PageTableFlags,
+        allocator: &mut FrameAllocator,
+    ) -> Result<(), MapError> {
+        let start = page.start_page();
+        let indices = [
+            start.pml4e_index(),
+            start.pml3e_index(),
+            start.pml2e_index(),
+            start.pml1e_index(),
+        ];
+
+        self.map_entry(
+            &indices[..5 - S::PAGE_MAP_LEVEL as usize],
+            frame.start_frame(),
+            flags | PageTableFlags::PRESENT | PageTableFlags::HUGE,
+            allocator,
+        )
+    }
+
+    /// Removes the mapping of `page`, returning the [`Frame`] it was mapped to.
+    ///
+    /// The TLB entry for `page` is invalidated, but intermediate [`PageTable`]s that become empty
+    /// are not freed.
+    ///
+    /// # Errors
+    /// - [`UnmapError::NotMapped`] if `page` is not mapped.
+    /// - [`UnmapError::HugePage`] if `page` lies within a huge page.
+    pub fn unmap(&mut self, page: Page) -> Result<Frame, UnmapError> {
+        let mut table: *mut PageTable = &mut *self.root;
+        for index in [page.pml4e_index(), page.pml3e_index(), page.pml2e_index()] {
+            // SAFETY:
+            // `table` is a table of the hierarchy, which is mapped at `offset`.
+            let entry = unsafe { &(&*table)[index as usize] };
+            let frame = entry.frame().ok_or(UnmapError::NotMapped)?;
+            if entry.flags().contains(PageTableFlags::HUGE) {
+                return Err(UnmapError::HugePage);
+            }
+
+            table = self.table_pointer(frame);
+        }
+
+        // SAFETY:
+        // Same as above.
+        let entry = unsafe { &mut (&mut *table)[page.pml1e_index() as usize] };
+        let frame = entry.frame().ok_or(UnmapError::NotMapped)?;
+        entry.set_unused();
+
+        // SAFETY:
+        // Invalidating a TLB entry has no memory safety implications.
+        unsafe {
+            core::arch::asm!("invlpg [{}]", in(reg) page.base_address().value(), options(nostack))
+        };
+
+        Ok(frame)
+    }
+
+    /// Returns the [`PhysicalAddress`] that `address` is mapped to, or [`None`] if it is not
+    /// mapped.
+    pub fn translate(&self, address: VirtualAddress) -> Option<PhysicalAddress> {
+        let page = Page::containing_address(address);
+        let indices = [
+            page.pml4e_index(),
+            page.pml3e_index(),
+            page.pml2e_index(),
+            page.pml1e_index(),
+        ];
+
+        let mut table: *const PageTable = &*self.root;
+        for (level, index) in indices.into_iter().enumerate() {
+            // SAFETY:
+            // `table` is a table of the hierarchy, which is mapped at `offset`.
+            let entry = unsafe { &(&*table)[index as usize] };
+            let frame = entry.frame()?;
+
+            // Page map level 3 and 2 entries can map 1 GiB and 2 MiB pages directly.
+            let maps_page = match level {
+                1 | 2 => entry.flags().contains(PageTableFlags::HUGE),
+                3 => true,
+                _ => false,
+            };
+            if maps_page {
+                let size = 1 << (12 + 9 * (3 - level));
+                return frame
+                    .base_address()
+                    .align_down(size)
+                    .checked_add(address.value() as u64 & (size - 1));
+            }
+
+            table = self.table_pointer(frame);
+        }
+
+        None
+    }
+
+    /// Walks the hierarchy along `indices`, creating intermediate [`PageTable`]s as needed, and
+    /// points the entry selected by the last index at `frame` with the given `flags`.
+    fn map_entry(
+        &mut self,
+        indices: &[u16],
+        frame: Frame,
+        flags: PageTableFlags,
+        allocator: &mut FrameAllocator,
+    ) -> Result<(), MapError> {
+        let Some((&leaf, path)) = indices.split_last() else {
+            unreachable!("a mapping always has a leaf entry");
+        };
+        let parent_flags =
+            PageTableFlags::PRESENT | PageTableFlags::WRITABLE | (flags & PageTableFlags::USER);
+
+        let mut table: *mut PageTable = &mut *self.root;
+        for &index in path {
+            // SAFETY:
+            // `table` is a table of the hierarchy, which is mapped at `offset`.
+            let entry = unsafe { &mut (&mut *table)[index as usize] };
+            table = self.next_table_or_create(entry, parent_flags, allocator)?;
+        }
+
+        // SAFETY:
+        // Same as above.
+        let entry = unsafe { &mut (&mut *table)[leaf as usize] };
+        if !entry.is_unused() {
+            return Err(MapError::AlreadyMapped);
+        }
+
+        entry.set(frame, flags);
+        Ok(())
+    }
+
+    /// Calls `visit` with the [`Frame`] of every [`PageTable`] in the hierarchy, starting with the
+    /// page map level 4 table.
+    pub fn for_each_table(&self, mut visit: impl FnMut(Frame)) {
+        let root: *const PageTable = &*self.root;
+        let root_address = root.addr() - self.offset.value();
+        visit(Frame::containing_address(PhysicalAddress::new_masked(
+            root_address as u64,
+        )));
+
+        self.visit_child_tables(root, 3, &mut visit);
+    }
+
+    /// Calls `visit` with the [`Frame`] of every [`PageTable`] that `table` refers to, descending
+    /// `levels` levels of the hierarchy.
+    fn visit_child_tables(
+        &self,
+        table: *const PageTable,
+        levels: u8,
+        visit: &mut impl FnMut(Frame),
+    ) {
+        if levels == 0 {
+            return;
+        }
+
+        for index in 0..PageTable::ENTRY_COUNT {
+            // SAFETY:
+            // `table` is a table of the hierarchy, which is mapped at `offset`.
+            let entry = unsafe { &(&*table)[index] };
+            let Some(frame) = entry.frame() else {
+                continue;
+            };
+            if entry.flags().contains(PageTableFlags::HUGE) {
+                continue;
+            }
+
+            visit(frame);
+            self.visit_child_tables(self.table_pointer(frame), levels - 1, visit);
+        }
+    }
+
+    /// Returns the [`PageTable`] that `entry` refers to, first allocating it from `allocator` and
+    /// pointing `entry` at it if `entry` is not present.
+    ///
+    /// `flags` are added to `entry`.
+    fn next_table_or_create(
+        &self,
+        entry: &mut PageTableEntry,
+        flags: PageTableFlags,
+        allocator: &mut FrameAllocator,
+    ) -> Result<*mut PageTable, MapError> {
+        let Some(frame) = entry.frame() else {
+            let frame = allocator
+                .allocate_frame()
+                .ok_or(MapError::FrameAllocationFailed)?;
+            let table = self.table_pointer(frame);
+            // SAFETY:
+            // `frame` was just allocated, so nothing else uses it, and it is mapped at `offset`.
+            unsafe { table.write_bytes(0, 1) };
+
+            entry.set(frame, flags);
+            return Ok(table);
+        };
+
+        if entry.flags().contains(PageTableFlags::HUGE) {
+            return Err(MapError::HugePage);
+        }
+
+        entry.set(frame, entry.flags() | flags);
+        Ok(self.table_pointer(frame))
+    }
+
+    /// Returns a pointer to the [`PageTable`] stored in `frame`.
+    fn table_pointer(&self, frame: Frame) -> *mut PageTable {
+        (self.offset + frame.base_address().value() as usize).value() as *mut PageTable
+    }
+}
+
+/// Various errors that can occur while mapping a [`Page`].
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum MapError {
+    /// A [`Frame`] for an intermediate [`PageTable`] could not be allocated.
+    FrameAllocationFailed,
+    /// The [`Page`] lies within an existing huge page.
+    HugePage,
+    /// The [`Page`] is already mapped.
+    AlreadyMapped,
+}
+
+impl fmt::Display for MapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::FrameAllocationFailed => f.pad("failed to allocate a frame for a page table"),
+            Self::HugePage => f.pad("page lies within an existing huge page"),
+            Self::AlreadyMapped => f.pad("page is already mapped"),
+        }
+    }
+}
+
+/// Various errors that can occur while unmapping a [`Page`].
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum UnmapError {
+    /// The [`Page`] is not mapped.
+    NotMapped,
+    /// The [`Page`] lies within a huge page.
+    HugePage,
+}
+
+impl fmt::Display for UnmapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotMapped => f.pad("page is not mapped"),
+            Self::HugePage => f.pad("page lies within a huge page"),
+        }
+    }
+}
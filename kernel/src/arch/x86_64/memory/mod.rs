@@ -1,6 +1,20 @@
 //! Definitions of various structures for interacting with memory in an organized manner.
 
-use core::fmt;
+use core::{
+    fmt,
+    ops::{Add, Sub},
+};
+
+use huge_page::{HugePage, HugePageSize};
+
+pub mod direct_map;
+pub mod heap;
+pub mod huge_page;
+pub mod kernel_memory;
+pub mod mapper;
+pub mod page_table;
+pub mod reserved;
+pub mod stack;
 
 /// A physical memory address.
 #[repr(transparent)]
@@ -41,6 +55,87 @@ impl PhysicalAddress {
     pub const fn frame_offset(&self) -> u64 {
         self.0 % Frame::FRAME_SIZE
     }
+
+    /// Returns the [`PhysicalAddress`] `offset` bytes above this [`PhysicalAddress`], or [`None`]
+    /// if it is not a valid [`PhysicalAddress`].
+    pub const fn checked_add(self, offset: u64) -> Option<Self> {
+        match self.0.checked_add(offset) {
+            Some(address) => Self::new(address),
+            None => None,
+        }
+    }
+
+    /// Returns the [`PhysicalAddress`] `offset` bytes below this [`PhysicalAddress`], or [`None`]
+    /// if it would be below zero.
+    pub const fn checked_sub(self, offset: u64) -> Option<Self> {
+        match self.0.checked_sub(offset) {
+            Some(address) => Some(Self(address)),
+            None => None,
+        }
+    }
+
+    /// Returns the lowest [`PhysicalAddress`] aligned to `align` that is not below this
+    /// [`PhysicalAddress`], or [`None`] if it is not a valid [`PhysicalAddress`].
+    ///
+    /// # Panics
+    /// Panics if `align` is not a power of two.
+    pub const fn align_up(self, align: u64) -> Option<Self> {
+        assert!(align.is_power_of_two(), "alignment must be a power of two");
+
+        match self.0.checked_add(align - 1) {
+            Some(address) => Self::new(address & !(align - 1)),
+            None => None,
+        }
+    }
+
+    /// Returns the highest [`PhysicalAddress`] aligned to `align` that is not above this
+    /// [`PhysicalAddress`].
+    ///
+    /// # Panics
+    /// Panics if `align` is not a power of two.
+    pub const fn align_down(self, align: u64) -> Self {
+        assert!(align.is_power_of_two(), "alignment must be a power of two");
+
+        Self(self.0 & !(align - 1))
+    }
+
+    /// Returns `true` if this [`PhysicalAddress`] is aligned to `align`.
+    ///
+    /// # Panics
+    /// Panics if `align` is not a power of two.
+    pub const fn is_aligned(self, align: u64) -> bool {
+        assert!(align.is_power_of_two(), "alignment must be a power of two");
+
+        self.0 & (align - 1) == 0
+    }
+}
+
+impl Add<u64> for PhysicalAddress {
+    type Output = Self;
+
+    fn add(self, offset: u64) -> Self::Output {
+        self.checked_add(offset)
+            .expect("physical address above the maximum physical address")
+    }
+}
+
+impl Sub<u64> for PhysicalAddress {
+    type Output = Self;
+
+    fn sub(self, offset: u64) -> Self::Output {
+        self.checked_sub(offset)
+            .expect("physical address below zero")
+    }
+}
+
+impl Sub<PhysicalAddress> for PhysicalAddress {
+    type Output = u64;
+
+    fn sub(self, other: PhysicalAddress) -> Self::Output {
+        self.0
+            .checked_sub(other.0)
+            .expect("subtracted a higher physical address from a lower one")
+    }
 }
 
 impl fmt::Debug for PhysicalAddress {
@@ -77,6 +172,11 @@ impl Frame {
 }
 
 /// A range of contiguous [`Frame`]s.
+///
+/// A [`FrameRange`] covers the [`Frame`]s from its start up to, but excluding, the [`Frame`]
+/// [`size_in_frames()`][Self::size_in_frames] past it. An empty [`FrameRange`] covers no
+/// [`Frame`]s: it contains no address, overlaps nothing, and is contained in every
+/// [`FrameRange`].
 #[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
 pub struct FrameRange {
     frame: Frame,
@@ -95,6 +195,14 @@ impl FrameRange {
         Self { frame: start, size }
     }
 
+    /// Returns the empty [`FrameRange`] that starts at `start`.
+    pub const fn empty(start: Frame) -> Self {
+        Self {
+            frame: start,
+            size: 0,
+        }
+    }
+
     /// Returns the [`Frame`] at the start of the [`FrameRange`].
     pub const fn start(&self) -> Frame {
         self.frame
@@ -136,7 +244,7 @@ impl FrameRange {
 
     /// Returns the [`PhysicalAddress`] located at the given `offset` in this [`FrameRange`].
     ///
-    /// If the given `offset` is greater than the size in bytes of this [`FrameRange`], this
+    /// If the given `offset` is not less than the size in bytes of this [`FrameRange`], this
     /// function returns [`None`].
     pub const fn address_at_offset(&self, offset: u64) -> Option<PhysicalAddress> {
         if !(offset < self.size_in_bytes()) {
@@ -146,17 +254,111 @@ impl FrameRange {
         Some(PhysicalAddress(self.start_address().value() + offset))
     }
 
-    /// Returns `true` if this [`FrameRange`] fully contains the given `other` [`FrameRange`].
+    /// Returns `true` if every [`Frame`] of the given `other` [`FrameRange`] lies in this
+    /// [`FrameRange`].
+    ///
+    /// Containment is inclusive: a [`FrameRange`] contains itself and any [`FrameRange`] within it
+    /// that shares either of its ends.
     pub const fn contains_range(&self, other: &FrameRange) -> bool {
-        self.start().number() <= other.start().number()
-            && other.start().number() + other.size_in_frames()
-                < self.start().number() + self.size_in_frames()
+        other.size_in_frames() == 0
+            || (self.start().number() <= other.start().number()
+                && other.end_number() <= self.end_number())
     }
 
-    /// Returns `true` if this [`FrameRange`] overlaps with the given `other` [`FrameRange`].
+    /// Returns `true` if this [`FrameRange`] and the given `other` [`FrameRange`] have at least
+    /// one [`Frame`] in common.
     pub const fn overlaps(&self, other: &FrameRange) -> bool {
-        self.start().number() < other.start().number() + other.size_in_frames()
-            && other.start().number() < self.start().number() + self.size_in_frames()
+        self.size_in_frames() != 0
+            && other.size_in_frames() != 0
+            && self.start().number() < other.end_number()
+            && other.start().number() < self.end_number()
+    }
+
+    /// Returns the [`FrameRange`] of the [`Frame`]s in both this [`FrameRange`] and `other`, or
+    /// [`None`] if they have none in common.
+    pub const fn intersection(&self, other: &FrameRange) -> Option<Self> {
+        Self::from_numbers(
+            max_u64(self.start().number(), other.start().number()),
+            min_u64(self.end_number(), other.end_number()),
+        )
+    }
+
+    /// Splits this [`FrameRange`] into the [`Frame`]s before `frame` and the [`Frame`]s from
+    /// `frame` onwards, with [`None`] in place of either piece if it is empty.
+    pub const fn split_at(&self, frame: Frame) -> (Option<Self>, Option<Self>) {
+        let split = min_u64(
+            max_u64(frame.number(), self.start().number()),
+            self.end_number(),
+        );
+
+        (
+            Self::from_numbers(self.start().number(), split),
+            Self::from_numbers(split, self.end_number()),
+        )
+    }
+
+    /// Returns the [`FrameRange`] covering both this [`FrameRange`] and `other` if they overlap
+    /// or touch, or [`None`] if a gap separates them.
+    ///
+    /// An empty [`FrameRange`] merges with anything, leaving the other [`FrameRange`] unchanged.
+    pub const fn merge(&self, other: &FrameRange) -> Option<Self> {
+        if self.size_in_frames() == 0 {
+            return other.non_empty();
+        } else if other.size_in_frames() == 0 {
+            return self.non_empty();
+        }
+
+        if self.start().number() > other.end_number() || other.start().number() > self.end_number()
+        {
+            return None;
+        }
+
+        Self::from_numbers(
+            min_u64(self.start().number(), other.start().number()),
+            max_u64(self.end_number(), other.end_number()),
+        )
+    }
+
+    /// Returns the pieces of this [`FrameRange`] that do not lie in `other`: those before `other`
+    /// followed by those after it, with [`None`] in place of either piece if it is empty.
+    pub const fn difference(&self, other: &FrameRange) -> [Option<Self>; 2] {
+        if other.size_in_frames() == 0 {
+            return [self.non_empty(), None];
+        }
+
+        [
+            Self::from_numbers(
+                self.start().number(),
+                min_u64(self.end_number(), other.start().number()),
+            ),
+            Self::from_numbers(
+                max_u64(self.start().number(), other.end_number()),
+                self.end_number(),
+            ),
+        ]
+    }
+
+    /// Returns the number of the first [`Frame`] after the end of this [`FrameRange`].
+    const fn end_number(&self) -> u64 {
+        self.start().number() + self.size_in_frames()
+    }
+
+    /// Returns this [`FrameRange`], or [`None`] if it is empty.
+    const fn non_empty(&self) -> Option<Self> {
+        Self::from_numbers(self.start().number(), self.end_number())
+    }
+
+    /// Returns the [`FrameRange`] of the [`Frame`]s numbered from `start` up to, but excluding,
+    /// `end`, or [`None`] if it would be empty.
+    const fn from_numbers(start: u64, end: u64) -> Option<Self> {
+        if start >= end {
+            return None;
+        }
+
+        Some(Self {
+            frame: Frame(start),
+            size: end - start,
+        })
     }
 }
 
@@ -186,6 +388,14 @@ impl FrameRangeIter {
             remaining: 0,
         }
     }
+
+    /// Returns the [`FrameRange`] of the [`Frame`]s this [`FrameRangeIter`] has yet to yield.
+    pub const fn remaining(&self) -> FrameRange {
+        FrameRange {
+            frame: self.frame,
+            size: self.remaining,
+        }
+    }
 }
 
 impl Iterator for FrameRangeIter {
@@ -248,6 +458,112 @@ impl VirtualAddress {
     pub const fn page_offset(&self) -> usize {
         self.0 % Page::PAGE_SIZE
     }
+
+    /// Returns the [`VirtualAddress`] `offset` bytes above this [`VirtualAddress`], or [`None`]
+    /// if it overflows or is not canonical.
+    pub const fn checked_add(self, offset: usize) -> Option<Self> {
+        match self.0.checked_add(offset) {
+            Some(address) => Self::new(address),
+            None => None,
+        }
+    }
+
+    /// Returns the [`VirtualAddress`] `offset` bytes below this [`VirtualAddress`], or [`None`]
+    /// if it underflows or is not canonical.
+    pub const fn checked_sub(self, offset: usize) -> Option<Self> {
+        match self.0.checked_sub(offset) {
+            Some(address) => Self::new(address),
+            None => None,
+        }
+    }
+
+    /// Returns the lowest [`VirtualAddress`] aligned to `align` that is not below this
+    /// [`VirtualAddress`], or [`None`] if it overflows or is not canonical.
+    ///
+    /// # Panics
+    /// Panics if `align` is not a power of two.
+    pub const fn align_up(self, align: usize) -> Option<Self> {
+        assert!(align.is_power_of_two(), "alignment must be a power of two");
+
+        match self.0.checked_add(align - 1) {
+            Some(address) => Self::new(address & !(align - 1)),
+            None => None,
+        }
+    }
+
+    /// Returns the highest canonical [`VirtualAddress`] aligned to `align` that is not above this
+    /// [`VirtualAddress`].
+    ///
+    /// # Panics
+    /// Panics if `align` is not a power of two.
+    pub const fn align_down(self, align: usize) -> Self {
+        assert!(align.is_power_of_two(), "alignment must be a power of two");
+
+        match Self::new(self.0 & !(align - 1)) {
+            Some(address) => address,
+            // Only alignments larger than the lower half can land in the gap, and then the only
+            // aligned address below the gap is zero.
+            None => Self::zero(),
+        }
+    }
+
+    /// Returns `true` if this [`VirtualAddress`] is aligned to `align`.
+    ///
+    /// # Panics
+    /// Panics if `align` is not a power of two.
+    pub const fn is_aligned(self, align: usize) -> bool {
+        assert!(align.is_power_of_two(), "alignment must be a power of two");
+
+        self.0 & (align - 1) == 0
+    }
+
+    /// Returns the index into the page map level 1 table.
+    pub const fn pml1e_index(&self) -> u16 {
+        Page::containing_address(*self).pml1e_index()
+    }
+
+    /// Returns the index into the page map level 2 table.
+    pub const fn pml2e_index(&self) -> u16 {
+        Page::containing_address(*self).pml2e_index()
+    }
+
+    /// Returns the index into the page map level 3 table.
+    pub const fn pml3e_index(&self) -> u16 {
+        Page::containing_address(*self).pml3e_index()
+    }
+
+    /// Returns the index into the page map level 4 table.
+    pub const fn pml4e_index(&self) -> u16 {
+        Page::containing_address(*self).pml4e_index()
+    }
+}
+
+impl Add<usize> for VirtualAddress {
+    type Output = Self;
+
+    fn add(self, offset: usize) -> Self::Output {
+        self.checked_add(offset)
+            .expect("virtual address overflowed or is not canonical")
+    }
+}
+
+impl Sub<usize> for VirtualAddress {
+    type Output = Self;
+
+    fn sub(self, offset: usize) -> Self::Output {
+        self.checked_sub(offset)
+            .expect("virtual address underflowed or is not canonical")
+    }
+}
+
+impl Sub<VirtualAddress> for VirtualAddress {
+    type Output = usize;
+
+    fn sub(self, other: VirtualAddress) -> Self::Output {
+        self.0
+            .checked_sub(other.0)
+            .expect("subtracted a higher virtual address from a lower one")
+    }
 }
 
 impl fmt::Debug for VirtualAddress {
@@ -277,6 +593,26 @@ impl Page {
         self.0
     }
 
+    /// Returns the [`Page`] that the page map tables reach through the given indices.
+    ///
+    /// Page map level 4 indices of 256 and above select the higher half of the virtual address
+    /// space.
+    ///
+    /// # Panics
+    /// Panics if any index is not less than 512.
+    pub const fn from_table_indices(pml4e: u16, pml3e: u16, pml2e: u16, pml1e: u16) -> Self {
+        assert!(
+            pml4e < 512 && pml3e < 512 && pml2e < 512 && pml1e < 512,
+            "page map table indices must be less than 512"
+        );
+
+        let address = (pml4e as usize) << 39
+            | (pml3e as usize) << 30
+            | (pml2e as usize) << 21
+            | (pml1e as usize) << 12;
+        Self::containing_address(VirtualAddress::new_canonical(address))
+    }
+
     /// Returns the [`VirtualAddress`] at the base of this [`Page`].
     pub const fn base_address(&self) -> VirtualAddress {
         VirtualAddress(self.0 * Self::PAGE_SIZE)
@@ -304,6 +640,11 @@ impl Page {
 }
 
 /// A range of contiguous [`Page`]s.
+///
+/// A [`PageRange`] covers the [`Page`]s from its start up to, but excluding, the [`Page`]
+/// [`size_in_pages()`][Self::size_in_pages] past it, and never crosses the virtual address space
+/// gap. An empty [`PageRange`] covers no [`Page`]s: it contains no address, overlaps nothing, and
+/// is contained in every [`PageRange`].
 #[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
 pub struct PageRange {
     page: Page,
@@ -346,7 +687,7 @@ impl PageRange {
         self.size
     }
 
-    /// Returns the number of bytes this [`FrameRange`] contains.
+    /// Returns the number of bytes this [`PageRange`] contains.
     pub const fn size_in_bytes(&self) -> usize {
         self.size * Page::PAGE_SIZE
     }
@@ -358,6 +699,14 @@ impl PageRange {
                 < self.start().number() + self.size_in_pages()
     }
 
+    /// Returns `true` if this [`PageRange`] is non-empty and consists entirely of whole
+    /// [`HugePage`]s of size `S`.
+    pub const fn is_huge_mappable<S: HugePageSize>(&self) -> bool {
+        self.size != 0
+            && self.page.number().is_multiple_of(HugePage::<S>::PAGE_COUNT)
+            && self.size.is_multiple_of(HugePage::<S>::PAGE_COUNT)
+    }
+
     /// Returns the offset into this [`PageRange`] at which the given [`VirtualAddress`] lies.
     ///
     /// If the given [`VirtualAddress`] is not contained within this [`PageRange`], this function
@@ -372,7 +721,7 @@ impl PageRange {
 
     /// Returns the [`VirtualAddress`] located at the given `offset` in this [`PageRange`].
     ///
-    /// If the given `offset` is greater than the size in bytes of this [`PageRange`], this
+    /// If the given `offset` is not less than the size in bytes of this [`PageRange`], this
     /// function returns [`None`].
     pub const fn address_at_offset(&self, offset: usize) -> Option<VirtualAddress> {
         if !(offset < self.size_in_bytes()) {
@@ -382,17 +731,110 @@ impl PageRange {
         Some(VirtualAddress(self.start_address().value() + offset))
     }
 
-    /// Returns `true` if this [`PageRange`] fully contains the given `other` [`PageRange`].
+    /// Returns `true` if every [`Page`] of the given `other` [`PageRange`] lies in this
+    /// [`PageRange`].
+    ///
+    /// Containment is inclusive: a [`PageRange`] contains itself and any [`PageRange`] within it
+    /// that shares either of its ends.
     pub const fn contains_range(&self, other: &PageRange) -> bool {
-        self.start().number() <= other.start().number()
-            && other.start().number() + other.size_in_pages()
-                < self.start().number() + self.size_in_pages()
+        other.size_in_pages() == 0
+            || (self.start().number() <= other.start().number()
+                && other.end_number() <= self.end_number())
     }
 
-    /// Returns `true` if this [`PageRange`] overlaps with the given `other` [`PageRange`].
+    /// Returns `true` if this [`PageRange`] and the given `other` [`PageRange`] have at least
+    /// one [`Page`] in common.
     pub const fn overlaps(&self, other: &PageRange) -> bool {
-        self.start().number() < other.start().number() + other.size_in_pages()
-            && other.start().number() < self.start().number() + self.size_in_pages()
+        self.size_in_pages() != 0
+            && other.size_in_pages() != 0
+            && self.start().number() < other.end_number()
+            && other.start().number() < self.end_number()
+    }
+
+    /// Returns the [`PageRange`] of the [`Page`]s in both this [`PageRange`] and `other`, or
+    /// [`None`] if they have none in common.
+    pub const fn intersection(&self, other: &PageRange) -> Option<Self> {
+        Self::from_numbers(
+            max_usize(self.start().number(), other.start().number()),
+            min_usize(self.end_number(), other.end_number()),
+        )
+    }
+
+    /// Splits this [`PageRange`] into the [`Page`]s before `page` and the [`Page`]s from `page`
+    /// onwards, with [`None`] in place of either piece if it is empty.
+    pub const fn split_at(&self, page: Page) -> (Option<Self>, Option<Self>) {
+        let split = min_usize(
+            max_usize(page.number(), self.start().number()),
+            self.end_number(),
+        );
+
+        (
+            Self::from_numbers(self.start().number(), split),
+            Self::from_numbers(split, self.end_number()),
+        )
+    }
+
+    /// Returns the [`PageRange`] covering both this [`PageRange`] and `other` if they overlap or
+    /// touch, or [`None`] if a gap separates them.
+    ///
+    /// An empty [`PageRange`] merges with anything, leaving the other [`PageRange`] unchanged.
+    /// Ranges on opposite sides of the virtual address space gap never touch, so they are never
+    /// merged.
+    pub const fn merge(&self, other: &PageRange) -> Option<Self> {
+        if self.size_in_pages() == 0 {
+            return other.non_empty();
+        } else if other.size_in_pages() == 0 {
+            return self.non_empty();
+        }
+
+        if self.start().number() > other.end_number() || other.start().number() > self.end_number()
+        {
+            return None;
+        }
+
+        Self::from_numbers(
+            min_usize(self.start().number(), other.start().number()),
+            max_usize(self.end_number(), other.end_number()),
+        )
+    }
+
+    /// Returns the pieces of this [`PageRange`] that do not lie in `other`: those before `other`
+    /// followed by those after it, with [`None`] in place of either piece if it is empty.
+    pub const fn difference(&self, other: &PageRange) -> [Option<Self>; 2] {
+        if other.size_in_pages() == 0 {
+            return [self.non_empty(), None];
+        }
+
+        [
+            Self::from_numbers(
+                self.start().number(),
+                min_usize(self.end_number(), other.start().number()),
+            ),
+            Self::from_numbers(
+                max_usize(self.start().number(), other.end_number()),
+                self.end_number(),
+            ),
+        ]
+    }
+
+    /// Returns the number of the first [`Page`] after the end of this [`PageRange`].
+    const fn end_number(&self) -> usize {
+        self.start().number() + self.size_in_pages()
+    }
+
+    /// Returns this [`PageRange`], or [`None`] if it is empty.
+    const fn non_empty(&self) -> Option<Self> {
+        Self::from_numbers(self.start().number(), self.end_number())
+    }
+
+    /// Returns the [`PageRange`] of the [`Page`]s numbered from `start` up to, but excluding,
+    /// `end`, or [`None`] if it would be empty or cross the virtual address space gap.
+    const fn from_numbers(start: usize, end: usize) -> Option<Self> {
+        if start >= end {
+            return None;
+        }
+
+        Self::inclusive_range(Page(start), Page(end - 1))
     }
 }
 
@@ -441,3 +883,625 @@ impl Iterator for PageRangeIter {
         Some(page)
     }
 }
+
+/// Returns the smaller of `a` and `b`.
+const fn min_u64(a: u64, b: u64) -> u64 {
+    if a < b {
+        a
+    } else {
+        b
+    }
+}
+
+/// Returns the larger of `a` and `b`.
+const fn max_u64(a: u64, b: u64) -> u64 {
+    if a > b {
+        a
+    } else {
+        b
+    }
+}
+
+/// Returns the smaller of `a` and `b`.
+const fn min_usize(a: usize, b: usize) -> usize {
+    if a < b {
+        a
+    } else {
+        b
+    }
+}
+
+/// Returns the larger of `a` and `b`.
+const fn max_usize(a: usize, b: usize) -> usize {
+    if a > b {
+        a
+    } else {
+        b
+    }
+}
+
+#[cfg(feature = "ktest")]
+crate::kernel_test! {
+    fn frame_range_addressing() {
+        let range = FrameRange::inclusive_range(Frame(2), Frame(4));
+        assert_eq!(range.size_in_frames(), 3);
+        assert_eq!(range.size_in_bytes(), 3 * Frame::FRAME_SIZE);
+        assert!(!range.contains_address(PhysicalAddress(2 * Frame::FRAME_SIZE - 1)));
+        assert!(range.contains_address(PhysicalAddress(5 * Frame::FRAME_SIZE - 1)));
+        assert!(!range.contains_address(PhysicalAddress(5 * Frame::FRAME_SIZE)));
+        assert_eq!(
+            range.offset_of_address(PhysicalAddress(3 * Frame::FRAME_SIZE)),
+            Some(Frame::FRAME_SIZE)
+        );
+        assert_eq!(range.address_at_offset(range.size_in_bytes()), None);
+        assert_eq!(range.into_iter().count(), 3);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{format, vec::Vec};
+
+    use super::{
+        huge_page::{Size1GiB, Size2MiB},
+        *,
+    };
+
+    #[test]
+    fn frames_contain_their_addresses() {
+        assert_eq!(Frame::containing_address(PhysicalAddress(0)), Frame(0));
+        assert_eq!(Frame::containing_address(PhysicalAddress(0xFFF)), Frame(0));
+        assert_eq!(Frame::containing_address(PhysicalAddress(0x1000)), Frame(1));
+        assert_eq!(Frame(0x1234).base_address(), PhysicalAddress(0x123_4000));
+        assert_eq!(Frame(0x1234).number(), 0x1234);
+
+        let last = Frame::containing_address(PhysicalAddress::new_masked(u64::MAX));
+        assert_eq!(
+            last.number(),
+            PhysicalAddress::ADDRESS_MASK / Frame::FRAME_SIZE
+        );
+        assert_eq!(
+            last.base_address(),
+            PhysicalAddress(PhysicalAddress::ADDRESS_MASK & !0xFFF)
+        );
+    }
+
+    #[test]
+    fn pages_contain_their_addresses() {
+        assert_eq!(Page::containing_address(VirtualAddress(0x1FFF)), Page(1));
+        assert_eq!(Page(1).base_address(), VirtualAddress(0x1000));
+
+        let upper = VirtualAddress::new_canonical(0xFFFF_8000_0012_3456);
+        let page = Page::containing_address(upper);
+        assert_eq!(page.base_address(), VirtualAddress(0xFFFF_8000_0012_3000));
+        assert_eq!(page.pml4e_index(), 256);
+        assert_eq!(page.pml1e_index(), 0x123);
+        assert_eq!(upper.page_offset(), 0x456);
+    }
+
+    #[test]
+    fn addresses_validate_their_range() {
+        assert_eq!(PhysicalAddress::new(0x1234), Some(PhysicalAddress(0x1234)));
+        assert_eq!(
+            PhysicalAddress::new(PhysicalAddress::ADDRESS_MASK),
+            Some(PhysicalAddress(PhysicalAddress::ADDRESS_MASK))
+        );
+        assert_eq!(PhysicalAddress::new(u64::MAX), None);
+
+        assert_eq!(VirtualAddress::new(0), Some(VirtualAddress::zero()));
+        assert!(VirtualAddress::new(VirtualAddress::START_GAP - 1).is_some());
+        assert!(VirtualAddress::new(VirtualAddress::END_GAP + 1).is_some());
+        assert!(VirtualAddress::new(usize::MAX).is_some());
+        assert!(VirtualAddress::new(0x0001_0000_0000_0000).is_none());
+        assert_eq!(
+            VirtualAddress::new_canonical(0x0000_FFFF_FFFF_F000).value(),
+            0xFFFF_FFFF_FFFF_F000
+        );
+        assert_eq!(
+            VirtualAddress::new_canonical(0xABCD_7FFF_FFFF_F000).value(),
+            0x0000_7FFF_FFFF_F000
+        );
+    }
+
+    #[test]
+    fn addresses_format_as_pointers() {
+        assert_eq!(
+            format!("{:?}", PhysicalAddress(0x1000)),
+            "PhysicalAddress(0x1000)"
+        );
+        assert_eq!(
+            format!(
+                "{:?}",
+                VirtualAddress::new_canonical(VirtualAddress::END_GAP + 1)
+            ),
+            "VirtualAddress(0xffff800000000000)"
+        );
+    }
+
+    #[test]
+    fn frame_ranges_iterate_over_their_frames() {
+        let range = FrameRange::inclusive_range(Frame(3), Frame(6));
+        assert_eq!(range.start(), Frame(3));
+        assert_eq!(range.start_address(), PhysicalAddress(0x3000));
+        assert_eq!(
+            range.into_iter().collect::<Vec<_>>(),
+            [Frame(3), Frame(4), Frame(5), Frame(6)]
+        );
+
+        let mut frames = range.into_iter();
+        assert_eq!(frames.next(), Some(Frame(3)));
+        assert_eq!(
+            frames.remaining(),
+            FrameRange::inclusive_range(Frame(4), Frame(6))
+        );
+        assert_eq!(frames.by_ref().count(), 3);
+        assert_eq!(frames.remaining().size_in_frames(), 0);
+        assert_eq!(frames.next(), None);
+
+        assert_eq!(FrameRangeIter::empty().next(), None);
+        assert_eq!(
+            FrameRange::inclusive_range(Frame(6), Frame(3))
+                .into_iter()
+                .next(),
+            None
+        );
+    }
+
+    #[test]
+    fn page_ranges_iterate_over_their_pages() {
+        let first_upper = Page::containing_address(VirtualAddress(VirtualAddress::END_GAP + 1));
+        let range =
+            PageRange::inclusive_range(first_upper, Page(first_upper.number() + 2)).unwrap();
+        assert_eq!(range.start_address().value(), VirtualAddress::END_GAP + 1);
+        assert_eq!(range.size_in_pages(), 3);
+        assert_eq!(range.size_in_bytes(), 3 * Page::PAGE_SIZE);
+        assert_eq!(
+            range.into_iter().collect::<Vec<_>>(),
+            [
+                first_upper,
+                Page(first_upper.number() + 1),
+                Page(first_upper.number() + 2)
+            ]
+        );
+
+        assert_eq!(PageRangeIter::empty().next(), None);
+        assert_eq!(
+            PageRange::inclusive_range(Page(2), Page(1))
+                .unwrap()
+                .into_iter()
+                .count(),
+            0
+        );
+    }
+
+    #[test]
+    fn page_ranges_do_not_cross_the_gap() {
+        let last_lower = Page::containing_address(VirtualAddress(VirtualAddress::START_GAP - 1));
+        let first_upper = Page::containing_address(VirtualAddress(VirtualAddress::END_GAP + 1));
+        assert!(PageRange::inclusive_range(last_lower, last_lower).is_some());
+        assert!(PageRange::inclusive_range(first_upper, first_upper).is_some());
+        assert!(PageRange::inclusive_range(last_lower, first_upper).is_none());
+        assert!(PageRange::inclusive_range(Page(0), first_upper).is_none());
+    }
+
+    #[test]
+    fn huge_mappable_ranges_are_whole_huge_pages() {
+        let pages = |start: usize, count: usize| {
+            PageRange::inclusive_range(Page(start), Page(start + count - 1)).unwrap()
+        };
+
+        assert!(pages(0, 512).is_huge_mappable::<Size2MiB>());
+        assert!(pages(512, 1024).is_huge_mappable::<Size2MiB>());
+        assert!(!pages(512, 1024).is_huge_mappable::<Size1GiB>());
+        assert!(pages(0, 512 * 512).is_huge_mappable::<Size1GiB>());
+        assert!(!pages(1, 512).is_huge_mappable::<Size2MiB>());
+        assert!(!pages(0, 511).is_huge_mappable::<Size2MiB>());
+        assert!(!PageRange::inclusive_range(Page(512), Page(511))
+            .unwrap()
+            .is_huge_mappable::<Size2MiB>());
+    }
+
+    #[test]
+    fn physical_address_rejects_bits_above_max() {
+        assert!(PhysicalAddress::new(1 << PhysicalAddress::MAX_BITS).is_none());
+        assert_eq!(
+            PhysicalAddress::new_masked((1 << PhysicalAddress::MAX_BITS) | 0x1234).value(),
+            0x1234
+        );
+        assert_eq!(PhysicalAddress::new_masked(0x1234).frame_offset(), 0x234);
+    }
+
+    #[test]
+    fn virtual_address_canonicalization() {
+        assert!(VirtualAddress::new(VirtualAddress::START_GAP).is_none());
+        assert!(VirtualAddress::new(VirtualAddress::END_GAP).is_none());
+        assert_eq!(
+            VirtualAddress::new_canonical(VirtualAddress::START_GAP).value(),
+            VirtualAddress::END_GAP + 1
+        );
+        assert_eq!(VirtualAddress::new_canonical(0x1234).page_offset(), 0x234);
+    }
+
+    #[test]
+    fn physical_address_arithmetic() {
+        let top = PhysicalAddress::new_masked(PhysicalAddress::ADDRESS_MASK);
+        assert_eq!(top.checked_add(1), None);
+        assert_eq!(
+            top.checked_sub(1),
+            PhysicalAddress::new(PhysicalAddress::ADDRESS_MASK - 1)
+        );
+        assert_eq!(PhysicalAddress::zero().checked_sub(1), None);
+        assert_eq!(PhysicalAddress(0x1000) + 0x234, PhysicalAddress(0x1234));
+        assert_eq!(PhysicalAddress(0x1234) - 0x234, PhysicalAddress(0x1000));
+        assert_eq!(PhysicalAddress(0x1234) - PhysicalAddress(0x1000), 0x234);
+    }
+
+    #[test]
+    fn physical_address_alignment() {
+        let address = PhysicalAddress(0x1234);
+        assert_eq!(address.align_up(0x1000), Some(PhysicalAddress(0x2000)));
+        assert_eq!(address.align_down(0x1000), PhysicalAddress(0x1000));
+        assert!(!address.is_aligned(0x1000));
+        assert!(address.align_down(0x1000).is_aligned(0x1000));
+        assert_eq!(
+            PhysicalAddress(0x2000).align_up(0x1000),
+            Some(PhysicalAddress(0x2000))
+        );
+
+        let top = PhysicalAddress::new_masked(PhysicalAddress::ADDRESS_MASK);
+        assert_eq!(top.align_up(0x1000), None);
+        assert_eq!(
+            top.align_down(0x1000),
+            PhysicalAddress(PhysicalAddress::ADDRESS_MASK & !0xFFF)
+        );
+    }
+
+    #[test]
+    fn virtual_address_arithmetic() {
+        let last_lower = VirtualAddress::new_canonical(VirtualAddress::START_GAP - 1);
+        let first_upper = VirtualAddress::new_canonical(VirtualAddress::END_GAP + 1);
+        assert_eq!(last_lower.checked_add(1), None);
+        assert_eq!(first_upper.checked_sub(1), None);
+        assert_eq!(
+            VirtualAddress::new_canonical(usize::MAX).checked_add(1),
+            None
+        );
+        assert_eq!(VirtualAddress::zero().checked_sub(1), None);
+        assert_eq!(
+            first_upper + 0x1000,
+            VirtualAddress(VirtualAddress::END_GAP + 0x1001)
+        );
+        assert_eq!(
+            last_lower - 0xFFF,
+            VirtualAddress(VirtualAddress::START_GAP - 0x1000)
+        );
+        assert_eq!(
+            first_upper - last_lower,
+            VirtualAddress::END_GAP - VirtualAddress::START_GAP + 2
+        );
+    }
+
+    #[test]
+    fn virtual_address_alignment() {
+        let last_lower = VirtualAddress::new_canonical(VirtualAddress::START_GAP - 1);
+        let first_upper = VirtualAddress::new_canonical(VirtualAddress::END_GAP + 1);
+        assert_eq!(last_lower.align_up(Page::PAGE_SIZE), None);
+        assert_eq!(
+            last_lower.align_down(Page::PAGE_SIZE),
+            VirtualAddress(VirtualAddress::START_GAP - Page::PAGE_SIZE)
+        );
+        assert_eq!(first_upper.align_up(Page::PAGE_SIZE), Some(first_upper));
+        assert!(first_upper.is_aligned(1 << 47));
+        assert_eq!(first_upper.align_down(1 << 48), VirtualAddress::zero());
+        assert_eq!(
+            VirtualAddress(0x1234).align_up(0x1000),
+            Some(VirtualAddress(0x2000))
+        );
+        assert_eq!(
+            VirtualAddress(0x1234).align_down(0x1000),
+            VirtualAddress(0x1000)
+        );
+    }
+
+    #[test]
+    fn page_table_indices_round_trip() {
+        let addresses = [
+            0,
+            0x1234_5000,
+            VirtualAddress::START_GAP - Page::PAGE_SIZE,
+            VirtualAddress::END_GAP + 1,
+            0xFFFF_FFFF_8000_0000,
+            usize::MAX - (Page::PAGE_SIZE - 1),
+        ];
+
+        for address in addresses {
+            let address = VirtualAddress::new(address).unwrap();
+            let page = Page::containing_address(address);
+            assert_eq!(address.pml4e_index(), page.pml4e_index());
+            assert_eq!(address.pml1e_index(), page.pml1e_index());
+            assert_eq!(
+                Page::from_table_indices(
+                    page.pml4e_index(),
+                    page.pml3e_index(),
+                    page.pml2e_index(),
+                    page.pml1e_index(),
+                ),
+                page
+            );
+        }
+
+        let last_lower = Page::from_table_indices(255, 511, 511, 511);
+        assert_eq!(
+            last_lower.base_address().value(),
+            VirtualAddress::START_GAP - Page::PAGE_SIZE
+        );
+        let first_upper = Page::from_table_indices(256, 0, 0, 0);
+        assert_eq!(
+            first_upper.base_address().value(),
+            VirtualAddress::END_GAP + 1
+        );
+        let last = Page::from_table_indices(511, 511, 511, 511);
+        assert_eq!(
+            last.base_address().value(),
+            usize::MAX - (Page::PAGE_SIZE - 1)
+        );
+    }
+
+    #[test]
+    fn frame_range_set_operations() {
+        let frames =
+            |start: u64, end: u64| FrameRange::inclusive_range(Frame(start), Frame(end - 1));
+        let range = frames(4, 8);
+        let empty = FrameRange::empty(Frame(6));
+
+        // Disjoint.
+        assert_eq!(range.intersection(&frames(10, 12)), None);
+        assert_eq!(range.merge(&frames(10, 12)), None);
+        assert_eq!(range.difference(&frames(10, 12)), [Some(range), None]);
+        // Touching.
+        assert_eq!(range.intersection(&frames(8, 10)), None);
+        assert_eq!(range.merge(&frames(8, 10)), Some(frames(4, 10)));
+        assert_eq!(frames(8, 10).merge(&range), Some(frames(4, 10)));
+        assert_eq!(range.difference(&frames(8, 10)), [Some(range), None]);
+        assert_eq!(range.difference(&frames(2, 4)), [None, Some(range)]);
+        // Overlapping.
+        assert_eq!(range.intersection(&frames(6, 10)), Some(frames(6, 8)));
+        assert_eq!(range.merge(&frames(6, 10)), Some(frames(4, 10)));
+        assert_eq!(range.difference(&frames(6, 10)), [Some(frames(4, 6)), None]);
+        assert_eq!(range.difference(&frames(2, 6)), [None, Some(frames(6, 8))]);
+        // Nested.
+        assert_eq!(range.intersection(&frames(5, 7)), Some(frames(5, 7)));
+        assert_eq!(range.merge(&frames(5, 7)), Some(range));
+        assert_eq!(
+            range.difference(&frames(5, 7)),
+            [Some(frames(4, 5)), Some(frames(7, 8))]
+        );
+        assert_eq!(range.difference(&frames(2, 10)), [None, None]);
+        assert_eq!(range.difference(&range), [None, None]);
+        // Empty.
+        assert_eq!(range.intersection(&empty), None);
+        assert_eq!(range.merge(&empty), Some(range));
+        assert_eq!(empty.merge(&empty), None);
+        assert_eq!(range.difference(&empty), [Some(range), None]);
+        assert_eq!(empty.difference(&range), [None, None]);
+
+        assert_eq!(
+            range.split_at(Frame(6)),
+            (Some(frames(4, 6)), Some(frames(6, 8)))
+        );
+        assert_eq!(range.split_at(Frame(4)), (None, Some(range)));
+        assert_eq!(range.split_at(Frame(8)), (Some(range), None));
+        assert_eq!(range.split_at(Frame(0)), (None, Some(range)));
+        assert_eq!(range.split_at(Frame(20)), (Some(range), None));
+        assert_eq!(empty.split_at(Frame(6)), (None, None));
+    }
+
+    #[test]
+    fn page_range_set_operations() {
+        let pages = |start: usize, end: usize| {
+            PageRange::inclusive_range(Page(start), Page(end - 1)).unwrap()
+        };
+        let last_lower = Page::containing_address(VirtualAddress(VirtualAddress::START_GAP - 1));
+        let first_upper = Page::containing_address(VirtualAddress(VirtualAddress::END_GAP + 1));
+        let lower = pages(last_lower.number() - 3, last_lower.number() + 1);
+        let upper = pages(first_upper.number(), first_upper.number() + 4);
+
+        // Ranges on either side of the gap are disjoint and never touch.
+        assert_eq!(lower.intersection(&upper), None);
+        assert_eq!(lower.merge(&upper), None);
+        assert_eq!(upper.merge(&lower), None);
+        assert_eq!(lower.difference(&upper), [Some(lower), None]);
+        assert_eq!(upper.difference(&lower), [None, Some(upper)]);
+        assert_eq!(lower.split_at(first_upper), (Some(lower), None));
+        assert_eq!(upper.split_at(last_lower), (None, Some(upper)));
+
+        // Operations at the edges of the gap stay on their side of it.
+        let top = pages(last_lower.number() - 1, last_lower.number() + 1);
+        assert_eq!(lower.intersection(&top), Some(top));
+        assert_eq!(
+            lower.difference(&top),
+            [
+                Some(pages(last_lower.number() - 3, last_lower.number() - 1)),
+                None
+            ]
+        );
+        assert_eq!(
+            upper.split_at(Page(first_upper.number() + 1)),
+            (
+                Some(pages(first_upper.number(), first_upper.number() + 1)),
+                Some(pages(first_upper.number() + 1, first_upper.number() + 4))
+            )
+        );
+
+        let range = pages(4, 8);
+        assert_eq!(range.merge(&pages(8, 10)), Some(pages(4, 10)));
+        assert_eq!(range.merge(&pages(9, 10)), None);
+        assert_eq!(range.intersection(&pages(6, 10)), Some(pages(6, 8)));
+        assert_eq!(
+            range.difference(&pages(5, 7)),
+            [Some(pages(4, 5)), Some(pages(7, 8))]
+        );
+        assert_eq!(range.difference(&range), [None, None]);
+        assert_eq!(range.split_at(Page(8)), (Some(range), None));
+    }
+
+    #[test]
+    fn frame_range_boundaries() {
+        let frames = |start: u64, end: u64| FrameRange::inclusive_range(Frame(start), Frame(end));
+        let range = frames(0, 9);
+        let empty = frames(5, 4);
+
+        // Identical ranges.
+        assert!(range.contains_range(&range));
+        assert!(range.overlaps(&range));
+        // Shared endpoints.
+        assert!(range.contains_range(&frames(5, 9)));
+        assert!(range.contains_range(&frames(0, 4)));
+        assert!(range.contains_range(&frames(9, 9)));
+        assert!(!range.contains_range(&frames(5, 10)));
+        assert!(!frames(5, 9).contains_range(&range));
+        assert!(range.overlaps(&frames(9, 12)));
+        assert!(!range.overlaps(&frames(10, 12)));
+        assert!(!frames(10, 12).overlaps(&range));
+        // Zero-size ranges.
+        assert_eq!(empty.size_in_frames(), 0);
+        assert!(range.contains_range(&empty));
+        assert!(frames(20, 30).contains_range(&empty));
+        assert!(empty.contains_range(&empty));
+        assert!(!empty.contains_range(&range));
+        assert!(!range.overlaps(&empty));
+        assert!(!empty.overlaps(&range));
+        assert!(!empty.contains_address(Frame(5).base_address()));
+        assert_eq!(empty.offset_of_address(Frame(5).base_address()), None);
+        assert_eq!(empty.address_at_offset(0), None);
+
+        // Addresses at either end of the range.
+        let last_byte = 10 * Frame::FRAME_SIZE - 1;
+        assert!(range.contains_address(PhysicalAddress(0)));
+        assert!(range.contains_address(PhysicalAddress(last_byte)));
+        assert!(!range.contains_address(PhysicalAddress(last_byte + 1)));
+        assert_eq!(range.offset_of_address(PhysicalAddress(0)), Some(0));
+        assert_eq!(
+            range.offset_of_address(PhysicalAddress(last_byte)),
+            Some(last_byte)
+        );
+        assert_eq!(
+            range.offset_of_address(PhysicalAddress(last_byte + 1)),
+            None
+        );
+        assert_eq!(range.address_at_offset(0), Some(PhysicalAddress(0)));
+        assert_eq!(
+            range.address_at_offset(last_byte),
+            Some(PhysicalAddress(last_byte))
+        );
+        assert_eq!(range.address_at_offset(last_byte + 1), None);
+    }
+
+    #[test]
+    fn page_range_boundaries() {
+        let pages =
+            |start: usize, end: usize| PageRange::inclusive_range(Page(start), Page(end)).unwrap();
+        let range = pages(0, 9);
+        let empty = pages(5, 4);
+
+        // Identical ranges.
+        assert!(range.contains_range(&range));
+        assert!(range.overlaps(&range));
+        // Shared endpoints.
+        assert!(range.contains_range(&pages(5, 9)));
+        assert!(range.contains_range(&pages(0, 4)));
+        assert!(!range.contains_range(&pages(5, 10)));
+        assert!(range.overlaps(&pages(9, 12)));
+        assert!(!range.overlaps(&pages(10, 12)));
+        // Zero-size ranges.
+        assert!(range.contains_range(&empty));
+        assert!(!empty.contains_range(&range));
+        assert!(!range.overlaps(&empty));
+        assert!(!empty.overlaps(&range));
+        assert!(!empty.contains_address(Page(5).base_address()));
+        assert_eq!(empty.address_at_offset(0), None);
+
+        // Addresses at either end of the range.
+        let last_byte = 10 * Page::PAGE_SIZE - 1;
+        assert!(range.contains_address(VirtualAddress(last_byte)));
+        assert!(!range.contains_address(VirtualAddress(last_byte + 1)));
+        assert_eq!(
+            range.offset_of_address(VirtualAddress(last_byte)),
+            Some(last_byte)
+        );
+        assert_eq!(
+            range.address_at_offset(last_byte),
+            Some(VirtualAddress(last_byte))
+        );
+        assert_eq!(range.address_at_offset(last_byte + 1), None);
+
+        // Ranges ending at either side of the canonical gap.
+        let last_lower = Page::containing_address(VirtualAddress(VirtualAddress::START_GAP - 1));
+        let first_upper = Page::containing_address(VirtualAddress(VirtualAddress::END_GAP + 1));
+        let lower = pages(last_lower.number() - 3, last_lower.number());
+        let upper = pages(first_upper.number(), first_upper.number() + 3);
+        assert!(PageRange::inclusive_range(last_lower, first_upper).is_none());
+        assert!(lower.contains_range(&pages(last_lower.number(), last_lower.number())));
+        assert!(!lower.contains_range(&upper));
+        assert!(!upper.contains_range(&lower));
+        assert!(!lower.overlaps(&upper));
+        assert!(!upper.overlaps(&lower));
+        assert!(lower.contains_address(VirtualAddress(VirtualAddress::START_GAP - 1)));
+        assert!(!lower.contains_address(first_upper.base_address()));
+        assert!(upper.contains_address(first_upper.base_address()));
+        assert!(!upper.contains_address(last_lower.base_address()));
+        assert_eq!(
+            lower.address_at_offset(lower.size_in_bytes() - 1),
+            Some(VirtualAddress(VirtualAddress::START_GAP - 1))
+        );
+        assert_eq!(lower.address_at_offset(lower.size_in_bytes()), None);
+        assert_eq!(upper.offset_of_address(first_upper.base_address()), Some(0));
+        assert_eq!(
+            upper.offset_of_address(VirtualAddress::new_canonical(usize::MAX)),
+            None
+        );
+    }
+
+    #[test]
+    fn containment_includes_the_last_element() {
+        let frames = |start: u64, end: u64| FrameRange::inclusive_range(Frame(start), Frame(end));
+        let range = frames(2, 4);
+        let one_past_end = Frame(5).base_address();
+
+        assert!(range.contains_address(one_past_end - 1));
+        assert!(!range.contains_address(one_past_end));
+        assert!(range.contains_range(&frames(4, 4)));
+        assert!(!range.contains_range(&frames(4, 5)));
+        assert!(!range.contains_range(&frames(5, 5)));
+        assert!(range.overlaps(&frames(4, 6)));
+        assert!(!range.overlaps(&frames(5, 6)));
+        assert_eq!(
+            range.offset_of_address(one_past_end - 1),
+            Some(3 * Frame::FRAME_SIZE - 1)
+        );
+        assert_eq!(range.offset_of_address(one_past_end), None);
+
+        let pages =
+            |start: usize, end: usize| PageRange::inclusive_range(Page(start), Page(end)).unwrap();
+        let range = pages(2, 4);
+        let one_past_end = Page(5).base_address();
+
+        assert!(range.contains_address(one_past_end - 1));
+        assert!(!range.contains_address(one_past_end));
+        assert!(range.contains_range(&pages(4, 4)));
+        assert!(!range.contains_range(&pages(4, 5)));
+        assert!(!range.contains_range(&pages(5, 5)));
+        assert!(range.overlaps(&pages(4, 6)));
+        assert!(!range.overlaps(&pages(5, 6)));
+
+        // The last page of the address space has no address one past its end.
+        let last = Page::containing_address(VirtualAddress(usize::MAX));
+        let top = pages(last.number() - 1, last.number());
+        assert!(top.contains_address(VirtualAddress(usize::MAX)));
+        assert!(top.contains_range(&pages(last.number(), last.number())));
+        assert_eq!(
+            top.offset_of_address(VirtualAddress(usize::MAX)),
+            Some(2 * Page::PAGE_SIZE - 1)
+        );
+    }
+}
@@ -0,0 +1,273 @@
+//! Definitions of the page tables `x86_64` processors walk to translate virtual addresses.
+
+use core::{
+    fmt,
+    ops::{BitAnd, BitOr, Index, IndexMut},
+};
+
+use crate::arch::x86_64::memory::{Frame, PhysicalAddress};
+
+/// A page map table at any level of the hierarchy.
+#[repr(C, align(4096))]
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+pub struct PageTable([PageTableEntry; PageTable::ENTRY_COUNT]);
+
+impl PageTable {
+    /// The number of [`PageTableEntry`]s in a [`PageTable`].
+    pub const ENTRY_COUNT: usize = 512;
+
+    /// Returns a [`PageTable`] whose entries are all unused.
+    pub const fn new() -> Self {
+        Self([PageTableEntry::unused(); Self::ENTRY_COUNT])
+    }
+}
+
+impl Default for PageTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Index<usize> for PageTable {
+    type Output = PageTableEntry;
+
+    /// Returns the [`PageTableEntry`] at `index`.
+    ///
+    /// # Panics
+    /// Panics if `index` is not less than [`PageTable::ENTRY_COUNT`].
+    fn index(&self, index: usize) -> &Self::Output {
+        &self.0[index]
+    }
+}
+
+impl IndexMut<usize> for PageTable {
+    /// Returns the [`PageTableEntry`] at `index`.
+    ///
+    /// # Panics
+    /// Panics if `index` is not less than [`PageTable::ENTRY_COUNT`].
+    fn index_mut(&mut self, index: usize) -> &mut Self::Output {
+        &mut self.0[index]
+    }
+}
+
+/// A single entry of a [`PageTable`], which either is unused or refers to a [`Frame`].
+#[repr(transparent)]
+#[derive(Clone, Copy, Hash, PartialEq, Eq)]
+pub struct PageTableEntry(u64);
+
+impl PageTableEntry {
+    /// The bits of a [`PageTableEntry`] that hold the [`PhysicalAddress`] of its [`Frame`].
+    pub const ADDRESS_MASK: u64 = PhysicalAddress::ADDRESS_MASK & !(Frame::FRAME_SIZE - 1);
+
+    /// Returns an unused [`PageTableEntry`].
+    pub const fn unused() -> Self {
+        Self(0)
+    }
+
+    /// Returns a [`PageTableEntry`] that refers to `frame` with the given `flags`.
+    pub const fn new(frame: Frame, flags: PageTableFlags) -> Self {
+        Self((frame.base_address().value() & Self::ADDRESS_MASK) | flags.bits())
+    }
+
+    /// Returns the [`Frame`] this [`PageTableEntry`] refers to, or [`None`] if it is not
+    /// present.
+    pub const fn frame(&self) -> Option<Frame> {
+        if !self.flags().contains(PageTableFlags::PRESENT) {
+            return None;
+        }
+
+        Some(Frame::containing_address(PhysicalAddress::new_masked(
+            self.0 & Self::ADDRESS_MASK,
+        )))
+    }
+
+    /// Returns the [`PageTableFlags`] set in this [`PageTableEntry`].
+    pub const fn flags(&self) -> PageTableFlags {
+        PageTableFlags(self.0 & PageTableFlags::ALL.bits())
+    }
+
+    /// Makes this [`PageTableEntry`] refer to `frame` with the given `flags`.
+    pub fn set(&mut self, frame: Frame, flags: PageTableFlags) {
+        *self = Self::new(frame, flags);
+    }
+
+    /// Returns `true` if this [`PageTableEntry`] is unused.
+    pub const fn is_unused(&self) -> bool {
+        self.0 == 0
+    }
+
+    /// Marks this [`PageTableEntry`] as unused.
+    pub fn set_unused(&mut self) {
+        *self = Self::unused();
+    }
+
+    /// Returns the raw value of this [`PageTableEntry`].
+    pub const fn value(&self) -> u64 {
+        self.0
+    }
+}
+
+impl fmt::Debug for PageTableEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PageTableEntry")
+            .field("address", &((self.0 & Self::ADDRESS_MASK) as *const u8))
+            .field("flags", &self.flags())
+            .finish()
+    }
+}
+
+/// The flags that control how a [`PageTableEntry`] is used by the processor.
+#[derive(Clone, Copy, Hash, PartialEq, Eq)]
+pub struct PageTableFlags(u64);
+
+impl PageTableFlags {
+    /// No flags.
+    pub const NONE: Self = Self(0);
+    /// The entry is used by the processor.
+    pub const PRESENT: Self = Self(1 << 0);
+    /// The memory referred to by the entry may be written.
+    pub const WRITABLE: Self = Self(1 << 1);
+    /// The memory referred to by the entry may be accessed from user mode.
+    pub const USER: Self = Self(1 << 2);
+    /// Writes to the memory referred to by the entry go straight to memory.
+    pub const WRITE_THROUGH: Self = Self(1 << 3);
+    /// The memory referred to by the entry is not cached.
+    pub const NO_CACHE: Self = Self(1 << 4);
+    /// Set by the processor when the entry is used in a translation.
+    pub const ACCESSED: Self = Self(1 << 5);
+    /// Set by the processor when the page referred to by the entry is written.
+    pub const DIRTY: Self = Self(1 << 6);
+    /// The entry maps a huge page rather than referring to the next level of tables.
+    pub const HUGE: Self = Self(1 << 7);
+    /// The translation is not flushed from the TLB when `cr3` is reloaded.
+    pub const GLOBAL: Self = Self(1 << 8);
+    /// Instructions may not be fetched from the memory referred to by the entry.
+    pub const NO_EXECUTE: Self = Self(1 << 63);
+    /// Every flag.
+    pub const ALL: Self = Self(
+        Self::PRESENT.0
+            | Self::WRITABLE.0
+            | Self::USER.0
+            | Self::WRITE_THROUGH.0
+            | Self::NO_CACHE.0
+            | Self::ACCESSED.0
+            | Self::DIRTY.0
+            | Self::HUGE.0
+            | Self::GLOBAL.0
+            | Self::NO_EXECUTE.0,
+    );
+
+    /// The name of every flag, in the order the bits are laid out.
+    const NAMES: [(Self, &'static str); 10] = [
+        (Self::PRESENT, "PRESENT"),
+        (Self::WRITABLE, "WRITABLE"),
+        (Self::USER, "USER"),
+        (Self::WRITE_THROUGH, "WRITE_THROUGH"),
+        (Self::NO_CACHE, "NO_CACHE"),
+        (Self::ACCESSED, "ACCESSED"),
+        (Self::DIRTY, "DIRTY"),
+        (Self::HUGE, "HUGE"),
+        (Self::GLOBAL, "GLOBAL"),
+        (Self::NO_EXECUTE, "NO_EXECUTE"),
+    ];
+
+    /// Returns the [`PageTableFlags`] encoded by `bits`, or [`None`] if `bits` contains unknown
+    /// flags.
+    pub const fn from_bits(bits: u64) -> Option<Self> {
+        if bits & !Self::ALL.0 != 0 {
+            return None;
+        }
+
+        Some(Self(bits))
+    }
+
+    /// Returns the bits encoding these [`PageTableFlags`].
+    pub const fn bits(self) -> u64 {
+        self.0
+    }
+
+    /// Returns `true` if these [`PageTableFlags`] include every flag in `other`.
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for PageTableFlags {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self::Output {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl BitAnd for PageTableFlags {
+    type Output = Self;
+
+    fn bitand(self, rhs: Self) -> Self::Output {
+        Self(self.0 & rhs.0)
+    }
+}
+
+impl fmt::Debug for PageTableFlags {
+    /// Formats the [`PageTableFlags`] as `PageTableFlags(PRESENT | WRITABLE)`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("PageTableFlags(")?;
+
+        let mut first = true;
+        for (flag, name) in Self::NAMES {
+            if !self.contains(flag) {
+                continue;
+            }
+
+            if !first {
+                f.write_str(" | ")?;
+            }
+            f.write_str(name)?;
+            first = false;
+        }
+
+        f.write_str(")")
+    }
+}
+
+#[cfg(feature = "ktest")]
+crate::kernel_test! {
+    fn page_table_entry_bit_packing() {
+        let frame = Frame::containing_address(PhysicalAddress::new_masked(
+            PhysicalAddress::ADDRESS_MASK,
+        ));
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+        let entry = PageTableEntry::new(frame, flags);
+
+        assert_eq!(entry.value(), 0x800F_FFFF_FFFF_F003);
+        assert_eq!(entry.frame(), Some(frame));
+        assert_eq!(entry.flags(), flags);
+        assert!(!entry.is_unused());
+
+        let not_present = PageTableEntry::new(frame, PageTableFlags::WRITABLE);
+        assert_eq!(not_present.frame(), None);
+        assert_eq!(not_present.flags(), PageTableFlags::WRITABLE);
+    }
+
+    fn page_table_flags_bits() {
+        assert_eq!(PageTableFlags::NO_EXECUTE.bits(), 1 << 63);
+        assert_eq!(PageTableFlags::ALL.bits() & PageTableEntry::ADDRESS_MASK, 0);
+        assert_eq!(PageTableFlags::from_bits(1 << 9), None);
+        assert_eq!(
+            PageTableFlags::from_bits(0b11),
+            Some(PageTableFlags::PRESENT | PageTableFlags::WRITABLE)
+        );
+    }
+
+    fn page_table_indexing() {
+        let frame = Frame::containing_address(PhysicalAddress::new_masked(0x1000));
+        let mut table = PageTable::new();
+        assert!(table[PageTable::ENTRY_COUNT - 1].is_unused());
+
+        table[511].set(frame, PageTableFlags::PRESENT);
+        assert_eq!(table[511].frame(), Some(frame));
+
+        table[511].set_unused();
+        assert!(table[511].is_unused());
+    }
+}
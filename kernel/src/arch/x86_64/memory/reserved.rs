@@ -0,0 +1,362 @@
+//! Registry of physical memory regions that must not be handed out by the frame allocator, even
+//! when the bootloader's memory map marks them as usable.
+
+use core::fmt;
+
+use crate::{
+    arch::x86_64::memory::{Frame, FrameRange, PhysicalAddress},
+    spinlock::Spinlock,
+};
+
+/// The maximum number of distinct regions that can be reserved at the same time.
+pub const MAX_RESERVED_REGIONS: usize = 32;
+
+/// The global registry of reserved regions.
+static RESERVED_REGIONS: Spinlock<ReservedRegions> = Spinlock::new(ReservedRegions::new());
+
+/// Reserves `range` with the given [`ReservationTag`], preventing the frame allocator from
+/// handing out any [`Frame`] in `range` until the reservation is released.
+///
+/// # Errors
+/// Returns [`ReservationError::RegistryFull`] if there is no room left to track the reservation.
+pub fn reserve(range: FrameRange, tag: ReservationTag) -> Result<(), ReservationError> {
+    RESERVED_REGIONS.lock().reserve(range, tag)
+}
+
+/// Releases all regions reserved with the given [`ReservationTag`], returning the number of
+/// [`Frame`]s that were released.
+pub fn release(tag: ReservationTag) -> u64 {
+    RESERVED_REGIONS.lock().release(tag)
+}
+
+/// Returns `true` if `frame` lies within a reserved region.
+pub fn is_reserved(frame: Frame) -> bool {
+    RESERVED_REGIONS.lock().is_reserved(frame)
+}
+
+/// Returns the [`FrameRange`] containing the `size` bytes starting at `address`.
+///
+/// If `size` is zero or the region extends past the end of the physical address space, this
+/// function returns [`None`].
+pub const fn frame_range_of(address: PhysicalAddress, size: u64) -> Option<FrameRange> {
+    if size == 0 {
+        return None;
+    }
+
+    let Some(end_address) = address.value().checked_add(size - 1) else {
+        return None;
+    };
+    let Some(end_address) = PhysicalAddress::new(end_address) else {
+        return None;
+    };
+
+    Some(FrameRange::inclusive_range(
+        Frame::containing_address(address),
+        Frame::containing_address(end_address),
+    ))
+}
+
+/// The reason a region of physical memory was reserved.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum ReservationTag {
+    /// The region backs bootloader request or response structures that are still in use.
+    BootStructures,
+    /// The region holds the most recently completed boot phase for the host to read back.
+    BootProgress,
+    /// The region lies in bootloader-reclaimable memory that the kernel keeps using after the
+    /// rest of it is reclaimed, such as the active page tables and the boot stack.
+    LiveBootMemory,
+    /// The region holds the kernel image or a boot module, which the bootloader reports as
+    /// available memory. It is never released.
+    KernelAndModules,
+}
+
+/// A fixed-capacity table of reserved [`FrameRange`]s.
+#[derive(Debug)]
+pub struct ReservedRegions {
+    /// The reserved regions, along with the reason they were reserved.
+    regions: [Option<(FrameRange, ReservationTag)>; MAX_RESERVED_REGIONS],
+}
+
+impl ReservedRegions {
+    /// Creates an empty [`ReservedRegions`] table.
+    pub const fn new() -> Self {
+        Self {
+            regions: [None; MAX_RESERVED_REGIONS],
+        }
+    }
+
+    /// Reserves `range` with the given [`ReservationTag`].
+    ///
+    /// Ranges that overlap or directly touch an existing region with the same [`ReservationTag`]
+    /// are merged into that region, so that contiguous structures only consume a single entry.
+    ///
+    /// # Errors
+    /// Returns [`ReservationError::RegistryFull`] if there is no room left to track the
+    /// reservation.
+    pub fn reserve(
+        &mut self,
+        range: FrameRange,
+        tag: ReservationTag,
+    ) -> Result<(), ReservationError> {
+        if range.size_in_frames() == 0 {
+            return Ok(());
+        }
+
+        // Absorb every region that the growing range touches, re-scanning after each merge, since
+        // the merged range may now touch a region that the original range did not.
+        let mut start = range.start().number();
+        let mut end = start + range.size_in_frames();
+        while let Some((region, _)) = self.regions.iter_mut().find_map(|slot| {
+            slot.take_if(|(region, region_tag)| {
+                let region_start = region.start().number();
+                let region_end = region_start + region.size_in_frames();
+                *region_tag == tag && start <= region_end && region_start <= end
+            })
+        }) {
+            start = start.min(region.start().number());
+            end = end.max(region.start().number() + region.size_in_frames());
+        }
+
+        let range = FrameRange::inclusive_range(
+            Frame::containing_address(PhysicalAddress::new_masked(start * Frame::FRAME_SIZE)),
+            Frame::containing_address(PhysicalAddress::new_masked((end - 1) * Frame::FRAME_SIZE)),
+        );
+
+        let Some(slot) = self.regions.iter_mut().find(|slot| slot.is_none()) else {
+            return Err(ReservationError::RegistryFull);
+        };
+        *slot = Some((range, tag));
+
+        Ok(())
+    }
+
+    /// Releases all regions reserved with the given [`ReservationTag`], returning the number of
+    /// [`Frame`]s that were released.
+    pub fn release(&mut self, tag: ReservationTag) -> u64 {
+        let mut released = 0;
+        for slot in self.regions.iter_mut() {
+            if let Some((range, region_tag)) = slot {
+                if *region_tag == tag {
+                    released += range.size_in_frames();
+                    *slot = None;
+                }
+            }
+        }
+
+        released
+    }
+
+    /// Returns `true` if `frame` lies within a reserved region.
+    pub fn is_reserved(&self, frame: Frame) -> bool {
+        self.regions
+            .iter()
+            .flatten()
+            .any(|(range, _)| range.contains_address(frame.base_address()))
+    }
+}
+
+impl Default for ReservedRegions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Various errors that can occur while reserving a region of physical memory.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum ReservationError {
+    /// There is no room left in the registry to track another region.
+    RegistryFull,
+}
+
+impl fmt::Display for ReservationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::RegistryFull => f.pad("reserved region registry is full"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns the [`FrameRange`] covering frames `start` through `end`, inclusively.
+    fn frames(start: u64, end: u64) -> FrameRange {
+        FrameRange::inclusive_range(
+            Frame::containing_address(PhysicalAddress::new_masked(start * Frame::FRAME_SIZE)),
+            Frame::containing_address(PhysicalAddress::new_masked(end * Frame::FRAME_SIZE)),
+        )
+    }
+
+    /// Returns the regions of `registry` with the given [`ReservationTag`], sorted by start.
+    fn regions_of(registry: &ReservedRegions, tag: ReservationTag) -> Vec<FrameRange> {
+        let mut regions = registry
+            .regions
+            .iter()
+            .flatten()
+            .filter(|(_, region_tag)| *region_tag == tag)
+            .map(|(range, _)| *range)
+            .collect::<Vec<_>>();
+        regions.sort();
+        regions
+    }
+
+    #[test]
+    fn frame_range_of_covers_partial_frames() {
+        let address = |value| PhysicalAddress::new(value).unwrap();
+
+        assert_eq!(frame_range_of(address(0x1000), 0x1000), Some(frames(1, 1)));
+        assert_eq!(frame_range_of(address(0x1000), 0x1001), Some(frames(1, 2)));
+        assert_eq!(frame_range_of(address(0x1FFF), 1), Some(frames(1, 1)));
+        assert_eq!(frame_range_of(address(0x1FFF), 2), Some(frames(1, 2)));
+        assert_eq!(frame_range_of(address(0x1800), 0x1000), Some(frames(1, 2)));
+        assert_eq!(frame_range_of(address(0), 0x3000), Some(frames(0, 2)));
+    }
+
+    #[test]
+    fn frame_range_of_rejects_invalid_regions() {
+        let address = |value| PhysicalAddress::new(value).unwrap();
+
+        assert_eq!(frame_range_of(address(0x1000), 0), None);
+        assert_eq!(frame_range_of(address(0x1000), u64::MAX), None);
+
+        let last = PhysicalAddress::new_masked(u64::MAX);
+        assert_eq!(
+            frame_range_of(last, 1),
+            Some(FrameRange::inclusive_range(
+                Frame::containing_address(last),
+                Frame::containing_address(last)
+            ))
+        );
+        assert_eq!(frame_range_of(last, 2), None);
+    }
+
+    #[test]
+    fn reserve_and_query() {
+        let mut registry = ReservedRegions::new();
+        registry
+            .reserve(frames(4, 7), ReservationTag::BootStructures)
+            .unwrap();
+
+        let frame = |number: u64| {
+            Frame::containing_address(PhysicalAddress::new_masked(number * Frame::FRAME_SIZE))
+        };
+        assert!(!registry.is_reserved(frame(3)));
+        assert!(registry.is_reserved(frame(4)));
+        assert!(registry.is_reserved(frame(7)));
+        assert!(!registry.is_reserved(frame(8)));
+
+        registry
+            .reserve(frames(9, 8), ReservationTag::BootStructures)
+            .unwrap();
+        assert_eq!(
+            regions_of(&registry, ReservationTag::BootStructures),
+            [frames(4, 7)]
+        );
+    }
+
+    #[test]
+    fn touching_regions_merge() {
+        let mut registry = ReservedRegions::new();
+        registry
+            .reserve(frames(4, 7), ReservationTag::BootStructures)
+            .unwrap();
+        registry
+            .reserve(frames(8, 9), ReservationTag::BootStructures)
+            .unwrap();
+        registry
+            .reserve(frames(2, 5), ReservationTag::BootStructures)
+            .unwrap();
+
+        assert_eq!(
+            regions_of(&registry, ReservationTag::BootStructures),
+            [frames(2, 9)]
+        );
+    }
+
+    #[test]
+    fn grown_region_merges_with_a_third_region() {
+        let mut registry = ReservedRegions::new();
+        registry
+            .reserve(frames(0, 1), ReservationTag::BootStructures)
+            .unwrap();
+        registry
+            .reserve(frames(6, 7), ReservationTag::BootStructures)
+            .unwrap();
+
+        // Touches the first region directly, and the second only once merged with the first.
+        registry
+            .reserve(frames(1, 5), ReservationTag::BootStructures)
+            .unwrap();
+        assert_eq!(
+            regions_of(&registry, ReservationTag::BootStructures),
+            [frames(0, 7)]
+        );
+
+        // The second region lies in an earlier slot than the one it now touches.
+        let mut registry = ReservedRegions::new();
+        registry
+            .reserve(frames(10, 11), ReservationTag::BootStructures)
+            .unwrap();
+        registry
+            .reserve(frames(0, 1), ReservationTag::BootStructures)
+            .unwrap();
+        registry
+            .reserve(frames(2, 9), ReservationTag::BootStructures)
+            .unwrap();
+        assert_eq!(
+            regions_of(&registry, ReservationTag::BootStructures),
+            [frames(0, 11)]
+        );
+    }
+
+    #[test]
+    fn regions_with_different_tags_stay_separate() {
+        let mut registry = ReservedRegions::new();
+        registry
+            .reserve(frames(0, 3), ReservationTag::BootStructures)
+            .unwrap();
+        registry
+            .reserve(frames(4, 5), ReservationTag::LiveBootMemory)
+            .unwrap();
+        registry
+            .reserve(frames(6, 7), ReservationTag::BootStructures)
+            .unwrap();
+
+        assert_eq!(
+            regions_of(&registry, ReservationTag::BootStructures),
+            [frames(0, 3), frames(6, 7)]
+        );
+        assert_eq!(registry.release(ReservationTag::BootStructures), 6);
+        assert_eq!(registry.release(ReservationTag::BootStructures), 0);
+        assert_eq!(
+            regions_of(&registry, ReservationTag::LiveBootMemory),
+            [frames(4, 5)]
+        );
+    }
+
+    #[test]
+    fn registry_fills_up() {
+        let mut registry = ReservedRegions::new();
+        for index in 0..MAX_RESERVED_REGIONS as u64 {
+            registry
+                .reserve(frames(index * 2, index * 2), ReservationTag::BootProgress)
+                .unwrap();
+        }
+
+        assert_eq!(
+            registry.reserve(frames(1000, 1000), ReservationTag::BootProgress),
+            Err(ReservationError::RegistryFull)
+        );
+
+        // Merging frees up entries, so a range bridging two regions still fits.
+        registry
+            .reserve(frames(1, 1), ReservationTag::BootProgress)
+            .unwrap();
+        assert_eq!(
+            regions_of(&registry, ReservationTag::BootProgress).len(),
+            MAX_RESERVED_REGIONS - 1
+        );
+    }
+}
@@ -0,0 +1,361 @@
+//! Kernel stacks with an unmapped guard page below them.
+//!
+//! Every [`KernelStack`] is carved out of a region of virtual memory reserved for stacks, with
+//! the [`Page`] directly below it left unmapped. Overflowing a [`KernelStack`] then faults on its
+//! guard page instead of corrupting whatever lies below, and [`overflowed_stack()`] lets the
+//! fault handlers identify such a fault as a probable stack overflow, while [`stack_containing()`]
+//! lets the backtrace bound its walk to the stack in use.
+//!
+//! The region is handed out from its start and never reused, since it is far larger than the
+//! stacks the kernel will ever need.
+
+use core::{
+    fmt,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::{
+    arch::x86_64::{
+        boot::FrameAllocator,
+        memory::{
+            mapper::{MapError, Mapper},
+            page_table::PageTableFlags,
+            Page, PageRange, VirtualAddress,
+        },
+    },
+    spinlock::Spinlock,
+};
+
+/// The start of the region of virtual memory reserved for kernel stacks.
+///
+/// This lies in the page map level 4 entry directly above the [kernel heap][super::heap].
+pub const STACK_REGION_START: VirtualAddress = VirtualAddress::new_canonical(0xFFFF_E080_0000_0000);
+/// The number of bytes reserved for kernel stacks, including their guard pages.
+pub const STACK_REGION_SIZE: usize = 64 * 1024 * 1024 * 1024;
+/// The maximum number of [`KernelStack`]s that can exist at once.
+pub const MAX_KERNEL_STACKS: usize = 64;
+
+/// The number of bytes at the start of the stack region that have been handed out.
+static RESERVED: Spinlock<usize> = Spinlock::new(0);
+
+/// The [`KernelStack`]s that currently exist.
+static GUARDED_STACKS: StackTable = StackTable::new();
+
+/// Returns the [`VirtualAddress`] at the bottom of the [`KernelStack`] whose guard page contains
+/// `address`, or [`None`] if `address` does not lie in the guard page of any [`KernelStack`].
+///
+/// This takes no locks, so it can be called from any fault handler.
+pub fn overflowed_stack(address: VirtualAddress) -> Option<VirtualAddress> {
+    GUARDED_STACKS.guarding(address)
+}
+
+/// Returns the mapped [`Page`]s of the [`KernelStack`] that contains `address`, or [`None`] if
+/// `address` does not lie in any [`KernelStack`].
+///
+/// This takes no locks, so it can be called from any fault handler.
+pub fn stack_containing(address: VirtualAddress) -> Option<PageRange> {
+    GUARDED_STACKS.containing(address)
+}
+
+/// A kernel stack, mapped directly above an unmapped guard [`Page`].
+///
+/// A [`KernelStack`] that is dropped instead of [freed][KernelStack::free] keeps its frames and
+/// its entry among the [`MAX_KERNEL_STACKS`] stacks.
+#[derive(Debug, Hash, PartialEq, Eq)]
+pub struct KernelStack {
+    /// The mapped [`Page`]s of the stack.
+    pages: PageRange,
+    /// The index of the stack's entry in [`GUARDED_STACKS`].
+    slot: usize,
+}
+
+impl KernelStack {
+    /// Reserves `size_in_pages + 1` [`Page`]s of the stack region and maps all but the lowest of
+    /// them with frames from `allocator`.
+    ///
+    /// # Errors
+    /// - [`StackError::ZeroSize`] if `size_in_pages` is zero.
+    /// - [`StackError::TooManyStacks`] if [`MAX_KERNEL_STACKS`] stacks already exist.
+    /// - [`StackError::RegionExhausted`] if the stack region has no room for the stack.
+    /// - [`StackError::FrameAllocationFailed`] if a [`Frame`][super::Frame] to back the stack
+    ///   could not be allocated.
+    /// - [`StackError::Map`] if a [`Page`] of the stack could not be mapped.
+    ///
+    /// # Safety
+    /// `mapper` must manage the active page tables, and every [`Frame`][super::Frame] that
+    /// `allocator` hands out must be unused.
+    pub unsafe fn allocate(
+        mapper: &mut Mapper,
+        allocator: &mut FrameAllocator,
+        size_in_pages: usize,
+    ) -> Result<Self, StackError> {
+        if size_in_pages == 0 {
+            return Err(StackError::ZeroSize);
+        }
+
+        let mut reserved = RESERVED.lock();
+        let reservation = size_in_pages
+            .checked_add(1)
+            .and_then(|pages| pages.checked_mul(Page::PAGE_SIZE))
+            .filter(|&size| size <= STACK_REGION_SIZE - *reserved)
+            .ok_or(StackError::RegionExhausted)?;
+
+        let guard_page = Page::containing_address(STACK_REGION_START + *reserved);
+        let bottom = guard_page.base_address() + Page::PAGE_SIZE;
+        let pages = PageRange::inclusive_range(
+            Page::containing_address(bottom),
+            Page::containing_address(bottom + (size_in_pages * Page::PAGE_SIZE - 1)),
+        )
+        .expect("stack region crosses the virtual address space gap");
+
+        let slot = GUARDED_STACKS
+            .insert(pages)
+            .ok_or(StackError::TooManyStacks)?;
+        let stack = Self { pages, slot };
+
+        let mut result = Ok(());
+        let mut mapped = 0;
+        for page in pages {
+            let Some(frame) = allocator.allocate_frame() else {
+                result = Err(StackError::FrameAllocationFailed);
+                break;
+            };
+
+            // SAFETY:
+            // The stack region is only mapped here, under `RESERVED`, so `page` is unused, and the
+            // caller guarantees that `frame` is unused.
+            let mapping =
+                unsafe { mapper.map_to(page, frame, PageTableFlags::WRITABLE, allocator) };
+            if let Err(error) = mapping {
+                // SAFETY:
+                // `frame` was allocated above and never mapped.
+                unsafe { allocator.deallocate_frame(frame) };
+                result = Err(StackError::Map(error));
+                break;
+            }
+
+            mapped += 1;
+        }
+
+        if let Err(error) = result {
+            // SAFETY:
+            // The first `mapped` pages of the stack were mapped above, and nothing has used them.
+            unsafe { stack.release(mapper, allocator, mapped) };
+            return Err(error);
+        }
+
+        // The region is only handed out once the stack is fully mapped, so that a failed
+        // allocation leaves its part of the region for the next one.
+        *reserved += reservation;
+        Ok(stack)
+    }
+
+    /// Unmaps this [`KernelStack`] and returns its frames to `allocator`.
+    ///
+    /// # Safety
+    /// `mapper` must manage the active page tables, and nothing may use this [`KernelStack`]
+    /// anymore.
+    pub unsafe fn free(self, mapper: &mut Mapper, allocator: &mut FrameAllocator) {
+        let size_in_pages = self.pages.size_in_pages();
+
+        // SAFETY:
+        // Every page of the stack was mapped by `allocate()`, and the caller guarantees that
+        // nothing uses them anymore.
+        unsafe { self.release(mapper, allocator, size_in_pages) }
+    }
+
+    /// Returns the [`VirtualAddress`] just above this [`KernelStack`], which is loaded into the
+    /// stack pointer to start using it.
+    pub const fn top(&self) -> VirtualAddress {
+        VirtualAddress(self.bottom().value() + self.pages.size_in_bytes())
+    }
+
+    /// Returns the lowest [`VirtualAddress`] of this [`KernelStack`].
+    pub const fn bottom(&self) -> VirtualAddress {
+        self.pages.start_address()
+    }
+
+    /// Returns the mapped [`Page`]s of this [`KernelStack`].
+    pub const fn pages(&self) -> PageRange {
+        self.pages
+    }
+
+    /// Returns the unmapped [`Page`] directly below this [`KernelStack`].
+    pub const fn guard_page(&self) -> Page {
+        Page(self.pages.start().number() - 1)
+    }
+
+    /// Unmaps the first `mapped` [`Page`]s of this [`KernelStack`], returns their frames to
+    /// `allocator`, and forgets the stack.
+    ///
+    /// # Safety
+    /// `mapper` must manage the active page tables, the first `mapped` [`Page`]s of this
+    /// [`KernelStack`] must be mapped, and nothing may use them anymore.
+    unsafe fn release(self, mapper: &mut Mapper, allocator: &mut FrameAllocator, mapped: usize) {
+        for page in self.pages.into_iter().take(mapped) {
+            let frame = mapper
+                .unmap(page)
+                .expect("kernel stack page was unmapped behind its back");
+
+            // SAFETY:
+            // `frame` backed a page of this stack, which the caller guarantees is unused.
+            unsafe { allocator.deallocate_frame(frame) };
+        }
+
+        GUARDED_STACKS.remove(self.slot);
+    }
+}
+
+/// Various errors that can occur while allocating a [`KernelStack`].
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum StackError {
+    /// A [`KernelStack`] of zero [`Page`]s was requested.
+    ZeroSize,
+    /// [`MAX_KERNEL_STACKS`] stacks already exist.
+    TooManyStacks,
+    /// The stack region has no room for the [`KernelStack`].
+    RegionExhausted,
+    /// A [`Frame`][super::Frame] to back the [`KernelStack`] could not be allocated.
+    FrameAllocationFailed,
+    /// A [`Page`] of the [`KernelStack`] could not be mapped.
+    Map(MapError),
+}
+
+impl fmt::Display for StackError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ZeroSize => f.pad("kernel stack has no pages"),
+            Self::TooManyStacks => f.pad("too many kernel stacks"),
+            Self::RegionExhausted => f.pad("kernel stack region is exhausted"),
+            Self::FrameAllocationFailed => f.pad("failed to allocate a frame for a kernel stack"),
+            Self::Map(error) => fmt::Display::fmt(error, f),
+        }
+    }
+}
+
+/// A table of the bounds of the guarded stacks, which can be searched without taking a lock.
+struct StackTable {
+    /// The bottom of each stack, or zero for an unused entry.
+    bottoms: [AtomicUsize; MAX_KERNEL_STACKS],
+    /// The address just above each stack, or zero while its entry is being filled in or cleared.
+    tops: [AtomicUsize; MAX_KERNEL_STACKS],
+}
+
+impl StackTable {
+    /// Returns an empty [`StackTable`].
+    const fn new() -> Self {
+        Self {
+            bottoms: [const { AtomicUsize::new(0) }; MAX_KERNEL_STACKS],
+            tops: [const { AtomicUsize::new(0) }; MAX_KERNEL_STACKS],
+        }
+    }
+
+    /// Records a stack made up of `pages`, returning the index of its entry, or [`None`] if the
+    /// table is full.
+    fn insert(&self, pages: PageRange) -> Option<usize> {
+        let bottom = pages.start_address().value();
+        let slot = self.bottoms.iter().position(|entry| {
+            entry
+                .compare_exchange(0, bottom, Ordering::AcqRel, Ordering::Relaxed)
+                .is_ok()
+        })?;
+        self.tops[slot].store(bottom + pages.size_in_bytes(), Ordering::Release);
+
+        Some(slot)
+    }
+
+    /// Forgets the stack recorded in entry `slot`.
+    fn remove(&self, slot: usize) {
+        self.tops[slot].store(0, Ordering::Release);
+        self.bottoms[slot].store(0, Ordering::Release);
+    }
+
+    /// Returns the [`Page`]s of the recorded stack that contains `address`.
+    fn containing(&self, address: VirtualAddress) -> Option<PageRange> {
+        self.bottoms
+            .iter()
+            .zip(&self.tops)
+            .map(|(bottom, top)| (bottom.load(Ordering::Acquire), top.load(Ordering::Acquire)))
+            .find(|&(bottom, top)| bottom != 0 && (bottom..top).contains(&address.value()))
+            .and_then(|(bottom, top)| {
+                PageRange::inclusive_range(
+                    Page::containing_address(VirtualAddress(bottom)),
+                    Page::containing_address(VirtualAddress(top - 1)),
+                )
+            })
+    }
+
+    /// Returns the bottom of the recorded stack whose guard page contains `address`.
+    fn guarding(&self, address: VirtualAddress) -> Option<VirtualAddress> {
+        self.bottoms
+            .iter()
+            .map(|entry| entry.load(Ordering::Acquire))
+            .find(|&bottom| {
+                bottom != 0 && (bottom - Page::PAGE_SIZE..bottom).contains(&address.value())
+            })
+            .map(VirtualAddress)
+    }
+}
+
+#[cfg(feature = "ktest")]
+crate::kernel_test! {
+    fn stack_table_identifies_guard_pages() {
+        let table = StackTable::new();
+        let bottom = STACK_REGION_START + 2 * Page::PAGE_SIZE;
+        let slot = table.insert(test_pages(bottom, 4)).unwrap();
+
+        assert_eq!(table.guarding(bottom - 1), Some(bottom));
+        assert_eq!(table.guarding(bottom - Page::PAGE_SIZE), Some(bottom));
+        assert_eq!(table.guarding(bottom - Page::PAGE_SIZE - 1), None);
+        assert_eq!(table.guarding(bottom), None);
+
+        let other = bottom + 8 * Page::PAGE_SIZE;
+        let other_slot = table.insert(test_pages(other, 1)).unwrap();
+        assert_ne!(slot, other_slot);
+        assert_eq!(table.guarding(other - 8), Some(other));
+
+        table.remove(slot);
+        assert_eq!(table.guarding(bottom - 1), None);
+        assert_eq!(table.insert(test_pages(bottom, 4)), Some(slot));
+    }
+
+    fn stack_table_finds_the_containing_stack() {
+        let table = StackTable::new();
+        let bottom = STACK_REGION_START + 2 * Page::PAGE_SIZE;
+        let pages = test_pages(bottom, 4);
+        let slot = table.insert(pages).unwrap();
+
+        assert_eq!(table.containing(bottom), Some(pages));
+        assert_eq!(table.containing(pages.start_address() + (pages.size_in_bytes() - 1)), Some(pages));
+        assert_eq!(table.containing(bottom - 1), None);
+        assert_eq!(table.containing(bottom + pages.size_in_bytes()), None);
+
+        table.remove(slot);
+        assert_eq!(table.containing(bottom), None);
+    }
+
+    fn stack_table_fills_up() {
+        let table = StackTable::new();
+        for index in 0..MAX_KERNEL_STACKS {
+            let bottom = STACK_REGION_START + (2 * index + 1) * Page::PAGE_SIZE;
+            assert_eq!(table.insert(test_pages(bottom, 1)), Some(index));
+        }
+
+        assert_eq!(table.insert(test_pages(STACK_REGION_START + Page::PAGE_SIZE, 1)), None);
+        table.remove(3);
+        assert_eq!(
+            table.insert(test_pages(STACK_REGION_START + 7 * Page::PAGE_SIZE, 1)),
+            Some(3)
+        );
+    }
+}
+
+/// Returns the `count` [`Page`]s starting at `bottom`.
+#[cfg(feature = "ktest")]
+fn test_pages(bottom: VirtualAddress, count: usize) -> PageRange {
+    PageRange::inclusive_range(
+        Page::containing_address(bottom),
+        Page::containing_address(bottom + (count * Page::PAGE_SIZE - 1)),
+    )
+    .unwrap()
+}
@@ -1,15 +1,98 @@
 //! Definitions of `x86_64` functionality.
 
-use structures::idt::InterruptDescriptorTable;
+use core::cell::UnsafeCell;
 
+use structures::{
+    gdt::GlobalDescriptorTable, idt::InterruptDescriptorTable, tss::TaskStateSegment,
+};
+
+use crate::sync::Once;
+
+pub use boot::info::{boot_info, BootInfo, MemoryKind};
+pub use memory::{
+    direct_map, kernel_memory,
+    stack::{KernelStack, StackError},
+    Frame, FrameRange, Page, PhysicalAddress, VirtualAddress,
+};
+#[cfg(feature = "qemu-exit")]
+pub use qemu::{qemu_exit, ExitCode};
+
+pub mod apic;
+pub mod backtrace;
 mod boot;
+pub mod boot_progress;
+pub mod context;
+pub mod cpu;
 #[cfg(feature = "debugcon-logging")]
 mod debugcon;
+pub mod interrupt_stats;
 #[cfg(feature = "logging")]
 pub mod logging;
 mod memory;
+pub mod per_cpu;
+pub mod ps2;
+#[cfg(feature = "qemu-exit")]
+pub mod qemu;
 #[cfg(feature = "serial-logging")]
-mod serial;
+pub mod serial;
+pub mod smp;
 mod structures;
+pub mod syscall;
+pub mod usermode;
+
+/// Idles the current processor until an interrupt arrives.
+///
+/// Maskable interrupts are only enabled once the local APIC timer is running. Until then, only a
+/// non-maskable interrupt ends the wait.
+pub fn wait_for_interrupt() {
+    if apic::timer_running() {
+        // SAFETY:
+        // The timer is only started once every vector it and the interrupt controllers can raise
+        // has a handler.
+        unsafe { cpu::wait_for_interrupt() }
+    } else {
+        cpu::halt();
+    }
+}
+
+/// The [`GlobalDescriptorTable`] of the bootstrap processor.
+///
+/// Application processors need task state segments, and so tables, of their own.
+static GDT: Once<GlobalDescriptorTable> = Once::new();
+
+/// The [`TaskStateSegment`] of the bootstrap processor.
+static TSS: Once<TaskStateSegment> = Once::new();
+
+/// The size, in bytes, of [`PRIVILEGE_STACK`].
+pub const PRIVILEGE_STACK_SIZE: usize = 16 * 1024;
+
+/// The stack the bootstrap processor switches to when an interrupt arrives in user mode.
+#[repr(C, align(16))]
+struct PrivilegeStack(UnsafeCell<[u8; PRIVILEGE_STACK_SIZE]>);
+
+// SAFETY:
+// The stack is only ever used by the processor on entry to ring 0 from user mode.
+unsafe impl Sync for PrivilegeStack {}
+
+/// The stack the bootstrap processor switches to when an interrupt arrives in user mode.
+static PRIVILEGE_STACK: PrivilegeStack = PrivilegeStack(UnsafeCell::new([0; PRIVILEGE_STACK_SIZE]));
+
+/// The size, in bytes, of [`DOUBLE_FAULT_STACK`].
+pub const DOUBLE_FAULT_STACK_SIZE: usize = 16 * 1024;
+
+/// The stack the bootstrap processor switches to when a double fault occurs, so that a double
+/// fault caused by an overflowing stack can still be handled.
+#[repr(C, align(16))]
+struct DoubleFaultStack(UnsafeCell<[u8; DOUBLE_FAULT_STACK_SIZE]>);
+
+// SAFETY:
+// The stack is only ever used by the processor on entry to the double fault handler, which never
+// returns.
+unsafe impl Sync for DoubleFaultStack {}
+
+/// The stack the bootstrap processor switches to when a double fault occurs.
+static DOUBLE_FAULT_STACK: DoubleFaultStack =
+    DoubleFaultStack(UnsafeCell::new([0; DOUBLE_FAULT_STACK_SIZE]));
 
-static mut IDT: InterruptDescriptorTable = InterruptDescriptorTable::new();
+/// The [`InterruptDescriptorTable`] shared by every processor.
+static IDT: Once<InterruptDescriptorTable> = Once::new();
@@ -0,0 +1,332 @@
+//! Per-CPU variables.
+//!
+//! Variables declared with [`per_cpu!`][crate::per_cpu] are placed in the `.percpu` section,
+//! which serves as a template for the per-CPU areas. Each processor copies the template into its
+//! own area during bring-up and points its GS base at it. A variable is then located in the
+//! current processor's area at the same offset as in the template, which the linker fixes, so
+//! offsets are stable and require no registration at boot.
+//!
+//! The template itself is never accessed through a [`PerCpu`], so its initial values are
+//! preserved for processors that are brought up later.
+
+use core::{
+    cell::{Cell, UnsafeCell},
+    fmt,
+    marker::PhantomData,
+    ops::{Deref, DerefMut},
+    ptr,
+};
+
+use crate::{arch::x86_64::cpu, sync::Once};
+
+/// The model specific register holding the base address of the GS segment.
+const IA32_GS_BASE: u32 = 0xC000_0101;
+
+/// The alignment of a per-CPU area, which must be at least that of the `.percpu` section.
+pub const AREA_ALIGN: usize = 4096;
+/// The maximum size, in bytes, of the per-CPU area of the bootstrap processor.
+pub const BOOTSTRAP_AREA_SIZE: usize = 2 * 4096;
+
+/// Storage for the per-CPU area of the bootstrap processor, which is brought up before any
+/// memory can be allocated.
+#[repr(C, align(4096))]
+struct BootstrapArea(UnsafeCell<[u8; BOOTSTRAP_AREA_SIZE]>);
+
+// SAFETY:
+// The area is only accessed through `init_bootstrap()`, which runs once, and then by the
+// bootstrap processor through its GS base.
+unsafe impl Sync for BootstrapArea {}
+
+/// The per-CPU area of the bootstrap processor.
+static BOOTSTRAP_AREA: BootstrapArea = BootstrapArea(UnsafeCell::new([0; BOOTSTRAP_AREA_SIZE]));
+/// Whether the per-CPU area of the bootstrap processor has been initialized.
+static BOOTSTRAP_INITIALIZED: Once<()> = Once::new();
+
+/// Declares one or more per-CPU variables.
+///
+/// Each variable is a [`PerCpu`] whose every processor starts with its own copy of the
+/// initializer.
+///
+/// ```ignore
+/// per_cpu! {
+///     /// The interrupt nesting depth of the current processor.
+///     static NESTING_DEPTH: u32 = 0;
+/// }
+/// ```
+#[macro_export]
+macro_rules! per_cpu {
+    ($($(#[$attr:meta])* $vis:vis static $name:ident: $ty:ty = $init:expr;)*) => {
+        $(
+            $(#[$attr])*
+            #[link_section = ".percpu"]
+            $vis static $name: $crate::arch::per_cpu::PerCpu<$ty> =
+                $crate::arch::per_cpu::PerCpu::new($init);
+        )*
+    };
+}
+
+/// Returns the start and size, in bytes, of the per-CPU template.
+fn template() -> (*const u8, usize) {
+    extern "C" {
+        #[link_name = "percpu_start"]
+        static PERCPU_START: core::ffi::c_void;
+        #[link_name = "percpu_end"]
+        static PERCPU_END: core::ffi::c_void;
+    }
+
+    let start = ptr::addr_of!(PERCPU_START).cast::<u8>();
+    let end = ptr::addr_of!(PERCPU_END).cast::<u8>();
+
+    (start, end as usize - start as usize)
+}
+
+/// Returns the size, in bytes, of a per-CPU area.
+pub fn area_size() -> usize {
+    template().1
+}
+
+/// Returns the offset of `pointer` from the start of the per-CPU template, or [`None`] if it does
+/// not point into the template.
+///
+/// A variable at this offset from the GS base is the current processor's copy of the variable at
+/// `pointer`.
+pub fn template_offset<T>(pointer: *const T) -> Option<usize> {
+    let (start, size) = template();
+
+    (pointer as usize)
+        .checked_sub(start as usize)
+        .filter(|&offset| offset < size)
+}
+
+/// Initializes the per-CPU area of the bootstrap processor and makes it current.
+///
+/// # Panics
+/// Panics if called more than once, or if the per-CPU variables do not fit in
+/// [`BOOTSTRAP_AREA_SIZE`] bytes.
+pub fn init_bootstrap() {
+    assert!(
+        area_size() <= BOOTSTRAP_AREA_SIZE,
+        "per-CPU area of {} bytes exceeds the bootstrap area of {BOOTSTRAP_AREA_SIZE} bytes",
+        area_size()
+    );
+    assert!(
+        !BOOTSTRAP_INITIALIZED.is_initialized(),
+        "bootstrap per-CPU area already initialized"
+    );
+
+    // SAFETY:
+    // `BOOTSTRAP_AREA` is large enough, suitably aligned, lives forever, and is only used as the
+    // per-CPU area of this processor, which `BOOTSTRAP_INITIALIZED` ensures happens once.
+    unsafe { init_area(BOOTSTRAP_AREA.0.get().cast()) }
+
+    BOOTSTRAP_INITIALIZED.init(());
+}
+
+/// Copies the per-CPU template into `area` and makes it the per-CPU area of the current
+/// processor.
+///
+/// # Safety
+/// - `area` must be valid for writes of [`area_size()`] bytes and aligned to [`AREA_ALIGN`].
+/// - `area` must remain valid forever and must not be used for anything else.
+/// - The current processor must not have a per-CPU area yet.
+pub unsafe fn init_area(area: *mut u8) {
+    let (start, size) = template();
+
+    // SAFETY:
+    // The template is valid for reads of `size` bytes, the caller guarantees that `area` is
+    // valid for writes of `size` bytes, and the two do not overlap.
+    unsafe { ptr::copy_nonoverlapping(start, area, size) };
+
+    // SAFETY:
+    // The caller guarantees that `area` is a valid per-CPU area for this processor.
+    unsafe { cpu::write_msr(IA32_GS_BASE, area as u64) };
+}
+
+/// Returns the base of the current processor's per-CPU area, or [`None`] if it has not been
+/// initialized.
+///
+/// The GS base left behind by the bootloader is not trusted, so nothing is returned until the
+/// bootstrap processor has been initialized. Processors brought up later start with a GS base of
+/// zero.
+fn area_base() -> Option<*mut u8> {
+    if !BOOTSTRAP_INITIALIZED.is_initialized() {
+        return None;
+    }
+
+    // SAFETY:
+    // `IA32_GS_BASE` exists on every `x86_64` processor and reading it has no side effects.
+    let base = unsafe { cpu::read_msr(IA32_GS_BASE) };
+
+    (base != 0).then_some(base as *mut u8)
+}
+
+/// A variable of which every processor has its own copy, declared with
+/// [`per_cpu!`][crate::per_cpu].
+///
+/// Accessing the variable disables interrupts for as long as the access lasts, so that an
+/// interrupt handler cannot observe it mid-update, and only one access to a given variable may be
+/// active on a processor at a time.
+pub struct PerCpu<T> {
+    /// Whether the current processor's copy is being accessed.
+    borrowed: Cell<bool>,
+    /// The value.
+    value: UnsafeCell<T>,
+}
+
+// SAFETY:
+// Each processor only ever accesses its own copy of the value, with interrupts disabled.
+unsafe impl<T: Send> Sync for PerCpu<T> {}
+
+impl<T> PerCpu<T> {
+    /// Creates a new [`PerCpu`] whose every copy starts as `value`.
+    ///
+    /// This must only be used by [`per_cpu!`][crate::per_cpu], which places the variable in the
+    /// per-CPU template.
+    #[doc(hidden)]
+    pub const fn new(value: T) -> Self {
+        Self {
+            borrowed: Cell::new(false),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    /// Returns the current processor's copy of this [`PerCpu`], or [`None`] if per-CPU areas have
+    /// not been initialized on this processor.
+    fn local(&'static self) -> Option<&'static Self> {
+        let base = area_base()?;
+        let offset = ptr::from_ref(self) as usize - template().0 as usize;
+
+        // SAFETY:
+        // The per-CPU area is a copy of the template, so `offset` lies within it.
+        let copy = unsafe { base.add(offset) }.cast::<Self>();
+        // SAFETY:
+        // The copy of `self` is located at the same offset in the per-CPU area as `self` is in
+        // the template, and the per-CPU area lives forever.
+        Some(unsafe { &*copy })
+    }
+
+    /// Accesses the current processor's copy, disabling interrupts until the returned
+    /// [`PerCpuRef`] is dropped.
+    ///
+    /// # Panics
+    /// Panics if per-CPU areas have not been initialized on this processor, or if the copy is
+    /// already being accessed.
+    #[track_caller]
+    pub fn borrow(&'static self) -> PerCpuRef<T> {
+        match self.try_borrow() {
+            Ok(value) => value,
+            Err(error) => panic!("{error}"),
+        }
+    }
+
+    /// Attempts to access the current processor's copy, disabling interrupts until the returned
+    /// [`PerCpuRef`] is dropped.
+    ///
+    /// # Errors
+    /// Returns [`PerCpuAccessError`] if per-CPU areas have not been initialized on this
+    /// processor, or if the copy is already being accessed. Interrupts are left unchanged.
+    pub fn try_borrow(&'static self) -> Result<PerCpuRef<T>, PerCpuAccessError> {
+        let restore = cpu::interrupts_enabled();
+        if restore {
+            cpu::disable_interrupts();
+        }
+
+        let result = match self.local() {
+            Some(local) if !local.borrowed.replace(true) => Ok(PerCpuRef {
+                local,
+                restore,
+                not_send: PhantomData,
+            }),
+            Some(_) => Err(PerCpuAccessError::AlreadyBorrowed),
+            None => Err(PerCpuAccessError::Uninitialized),
+        };
+
+        if result.is_err() && restore {
+            // SAFETY:
+            // Interrupts were enabled on entry, so this restores the previous state.
+            unsafe { cpu::enable_interrupts() }
+        }
+
+        result
+    }
+
+    /// Calls `f` with the current processor's copy, with interrupts disabled.
+    ///
+    /// # Panics
+    /// Panics if per-CPU areas have not been initialized on this processor, or if the copy is
+    /// already being accessed.
+    #[track_caller]
+    pub fn with<R>(&'static self, f: impl FnOnce(&mut T) -> R) -> R {
+        f(&mut self.borrow())
+    }
+
+    /// Calls `f` with the current processor's copy, with interrupts disabled.
+    ///
+    /// # Errors
+    /// Returns [`PerCpuAccessError`] without calling `f` if the copy cannot be accessed.
+    pub fn try_with<R>(&'static self, f: impl FnOnce(&mut T) -> R) -> Result<R, PerCpuAccessError> {
+        self.try_borrow().map(|mut value| f(&mut value))
+    }
+}
+
+/// An access to the current processor's copy of a [`PerCpu`]. Interrupts stay disabled until
+/// this structure is dropped.
+pub struct PerCpuRef<T: 'static> {
+    /// The current processor's copy.
+    local: &'static PerCpu<T>,
+    /// Whether interrupts must be enabled once the access ends.
+    restore: bool,
+    /// Keeps the access on the processor that owns the copy.
+    not_send: PhantomData<*const ()>,
+}
+
+impl<T> Deref for PerCpuRef<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        // SAFETY:
+        // `self.local.borrowed` guarantees that this is the only access to the copy.
+        unsafe { &*self.local.value.get() }
+    }
+}
+
+impl<T> DerefMut for PerCpuRef<T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        // SAFETY:
+        // `self.local.borrowed` guarantees that this is the only access to the copy.
+        unsafe { &mut *self.local.value.get() }
+    }
+}
+
+impl<T> Drop for PerCpuRef<T> {
+    fn drop(&mut self) {
+        self.local.borrowed.set(false);
+
+        if self.restore {
+            // SAFETY:
+            // Interrupts were enabled when the access began, so this restores the previous
+            // state.
+            unsafe { cpu::enable_interrupts() }
+        }
+    }
+}
+
+/// Various errors that can occur when accessing a [`PerCpu`].
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum PerCpuAccessError {
+    /// Per-CPU areas have not been initialized on the current processor.
+    Uninitialized,
+    /// The current processor's copy is already being accessed.
+    AlreadyBorrowed,
+}
+
+impl fmt::Display for PerCpuAccessError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Uninitialized => f.pad("per-CPU variable accessed before per-CPU initialization"),
+            Self::AlreadyBorrowed => f.pad("per-CPU variable is already being accessed"),
+        }
+    }
+}
+
+impl core::error::Error for PerCpuAccessError {}
@@ -0,0 +1,392 @@
+//! Driver for the keyboard on the first port of the PS/2 controller.
+//!
+//! [`init()`] resets the controller and the keyboard, selects scancode set 2 with the controller's
+//! translation to set 1 disabled, and routes IRQ 1 through the legacy PICs. The interrupt handler
+//! queues raw scancodes, which [`try_read_scancode()`] hands out for decoding by
+//! [`keyboard`][crate::keyboard]. If IRQ 1 cannot be routed, the controller is polled instead.
+
+use core::{
+    fmt,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use crate::{
+    arch::x86_64::{
+        apic, interrupt_stats,
+        structures::idt::{InterruptDescriptorTable, InterruptStackFrame},
+    },
+    spinlock::IrqSpinlock,
+};
+
+/// The legacy IRQ line of the first PS/2 port.
+pub const KEYBOARD_IRQ: u8 = 1;
+/// The interrupt vector IRQ 1 arrives on once routed.
+pub const KEYBOARD_VECTOR: u8 = apic::LEGACY_PIC_VECTOR_BASE + KEYBOARD_IRQ;
+
+/// The number of scancodes queued by the interrupt handler before further ones are dropped.
+pub const QUEUE_CAPACITY: usize = 64;
+
+/// The data port of the controller.
+const DATA_PORT: u16 = 0x60;
+/// The status register, when read, and command register, when written, of the controller.
+const COMMAND_PORT: u16 = 0x64;
+
+/// The status bit set when the output buffer holds a byte for the kernel.
+const STATUS_OUTPUT_FULL: u8 = 1 << 0;
+/// The status bit set while the controller has not consumed the last byte written to it.
+const STATUS_INPUT_FULL: u8 = 1 << 1;
+/// The status bit set when the byte in the output buffer came from the second port.
+const STATUS_AUX_DATA: u8 = 1 << 5;
+
+/// The controller command that reads the configuration byte.
+const COMMAND_READ_CONFIG: u8 = 0x20;
+/// The controller command that writes the configuration byte.
+const COMMAND_WRITE_CONFIG: u8 = 0x60;
+/// The controller command that disables the second port.
+const COMMAND_DISABLE_PORT_2: u8 = 0xA7;
+/// The controller command that runs the controller's self test.
+const COMMAND_SELF_TEST: u8 = 0xAA;
+/// The controller command that tests the first port.
+const COMMAND_TEST_PORT_1: u8 = 0xAB;
+/// The controller command that disables the first port.
+const COMMAND_DISABLE_PORT_1: u8 = 0xAD;
+/// The controller command that enables the first port.
+const COMMAND_ENABLE_PORT_1: u8 = 0xAE;
+
+/// The response to a passed controller self test.
+const SELF_TEST_PASSED: u8 = 0x55;
+/// The response to a passed port test.
+const PORT_TEST_PASSED: u8 = 0x00;
+
+/// The configuration bit that enables the first port's interrupt.
+const CONFIG_PORT_1_INTERRUPT: u8 = 1 << 0;
+/// The configuration bit that enables the second port's interrupt.
+const CONFIG_PORT_2_INTERRUPT: u8 = 1 << 1;
+/// The configuration bit that enables translation of the first port to scancode set 1.
+const CONFIG_TRANSLATION: u8 = 1 << 6;
+
+/// The keyboard command that resets the keyboard and runs its self test.
+const KEYBOARD_RESET: u8 = 0xFF;
+/// The keyboard command that selects a scancode set.
+const KEYBOARD_SET_SCANCODE_SET: u8 = 0xF0;
+/// The keyboard command that starts sending scancodes.
+const KEYBOARD_ENABLE_SCANNING: u8 = 0xF4;
+/// The keyboard's acknowledgement of a command.
+const KEYBOARD_ACK: u8 = 0xFA;
+/// The keyboard's response to a passed self test.
+const KEYBOARD_SELF_TEST_PASSED: u8 = 0xAA;
+
+/// The number of times the status register is polled before giving up on the controller.
+const MAX_POLLS: u32 = 100_000;
+
+/// Whether the keyboard has been initialized.
+static INITIALIZED: AtomicBool = AtomicBool::new(false);
+/// Whether IRQ 1 is routed, so that scancodes are queued by the interrupt handler.
+static INTERRUPTS_ROUTED: AtomicBool = AtomicBool::new(false);
+
+// The queue is filled by the interrupt handler, so it is only held with interrupts disabled.
+static QUEUE: IrqSpinlock<ScancodeQueue> = IrqSpinlock::new(ScancodeQueue::new());
+
+/// Initializes the controller and the keyboard on its first port, returning `true` if IRQ 1 was
+/// routed and `false` if the keyboard is polled.
+///
+/// # Errors
+/// Returns [`Ps2Error`] if the controller or the keyboard does not respond as expected, in which
+/// case the keyboard is left disabled.
+pub fn init() -> Result<bool, Ps2Error> {
+    write_command(COMMAND_DISABLE_PORT_1)?;
+    write_command(COMMAND_DISABLE_PORT_2)?;
+    for _ in 0..QUEUE_CAPACITY {
+        if status() & STATUS_OUTPUT_FULL == 0 {
+            break;
+        }
+        inb(DATA_PORT);
+    }
+
+    let config =
+        read_config()? & !(CONFIG_PORT_1_INTERRUPT | CONFIG_PORT_2_INTERRUPT | CONFIG_TRANSLATION);
+    write_config(config)?;
+
+    write_command(COMMAND_SELF_TEST)?;
+    match read_data()? {
+        SELF_TEST_PASSED => {}
+        response => return Err(Ps2Error::SelfTestFailed(response)),
+    }
+    // The self test may reset the controller, so the configuration is written again.
+    write_config(config)?;
+    if read_config()? & CONFIG_TRANSLATION != 0 {
+        return Err(Ps2Error::TranslationEnabled);
+    }
+
+    write_command(COMMAND_TEST_PORT_1)?;
+    match read_data()? {
+        PORT_TEST_PASSED => {}
+        response => return Err(Ps2Error::PortTestFailed(response)),
+    }
+    write_command(COMMAND_ENABLE_PORT_1)?;
+
+    keyboard_command(KEYBOARD_RESET)?;
+    match read_data()? {
+        KEYBOARD_SELF_TEST_PASSED => {}
+        response => return Err(Ps2Error::KeyboardSelfTestFailed(response)),
+    }
+    keyboard_command(KEYBOARD_SET_SCANCODE_SET)?;
+    keyboard_command(2)?;
+    keyboard_command(KEYBOARD_ENABLE_SCANNING)?;
+
+    let routed = apic::enable_legacy_irq(KEYBOARD_IRQ).is_some();
+    let interrupt = if routed { CONFIG_PORT_1_INTERRUPT } else { 0 };
+    write_config(config | interrupt)?;
+
+    INTERRUPTS_ROUTED.store(routed, Ordering::Release);
+    INITIALIZED.store(true, Ordering::Release);
+    Ok(routed)
+}
+
+/// Installs the handler of IRQ 1 in `idt`.
+pub fn install_handlers(idt: &mut InterruptDescriptorTable) {
+    idt.general_interrupts[usize::from(KEYBOARD_VECTOR) - 32].set_handler_fn(keyboard_handler);
+}
+
+/// Returns the next scancode set 2 byte received from the keyboard, or [`None`] if there is none
+/// or the keyboard has not been initialized.
+pub fn try_read_scancode() -> Option<u8> {
+    if !INITIALIZED.load(Ordering::Acquire) {
+        return None;
+    }
+
+    if INTERRUPTS_ROUTED.load(Ordering::Acquire) {
+        return QUEUE.lock().pop();
+    }
+
+    let status = status();
+    (status & STATUS_OUTPUT_FULL != 0 && status & STATUS_AUX_DATA == 0).then(|| inb(DATA_PORT))
+}
+
+/// Returns the number of scancodes dropped because the queue was full.
+pub fn dropped_scancodes() -> u64 {
+    QUEUE.lock().dropped()
+}
+
+extern "x86-interrupt" fn keyboard_handler(_frame: InterruptStackFrame) {
+    interrupt_stats::record(KEYBOARD_VECTOR);
+
+    let status = status();
+    if status & STATUS_OUTPUT_FULL != 0 && status & STATUS_AUX_DATA == 0 {
+        QUEUE.lock().push(inb(DATA_PORT));
+    }
+    apic::legacy_end_of_interrupt(KEYBOARD_IRQ);
+}
+
+/// Sends `byte` to the keyboard, waiting for its acknowledgement.
+fn keyboard_command(byte: u8) -> Result<(), Ps2Error> {
+    write_data(byte)?;
+    match read_data()? {
+        KEYBOARD_ACK => Ok(()),
+        response => Err(Ps2Error::NotAcknowledged { byte, response }),
+    }
+}
+
+/// Reads the controller's configuration byte.
+fn read_config() -> Result<u8, Ps2Error> {
+    write_command(COMMAND_READ_CONFIG)?;
+    read_data()
+}
+
+/// Writes the controller's configuration byte.
+fn write_config(config: u8) -> Result<(), Ps2Error> {
+    write_command(COMMAND_WRITE_CONFIG)?;
+    write_data(config)
+}
+
+/// Writes `command` to the controller once it is ready to accept it.
+fn write_command(command: u8) -> Result<(), Ps2Error> {
+    wait_for(|status| status & STATUS_INPUT_FULL == 0)?;
+    outb(COMMAND_PORT, command);
+    Ok(())
+}
+
+/// Writes `byte` to the data port once the controller is ready to accept it.
+fn write_data(byte: u8) -> Result<(), Ps2Error> {
+    wait_for(|status| status & STATUS_INPUT_FULL == 0)?;
+    outb(DATA_PORT, byte);
+    Ok(())
+}
+
+/// Reads a byte from the data port once the controller has one.
+fn read_data() -> Result<u8, Ps2Error> {
+    wait_for(|status| status & STATUS_OUTPUT_FULL != 0)?;
+    Ok(inb(DATA_PORT))
+}
+
+/// Polls the status register until `ready` accepts it.
+fn wait_for(ready: impl Fn(u8) -> bool) -> Result<(), Ps2Error> {
+    for _ in 0..MAX_POLLS {
+        if ready(status()) {
+            return Ok(());
+        }
+        core::hint::spin_loop();
+    }
+
+    Err(Ps2Error::Timeout)
+}
+
+/// Reads the controller's status register.
+fn status() -> u8 {
+    inb(COMMAND_PORT)
+}
+
+fn outb(port: u16, byte: u8) {
+    // SAFETY:
+    // The ports written by this module belong to the PS/2 controller, which has no memory safety
+    // implications.
+    unsafe {
+        core::arch::asm!(
+            "out dx, al",
+            in("dx") port,
+            in("al") byte,
+            options(nomem, nostack, preserves_flags)
+        );
+    }
+}
+
+fn inb(port: u16) -> u8 {
+    let byte: u8;
+    // SAFETY:
+    // The ports read by this module belong to the PS/2 controller, and reading them only consumes
+    // the byte waiting in its output buffer.
+    unsafe {
+        core::arch::asm!(
+            "in al, dx",
+            in("dx") port,
+            out("al") byte,
+            options(nomem, nostack, preserves_flags)
+        );
+    }
+
+    byte
+}
+
+/// A fixed-capacity ring buffer of scancodes.
+#[derive(Clone, Copy, Debug)]
+pub struct ScancodeQueue {
+    /// The queued scancodes, starting at `head`.
+    bytes: [u8; QUEUE_CAPACITY],
+    /// The index of the oldest queued scancode.
+    head: usize,
+    /// The number of queued scancodes.
+    len: usize,
+    /// The number of scancodes dropped because the queue was full.
+    dropped: u64,
+}
+
+impl ScancodeQueue {
+    /// Creates an empty [`ScancodeQueue`].
+    pub const fn new() -> Self {
+        Self {
+            bytes: [0; QUEUE_CAPACITY],
+            head: 0,
+            len: 0,
+            dropped: 0,
+        }
+    }
+
+    /// Queues `scancode`, dropping it if the queue is full.
+    pub fn push(&mut self, scancode: u8) {
+        if self.len == QUEUE_CAPACITY {
+            self.dropped += 1;
+            return;
+        }
+
+        self.bytes[(self.head + self.len) % QUEUE_CAPACITY] = scancode;
+        self.len += 1;
+    }
+
+    /// Removes and returns the oldest queued scancode.
+    pub fn pop(&mut self) -> Option<u8> {
+        if self.len == 0 {
+            return None;
+        }
+
+        let scancode = self.bytes[self.head];
+        self.head = (self.head + 1) % QUEUE_CAPACITY;
+        self.len -= 1;
+        Some(scancode)
+    }
+
+    /// Returns the number of scancodes dropped because the queue was full.
+    pub const fn dropped(&self) -> u64 {
+        self.dropped
+    }
+}
+
+impl Default for ScancodeQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Various errors that can occur while initializing the PS/2 keyboard.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum Ps2Error {
+    /// The controller did not become ready in time, or is not present.
+    Timeout,
+    /// The controller's self test failed with the given response.
+    SelfTestFailed(u8),
+    /// The test of the first port failed with the given response.
+    PortTestFailed(u8),
+    /// The keyboard's self test failed with the given response.
+    KeyboardSelfTestFailed(u8),
+    /// The keyboard did not acknowledge a byte.
+    NotAcknowledged {
+        /// The byte sent to the keyboard.
+        byte: u8,
+        /// The keyboard's response.
+        response: u8,
+    },
+    /// The controller kept translating scancodes to set 1 after being told not to.
+    TranslationEnabled,
+}
+
+impl fmt::Display for Ps2Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::Timeout => f.write_str("controller timed out"),
+            Self::SelfTestFailed(response) => {
+                write!(f, "controller self test failed ({response:#04x})")
+            }
+            Self::PortTestFailed(response) => {
+                write!(f, "first port test failed ({response:#04x})")
+            }
+            Self::KeyboardSelfTestFailed(response) => {
+                write!(f, "keyboard self test failed ({response:#04x})")
+            }
+            Self::NotAcknowledged { byte, response } => {
+                write!(f, "keyboard answered {byte:#04x} with {response:#04x}")
+            }
+            Self::TranslationEnabled => f.write_str("scancode translation cannot be disabled"),
+        }
+    }
+}
+
+#[cfg(feature = "ktest")]
+crate::kernel_test! {
+    fn ps2_scancode_queue() {
+        let mut queue = ScancodeQueue::new();
+        assert_eq!(queue.pop(), None);
+
+        for scancode in 0..QUEUE_CAPACITY as u8 + 2 {
+            queue.push(scancode);
+        }
+        assert_eq!(queue.dropped(), 2);
+        for scancode in 0..QUEUE_CAPACITY as u8 {
+            assert_eq!(queue.pop(), Some(scancode));
+        }
+        assert_eq!(queue.pop(), None);
+
+        queue.push(0xF0);
+        queue.push(0x1C);
+        assert_eq!(queue.pop(), Some(0xF0));
+        assert_eq!(queue.pop(), Some(0x1C));
+    }
+}
@@ -0,0 +1,85 @@
+//! Termination of QEMU through its `isa-debug-exit` device.
+//!
+//! Writing a value `v` to the device makes QEMU exit with status `(v << 1) | 1`. Because the exit
+//! status is truncated to 8 bits by the host, only values up to `0x7f` survive intact, and a
+//! status of 0 cannot be produced at all. The kernel therefore maps [`ExitCode`]s as follows:
+//!
+//! | [`ExitCode`]   | Port value               | QEMU exit status           |
+//! |----------------|--------------------------|----------------------------|
+//! | `Success`      | `0x10`                   | 33                         |
+//! | `Failed(code)` | `0x11 + min(code, 0x6e)` | `35 + 2 * min(code, 0x6e)` |
+//!
+//! Any other exit status means that QEMU exited for another reason, such as the monitor's `quit`
+//! command. The host side decoder of this mapping lives in `xtask`'s `decode_guest_exit()` and
+//! must be kept in sync with [`ExitCode::port_value()`].
+
+/// The I/O port at which `xtask` attaches the `isa-debug-exit` device.
+pub const PORT: u16 = 0xf4;
+
+/// The port value written for [`ExitCode::Success`].
+const SUCCESS_VALUE: u32 = 0x10;
+/// The port value written for [`ExitCode::Failed`] with a code of zero.
+const FAILURE_BASE_VALUE: u32 = 0x11;
+/// The largest failure code that survives the host truncating the exit status to 8 bits.
+pub const MAX_FAILURE_CODE: u8 = 0x7f - FAILURE_BASE_VALUE as u8;
+
+/// The status with which the kernel asks QEMU to exit.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum ExitCode {
+    /// The kernel completed its work successfully.
+    Success,
+    /// The kernel failed with the given code.
+    ///
+    /// Codes above [`MAX_FAILURE_CODE`] are reported as [`MAX_FAILURE_CODE`].
+    Failed(u8),
+}
+
+impl ExitCode {
+    /// Returns the value written to the `isa-debug-exit` device to report this [`ExitCode`].
+    pub const fn port_value(self) -> u32 {
+        match self {
+            Self::Success => SUCCESS_VALUE,
+            Self::Failed(code) => {
+                let code = if code > MAX_FAILURE_CODE {
+                    MAX_FAILURE_CODE
+                } else {
+                    code
+                };
+
+                FAILURE_BASE_VALUE + code as u32
+            }
+        }
+    }
+}
+
+/// Flushes the logger and makes QEMU exit with the status corresponding to `code`.
+///
+/// If the `isa-debug-exit` device is absent, the write is ignored and the current processor halts
+/// forever instead.
+pub fn qemu_exit(code: ExitCode) -> ! {
+    #[cfg(feature = "logging")]
+    log::logger().flush();
+
+    qemu_exit_unflushed(code)
+}
+
+/// Makes QEMU exit with the status corresponding to `code` without touching the logger, for
+/// paths that cannot take its locks.
+///
+/// If the `isa-debug-exit` device is absent, the write is ignored and the current processor halts
+/// forever instead.
+pub fn qemu_exit_unflushed(code: ExitCode) -> ! {
+    // SAFETY:
+    // Writing to the `isa-debug-exit` port either terminates QEMU or is ignored when the device is
+    // absent.
+    unsafe {
+        core::arch::asm!(
+            "out dx, eax",
+            in("dx") PORT,
+            in("eax") code.port_value(),
+            options(nomem, nostack, preserves_flags)
+        )
+    }
+
+    super::cpu::halt_forever()
+}
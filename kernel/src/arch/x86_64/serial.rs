@@ -2,69 +2,807 @@
 
 use core::fmt;
 
-pub struct SerialPort {
+use crate::spinlock::{Acquisition, IrqSpinlock, IrqSpinlockGuard};
+
+/// The base I/O port of the serial port used when nothing else has been selected.
+pub const DEFAULT_SERIAL_PORT: u16 = 0x3F8;
+
+// The serial port is written by the logging path, which may run in interrupt handlers, so it is
+// only held with interrupts disabled.
+//
+// SAFETY:
+// `DEFAULT_SERIAL_PORT` is the standard location of COM1.
+static LOCK: IrqSpinlock<SerialPort> =
+    IrqSpinlock::new(unsafe { SerialPort::new(DEFAULT_SERIAL_PORT) });
+
+/// Acquires the serial port driver, disabling interrupts until the returned guard is dropped.
+pub fn acquire_serial_port() -> IrqSpinlockGuard<'static, SerialPort> {
+    LOCK.lock()
+}
+
+/// Acquires the serial port driver, forcibly unlocking it after `max_attempts` failed attempts.
+///
+/// # Safety
+/// See [`IrqSpinlock::force_unlock()`].
+pub unsafe fn acquire_serial_port_or_bypass(
+    max_attempts: usize,
+) -> (IrqSpinlockGuard<'static, SerialPort>, Acquisition) {
+    // SAFETY:
+    // The invariants of `IrqSpinlock::lock_or_bypass()` are upheld by the caller.
+    unsafe { LOCK.lock_or_bypass(max_attempts) }
+}
+
+/// Reads a line of input from the serial port into `buffer`, echoing typed characters.
+///
+/// Backspace and DEL erase the previous character, CR or LF ends the line, and input beyond the
+/// capacity of `buffer` is rejected with a bell. Only printable ASCII is accepted.
+pub fn read_line(buffer: &mut [u8]) -> &str {
+    read_line_with(buffer, || None)
+}
+
+/// Reads a line of input into `buffer` like [`read_line()`], also accepting bytes from `other`.
+///
+/// `other` is polled before the serial port on every iteration and is called without the serial
+/// port held. Typed characters are echoed to the serial port regardless of where they came from.
+pub fn read_line_with(buffer: &mut [u8], mut other: impl FnMut() -> Option<u8>) -> &str {
+    let mut editor = LineEditor::new();
+    loop {
+        let other_byte = other();
+
+        // The lock is only held for a single byte so that logging can interleave with input.
+        let mut serial_port = acquire_serial_port();
+        let byte = match other_byte {
+            Some(byte) => byte,
+            None => match serial_port.try_read_byte() {
+                Ok(byte) => byte,
+                Err(_) => {
+                    drop(serial_port);
+                    core::hint::spin_loop();
+                    continue;
+                }
+            },
+        };
+
+        match editor.feed(buffer, byte) {
+            LineEvent::Echo(byte) => serial_port.write_byte(byte),
+            LineEvent::Erase => serial_port.write_bytes(b"\x08 \x08"),
+            LineEvent::Bell => serial_port.write_byte(0x07),
+            LineEvent::Ignore => {}
+            LineEvent::Done => {
+                serial_port.write_bytes(b"\r\n");
+                break;
+            }
+        }
+    }
+
+    // `LineEditor` only accepts printable ASCII.
+    core::str::from_utf8(&buffer[..editor.len()]).unwrap_or_default()
+}
+
+/// The line-editing state machine behind [`read_line`].
+#[derive(Clone, Copy, Debug, Default, Hash, PartialEq, Eq)]
+pub struct LineEditor {
+    /// The number of bytes in the line.
+    len: usize,
+}
+
+impl LineEditor {
+    /// Creates a [`LineEditor`] for an empty line.
+    pub const fn new() -> Self {
+        Self { len: 0 }
+    }
+
+    /// Returns the number of bytes in the line.
+    pub const fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if the line is empty.
+    pub const fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Applies the input `byte` to the line stored in `buffer`, returning the resulting
+    /// [`LineEvent`].
+    pub fn feed(&mut self, buffer: &mut [u8], byte: u8) -> LineEvent {
+        match byte {
+            b'\r' | b'\n' => LineEvent::Done,
+            0x08 | 0x7F => {
+                if self.len == 0 {
+                    return LineEvent::Ignore;
+                }
+
+                self.len -= 1;
+                LineEvent::Erase
+            }
+            0x20..=0x7E => {
+                let Some(slot) = buffer.get_mut(self.len) else {
+                    return LineEvent::Bell;
+                };
+
+                *slot = byte;
+                self.len += 1;
+                LineEvent::Echo(byte)
+            }
+            _ => LineEvent::Ignore,
+        }
+    }
+}
+
+/// The outcome of feeding a byte to a [`LineEditor`].
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum LineEvent {
+    /// The byte was appended to the line and should be echoed.
+    Echo(u8),
+    /// The last byte of the line was removed and should be erased from the terminal.
+    Erase,
+    /// The line is full and the byte was rejected.
+    Bell,
+    /// The line is complete.
+    Done,
+    /// The byte had no effect.
+    Ignore,
+}
+
+/// The depth, in bytes, of the transmit FIFO of a 16550 UART.
+pub const FIFO_DEPTH: usize = 16;
+/// The values written to the scratch register to verify that it retains them.
+const SCRATCH_TEST_PATTERNS: [u8; 2] = [0x5A, 0xA5];
+/// The byte sent through the loopback path by [`SerialPort::self_test`].
+const LOOPBACK_TEST_BYTE: u8 = 0xAE;
+/// The maximum number of times a register is polled during [`SerialPort::self_test`].
+const SELF_TEST_POLL_LIMIT: u32 = 10_000;
+/// The maximum number of times the line status register is polled by [`SerialPort::flush`].
+///
+/// At 300 baud, a full FIFO drains in well under a second, which this comfortably exceeds, while
+/// still bounding the wait if no UART is present.
+pub const FLUSH_POLL_LIMIT: u32 = 1_000_000;
+/// The approximate number of times the line status register can be read per second, since each
+/// port read takes roughly a microsecond.
+const LINE_STATUS_POLLS_PER_SECOND: u32 = 1_000_000;
+/// The number of bits sent per character, including the start and stop bits.
+const BITS_PER_CHARACTER: u32 = 10;
+/// The number of character times, beyond draining a full FIFO, waited for the transmitter.
+const TRANSMIT_TIMEOUT_CHARACTERS: u32 = 4;
+/// The minimum number of polls made before a transmit wait times out.
+const MIN_TRANSMIT_POLL_LIMIT: u32 = 1_000;
+/// The number of polls made before giving up while the transmitter is degraded.
+///
+/// This is small so that a stalled UART costs little per write, while still letting writes
+/// resume as soon as it recovers.
+pub const DEGRADED_POLL_LIMIT: u32 = 16;
+/// The divisor assumed before [`SerialPort::configure`] is called, that of 9600 baud.
+const ASSUMED_DIVISOR: u16 = 12;
+
+/// The modem control value enabling loopback mode, with RTS, OUT1, and OUT2 set.
+const MODEM_CONTROL_LOOPBACK: u8 = 0x1E;
+/// The modem control value for normal operation, with DTR, RTS, OUT1, and OUT2 set.
+const MODEM_CONTROL_NORMAL: u8 = 0x0F;
+
+/// The base I/O ports of the standard COM1 through COM4 serial ports.
+pub const STANDARD_PORTS: [u16; 4] = [0x3F8, 0x2F8, 0x3E8, 0x2E8];
+
+/// The serial port requested by the kernel command line.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum PortSelection {
+    /// The serial port with the given base I/O port.
+    Port(u16),
+    /// The first of the [`STANDARD_PORTS`] that passes [`SerialPort::self_test`].
+    Auto,
+}
+
+impl PortSelection {
+    /// Parses a [`PortSelection`] from `value`, which is either `auto`, a named port `com1`
+    /// through `com4`, or a hexadecimal base I/O port prefixed with `0x`.
+    ///
+    /// # Errors
+    /// Returns [`PortSelectionError`] if `value` does not describe a serial port.
+    pub fn parse(value: &str) -> Result<Self, PortSelectionError> {
+        if value.is_empty() {
+            return Err(PortSelectionError::Empty);
+        }
+
+        if value.eq_ignore_ascii_case("auto") {
+            return Ok(Self::Auto);
+        }
+
+        if let Some(number) = value
+            .get(..3)
+            .filter(|prefix| prefix.eq_ignore_ascii_case("com"))
+            .and_then(|_| value[3..].parse::<usize>().ok())
+        {
+            return number
+                .checked_sub(1)
+                .and_then(|index| STANDARD_PORTS.get(index))
+                .map(|&port| Self::Port(port))
+                .ok_or(PortSelectionError::UnknownNamedPort);
+        }
+
+        let digits = value
+            .strip_prefix("0x")
+            .or_else(|| value.strip_prefix("0X"))
+            .ok_or(PortSelectionError::InvalidPort)?;
+        match u16::from_str_radix(digits, 16) {
+            Ok(0) | Err(_) => Err(PortSelectionError::InvalidPort),
+            Ok(port) => Ok(Self::Port(port)),
+        }
+    }
+}
+
+/// Various errors that can occur while parsing a [`PortSelection`].
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum PortSelectionError {
+    /// No port was specified.
+    Empty,
+    /// A named port other than `com1` through `com4` was specified.
+    UnknownNamedPort,
+    /// The value is neither a named port nor a valid, non-zero hexadecimal I/O port.
+    InvalidPort,
+}
+
+impl fmt::Display for PortSelectionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Empty => f.pad("no serial port specified"),
+            Self::UnknownNamedPort => f.pad("only com1 through com4 are supported"),
+            Self::InvalidPort => f.pad("expected auto, com1-com4, or a hexadecimal I/O port"),
+        }
+    }
+}
+
+/// The frequency, in hertz, of the UART clock divided by 16, which is the baud rate produced by a
+/// divisor of 1.
+pub const BASE_BAUD_RATE: u32 = 115200;
+/// The maximum deviation, in tenths of a percent, from the requested baud rate that is accepted.
+const MAX_BAUD_ERROR_PER_MILLE: u32 = 20;
+
+/// The speeds at which a [`SerialPort`] can operate.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum BaudRate {
+    /// 115200 baud.
+    Baud115200,
+    /// 57600 baud.
+    Baud57600,
+    /// 38400 baud.
+    Baud38400,
+    /// 19200 baud.
+    Baud19200,
+    /// 9600 baud.
+    Baud9600,
+    /// 4800 baud.
+    Baud4800,
+    /// An arbitrary baud rate.
+    Custom(u32),
+}
+
+impl BaudRate {
+    /// Returns the baud rate in bits per second.
+    pub const fn bits_per_second(self) -> u32 {
+        match self {
+            Self::Baud115200 => 115200,
+            Self::Baud57600 => 57600,
+            Self::Baud38400 => 38400,
+            Self::Baud19200 => 19200,
+            Self::Baud9600 => 9600,
+            Self::Baud4800 => 4800,
+            Self::Custom(rate) => rate,
+        }
+    }
+
+    /// Returns the divisor of [`BASE_BAUD_RATE`] that produces this [`BaudRate`].
+    ///
+    /// # Errors
+    /// Returns [`BaudError`] if the rate is zero, cannot be represented by a 16-bit divisor, or
+    /// would deviate from the requested rate by more than 2%.
+    pub const fn to_divisor(self) -> Result<u16, BaudError> {
+        let rate = self.bits_per_second();
+        if rate == 0 {
+            return Err(BaudError::Zero);
+        }
+        if rate > BASE_BAUD_RATE {
+            return Err(BaudError::TooFast { requested: rate });
+        }
+
+        let divisor = (BASE_BAUD_RATE + rate / 2) / rate;
+        if divisor > u16::MAX as u32 {
+            return Err(BaudError::TooSlow { requested: rate });
+        }
+
+        let actual = BASE_BAUD_RATE / divisor;
+        let difference = actual.abs_diff(rate) as u64;
+        if difference * 1000 > rate as u64 * MAX_BAUD_ERROR_PER_MILLE as u64 {
+            return Err(BaudError::Inexact {
+                requested: rate,
+                actual,
+            });
+        }
+
+        Ok(divisor as u16)
+    }
+}
+
+/// Various errors that can occur while converting a [`BaudRate`] to a divisor.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum BaudError {
+    /// A baud rate of zero was requested.
+    Zero,
+    /// The requested baud rate is faster than [`BASE_BAUD_RATE`].
+    TooFast {
+        /// The requested baud rate.
+        requested: u32,
+    },
+    /// The requested baud rate requires a divisor that does not fit in 16 bits.
+    TooSlow {
+        /// The requested baud rate.
+        requested: u32,
+    },
+    /// The closest achievable baud rate deviates too far from the requested baud rate.
+    Inexact {
+        /// The requested baud rate.
+        requested: u32,
+        /// The closest achievable baud rate.
+        actual: u32,
+    },
+}
+
+impl fmt::Display for BaudError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Zero => f.pad("baud rate must be non-zero"),
+            Self::TooFast { requested } => {
+                write!(
+                    f,
+                    "{requested} baud exceeds the maximum of {BASE_BAUD_RATE}"
+                )
+            }
+            Self::TooSlow { requested } => write!(f, "{requested} baud is too slow"),
+            Self::Inexact { requested, actual } => write!(
+                f,
+                "{requested} baud cannot be produced accurately (closest is {actual})"
+            ),
+        }
+    }
+}
+
+/// Access to the I/O ports backing a [`SerialPort`].
+pub trait PortIo {
+    /// Reads a byte from `port`.
+    fn read(&self, port: u16) -> u8;
+
+    /// Writes `value` to `port`.
+    fn write(&self, port: u16, value: u8);
+}
+
+/// [`PortIo`] implementation using the `in` and `out` instructions.
+///
+/// This can only be obtained through [`SerialPort::new()`], whose caller vouches for the ports
+/// that are accessed.
+#[derive(Clone, Copy, Debug)]
+pub struct RawPortIo(());
+
+impl PortIo for RawPortIo {
+    fn read(&self, port: u16) -> u8 {
+        inb(port)
+    }
+
+    fn write(&self, port: u16, value: u8) {
+        outb(port, value)
+    }
+}
+
+/// Various errors that can occur while verifying that a UART is present.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum SerialProbeError {
+    /// The scratch register did not retain the value written to it.
+    ScratchMismatch {
+        /// The value written to the scratch register.
+        written: u8,
+        /// The value read back from the scratch register.
+        read: u8,
+    },
+    /// The transmit holding register never became empty.
+    TransmitTimeout,
+    /// The byte sent in loopback mode was never received.
+    ReceiveTimeout,
+    /// The byte received in loopback mode did not match the byte sent.
+    LoopbackMismatch {
+        /// The byte that was sent.
+        sent: u8,
+        /// The byte that was received.
+        received: u8,
+    },
+}
+
+impl fmt::Display for SerialProbeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ScratchMismatch { written, read } => write!(
+                f,
+                "scratch register mismatch: wrote {written:#04X}, read {read:#04X}"
+            ),
+            Self::TransmitTimeout => f.pad("transmitter never became ready"),
+            Self::ReceiveTimeout => f.pad("loopback byte was never received"),
+            Self::LoopbackMismatch { sent, received } => write!(
+                f,
+                "loopback mismatch: sent {sent:#04X}, received {received:#04X}"
+            ),
+        }
+    }
+}
+
+/// The error returned by [`SerialPort::flush`] when the transmitter does not become empty.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub struct FlushTimeout;
+
+impl fmt::Display for FlushTimeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad("transmitter did not drain")
+    }
+}
+
+/// The state of the bounded wait for the transmitter of a [`SerialPort`].
+///
+/// Waits are limited to [`TransmitWatchdog::poll_limit`] polls. When a wait times out, the bytes
+/// that were to be written are dropped and the transmitter is considered degraded, which shortens
+/// later waits to [`DEGRADED_POLL_LIMIT`] polls until the transmitter becomes ready again.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub struct TransmitWatchdog {
+    /// The number of polls made before a wait times out while not degraded.
+    poll_limit: u32,
+    /// Whether the last wait timed out.
+    degraded: bool,
+    /// Whether a warning about becoming degraded is waiting to be taken.
+    warning_pending: bool,
+    /// Whether a warning about becoming degraded has ever been raised.
+    warned: bool,
+    /// The number of bytes dropped because the transmitter was not ready.
+    dropped_bytes: u64,
+}
+
+impl TransmitWatchdog {
+    /// Creates a new [`TransmitWatchdog`] whose waits time out after `poll_limit` polls.
+    pub const fn new(poll_limit: u32) -> Self {
+        Self {
+            poll_limit,
+            degraded: false,
+            warning_pending: false,
+            warned: false,
+            dropped_bytes: 0,
+        }
+    }
+
+    /// Returns the number of polls a wait makes before timing out while not degraded.
+    pub const fn poll_limit(&self) -> u32 {
+        self.poll_limit
+    }
+
+    /// Sets the number of polls a wait makes before timing out while not degraded.
+    pub fn set_poll_limit(&mut self, poll_limit: u32) {
+        self.poll_limit = poll_limit;
+    }
+
+    /// Returns the number of polls the next wait should make before timing out.
+    pub const fn current_limit(&self) -> u32 {
+        if self.degraded {
+            DEGRADED_POLL_LIMIT
+        } else {
+            self.poll_limit
+        }
+    }
+
+    /// Returns `true` if the last wait timed out.
+    pub const fn degraded(&self) -> bool {
+        self.degraded
+    }
+
+    /// Returns the number of bytes dropped because the transmitter was not ready.
+    pub const fn dropped_bytes(&self) -> u64 {
+        self.dropped_bytes
+    }
+
+    /// Records that the transmitter became ready, leaving the degraded state.
+    pub fn ready(&mut self) {
+        self.degraded = false;
+    }
+
+    /// Records that a wait timed out, dropping `dropped` bytes.
+    pub fn timed_out(&mut self, dropped: usize) {
+        self.dropped_bytes += dropped as u64;
+        if !self.degraded && !self.warned {
+            self.warned = true;
+            self.warning_pending = true;
+        }
+        self.degraded = true;
+    }
+
+    /// Returns `true` the first time it is called after the transmitter first became degraded.
+    pub fn take_warning(&mut self) -> bool {
+        core::mem::take(&mut self.warning_pending)
+    }
+}
+
+pub struct SerialPort<Io: PortIo = RawPortIo> {
     io_port: u16,
+    io: Io,
+    /// The number of bytes that can be written after the transmitter reports that it is empty.
+    fifo_depth: usize,
+    /// The divisor most recently programmed, used to size [`TransmitWatchdog::poll_limit`].
+    divisor: u16,
+    /// The number of times the line status register has been polled while writing.
+    line_status_polls: u64,
+    /// The bounded wait for the transmitter.
+    watchdog: TransmitWatchdog,
 }
 
 impl SerialPort {
+    /// Creates a new [`SerialPort`] at `io_port` that accesses its registers with the `in` and
+    /// `out` instructions.
+    ///
+    /// # Safety
+    /// The eight I/O ports starting at `io_port` must belong to a serial port or to no device at
+    /// all, so that accessing them cannot affect any other device.
     pub const unsafe fn new(io_port: u16) -> Self {
-        Self { io_port }
+        Self::with_io(io_port, RawPortIo(()))
+    }
+}
+
+impl<Io: PortIo> SerialPort<Io> {
+    /// Creates a new [`SerialPort`] at `io_port` that accesses its registers through `io`.
+    pub const fn with_io(io_port: u16, io: Io) -> Self {
+        Self {
+            io_port,
+            io,
+            fifo_depth: 1,
+            divisor: ASSUMED_DIVISOR,
+            line_status_polls: 0,
+            watchdog: TransmitWatchdog::new(transmit_poll_limit(ASSUMED_DIVISOR, 1)),
+        }
+    }
+
+    /// Returns the state of the bounded wait for the transmitter.
+    pub const fn watchdog(&self) -> &TransmitWatchdog {
+        &self.watchdog
+    }
+
+    /// Sets the number of polls made while waiting for the transmitter before bytes are dropped.
+    ///
+    /// This is recalculated whenever the divisor or FIFO control register is set.
+    pub fn set_transmit_poll_limit(&mut self, poll_limit: u32) {
+        self.watchdog.set_poll_limit(poll_limit);
+    }
+
+    /// Returns the state of the bounded wait for the transmitter, for taking its warning.
+    pub fn watchdog_mut(&mut self) -> &mut TransmitWatchdog {
+        &mut self.watchdog
+    }
+
+    /// Recalculates [`TransmitWatchdog::poll_limit`] from the divisor and FIFO depth.
+    fn update_transmit_poll_limit(&mut self) {
+        self.watchdog
+            .set_poll_limit(transmit_poll_limit(self.divisor, self.fifo_depth));
+    }
+
+    /// Returns the number of times the line status register has been polled while writing.
+    pub const fn line_status_polls(&self) -> u64 {
+        self.line_status_polls
+    }
+
+    /// Returns the base I/O port of this [`SerialPort`].
+    pub const fn io_port(&self) -> u16 {
+        self.io_port
     }
 
     pub fn set_interrupt_enable(&mut self, interrupt_enable: InterruptEnable) {
-        outb(self.interrupt_enable_port(), interrupt_enable.0)
+        self.io
+            .write(self.interrupt_enable_port(), interrupt_enable.0)
     }
 
     pub fn get_interrupt_enable(&self) -> InterruptEnable {
-        InterruptEnable(inb(self.interrupt_enable_port()))
+        InterruptEnable(self.io.read(self.interrupt_enable_port()))
     }
 
     pub fn get_interrupt_status(&self) -> InterruptStatus {
-        InterruptStatus(inb(self.interrupt_status_port()))
+        InterruptStatus(self.io.read(self.interrupt_status_port()))
     }
 
     pub fn set_fifo_control(&mut self, fifo_control: FifoControl) {
-        outb(self.fifo_control_port(), fifo_control.0)
+        self.io.write(self.fifo_control_port(), fifo_control.0);
+        self.fifo_depth = if fifo_control.fifo_enabled() {
+            FIFO_DEPTH
+        } else {
+            1
+        };
+        self.update_transmit_poll_limit();
     }
 
     pub fn set_line_control(&mut self, line_control: LineControl) {
-        outb(self.line_control_port(), line_control.0)
+        self.io.write(self.line_control_port(), line_control.0)
     }
 
     pub fn get_line_control(&self) -> LineControl {
-        LineControl(inb(self.line_control_port()))
+        LineControl(self.io.read(self.line_control_port()))
     }
 
     pub fn set_divisor(&mut self, divisor: u16) {
-        outb(self.divisor_low_port(), divisor as u8);
-        outb(self.divisor_high_port(), (divisor >> 8) as u8);
+        self.io.write(self.divisor_low_port(), divisor as u8);
+        self.io
+            .write(self.divisor_high_port(), (divisor >> 8) as u8);
+        self.divisor = divisor;
+        self.update_transmit_poll_limit();
     }
 
     pub fn get_line_status(&self) -> LineStatus {
-        LineStatus(inb(self.line_status_port()))
+        LineStatus(self.io.read(self.line_status_port()))
+    }
+
+    /// Returns the contents of the modem control register.
+    pub fn get_modem_control(&self) -> ModemControl {
+        ModemControl(self.io.read(self.modem_control_port()))
+    }
+
+    /// Returns the contents of the modem status register.
+    pub fn get_modem_status(&self) -> ModemStatus {
+        ModemStatus(self.io.read(self.modem_status_port()))
+    }
+
+    /// Returns the contents of the scratch register.
+    pub fn get_scratch(&self) -> u8 {
+        self.io.read(self.scratch_pad_port())
+    }
+
+    /// Reads every register of the UART, for diagnosing a misbehaving serial port.
+    ///
+    /// The divisor latch shares its ports with the data and interrupt enable registers, so DLAB
+    /// is cleared while the other registers are read, set while the divisor is read, and then
+    /// the original line control value is restored. Reading the interrupt status and line status
+    /// registers acknowledges a pending transmit interrupt and clears the error bits, exactly as
+    /// any other read of them would.
+    pub fn dump_state(&self) -> SerialState {
+        let line_control = self.get_line_control();
+
+        self.io
+            .write(self.line_control_port(), line_control.set_dlab(false).0);
+        let interrupt_enable = self.get_interrupt_enable();
+        let interrupt_status = self.get_interrupt_status();
+        let modem_control = self.get_modem_control();
+        let line_status = self.get_line_status();
+        let modem_status = self.get_modem_status();
+        let scratch = self.get_scratch();
+
+        self.io
+            .write(self.line_control_port(), line_control.set_dlab(true).0);
+        let divisor = self.get_divisor();
+        self.io.write(self.line_control_port(), line_control.0);
+
+        SerialState {
+            interrupt_enable,
+            interrupt_status,
+            line_control,
+            modem_control,
+            line_status,
+            modem_status,
+            divisor,
+            scratch,
+        }
+    }
+
+    /// Configures the serial port to use `baud_rate`, `data_bits`, `stop_bits`, and `parity`.
+    ///
+    /// # Errors
+    /// Returns [`BaudError`] if `baud_rate` cannot be produced by the UART, in which case the
+    /// serial port is left unchanged.
+    pub fn configure(
+        &mut self,
+        baud_rate: BaudRate,
+        data_bits: DataBits,
+        stop_bits: StopBits,
+        parity: Parity,
+    ) -> Result<(), BaudError> {
+        let divisor = baud_rate.to_divisor()?;
+
+        let line_control = LineControl::new()
+            .set_data_bits(data_bits)
+            .set_stop_bits(stop_bits)
+            .set_parity(parity);
+
+        self.set_line_control(line_control.set_dlab(true));
+        self.set_divisor(divisor);
+        self.set_line_control(line_control);
+
+        Ok(())
     }
 
     pub fn get_divisor(&self) -> u16 {
-        let low = inb(self.divisor_low_port());
-        let high = inb(self.divisor_high_port());
+        let low = self.io.read(self.divisor_low_port());
+        let high = self.io.read(self.divisor_high_port());
 
         ((high as u16) << 8) | (low as u16)
     }
 
+    /// Writes `byte` to the serial port.
+    ///
+    /// If the transmitter does not become ready within [`TransmitWatchdog::current_limit`]
+    /// polls, the byte is dropped.
     pub fn write_byte(&mut self, byte: u8) {
-        while self.try_write_byte(byte).is_err() {}
+        if self.wait_output_empty(1) {
+            self.io.write(self.transmit_port(), byte);
+        }
     }
 
     pub fn try_write_byte(&mut self, byte: u8) -> Result<(), u8> {
-        let line_status = self.get_line_status();
-        if line_status.output_empty() {
-            outb(self.transmit_port(), byte);
+        if self.poll_output_empty() {
+            self.io.write(self.transmit_port(), byte);
             Ok(())
         } else {
             Err(byte)
         }
     }
 
+    /// Writes `bytes` to the serial port.
+    ///
+    /// Once the transmitter reports that it is empty, up to a FIFO's worth of bytes are written
+    /// before polling again. If the FIFO is disabled, this polls before every byte.
+    ///
+    /// If the transmitter does not become ready within [`TransmitWatchdog::current_limit`] polls,
+    /// the remaining bytes are dropped.
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        let mut remaining = bytes.len();
+        for chunk in bytes.chunks(self.fifo_depth) {
+            if !self.wait_output_empty(remaining) {
+                return;
+            }
+
+            for &byte in chunk {
+                self.io.write(self.transmit_port(), byte);
+            }
+            remaining -= chunk.len();
+        }
+    }
+
+    /// Waits for the transmitter to accept more data, as bounded by the [`TransmitWatchdog`],
+    /// counting `dropped` bytes as dropped if it does not.
+    fn wait_output_empty(&mut self, dropped: usize) -> bool {
+        for _ in 0..self.watchdog.current_limit() {
+            if self.poll_output_empty() {
+                self.watchdog.ready();
+                return true;
+            }
+
+            core::hint::spin_loop();
+        }
+
+        self.watchdog.timed_out(dropped);
+        false
+    }
+
+    /// Returns `true` if the transmitter can accept more data, counting the poll.
+    fn poll_output_empty(&mut self) -> bool {
+        self.line_status_polls += 1;
+        self.get_line_status().output_empty()
+    }
+
+    /// Waits until every byte written to the serial port has been shifted out onto the line.
+    ///
+    /// Unlike [`LineStatus::output_empty`], which only indicates that more bytes can be written,
+    /// this waits for [`LineStatus::transmitter_empty`], so that nothing is lost if the machine is
+    /// halted or reset afterwards.
+    ///
+    /// # Errors
+    /// Returns [`FlushTimeout`] if the transmitter is still busy after [`FLUSH_POLL_LIMIT`] polls,
+    /// which usually means that no UART is present.
+    pub fn flush(&mut self) -> Result<(), FlushTimeout> {
+        for _ in 0..FLUSH_POLL_LIMIT {
+            self.line_status_polls += 1;
+            if self.get_line_status().transmitter_empty() {
+                return Ok(());
+            }
+
+            core::hint::spin_loop();
+        }
+
+        Err(FlushTimeout)
+    }
+
     pub fn read_byte(&mut self) -> u8 {
         loop {
             let result = self.try_read_byte();
@@ -77,14 +815,81 @@ impl SerialPort {
 
     pub fn try_read_byte(&mut self) -> Result<u8, LineStatus> {
         let line_status = self.get_line_status();
-        if !line_status.error_set() {
-            let byte = inb(self.recieve_port());
+        if line_status.data_ready() && !line_status.error_set() {
+            let byte = self.io.read(self.recieve_port());
             Ok(byte)
         } else {
             Err(line_status)
         }
     }
 
+    /// Verifies that a working UART is present at this [`SerialPort`].
+    ///
+    /// This checks that the scratch register retains written values and that a byte sent with
+    /// the modem control loopback bit set is received unchanged. The modem control register is
+    /// left configured for normal operation afterwards.
+    ///
+    /// # Errors
+    /// Returns a [`SerialProbeError`] describing the first check that failed.
+    pub fn self_test(&mut self) -> Result<(), SerialProbeError> {
+        for pattern in SCRATCH_TEST_PATTERNS {
+            self.io.write(self.scratch_pad_port(), pattern);
+            let read = self.io.read(self.scratch_pad_port());
+            if read != pattern {
+                return Err(SerialProbeError::ScratchMismatch {
+                    written: pattern,
+                    read,
+                });
+            }
+        }
+
+        self.io
+            .write(self.modem_control_port(), MODEM_CONTROL_LOOPBACK);
+        let result = self.loopback_test();
+        self.io
+            .write(self.modem_control_port(), MODEM_CONTROL_NORMAL);
+
+        result
+    }
+
+    /// Sends [`LOOPBACK_TEST_BYTE`] and verifies that it is received unchanged.
+    fn loopback_test(&mut self) -> Result<(), SerialProbeError> {
+        // Discard anything left over in the receive buffer.
+        for _ in 0..SELF_TEST_POLL_LIMIT {
+            if !self.get_line_status().data_ready() {
+                break;
+            }
+            self.io.read(self.recieve_port());
+        }
+
+        let mut polls = 0;
+        while !self.get_line_status().output_empty() {
+            polls += 1;
+            if polls >= SELF_TEST_POLL_LIMIT {
+                return Err(SerialProbeError::TransmitTimeout);
+            }
+        }
+        self.io.write(self.transmit_port(), LOOPBACK_TEST_BYTE);
+
+        let mut polls = 0;
+        while !self.get_line_status().data_ready() {
+            polls += 1;
+            if polls >= SELF_TEST_POLL_LIMIT {
+                return Err(SerialProbeError::ReceiveTimeout);
+            }
+        }
+
+        let received = self.io.read(self.recieve_port());
+        if received != LOOPBACK_TEST_BYTE {
+            return Err(SerialProbeError::LoopbackMismatch {
+                sent: LOOPBACK_TEST_BYTE,
+                received,
+            });
+        }
+
+        Ok(())
+    }
+
     fn recieve_port(&self) -> u16 {
         self.io_port
     }
@@ -134,11 +939,38 @@ impl SerialPort {
     }
 }
 
-impl fmt::Write for SerialPort {
+/// Returns the number of polls that cover draining `fifo_depth` bytes and then
+/// [`TRANSMIT_TIMEOUT_CHARACTERS`] more characters at the baud rate produced by `divisor`.
+const fn transmit_poll_limit(divisor: u16, fifo_depth: usize) -> u32 {
+    let baud_rate = BASE_BAUD_RATE / if divisor == 0 { 1 } else { divisor as u32 };
+    let polls_per_character = LINE_STATUS_POLLS_PER_SECOND * BITS_PER_CHARACTER / baud_rate;
+    let characters = fifo_depth as u32 + TRANSMIT_TIMEOUT_CHARACTERS;
+
+    let limit = polls_per_character.saturating_mul(characters);
+    if limit < MIN_TRANSMIT_POLL_LIMIT {
+        MIN_TRANSMIT_POLL_LIMIT
+    } else {
+        limit
+    }
+}
+
+impl<Io: PortIo> fmt::Debug for SerialPort<Io> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut debug_struct = f.debug_struct("SerialPort");
+
+        debug_struct.field("io_port", &format_args!("{:#X}", self.io_port));
+        debug_struct.field("fifo_depth", &self.fifo_depth);
+        debug_struct.field("line_status_polls", &self.line_status_polls);
+        debug_struct.field("watchdog", &self.watchdog);
+        debug_struct.field("state", &self.dump_state());
+
+        debug_struct.finish()
+    }
+}
+
+impl<Io: PortIo> fmt::Write for SerialPort<Io> {
     fn write_str(&mut self, s: &str) -> fmt::Result {
-        for byte in s.bytes() {
-            self.write_byte(byte);
-        }
+        self.write_bytes(s.as_bytes());
 
         Ok(())
     }
@@ -234,28 +1066,72 @@ impl FifoControl {
         Self((self.0 & !0b1) | (enable as u8))
     }
 
+    /// Returns `true` if the FIFOs are enabled.
+    pub const fn fifo_enabled(self) -> bool {
+        self.0 & 0b1 == 0b1
+    }
+
     pub const fn reset_receive_fifo(self, reset: bool) -> Self {
         Self((self.0 & !0b10) | ((reset as u8) << 1))
     }
 
     pub const fn reset_transmit_fifo(self, reset: bool) -> Self {
-        Self((self.0 & 0b100) | ((reset as u8) << 2))
+        Self((self.0 & !0b100) | ((reset as u8) << 2))
+    }
+
+    pub const fn set_dma_mode(self, dma_mode: DmaMode) -> Self {
+        Self((self.0 & !0b1000) | ((dma_mode as u8) << 3))
+    }
+
+    /// Returns the [`DmaMode`] that is selected.
+    pub const fn dma_mode(self) -> DmaMode {
+        match (self.0 >> 3) & 0b1 {
+            0 => DmaMode::SingleByte,
+            1 => DmaMode::MultiByte,
+            _ => unreachable!(),
+        }
     }
 
-    pub const fn dma_mode(self, dma_mode: DmaMode) -> Self {
-        Self((self.0 & 0b1000) | ((dma_mode as u8) << 3))
+    pub const fn set_trigger_level(self, dma_trigger_level: DmaTriggerLevel) -> Self {
+        Self((self.0 & !0b11000000) | ((dma_trigger_level as u8) << 6))
     }
 
-    pub const fn trigger_level(self, dma_trigger_level: DmaTriggerLevel) -> Self {
-        Self((self.0 & 0b11000000) | ((dma_trigger_level as u8) << 6))
+    /// Returns the [`DmaTriggerLevel`] that is selected.
+    pub const fn trigger_level(self) -> DmaTriggerLevel {
+        match (self.0 >> 6) & 0b11 {
+            0 => DmaTriggerLevel::Byte1,
+            1 => DmaTriggerLevel::Bytes4,
+            2 => DmaTriggerLevel::Bytes8,
+            3 => DmaTriggerLevel::Bytes14,
+            _ => unreachable!(),
+        }
+    }
+
+    /// Returns the raw value written to the FIFO control register.
+    pub const fn bits(self) -> u8 {
+        self.0
     }
 }
 
+impl fmt::Debug for FifoControl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut debug_struct = f.debug_struct("FifoControl");
+
+        debug_struct.field("fifo_enabled", &self.fifo_enabled());
+        debug_struct.field("dma_mode", &self.dma_mode());
+        debug_struct.field("trigger_level", &self.trigger_level());
+
+        debug_struct.finish()
+    }
+}
+
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
 pub enum DmaMode {
     SingleByte = 0,
     MultiByte = 1,
 }
 
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
 pub enum DmaTriggerLevel {
     Byte1 = 0,
     Bytes4 = 1,
@@ -307,9 +1183,9 @@ impl LineControl {
     }
 
     pub const fn stop_bits(self) -> StopBits {
-        match (self.0 >> 1) & 0b1 {
+        match (self.0 >> 2) & 0b1 {
             0 => StopBits::OneBit,
-            1 => StopBits::OneAndHalfBits,
+            1 => StopBits::TwoBits,
             _ => unreachable!(),
         }
     }
@@ -319,8 +1195,8 @@ impl LineControl {
             0 | 2 | 4 | 6 => Parity::Disabled,
             1 => Parity::Odd,
             3 => Parity::Even,
-            5 => Parity::Forced0,
-            7 => Parity::Forced1,
+            5 => Parity::Forced1,
+            7 => Parity::Forced0,
             _ => unreachable!(),
         }
     }
@@ -356,10 +1232,14 @@ pub enum DataBits {
     Bits8 = 3,
 }
 
+/// The number of stop bits sent after each character.
 #[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
 pub enum StopBits {
+    /// A single stop bit.
     OneBit = 0,
-    OneAndHalfBits = 1,
+    /// Two stop bits when the data width is 6 to 8 bits, or one and a half stop bits when the
+    /// data width is 5 bits.
+    TwoBits = 1,
 }
 
 #[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
@@ -412,7 +1292,218 @@ impl LineStatus {
     }
 }
 
+impl fmt::Debug for LineStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut debug_struct = f.debug_struct("LineStatus");
+
+        debug_struct.field("data_ready", &self.data_ready());
+        debug_struct.field("overrun_error", &self.overrun_error());
+        debug_struct.field("parity_error", &self.parity_error());
+        debug_struct.field("framing_error", &self.framing_error());
+        debug_struct.field("break_indicator", &self.break_indicator());
+        debug_struct.field("output_empty", &self.output_empty());
+        debug_struct.field("transmitter_empty", &self.transmitter_empty());
+        debug_struct.field("fifo_error", &self.fifo_error());
+
+        debug_struct.finish()
+    }
+}
+
+/// The contents of the modem control register.
+#[derive(Clone, Copy, Hash, PartialEq, Eq)]
+pub struct ModemControl(u8);
+
+impl ModemControl {
+    /// Returns `true` if Data Terminal Ready is asserted.
+    pub const fn data_terminal_ready(self) -> bool {
+        self.0 & 0b1 == 0b1
+    }
+
+    /// Returns `true` if Request To Send is asserted.
+    pub const fn request_to_send(self) -> bool {
+        (self.0 >> 1) & 0b1 == 0b1
+    }
+
+    /// Returns `true` if the OUT1 output is asserted.
+    pub const fn out1(self) -> bool {
+        (self.0 >> 2) & 0b1 == 0b1
+    }
+
+    /// Returns `true` if the OUT2 output, which gates the UART's interrupt line, is asserted.
+    pub const fn out2(self) -> bool {
+        (self.0 >> 3) & 0b1 == 0b1
+    }
+
+    /// Returns `true` if loopback mode is enabled.
+    pub const fn loopback(self) -> bool {
+        (self.0 >> 4) & 0b1 == 0b1
+    }
+}
+
+impl fmt::Debug for ModemControl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut debug_struct = f.debug_struct("ModemControl");
+
+        debug_struct.field("data_terminal_ready", &self.data_terminal_ready());
+        debug_struct.field("request_to_send", &self.request_to_send());
+        debug_struct.field("out1", &self.out1());
+        debug_struct.field("out2", &self.out2());
+        debug_struct.field("loopback", &self.loopback());
+
+        debug_struct.finish()
+    }
+}
+
+/// The contents of the modem status register.
+#[derive(Clone, Copy, Hash, PartialEq, Eq)]
+pub struct ModemStatus(u8);
+
+impl ModemStatus {
+    /// Returns `true` if Clear To Send changed since the register was last read.
+    pub const fn clear_to_send_changed(self) -> bool {
+        self.0 & 0b1 == 0b1
+    }
+
+    /// Returns `true` if Data Set Ready changed since the register was last read.
+    pub const fn data_set_ready_changed(self) -> bool {
+        (self.0 >> 1) & 0b1 == 0b1
+    }
+
+    /// Returns `true` if Ring Indicator went from asserted to deasserted.
+    pub const fn ring_indicator_trailing_edge(self) -> bool {
+        (self.0 >> 2) & 0b1 == 0b1
+    }
+
+    /// Returns `true` if Data Carrier Detect changed since the register was last read.
+    pub const fn carrier_detect_changed(self) -> bool {
+        (self.0 >> 3) & 0b1 == 0b1
+    }
+
+    /// Returns `true` if Clear To Send is asserted.
+    pub const fn clear_to_send(self) -> bool {
+        (self.0 >> 4) & 0b1 == 0b1
+    }
+
+    /// Returns `true` if Data Set Ready is asserted.
+    pub const fn data_set_ready(self) -> bool {
+        (self.0 >> 5) & 0b1 == 0b1
+    }
+
+    /// Returns `true` if Ring Indicator is asserted.
+    pub const fn ring_indicator(self) -> bool {
+        (self.0 >> 6) & 0b1 == 0b1
+    }
+
+    /// Returns `true` if Data Carrier Detect is asserted.
+    pub const fn carrier_detect(self) -> bool {
+        (self.0 >> 7) & 0b1 == 0b1
+    }
+}
+
+impl fmt::Debug for ModemStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut debug_struct = f.debug_struct("ModemStatus");
+
+        debug_struct.field("clear_to_send_changed", &self.clear_to_send_changed());
+        debug_struct.field("data_set_ready_changed", &self.data_set_ready_changed());
+        debug_struct.field(
+            "ring_indicator_trailing_edge",
+            &self.ring_indicator_trailing_edge(),
+        );
+        debug_struct.field("carrier_detect_changed", &self.carrier_detect_changed());
+        debug_struct.field("clear_to_send", &self.clear_to_send());
+        debug_struct.field("data_set_ready", &self.data_set_ready());
+        debug_struct.field("ring_indicator", &self.ring_indicator());
+        debug_struct.field("carrier_detect", &self.carrier_detect());
+
+        debug_struct.finish()
+    }
+}
+
+/// A snapshot of every register of a [`SerialPort`], produced by [`SerialPort::dump_state`].
+#[derive(Clone, Copy, Hash, PartialEq, Eq)]
+pub struct SerialState {
+    /// The contents of the interrupt enable register.
+    pub interrupt_enable: InterruptEnable,
+    /// The contents of the interrupt status register.
+    pub interrupt_status: InterruptStatus,
+    /// The contents of the line control register.
+    pub line_control: LineControl,
+    /// The contents of the modem control register.
+    pub modem_control: ModemControl,
+    /// The contents of the line status register.
+    pub line_status: LineStatus,
+    /// The contents of the modem status register.
+    pub modem_status: ModemStatus,
+    /// The baud rate divisor.
+    pub divisor: u16,
+    /// The contents of the scratch register.
+    pub scratch: u8,
+}
+
+impl SerialState {
+    /// Returns the baud rate produced by [`SerialState::divisor`], or `None` if it is zero.
+    pub const fn baud_rate(&self) -> Option<u32> {
+        match self.divisor {
+            0 => None,
+            divisor => Some(BASE_BAUD_RATE / divisor as u32),
+        }
+    }
+}
+
+impl fmt::Debug for SerialState {
+    /// Formats each register on its own line, decoded and followed by its raw value.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "SerialState {{")?;
+        writeln!(
+            f,
+            "    interrupt_enable: {:#04X} {:?}",
+            self.interrupt_enable.0, self.interrupt_enable
+        )?;
+        writeln!(
+            f,
+            "    interrupt_status: {:#04X} {:?}",
+            self.interrupt_status.0, self.interrupt_status
+        )?;
+        writeln!(
+            f,
+            "    line_control:     {:#04X} {:?}",
+            self.line_control.0, self.line_control
+        )?;
+        writeln!(
+            f,
+            "    modem_control:    {:#04X} {:?}",
+            self.modem_control.0, self.modem_control
+        )?;
+        writeln!(
+            f,
+            "    line_status:      {:#04X} {:?}",
+            self.line_status.0, self.line_status
+        )?;
+        writeln!(
+            f,
+            "    modem_status:     {:#04X} {:?}",
+            self.modem_status.0, self.modem_status
+        )?;
+        match self.baud_rate() {
+            Some(baud_rate) => writeln!(
+                f,
+                "    divisor:          {:#06X} ({baud_rate} baud)",
+                self.divisor
+            )?,
+            None => writeln!(f, "    divisor:          {:#06X}", self.divisor)?,
+        }
+        writeln!(f, "    scratch:          {:#04X}", self.scratch)?;
+        write!(f, "}}")
+    }
+}
+
+/// Writes `byte` to the I/O port `port`.
 fn outb(port: u16, byte: u8) {
+    // SAFETY:
+    // Port I/O does not access memory, and this is only reached through a [`RawPortIo`], whose
+    // ports the caller of [`SerialPort::new()`] guarantees can be accessed without affecting any
+    // other device.
     unsafe {
         core::arch::asm!(
             "out dx, al",
@@ -423,9 +1514,14 @@ fn outb(port: u16, byte: u8) {
     }
 }
 
+/// Reads a byte from the I/O port `port`.
 fn inb(port: u16) -> u8 {
     let byte: u8;
 
+    // SAFETY:
+    // Port I/O does not access memory, and this is only reached through a [`RawPortIo`], whose
+    // ports the caller of [`SerialPort::new()`] guarantees can be accessed without affecting any
+    // other device.
     unsafe {
         core::arch::asm!(
             "in al, dx",
@@ -437,3 +1533,788 @@ fn inb(port: u16) -> u8 {
 
     byte
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, collections::VecDeque};
+
+    use super::*;
+
+    /// The base I/O port of the [`MockUart`]s under test.
+    const BASE: u16 = 0x3F8;
+
+    /// A simulated 16550 UART, driven through [`PortIo`].
+    #[derive(Default)]
+    struct MockUart {
+        /// The registers and buffers of the UART.
+        state: RefCell<MockState>,
+    }
+
+    /// The state of a [`MockUart`].
+    #[derive(Default)]
+    struct MockState {
+        /// Whether no UART is present, so that every read sees a floating bus.
+        absent: bool,
+        /// Whether the transmitter never becomes ready.
+        stalled: bool,
+        /// The number of line status reads for which the transmitter is still busy.
+        busy_polls: u32,
+        /// Whether bytes sent in loopback mode are lost.
+        loopback_disconnected: bool,
+        /// The value XORed into bytes sent in loopback mode.
+        loopback_corruption: u8,
+        interrupt_enable: u8,
+        fifo_control: u8,
+        line_control: u8,
+        modem_control: u8,
+        scratch: u8,
+        divisor: u16,
+        /// The bytes waiting to be received.
+        received: VecDeque<u8>,
+        /// The bytes sent onto the line.
+        transmitted: Vec<u8>,
+        /// Every access made to the registers, in order.
+        accesses: Vec<Access>,
+    }
+
+    /// An access to the register at an offset from [`BASE`].
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    enum Access {
+        /// The register was read.
+        Read(u16),
+        /// The value was written to the register.
+        Write(u16, u8),
+    }
+
+    impl MockUart {
+        /// Returns a [`MockUart`] that behaves like working hardware.
+        fn new() -> Self {
+            Self::default()
+        }
+
+        /// Returns a [`MockUart`] whose state is first adjusted by `configure`.
+        fn with(configure: impl FnOnce(&mut MockState)) -> Self {
+            let uart = Self::new();
+            configure(&mut uart.state.borrow_mut());
+            uart
+        }
+    }
+
+    impl PortIo for MockUart {
+        fn read(&self, port: u16) -> u8 {
+            let mut state = self.state.borrow_mut();
+            state.accesses.push(Access::Read(port - BASE));
+            if state.absent {
+                return 0xFF;
+            }
+
+            let dlab = state.line_control & 0x80 != 0;
+            match port - BASE {
+                0 if dlab => state.divisor as u8,
+                0 => state.received.pop_front().unwrap_or(0),
+                1 if dlab => (state.divisor >> 8) as u8,
+                1 => state.interrupt_enable,
+                2 => 0x01 | if state.fifo_control & 1 != 0 { 0xC0 } else { 0 },
+                3 => state.line_control,
+                4 => state.modem_control,
+                5 => {
+                    let busy = state.stalled || state.busy_polls != 0;
+                    state.busy_polls = state.busy_polls.saturating_sub(1);
+                    let ready = if busy { 0 } else { 0x60 };
+                    ready | !state.received.is_empty() as u8
+                }
+                6 => 0,
+                7 => state.scratch,
+                offset => panic!("read from unknown register {offset}"),
+            }
+        }
+
+        fn write(&self, port: u16, value: u8) {
+            let mut state = self.state.borrow_mut();
+            state.accesses.push(Access::Write(port - BASE, value));
+            if state.absent {
+                return;
+            }
+
+            let dlab = state.line_control & 0x80 != 0;
+            match port - BASE {
+                0 if dlab => state.divisor = (state.divisor & 0xFF00) | value as u16,
+                0 if state.modem_control & 0x10 != 0 => {
+                    if !state.loopback_disconnected {
+                        let byte = value ^ state.loopback_corruption;
+                        state.received.push_back(byte);
+                    }
+                }
+                0 => state.transmitted.push(value),
+                1 if dlab => state.divisor = (state.divisor & 0x00FF) | (value as u16) << 8,
+                1 => state.interrupt_enable = value,
+                2 => state.fifo_control = value,
+                3 => state.line_control = value,
+                4 => state.modem_control = value,
+                7 => state.scratch = value,
+                offset => panic!("write to read-only register {offset}"),
+            }
+        }
+    }
+
+    #[test]
+    fn self_test_passes_on_working_uart() {
+        let mut port = SerialPort::with_io(BASE, MockUart::new());
+
+        assert_eq!(port.self_test(), Ok(()));
+
+        let state = port.io.state.borrow();
+        assert_eq!(state.modem_control, MODEM_CONTROL_NORMAL);
+        assert!(state.received.is_empty());
+        assert!(state.transmitted.is_empty());
+    }
+
+    #[test]
+    fn self_test_discards_stale_received_bytes() {
+        let uart = MockUart::with(|state| state.received.extend([1, 2, 3]));
+        let mut port = SerialPort::with_io(BASE, uart);
+
+        assert_eq!(port.self_test(), Ok(()));
+        assert!(port.io.state.borrow().received.is_empty());
+    }
+
+    #[test]
+    fn self_test_detects_missing_uart() {
+        let mut port = SerialPort::with_io(BASE, MockUart::with(|state| state.absent = true));
+
+        assert_eq!(
+            port.self_test(),
+            Err(SerialProbeError::ScratchMismatch {
+                written: SCRATCH_TEST_PATTERNS[0],
+                read: 0xFF,
+            })
+        );
+    }
+
+    #[test]
+    fn self_test_detects_stalled_transmitter() {
+        let mut port = SerialPort::with_io(BASE, MockUart::with(|state| state.stalled = true));
+
+        assert_eq!(port.self_test(), Err(SerialProbeError::TransmitTimeout));
+        assert_eq!(port.io.state.borrow().modem_control, MODEM_CONTROL_NORMAL);
+    }
+
+    #[test]
+    fn self_test_detects_broken_loopback() {
+        let uart = MockUart::with(|state| state.loopback_disconnected = true);
+        let mut port = SerialPort::with_io(BASE, uart);
+
+        assert_eq!(port.self_test(), Err(SerialProbeError::ReceiveTimeout));
+        assert_eq!(port.io.state.borrow().modem_control, MODEM_CONTROL_NORMAL);
+
+        let uart = MockUart::with(|state| state.loopback_corruption = 0x01);
+        let mut port = SerialPort::with_io(BASE, uart);
+
+        assert_eq!(
+            port.self_test(),
+            Err(SerialProbeError::LoopbackMismatch {
+                sent: LOOPBACK_TEST_BYTE,
+                received: LOOPBACK_TEST_BYTE ^ 0x01,
+            })
+        );
+        assert_eq!(port.io.state.borrow().modem_control, MODEM_CONTROL_NORMAL);
+    }
+
+    #[test]
+    fn port_selection_parses_valid_specs() {
+        assert_eq!(PortSelection::parse("auto"), Ok(PortSelection::Auto));
+        assert_eq!(PortSelection::parse("AUTO"), Ok(PortSelection::Auto));
+
+        for (index, &port) in STANDARD_PORTS.iter().enumerate() {
+            let name = format!("com{}", index + 1);
+            assert_eq!(PortSelection::parse(&name), Ok(PortSelection::Port(port)));
+            let name = name.to_uppercase();
+            assert_eq!(PortSelection::parse(&name), Ok(PortSelection::Port(port)));
+        }
+
+        assert_eq!(
+            PortSelection::parse("0x2f8"),
+            Ok(PortSelection::Port(0x2F8))
+        );
+        assert_eq!(
+            PortSelection::parse("0X2F8"),
+            Ok(PortSelection::Port(0x2F8))
+        );
+        assert_eq!(PortSelection::parse("0x1"), Ok(PortSelection::Port(0x1)));
+        assert_eq!(
+            PortSelection::parse("0xFFFF"),
+            Ok(PortSelection::Port(0xFFFF))
+        );
+    }
+
+    #[test]
+    fn port_selection_rejects_invalid_specs() {
+        assert_eq!(PortSelection::parse(""), Err(PortSelectionError::Empty));
+
+        for name in ["com0", "com5", "com99"] {
+            assert_eq!(
+                PortSelection::parse(name),
+                Err(PortSelectionError::UnknownNamedPort),
+                "{name}"
+            );
+        }
+
+        for value in [
+            "com", "comx", "2f8", "0x", "0x0", "0x10000", "0xg", "0x-1", "0b10", "ttyS0", "au",
+        ] {
+            assert_eq!(
+                PortSelection::parse(value),
+                Err(PortSelectionError::InvalidPort),
+                "{value}"
+            );
+        }
+
+        assert_eq!(
+            PortSelectionError::InvalidPort.to_string(),
+            "expected auto, com1-com4, or a hexadecimal I/O port"
+        );
+    }
+
+    #[test]
+    fn baud_rates_convert_to_divisors() {
+        let table = [
+            (BaudRate::Baud115200, 1),
+            (BaudRate::Baud57600, 2),
+            (BaudRate::Baud38400, 3),
+            (BaudRate::Baud19200, 6),
+            (BaudRate::Baud9600, 12),
+            (BaudRate::Baud4800, 24),
+            (BaudRate::Custom(14400), 8),
+            (BaudRate::Custom(300), 384),
+            (BaudRate::Custom(50), 2304),
+            (BaudRate::Custom(2), 57600),
+        ];
+        for (baud_rate, divisor) in table {
+            assert_eq!(baud_rate.to_divisor(), Ok(divisor), "{baud_rate:?}");
+            assert_eq!(BASE_BAUD_RATE / divisor as u32, baud_rate.bits_per_second());
+        }
+
+        // Not exactly divisible, but within 2% once rounded to the nearest divisor.
+        assert_eq!(BaudRate::Custom(2000).to_divisor(), Ok(58));
+        assert_eq!(BaudRate::Custom(110).to_divisor(), Ok(1047));
+        assert_eq!(BaudRate::Custom(113000).to_divisor(), Ok(1));
+    }
+
+    #[test]
+    fn baud_rates_without_a_divisor_are_rejected() {
+        assert_eq!(BaudRate::Custom(0).to_divisor(), Err(BaudError::Zero));
+        assert_eq!(
+            BaudRate::Custom(115201).to_divisor(),
+            Err(BaudError::TooFast { requested: 115201 })
+        );
+        assert_eq!(
+            BaudRate::Custom(1).to_divisor(),
+            Err(BaudError::TooSlow { requested: 1 })
+        );
+        assert_eq!(
+            BaudRate::Custom(56000).to_divisor(),
+            Err(BaudError::Inexact {
+                requested: 56000,
+                actual: 57600,
+            })
+        );
+        assert_eq!(
+            BaudRate::Custom(31250).to_divisor(),
+            Err(BaudError::Inexact {
+                requested: 31250,
+                actual: 28800,
+            })
+        );
+
+        assert_eq!(BaudError::Zero.to_string(), "baud rate must be non-zero");
+        assert_eq!(
+            BaudError::TooFast { requested: 230400 }.to_string(),
+            "230400 baud exceeds the maximum of 115200"
+        );
+        assert_eq!(
+            BaudError::Inexact {
+                requested: 56000,
+                actual: 57600
+            }
+            .to_string(),
+            "56000 baud cannot be produced accurately (closest is 57600)"
+        );
+    }
+
+    #[test]
+    fn configure_programs_divisor_and_line_control() {
+        let mut port = SerialPort::with_io(BASE, MockUart::new());
+        port.configure(
+            BaudRate::Baud38400,
+            DataBits::Bits7,
+            StopBits::TwoBits,
+            Parity::Even,
+        )
+        .unwrap();
+
+        let expected = LineControl::new()
+            .set_data_bits(DataBits::Bits7)
+            .set_stop_bits(StopBits::TwoBits)
+            .set_parity(Parity::Even);
+        let state = port.io.state.borrow();
+        assert_eq!(state.divisor, 3);
+        assert_eq!(state.line_control, expected.0);
+        drop(state);
+
+        assert_eq!(
+            port.configure(
+                BaudRate::Custom(56000),
+                DataBits::Bits8,
+                StopBits::OneBit,
+                Parity::Disabled
+            ),
+            Err(BaudError::Inexact {
+                requested: 56000,
+                actual: 57600
+            })
+        );
+        let state = port.io.state.borrow();
+        assert_eq!(state.divisor, 3);
+        assert_eq!(state.line_control, expected.0);
+    }
+
+    /// A step of writing to a [`MockUart`].
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    enum WriteStep {
+        /// The line status register was polled.
+        Poll,
+        /// The byte was written to the transmit holding register.
+        Send(u8),
+    }
+
+    /// Returns the polls of the line status register and the writes to the transmit holding
+    /// register made through `uart`, in order.
+    fn write_steps(uart: &MockUart) -> Vec<WriteStep> {
+        uart.state
+            .borrow()
+            .accesses
+            .iter()
+            .filter_map(|access| match *access {
+                Access::Read(5) => Some(WriteStep::Poll),
+                Access::Write(0, byte) => Some(WriteStep::Send(byte)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Returns the [`WriteStep`]s expected when `bytes` are written in chunks of `chunk_size`.
+    fn chunked_steps(bytes: &[u8], chunk_size: usize) -> Vec<WriteStep> {
+        bytes
+            .chunks(chunk_size)
+            .flat_map(|chunk| {
+                core::iter::once(WriteStep::Poll)
+                    .chain(chunk.iter().map(|&byte| WriteStep::Send(byte)))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn writes_poll_once_per_fifo_chunk() {
+        let mut port = SerialPort::with_io(BASE, MockUart::new());
+        port.set_fifo_control(FifoControl::new().enable_fifo(true));
+        port.io.state.borrow_mut().accesses.clear();
+
+        let bytes = (0..40).collect::<Vec<u8>>();
+        port.write_bytes(&bytes);
+
+        assert_eq!(write_steps(&port.io), chunked_steps(&bytes, FIFO_DEPTH));
+        assert_eq!(port.line_status_polls(), 3);
+        assert_eq!(port.io.state.borrow().transmitted, bytes);
+
+        // Exactly one FIFO's worth of bytes needs only a single poll.
+        port.io.state.borrow_mut().accesses.clear();
+        port.write_bytes(&bytes[..FIFO_DEPTH]);
+        assert_eq!(
+            write_steps(&port.io),
+            chunked_steps(&bytes[..FIFO_DEPTH], FIFO_DEPTH)
+        );
+        assert_eq!(port.line_status_polls(), 4);
+    }
+
+    #[test]
+    fn writes_poll_before_every_byte_without_fifo() {
+        let mut port = SerialPort::with_io(BASE, MockUart::new());
+        port.set_fifo_control(FifoControl::new().enable_fifo(true));
+        port.set_fifo_control(FifoControl::new());
+        port.io.state.borrow_mut().accesses.clear();
+
+        port.write_bytes(b"abc");
+        assert_eq!(write_steps(&port.io), chunked_steps(b"abc", 1));
+        assert_eq!(port.line_status_polls(), 3);
+
+        port.write_bytes(&[]);
+        assert_eq!(port.line_status_polls(), 3);
+    }
+
+    #[test]
+    fn formatted_writes_are_chunked() {
+        use core::fmt::Write;
+
+        let mut port = SerialPort::with_io(BASE, MockUart::new());
+        port.set_fifo_control(FifoControl::new().enable_fifo(true));
+        port.io.state.borrow_mut().accesses.clear();
+
+        write!(port, "{}", "x".repeat(20)).unwrap();
+        assert_eq!(
+            write_steps(&port.io),
+            chunked_steps(&[b'x'; 20], FIFO_DEPTH)
+        );
+    }
+
+    #[test]
+    fn flush_waits_for_the_transmitter_to_drain() {
+        let mut port = SerialPort::with_io(BASE, MockUart::with(|state| state.busy_polls = 5));
+
+        assert_eq!(port.flush(), Ok(()));
+        assert_eq!(port.line_status_polls(), 6);
+
+        assert_eq!(port.flush(), Ok(()));
+        assert_eq!(port.line_status_polls(), 7);
+    }
+
+    #[test]
+    fn flush_times_out_without_a_transmitter() {
+        let mut port = SerialPort::with_io(BASE, MockUart::with(|state| state.stalled = true));
+
+        assert_eq!(port.flush(), Err(FlushTimeout));
+        assert_eq!(port.line_status_polls(), FLUSH_POLL_LIMIT as u64);
+        assert_eq!(FlushTimeout.to_string(), "transmitter did not drain");
+    }
+
+    #[test]
+    fn dump_state_restores_dlab() {
+        for line_control in [0x03, 0x83, 0x1F] {
+            let uart = MockUart::with(|state| {
+                state.line_control = line_control;
+                state.interrupt_enable = 0x05;
+                state.modem_control = MODEM_CONTROL_NORMAL;
+                state.scratch = 0x42;
+                state.divisor = 0x0102;
+            });
+            let port = SerialPort::with_io(BASE, uart);
+
+            let state = port.dump_state();
+
+            let without_dlab = line_control & !0x80;
+            let with_dlab = line_control | 0x80;
+            assert_eq!(
+                port.io.state.borrow().accesses,
+                [
+                    Access::Read(3),
+                    Access::Write(3, without_dlab),
+                    Access::Read(1),
+                    Access::Read(2),
+                    Access::Read(4),
+                    Access::Read(5),
+                    Access::Read(6),
+                    Access::Read(7),
+                    Access::Write(3, with_dlab),
+                    Access::Read(0),
+                    Access::Read(1),
+                    Access::Write(3, line_control),
+                ],
+                "line control {line_control:#04X}"
+            );
+            assert_eq!(port.io.state.borrow().line_control, line_control);
+
+            assert_eq!(state.line_control.0, line_control);
+            assert_eq!(state.interrupt_enable.0, 0x05);
+            assert_eq!(state.modem_control.0, MODEM_CONTROL_NORMAL);
+            assert_eq!(state.scratch, 0x42);
+            assert_eq!(state.divisor, 0x0102);
+            assert_eq!(state.baud_rate(), Some(BASE_BAUD_RATE / 0x0102));
+        }
+    }
+
+    #[test]
+    fn serial_state_formats_every_register() {
+        let uart = MockUart::with(|state| {
+            state.line_control = 0x03;
+            state.divisor = 1;
+        });
+        let port = SerialPort::with_io(BASE, uart);
+
+        let dump = format!("{:?}", port.dump_state());
+        assert!(dump.starts_with("SerialState {\n"));
+        assert!(dump.contains("    line_control:     0x03 LineControl {"));
+        assert!(dump.contains("    divisor:          0x0001 (115200 baud)\n"));
+        assert!(dump.ends_with("    scratch:          0x00\n}"));
+
+        port.io.state.borrow_mut().divisor = 0;
+        let dump = format!("{:?}", port.dump_state());
+        assert!(dump.contains("    divisor:          0x0000\n"));
+    }
+
+    #[test]
+    fn fifo_control_round_trips() {
+        let trigger_levels = [
+            (DmaTriggerLevel::Byte1, 0x00),
+            (DmaTriggerLevel::Bytes4, 0x40),
+            (DmaTriggerLevel::Bytes8, 0x80),
+            (DmaTriggerLevel::Bytes14, 0xC0),
+        ];
+        let dma_modes = [(DmaMode::SingleByte, 0x00), (DmaMode::MultiByte, 0x08)];
+
+        for enable in [false, true] {
+            for reset_receive in [false, true] {
+                for reset_transmit in [false, true] {
+                    for (dma_mode, dma_bits) in dma_modes {
+                        for (trigger_level, trigger_bits) in trigger_levels {
+                            let fifo_control = FifoControl::new()
+                                .enable_fifo(enable)
+                                .reset_receive_fifo(reset_receive)
+                                .reset_transmit_fifo(reset_transmit)
+                                .set_dma_mode(dma_mode)
+                                .set_trigger_level(trigger_level);
+
+                            let expected = enable as u8
+                                | (reset_receive as u8) << 1
+                                | (reset_transmit as u8) << 2
+                                | dma_bits
+                                | trigger_bits;
+                            assert_eq!(fifo_control.bits(), expected);
+                            assert_eq!(fifo_control.fifo_enabled(), enable);
+                            assert_eq!(fifo_control.dma_mode(), dma_mode);
+                            assert_eq!(fifo_control.trigger_level(), trigger_level);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn fifo_control_setters_only_touch_their_field() {
+        let all = FifoControl::new()
+            .enable_fifo(true)
+            .reset_receive_fifo(true)
+            .reset_transmit_fifo(true)
+            .set_dma_mode(DmaMode::MultiByte)
+            .set_trigger_level(DmaTriggerLevel::Bytes14);
+        assert_eq!(all.bits(), 0xCF);
+
+        assert_eq!(all.enable_fifo(false).bits(), 0xCE);
+        assert_eq!(all.reset_receive_fifo(false).bits(), 0xCD);
+        assert_eq!(all.reset_transmit_fifo(false).bits(), 0xCB);
+        assert_eq!(all.set_dma_mode(DmaMode::SingleByte).bits(), 0xC7);
+        assert_eq!(all.set_trigger_level(DmaTriggerLevel::Byte1).bits(), 0x0F);
+    }
+
+    #[test]
+    fn interrupt_enable_round_trips() {
+        for bits in 0..0x10u8 {
+            let (receive, write, error, modem_status) =
+                (bits & 1 != 0, bits & 2 != 0, bits & 4 != 0, bits & 8 != 0);
+            let interrupt_enable = InterruptEnable::new()
+                .set_receive(receive)
+                .set_write(write)
+                .set_error(error)
+                .set_modem_status(modem_status);
+
+            assert_eq!(interrupt_enable.0, bits);
+            assert_eq!(interrupt_enable.receive_enabled(), receive);
+            assert_eq!(interrupt_enable.write_enabled(), write);
+            assert_eq!(interrupt_enable.error_enabled(), error);
+            assert_eq!(interrupt_enable.modem_status_enabled(), modem_status);
+
+            let cleared = interrupt_enable
+                .set_receive(false)
+                .set_write(false)
+                .set_error(false)
+                .set_modem_status(false);
+            assert_eq!(cleared, InterruptEnable::new());
+        }
+    }
+
+    #[test]
+    fn line_control_round_trips() {
+        let data_bits = [
+            (DataBits::Bits5, 0b00),
+            (DataBits::Bits6, 0b01),
+            (DataBits::Bits7, 0b10),
+            (DataBits::Bits8, 0b11),
+        ];
+        let stop_bits = [(StopBits::OneBit, 0b000), (StopBits::TwoBits, 0b100)];
+        let parities = [
+            (Parity::Disabled, 0b000_000),
+            (Parity::Odd, 0b001_000),
+            (Parity::Even, 0b011_000),
+            (Parity::Forced1, 0b101_000),
+            (Parity::Forced0, 0b111_000),
+        ];
+
+        for (data, data_encoding) in data_bits {
+            for (stop, stop_encoding) in stop_bits {
+                for (parity, parity_encoding) in parities {
+                    for enable_break in [false, true] {
+                        for dlab in [false, true] {
+                            let line_control = LineControl::new()
+                                .set_data_bits(data)
+                                .set_stop_bits(stop)
+                                .set_parity(parity)
+                                .set_break(enable_break)
+                                .set_dlab(dlab);
+
+                            let expected = data_encoding
+                                | stop_encoding
+                                | parity_encoding
+                                | (enable_break as u8) << 6
+                                | (dlab as u8) << 7;
+                            assert_eq!(line_control.0, expected);
+                            assert_eq!(line_control.data_bits(), data);
+                            assert_eq!(line_control.stop_bits(), stop);
+                            assert_eq!(line_control.parity(), parity);
+                            assert_eq!(line_control.break_bit(), enable_break);
+                            assert_eq!(line_control.dlab_bit(), dlab);
+
+                            // Every field can be changed without disturbing the others.
+                            let reset = line_control
+                                .set_data_bits(DataBits::Bits8)
+                                .set_stop_bits(StopBits::OneBit)
+                                .set_parity(Parity::Disabled)
+                                .set_break(false)
+                                .set_dlab(false);
+                            assert_eq!(reset, LineControl::new());
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn line_control_decodes_unused_parity_encodings_as_disabled() {
+        for encoding in [0b010, 0b100, 0b110] {
+            assert_eq!(LineControl(encoding << 3).parity(), Parity::Disabled);
+        }
+    }
+
+    #[test]
+    fn watchdog_degrades_and_recovers() {
+        let mut watchdog = TransmitWatchdog::new(1000);
+        assert_eq!(watchdog.current_limit(), 1000);
+        assert!(!watchdog.degraded());
+        assert!(!watchdog.take_warning());
+
+        // A stall degrades the watchdog and raises a single warning.
+        watchdog.timed_out(3);
+        assert!(watchdog.degraded());
+        assert_eq!(watchdog.current_limit(), DEGRADED_POLL_LIMIT);
+        assert_eq!(watchdog.dropped_bytes(), 3);
+        assert!(watchdog.take_warning());
+        assert!(!watchdog.take_warning());
+
+        // Further drops while degraded are counted without warning again.
+        watchdog.timed_out(5);
+        assert_eq!(watchdog.dropped_bytes(), 8);
+        assert!(!watchdog.take_warning());
+
+        watchdog.ready();
+        assert!(!watchdog.degraded());
+        assert_eq!(watchdog.current_limit(), 1000);
+        assert_eq!(watchdog.dropped_bytes(), 8);
+
+        // The warning is only ever raised once.
+        watchdog.timed_out(1);
+        assert!(watchdog.degraded());
+        assert!(!watchdog.take_warning());
+        assert_eq!(watchdog.dropped_bytes(), 9);
+
+        watchdog.set_poll_limit(50);
+        assert_eq!(watchdog.current_limit(), DEGRADED_POLL_LIMIT);
+        watchdog.ready();
+        assert_eq!(watchdog.current_limit(), 50);
+    }
+
+    #[test]
+    fn stalled_transmitter_drops_bytes_and_recovers() {
+        let mut port = SerialPort::with_io(BASE, MockUart::with(|state| state.stalled = true));
+        let poll_limit = port.watchdog().poll_limit();
+        assert_eq!(poll_limit, transmit_poll_limit(ASSUMED_DIVISOR, 1));
+
+        // The first stall waits out the full limit.
+        port.write_byte(b'a');
+        assert_eq!(port.line_status_polls(), poll_limit as u64);
+        assert!(port.watchdog().degraded());
+        assert_eq!(port.watchdog().dropped_bytes(), 1);
+        assert!(port.watchdog_mut().take_warning());
+
+        // Later writes give up quickly, dropping everything that was not written.
+        port.write_bytes(b"bcdef");
+        assert_eq!(
+            port.line_status_polls(),
+            poll_limit as u64 + DEGRADED_POLL_LIMIT as u64
+        );
+        assert_eq!(port.watchdog().dropped_bytes(), 6);
+        assert!(port.io.state.borrow().transmitted.is_empty());
+
+        // Writes resume as soon as the transmitter does.
+        port.io.state.borrow_mut().stalled = false;
+        port.write_bytes(b"gh");
+        assert!(!port.watchdog().degraded());
+        assert_eq!(port.io.state.borrow().transmitted, b"gh");
+        assert_eq!(port.watchdog().dropped_bytes(), 6);
+        assert!(!port.watchdog_mut().take_warning());
+    }
+
+    #[test]
+    fn transmit_poll_limit_follows_baud_rate_and_fifo() {
+        let polls_per_character = |divisor: u32| {
+            LINE_STATUS_POLLS_PER_SECOND * BITS_PER_CHARACTER * divisor / BASE_BAUD_RATE
+        };
+
+        assert_eq!(transmit_poll_limit(1, 1), MIN_TRANSMIT_POLL_LIMIT);
+        assert_eq!(
+            transmit_poll_limit(12, 1),
+            polls_per_character(12) * (1 + TRANSMIT_TIMEOUT_CHARACTERS)
+        );
+        assert_eq!(
+            transmit_poll_limit(12, FIFO_DEPTH),
+            polls_per_character(12) * (FIFO_DEPTH as u32 + TRANSMIT_TIMEOUT_CHARACTERS)
+        );
+        assert_eq!(transmit_poll_limit(0, 1), transmit_poll_limit(1, 1));
+
+        let mut port = SerialPort::with_io(BASE, MockUart::new());
+        port.set_divisor(384);
+        assert_eq!(port.watchdog().poll_limit(), transmit_poll_limit(384, 1));
+        port.set_fifo_control(FifoControl::new().enable_fifo(true));
+        assert_eq!(
+            port.watchdog().poll_limit(),
+            transmit_poll_limit(384, FIFO_DEPTH)
+        );
+    }
+
+    #[test]
+    fn probe_errors_display() {
+        let error = SerialProbeError::ScratchMismatch {
+            written: 0x5A,
+            read: 0xFF,
+        };
+        assert_eq!(
+            error.to_string(),
+            "scratch register mismatch: wrote 0x5A, read 0xFF"
+        );
+
+        let error = SerialProbeError::LoopbackMismatch {
+            sent: 0xAE,
+            received: 0x00,
+        };
+        assert_eq!(
+            error.to_string(),
+            "loopback mismatch: sent 0xAE, received 0x00"
+        );
+        assert_eq!(
+            SerialProbeError::TransmitTimeout.to_string(),
+            "transmitter never became ready"
+        );
+        assert_eq!(
+            SerialProbeError::ReceiveTimeout.to_string(),
+            "loopback byte was never received"
+        );
+    }
+}
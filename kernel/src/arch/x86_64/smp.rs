@@ -0,0 +1,57 @@
+//! Coordination between the bootstrap processor and the application processors during bring-up.
+//!
+//! The bootstrap processor performs global initialization, such as setting up the memory
+//! subsystem, alone, and then calls [`complete_global_init()`] with the number of processors
+//! taking part in bring-up. Each application processor initializes its own per-CPU area and then
+//! calls [`wait_for_global_init()`], which returns once the bootstrap processor has called
+//! [`complete_global_init()`].
+//!
+//! Every processor, the bootstrap processor included, then calls [`wait_for_all_online()`], which
+//! returns once all of them have arrived, so that none enters the scheduler while another is
+//! still initializing.
+
+use crate::sync::{Barrier, BarrierWaitResult, GlobalOnce, Once};
+
+/// Completed by the bootstrap processor once global initialization is done.
+static GLOBAL_INIT: GlobalOnce = GlobalOnce::new();
+
+/// The [`Barrier`] at which the processors taking part in bring-up meet once they are online.
+///
+/// This is recorded before [`GLOBAL_INIT`] is completed, so it is available to every processor
+/// that returns from [`wait_for_global_init()`].
+static ONLINE: Once<Barrier> = Once::new();
+
+/// Releases the application processors waiting in [`wait_for_global_init()`], recording that
+/// `processors` processors, the bootstrap processor included, take part in bring-up.
+///
+/// Everything the bootstrap processor wrote before calling this is visible to the application
+/// processors once they are released.
+///
+/// # Panics
+/// Panics if `processors` is zero or if global initialization has already been completed.
+pub fn complete_global_init(processors: u32) {
+    ONLINE.init(Barrier::new(processors));
+    GLOBAL_INIT.call_once(|| {});
+}
+
+/// Spins until the bootstrap processor has completed global initialization.
+pub fn wait_for_global_init() {
+    GLOBAL_INIT.wait();
+}
+
+/// Spins until every processor taking part in bring-up has called this function.
+///
+/// Exactly one processor, the last to arrive, is told that it is the leader.
+pub fn wait_for_all_online() -> BarrierWaitResult {
+    wait_for_global_init();
+
+    let online = ONLINE.wait();
+    let result = online.wait();
+
+    #[cfg(feature = "logging")]
+    if result.is_leader() {
+        log::info!("{} processor(s) online", online.count());
+    }
+
+    result
+}
@@ -1,6 +1,6 @@
 //! Module controlling interaction with the Global Descriptor Table.
 
-use crate::arch::x86_64::structures::PrivilegeLevel;
+use crate::arch::x86_64::structures::{tss::TaskStateSegment, PrivilegeLevel};
 
 /// Selects a GDT segment to use.
 #[repr(transparent)]
@@ -17,6 +17,11 @@ impl SegmentSelector {
         Self(index << 3 | rpl as u16)
     }
 
+    /// Returns the raw value of this [`SegmentSelector`].
+    pub const fn value(&self) -> u16 {
+        self.0
+    }
+
     /// Returns the index of the segment associated with this [`SegmentSelector`].
     pub const fn index(&self) -> u16 {
         self.0 >> 3
@@ -38,3 +43,236 @@ impl SegmentSelector {
         self.0 = self.0 & 0xFFF8 | level as u16
     }
 }
+
+/// The [`SegmentSelector`] of the kernel code segment.
+pub const KERNEL_CODE_SELECTOR: SegmentSelector = SegmentSelector::new(1, PrivilegeLevel::Ring0);
+/// The [`SegmentSelector`] of the kernel data segment.
+pub const KERNEL_DATA_SELECTOR: SegmentSelector = SegmentSelector::new(2, PrivilegeLevel::Ring0);
+/// The [`SegmentSelector`] of the user data segment.
+pub const USER_DATA_SELECTOR: SegmentSelector = SegmentSelector::new(3, PrivilegeLevel::Ring3);
+/// The [`SegmentSelector`] of the user code segment.
+pub const USER_CODE_SELECTOR: SegmentSelector = SegmentSelector::new(4, PrivilegeLevel::Ring3);
+/// The [`SegmentSelector`] of the task state segment.
+pub const TSS_SELECTOR: SegmentSelector = SegmentSelector::new(5, PrivilegeLevel::Ring0);
+
+/// The number of descriptor slots in a [`GlobalDescriptorTable`]. The task state segment
+/// descriptor occupies two.
+const DESCRIPTOR_COUNT: usize = 7;
+
+/// The table of segment descriptors used by the kernel.
+///
+/// The layout is fixed by the requirements of `syscall` and `sysret`: the kernel data segment
+/// directly follows the kernel code segment, and the user data segment directly precedes the user
+/// code segment.
+///
+/// | Index | Segment     | Selector |
+/// |-------|-------------|----------|
+/// | 0     | Null        | `0x00`   |
+/// | 1     | Kernel code | `0x08`   |
+/// | 2     | Kernel data | `0x10`   |
+/// | 3     | User data   | `0x1b`   |
+/// | 4     | User code   | `0x23`   |
+/// | 5, 6  | Task state  | `0x28`   |
+#[repr(C, align(8))]
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub struct GlobalDescriptorTable {
+    /// The segment descriptors.
+    descriptors: [u64; DESCRIPTOR_COUNT],
+}
+
+impl GlobalDescriptorTable {
+    /// The bit of a segment descriptor that is set once the segment has been accessed. It is set
+    /// in advance so that the processor does not write to the code and data descriptors.
+    const ACCESSED: u64 = 1 << 40;
+    /// The bits of a segment descriptor that mark a present, writable data segment.
+    const DATA: u64 = Self::ACCESSED | (1 << 41) | (1 << 44) | (1 << 47);
+    /// The bits of a segment descriptor that mark a present, readable, 64-bit code segment.
+    const CODE: u64 = Self::DATA | (1 << 43) | (1 << 53);
+    /// The bits of a segment descriptor that place it at [`PrivilegeLevel::Ring3`].
+    const USER: u64 = 3 << 45;
+    /// The bits of a system segment descriptor that mark a present, available 64-bit task state
+    /// segment.
+    const TSS: u64 = (0x9 << 40) | (1 << 47);
+
+    /// Creates the [`GlobalDescriptorTable`] used by the kernel, whose task state segment is
+    /// `tss`.
+    pub fn new(tss: &'static TaskStateSegment) -> Self {
+        let base = tss as *const TaskStateSegment as u64;
+        let limit = (core::mem::size_of::<TaskStateSegment>() - 1) as u64;
+
+        let tss_low = (limit & 0xFFFF)
+            | ((base & 0xFF_FFFF) << 16)
+            | Self::TSS
+            | (((limit >> 16) & 0xF) << 48)
+            | (((base >> 24) & 0xFF) << 56);
+        let tss_high = base >> 32;
+
+        Self {
+            descriptors: [
+                0,
+                Self::CODE,
+                Self::DATA,
+                Self::DATA | Self::USER,
+                Self::CODE | Self::USER,
+                tss_low,
+                tss_high,
+            ],
+        }
+    }
+
+    /// Loads this [`GlobalDescriptorTable`], reloads `cs`, `ss`, `ds`, and `es` with the kernel
+    /// segments, and loads the task register with [`TSS_SELECTOR`].
+    ///
+    /// `fs` and `gs` are left untouched, since loading them would clear their base addresses.
+    /// Loading the task register marks the task state segment descriptor as busy, which the
+    /// processor writes to the table.
+    ///
+    /// # Safety
+    /// Interrupt descriptors must select [`KERNEL_CODE_SELECTOR`] as their code segment, no code
+    /// may rely on the segments of the previous table, and the task state segment must not be in
+    /// use by any processor.
+    pub unsafe fn load(&'static self) {
+        #[repr(C, packed)]
+        struct Gdtr {
+            limit: u16,
+            base: u64,
+        }
+
+        let gdtr = Gdtr {
+            limit: (core::mem::size_of::<Self>() - 1) as u16,
+            base: self as *const Self as u64,
+        };
+
+        // SAFETY:
+        // The table lives forever and contains valid kernel segments and a valid task state
+        // segment, which are loaded immediately, and the caller guarantees that nothing relies on
+        // the previous table and that the task state segment is not in use.
+        unsafe {
+            core::arch::asm!(
+                "lgdt [{gdtr}]",
+                "push {code}",
+                "lea {tmp}, [rip + 2f]",
+                "push {tmp}",
+                "retfq",
+                "2:",
+                "mov {tmp:e}, {data}",
+                "mov ss, {tmp:e}",
+                "mov ds, {tmp:e}",
+                "mov es, {tmp:e}",
+                "mov {tmp:e}, {tss}",
+                "ltr {tmp:x}",
+                gdtr = in(reg) &gdtr,
+                code = const KERNEL_CODE_SELECTOR.0,
+                data = const KERNEL_DATA_SELECTOR.0,
+                tss = const TSS_SELECTOR.0,
+                tmp = out(reg) _,
+                options(preserves_flags)
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn selectors_encode_index_and_privilege_level() {
+        let selector = SegmentSelector::new(5, PrivilegeLevel::Ring3);
+        assert_eq!(selector.value(), 0x2b);
+        assert_eq!(selector.index(), 5);
+        assert_eq!(
+            selector.privilege_level() as u8,
+            PrivilegeLevel::Ring3 as u8
+        );
+
+        assert_eq!(SegmentSelector::NULL.value(), 0);
+        assert_eq!(
+            SegmentSelector::new(0x1FFF, PrivilegeLevel::Ring2).value(),
+            0xFFFA
+        );
+        for level in [
+            PrivilegeLevel::Ring0,
+            PrivilegeLevel::Ring1,
+            PrivilegeLevel::Ring2,
+            PrivilegeLevel::Ring3,
+        ] {
+            let level = level as u8;
+            let selector = SegmentSelector(0x40 | u16::from(level));
+            assert_eq!(selector.privilege_level() as u8, level);
+            assert_eq!(selector.index(), 8);
+        }
+    }
+
+    #[test]
+    fn setting_the_privilege_level_keeps_the_index() {
+        let mut selector = SegmentSelector::new(0x123, PrivilegeLevel::Ring3);
+        selector.set_privilege_level(PrivilegeLevel::Ring1);
+        assert_eq!(selector.index(), 0x123);
+        assert_eq!(selector.privilege_level() as u8, 1);
+        assert_eq!(selector.value(), 0x123 << 3 | 1);
+    }
+
+    #[test]
+    fn kernel_selectors_match_the_table_layout() {
+        assert_eq!(KERNEL_CODE_SELECTOR.value(), 0x08);
+        assert_eq!(KERNEL_DATA_SELECTOR.value(), 0x10);
+        assert_eq!(USER_DATA_SELECTOR.value(), 0x1b);
+        assert_eq!(USER_CODE_SELECTOR.value(), 0x23);
+        assert_eq!(TSS_SELECTOR.value(), 0x28);
+
+        // `syscall` loads the kernel code segment from a base selector and the data segment from
+        // the next one, and `sysret` loads the user data segment from eight past its base selector
+        // and the user code segment from sixteen past it.
+        assert_eq!(
+            KERNEL_DATA_SELECTOR.index(),
+            KERNEL_CODE_SELECTOR.index() + 1
+        );
+        assert_eq!(USER_CODE_SELECTOR.index(), USER_DATA_SELECTOR.index() + 1);
+    }
+
+    #[test]
+    fn descriptors_encode_code_and_data_segments() {
+        static TSS: TaskStateSegment = TaskStateSegment::new();
+        let table = GlobalDescriptorTable::new(&TSS);
+
+        assert_eq!(table.descriptors[0], 0);
+        assert_eq!(table.descriptors[1], 0x0020_9B00_0000_0000);
+        assert_eq!(table.descriptors[2], 0x0000_9300_0000_0000);
+        assert_eq!(table.descriptors[3], 0x0000_F300_0000_0000);
+        assert_eq!(table.descriptors[4], 0x0020_FB00_0000_0000);
+
+        for (selector, user) in [
+            (KERNEL_CODE_SELECTOR, false),
+            (KERNEL_DATA_SELECTOR, false),
+            (USER_DATA_SELECTOR, true),
+            (USER_CODE_SELECTOR, true),
+        ] {
+            let descriptor = table.descriptors[usize::from(selector.index())];
+            let dpl = (descriptor >> 45) & 0b11;
+            assert_eq!(dpl, selector.privilege_level() as u64);
+            assert_eq!(dpl == 3, user);
+        }
+    }
+
+    #[test]
+    fn descriptors_encode_the_task_state_segment() {
+        static TSS: TaskStateSegment = TaskStateSegment::new();
+        let table = GlobalDescriptorTable::new(&TSS);
+        let base = &raw const TSS as u64;
+        let low = table.descriptors[usize::from(TSS_SELECTOR.index())];
+        let high = table.descriptors[usize::from(TSS_SELECTOR.index()) + 1];
+
+        assert_eq!(low & 0xFFFF, 103, "limit");
+        assert_eq!((low >> 48) & 0xF, 0, "limit");
+        assert_eq!(
+            (low >> 40) & 0xF,
+            0x9,
+            "available 64-bit task state segment"
+        );
+        assert_eq!((low >> 47) & 1, 1, "present");
+        let decoded = ((low >> 16) & 0xFF_FFFF) | ((low >> 56) << 24) | (high << 32);
+        assert_eq!(decoded, base);
+        assert_eq!(high >> 32, 0, "reserved");
+    }
+}
@@ -7,7 +7,10 @@ use core::{
 
 use crate::arch::{
     x86_64::memory::VirtualAddress,
-    x86_64::structures::{gdt::SegmentSelector, PrivilegeLevel},
+    x86_64::structures::{
+        gdt::{SegmentSelector, KERNEL_CODE_SELECTOR},
+        PrivilegeLevel,
+    },
 };
 
 /// Table of [`InterruptDescriptor`]s that describe how an interrupt should be handled.
@@ -180,15 +183,30 @@ impl<F> InterruptDescriptor<F> {
     pub unsafe fn set_options(&mut self, options: InterruptDescriptorOptions) {
         self.options = options;
     }
+
+    /// Returns the [`InterruptDescriptorOptions`], which control the behavior of the interrupt
+    /// and interrupt handler.
+    pub const fn options(&self) -> InterruptDescriptorOptions {
+        self.options
+    }
+
+    /// Sets the stack that the CPU switches to when the interrupt occurs, leaving the other
+    /// options unchanged.
+    ///
+    /// # Safety
+    /// The entry of the interrupt stack table selected by `ist` must point to the top of a stack
+    /// that is only used by this interrupt.
+    pub unsafe fn set_stack(&mut self, ist: IstSetting) {
+        self.options = self.options.with_ist(ist);
+    }
 }
 
 impl<F: HandlerFuncSupport> InterruptDescriptor<F> {
     /// Sets the address of the handler function to the value of `handler.address()`.
     ///
-    /// Also sets the code segment selector to select the segment in index 2 at
-    /// [`PrivilegeLevel::Ring0`] as the code segment and the options to indicate that the
-    /// interrupt handler is present, should disable interrupts, operate on the same stack, and
-    /// handle the interrupt at [`PrivilegeLevel::Ring0`].
+    /// Also sets the code segment selector to [`KERNEL_CODE_SELECTOR`] and the options to
+    /// indicate that the interrupt handler is present, should disable interrupts, operate on the
+    /// same stack, and handle the interrupt at [`PrivilegeLevel::Ring0`].
     pub fn set_handler_fn(&mut self, handler: F) {
         let address = handler.address().value();
 
@@ -202,12 +220,12 @@ impl<F: HandlerFuncSupport> InterruptDescriptor<F> {
             true,
             PrivilegeLevel::Ring0,
         );
-        self.code_segment = SegmentSelector::new(2, PrivilegeLevel::Ring0);
+        self.code_segment = KERNEL_CODE_SELECTOR;
     }
 }
 
 /// Loads the provided [`InterruptDescriptorTable`].
-pub unsafe fn load_idt(table: &'static mut InterruptDescriptorTable) {
+pub unsafe fn load_idt(table: &'static InterruptDescriptorTable) {
     #[repr(C)]
     struct Idtr {
         _unused: MaybeUninit<[u8; 6]>,
@@ -218,7 +236,7 @@ pub unsafe fn load_idt(table: &'static mut InterruptDescriptorTable) {
     let idtr = Idtr {
         _unused: MaybeUninit::uninit(),
         size: (mem::size_of::<InterruptDescriptorTable>() - 1) as u16,
-        address: table as *mut InterruptDescriptorTable as u64,
+        address: table as *const InterruptDescriptorTable as u64,
     };
 
     unsafe {
@@ -257,6 +275,12 @@ impl InterruptDescriptorOptions {
         )
     }
 
+    /// Returns these [`InterruptDescriptorOptions`] with the stack to switch to replaced by
+    /// `ist`.
+    pub const fn with_ist(self, ist: IstSetting) -> Self {
+        Self((self.0 & !0b111) | ist as u16)
+    }
+
     /// Which stack to switch to when this interrupt occurs.
     pub const fn ist(&self) -> IstSetting {
         match self.0 & 0b111 {
@@ -295,6 +319,7 @@ impl InterruptDescriptorOptions {
 }
 
 /// The stack to switch to if when handling the interrupt occurs.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
 pub enum IstSetting {
     /// Don't switch stacks.
     NoSwitch = 0,
@@ -357,3 +382,184 @@ pub struct InterruptStackFrame {
     stack_pointer: VirtualAddress,
     stack_segment: SegmentSelector,
 }
+
+impl InterruptStackFrame {
+    /// Returns the address of the instruction the interrupted code resumes at.
+    pub const fn instruction_pointer(&self) -> VirtualAddress {
+        self.interrupt_pointer
+    }
+
+    /// Returns the code segment of the interrupted code.
+    pub const fn code_segment(&self) -> SegmentSelector {
+        self.code_segment
+    }
+
+    /// Returns the stack pointer of the interrupted code.
+    pub const fn stack_pointer(&self) -> VirtualAddress {
+        self.stack_pointer
+    }
+
+    /// Returns `true` if the interrupted code was running in user mode.
+    pub const fn interrupted_user_mode(&self) -> bool {
+        matches!(self.code_segment.privilege_level(), PrivilegeLevel::Ring3)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arch::x86_64::structures::gdt::USER_CODE_SELECTOR;
+
+    /// Every [`IstSetting`], in order of their index.
+    const IST_SETTINGS: [IstSetting; 8] = [
+        IstSetting::NoSwitch,
+        IstSetting::Ist1,
+        IstSetting::Ist2,
+        IstSetting::Ist3,
+        IstSetting::Ist4,
+        IstSetting::Ist5,
+        IstSetting::Ist6,
+        IstSetting::Ist7,
+    ];
+
+    #[test]
+    fn table_layout_matches_the_vectors() {
+        assert_eq!(mem::size_of::<InterruptDescriptor<HandlerFunc>>(), 16);
+        assert_eq!(mem::size_of::<InterruptDescriptorTable>(), 256 * 16);
+        assert_eq!(mem::align_of::<InterruptDescriptorTable>(), 4096);
+
+        let offset = |vector: usize| vector * 16;
+        assert_eq!(
+            mem::offset_of!(InterruptDescriptorTable, divide_error),
+            offset(0)
+        );
+        assert_eq!(
+            mem::offset_of!(InterruptDescriptorTable, breakpoint),
+            offset(3)
+        );
+        assert_eq!(
+            mem::offset_of!(InterruptDescriptorTable, invalid_opcode),
+            offset(6)
+        );
+        assert_eq!(
+            mem::offset_of!(InterruptDescriptorTable, double_fault),
+            offset(8)
+        );
+        assert_eq!(
+            mem::offset_of!(InterruptDescriptorTable, general_protection_fault),
+            offset(13)
+        );
+        assert_eq!(
+            mem::offset_of!(InterruptDescriptorTable, page_fault),
+            offset(14)
+        );
+        assert_eq!(
+            mem::offset_of!(InterruptDescriptorTable, machine_check),
+            offset(18)
+        );
+        assert_eq!(
+            mem::offset_of!(InterruptDescriptorTable, cp_protection_exception),
+            offset(21)
+        );
+        assert_eq!(
+            mem::offset_of!(InterruptDescriptorTable, general_interrupts),
+            offset(32)
+        );
+    }
+
+    #[test]
+    fn options_encode_their_fields() {
+        let options =
+            InterruptDescriptorOptions::new(true, IstSetting::Ist3, false, PrivilegeLevel::Ring3);
+        assert_eq!(options.0, 0b1110_1110_0000_0011);
+        assert!(options.present());
+        assert_eq!(options.ist(), IstSetting::Ist3);
+        assert_eq!(options.privilege_level() as u8, 3);
+
+        let missing = InterruptDescriptorOptions::MISSING;
+        assert!(!missing.present());
+        assert_eq!(missing.ist(), IstSetting::NoSwitch);
+        assert_eq!(missing.privilege_level() as u8, 0);
+        // The reserved type bits are always set.
+        assert_eq!(missing.0 & (0b111 << 9), 0b111 << 9);
+    }
+
+    #[test]
+    fn options_round_trip_every_stack_and_privilege_level() {
+        for ist in IST_SETTINGS {
+            for level in 0..4u8 {
+                let privilege_level = match level {
+                    0 => PrivilegeLevel::Ring0,
+                    1 => PrivilegeLevel::Ring1,
+                    2 => PrivilegeLevel::Ring2,
+                    _ => PrivilegeLevel::Ring3,
+                };
+                let options = InterruptDescriptorOptions::new(true, ist, true, privilege_level);
+                assert_eq!(options.ist(), ist);
+                assert_eq!(options.privilege_level() as u8, level);
+                assert!(options.present());
+            }
+        }
+    }
+
+    #[test]
+    fn with_ist_only_replaces_the_stack() {
+        let options =
+            InterruptDescriptorOptions::new(true, IstSetting::Ist7, true, PrivilegeLevel::Ring3);
+        for ist in IST_SETTINGS {
+            let replaced = options.with_ist(ist);
+            assert_eq!(replaced.ist(), ist);
+            assert_eq!(replaced.0 & !0b111, options.0 & !0b111);
+        }
+    }
+
+    #[test]
+    fn descriptors_split_the_handler_address() {
+        let address = VirtualAddress::new_canonical(0xFFFF_8123_4567_89AB);
+        // SAFETY:
+        // The descriptor is only inspected, never loaded.
+        let descriptor = unsafe {
+            InterruptDescriptor::<HandlerFunc>::new(
+                address,
+                KERNEL_CODE_SELECTOR,
+                InterruptDescriptorOptions::MISSING,
+            )
+        };
+
+        assert_eq!(descriptor.low_func_ptr, 0x89AB);
+        assert_eq!(descriptor.mid_func_ptr, 0x4567);
+        assert_eq!(descriptor.high_func_ptr, 0xFFFF_8123);
+        assert_eq!(descriptor._reserved, 0);
+        assert_eq!(descriptor.func_ptr(), address);
+        assert_eq!(descriptor.code_segment, KERNEL_CODE_SELECTOR);
+    }
+
+    #[test]
+    fn setting_the_stack_keeps_the_handler() {
+        let mut descriptor = InterruptDescriptor::<HandlerFuncErrorCode>::MISSING;
+        // SAFETY:
+        // The descriptor is only inspected, never loaded.
+        unsafe { descriptor.set_stack(IstSetting::Ist2) };
+        assert_eq!(descriptor.options().ist(), IstSetting::Ist2);
+        assert!(!descriptor.options().present());
+        assert_eq!(descriptor.func_ptr(), VirtualAddress::zero());
+
+        // SAFETY:
+        // Same as above.
+        unsafe { descriptor.set_code_segment(USER_CODE_SELECTOR) };
+        assert_eq!(descriptor.code_segment, USER_CODE_SELECTOR);
+    }
+
+    #[test]
+    fn new_tables_are_missing_every_handler() {
+        let table = InterruptDescriptorTable::new();
+        let missing = InterruptDescriptor::<HandlerFunc>::MISSING;
+        assert!(table.divide_error == missing);
+        assert!(table
+            .general_interrupts
+            .iter()
+            .all(|entry| *entry == missing));
+        assert!(!table.page_fault.options().present());
+        assert!(!table.double_fault.options().present());
+    }
+}
@@ -2,6 +2,7 @@
 
 pub mod gdt;
 pub mod idt;
+pub mod tss;
 
 /// The privilege level associated with an item.
 pub enum PrivilegeLevel {
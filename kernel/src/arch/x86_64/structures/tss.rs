@@ -0,0 +1,122 @@
+//! Module controlling the Task State Segment.
+
+use core::mem;
+
+use crate::arch::x86_64::{memory::VirtualAddress, structures::idt::IstSetting};
+
+/// The 64-bit Task State Segment, which holds the stacks the processor switches to when an
+/// interrupt raises the privilege level or selects an interrupt stack table entry.
+#[repr(C, packed(4))]
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub struct TaskStateSegment {
+    _reserved_1: u32,
+    /// The stacks loaded when entering rings 0 through 2 from a less privileged ring.
+    privilege_stacks: [u64; 3],
+    _reserved_2: u64,
+    /// The stacks selected by the interrupt stack table index of an interrupt descriptor.
+    interrupt_stacks: [u64; 7],
+    _reserved_3: u64,
+    _reserved_4: u16,
+    /// The offset of the I/O permission bitmap from the start of the segment.
+    io_map_base: u16,
+}
+
+const _: () = assert!(mem::size_of::<TaskStateSegment>() == 104);
+
+impl TaskStateSegment {
+    /// Creates a [`TaskStateSegment`] with no stacks and no I/O permission bitmap.
+    pub const fn new() -> Self {
+        Self {
+            _reserved_1: 0,
+            privilege_stacks: [0; 3],
+            _reserved_2: 0,
+            interrupt_stacks: [0; 7],
+            _reserved_3: 0,
+            _reserved_4: 0,
+            io_map_base: mem::size_of::<Self>() as u16,
+        }
+    }
+
+    /// Sets the stack loaded when an interrupt enters ring 0 from user mode.
+    pub fn set_kernel_stack(&mut self, stack_top: VirtualAddress) {
+        self.privilege_stacks[0] = stack_top.value() as u64;
+    }
+
+    /// Returns the stack loaded when an interrupt enters ring 0 from user mode.
+    pub fn kernel_stack(&self) -> VirtualAddress {
+        let privilege_stacks = self.privilege_stacks;
+        VirtualAddress::new_canonical(privilege_stacks[0] as usize)
+    }
+
+    /// Sets the stack loaded when an interrupt whose descriptor selects `ist` arrives.
+    ///
+    /// # Panics
+    /// Panics if `ist` is [`IstSetting::NoSwitch`].
+    pub fn set_interrupt_stack(&mut self, ist: IstSetting, stack_top: VirtualAddress) {
+        let index = (ist as usize)
+            .checked_sub(1)
+            .expect("no interrupt stack table entry selected");
+        self.interrupt_stacks[index] = stack_top.value() as u64;
+    }
+
+    /// Returns the stack loaded when an interrupt whose descriptor selects `ist` arrives, or
+    /// [`None`] if `ist` is [`IstSetting::NoSwitch`].
+    pub fn interrupt_stack(&self, ist: IstSetting) -> Option<VirtualAddress> {
+        let interrupt_stacks = self.interrupt_stacks;
+        let index = (ist as usize).checked_sub(1)?;
+        Some(VirtualAddress::new_canonical(
+            interrupt_stacks[index] as usize,
+        ))
+    }
+}
+
+impl Default for TaskStateSegment {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn layout_matches_the_architecture() {
+        assert_eq!(mem::offset_of!(TaskStateSegment, privilege_stacks), 0x04);
+        assert_eq!(mem::offset_of!(TaskStateSegment, interrupt_stacks), 0x24);
+        assert_eq!(mem::offset_of!(TaskStateSegment, io_map_base), 0x66);
+        assert_eq!({ TaskStateSegment::new().io_map_base }, 104);
+    }
+
+    #[test]
+    fn stacks_are_stored_in_their_slots() {
+        let mut tss = TaskStateSegment::new();
+        let kernel = VirtualAddress::new_canonical(0xFFFF_8000_0001_0000);
+        tss.set_kernel_stack(kernel);
+        assert_eq!(tss.kernel_stack(), kernel);
+
+        tss.set_interrupt_stack(IstSetting::Ist1, VirtualAddress::new_canonical(0x1000));
+        tss.set_interrupt_stack(IstSetting::Ist7, VirtualAddress::new_canonical(0x7000));
+        assert_eq!(
+            tss.interrupt_stack(IstSetting::Ist1),
+            Some(VirtualAddress::new_canonical(0x1000))
+        );
+        assert_eq!(
+            tss.interrupt_stack(IstSetting::Ist7),
+            Some(VirtualAddress::new_canonical(0x7000))
+        );
+        assert_eq!(
+            tss.interrupt_stack(IstSetting::Ist2),
+            Some(VirtualAddress::zero())
+        );
+        assert_eq!(tss.interrupt_stack(IstSetting::NoSwitch), None);
+        assert_eq!({ tss.interrupt_stacks }, [0x1000, 0, 0, 0, 0, 0, 0x7000]);
+        assert_eq!({ tss.privilege_stacks }[0], kernel.value() as u64);
+    }
+
+    #[test]
+    #[should_panic = "no interrupt stack table entry selected"]
+    fn no_switch_has_no_interrupt_stack() {
+        TaskStateSegment::new().set_interrupt_stack(IstSetting::NoSwitch, VirtualAddress::zero());
+    }
+}
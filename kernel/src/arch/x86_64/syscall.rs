@@ -0,0 +1,497 @@
+//! System call entry through `syscall` and `sysret`.
+//!
+//! `syscall` enters the kernel at `syscall_entry` with the user `rip` in `rcx` and `rflags` in
+//! `r11`, and leaves the stack pointer untouched. The entry stub swaps to the kernel GS base,
+//! saves the user stack pointer in the current processor's per-CPU scratch space, switches to the
+//! processor's kernel stack, and pushes a [`SyscallFrame`], which it hands to
+//! [`syscall_dispatch()`]. The result is written back to the frame, from which the user registers
+//! are restored before returning with `sysretq`.
+//!
+//! `sysretq` raises a general protection fault in kernel mode, on the user stack, if the return
+//! address is not canonical, which happens when the `syscall` instruction ends the lower half.
+//! Such a task cannot be returned to, so it is ended as if it had faulted instead.
+//!
+//! # Calling convention
+//!
+//! The system call number is passed in `rax` and up to six arguments in `rdi`, `rsi`, `rdx`,
+//! `r10`, `r8`, and `r9`. The result is returned in `rax`: non-negative on success, or the
+//! negative [`SyscallError::code()`] on failure. `syscall` itself clobbers `rcx` and `r11`; every
+//! other register is preserved.
+
+use core::{cell::UnsafeCell, fmt, mem, ptr};
+
+use crate::arch::x86_64::{
+    backtrace::{self, StackBounds},
+    cpu,
+    memory::VirtualAddress,
+    per_cpu,
+    structures::gdt::{
+        KERNEL_CODE_SELECTOR, KERNEL_DATA_SELECTOR, USER_CODE_SELECTOR, USER_DATA_SELECTOR,
+    },
+    usermode::{self, UserFault},
+};
+
+/// The model specific register holding the extended feature enables.
+const IA32_EFER: u32 = 0xC000_0080;
+/// The bit of [`IA32_EFER`] that enables `syscall` and `sysret`.
+const EFER_SYSCALL_ENABLE: u64 = 1 << 0;
+/// The model specific register holding the segment selector bases used by `syscall` and
+/// `sysret`.
+const IA32_STAR: u32 = 0xC000_0081;
+/// The model specific register holding the address `syscall` jumps to.
+const IA32_LSTAR: u32 = 0xC000_0082;
+/// The model specific register holding the `rflags` bits cleared by `syscall`.
+const IA32_FMASK: u32 = 0xC000_0084;
+/// The model specific register holding the GS base swapped in by `swapgs`.
+const IA32_KERNEL_GS_BASE: u32 = 0xC000_0102;
+
+/// The `rflags` bits cleared on entry: the trap flag, so that single-stepping does not continue
+/// into the kernel, the interrupt enable flag, so that nothing interrupts the stub while it runs
+/// on the user stack, the direction flag, which the ABI requires to be clear, and the alignment
+/// check flag, which would otherwise permit the kernel to access user memory under SMAP.
+const SYSCALL_FLAG_MASK: u64 = (1 << 8) | (1 << 9) | (1 << 10) | (1 << 18);
+
+// `syscall` loads SS with the selector after the kernel code segment, and `sysret` loads CS and SS
+// with the selectors 16 and 8 bytes after the user base, so the GDT must place them accordingly.
+const _: () = assert!(KERNEL_DATA_SELECTOR.index() == KERNEL_CODE_SELECTOR.index() + 1);
+const _: () = assert!(USER_CODE_SELECTOR.index() == USER_DATA_SELECTOR.index() + 1);
+
+/// The selector base from which `sysret` derives the user code and stack segments.
+const USER_SELECTOR_BASE: u16 = USER_DATA_SELECTOR.value() - 8;
+
+/// The number of argument registers passed to a [`SyscallHandler`].
+pub const ARGUMENT_COUNT: usize = 6;
+
+/// The size of the kernel stack used for system calls on the bootstrap processor.
+pub const BOOTSTRAP_STACK_SIZE: usize = 16 * 1024;
+
+/// The kernel stack used for system calls on the bootstrap processor.
+#[repr(C, align(16))]
+struct BootstrapStack(UnsafeCell<[u8; BOOTSTRAP_STACK_SIZE]>);
+
+// SAFETY:
+// The stack is only ever used by the entry stub on the bootstrap processor.
+unsafe impl Sync for BootstrapStack {}
+
+/// The kernel stack used for system calls on the bootstrap processor.
+static BOOTSTRAP_STACK: BootstrapStack = BootstrapStack(UnsafeCell::new([0; BOOTSTRAP_STACK_SIZE]));
+
+/// Scratch space used by the entry stub, located at the start of every per-CPU area so that the
+/// stub can address it relative to GS before it has any free registers.
+#[repr(C)]
+struct EntryScratch {
+    /// The user stack pointer, saved on entry.
+    user_rsp: u64,
+    /// The top of the kernel stack switched to on entry.
+    kernel_rsp: u64,
+}
+
+/// The template of each processor's [`EntryScratch`].
+///
+/// Only the per-CPU copies are written, through GS, so the template keeps its initial value.
+#[used]
+#[link_section = ".percpu.syscall"]
+static ENTRY_SCRATCH: EntryScratch = EntryScratch {
+    user_rsp: 0,
+    kernel_rsp: 0,
+};
+
+/// The offset of [`EntryScratch::user_rsp`] from the GS base.
+const USER_RSP_OFFSET: usize = mem::offset_of!(EntryScratch, user_rsp);
+/// The offset of [`EntryScratch::kernel_rsp`] from the GS base.
+const KERNEL_RSP_OFFSET: usize = mem::offset_of!(EntryScratch, kernel_rsp);
+
+/// The user registers saved by the entry stub, in the order they lie on the kernel stack.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, Hash, PartialEq, Eq)]
+pub struct SyscallFrame {
+    /// The user `r15`.
+    pub r15: u64,
+    /// The user `r14`.
+    pub r14: u64,
+    /// The user `r13`.
+    pub r13: u64,
+    /// The user `r12`.
+    pub r12: u64,
+    /// The user `rbp`.
+    pub rbp: u64,
+    /// The user `rbx`.
+    pub rbx: u64,
+    /// The sixth argument.
+    pub r9: u64,
+    /// The fifth argument.
+    pub r8: u64,
+    /// The fourth argument.
+    pub r10: u64,
+    /// The third argument.
+    pub rdx: u64,
+    /// The second argument.
+    pub rsi: u64,
+    /// The first argument.
+    pub rdi: u64,
+    /// The system call number on entry, and the result on return.
+    pub rax: u64,
+    /// The user instruction pointer, saved by `syscall` in `rcx`.
+    pub rip: u64,
+    /// The user flags, saved by `syscall` in `r11`.
+    pub rflags: u64,
+    /// The user stack pointer.
+    pub rsp: u64,
+}
+
+// The entry stub pushes the registers in the reverse order of the fields and passes the final
+// stack pointer as the frame, so every offset below is fixed by the stub.
+const _: () = {
+    assert!(mem::size_of::<SyscallFrame>() == 16 * 8);
+    assert!(mem::size_of::<SyscallFrame>().is_multiple_of(16));
+    assert!(mem::offset_of!(SyscallFrame, r15) == 0);
+    assert!(mem::offset_of!(SyscallFrame, rbx) == 5 * 8);
+    assert!(mem::offset_of!(SyscallFrame, r9) == 6 * 8);
+    assert!(mem::offset_of!(SyscallFrame, r10) == 8 * 8);
+    assert!(mem::offset_of!(SyscallFrame, rdi) == 11 * 8);
+    assert!(mem::offset_of!(SyscallFrame, rax) == 12 * 8);
+    assert!(mem::offset_of!(SyscallFrame, rip) == 13 * 8);
+    assert!(mem::offset_of!(SyscallFrame, rflags) == 14 * 8);
+    assert!(mem::offset_of!(SyscallFrame, rsp) == 15 * 8);
+    assert!(USER_RSP_OFFSET == 0);
+    assert!(KERNEL_RSP_OFFSET == 8);
+};
+
+impl SyscallFrame {
+    /// Returns the system call number.
+    pub const fn number(&self) -> u64 {
+        self.rax
+    }
+
+    /// Returns the arguments, in the order of the calling convention.
+    pub const fn arguments(&self) -> [u64; ARGUMENT_COUNT] {
+        [self.rdi, self.rsi, self.rdx, self.r10, self.r8, self.r9]
+    }
+}
+
+core::arch::global_asm!(
+    ".pushsection .text.syscall_entry, \"ax\"",
+    ".global syscall_entry",
+    "syscall_entry:",
+    "swapgs",
+    "mov qword ptr gs:[{user_rsp}], rsp",
+    "mov rsp, qword ptr gs:[{kernel_rsp}]",
+    "push qword ptr gs:[{user_rsp}]",
+    "push r11",
+    "push rcx",
+    "push rax",
+    "push rdi",
+    "push rsi",
+    "push rdx",
+    "push r10",
+    "push r8",
+    "push r9",
+    "push rbx",
+    "push rbp",
+    "push r12",
+    "push r13",
+    "push r14",
+    "push r15",
+    "mov rdi, rsp",
+    "call {handler}",
+    "pop r15",
+    "pop r14",
+    "pop r13",
+    "pop r12",
+    "pop rbp",
+    "pop rbx",
+    "pop r9",
+    "pop r8",
+    "pop r10",
+    "pop rdx",
+    "pop rsi",
+    "pop rdi",
+    "pop rax",
+    "pop rcx",
+    "pop r11",
+    "pop rsp",
+    "swapgs",
+    "sysretq",
+    ".popsection",
+    user_rsp = const USER_RSP_OFFSET,
+    kernel_rsp = const KERNEL_RSP_OFFSET,
+    handler = sym handle_syscall,
+);
+
+extern "C" {
+    /// The entry stub that `syscall` jumps to.
+    fn syscall_entry();
+}
+
+/// Dispatches the system call described by `frame`, storing the result in its `rax`.
+///
+/// Ends the user task instead of returning if `sysretq` cannot return to it.
+extern "C" fn handle_syscall(frame: &mut SyscallFrame) {
+    frame.rax = syscall_dispatch(frame.number(), frame.arguments()) as u64;
+
+    if !can_sysret(frame.rip) {
+        let fault = UserFault {
+            vector: usermode::GENERAL_PROTECTION_VECTOR,
+            instruction_pointer: VirtualAddress::new_canonical(
+                frame.rip.wrapping_sub(SYSCALL_INSTRUCTION_LEN) as usize,
+            ),
+            error_code: Some(0),
+            fault_address: None,
+        };
+
+        // SAFETY:
+        // The entry stub swapped in the kernel GS base and `syscall` disabled interrupts. The
+        // frame of this handler lies on the system call stack, which is reset on the next entry.
+        unsafe { usermode::end_task(fault) }
+    }
+}
+
+/// The length of the `syscall` instruction.
+const SYSCALL_INSTRUCTION_LEN: u64 = 2;
+
+/// Returns `true` if `sysretq` can return to `rip`, which must lie in the lower half, since
+/// `sysretq` faults in kernel mode on a non-canonical return address.
+pub const fn can_sysret(rip: u64) -> bool {
+    rip < USER_ADDRESS_END
+}
+
+/// Enables `syscall` on the bootstrap processor, using a statically allocated kernel stack, which
+/// is registered for backtraces.
+///
+/// # Panics
+/// Panics if the per-CPU entry scratch space is not at the start of the per-CPU template.
+pub fn init_bootstrap() {
+    let stack_bottom = BOOTSTRAP_STACK.0.get() as usize;
+    let stack_top = stack_bottom + BOOTSTRAP_STACK_SIZE;
+    backtrace::register_stack(StackBounds {
+        start: stack_bottom,
+        end: stack_top,
+    });
+
+    // SAFETY:
+    // The bootstrap stack is 16 byte aligned, lives forever, and is only used by the entry stub on
+    // the bootstrap processor, which is the only processor running this early.
+    unsafe { init(VirtualAddress::new_canonical(stack_top)) }
+}
+
+/// Enables `syscall` on the current processor, entering the kernel on the stack that ends at
+/// `kernel_stack_top`.
+///
+/// # Panics
+/// Panics if the per-CPU entry scratch space is not at the start of the per-CPU template.
+///
+/// # Safety
+/// - The per-CPU area of the current processor must be initialized and the kernel
+///   [`GlobalDescriptorTable`][crate::arch::x86_64::structures::gdt::GlobalDescriptorTable]
+///   loaded.
+/// - `kernel_stack_top` must be the 16 byte aligned end of a stack that lives forever and is used
+///   by nothing but system calls on the current processor.
+pub unsafe fn init(kernel_stack_top: VirtualAddress) {
+    assert_eq!(
+        per_cpu::template_offset(ptr::addr_of!(ENTRY_SCRATCH)),
+        Some(0),
+        "syscall entry scratch space is not at the start of the per-CPU area"
+    );
+
+    // SAFETY:
+    // The invariants are upheld by the caller.
+    unsafe { set_kernel_stack(kernel_stack_top) };
+
+    // SAFETY:
+    // `IA32_EFER` exists on every `x86_64` processor, and reading it has no side effects.
+    let efer = unsafe { cpu::read_msr(IA32_EFER) };
+    // SAFETY:
+    // Enabling `syscall` only adds an entry point, which is configured below before any user code
+    // can run.
+    unsafe { cpu::write_msr(IA32_EFER, efer | EFER_SYSCALL_ENABLE) };
+    // SAFETY:
+    // The selectors match the layout of the kernel GDT, as asserted above.
+    unsafe {
+        cpu::write_msr(
+            IA32_STAR,
+            (u64::from(USER_SELECTOR_BASE) << 48) | (u64::from(KERNEL_CODE_SELECTOR.value()) << 32),
+        )
+    };
+    // SAFETY:
+    // `syscall_entry` is the entry stub defined above.
+    unsafe { cpu::write_msr(IA32_LSTAR, syscall_entry as *const () as u64) };
+    // SAFETY:
+    // Clearing these flags on entry only restricts what the entry stub can observe.
+    unsafe { cpu::write_msr(IA32_FMASK, SYSCALL_FLAG_MASK) };
+    // SAFETY:
+    // User code starts with a GS base of zero, which `swapgs` exchanges with the kernel GS base on
+    // every entry and exit.
+    unsafe { cpu::write_msr(IA32_KERNEL_GS_BASE, 0) };
+}
+
+/// Sets the stack that the entry stub switches to on the current processor.
+///
+/// # Safety
+/// - The per-CPU area of the current processor must be initialized.
+/// - `kernel_stack_top` must be the 16 byte aligned end of a stack that lives for as long as it
+///   is used by system calls on the current processor, and is used by nothing else meanwhile.
+pub unsafe fn set_kernel_stack(kernel_stack_top: VirtualAddress) {
+    // SAFETY:
+    // The entry scratch space is at the start of the current processor's per-CPU area, which the
+    // caller guarantees is initialized.
+    unsafe {
+        core::arch::asm!(
+            "mov qword ptr gs:[{offset}], {top}",
+            offset = const KERNEL_RSP_OFFSET,
+            top = in(reg) kernel_stack_top.value(),
+            options(nostack, preserves_flags)
+        )
+    };
+}
+
+/// A system call handler, called with the argument registers.
+pub type SyscallHandler = fn(&[u64; ARGUMENT_COUNT]) -> Result<usize, SyscallError>;
+
+/// The number of the system call that writes a string to the kernel log.
+pub const SYS_DEBUG_WRITE: u64 = 0;
+/// The number of the system call that yields the processor.
+pub const SYS_YIELD: u64 = 1;
+
+/// The system call handlers, indexed by system call number.
+pub static SYSCALL_TABLE: [SyscallHandler; 2] = [debug_write, yield_now];
+
+/// Calls the handler for system call `number` in [`SYSCALL_TABLE`] with `arguments`, returning
+/// the value passed back to user code.
+pub fn syscall_dispatch(number: u64, arguments: [u64; ARGUMENT_COUNT]) -> isize {
+    dispatch(&SYSCALL_TABLE, number, arguments)
+}
+
+/// Calls the handler for system call `number` in `table` with `arguments`, returning the value
+/// passed back to user code.
+///
+/// Results that do not fit in an [`isize`] are saturated to [`isize::MAX`].
+pub fn dispatch(table: &[SyscallHandler], number: u64, arguments: [u64; ARGUMENT_COUNT]) -> isize {
+    let Some(handler) = usize::try_from(number)
+        .ok()
+        .and_then(|number| table.get(number))
+    else {
+        return SyscallError::InvalidSyscall.code();
+    };
+
+    match handler(&arguments) {
+        Ok(value) => isize::try_from(value).unwrap_or(isize::MAX),
+        Err(error) => error.code(),
+    }
+}
+
+/// The end of the lower half of the address space, which user code occupies.
+const USER_ADDRESS_END: u64 = 0x0000_8000_0000_0000;
+/// The maximum number of bytes written by a single [`SYS_DEBUG_WRITE`].
+pub const DEBUG_WRITE_MAX_LEN: usize = 256;
+
+/// Writes the UTF-8 string of `arguments[1]` bytes at user address `arguments[0]` to the kernel
+/// log, returning the number of bytes written.
+fn debug_write(arguments: &[u64; ARGUMENT_COUNT]) -> Result<usize, SyscallError> {
+    let (address, len) = (arguments[0], arguments[1]);
+    let len = usize::try_from(len)
+        .ok()
+        .filter(|&len| len <= DEBUG_WRITE_MAX_LEN)
+        .ok_or(SyscallError::InvalidArgument)?;
+    if len == 0 {
+        return Ok(0);
+    }
+    if address == 0 || address.saturating_add(len as u64) > USER_ADDRESS_END {
+        return Err(SyscallError::BadAddress);
+    }
+
+    let mut buffer = [0u8; DEBUG_WRITE_MAX_LEN];
+    // SAFETY:
+    // The buffer lies entirely in the user half of the address space and is copied before it is
+    // inspected, so user code cannot change it afterwards. User mappings are not checked yet, so
+    // an unmapped buffer faults in the kernel.
+    unsafe { ptr::copy_nonoverlapping(address as *const u8, buffer.as_mut_ptr(), len) };
+    let _text = core::str::from_utf8(&buffer[..len]).map_err(|_| SyscallError::InvalidArgument)?;
+
+    #[cfg(feature = "logging")]
+    log::info!(target: "user", "{_text}");
+
+    Ok(len)
+}
+
+/// Yields the processor to another task.
+///
+/// There is nothing else to run yet, so this returns immediately.
+fn yield_now(_arguments: &[u64; ARGUMENT_COUNT]) -> Result<usize, SyscallError> {
+    Ok(0)
+}
+
+/// Various errors that a system call can return.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum SyscallError {
+    /// The system call number does not name a system call.
+    InvalidSyscall,
+    /// An argument is not valid for the system call.
+    InvalidArgument,
+    /// A buffer does not lie in user memory.
+    BadAddress,
+}
+
+impl SyscallError {
+    /// Returns the negative value passed back to user code for this [`SyscallError`].
+    pub const fn code(self) -> isize {
+        match self {
+            Self::InvalidSyscall => -1,
+            Self::InvalidArgument => -2,
+            Self::BadAddress => -3,
+        }
+    }
+}
+
+impl fmt::Display for SyscallError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidSyscall => f.write_str("invalid system call number"),
+            Self::InvalidArgument => f.write_str("invalid system call argument"),
+            Self::BadAddress => f.write_str("buffer does not lie in user memory"),
+        }
+    }
+}
+
+#[cfg(feature = "ktest")]
+crate::kernel_test! {
+    fn syscall_msrs_are_programmed() {
+        // SAFETY:
+        // `IA32_LSTAR` exists on every `x86_64` processor, and reading it has no side effects.
+        let lstar = unsafe { cpu::read_msr(IA32_LSTAR) };
+        assert_eq!(lstar, syscall_entry as *const () as u64);
+        // SAFETY:
+        // `IA32_EFER` exists on every `x86_64` processor, and reading it has no side effects.
+        let efer = unsafe { cpu::read_msr(IA32_EFER) };
+        assert_eq!(efer & EFER_SYSCALL_ENABLE, EFER_SYSCALL_ENABLE);
+    }
+
+    fn syscall_dispatch_reports_errors() {
+        let no_arguments = [0; ARGUMENT_COUNT];
+        assert_eq!(
+            syscall_dispatch(SYSCALL_TABLE.len() as u64, no_arguments),
+            SyscallError::InvalidSyscall.code()
+        );
+        assert_eq!(
+            syscall_dispatch(u64::MAX, no_arguments),
+            SyscallError::InvalidSyscall.code()
+        );
+        assert_eq!(syscall_dispatch(SYS_YIELD, no_arguments), 0);
+
+        assert_eq!(
+            syscall_dispatch(SYS_DEBUG_WRITE, [USER_ADDRESS_END, 16, 0, 0, 0, 0]),
+            SyscallError::BadAddress.code()
+        );
+        assert_eq!(
+            syscall_dispatch(SYS_DEBUG_WRITE, [0x1000, DEBUG_WRITE_MAX_LEN as u64 + 1, 0, 0, 0, 0]),
+            SyscallError::InvalidArgument.code()
+        );
+    }
+
+    fn sysret_requires_a_lower_half_return_address() {
+        assert!(can_sysret(0));
+        assert!(can_sysret(USER_ADDRESS_END - 1));
+        assert!(!can_sysret(USER_ADDRESS_END));
+        assert!(!can_sysret(0xFFFF_8000_0000_0000));
+        assert!(!can_sysret(u64::MAX));
+    }
+}
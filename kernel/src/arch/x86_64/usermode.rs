@@ -0,0 +1,452 @@
+//! Running code in user mode and fielding the exceptions it raises.
+//!
+//! [`run()`] enters user mode from a kernel thread with `iretq` and returns once the user code
+//! raises an exception. The kernel thread's [`Context`] is saved on entry, and the exception
+//! handlers installed by [`install_handlers()`] switch back to it instead of returning to the
+//! faulting instruction. The exception arrives on the kernel stack of the task state segment, so
+//! the abandoned handler frame is simply discarded. A system call that cannot return to its task
+//! ends it the same way, through [`end_task()`].
+//!
+//! User code runs with a GS base of zero. The exception handlers here swap in the kernel GS base
+//! before touching per-CPU state, while other interrupt handlers see no per-CPU area at all, so
+//! the timer tick does not preempt user code.
+//!
+//! Only one user task can run at a time, on the bootstrap processor.
+
+use core::{
+    cell::UnsafeCell,
+    fmt, ptr,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use crate::arch::x86_64::{
+    context::{switch_context, Context},
+    cpu, interrupt_stats,
+    memory::{stack, VirtualAddress},
+    structures::{
+        gdt::{USER_CODE_SELECTOR, USER_DATA_SELECTOR},
+        idt::{InterruptDescriptorTable, InterruptStackFrame},
+    },
+    TSS,
+};
+
+/// The interrupt vector of the invalid opcode exception.
+pub const INVALID_OPCODE_VECTOR: u8 = 6;
+/// The interrupt vector of the general protection exception.
+pub const GENERAL_PROTECTION_VECTOR: u8 = 13;
+/// The interrupt vector of the page fault exception.
+pub const PAGE_FAULT_VECTOR: u8 = 14;
+
+/// The `rflags` user code starts with: interrupts enabled, plus the bit that is always set.
+const USER_RFLAGS: u64 = (1 << 9) | (1 << 1);
+
+/// Whether a user task is running.
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// The state shared between [`run()`] and the exception handlers.
+static USER_RETURN: UserReturnCell = UserReturnCell(UnsafeCell::new(UserReturn {
+    kernel: Context {
+        rbx: 0,
+        rbp: 0,
+        r12: 0,
+        r13: 0,
+        r14: 0,
+        r15: 0,
+        rsp: 0,
+    },
+    abandoned: Context {
+        rbx: 0,
+        rbp: 0,
+        r12: 0,
+        r13: 0,
+        r14: 0,
+        r15: 0,
+        rsp: 0,
+    },
+    fault: None,
+}));
+
+/// Runs user code starting at `entry` with its stack pointer at `stack_top`, returning the
+/// exception that ended it.
+///
+/// System calls made by the user code are handled as usual and return to it.
+///
+/// # Panics
+/// Panics if another user task is running or the task state segment has not been set up.
+///
+/// # Safety
+/// - `entry` must be mapped user accessible and executable, and `stack_top` must be the 16 byte
+///   aligned end of a user accessible, writable stack.
+/// - Nothing reachable from user mode may be relied upon by the kernel.
+pub unsafe fn run(entry: VirtualAddress, stack_top: VirtualAddress) -> UserFault {
+    let kernel_stack = TSS
+        .get()
+        .expect("task state segment has not been set up")
+        .kernel_stack();
+    assert!(
+        !ACTIVE.swap(true, Ordering::Acquire),
+        "a user task is already running"
+    );
+
+    let user_entry = UserEntry {
+        instruction_pointer: entry.value() as u64,
+        stack_pointer: stack_top.value() as u64,
+    };
+    let fault = cpu::without_interrupts(|| {
+        let state = USER_RETURN.0.get();
+
+        // SAFETY:
+        // The privilege stack is not in use, since no user code is running, and its top is 16
+        // byte aligned. `user_entry` outlives the launch, which reads it before entering user mode.
+        let launcher =
+            unsafe { Context::seed(kernel_stack, launch, ptr::addr_of!(user_entry) as usize) };
+        // SAFETY:
+        // `state` points to a static, and `ACTIVE` gives this call exclusive use of it.
+        let kernel = unsafe { ptr::addr_of_mut!((*state).kernel) };
+        // SAFETY:
+        // `launcher` was seeded above, and interrupts are disabled until user mode is entered.
+        unsafe { switch_context(kernel, &launcher) };
+
+        // SAFETY:
+        // The exception handler that switched back is done with the state.
+        unsafe { (*state).fault.take() }
+    });
+    ACTIVE.store(false, Ordering::Release);
+
+    fault.expect("returned from user mode without an exception")
+}
+
+/// Installs the handlers of the exceptions that end a user task in `idt`.
+///
+/// The same exceptions raised in kernel mode panic.
+pub fn install_handlers(idt: &mut InterruptDescriptorTable) {
+    idt.invalid_opcode.set_handler_fn(invalid_opcode_handler);
+    idt.general_protection_fault
+        .set_handler_fn(general_protection_handler);
+    idt.page_fault.set_handler_fn(page_fault_handler);
+}
+
+/// The first code run on the privilege stack by [`run()`], which enters user mode as described by
+/// the [`UserEntry`] at `user_entry`.
+extern "C" fn launch(user_entry: usize) -> ! {
+    // SAFETY:
+    // `run()` passes a pointer to a `UserEntry` that lives until it returns.
+    let user_entry = unsafe { ptr::read(user_entry as *const UserEntry) };
+
+    // SAFETY:
+    // The caller of `run()` guarantees that the entry point and stack are user accessible. The
+    // GS base is swapped so that user code starts with a GS base of zero, and every general
+    // purpose register is cleared so that no kernel values leak.
+    unsafe {
+        core::arch::asm!(
+            "swapgs",
+            "push {ss}",
+            "push {rsp}",
+            "push {rflags}",
+            "push {cs}",
+            "push {rip}",
+            "xor eax, eax",
+            "xor ebx, ebx",
+            "xor ecx, ecx",
+            "xor edx, edx",
+            "xor esi, esi",
+            "xor edi, edi",
+            "xor ebp, ebp",
+            "xor r8d, r8d",
+            "xor r9d, r9d",
+            "xor r10d, r10d",
+            "xor r11d, r11d",
+            "xor r12d, r12d",
+            "xor r13d, r13d",
+            "xor r14d, r14d",
+            "xor r15d, r15d",
+            "iretq",
+            ss = const USER_DATA_SELECTOR.value(),
+            cs = const USER_CODE_SELECTOR.value(),
+            rflags = const USER_RFLAGS,
+            rsp = in(reg) user_entry.stack_pointer,
+            rip = in(reg) user_entry.instruction_pointer,
+            options(noreturn)
+        )
+    }
+}
+
+extern "x86-interrupt" fn invalid_opcode_handler(frame: InterruptStackFrame) {
+    handle_exception(INVALID_OPCODE_VECTOR, &frame, None)
+}
+
+extern "x86-interrupt" fn general_protection_handler(frame: InterruptStackFrame, code: u64) {
+    handle_exception(GENERAL_PROTECTION_VECTOR, &frame, Some(code))
+}
+
+extern "x86-interrupt" fn page_fault_handler(frame: InterruptStackFrame, code: u64) {
+    handle_exception(PAGE_FAULT_VECTOR, &frame, Some(code))
+}
+
+/// Ends the running user task with the exception described by `vector`, `frame`, and
+/// `error_code`, or panics if the exception was raised in kernel mode.
+fn handle_exception(vector: u8, frame: &InterruptStackFrame, error_code: Option<u64>) -> ! {
+    let fault = UserFault {
+        vector,
+        instruction_pointer: frame.instruction_pointer(),
+        error_code,
+        fault_address: (vector == PAGE_FAULT_VECTOR).then(cpu::read_cr2),
+    };
+
+    if !frame.interrupted_user_mode() {
+        interrupt_stats::record(vector);
+        let overflowed = fault.fault_address.and_then(|address| {
+            stack::overflowed_stack(VirtualAddress::new_canonical(address as usize))
+        });
+        if let Some(bottom) = overflowed {
+            panic!(
+                "{fault} in kernel mode, probably an overflow of the kernel stack at {bottom:?}"
+            );
+        }
+        panic!("{fault} in kernel mode");
+    }
+
+    // SAFETY:
+    // The exception was raised in user mode, so the GS base is the user's and the kernel's is
+    // waiting to be swapped in.
+    unsafe { core::arch::asm!("swapgs", options(nomem, nostack, preserves_flags)) };
+    interrupt_stats::record(vector);
+
+    // SAFETY:
+    // The kernel GS base was just swapped in, interrupts are disabled in the handler, and the
+    // handler runs on the privilege stack, which is reused the next time user mode is entered.
+    unsafe { end_task(fault) }
+}
+
+/// Ends the running user task with `fault`, switching back to the kernel thread that entered user
+/// mode through [`run()`].
+///
+/// # Panics
+/// Panics if no user task is running.
+///
+/// # Safety
+/// - The kernel GS base must be active and interrupts disabled.
+/// - The current stack must not be needed again: the calling frame is abandoned, never resumed.
+pub(crate) unsafe fn end_task(fault: UserFault) -> ! {
+    assert!(ACTIVE.load(Ordering::Acquire), "{fault} with no user task");
+
+    let state = USER_RETURN.0.get();
+    // SAFETY:
+    // `state` points to a static, and `run()` does not access it until it is switched back to.
+    unsafe { (*state).fault = Some(fault) };
+    // SAFETY:
+    // Same as above.
+    let abandoned = unsafe { ptr::addr_of_mut!((*state).abandoned) };
+    // SAFETY:
+    // Same as above.
+    let kernel = unsafe { ptr::addr_of!((*state).kernel) };
+    // SAFETY:
+    // `kernel` was saved by `switch_context()` in `run()`, whose stack is not in use, and the
+    // caller guarantees that interrupts are disabled and that the current frame can be abandoned.
+    unsafe { switch_context(abandoned, kernel) };
+
+    unreachable!("abandoned user task was resumed")
+}
+
+/// The state shared between [`run()`] and the exception handlers.
+struct UserReturn {
+    /// The context of the kernel thread that entered user mode.
+    kernel: Context,
+    /// Where the context of the exception handler that switches back is saved, and forgotten.
+    abandoned: Context,
+    /// The exception that ended the user task.
+    fault: Option<UserFault>,
+}
+
+/// A [`UserReturn`] that can be placed in a static.
+struct UserReturnCell(UnsafeCell<UserReturn>);
+
+// SAFETY:
+// The state is only accessed by the user task's kernel thread and by exception handlers on the
+// same processor, one after the other, as serialized by `ACTIVE`.
+unsafe impl Sync for UserReturnCell {}
+
+/// Where user code starts.
+#[derive(Clone, Copy)]
+struct UserEntry {
+    /// The address of the first instruction.
+    instruction_pointer: u64,
+    /// The initial stack pointer.
+    stack_pointer: u64,
+}
+
+/// An exception that ended a user task.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub struct UserFault {
+    /// The interrupt vector of the exception.
+    pub vector: u8,
+    /// The address of the faulting instruction.
+    pub instruction_pointer: VirtualAddress,
+    /// The error code pushed by the exception, if it pushes one.
+    pub error_code: Option<u64>,
+    /// The address whose access caused a page fault.
+    pub fault_address: Option<u64>,
+}
+
+impl UserFault {
+    /// Returns the name of the exception.
+    pub const fn name(&self) -> &'static str {
+        match self.vector {
+            INVALID_OPCODE_VECTOR => "invalid opcode",
+            GENERAL_PROTECTION_VECTOR => "general protection fault",
+            PAGE_FAULT_VECTOR => "page fault",
+            _ => "exception",
+        }
+    }
+}
+
+impl fmt::Display for UserFault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at {:?}", self.name(), self.instruction_pointer)?;
+        if let Some(error_code) = self.error_code {
+            write!(f, " (error code {error_code:#x})")?;
+        }
+        if let Some(address) = self.fault_address {
+            write!(f, " accessing {address:#x}")?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(feature = "ktest")]
+crate::kernel_test! {
+    fn usermode() {
+        use crate::{
+            arch::{direct_map, Frame},
+            cells::{
+                capability::CapabilitySlot,
+                cnode::root,
+                untyped::ObjectKind,
+            },
+        };
+
+        /// The PML4 entry that maps the test's user pages, which is the last in the lower half.
+        const PML4_INDEX: usize = 255;
+        /// The address the user code is mapped at; the user stack follows it.
+        const USER_BASE: usize = PML4_INDEX << 39;
+        /// Present, writable, and user accessible.
+        const USER_TABLE_FLAGS: u64 = 0b111;
+        /// The number of frames needed: three page tables, the code, and the stack.
+        const FRAME_COUNT: usize = 5;
+
+        /// `lea rdi, [rip + 0x11]; mov esi, 1; xor eax, eax; syscall; push rax; ud2`, followed by
+        /// the byte written through the debug write system call.
+        const USER_CODE: [u8; 25] = [
+            0x48, 0x8d, 0x3d, 0x11, 0x00, 0x00, 0x00, // lea rdi, [rip + 0x11]
+            0xbe, 0x01, 0x00, 0x00, 0x00, // mov esi, 1
+            0x31, 0xc0, // xor eax, eax
+            0x0f, 0x05, // syscall
+            0x50, // push rax
+            0x0f, 0x0b, // ud2
+            0xcc, 0xcc, 0xcc, 0xcc, 0xcc, // int3 padding
+            b'u',
+        ];
+        /// The offset of `ud2` in [`USER_CODE`].
+        const UD2_OFFSET: usize = 17;
+
+        let table = |frame: Frame| direct_map::phys_to_ptr::<u64>(frame.base_address());
+
+        let mut root_guard = root().expect("root CNode has not been set up").lock();
+        let untyped = (0..root_guard.size())
+            .find(|&index| {
+                matches!(
+                    root_guard.lookup(index),
+                    Ok(CapabilitySlot::Untyped(untyped))
+                        if untyped.is_pristine() && untyped.free_frames() >= FRAME_COUNT as u64
+                )
+            })
+            .expect("no pristine untyped memory large enough");
+        let destination = root_guard.size() - FRAME_COUNT;
+        root_guard
+            .retype(untyped, ObjectKind::Frame, destination, FRAME_COUNT)
+            .unwrap();
+        let frames: [Frame; FRAME_COUNT] = core::array::from_fn(|index| {
+            match root_guard.lookup(destination + index) {
+                Ok(CapabilitySlot::Frame { frame, .. }) => frame,
+                _ => panic!("retype did not produce a frame capability"),
+            }
+        });
+        drop(root_guard);
+        let [pdpt, pd, pt, code, stack] = frames;
+
+        let cr3: u64;
+        // SAFETY:
+        // Reading `cr3` has no side effects.
+        unsafe { core::arch::asm!("mov {}, cr3", out(reg) cr3, options(nomem, nostack)) };
+        let pml4 = table(Frame::containing_address(crate::arch::PhysicalAddress::new_masked(cr3)));
+        // SAFETY:
+        // The active PML4 is covered by the direct map.
+        let pml4_entry = unsafe { pml4.add(PML4_INDEX) };
+
+        // SAFETY:
+        // The entry lies in the active PML4.
+        assert_eq!(unsafe { pml4_entry.read_volatile() } & 1, 0, "PML4 entry is in use");
+        // SAFETY:
+        // The frames were just carved out of untyped memory for this test and are covered by the
+        // direct map. The code frame holds at least `USER_CODE.len()` bytes.
+        unsafe {
+            ptr::copy_nonoverlapping(USER_CODE.as_ptr(), table(code).cast::<u8>(), USER_CODE.len())
+        };
+        // SAFETY:
+        // Same as above.
+        unsafe { table(pt).write_volatile(code.base_address().value() | USER_TABLE_FLAGS) };
+        // SAFETY:
+        // Same as above. A page table holds 512 entries.
+        let stack_entry = unsafe { table(pt).add(1) };
+        // SAFETY:
+        // Same as above.
+        unsafe { stack_entry.write_volatile(stack.base_address().value() | USER_TABLE_FLAGS) };
+        // SAFETY:
+        // Same as above.
+        unsafe { table(pd).write_volatile(pt.base_address().value() | USER_TABLE_FLAGS) };
+        // SAFETY:
+        // Same as above.
+        unsafe { table(pdpt).write_volatile(pd.base_address().value() | USER_TABLE_FLAGS) };
+        // SAFETY:
+        // The entry was unused, so installing the new tables changes no existing mapping.
+        unsafe { pml4_entry.write_volatile(pdpt.base_address().value() | USER_TABLE_FLAGS) };
+
+        let entry = VirtualAddress::new_canonical(USER_BASE);
+        let stack_top = VirtualAddress::new_canonical(USER_BASE + 2 * Frame::FRAME_SIZE as usize);
+        // SAFETY:
+        // The code and stack pages were just mapped user accessible, and nothing else lives in
+        // them.
+        let fault = unsafe { run(entry, stack_top) };
+
+        // SAFETY:
+        // The entry lies in the active PML4 and the test's pages are no longer in use.
+        unsafe { pml4_entry.write_volatile(0) };
+        for page in [entry, VirtualAddress::new_canonical(USER_BASE + Frame::FRAME_SIZE as usize)] {
+            // SAFETY:
+            // Invalidating a TLB entry has no memory safety implications.
+            unsafe {
+                core::arch::asm!("invlpg [{}]", in(reg) page.value(), options(nostack))
+            };
+        }
+
+        assert_eq!(fault.vector, INVALID_OPCODE_VECTOR, "unexpected {fault}");
+        assert_eq!(fault.instruction_pointer.value(), USER_BASE + UD2_OFFSET);
+        #[cfg(feature = "logging")]
+        log::info!("user task faulted as expected: {fault}");
+
+        // SAFETY:
+        // The stack frame is covered by the direct map and holds `FRAME_SIZE / 8` words.
+        let top_word = unsafe { table(stack).add(Frame::FRAME_SIZE as usize / 8 - 1) };
+        // SAFETY:
+        // The stack frame is no longer mapped for user mode, so nothing writes to it anymore.
+        let pushed = unsafe { top_word.read_volatile() };
+        assert_eq!(pushed, 1, "debug write system call did not return the length written");
+
+        let mut root_guard = root().expect("root CNode has not been set up").lock();
+        for index in destination..root_guard.size() {
+            root_guard.delete(index).unwrap();
+        }
+        root_guard.revoke(untyped).unwrap();
+    }
+}
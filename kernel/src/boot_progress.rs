@@ -0,0 +1,244 @@
+//! Tracking of how far boot has progressed, so that a hang can be located even when nothing was
+//! logged.
+//!
+//! The boot path calls [`reach()`] as each [`BootPhase`] completes, which publishes the phase
+//! through [`arch::boot_progress`][crate::arch::boot_progress] in places that can be read from
+//! outside the machine. Once the timer ticks, [`on_tick()`] panics if the next phase is not
+//! reached within the budget given by the `boot_watchdog_ms` option.
+
+use core::{
+    fmt,
+    sync::atomic::{AtomicU8, Ordering},
+};
+
+use crate::spinlock::IrqSpinlock;
+
+/// The most recently completed [`BootPhase`], as its [`BootPhase::code()`], or zero if none has
+/// completed.
+static PHASE: AtomicU8 = AtomicU8::new(0);
+
+/// The watchdog that detects a stalled boot.
+static WATCHDOG: IrqSpinlock<Watchdog> = IrqSpinlock::new(Watchdog::new());
+
+/// Records that `phase` has completed, publishes it, and restarts the watchdog's budget for the
+/// next phase.
+pub fn reach(phase: BootPhase) {
+    PHASE.store(phase.code(), Ordering::Relaxed);
+    crate::arch::boot_progress::publish(phase);
+
+    WATCHDOG
+        .lock()
+        .progress(crate::arch::apic::ticks(), phase.next().is_none());
+}
+
+/// Returns the most recently completed [`BootPhase`], or [`None`] if no phase has completed.
+pub fn current() -> Option<BootPhase> {
+    BootPhase::from_code(PHASE.load(Ordering::Relaxed))
+}
+
+/// Starts the watchdog at tick `now` of a timer ticking at `tick_hz`, with the budget given by the
+/// `boot_watchdog_ms` option.
+///
+/// A budget of zero leaves the watchdog disabled.
+pub fn start_watchdog(now: u64, tick_hz: u64) {
+    let budget_ms = crate::options::get::<u64>("boot_watchdog_ms").unwrap_or(0);
+    let budget_ticks = Watchdog::budget_ticks(budget_ms, tick_hz);
+    if current().is_some_and(|phase| phase.next().is_none()) {
+        return;
+    }
+
+    WATCHDOG.lock().start(now, budget_ticks);
+}
+
+/// Panics if boot has not progressed within the watchdog's budget by tick `now`.
+///
+/// This is called by the timer interrupt handler.
+pub fn on_tick(now: u64) {
+    let expired = {
+        let mut watchdog = WATCHDOG.lock();
+        let expired = watchdog.is_expired(now);
+        if expired {
+            watchdog.disarm();
+        }
+        expired
+    };
+
+    if expired {
+        match current() {
+            Some(phase) => panic!("boot stalled in phase {phase}"),
+            None => panic!("boot stalled before any phase completed"),
+        }
+    }
+}
+
+/// A phase of booting, in the order in which they complete.
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub enum BootPhase {
+    /// The bootloader entered the kernel.
+    EntryReached = 1,
+    /// Logging has been initialized, whether or not any sink came up.
+    LoggingUp = 2,
+    /// The bootloader's memory map has been captured.
+    MemoryMapParsed = 3,
+    /// The page tables backing the kernel have been accounted for.
+    PageTablesBuilt = 4,
+    /// Interrupt handling has been set up and, if the timer runs, interrupts are enabled.
+    InterruptsEnabled = 5,
+    /// The architecture independent entry point was reached.
+    KmainReached = 6,
+}
+
+impl BootPhase {
+    /// Every [`BootPhase`], in order.
+    pub const ALL: [Self; 6] = [
+        Self::EntryReached,
+        Self::LoggingUp,
+        Self::MemoryMapParsed,
+        Self::PageTablesBuilt,
+        Self::InterruptsEnabled,
+        Self::KmainReached,
+    ];
+
+    /// Returns the nonzero code under which this [`BootPhase`] is published.
+    pub const fn code(self) -> u8 {
+        self as u8
+    }
+
+    /// Returns the [`BootPhase`] published as `code`, or [`None`] if `code` names no phase.
+    pub const fn from_code(code: u8) -> Option<Self> {
+        match code {
+            1 => Some(Self::EntryReached),
+            2 => Some(Self::LoggingUp),
+            3 => Some(Self::MemoryMapParsed),
+            4 => Some(Self::PageTablesBuilt),
+            5 => Some(Self::InterruptsEnabled),
+            6 => Some(Self::KmainReached),
+            _ => None,
+        }
+    }
+
+    /// Returns the [`BootPhase`] that follows this one, or [`None`] if this is the last.
+    pub const fn next(self) -> Option<Self> {
+        Self::from_code(self.code() + 1)
+    }
+
+    /// Returns the name of this [`BootPhase`].
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::EntryReached => "EntryReached",
+            Self::LoggingUp => "LoggingUp",
+            Self::MemoryMapParsed => "MemoryMapParsed",
+            Self::PageTablesBuilt => "PageTablesBuilt",
+            Self::InterruptsEnabled => "InterruptsEnabled",
+            Self::KmainReached => "KmainReached",
+        }
+    }
+}
+
+impl fmt::Display for BootPhase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// A deadline, in timer ticks, by which the next [`BootPhase`] must be reached.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub struct Watchdog {
+    /// The number of ticks each phase may take, or zero if the watchdog is disabled.
+    budget_ticks: u64,
+    /// The tick at which the watchdog expires, if it is armed.
+    deadline: Option<u64>,
+}
+
+impl Watchdog {
+    /// Creates a disarmed [`Watchdog`].
+    pub const fn new() -> Self {
+        Self {
+            budget_ticks: 0,
+            deadline: None,
+        }
+    }
+
+    /// Returns the number of ticks of a timer ticking at `tick_hz` that cover `budget_ms`
+    /// milliseconds, rounded up.
+    pub const fn budget_ticks(budget_ms: u64, tick_hz: u64) -> u64 {
+        budget_ms.saturating_mul(tick_hz).div_ceil(1000)
+    }
+
+    /// Arms the [`Watchdog`] to expire `budget_ticks` after tick `now`, or disables it if
+    /// `budget_ticks` is zero.
+    pub fn start(&mut self, now: u64, budget_ticks: u64) {
+        self.budget_ticks = budget_ticks;
+        self.deadline = (budget_ticks != 0).then(|| now.saturating_add(budget_ticks));
+    }
+
+    /// Restarts the budget of an armed [`Watchdog`] at tick `now`, or disarms it if the last
+    /// phase was reached.
+    pub fn progress(&mut self, now: u64, last_phase: bool) {
+        if last_phase {
+            self.disarm();
+        } else if self.deadline.is_some() {
+            self.deadline = Some(now.saturating_add(self.budget_ticks));
+        }
+    }
+
+    /// Disarms the [`Watchdog`].
+    pub fn disarm(&mut self) {
+        self.deadline = None;
+    }
+
+    /// Returns `true` if the [`Watchdog`] is armed and has expired by tick `now`.
+    pub const fn is_expired(&self, now: u64) -> bool {
+        match self.deadline {
+            Some(deadline) => now >= deadline,
+            None => false,
+        }
+    }
+}
+
+impl Default for Watchdog {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "ktest")]
+crate::kernel_test! {
+    fn boot_phase_codes_round_trip() {
+        for (index, phase) in BootPhase::ALL.into_iter().enumerate() {
+            assert_ne!(phase.code(), 0);
+            assert_eq!(BootPhase::from_code(phase.code()), Some(phase));
+            assert_eq!(phase.next(), BootPhase::ALL.get(index + 1).copied());
+        }
+        assert_eq!(BootPhase::from_code(0), None);
+        assert_eq!(BootPhase::from_code(BootPhase::KmainReached.code() + 1), None);
+        assert_eq!(current(), Some(BootPhase::KmainReached));
+    }
+
+    fn boot_watchdog_budget() {
+        assert_eq!(Watchdog::budget_ticks(5000, 100), 500);
+        assert_eq!(Watchdog::budget_ticks(1, 100), 1);
+        assert_eq!(Watchdog::budget_ticks(0, 100), 0);
+        assert_eq!(Watchdog::budget_ticks(u64::MAX, 100), u64::MAX / 1000 + 1);
+
+        let mut watchdog = Watchdog::new();
+        assert!(!watchdog.is_expired(u64::MAX));
+
+        watchdog.start(10, 5);
+        assert!(!watchdog.is_expired(14));
+        assert!(watchdog.is_expired(15));
+
+        watchdog.progress(14, false);
+        assert!(!watchdog.is_expired(18));
+        assert!(watchdog.is_expired(19));
+
+        watchdog.progress(18, true);
+        assert!(!watchdog.is_expired(u64::MAX));
+        watchdog.progress(20, false);
+        assert!(!watchdog.is_expired(u64::MAX));
+
+        watchdog.start(10, 0);
+        assert!(!watchdog.is_expired(u64::MAX));
+    }
+}
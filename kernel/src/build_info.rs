@@ -0,0 +1,218 @@
+//! Metadata identifying the build of the kernel.
+//!
+//! The git commit, dirty flag, build profile, and rustc version are captured by the build script,
+//! which records `unknown` for any of them that cannot be determined, such as when building
+//! outside of a git checkout.
+
+use core::fmt;
+
+/// The version of the kernel crate.
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+/// The full hash of the git commit the kernel was built from, or `unknown`.
+pub const GIT_COMMIT: &str = env!("CAPORA_GIT_COMMIT");
+/// Whether the git checkout had uncommitted changes: `true`, `false`, or `unknown`.
+const GIT_DIRTY: &str = env!("CAPORA_GIT_DIRTY");
+/// The cargo profile the kernel was built with, or `unknown`.
+pub const PROFILE: &str = env!("CAPORA_BUILD_PROFILE");
+/// The output of `rustc -V` for the compiler that built the kernel, or `unknown`.
+pub const RUSTC_VERSION: &str = env!("CAPORA_RUSTC_VERSION");
+
+/// The number of hexadecimal digits of [`GIT_COMMIT`] shown by [`summary()`].
+const SHORT_COMMIT_LEN: usize = 12;
+
+/// Every feature of the kernel crate, paired with whether it is enabled.
+pub const FEATURES: &[(&str, bool)] = &[
+    ("capora-boot-api", cfg!(feature = "capora-boot-api")),
+    ("limine-boot-api", cfg!(feature = "limine-boot-api")),
+    ("multiboot2-boot-api", cfg!(feature = "multiboot2-boot-api")),
+    ("boot-selftest", cfg!(feature = "boot-selftest")),
+    ("logging", cfg!(feature = "logging")),
+    ("debugcon-logging", cfg!(feature = "debugcon-logging")),
+    ("serial-logging", cfg!(feature = "serial-logging")),
+    ("framebuffer-logging", cfg!(feature = "framebuffer-logging")),
+    ("log-level-error", cfg!(feature = "log-level-error")),
+    ("log-level-warn", cfg!(feature = "log-level-warn")),
+    ("log-level-info", cfg!(feature = "log-level-info")),
+    ("log-level-debug", cfg!(feature = "log-level-debug")),
+    (
+        "release-log-level-error",
+        cfg!(feature = "release-log-level-error"),
+    ),
+    (
+        "release-log-level-warn",
+        cfg!(feature = "release-log-level-warn"),
+    ),
+    (
+        "release-log-level-info",
+        cfg!(feature = "release-log-level-info"),
+    ),
+    (
+        "release-log-level-debug",
+        cfg!(feature = "release-log-level-debug"),
+    ),
+    ("debug-shell", cfg!(feature = "debug-shell")),
+    ("debug-locks", cfg!(feature = "debug-locks")),
+    ("qemu-exit", cfg!(feature = "qemu-exit")),
+    ("ktest", cfg!(feature = "ktest")),
+];
+
+/// Returns whether the git checkout had uncommitted changes, or [`None`] if unknown.
+pub fn git_dirty() -> Option<bool> {
+    parse_dirty(GIT_DIRTY)
+}
+
+/// Parses a dirty flag recorded by the build script, returning [`None`] if it is `unknown`.
+fn parse_dirty(value: &str) -> Option<bool> {
+    match value {
+        "true" => Some(true),
+        "false" => Some(false),
+        _ => None,
+    }
+}
+
+/// Returns the names of the enabled features of the kernel crate.
+pub fn enabled_features() -> impl Iterator<Item = &'static str> {
+    FEATURES
+        .iter()
+        .filter(|&&(_, enabled)| enabled)
+        .map(|&(name, _)| name)
+}
+
+/// Returns a one line summary of the build of the kernel.
+pub fn summary() -> Summary {
+    Summary {
+        version: VERSION,
+        commit: GIT_COMMIT,
+        dirty: git_dirty(),
+        profile: PROFILE,
+        rustc_version: RUSTC_VERSION,
+    }
+}
+
+/// A one line summary of the build of the kernel, as returned by [`summary()`].
+///
+/// ```text
+/// capora-kernel 0.1.0 (1a2b3c4d5e6f-dirty, debug, rustc 1.83.0-nightly) features: logging
+/// ```
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub struct Summary {
+    /// The version of the kernel crate.
+    pub version: &'static str,
+    /// The git commit the kernel was built from, or `unknown`.
+    pub commit: &'static str,
+    /// Whether the git checkout had uncommitted changes, or [`None`] if unknown.
+    pub dirty: Option<bool>,
+    /// The cargo profile the kernel was built with.
+    pub profile: &'static str,
+    /// The output of `rustc -V`.
+    pub rustc_version: &'static str,
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let commit = self.commit.get(..SHORT_COMMIT_LEN).unwrap_or(self.commit);
+        write!(f, "capora-kernel {} ({commit}", self.version)?;
+        if self.dirty == Some(true) {
+            f.write_str("-dirty")?;
+        }
+        write!(f, ", {}, {}) features:", self.profile, self.rustc_version)?;
+
+        let mut features = enabled_features().peekable();
+        if features.peek().is_none() {
+            return f.write_str(" none");
+        }
+        for (index, feature) in features.enumerate() {
+            let separator = if index == 0 { " " } else { "," };
+            write!(f, "{separator}{feature}")?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{string::String, vec::Vec};
+
+    use super::*;
+
+    /// The enabled features as [`Summary`] lists them.
+    fn features() -> String {
+        let features: Vec<_> = enabled_features().collect();
+        if features.is_empty() {
+            return String::from("none");
+        }
+
+        features.join(",")
+    }
+
+    /// Returns a [`Summary`] of a build of `commit` with the given dirty flag.
+    fn summary_of(commit: &'static str, dirty: Option<bool>) -> Summary {
+        Summary {
+            version: "0.1.0",
+            commit,
+            dirty,
+            profile: "debug",
+            rustc_version: "rustc 1.83.0-nightly",
+        }
+    }
+
+    #[test]
+    fn summary_shortens_the_commit() {
+        let commit = "1a2b3c4d5e6f7a8b9c0d1e2f3a4b5c6d7e8f9a0b";
+
+        assert_eq!(
+            std::format!("{}", summary_of(commit, Some(false))),
+            std::format!(
+                "capora-kernel 0.1.0 (1a2b3c4d5e6f, debug, rustc 1.83.0-nightly) features: {}",
+                features()
+            )
+        );
+        assert_eq!(
+            std::format!("{}", summary_of(commit, Some(true))),
+            std::format!(
+                "capora-kernel 0.1.0 (1a2b3c4d5e6f-dirty, debug, rustc 1.83.0-nightly) \
+                 features: {}",
+                features()
+            )
+        );
+    }
+
+    #[test]
+    fn summary_falls_back_to_unknown() {
+        let summary = Summary {
+            version: "0.1.0",
+            commit: "unknown",
+            dirty: parse_dirty("unknown"),
+            profile: "unknown",
+            rustc_version: "unknown",
+        };
+
+        assert_eq!(
+            std::format!("{summary}"),
+            std::format!(
+                "capora-kernel 0.1.0 (unknown, unknown, unknown) features: {}",
+                features()
+            )
+        );
+    }
+
+    #[test]
+    fn dirty_flag_parsing() {
+        assert_eq!(parse_dirty("true"), Some(true));
+        assert_eq!(parse_dirty("false"), Some(false));
+        assert_eq!(parse_dirty("unknown"), None);
+        assert_eq!(parse_dirty(""), None);
+    }
+
+    #[test]
+    fn summary_describes_this_build() {
+        let summary = summary();
+
+        assert_eq!(summary.version, env!("CARGO_PKG_VERSION"));
+        assert_eq!(summary.commit, GIT_COMMIT);
+        assert_eq!(summary.dirty, git_dirty());
+        assert!(std::format!("{summary}").ends_with(&std::format!(" features: {}", features())));
+        assert!(enabled_features().all(|name| FEATURES.contains(&(name, true))));
+    }
+}
@@ -0,0 +1,348 @@
+//! Capability slots and the rights they confer.
+//!
+//! A [`CapabilitySlot`] either is empty or holds a capability to a single kernel object. Slots
+//! only move between states through [`CapabilitySlot::insert()`], [`CapabilitySlot::take()`], and
+//! [`CapabilitySlot::diminish()`], which reject inserting over an occupied slot and deriving a
+//! capability with more rights than its source.
+
+use core::{
+    fmt,
+    ops::{BitAnd, BitOr},
+};
+
+use crate::{
+    arch::{Frame, FrameRange},
+    cells::untyped::Untyped,
+};
+
+/// The set of operations a capability permits on its object.
+#[derive(Clone, Copy, Hash, PartialEq, Eq)]
+pub struct Rights(u8);
+
+impl Rights {
+    /// No rights.
+    pub const NONE: Self = Self(0);
+    /// Permits reading the object.
+    pub const READ: Self = Self(0x1);
+    /// Permits modifying the object.
+    pub const WRITE: Self = Self(0x2);
+    /// Permits passing the capability on to others.
+    pub const GRANT: Self = Self(0x4);
+    /// Every right.
+    pub const ALL: Self = Self(Self::READ.0 | Self::WRITE.0 | Self::GRANT.0);
+
+    /// Returns the [`Rights`] encoded by `bits`, or [`None`] if `bits` contains unknown rights.
+    pub const fn from_bits(bits: u8) -> Option<Self> {
+        if bits & !Self::ALL.0 != 0 {
+            return None;
+        }
+
+        Some(Self(bits))
+    }
+
+    /// Returns the bits encoding these [`Rights`].
+    pub const fn bits(self) -> u8 {
+        self.0
+    }
+
+    /// Returns `true` if these [`Rights`] include every right in `other`.
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for Rights {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self::Output {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl BitAnd for Rights {
+    type Output = Self;
+
+    fn bitand(self, rhs: Self) -> Self::Output {
+        Self(self.0 & rhs.0)
+    }
+}
+
+impl fmt::Display for Rights {
+    /// Formats the [`Rights`] as `rwg`, with `-` in place of each missing right.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (right, c) in [(Self::READ, 'r'), (Self::WRITE, 'w'), (Self::GRANT, 'g')] {
+            let c = if self.contains(right) { c } else { '-' };
+            fmt::Write::write_char(f, c)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Rights {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Rights({self})")
+    }
+}
+
+/// A slot holding at most one capability.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum CapabilitySlot {
+    /// The slot holds no capability.
+    Empty,
+    /// A capability to a region of physical memory from which other objects can be created.
+    Untyped(Untyped),
+    /// A capability to a single frame of physical memory.
+    Frame {
+        /// The frame covered by the capability.
+        frame: Frame,
+        /// The operations permitted on the frame.
+        rights: Rights,
+    },
+    /// A capability to a [`CNode`][crate::cells::cnode::CNode] carved out of untyped memory.
+    CNode {
+        /// The physical memory backing the [`CNode`][crate::cells::cnode::CNode].
+        frame_range: FrameRange,
+        /// The base-2 logarithm of the number of slots.
+        size_bits: u8,
+    },
+    /// A capability to handle the interrupt with the given vector.
+    IrqHandler {
+        /// The interrupt vector handled through the capability.
+        vector: u8,
+    },
+}
+
+impl CapabilitySlot {
+    /// Returns `true` if this [`CapabilitySlot`] holds no capability.
+    pub const fn is_empty(&self) -> bool {
+        matches!(self, Self::Empty)
+    }
+
+    /// Returns the [`Rights`] conferred by the capability in this [`CapabilitySlot`].
+    ///
+    /// Capabilities without a rights field confer [`Rights::ALL`], and an empty slot confers
+    /// [`Rights::NONE`].
+    pub const fn rights(&self) -> Rights {
+        match self {
+            Self::Empty => Rights::NONE,
+            Self::Frame { rights, .. } => *rights,
+            Self::Untyped(_) | Self::CNode { .. } | Self::IrqHandler { .. } => Rights::ALL,
+        }
+    }
+
+    /// Places `capability` in this [`CapabilitySlot`].
+    ///
+    /// # Errors
+    /// Returns [`SlotError::Occupied`] if this slot already holds a capability, or
+    /// [`SlotError::Empty`] if `capability` is [`CapabilitySlot::Empty`].
+    pub fn insert(&mut self, capability: CapabilitySlot) -> Result<(), SlotError> {
+        if !self.is_empty() {
+            return Err(SlotError::Occupied);
+        }
+        if capability.is_empty() {
+            return Err(SlotError::Empty);
+        }
+
+        *self = capability;
+        Ok(())
+    }
+
+    /// Removes and returns the capability in this [`CapabilitySlot`], leaving it empty.
+    ///
+    /// # Errors
+    /// Returns [`SlotError::Empty`] if this slot holds no capability.
+    pub fn take(&mut self) -> Result<CapabilitySlot, SlotError> {
+        if self.is_empty() {
+            return Err(SlotError::Empty);
+        }
+
+        Ok(core::mem::replace(self, Self::Empty))
+    }
+
+    /// Returns a copy of the capability in this [`CapabilitySlot`] that confers only `rights`.
+    ///
+    /// # Errors
+    /// - [`SlotError::Empty`] if this slot holds no capability.
+    /// - [`SlotError::RightsEscalation`] if `rights` includes a right this capability lacks.
+    /// - [`SlotError::CannotDiminish`] if `rights` would reduce a capability with no rights field.
+    /// - [`SlotError::Uncopyable`] if this is an untyped capability, whose copies would hand out
+    ///   the same memory twice.
+    pub fn diminish(&self, rights: Rights) -> Result<CapabilitySlot, SlotError> {
+        if self.is_empty() {
+            return Err(SlotError::Empty);
+        }
+        if !self.rights().contains(rights) {
+            return Err(SlotError::RightsEscalation);
+        }
+
+        match *self {
+            Self::Untyped(_) => Err(SlotError::Uncopyable),
+            Self::Frame { frame, .. } => Ok(Self::Frame { frame, rights }),
+            capability if rights == Rights::ALL => Ok(capability),
+            _ => Err(SlotError::CannotDiminish),
+        }
+    }
+}
+
+/// Various errors that can occur while changing the state of a [`CapabilitySlot`].
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum SlotError {
+    /// The slot already holds a capability.
+    Occupied,
+    /// The slot, or the capability to place in it, is empty.
+    Empty,
+    /// The requested rights include a right the source capability lacks.
+    RightsEscalation,
+    /// The capability has no rights field, so its rights cannot be reduced.
+    CannotDiminish,
+    /// The capability cannot be copied.
+    Uncopyable,
+}
+
+impl fmt::Display for SlotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Occupied => f.write_str("capability slot is occupied"),
+            Self::Empty => f.write_str("capability slot is empty"),
+            Self::RightsEscalation => f.write_str("rights exceed those of the source capability"),
+            Self::CannotDiminish => f.write_str("capability rights cannot be diminished"),
+            Self::Uncopyable => f.write_str("capability cannot be copied"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arch::PhysicalAddress;
+
+    /// Returns a frame capability conferring `rights`.
+    fn frame_capability(rights: Rights) -> CapabilitySlot {
+        CapabilitySlot::Frame {
+            frame: Frame::containing_address(PhysicalAddress::new_masked(0x5000)),
+            rights,
+        }
+    }
+
+    #[test]
+    fn rights_subsets() {
+        let read_write = Rights::READ | Rights::WRITE;
+
+        assert!(Rights::ALL.contains(read_write));
+        assert!(read_write.contains(Rights::READ));
+        assert!(read_write.contains(Rights::NONE));
+        assert!(!read_write.contains(Rights::GRANT));
+        assert!(!read_write.contains(Rights::ALL));
+        assert!(!Rights::NONE.contains(Rights::READ));
+        assert_eq!(read_write & Rights::WRITE, Rights::WRITE);
+        assert_eq!(read_write & Rights::GRANT, Rights::NONE);
+    }
+
+    #[test]
+    fn rights_bits_round_trip() {
+        for bits in 0..=Rights::ALL.bits() {
+            assert_eq!(Rights::from_bits(bits).map(Rights::bits), Some(bits));
+        }
+        assert_eq!(Rights::from_bits(0x8), None);
+        assert_eq!(Rights::from_bits(Rights::ALL.bits() | 0x80), None);
+    }
+
+    #[test]
+    fn rights_display() {
+        assert_eq!(std::format!("{}", Rights::ALL), "rwg");
+        assert_eq!(std::format!("{}", Rights::NONE), "---");
+        assert_eq!(std::format!("{}", Rights::READ | Rights::GRANT), "r-g");
+        assert_eq!(std::format!("{:?}", Rights::WRITE), "Rights(-w-)");
+    }
+
+    #[test]
+    fn diminish_to_fewer_rights() {
+        let capability = frame_capability(Rights::READ | Rights::WRITE);
+
+        assert_eq!(
+            capability.diminish(Rights::READ),
+            Ok(frame_capability(Rights::READ))
+        );
+        assert_eq!(
+            capability.diminish(Rights::READ | Rights::WRITE),
+            Ok(capability)
+        );
+        assert_eq!(
+            capability.diminish(Rights::NONE),
+            Ok(frame_capability(Rights::NONE))
+        );
+    }
+
+    #[test]
+    fn upward_derivation_is_rejected() {
+        let capability = frame_capability(Rights::READ);
+
+        assert_eq!(
+            capability.diminish(Rights::READ | Rights::WRITE),
+            Err(SlotError::RightsEscalation)
+        );
+        assert_eq!(
+            capability.diminish(Rights::GRANT),
+            Err(SlotError::RightsEscalation)
+        );
+        assert_eq!(
+            frame_capability(Rights::NONE).diminish(Rights::READ),
+            Err(SlotError::RightsEscalation)
+        );
+    }
+
+    #[test]
+    fn diminish_without_rights_field() {
+        let irq = CapabilitySlot::IrqHandler { vector: 0x40 };
+        assert_eq!(irq.rights(), Rights::ALL);
+        assert_eq!(irq.diminish(Rights::ALL), Ok(irq));
+        assert_eq!(irq.diminish(Rights::READ), Err(SlotError::CannotDiminish));
+
+        let frame_range = FrameRange::inclusive_range(
+            Frame::containing_address(PhysicalAddress::new_masked(0x10000)),
+            Frame::containing_address(PhysicalAddress::new_masked(0x1f000)),
+        );
+        let untyped = CapabilitySlot::Untyped(Untyped::new(frame_range));
+        assert_eq!(untyped.diminish(Rights::ALL), Err(SlotError::Uncopyable));
+        assert_eq!(untyped.diminish(Rights::READ), Err(SlotError::Uncopyable));
+
+        assert_eq!(CapabilitySlot::Empty.rights(), Rights::NONE);
+        assert_eq!(
+            CapabilitySlot::Empty.diminish(Rights::NONE),
+            Err(SlotError::Empty)
+        );
+    }
+
+    #[test]
+    fn insert_over_occupied_slot_is_rejected() {
+        let mut slot = CapabilitySlot::Empty;
+        let first = frame_capability(Rights::ALL);
+
+        assert_eq!(slot.insert(first), Ok(()));
+        assert_eq!(slot, first);
+        assert_eq!(
+            slot.insert(CapabilitySlot::IrqHandler { vector: 0x20 }),
+            Err(SlotError::Occupied)
+        );
+        assert_eq!(slot, first);
+    }
+
+    #[test]
+    fn insert_of_empty_capability_is_rejected() {
+        let mut slot = CapabilitySlot::Empty;
+
+        assert_eq!(slot.insert(CapabilitySlot::Empty), Err(SlotError::Empty));
+        assert!(slot.is_empty());
+    }
+
+    #[test]
+    fn take_empties_the_slot() {
+        let mut slot = frame_capability(Rights::READ);
+
+        assert_eq!(slot.take(), Ok(frame_capability(Rights::READ)));
+        assert!(slot.is_empty());
+        assert_eq!(slot.take(), Err(SlotError::Empty));
+        assert!(slot.is_empty());
+    }
+}
@@ -0,0 +1,389 @@
+//! Tables of [`CapabilitySlot`]s.
+//!
+//! A [`CNode`] holds a power-of-two number of slots in frames taken from a frame allocator and
+//! accessed through the direct map. Slots are addressed by index, and every change to a slot goes
+//! through [`CapabilitySlot`]'s transitions, so the checks against overwriting a capability or
+//! escalating its rights apply to every [`CNode`] alike.
+//!
+//! The root [`CNode`] is created during boot by [`init_root()`] and accessed through [`root()`].
+//! Further [`CNode`]s and frames are carved out of untyped memory with [`CNode::retype()`].
+
+use core::{fmt, mem, ptr};
+
+use crate::{
+    arch::{direct_map, Frame, VirtualAddress},
+    cells::{
+        capability::{CapabilitySlot, Rights, SlotError},
+        untyped::{ObjectKind, UntypedError},
+    },
+    spinlock::Spinlock,
+    sync::Once,
+};
+
+/// The largest supported `size_bits` of a [`CNode`].
+pub const MAX_SIZE_BITS: u8 = 12;
+/// The `size_bits` of the root [`CNode`].
+pub const ROOT_SIZE_BITS: u8 = 8;
+
+/// The number of [`CapabilitySlot`]s that fit in a single frame.
+const SLOTS_PER_FRAME: usize = Frame::FRAME_SIZE as usize / mem::size_of::<CapabilitySlot>();
+/// The number of frames backing a [`CNode`] with [`MAX_SIZE_BITS`].
+const MAX_FRAMES: usize = frames_for(MAX_SIZE_BITS) as usize;
+
+/// The root [`CNode`], from which every capability is eventually derived.
+static ROOT: Once<Spinlock<CNode>> = Once::new();
+
+/// Creates the root [`CNode`] with frames obtained from `allocate`.
+///
+/// # Errors
+/// Returns an error if the [`CNode`] could not be created, in which case the root [`CNode`]
+/// remains uninitialized.
+///
+/// # Panics
+/// Panics if the root [`CNode`] has already been initialized.
+pub fn init_root(allocate: impl FnMut() -> Option<Frame>) -> Result<(), CNodeError> {
+    let cnode = CNode::new(ROOT_SIZE_BITS, allocate)?;
+    ROOT.init(Spinlock::new(cnode));
+
+    Ok(())
+}
+
+/// Returns the root [`CNode`], or [`None`] if [`init_root()`] has not succeeded yet.
+pub fn root() -> Option<&'static Spinlock<CNode>> {
+    ROOT.get()
+}
+
+/// Returns the number of frames backing a [`CNode`] with `1 << size_bits` slots.
+pub const fn frames_for(size_bits: u8) -> u64 {
+    (1usize << size_bits).div_ceil(SLOTS_PER_FRAME) as u64
+}
+
+/// A power-of-two sized table of [`CapabilitySlot`]s.
+///
+/// The frames backing a [`CNode`] are owned by it for as long as it exists. They are not yet
+/// returned to the frame allocator when it is dropped.
+#[derive(Debug)]
+pub struct CNode {
+    /// The base of the direct mapping of each frame backing this [`CNode`].
+    frames: [VirtualAddress; MAX_FRAMES],
+    /// The base-2 logarithm of the number of slots in this [`CNode`].
+    size_bits: u8,
+}
+
+impl CNode {
+    /// Creates a [`CNode`] with `1 << size_bits` empty slots, backed by frames obtained from
+    /// `allocate`.
+    ///
+    /// # Errors
+    /// - [`CNodeError::InvalidSize`] if `size_bits` exceeds [`MAX_SIZE_BITS`].
+    /// - [`CNodeError::DirectMapUnavailable`] if the direct map has not been initialized.
+    /// - [`CNodeError::OutOfMemory`] if `allocate` runs out of frames.
+    pub fn new(
+        size_bits: u8,
+        mut allocate: impl FnMut() -> Option<Frame>,
+    ) -> Result<Self, CNodeError> {
+        if size_bits > MAX_SIZE_BITS {
+            return Err(CNodeError::InvalidSize(size_bits));
+        }
+        direct_map::offset().ok_or(CNodeError::DirectMapUnavailable)?;
+
+        let mut cnode = Self {
+            frames: [VirtualAddress::zero(); MAX_FRAMES],
+            size_bits,
+        };
+        let frame_count = frames_for(size_bits) as usize;
+        for base in &mut cnode.frames[..frame_count] {
+            let frame = allocate().ok_or(CNodeError::OutOfMemory)?;
+            *base = direct_map::phys_to_virt(frame.base_address());
+        }
+
+        for index in 0..cnode.size() {
+            // SAFETY:
+            // `index` is in range, and the frame containing its slot was just allocated for this
+            // `CNode`, so nothing else refers to it.
+            unsafe { cnode.slot_ptr(index).write(CapabilitySlot::Empty) }
+        }
+
+        Ok(cnode)
+    }
+
+    /// Returns the number of slots in this [`CNode`].
+    pub const fn size(&self) -> usize {
+        1 << self.size_bits
+    }
+
+    /// Returns the base-2 logarithm of the number of slots in this [`CNode`].
+    pub const fn size_bits(&self) -> u8 {
+        self.size_bits
+    }
+
+    /// Returns a copy of the slot at `index`.
+    ///
+    /// # Errors
+    /// Returns [`CNodeError::IndexOutOfRange`] if `index` is not a slot of this [`CNode`].
+    pub fn lookup(&self, index: usize) -> Result<CapabilitySlot, CNodeError> {
+        self.slot(index).copied()
+    }
+
+    /// Places `capability` in the empty slot at `index`.
+    ///
+    /// # Errors
+    /// Returns [`CNodeError::IndexOutOfRange`] if `index` is not a slot of this [`CNode`], or a
+    /// [`CNodeError::Slot`] if the slot is occupied or `capability` is empty.
+    pub fn insert(&mut self, index: usize, capability: CapabilitySlot) -> Result<(), CNodeError> {
+        Ok(self.slot_mut(index)?.insert(capability)?)
+    }
+
+    /// Removes and returns the capability in the slot at `index`.
+    ///
+    /// # Errors
+    /// Returns [`CNodeError::IndexOutOfRange`] if `index` is not a slot of this [`CNode`], or a
+    /// [`CNodeError::Slot`] if the slot is empty.
+    pub fn delete(&mut self, index: usize) -> Result<CapabilitySlot, CNodeError> {
+        Ok(self.slot_mut(index)?.take()?)
+    }
+
+    /// Places a copy of the capability at `source` that confers only `rights` in the empty slot at
+    /// `destination`.
+    ///
+    /// # Errors
+    /// Returns [`CNodeError::IndexOutOfRange`] if either index is not a slot of this [`CNode`], or
+    /// a [`CNodeError::Slot`] if the source is empty, the destination is occupied, or the
+    /// capability cannot confer `rights`.
+    pub fn copy_with_diminished_rights(
+        &mut self,
+        source: usize,
+        destination: usize,
+        rights: Rights,
+    ) -> Result<(), CNodeError> {
+        let capability = self.slot(source)?.diminish(rights)?;
+        self.insert(destination, capability)
+    }
+
+    /// Carves `count` objects of the given [`ObjectKind`] out of the untyped memory at `untyped`,
+    /// placing capabilities to them in the empty slots starting at `destination`.
+    ///
+    /// Frames are zeroed and [`CNode`]s start out with every slot empty. Frame capabilities confer
+    /// [`Rights::ALL`]. On failure, no slot is changed.
+    ///
+    /// # Errors
+    /// - [`CNodeError::IndexOutOfRange`] if `untyped` or any destination is not a slot of this
+    ///   [`CNode`].
+    /// - [`CNodeError::Untyped`] if `untyped` does not hold untyped memory or the objects cannot
+    ///   be carved out of it.
+    /// - [`CNodeError::Slot`] if any destination slot is occupied.
+    /// - [`CNodeError::DirectMapUnavailable`] if the direct map has not been initialized.
+    pub fn retype(
+        &mut self,
+        untyped: usize,
+        kind: ObjectKind,
+        destination: usize,
+        count: usize,
+    ) -> Result<(), CNodeError> {
+        let CapabilitySlot::Untyped(mut parent) = self.lookup(untyped)? else {
+            return Err(UntypedError::NotUntyped.into());
+        };
+        let objects = parent.retype(kind, count as u64)?;
+
+        let end = destination
+            .checked_add(count)
+            .filter(|&end| end <= self.size())
+            .ok_or(CNodeError::IndexOutOfRange(
+                destination.saturating_add(count - 1),
+            ))?;
+        for index in destination..end {
+            if !self.slot(index)?.is_empty() {
+                return Err(SlotError::Occupied.into());
+            }
+        }
+        direct_map::offset().ok_or(CNodeError::DirectMapUnavailable)?;
+
+        for (index, frame_range) in (destination..end).zip(objects) {
+            let capability = match kind {
+                ObjectKind::Frame => {
+                    let base = direct_map::phys_to_ptr::<u8>(frame_range.start_address());
+                    // SAFETY:
+                    // The frame was just carved out of untyped memory, which the direct map
+                    // covers, and no capability refers to it yet.
+                    unsafe { ptr::write_bytes(base, 0, Frame::FRAME_SIZE as usize) }
+
+                    CapabilitySlot::Frame {
+                        frame: frame_range.start(),
+                        rights: Rights::ALL,
+                    }
+                }
+                ObjectKind::CNode { size_bits } => {
+                    let mut frames = frame_range.into_iter();
+                    Self::new(size_bits, || frames.next())?;
+
+                    CapabilitySlot::CNode {
+                        frame_range,
+                        size_bits,
+                    }
+                }
+            };
+            self.insert(index, capability)?;
+        }
+
+        *self.slot_mut(untyped)? = CapabilitySlot::Untyped(parent);
+        Ok(())
+    }
+
+    /// Returns the untyped memory at `untyped` to its pristine state, making all of it available
+    /// to [`CNode::retype()`] again.
+    ///
+    /// Objects carved out of untyped memory are tracked through the capabilities to them in the
+    /// same [`CNode`], since [`CNode::retype()`] places them there and no operation moves a
+    /// capability to another [`CNode`] yet.
+    ///
+    /// # Errors
+    /// - [`CNodeError::IndexOutOfRange`] if `untyped` is not a slot of this [`CNode`].
+    /// - [`CNodeError::Untyped`] if `untyped` does not hold untyped memory, or if any capability
+    ///   to an object carved out of it remains.
+    pub fn revoke(&mut self, untyped: usize) -> Result<(), CNodeError> {
+        let CapabilitySlot::Untyped(mut parent) = self.lookup(untyped)? else {
+            return Err(UntypedError::NotUntyped.into());
+        };
+
+        let has_children = (0..self.size())
+            .filter_map(|index| self.lookup(index).ok())
+            .any(|capability| parent.is_parent_of(&capability));
+        if has_children {
+            return Err(UntypedError::HasChildren.into());
+        }
+
+        parent.reset();
+        *self.slot_mut(untyped)? = CapabilitySlot::Untyped(parent);
+        Ok(())
+    }
+
+    /// Returns a reference to the slot at `index`.
+    fn slot(&self, index: usize) -> Result<&CapabilitySlot, CNodeError> {
+        if index >= self.size() {
+            return Err(CNodeError::IndexOutOfRange(index));
+        }
+
+        // SAFETY:
+        // `index` is in range, so the slot lies in a frame owned by this `CNode` and was
+        // initialized by `CNode::new()`. Mutation requires `&mut self`, so the slot is not
+        // modified while the reference exists.
+        Ok(unsafe { &*self.slot_ptr(index) })
+    }
+
+    /// Returns a mutable reference to the slot at `index`.
+    fn slot_mut(&mut self, index: usize) -> Result<&mut CapabilitySlot, CNodeError> {
+        if index >= self.size() {
+            return Err(CNodeError::IndexOutOfRange(index));
+        }
+
+        // SAFETY:
+        // `index` is in range, so the slot lies in a frame owned by this `CNode` and was
+        // initialized by `CNode::new()`. This `CNode` is mutably borrowed, so no other reference
+        // to the slot exists.
+        Ok(unsafe { &mut *self.slot_ptr(index) })
+    }
+
+    /// Returns a pointer to the slot at `index`, which is only valid if `index` is less than
+    /// [`CNode::size()`].
+    fn slot_ptr(&self, index: usize) -> *mut CapabilitySlot {
+        let base = self.frames[index / SLOTS_PER_FRAME].value() as *mut CapabilitySlot;
+
+        base.wrapping_add(index % SLOTS_PER_FRAME)
+    }
+}
+
+/// Various errors that can occur while operating on a [`CNode`].
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum CNodeError {
+    /// The index does not name a slot of the [`CNode`].
+    IndexOutOfRange(usize),
+    /// The requested `size_bits` exceeds [`MAX_SIZE_BITS`].
+    InvalidSize(u8),
+    /// The frame allocator ran out of frames.
+    OutOfMemory,
+    /// The direct map has not been initialized, so the frames cannot be accessed.
+    DirectMapUnavailable,
+    /// The slot cannot make the requested transition.
+    Slot(SlotError),
+    /// The untyped memory cannot perform the requested operation.
+    Untyped(UntypedError),
+}
+
+impl From<SlotError> for CNodeError {
+    fn from(error: SlotError) -> Self {
+        Self::Slot(error)
+    }
+}
+
+impl From<UntypedError> for CNodeError {
+    fn from(error: UntypedError) -> Self {
+        Self::Untyped(error)
+    }
+}
+
+impl fmt::Display for CNodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::IndexOutOfRange(index) => write!(f, "slot index {index} is out of range"),
+            Self::InvalidSize(size_bits) => write!(
+                f,
+                "CNode of 2^{size_bits} slots exceeds the maximum of 2^{MAX_SIZE_BITS}"
+            ),
+            Self::OutOfMemory => f.write_str("out of frames for CNode"),
+            Self::DirectMapUnavailable => f.write_str("direct map is not initialized"),
+            Self::Slot(error) => fmt::Display::fmt(error, f),
+            Self::Untyped(error) => fmt::Display::fmt(error, f),
+        }
+    }
+}
+
+#[cfg(feature = "ktest")]
+crate::kernel_test! {
+    fn root_cnode_operations() {
+        use crate::arch::PhysicalAddress;
+
+        let mut root = root().expect("root CNode has not been set up").lock();
+        assert_eq!(root.size(), 1 << ROOT_SIZE_BITS);
+
+        let (source, destination) = (root.size() - 2, root.size() - 1);
+        let frame = Frame::containing_address(PhysicalAddress::zero());
+        let capability = CapabilitySlot::Frame {
+            frame,
+            rights: Rights::READ | Rights::WRITE,
+        };
+
+        assert_eq!(root.lookup(source), Ok(CapabilitySlot::Empty));
+        assert_eq!(
+            root.lookup(root.size()),
+            Err(CNodeError::IndexOutOfRange(root.size()))
+        );
+
+        root.insert(source, capability).unwrap();
+        assert_eq!(root.lookup(source), Ok(capability));
+        assert_eq!(
+            root.insert(source, capability),
+            Err(CNodeError::Slot(SlotError::Occupied))
+        );
+
+        assert_eq!(
+            root.copy_with_diminished_rights(source, destination, Rights::ALL),
+            Err(CNodeError::Slot(SlotError::RightsEscalation))
+        );
+        root.copy_with_diminished_rights(source, destination, Rights::READ)
+            .unwrap();
+        assert_eq!(
+            root.lookup(destination),
+            Ok(CapabilitySlot::Frame {
+                frame,
+                rights: Rights::READ
+            })
+        );
+
+        assert_eq!(root.delete(source), Ok(capability));
+        assert!(root.delete(destination).is_ok());
+        assert_eq!(
+            root.delete(source),
+            Err(CNodeError::Slot(SlotError::Empty))
+        );
+    }
+}
@@ -0,0 +1,122 @@
+//! Code for controlled modification, placing unsafety on the initialization/changing function.
+//!
+//! This produces better code at the cost of safety.
+//!
+//! # Bootloader writes
+//!
+//! Bootloader request structures, such as those of the Limine boot protocol, are statics that the
+//! bootloader writes to before the kernel runs. Rust code never observes those writes happen, so
+//! a plain `static` would let the compiler assume that the values it was compiled with are still
+//! in place and fold reads of them into constants.
+//!
+//! Wrapping such statics in a [`ControlledModificationCell`] places them in an [`UnsafeCell`],
+//! which tells the compiler that the contents may change behind shared references and keeps the
+//! statics out of read-only memory. Reading them is sound because every bootloader write completes
+//! before the kernel's first instruction executes, so the write happens-before every read and no
+//! reference is ever live while the contents change. Fields that only the bootloader writes,
+//! like the Limine base revision word and response pointers, should still be read with
+//! [`ControlledModificationCell::read_volatile()`] or [`core::ptr::read_volatile()`] so that the
+//! read is emitted as written rather than derived from the initializer.
+//!
+//! # Capabilities
+//!
+//! The [`capability`] and [`cnode`] modules hold the capability slots through which all kernel
+//! objects are eventually accessed, and the tables of slots that contain them. The [`untyped`]
+//! module tracks the memory from which those objects are carved.
+
+use core::cell::UnsafeCell;
+
+pub mod capability;
+pub mod cnode;
+pub mod untyped;
+
+/// Wrapper struct for variables that are modified in a thread safe manner that is not visible to
+/// Rust code.
+#[derive(Debug)]
+pub struct ControlledModificationCell<T: ?Sized> {
+    /// The variable that is modified.
+    value: UnsafeCell<T>,
+}
+
+// SAFETY:
+//
+// Since all mutations are thread-safe, and [`T`] is [`Send`], this is safe.
+unsafe impl<T: ?Sized + Send> Sync for ControlledModificationCell<T> {}
+// SAFETY:
+//
+// It is safe to send this across thread boundaries when [`T`] is safe to send across
+// because all mutations are thread-safe, and [`T`] is safe to send.
+unsafe impl<T: ?Sized + Send> Send for ControlledModificationCell<T> {}
+
+impl<T> ControlledModificationCell<T> {
+    /// Constructs a new instance of a [`ControlledModificationCell`].
+    pub const fn new(value: T) -> Self {
+        Self {
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    /// Returns a immutable reference to the contained value.
+    pub fn get(&self) -> &T {
+        // SAFETY:
+        // This item is only modified in a thread-safe manner.
+        unsafe { &*self.value.get() }
+    }
+
+    /// Returns a mutable reference to the wrapped value.
+    ///
+    /// This is intended for single-threaded boot contexts that set up the value before anything
+    /// else can observe it.
+    ///
+    /// # Safety
+    /// - The lifetime of the mutable reference produced by this function does not overlap with the
+    ///     lifetime of any other reference, mutable or immutable, pointing to this value.
+    /// - All synchronization necessary to soundly mutate this value must be performed outside of
+    ///     this function.
+    /// - This modification is performed in a thread-safe manner if this
+    ///     [`ControlledModificationCell`] has been transferred cross thread.
+    #[allow(clippy::mut_from_ref)]
+    pub unsafe fn get_mut(&self) -> &mut T {
+        // SAFETY:
+        // According to the invariants of [`Self::get_mut()`], creating a mutable reference is
+        // safe.
+        unsafe { &mut *self.value.get() }
+    }
+
+    /// Returns a raw pointer to the wrapped value.
+    ///
+    /// This is suitable for handing the value to code outside of Rust, such as a bootloader that
+    /// locates it in a dedicated section.
+    pub const fn as_ptr(&self) -> *const T {
+        self.value.get()
+    }
+
+    /// Returns a mutable raw pointer to the wrapped value.
+    ///
+    /// Writing through the pointer is subject to the same requirements as
+    /// [`ControlledModificationCell::get_mut()`].
+    pub const fn as_mut_ptr(&self) -> *mut T {
+        self.value.get()
+    }
+}
+
+impl<T: Copy> ControlledModificationCell<T> {
+    /// Copies the stored value.
+    pub fn copy(&self) -> T {
+        // SAFETY:
+        // This item is only modified in a thread-safe manner.
+        unsafe { self.value.get().read() }
+    }
+
+    /// Copies the stored value with a volatile read, so that the compiler cannot substitute the
+    /// value the cell was initialized with.
+    ///
+    /// This is intended for values written by code outside of Rust, such as a bootloader, before
+    /// the kernel runs.
+    pub fn read_volatile(&self) -> T {
+        // SAFETY:
+        // The pointer is valid and aligned, and this item is only modified in a thread-safe
+        // manner.
+        unsafe { self.value.get().read_volatile() }
+    }
+}
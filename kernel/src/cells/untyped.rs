@@ -0,0 +1,495 @@
+//! Untyped memory, from which every other kernel object is carved.
+//!
+//! An [`Untyped`] covers a [`FrameRange`] and hands out objects from its start upwards, tracking
+//! the number of frames handed out so far in a watermark so that no frame is ever handed out
+//! twice. Each object is aligned to its own size, which is always a power of two number of
+//! frames, so the frames skipped to align an object are wasted until the [`Untyped`] is reset.
+//!
+//! Resetting an [`Untyped`] is only sound once none of the objects carved out of it remain, which
+//! [`CNode::revoke()`][crate::cells::cnode::CNode::revoke] checks with [`Untyped::is_parent_of()`].
+
+use core::fmt;
+
+use crate::{
+    arch::{Frame, FrameRange, PhysicalAddress},
+    cells::{
+        capability::CapabilitySlot,
+        cnode::{self, MAX_SIZE_BITS},
+    },
+};
+
+/// A region of physical memory from which other kernel objects are created.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub struct Untyped {
+    /// The physical memory covered by this [`Untyped`].
+    frame_range: FrameRange,
+    /// The number of frames at the start of [`Untyped::frame_range`] that have been handed out.
+    watermark: u64,
+}
+
+impl Untyped {
+    /// Creates a pristine [`Untyped`] covering `frame_range`.
+    pub const fn new(frame_range: FrameRange) -> Self {
+        Self {
+            frame_range,
+            watermark: 0,
+        }
+    }
+
+    /// Returns the physical memory covered by this [`Untyped`].
+    pub const fn frame_range(&self) -> FrameRange {
+        self.frame_range
+    }
+
+    /// Returns the number of frames at the start of this [`Untyped`] that have been handed out,
+    /// including those skipped for alignment.
+    pub const fn watermark(&self) -> u64 {
+        self.watermark
+    }
+
+    /// Returns the number of frames of this [`Untyped`] that have not been handed out.
+    pub const fn free_frames(&self) -> u64 {
+        self.frame_range.size_in_frames() - self.watermark
+    }
+
+    /// Returns `true` if no objects have been carved out of this [`Untyped`] since it was created
+    /// or last reset.
+    pub const fn is_pristine(&self) -> bool {
+        self.watermark == 0
+    }
+
+    /// Carves `count` objects of the given [`ObjectKind`] out of this [`Untyped`], returning the
+    /// [`FrameRange`] of each.
+    ///
+    /// The objects are placed back to back after the watermark, with the first aligned to the
+    /// size of an object. On failure, this [`Untyped`] is left unchanged.
+    ///
+    /// # Errors
+    /// - [`UntypedError::InvalidCount`] if `count` is zero.
+    /// - [`UntypedError::InvalidSize`] if `kind` is a [`CNode`][cnode::CNode] larger than
+    ///   [`MAX_SIZE_BITS`].
+    /// - [`UntypedError::Exhausted`] if the objects do not fit in the remaining memory.
+    pub fn retype(&mut self, kind: ObjectKind, count: u64) -> Result<Retyped, UntypedError> {
+        if count == 0 {
+            return Err(UntypedError::InvalidCount);
+        }
+        let object_frames = kind.size_in_frames()?;
+
+        let range_start = self.frame_range.start().number();
+        let range_end = range_start + self.frame_range.size_in_frames();
+        let start = (range_start + self.watermark)
+            .checked_next_multiple_of(object_frames)
+            .ok_or(UntypedError::Exhausted)?;
+        let end = object_frames
+            .checked_mul(count)
+            .and_then(|size| start.checked_add(size))
+            .ok_or(UntypedError::Exhausted)?;
+        if end > range_end {
+            return Err(UntypedError::Exhausted);
+        }
+
+        self.watermark = end - range_start;
+        Ok(Retyped {
+            next: start,
+            object_frames,
+            remaining: count,
+        })
+    }
+
+    /// Returns this [`Untyped`] to its pristine state, making all of its memory available again.
+    ///
+    /// The caller is responsible for ensuring that no object carved out of this [`Untyped`]
+    /// remains.
+    pub fn reset(&mut self) {
+        self.watermark = 0;
+    }
+
+    /// Returns `true` if `capability` refers to an object carved out of this [`Untyped`].
+    pub fn is_parent_of(&self, capability: &CapabilitySlot) -> bool {
+        if self.watermark == 0 {
+            return false;
+        }
+        let used = FrameRange::inclusive_range(
+            self.frame_range.start(),
+            frame_at(self.frame_range.start().number() + self.watermark - 1),
+        );
+
+        match capability {
+            CapabilitySlot::Frame { frame, .. } => used.contains_address(frame.base_address()),
+            CapabilitySlot::CNode { frame_range, .. } => used.overlaps(frame_range),
+            CapabilitySlot::Empty
+            | CapabilitySlot::Untyped(_)
+            | CapabilitySlot::IrqHandler { .. } => false,
+        }
+    }
+}
+
+/// The kinds of object that can be carved out of an [`Untyped`].
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum ObjectKind {
+    /// A single frame of memory.
+    Frame,
+    /// A [`CNode`][cnode::CNode] with `1 << size_bits` slots.
+    CNode {
+        /// The base-2 logarithm of the number of slots.
+        size_bits: u8,
+    },
+}
+
+impl ObjectKind {
+    /// Returns the number of frames occupied by an object of this [`ObjectKind`], which is also
+    /// its alignment in frames.
+    ///
+    /// # Errors
+    /// Returns [`UntypedError::InvalidSize`] if this is a [`CNode`][cnode::CNode] larger than
+    /// [`MAX_SIZE_BITS`].
+    pub fn size_in_frames(self) -> Result<u64, UntypedError> {
+        match self {
+            Self::Frame => Ok(1),
+            Self::CNode { size_bits } if size_bits > MAX_SIZE_BITS => {
+                Err(UntypedError::InvalidSize(size_bits))
+            }
+            Self::CNode { size_bits } => Ok(cnode::frames_for(size_bits).next_power_of_two()),
+        }
+    }
+}
+
+impl fmt::Display for ObjectKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Frame => f.write_str("frame"),
+            Self::CNode { size_bits } => write!(f, "CNode of 2^{size_bits} slots"),
+        }
+    }
+}
+
+/// An [`Iterator`] over the [`FrameRange`]s of the objects carved out by [`Untyped::retype()`].
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+pub struct Retyped {
+    /// The number of the first frame of the next object.
+    next: u64,
+    /// The number of frames occupied by each object.
+    object_frames: u64,
+    /// The number of objects not yet returned.
+    remaining: u64,
+}
+
+impl Iterator for Retyped {
+    type Item = FrameRange;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+
+        let start = self.next;
+        self.next += self.object_frames;
+        self.remaining -= 1;
+
+        Some(FrameRange::inclusive_range(
+            frame_at(start),
+            frame_at(self.next - 1),
+        ))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = usize::try_from(self.remaining).unwrap_or(usize::MAX);
+        (remaining, usize::try_from(self.remaining).ok())
+    }
+}
+
+/// Returns the [`Frame`] with the given `number`.
+const fn frame_at(number: u64) -> Frame {
+    Frame::containing_address(PhysicalAddress::new_masked(number * Frame::FRAME_SIZE))
+}
+
+/// Various errors that can occur while operating on an [`Untyped`].
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum UntypedError {
+    /// The slot does not hold an untyped capability.
+    NotUntyped,
+    /// No objects were requested.
+    InvalidCount,
+    /// The requested `size_bits` of a [`CNode`][cnode::CNode] exceeds [`MAX_SIZE_BITS`].
+    InvalidSize(u8),
+    /// The requested objects do not fit in the remaining memory.
+    Exhausted,
+    /// Objects carved out of the untyped memory still exist.
+    HasChildren,
+}
+
+impl fmt::Display for UntypedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotUntyped => f.write_str("capability is not untyped memory"),
+            Self::InvalidCount => f.write_str("no objects requested"),
+            Self::InvalidSize(size_bits) => write!(
+                f,
+                "CNode of 2^{size_bits} slots exceeds the maximum of 2^{MAX_SIZE_BITS}"
+            ),
+            Self::Exhausted => f.write_str("untyped memory exhausted"),
+            Self::HasChildren => f.write_str("objects carved out of untyped memory still exist"),
+        }
+    }
+}
+
+#[cfg(feature = "ktest")]
+crate::kernel_test! {
+    fn retype_use_revoke() {
+        use crate::{
+            arch::direct_map,
+            cells::{
+                capability::{Rights, SlotError},
+                cnode::{root, CNodeError},
+            },
+        };
+
+        let mut root = root().expect("root CNode has not been set up").lock();
+        let untyped = (0..root.size())
+            .find(|&index| {
+                matches!(
+                    root.lookup(index),
+                    Ok(CapabilitySlot::Untyped(untyped))
+                        if untyped.is_pristine() && untyped.free_frames() >= 16
+                )
+            })
+            .expect("no pristine untyped memory of at least 16 frames");
+        let destination = root.size() - 3;
+
+        assert_eq!(
+            root.copy_with_diminished_rights(untyped, destination, Rights::ALL),
+            Err(CNodeError::Slot(SlotError::Uncopyable))
+        );
+
+        root.retype(untyped, ObjectKind::Frame, destination, 2)
+            .unwrap();
+        root.retype(untyped, ObjectKind::CNode { size_bits: 8 }, destination + 2, 1)
+            .unwrap();
+        assert_eq!(
+            root.retype(untyped, ObjectKind::Frame, destination, 1),
+            Err(CNodeError::Slot(SlotError::Occupied))
+        );
+
+        let Ok(CapabilitySlot::Frame { frame, rights }) = root.lookup(destination) else {
+            panic!("retype did not produce a frame capability");
+        };
+        assert_eq!(rights, Rights::ALL);
+        let Ok(CapabilitySlot::CNode { frame_range, .. }) = root.lookup(destination + 2) else {
+            panic!("retype did not produce a CNode capability");
+        };
+        let cnode_frames = ObjectKind::CNode { size_bits: 8 }.size_in_frames().unwrap();
+        assert_eq!(frame_range.size_in_frames(), cnode_frames);
+        assert_eq!(frame_range.start().number() % cnode_frames, 0);
+
+        let address = direct_map::phys_to_ptr::<u64>(frame.base_address());
+        // SAFETY:
+        // The frame was just carved out of untyped memory for this test and is covered by the
+        // direct map.
+        assert_eq!(unsafe { address.read_volatile() }, 0);
+        // SAFETY:
+        // The frame was just carved out of untyped memory for this test and is covered by the
+        // direct map.
+        unsafe { address.write_volatile(0xdead_beef) }
+
+        assert_eq!(
+            root.revoke(untyped),
+            Err(CNodeError::Untyped(UntypedError::HasChildren))
+        );
+        for index in destination..root.size() {
+            root.delete(index).unwrap();
+        }
+        root.revoke(untyped).unwrap();
+        assert!(matches!(
+            root.lookup(untyped),
+            Ok(CapabilitySlot::Untyped(untyped)) if untyped.is_pristine()
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::vec::Vec;
+
+    use super::*;
+
+    /// Returns an [`Untyped`] covering the `count` frames starting at frame number `start`.
+    fn untyped(start: u64, count: u64) -> Untyped {
+        Untyped::new(FrameRange::inclusive_range(
+            frame_at(start),
+            frame_at(start + count - 1),
+        ))
+    }
+
+    /// Returns the first frame number and size in frames of each range in `retyped`.
+    fn objects(retyped: Retyped) -> Vec<(u64, u64)> {
+        retyped
+            .map(|range| (range.start().number(), range.size_in_frames()))
+            .collect()
+    }
+
+    #[test]
+    fn frames_are_handed_out_back_to_back() {
+        let mut memory = untyped(16, 8);
+        assert!(memory.is_pristine());
+
+        let retyped = memory.retype(ObjectKind::Frame, 3).unwrap();
+        assert_eq!(retyped.size_hint(), (3, Some(3)));
+        assert_eq!(objects(retyped), [(16, 1), (17, 1), (18, 1)]);
+        assert_eq!(memory.watermark(), 3);
+        assert_eq!(memory.free_frames(), 5);
+
+        let retyped = memory.retype(ObjectKind::Frame, 1).unwrap();
+        assert_eq!(objects(retyped), [(19, 1)]);
+        assert!(!memory.is_pristine());
+    }
+
+    #[test]
+    fn aligned_start_wastes_nothing() {
+        let kind = ObjectKind::CNode { size_bits: 8 };
+        let object_frames = kind.size_in_frames().unwrap();
+        let start = object_frames * 4;
+        let mut memory = untyped(start, object_frames * 2);
+
+        let retyped = memory.retype(kind, 2).unwrap();
+        assert_eq!(
+            objects(retyped),
+            [
+                (start, object_frames),
+                (start + object_frames, object_frames)
+            ]
+        );
+        assert_eq!(memory.free_frames(), 0);
+    }
+
+    #[test]
+    fn unaligned_start_skips_to_the_object_alignment() {
+        let kind = ObjectKind::CNode {
+            size_bits: MAX_SIZE_BITS,
+        };
+        let object_frames = kind.size_in_frames().unwrap();
+        assert!(object_frames > 1);
+
+        let mut memory = untyped(13, object_frames * 3);
+        memory.retype(ObjectKind::Frame, 1).unwrap();
+
+        let retyped = memory.retype(kind, 1).unwrap();
+        let expected_start = 14u64.next_multiple_of(object_frames);
+        assert_eq!(objects(retyped), [(expected_start, object_frames)]);
+        assert_eq!(memory.watermark(), expected_start + object_frames - 13);
+
+        // Frames skipped for alignment are not handed out afterwards.
+        let retyped = memory.retype(ObjectKind::Frame, 1).unwrap();
+        assert_eq!(objects(retyped), [(expected_start + object_frames, 1)]);
+    }
+
+    #[test]
+    fn exhaustion_leaves_the_untyped_unchanged() {
+        let mut memory = untyped(0, 4);
+        memory.retype(ObjectKind::Frame, 3).unwrap();
+        let before = memory;
+
+        assert_eq!(
+            memory.retype(ObjectKind::Frame, 2),
+            Err(UntypedError::Exhausted)
+        );
+        assert_eq!(
+            memory.retype(ObjectKind::Frame, u64::MAX),
+            Err(UntypedError::Exhausted)
+        );
+        assert_eq!(memory, before);
+
+        memory.retype(ObjectKind::Frame, 1).unwrap();
+        assert_eq!(memory.free_frames(), 0);
+        assert_eq!(
+            memory.retype(ObjectKind::Frame, 1),
+            Err(UntypedError::Exhausted)
+        );
+    }
+
+    #[test]
+    fn alignment_can_exhaust_the_untyped() {
+        let kind = ObjectKind::CNode {
+            size_bits: MAX_SIZE_BITS,
+        };
+        let object_frames = kind.size_in_frames().unwrap();
+        // Enough frames for one object, but not once the start is aligned.
+        let mut memory = untyped(1, object_frames);
+
+        assert_eq!(memory.retype(kind, 1), Err(UntypedError::Exhausted));
+        assert!(memory.is_pristine());
+    }
+
+    #[test]
+    fn invalid_requests_are_rejected() {
+        let mut memory = untyped(0, 4);
+
+        assert_eq!(
+            memory.retype(ObjectKind::Frame, 0),
+            Err(UntypedError::InvalidCount)
+        );
+        assert_eq!(
+            memory.retype(
+                ObjectKind::CNode {
+                    size_bits: MAX_SIZE_BITS + 1
+                },
+                1
+            ),
+            Err(UntypedError::InvalidSize(MAX_SIZE_BITS + 1))
+        );
+        assert!(memory.is_pristine());
+    }
+
+    #[test]
+    fn cnode_sizes_are_powers_of_two_frames() {
+        assert_eq!(ObjectKind::Frame.size_in_frames(), Ok(1));
+        for size_bits in 0..=MAX_SIZE_BITS {
+            let frames = ObjectKind::CNode { size_bits }.size_in_frames().unwrap();
+            assert!(frames.is_power_of_two());
+            assert!(frames >= cnode::frames_for(size_bits));
+        }
+        assert_eq!(ObjectKind::CNode { size_bits: 0 }.size_in_frames(), Ok(1));
+    }
+
+    #[test]
+    fn reset_after_revoke_makes_everything_available() {
+        let mut memory = untyped(8, 8);
+        let frames: Vec<_> = memory.retype(ObjectKind::Frame, 8).unwrap().collect();
+        assert_eq!(memory.free_frames(), 0);
+
+        let child = CapabilitySlot::Frame {
+            frame: frames[7].start(),
+            rights: crate::cells::capability::Rights::ALL,
+        };
+        assert!(memory.is_parent_of(&child));
+
+        memory.reset();
+        assert!(memory.is_pristine());
+        assert_eq!(memory.free_frames(), 8);
+        assert!(!memory.is_parent_of(&child));
+
+        let retyped = memory.retype(ObjectKind::Frame, 8).unwrap();
+        assert_eq!(objects(retyped).first(), Some(&(8, 1)));
+    }
+
+    #[test]
+    fn parent_covers_only_handed_out_frames() {
+        let mut memory = untyped(0, 16);
+        memory.retype(ObjectKind::Frame, 2).unwrap();
+
+        let frame = |number| CapabilitySlot::Frame {
+            frame: frame_at(number),
+            rights: crate::cells::capability::Rights::READ,
+        };
+        assert!(memory.is_parent_of(&frame(1)));
+        assert!(!memory.is_parent_of(&frame(2)));
+        assert!(!memory.is_parent_of(&frame(100)));
+
+        let cnode = CapabilitySlot::CNode {
+            frame_range: FrameRange::inclusive_range(frame_at(1), frame_at(4)),
+            size_bits: 0,
+        };
+        assert!(memory.is_parent_of(&cnode));
+        assert!(!memory.is_parent_of(&CapabilitySlot::Untyped(untyped(0, 1))));
+        assert!(!memory.is_parent_of(&CapabilitySlot::IrqHandler { vector: 0x30 }));
+    }
+}
@@ -0,0 +1,76 @@
+//! Parsing of the kernel command line.
+//!
+//! The command line is a whitespace separated list of options, each either a bare `key` or a
+//! `key=value` pair.
+
+/// Returns an [`Iterator`] over the `(key, value)` pairs in `cmdline`.
+///
+/// Options without a `=` yield an empty value.
+pub fn options(cmdline: &str) -> impl Iterator<Item = (&str, &str)> {
+    cmdline
+        .split_ascii_whitespace()
+        .map(|option| option.split_once('=').unwrap_or((option, "")))
+}
+
+/// Returns the value of the last occurrence of `key` in `cmdline`, or [`None`] if `key` is not
+/// present.
+pub fn get_str<'cmdline>(cmdline: &'cmdline str, key: &str) -> Option<&'cmdline str> {
+    options(cmdline)
+        .filter(|&(option, _)| option == key)
+        .last()
+        .map(|(_, value)| value)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::vec::Vec;
+
+    use super::*;
+
+    #[test]
+    fn options_split_on_whitespace_and_the_first_equals_sign() {
+        let cmdline = "  quiet loglevel=debug\tlog=mm=trace,sched=warn\n\nserial= ";
+        assert_eq!(
+            options(cmdline).collect::<Vec<_>>(),
+            [
+                ("quiet", ""),
+                ("loglevel", "debug"),
+                ("log", "mm=trace,sched=warn"),
+                ("serial", ""),
+            ]
+        );
+    }
+
+    #[test]
+    fn empty_command_lines_have_no_options() {
+        assert_eq!(options("").count(), 0);
+        assert_eq!(options(" \t\n ").count(), 0);
+    }
+
+    #[test]
+    fn options_keep_their_order_and_duplicates() {
+        assert_eq!(
+            options("a=1 b a=2 =3").collect::<Vec<_>>(),
+            [("a", "1"), ("b", ""), ("a", "2"), ("", "3")]
+        );
+    }
+
+    #[test]
+    fn get_str_returns_the_last_occurrence() {
+        let cmdline = "loglevel=warn quiet loglevel=trace";
+        assert_eq!(get_str(cmdline, "loglevel"), Some("trace"));
+        assert_eq!(get_str(cmdline, "quiet"), Some(""));
+        assert_eq!(get_str(cmdline, "serial"), None);
+    }
+
+    #[test]
+    fn get_str_matches_whole_keys() {
+        let cmdline = "log=mm=trace log.serial=debug logtarget=off";
+        assert_eq!(get_str(cmdline, "log"), Some("mm=trace"));
+        assert_eq!(get_str(cmdline, "log.serial"), Some("debug"));
+        assert_eq!(get_str(cmdline, "log."), None);
+        assert_eq!(get_str(cmdline, "logtarget"), Some("off"));
+        assert_eq!(get_str(cmdline, "target"), None);
+        assert_eq!(get_str(cmdline, ""), None);
+    }
+}
@@ -0,0 +1,74 @@
+//! Bitmap fonts in the PC Screen Font (PSF1) format.
+
+/// The magic number at the start of a PSF1 file.
+const PSF1_MAGIC: [u8; 2] = [0x36, 0x04];
+/// The bit of the PSF1 mode byte indicating that the font contains 512 glyphs instead of 256.
+const PSF1_MODE_512: u8 = 0x01;
+/// The size, in bytes, of the PSF1 header.
+const PSF1_HEADER_SIZE: usize = 4;
+
+/// The built-in 8x16 font, derived from the public domain X11 `misc-fixed` 8x13 font padded to
+/// 16 rows and covering ISO 8859-1.
+pub const BUILTIN: Font<'static> = match Font::parse_psf1(include_bytes!("font.psf")) {
+    Some(font) => font,
+    None => panic!("invalid built-in font"),
+};
+
+/// A bitmap font whose glyphs are 8 pixels wide.
+#[derive(Clone, Copy, Debug)]
+pub struct Font<'data> {
+    /// The glyph bitmaps, each `height` bytes with the most significant bit as the leftmost pixel.
+    glyphs: &'data [u8],
+    /// The number of glyphs.
+    glyph_count: usize,
+    /// The height, in pixels, of each glyph.
+    height: usize,
+}
+
+impl<'data> Font<'data> {
+    /// The width, in pixels, of each glyph.
+    pub const WIDTH: usize = 8;
+
+    /// Parses a PSF1 font, returning [`None`] if `data` is not a valid PSF1 font.
+    pub const fn parse_psf1(data: &'data [u8]) -> Option<Self> {
+        if data.len() < PSF1_HEADER_SIZE || data[0] != PSF1_MAGIC[0] || data[1] != PSF1_MAGIC[1] {
+            return None;
+        }
+
+        let glyph_count = if data[2] & PSF1_MODE_512 == PSF1_MODE_512 {
+            512
+        } else {
+            256
+        };
+        let height = data[3] as usize;
+        if height == 0 {
+            return None;
+        }
+
+        let (_, glyphs) = data.split_at(PSF1_HEADER_SIZE);
+        if glyphs.len() < glyph_count * height {
+            return None;
+        }
+
+        Some(Self {
+            glyphs,
+            glyph_count,
+            height,
+        })
+    }
+
+    /// Returns the height, in pixels, of each glyph.
+    pub const fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Returns the rows of the glyph for `c`, or of `?` if the font has no glyph for `c`.
+    pub fn glyph(&self, c: char) -> &'data [u8] {
+        let index = match c as usize {
+            index if index < self.glyph_count => index,
+            _ => '?' as usize,
+        };
+
+        &self.glyphs[index * self.height..(index + 1) * self.height]
+    }
+}
@@ -0,0 +1,531 @@
+//! A text console rendered directly to a linear framebuffer.
+//!
+//! The [`Console`] draws text using an 8 pixel wide bitmap [`Font`], wrapping at the right edge
+//! and scrolling the contents of the framebuffer up by one line of text once the bottom is
+//! reached. Only 32 bits per pixel layouts with 8 bit color channels are supported.
+
+use core::fmt;
+
+use font::Font;
+
+pub mod font;
+
+/// The number of bytes per pixel of every supported layout.
+const BYTES_PER_PIXEL: usize = 4;
+/// The number of columns between tab stops.
+const TAB_WIDTH: usize = 8;
+
+/// A color with 8 bits per channel.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub struct Color {
+    /// The intensity of the red channel.
+    pub red: u8,
+    /// The intensity of the green channel.
+    pub green: u8,
+    /// The intensity of the blue channel.
+    pub blue: u8,
+}
+
+impl Color {
+    /// Black.
+    pub const BLACK: Self = Self::new(0, 0, 0);
+    /// Light gray.
+    pub const LIGHT_GRAY: Self = Self::new(0xAA, 0xAA, 0xAA);
+
+    /// Creates a new [`Color`] from its channel intensities.
+    pub const fn new(red: u8, green: u8, blue: u8) -> Self {
+        Self { red, green, blue }
+    }
+}
+
+/// The arrangement of color channels within a 32 bit pixel.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub struct PixelFormat {
+    /// The bit offset of the red channel.
+    red_shift: u8,
+    /// The bit offset of the green channel.
+    green_shift: u8,
+    /// The bit offset of the blue channel.
+    blue_shift: u8,
+}
+
+impl PixelFormat {
+    /// The layout with blue in the lowest byte, as used by most UEFI framebuffers.
+    pub const BGR: Self = Self {
+        red_shift: 16,
+        green_shift: 8,
+        blue_shift: 0,
+    };
+    /// The layout with red in the lowest byte.
+    pub const RGB: Self = Self {
+        red_shift: 0,
+        green_shift: 8,
+        blue_shift: 16,
+    };
+
+    /// Creates a [`PixelFormat`] from the bits per pixel and the `(shift, size)` of each color
+    /// channel.
+    ///
+    /// # Errors
+    /// - [`ConsoleError::UnsupportedBitsPerPixel`]: `bpp` is not 32.
+    /// - [`ConsoleError::UnsupportedChannel`]: a channel is not 8 bits wide and byte aligned.
+    pub const fn from_masks(
+        bpp: u8,
+        red: (u8, u8),
+        green: (u8, u8),
+        blue: (u8, u8),
+    ) -> Result<Self, ConsoleError> {
+        if bpp != 32 {
+            return Err(ConsoleError::UnsupportedBitsPerPixel(bpp));
+        }
+
+        let channels = [red, green, blue];
+        let mut index = 0;
+        while index < channels.len() {
+            let (shift, size) = channels[index];
+            if size != 8 || shift % 8 != 0 || shift > 24 {
+                return Err(ConsoleError::UnsupportedChannel);
+            }
+            index += 1;
+        }
+
+        Ok(Self {
+            red_shift: red.0,
+            green_shift: green.0,
+            blue_shift: blue.0,
+        })
+    }
+
+    /// Returns the pixel value representing `color`.
+    pub const fn encode(self, color: Color) -> u32 {
+        (color.red as u32) << self.red_shift
+            | (color.green as u32) << self.green_shift
+            | (color.blue as u32) << self.blue_shift
+    }
+}
+
+/// The dimensions and pixel format of a framebuffer.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub struct FramebufferLayout {
+    /// The width, in pixels, of the framebuffer.
+    pub width: usize,
+    /// The height, in pixels, of the framebuffer.
+    pub height: usize,
+    /// The number of bytes between the starts of consecutive rows.
+    pub pitch: usize,
+    /// The arrangement of color channels within each pixel.
+    pub format: PixelFormat,
+}
+
+/// A text console drawing to a framebuffer.
+pub struct Console<'fb> {
+    /// The pixels of the framebuffer.
+    buffer: &'fb mut [u8],
+    /// The layout of `buffer`.
+    layout: FramebufferLayout,
+    /// The font used to draw text.
+    font: Font<'static>,
+    /// The column at which the next character is drawn.
+    column: usize,
+    /// The row at which the next character is drawn.
+    row: usize,
+    /// The pixel value used for text.
+    foreground: u32,
+    /// The pixel value used behind text.
+    background: u32,
+}
+
+impl<'fb> Console<'fb> {
+    /// Creates a new [`Console`] drawing to `buffer` with the built-in font, and clears it.
+    ///
+    /// # Errors
+    /// - [`ConsoleError::BufferTooSmall`]: `buffer` cannot hold `layout`, or `layout` cannot hold
+    ///   a single character.
+    pub fn new(buffer: &'fb mut [u8], layout: FramebufferLayout) -> Result<Self, ConsoleError> {
+        let font = font::BUILTIN;
+        if layout.pitch < layout.width * BYTES_PER_PIXEL
+            || buffer.len() < layout.pitch * layout.height
+            || layout.width < Font::WIDTH
+            || layout.height < font.height()
+        {
+            return Err(ConsoleError::BufferTooSmall);
+        }
+
+        let mut console = Self {
+            buffer,
+            layout,
+            font,
+            column: 0,
+            row: 0,
+            foreground: layout.format.encode(Color::LIGHT_GRAY),
+            background: layout.format.encode(Color::BLACK),
+        };
+        console.clear();
+
+        Ok(console)
+    }
+
+    /// Returns the number of characters that fit on a line.
+    pub fn columns(&self) -> usize {
+        self.layout.width / Font::WIDTH
+    }
+
+    /// Returns the number of lines that fit on the screen.
+    pub fn rows(&self) -> usize {
+        self.layout.height / self.font.height()
+    }
+
+    /// Returns the `(column, row)` at which the next character is drawn.
+    pub fn cursor(&self) -> (usize, usize) {
+        (self.column, self.row)
+    }
+
+    /// Sets the colors used for subsequent text.
+    pub fn set_colors(&mut self, foreground: Color, background: Color) {
+        self.foreground = self.layout.format.encode(foreground);
+        self.background = self.layout.format.encode(background);
+    }
+
+    /// Fills the screen with the background color and moves the cursor to the top left.
+    pub fn clear(&mut self) {
+        for y in 0..self.layout.height {
+            self.fill_line(y);
+        }
+
+        self.column = 0;
+        self.row = 0;
+    }
+
+    /// Writes `c` at the cursor, handling `\n`, `\r`, and `\t`.
+    ///
+    /// Other control characters are ignored.
+    pub fn put_char(&mut self, c: char) {
+        match c {
+            '\n' => self.newline(),
+            '\r' => self.column = 0,
+            '\t' => {
+                let spaces = TAB_WIDTH - self.column % TAB_WIDTH;
+                for _ in 0..spaces {
+                    self.put_char(' ');
+                }
+            }
+            c if c.is_control() => {}
+            c => {
+                if self.column == self.columns() {
+                    self.newline();
+                }
+
+                self.draw_glyph(self.column, self.row, c);
+                self.column += 1;
+            }
+        }
+    }
+
+    /// Draws the glyph for `c` in the cell at `column` and `row`.
+    fn draw_glyph(&mut self, column: usize, row: usize, c: char) {
+        let glyph = self.font.glyph(c);
+        let x = column * Font::WIDTH;
+        let y = row * self.font.height();
+
+        for (line, &bits) in glyph.iter().enumerate() {
+            let start = (y + line) * self.layout.pitch + x * BYTES_PER_PIXEL;
+            let pixels = &mut self.buffer[start..start + Font::WIDTH * BYTES_PER_PIXEL];
+            for (bit, pixel) in pixels.chunks_exact_mut(BYTES_PER_PIXEL).enumerate() {
+                let value = if bits & (0x80 >> bit) != 0 {
+                    self.foreground
+                } else {
+                    self.background
+                };
+                pixel.copy_from_slice(&value.to_le_bytes());
+            }
+        }
+    }
+
+    /// Moves the cursor to the start of the next line, scrolling if it is on the last line.
+    fn newline(&mut self) {
+        self.column = 0;
+        if self.row + 1 < self.rows() {
+            self.row += 1;
+        } else {
+            self.scroll();
+        }
+    }
+
+    /// Moves every line of text up by one and clears the last line.
+    fn scroll(&mut self) {
+        let line_height = self.font.height();
+        let line_bytes = line_height * self.layout.pitch;
+        let text_bytes = self.rows() * line_bytes;
+
+        self.buffer.copy_within(line_bytes..text_bytes, 0);
+        for y in (self.rows() - 1) * line_height..self.rows() * line_height {
+            self.fill_line(y);
+        }
+    }
+
+    /// Fills pixel row `y` with the background color.
+    fn fill_line(&mut self, y: usize) {
+        let start = y * self.layout.pitch;
+        let pixels = &mut self.buffer[start..start + self.layout.width * BYTES_PER_PIXEL];
+        for pixel in pixels.chunks_exact_mut(BYTES_PER_PIXEL) {
+            pixel.copy_from_slice(&self.background.to_le_bytes());
+        }
+    }
+}
+
+impl fmt::Write for Console<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            self.put_char(c);
+        }
+
+        Ok(())
+    }
+}
+
+/// Various errors that can occur while creating a [`Console`].
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum ConsoleError {
+    /// The framebuffer does not use 32 bits per pixel.
+    UnsupportedBitsPerPixel(u8),
+    /// A color channel of the framebuffer is not 8 bits wide and byte aligned.
+    UnsupportedChannel,
+    /// The framebuffer is too small to hold its layout or a single character.
+    BufferTooSmall,
+}
+
+impl fmt::Display for ConsoleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnsupportedBitsPerPixel(bpp) => {
+                write!(f, "unsupported pixel format: {bpp} bits per pixel")
+            }
+            Self::UnsupportedChannel => {
+                f.write_str("unsupported pixel format: color channel layout")
+            }
+            Self::BufferTooSmall => f.write_str("framebuffer too small"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{vec, vec::Vec};
+
+    use super::*;
+
+    /// A layout of 10 columns by 3 rows of text, with padding at the end of every pixel row.
+    const LAYOUT: FramebufferLayout = FramebufferLayout {
+        width: 10 * Font::WIDTH,
+        height: 3 * 16,
+        pitch: 10 * Font::WIDTH * BYTES_PER_PIXEL + 16,
+        format: PixelFormat::BGR,
+    };
+    /// The color of text.
+    const FOREGROUND: Color = Color::new(0x12, 0x34, 0x56);
+    /// The color behind text.
+    const BACKGROUND: Color = Color::new(0x01, 0x02, 0x03);
+
+    /// Returns a framebuffer large enough for [`LAYOUT`].
+    fn framebuffer() -> Vec<u8> {
+        vec![0xFF; LAYOUT.pitch * LAYOUT.height]
+    }
+
+    /// Returns a [`Console`] drawing to `buffer` in [`FOREGROUND`] on [`BACKGROUND`].
+    fn console(buffer: &mut [u8]) -> Console<'_> {
+        let mut console = Console::new(buffer, LAYOUT).unwrap();
+        console.set_colors(FOREGROUND, BACKGROUND);
+        console.clear();
+        console
+    }
+
+    /// Returns the pixel at `(x, y)` of `console`.
+    fn pixel(console: &Console, x: usize, y: usize) -> u32 {
+        let start = y * console.layout.pitch + x * BYTES_PER_PIXEL;
+        u32::from_le_bytes(
+            *console.buffer[start..start + BYTES_PER_PIXEL]
+                .first_chunk()
+                .unwrap(),
+        )
+    }
+
+    /// Returns `true` if the cell at `column` and `row` of `console` shows `c`.
+    fn shows(console: &Console, column: usize, row: usize, c: char) -> bool {
+        let foreground = PixelFormat::BGR.encode(FOREGROUND);
+        let background = PixelFormat::BGR.encode(BACKGROUND);
+        let height = console.font.height();
+
+        console
+            .font
+            .glyph(c)
+            .iter()
+            .enumerate()
+            .all(|(line, &bits)| {
+                (0..Font::WIDTH).all(|bit| {
+                    let expected = if bits & (0x80 >> bit) != 0 {
+                        foreground
+                    } else {
+                        background
+                    };
+                    pixel(console, column * Font::WIDTH + bit, row * height + line) == expected
+                })
+            })
+    }
+
+    #[test]
+    fn pixel_formats_from_masks() {
+        assert_eq!(
+            PixelFormat::from_masks(32, (0, 8), (8, 8), (16, 8)),
+            Ok(PixelFormat::RGB)
+        );
+        assert_eq!(
+            PixelFormat::from_masks(32, (16, 8), (8, 8), (0, 8)),
+            Ok(PixelFormat::BGR)
+        );
+        assert_eq!(
+            PixelFormat::from_masks(24, (16, 8), (8, 8), (0, 8)),
+            Err(ConsoleError::UnsupportedBitsPerPixel(24))
+        );
+        for (red, green, blue) in [
+            ((16, 5), (8, 6), (0, 5)),
+            ((20, 8), (8, 8), (0, 8)),
+            ((32, 8), (8, 8), (0, 8)),
+            ((16, 8), (8, 8), (0, 10)),
+        ] {
+            assert_eq!(
+                PixelFormat::from_masks(32, red, green, blue),
+                Err(ConsoleError::UnsupportedChannel)
+            );
+        }
+    }
+
+    #[test]
+    fn pixel_formats_encode_channels() {
+        let color = Color::new(0x11, 0x22, 0x33);
+        assert_eq!(PixelFormat::RGB.encode(color), 0x0033_2211);
+        assert_eq!(PixelFormat::BGR.encode(color), 0x0011_2233);
+        assert_eq!(PixelFormat::BGR.encode(Color::BLACK), 0);
+
+        // A layout with padding in the lowest byte.
+        let format = PixelFormat::from_masks(32, (24, 8), (16, 8), (8, 8)).unwrap();
+        assert_eq!(format.encode(color), 0x1122_3300);
+    }
+
+    #[test]
+    fn new_rejects_unsupported_layouts() {
+        let mut buffer = framebuffer();
+        let too_small = [
+            FramebufferLayout {
+                pitch: LAYOUT.width * BYTES_PER_PIXEL - 1,
+                ..LAYOUT
+            },
+            FramebufferLayout {
+                height: LAYOUT.height + 1,
+                ..LAYOUT
+            },
+            FramebufferLayout {
+                width: Font::WIDTH - 1,
+                ..LAYOUT
+            },
+            FramebufferLayout {
+                height: font::BUILTIN.height() - 1,
+                ..LAYOUT
+            },
+        ];
+        for layout in too_small {
+            assert_eq!(
+                Console::new(&mut buffer, layout).err(),
+                Some(ConsoleError::BufferTooSmall),
+                "{layout:?}"
+            );
+        }
+
+        let console = Console::new(&mut buffer, LAYOUT).unwrap();
+        assert_eq!((console.columns(), console.rows()), (10, 3));
+    }
+
+    #[test]
+    fn new_clears_to_the_background_but_not_the_padding() {
+        let mut buffer = framebuffer();
+        let console = Console::new(&mut buffer, LAYOUT).unwrap();
+
+        assert_eq!(console.cursor(), (0, 0));
+        assert_eq!(pixel(&console, 0, 0), PixelFormat::BGR.encode(Color::BLACK));
+        assert_eq!(
+            pixel(&console, LAYOUT.width - 1, LAYOUT.height - 1),
+            PixelFormat::BGR.encode(Color::BLACK)
+        );
+        assert_eq!(pixel(&console, LAYOUT.width, 0), 0xFFFF_FFFF);
+    }
+
+    #[test]
+    fn put_char_draws_the_glyph() {
+        let mut buffer = framebuffer();
+        let mut console = console(&mut buffer);
+
+        console.put_char('A');
+        console.put_char('g');
+        assert_eq!(console.cursor(), (2, 0));
+        assert!(shows(&console, 0, 0, 'A'));
+        assert!(shows(&console, 1, 0, 'g'));
+        assert!(shows(&console, 2, 0, ' '));
+        assert!(!shows(&console, 0, 0, 'B'));
+    }
+
+    #[test]
+    fn control_characters_move_the_cursor() {
+        let mut buffer = framebuffer();
+        let mut console = console(&mut buffer);
+
+        console.put_char('a');
+        console.put_char('b');
+        console.put_char('\r');
+        assert_eq!(console.cursor(), (0, 0));
+        console.put_char('c');
+        assert!(shows(&console, 0, 0, 'c'));
+        assert!(shows(&console, 1, 0, 'b'));
+
+        console.put_char('\n');
+        assert_eq!(console.cursor(), (0, 1));
+        console.put_char('\t');
+        assert_eq!(console.cursor(), (8, 1));
+        console.put_char('\u{7}');
+        assert_eq!(console.cursor(), (8, 1));
+    }
+
+    #[test]
+    fn put_char_wraps_at_the_right_edge() {
+        let mut buffer = framebuffer();
+        let mut console = console(&mut buffer);
+
+        for c in "0123456789".chars() {
+            console.put_char(c);
+        }
+        // The cursor stays past the last column until another character needs the space.
+        assert_eq!(console.cursor(), (10, 0));
+        console.put_char('x');
+        assert_eq!(console.cursor(), (1, 1));
+        assert!(shows(&console, 9, 0, '9'));
+        assert!(shows(&console, 0, 1, 'x'));
+    }
+
+    #[test]
+    fn newline_on_the_last_row_scrolls() {
+        let mut buffer = framebuffer();
+        let mut console = console(&mut buffer);
+
+        fmt::Write::write_str(&mut console, "a\nbb\ncc").unwrap();
+        assert_eq!(console.cursor(), (2, 2));
+        console.put_char('\n');
+        assert_eq!(console.cursor(), (0, 2));
+        console.put_char('d');
+
+        assert!(shows(&console, 0, 0, 'b'));
+        assert!(shows(&console, 1, 0, 'b'));
+        assert!(shows(&console, 0, 1, 'c'));
+        assert!(shows(&console, 0, 2, 'd'));
+        // The last row was cleared before `d` was drawn.
+        assert!(shows(&console, 1, 2, ' '));
+        assert_eq!(pixel(&console, LAYOUT.width, 0), 0xFFFF_FFFF);
+    }
+}
@@ -0,0 +1,516 @@
+//! The kernel heap, which backs the allocations of the `alloc` crate.
+//!
+//! The heap's memory is managed by a [`LinkedListAllocator`], which only works with the memory it
+//! is handed and so can be exercised against any byte buffer. The architecture dependent code maps
+//! the heap's region of virtual memory and hands it over through [`add_region()`]. Until then,
+//! every allocation fails and returns null.
+
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    mem,
+    ptr::{self, NonNull},
+};
+
+use crate::{arch::cpu, spinlock::TicketSpinlock};
+
+/// The memory of the kernel heap.
+///
+/// The lock is fair, so that a processor that allocates often cannot keep the others waiting, and
+/// is only taken through [`with_heap()`].
+static HEAP: TicketSpinlock<LinkedListAllocator> = TicketSpinlock::new(LinkedListAllocator::new());
+
+/// The allocator used by the `alloc` crate.
+///
+/// Host builds keep the standard library's allocator.
+#[cfg(not(test))]
+#[global_allocator]
+static KERNEL_HEAP: KernelHeap = KernelHeap;
+
+/// Hands the `size` bytes starting at `start` over to the kernel heap.
+///
+/// # Safety
+/// The memory must be valid for reads and writes, must not be used by anything else, and must stay
+/// that way for the rest of the kernel's lifetime.
+pub unsafe fn add_region(start: *mut u8, size: usize) {
+    // SAFETY:
+    // The caller guarantees that the memory belongs to the heap from now on.
+    with_heap(|heap| unsafe { heap.add_region(start, size) })
+}
+
+/// Returns the current [`HeapStats`] of the kernel heap.
+pub fn stats() -> HeapStats {
+    with_heap(|heap| HeapStats {
+        capacity: heap.capacity(),
+        used: heap.used(),
+    })
+}
+
+/// Runs `f` on the [`LinkedListAllocator`] of the kernel heap.
+///
+/// Interrupts are disabled while the heap is locked, so that an interrupt handler that allocates
+/// cannot spin on a lock held by the context it interrupted.
+fn with_heap<R>(f: impl FnOnce(&mut LinkedListAllocator) -> R) -> R {
+    cpu::without_interrupts(|| f(&mut HEAP.lock()))
+}
+
+/// The usage of the kernel heap.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub struct HeapStats {
+    /// The number of bytes handed over to the heap.
+    pub capacity: usize,
+    /// The number of bytes taken by live allocations, including their rounding.
+    pub used: usize,
+}
+
+/// The [`GlobalAlloc`] that allocates from the kernel heap.
+#[derive(Clone, Copy, Debug)]
+pub struct KernelHeap;
+
+// SAFETY:
+// Every allocation comes from memory handed to `HEAP`, whose `LinkedListAllocator` never hands out
+// overlapping blocks, and each block satisfies the alignment of its `Layout`.
+unsafe impl GlobalAlloc for KernelHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        with_heap(|heap| heap.allocate(layout)).map_or(ptr::null_mut(), NonNull::as_ptr)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        // SAFETY:
+        // The caller guarantees that `ptr` was returned by `alloc()`, which never returns null
+        // from a successful allocation.
+        let ptr = unsafe { NonNull::new_unchecked(ptr) };
+
+        // SAFETY:
+        // The caller guarantees that `ptr` was returned by `alloc()` with `layout`, so it came from
+        // `HEAP`.
+        with_heap(|heap| unsafe { heap.deallocate(ptr, layout) })
+    }
+}
+
+/// A first-fit allocator that keeps the memory it manages in an address-ordered list of free
+/// blocks, each of which stores its [`FreeBlock`] header in its own first bytes.
+///
+/// Every block starts at a multiple of [`LinkedListAllocator::BLOCK_SIZE`] and spans a multiple of
+/// it, so that any memory left over around an allocation can hold a [`FreeBlock`]. Neighbouring
+/// free blocks are merged when memory is freed or added.
+#[derive(Debug)]
+pub struct LinkedListAllocator {
+    /// The free block with the lowest address, or [`None`] if no memory is free.
+    head: Option<NonNull<FreeBlock>>,
+    /// The number of bytes handed over through [`LinkedListAllocator::add_region()`].
+    capacity: usize,
+    /// The number of bytes taken by live allocations.
+    used: usize,
+}
+
+impl LinkedListAllocator {
+    /// The granularity, in bytes, of the blocks handed out by a [`LinkedListAllocator`].
+    pub const BLOCK_SIZE: usize = mem::size_of::<FreeBlock>();
+
+    /// Returns a [`LinkedListAllocator`] without any memory, from which every allocation fails.
+    pub const fn new() -> Self {
+        Self {
+            head: None,
+            capacity: 0,
+            used: 0,
+        }
+    }
+
+    /// Adds the `size` bytes starting at `start` to the memory managed by this
+    /// [`LinkedListAllocator`].
+    ///
+    /// The region is shrunk to whole blocks, and merged with any free block it borders.
+    ///
+    /// # Safety
+    /// The memory must be valid for reads and writes, must not be used by anything else, and must
+    /// stay that way for as long as this [`LinkedListAllocator`] is used.
+    pub unsafe fn add_region(&mut self, start: *mut u8, size: usize) {
+        let Some(aligned) = start.addr().checked_next_multiple_of(Self::BLOCK_SIZE) else {
+            return;
+        };
+        let padding = aligned - start.addr();
+        let size = size.saturating_sub(padding) / Self::BLOCK_SIZE * Self::BLOCK_SIZE;
+        let Some(block) = NonNull::new(start.wrapping_add(padding)) else {
+            return;
+        };
+        if size == 0 {
+            return;
+        }
+
+        self.capacity += size;
+        // SAFETY:
+        // The caller guarantees that the memory is usable and unused, and the block is aligned to
+        // `BLOCK_SIZE`.
+        unsafe { self.insert(block, size) }
+    }
+
+    /// Returns a block of memory that fits `layout`, or [`None`] if no free block is large enough.
+    pub fn allocate(&mut self, layout: Layout) -> Option<NonNull<u8>> {
+        let (size, align) = Self::block_layout(layout)?;
+
+        let mut previous: Option<NonNull<FreeBlock>> = None;
+        let mut current = self.head;
+        while let Some(block) = current {
+            // SAFETY:
+            // Every block in the list is free memory of this allocator holding a `FreeBlock`.
+            let FreeBlock {
+                size: block_size,
+                next,
+            } = unsafe { block.read() };
+            current = next;
+
+            let start = block.as_ptr().addr();
+            let Some(front) = start
+                .checked_next_multiple_of(align)
+                .map(|aligned| aligned - start)
+            else {
+                previous = Some(block);
+                continue;
+            };
+            if front.checked_add(size).is_none_or(|end| end > block_size) {
+                previous = Some(block);
+                continue;
+            }
+
+            match previous {
+                // SAFETY:
+                // Same as above.
+                Some(mut previous) => unsafe { previous.as_mut().next = next },
+                None => self.head = next,
+            }
+
+            let block = block.cast::<u8>();
+            // SAFETY:
+            // `front` lies within the removed free block.
+            let allocation = unsafe { block.add(front) };
+            let back = block_size - front - size;
+            if front != 0 {
+                // SAFETY:
+                // The memory in front of the allocation was part of the removed free block, and
+                // `block` and `front` are multiples of `BLOCK_SIZE`.
+                unsafe { self.insert(block, front) };
+            }
+            if back != 0 {
+                // SAFETY:
+                // `front + size` does not exceed the size of the removed free block.
+                let tail = unsafe { allocation.add(size) };
+                // SAFETY:
+                // The memory behind the allocation was part of the removed free block, and `tail`
+                // and `back` are multiples of `BLOCK_SIZE`.
+                unsafe { self.insert(tail, back) };
+            }
+
+            self.used += size;
+            return Some(allocation);
+        }
+
+        None
+    }
+
+    /// Returns the block at `ptr` to this [`LinkedListAllocator`].
+    ///
+    /// # Safety
+    /// `ptr` must have been returned by [`LinkedListAllocator::allocate()`] on this
+    /// [`LinkedListAllocator`] with the same `layout`, and must not have been deallocated since.
+    pub unsafe fn deallocate(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        let (size, _) = Self::block_layout(layout).expect("deallocated layout was never allocated");
+
+        self.used -= size;
+        // SAFETY:
+        // The caller guarantees that the block was allocated from this allocator and is no longer
+        // in use.
+        unsafe { self.insert(ptr, size) }
+    }
+
+    /// Returns the number of bytes handed over to this [`LinkedListAllocator`].
+    pub const fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Returns the number of bytes taken by live allocations, including their rounding.
+    pub const fn used(&self) -> usize {
+        self.used
+    }
+
+    /// Returns the size and alignment of the block that holds an allocation of `layout`, or
+    /// [`None`] if the size cannot be represented.
+    fn block_layout(layout: Layout) -> Option<(usize, usize)> {
+        let size = layout
+            .size()
+            .max(1)
+            .checked_next_multiple_of(Self::BLOCK_SIZE)?;
+
+        Some((size, layout.align().max(Self::BLOCK_SIZE)))
+    }
+
+    /// Adds the `size` bytes at `block` to the free list, merging them with the free blocks on
+    /// either side.
+    ///
+    /// # Safety
+    /// The memory must be unused memory of this [`LinkedListAllocator`] that is not already in the
+    /// free list, and `block` and `size` must be multiples of [`LinkedListAllocator::BLOCK_SIZE`].
+    unsafe fn insert(&mut self, block: NonNull<u8>, mut size: usize) {
+        let address = block.as_ptr().addr();
+
+        let mut previous: Option<NonNull<FreeBlock>> = None;
+        let mut next = self.head;
+        while let Some(current) = next {
+            if current.as_ptr().addr() > address {
+                break;
+            }
+
+            previous = Some(current);
+            // SAFETY:
+            // Every block in the list is free memory of this allocator holding a `FreeBlock`.
+            next = unsafe { current.as_ref().next };
+        }
+
+        if let Some(following) = next {
+            if address + size == following.as_ptr().addr() {
+                // SAFETY:
+                // Same as above.
+                let following = unsafe { following.read() };
+                size += following.size;
+                next = following.next;
+            }
+        }
+
+        if let Some(mut previous) = previous {
+            // SAFETY:
+            // Same as above.
+            let previous = unsafe { previous.as_mut() };
+            let previous_end = ptr::from_mut(previous).addr() + previous.size;
+            debug_assert!(previous_end <= address, "freed block overlaps a free block");
+
+            if previous_end == address {
+                previous.size += size;
+                previous.next = next;
+                return;
+            }
+        }
+
+        let block = block.cast::<FreeBlock>();
+        // SAFETY:
+        // The caller guarantees that the memory is unused and aligned to `BLOCK_SIZE`, which
+        // satisfies the alignment of `FreeBlock`.
+        unsafe { block.write(FreeBlock { size, next }) };
+        match previous {
+            // SAFETY:
+            // Every block in the list is free memory of this allocator holding a `FreeBlock`.
+            Some(mut previous) => unsafe { previous.as_mut().next = Some(block) },
+            None => self.head = Some(block),
+        }
+    }
+}
+
+impl Default for LinkedListAllocator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// SAFETY:
+// The free blocks are only reached through the `LinkedListAllocator` that owns their memory.
+unsafe impl Send for LinkedListAllocator {}
+
+/// The header stored at the start of each free block of a [`LinkedListAllocator`].
+#[repr(C)]
+#[derive(Debug)]
+struct FreeBlock {
+    /// The number of bytes in this free block, including the header.
+    size: usize,
+    /// The free block following this one in memory, or [`None`] if this is the last one.
+    next: Option<NonNull<FreeBlock>>,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::boxed::Box;
+
+    use super::*;
+
+    /// A page-aligned buffer for a [`LinkedListAllocator`] to manage.
+    #[repr(C, align(4096))]
+    struct Buffer([u8; 4096]);
+
+    impl Buffer {
+        /// Returns a zeroed [`Buffer`] on the heap, along with a pointer to its first byte.
+        fn new() -> (Box<Self>, *mut u8) {
+            let mut buffer = Box::new(Self([0; 4096]));
+            let start = buffer.0.as_mut_ptr();
+
+            (buffer, start)
+        }
+    }
+
+    /// Returns a [`Layout`] of `size` bytes aligned to `align`.
+    fn layout(size: usize, align: usize) -> Layout {
+        Layout::from_size_align(size, align).unwrap()
+    }
+
+    #[test]
+    fn allocation_without_memory_fails() {
+        let mut allocator = LinkedListAllocator::new();
+        assert_eq!(allocator.allocate(Layout::new::<u8>()), None);
+        assert_eq!(allocator.capacity(), 0);
+        assert_eq!(allocator.used(), 0);
+    }
+
+    #[test]
+    fn alignment() {
+        let (_buffer, start) = Buffer::new();
+        let mut allocator = LinkedListAllocator::new();
+
+        // SAFETY:
+        // The buffer outlives the allocator and is only used through it.
+        unsafe { allocator.add_region(start.wrapping_add(1), 4095) };
+        assert_eq!(allocator.capacity(), 4096 - LinkedListAllocator::BLOCK_SIZE);
+
+        let byte = allocator.allocate(Layout::new::<u8>()).unwrap();
+        assert_eq!(
+            byte.as_ptr(),
+            start.wrapping_add(LinkedListAllocator::BLOCK_SIZE)
+        );
+        assert_eq!(allocator.used(), LinkedListAllocator::BLOCK_SIZE);
+
+        for align in [64, 256, 1024] {
+            let block = allocator.allocate(layout(24, align)).unwrap();
+            assert_eq!(block.as_ptr().addr() % align, 0);
+        }
+
+        assert_eq!(allocator.allocate(layout(4096, 16)), None);
+    }
+
+    #[test]
+    fn reuse_after_free() {
+        let (_buffer, start) = Buffer::new();
+        let mut allocator = LinkedListAllocator::new();
+        // SAFETY:
+        // The buffer outlives the allocator and is only used through it.
+        unsafe { allocator.add_region(start, 4096) };
+
+        let quarter = layout(1024, 16);
+        let blocks = [(); 4].map(|()| allocator.allocate(quarter).unwrap());
+        assert_eq!(allocator.allocate(quarter), None);
+        assert_eq!(allocator.used(), 4096);
+
+        // SAFETY:
+        // Each block was allocated with `quarter` and is freed once.
+        unsafe { allocator.deallocate(blocks[1], quarter) };
+        // SAFETY:
+        // Same as above.
+        unsafe { allocator.deallocate(blocks[2], quarter) };
+        assert_eq!(allocator.used(), 2048);
+
+        // The two freed neighbours merge into a block large enough for both of them.
+        let half = layout(2048, 16);
+        assert_eq!(allocator.allocate(half), Some(blocks[1]));
+
+        // SAFETY:
+        // Same as above, with the merged block allocated with `half`.
+        unsafe { allocator.deallocate(blocks[1], half) };
+        // SAFETY:
+        // Same as above.
+        unsafe { allocator.deallocate(blocks[0], quarter) };
+        // SAFETY:
+        // Same as above.
+        unsafe { allocator.deallocate(blocks[3], quarter) };
+        assert_eq!(allocator.used(), 0);
+        assert_eq!(allocator.allocate(layout(4096, 16)), Some(blocks[0]));
+    }
+
+    #[test]
+    fn growth() {
+        let (_buffer, start) = Buffer::new();
+        let mut allocator = LinkedListAllocator::new();
+        // SAFETY:
+        // The buffer outlives the allocator and is only used through it.
+        unsafe { allocator.add_region(start, 2048) };
+
+        let large = layout(3072, 16);
+        assert_eq!(allocator.allocate(large), None);
+
+        // Growing the region in place merges the new memory with the free block below it.
+        // SAFETY:
+        // Same as above.
+        unsafe { allocator.add_region(start.wrapping_add(2048), 2048) };
+        assert_eq!(allocator.capacity(), 4096);
+        assert_eq!(allocator.allocate(large).map(NonNull::as_ptr), Some(start));
+    }
+
+    #[test]
+    fn first_fit_takes_the_lowest_block_that_fits() {
+        let (_buffer, start) = Buffer::new();
+        let mut allocator = LinkedListAllocator::new();
+        // SAFETY:
+        // The buffer outlives the allocator and is only used through it.
+        unsafe { allocator.add_region(start, 4096) };
+
+        let small = layout(256, 16);
+        let large = layout(1024, 16);
+        let blocks =
+            [small, large, small, large, small].map(|layout| allocator.allocate(layout).unwrap());
+        assert_eq!(blocks[0].as_ptr(), start);
+
+        // Free the first two blocks, which merge into a 1280 byte hole, and the second large
+        // block, which leaves a 1024 byte hole.
+        // SAFETY:
+        // Each block was allocated with the layout it is freed with, and is freed once.
+        unsafe { allocator.deallocate(blocks[0], small) };
+        // SAFETY:
+        // Same as above.
+        unsafe { allocator.deallocate(blocks[1], large) };
+        // SAFETY:
+        // Same as above.
+        unsafe { allocator.deallocate(blocks[3], large) };
+
+        // The lowest hole is used even though the other one fits exactly.
+        assert_eq!(allocator.allocate(large), Some(blocks[0]));
+        assert_eq!(
+            allocator.allocate(small).map(NonNull::as_ptr),
+            Some(start.wrapping_add(1024))
+        );
+        assert_eq!(allocator.allocate(large), Some(blocks[3]));
+    }
+
+    #[test]
+    fn alignment_padding_stays_free() {
+        let (_buffer, start) = Buffer::new();
+        let mut allocator = LinkedListAllocator::new();
+        // SAFETY:
+        // The buffer outlives the allocator and is only used through it.
+        unsafe { allocator.add_region(start, 4096) };
+
+        let first = allocator.allocate(layout(16, 16)).unwrap();
+        assert_eq!(first.as_ptr(), start);
+
+        // The aligned block leaves a gap behind `first`, which the next small block fills.
+        let aligned = allocator.allocate(layout(16, 1024)).unwrap();
+        assert_eq!(aligned.as_ptr(), start.wrapping_add(1024));
+        let filler = allocator.allocate(layout(16, 16)).unwrap();
+        assert_eq!(
+            filler.as_ptr(),
+            start.wrapping_add(LinkedListAllocator::BLOCK_SIZE)
+        );
+    }
+
+    #[test]
+    fn zero_sized_and_oversized_layouts() {
+        let (_buffer, start) = Buffer::new();
+        let mut allocator = LinkedListAllocator::new();
+        // SAFETY:
+        // The buffer outlives the allocator and is only used through it.
+        unsafe { allocator.add_region(start, 4096) };
+
+        let empty = layout(0, 1);
+        let block = allocator.allocate(empty).unwrap();
+        assert_eq!(allocator.used(), LinkedListAllocator::BLOCK_SIZE);
+        // SAFETY:
+        // `block` was allocated with `empty` and is freed once.
+        unsafe { allocator.deallocate(block, empty) };
+        assert_eq!(allocator.used(), 0);
+
+        assert_eq!(allocator.allocate(layout(isize::MAX as usize, 1)), None);
+        assert_eq!(allocator.allocate(layout(4097, 16)), None);
+    }
+}
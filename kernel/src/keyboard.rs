@@ -0,0 +1,327 @@
+//! Decoding of keyboard scancodes into ASCII.
+//!
+//! Scancode set 2 bytes from [`arch::ps2`][crate::arch::ps2] are fed through [`decode()`], a pure
+//! state machine that tracks the shift, control, and caps lock modifiers along with the extended
+//! and release prefixes, and yields the ASCII byte produced by each key press. Keys that produce
+//! no ASCII, such as the arrows and function keys, are dropped.
+
+use crate::spinlock::Spinlock;
+
+/// The decoder fed by [`try_read_char()`].
+static DECODER: Spinlock<DecoderState> = Spinlock::new(DecoderState::new());
+
+/// Returns the next character typed on the keyboard, or [`None`] if no complete key press is
+/// waiting.
+pub fn try_read_char() -> Option<char> {
+    let mut state = DECODER.lock();
+    while let Some(scancode) = crate::arch::ps2::try_read_scancode() {
+        let (next, byte) = decode(*state, scancode);
+        *state = next;
+        if let Some(byte) = byte {
+            return Some(char::from(byte));
+        }
+    }
+
+    None
+}
+
+/// The prefix byte of extended scancodes.
+const EXTENDED_PREFIX: u8 = 0xE0;
+/// The prefix byte of key releases.
+const RELEASE_PREFIX: u8 = 0xF0;
+/// The first byte of the eight byte sequence sent when Pause is pressed, which has no release.
+const PAUSE_PREFIX: u8 = 0xE1;
+/// The number of bytes following [`PAUSE_PREFIX`] in the Pause sequence.
+const PAUSE_SEQUENCE_REST: u8 = 7;
+
+/// The scancode of the left shift key.
+const LEFT_SHIFT: u8 = 0x12;
+/// The scancode of the right shift key.
+const RIGHT_SHIFT: u8 = 0x59;
+/// The scancode of the left control key, or of the right control key when extended.
+const CONTROL: u8 = 0x14;
+/// The scancode of the caps lock key.
+const CAPS_LOCK: u8 = 0x58;
+
+/// The ASCII produced by each non-extended scancode, without and with shift, or zero if the key
+/// produces none.
+const KEYMAP: [[u8; 2]; 0x80] = {
+    const KEYS: &[(u8, u8, u8)] = &[
+        (0x0D, b'\t', b'\t'),
+        (0x0E, b'`', b'~'),
+        (0x15, b'q', b'Q'),
+        (0x16, b'1', b'!'),
+        (0x1A, b'z', b'Z'),
+        (0x1B, b's', b'S'),
+        (0x1C, b'a', b'A'),
+        (0x1D, b'w', b'W'),
+        (0x1E, b'2', b'@'),
+        (0x21, b'c', b'C'),
+        (0x22, b'x', b'X'),
+        (0x23, b'd', b'D'),
+        (0x24, b'e', b'E'),
+        (0x25, b'4', b'$'),
+        (0x26, b'3', b'#'),
+        (0x29, b' ', b' '),
+        (0x2A, b'v', b'V'),
+        (0x2B, b'f', b'F'),
+        (0x2C, b't', b'T'),
+        (0x2D, b'r', b'R'),
+        (0x2E, b'5', b'%'),
+        (0x31, b'n', b'N'),
+        (0x32, b'b', b'B'),
+        (0x33, b'h', b'H'),
+        (0x34, b'g', b'G'),
+        (0x35, b'y', b'Y'),
+        (0x36, b'6', b'^'),
+        (0x3A, b'm', b'M'),
+        (0x3B, b'j', b'J'),
+        (0x3C, b'u', b'U'),
+        (0x3D, b'7', b'&'),
+        (0x3E, b'8', b'*'),
+        (0x41, b',', b'<'),
+        (0x42, b'k', b'K'),
+        (0x43, b'i', b'I'),
+        (0x44, b'o', b'O'),
+        (0x45, b'0', b')'),
+        (0x46, b'9', b'('),
+        (0x49, b'.', b'>'),
+        (0x4A, b'/', b'?'),
+        (0x4B, b'l', b'L'),
+        (0x4C, b';', b':'),
+        (0x4D, b'p', b'P'),
+        (0x4E, b'-', b'_'),
+        (0x52, b'\'', b'"'),
+        (0x54, b'[', b'{'),
+        (0x55, b'=', b'+'),
+        (0x5A, b'\n', b'\n'),
+        (0x5B, b']', b'}'),
+        (0x5D, b'\\', b'|'),
+        (0x66, 0x08, 0x08),
+        (0x69, b'1', b'1'),
+        (0x6B, b'4', b'4'),
+        (0x6C, b'7', b'7'),
+        (0x70, b'0', b'0'),
+        (0x71, b'.', b'.'),
+        (0x72, b'2', b'2'),
+        (0x73, b'5', b'5'),
+        (0x74, b'6', b'6'),
+        (0x75, b'8', b'8'),
+        (0x76, 0x1B, 0x1B),
+        (0x79, b'+', b'+'),
+        (0x7A, b'3', b'3'),
+        (0x7B, b'-', b'-'),
+        (0x7C, b'*', b'*'),
+        (0x7D, b'9', b'9'),
+    ];
+
+    let mut keymap = [[0; 2]; 0x80];
+    let mut index = 0;
+    while index < KEYS.len() {
+        let (scancode, unshifted, shifted) = KEYS[index];
+        keymap[scancode as usize] = [unshifted, shifted];
+        index += 1;
+    }
+    keymap
+};
+
+/// Returns the ASCII produced by the extended scancode `scancode`, or zero if the key produces
+/// none.
+const fn extended_key(scancode: u8) -> u8 {
+    match scancode {
+        // Keypad enter and slash.
+        0x5A => b'\n',
+        0x4A => b'/',
+        // Delete.
+        0x71 => 0x7F,
+        _ => 0,
+    }
+}
+
+/// Feeds `scancode` to the decoder in `state`, returning the next state and the ASCII byte
+/// produced by the key press it completes, if any.
+pub const fn decode(state: DecoderState, scancode: u8) -> (DecoderState, Option<u8>) {
+    let mut state = state;
+    if state.skip != 0 {
+        state.skip -= 1;
+        return (state, None);
+    }
+
+    match scancode {
+        EXTENDED_PREFIX => {
+            state.extended = true;
+            return (state, None);
+        }
+        RELEASE_PREFIX => {
+            state.release = true;
+            return (state, None);
+        }
+        PAUSE_PREFIX => {
+            state.skip = PAUSE_SEQUENCE_REST;
+            return (state, None);
+        }
+        // Key detection errors, buffer overruns, and command responses.
+        0x00 | 0xAA | 0xEE | 0xFA | 0xFC | 0xFD | 0xFE | 0xFF => {
+            state.extended = false;
+            state.release = false;
+            return (state, None);
+        }
+        _ => {}
+    }
+
+    let extended = state.extended;
+    let pressed = !state.release;
+    state.extended = false;
+    state.release = false;
+
+    match (extended, scancode) {
+        (false, LEFT_SHIFT) => state.left_shift = pressed,
+        (false, RIGHT_SHIFT) => state.right_shift = pressed,
+        (false, CONTROL) => state.left_control = pressed,
+        (true, CONTROL) => state.right_control = pressed,
+        (false, CAPS_LOCK) => {
+            // Holding the key repeats its make code, which must not toggle caps lock again.
+            if pressed && !state.caps_held {
+                state.caps_lock = !state.caps_lock;
+            }
+            state.caps_held = pressed;
+        }
+        // Print Screen and the navigation keys wrap themselves in extended shift codes.
+        (true, LEFT_SHIFT | RIGHT_SHIFT) => {}
+        _ if !pressed => {}
+        (true, scancode) => {
+            let byte = extended_key(scancode);
+            if byte != 0 {
+                return (state, Some(byte));
+            }
+        }
+        (false, scancode) => {
+            if scancode as usize >= KEYMAP.len() {
+                return (state, None);
+            }
+
+            let [unshifted, shifted] = KEYMAP[scancode as usize];
+            let shift = state.left_shift || state.right_shift;
+            let byte = if unshifted.is_ascii_lowercase() {
+                if state.left_control || state.right_control {
+                    unshifted & 0x1F
+                } else if shift != state.caps_lock {
+                    shifted
+                } else {
+                    unshifted
+                }
+            } else if shift {
+                shifted
+            } else {
+                unshifted
+            };
+
+            if byte != 0 {
+                return (state, Some(byte));
+            }
+        }
+    }
+
+    (state, None)
+}
+
+/// The state of the scancode decoder between bytes.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub struct DecoderState {
+    /// Whether the next byte completes an extended scancode.
+    extended: bool,
+    /// Whether the next byte completes a key release.
+    release: bool,
+    /// The number of bytes of the Pause sequence still to be ignored.
+    skip: u8,
+    /// Whether the left shift key is held.
+    left_shift: bool,
+    /// Whether the right shift key is held.
+    right_shift: bool,
+    /// Whether the left control key is held.
+    left_control: bool,
+    /// Whether the right control key is held.
+    right_control: bool,
+    /// Whether caps lock is on.
+    caps_lock: bool,
+    /// Whether the caps lock key is held.
+    caps_held: bool,
+}
+
+impl DecoderState {
+    /// Creates a [`DecoderState`] with no prefixes pending, no modifiers held, and caps lock off.
+    pub const fn new() -> Self {
+        Self {
+            extended: false,
+            release: false,
+            skip: 0,
+            left_shift: false,
+            right_shift: false,
+            left_control: false,
+            right_control: false,
+            caps_lock: false,
+            caps_held: false,
+        }
+    }
+
+    /// Returns `true` if caps lock is on.
+    pub const fn caps_lock(&self) -> bool {
+        self.caps_lock
+    }
+}
+
+impl Default for DecoderState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "ktest")]
+crate::kernel_test! {
+    fn keyboard_decodes_modifier_sequences() {
+        const CASES: &[(&str, &[u8], &[u8])] = &[
+            ("plain keys", &[0x1C, 0xF0, 0x1C, 0x16, 0xF0, 0x16], b"a1"),
+            ("left shift", &[0x12, 0x1C, 0x16, 0xF0, 0x12, 0x1C], b"A!a"),
+            ("right shift", &[0x59, 0x4E, 0xF0, 0x59, 0x4E], b"_-"),
+            ("caps lock letters", &[0x58, 0xF0, 0x58, 0x1C, 0x16], b"A1"),
+            ("caps lock with shift", &[0x58, 0xF0, 0x58, 0x12, 0x1C, 0x16], b"a!"),
+            ("caps lock toggles off", &[0x58, 0xF0, 0x58, 0x58, 0xF0, 0x58, 0x1C], b"a"),
+            ("caps lock repeat", &[0x58, 0x58, 0x58, 0xF0, 0x58, 0x1C], b"A"),
+            ("control letter", &[0x14, 0x21, 0xF0, 0x14, 0x21], b"\x03c"),
+            ("right control", &[0xE0, 0x14, 0x21, 0xE0, 0xF0, 0x14, 0x21], b"\x03c"),
+            ("release emits nothing", &[0xF0, 0x1C, 0xF0, 0x5A], b""),
+            ("keypad enter and slash", &[0xE0, 0x5A, 0xE0, 0x4A, 0x5A], b"\n/\n"),
+            ("arrow keys ignored", &[0xE0, 0x75, 0xE0, 0xF0, 0x75, 0x1C], b"a"),
+            (
+                "print screen fake shift",
+                &[0xE0, 0x12, 0xE0, 0x7C, 0xE0, 0xF0, 0x7C, 0xE0, 0xF0, 0x12, 0x1C],
+                b"a",
+            ),
+            (
+                "pause sequence",
+                &[0xE1, 0x14, 0x77, 0xE1, 0xF0, 0x14, 0xF0, 0x77, 0x1C],
+                b"a",
+            ),
+            ("shift survives extended keys", &[0x12, 0xE0, 0x75, 0x1C], b"A"),
+            ("backspace and escape", &[0x66, 0x76], b"\x08\x1b"),
+            ("keypad ignores shift", &[0x12, 0x69, 0x7C], b"1*"),
+            ("response bytes reset prefixes", &[0xE0, 0xFA, 0x5A], b"\n"),
+        ];
+
+        for &(name, scancodes, expected) in CASES {
+            let mut state = DecoderState::new();
+            let mut output = [0u8; 16];
+            let mut len = 0;
+            for &scancode in scancodes {
+                let (next, byte) = decode(state, scancode);
+                state = next;
+                if let Some(byte) = byte {
+                    output[len] = byte;
+                    len += 1;
+                }
+            }
+
+            assert_eq!(&output[..len], expected, "{name}");
+        }
+    }
+}
@@ -0,0 +1,239 @@
+//! A minimal interactive shell for debugging, reading input from the serial port or a PS/2
+//! keyboard and writing output to the serial port.
+
+use core::fmt::Write;
+
+use crate::arch::{
+    boot_info, interrupt_stats,
+    serial::{acquire_serial_port, read_line_with},
+    MemoryKind,
+};
+
+/// The maximum length of a line entered into the shell.
+const MAX_LINE_LENGTH: usize = 128;
+
+/// A command understood by the shell.
+struct Command {
+    /// The name used to invoke the command.
+    name: &'static str,
+    /// A short description of the command.
+    help: &'static str,
+    /// The function that runs the command, given the remainder of the line.
+    run: fn(&str),
+}
+
+/// The commands understood by the shell.
+static COMMANDS: &[Command] = &[
+    Command {
+        name: "help",
+        help: "list the available commands",
+        run: help,
+    },
+    Command {
+        name: "mem",
+        help: "show memory statistics",
+        run: mem,
+    },
+    Command {
+        name: "intstats",
+        help: "show interrupt statistics",
+        run: intstats,
+    },
+    Command {
+        name: "serial",
+        help: "show the state of the serial port",
+        run: serial,
+    },
+    Command {
+        name: "dmesg",
+        help: "show the in-memory log history",
+        run: dmesg,
+    },
+    Command {
+        name: "logstats",
+        help: "show logging statistics",
+        run: logstats,
+    },
+    Command {
+        name: "options",
+        help: "show the effective kernel command line options",
+        run: options,
+    },
+    Command {
+        name: "panic",
+        help: "trigger a kernel panic, or with `nested`, a panic inside the panic handler",
+        run: panic,
+    },
+];
+
+/// Runs the shell, reading and executing commands forever.
+pub fn run() -> ! {
+    let mut buffer = [0; MAX_LINE_LENGTH];
+    loop {
+        let _ = write!(acquire_serial_port(), "kshell> ");
+
+        let line = read_line_with(&mut buffer, || {
+            crate::keyboard::try_read_char().map(|c| c as u8)
+        })
+        .trim();
+        let (name, args) = line.split_once(' ').unwrap_or((line, ""));
+        if name.is_empty() {
+            continue;
+        }
+
+        match COMMANDS.iter().find(|command| command.name == name) {
+            Some(command) => (command.run)(args.trim()),
+            None => {
+                let _ = writeln!(
+                    acquire_serial_port(),
+                    "unknown command `{name}`; try `help`\r"
+                );
+            }
+        }
+    }
+}
+
+/// Lists the available commands.
+fn help(_: &str) {
+    let mut serial_port = acquire_serial_port();
+    for command in COMMANDS {
+        let _ = writeln!(serial_port, "{:<10} {}\r", command.name, command.help);
+    }
+}
+
+/// Shows the in-memory log history.
+fn dmesg(_: &str) {
+    let _ = crate::logging::dump_ring_buffer(&mut *acquire_serial_port());
+}
+
+/// Shows the contents of every register of the serial port.
+fn serial(_: &str) {
+    let mut serial_port = acquire_serial_port();
+    let io_port = serial_port.io_port();
+    let state = serial_port.dump_state();
+
+    let watchdog = *serial_port.watchdog();
+
+    let _ = writeln!(serial_port, "port: {io_port:#X}\r");
+    let _ = writeln!(
+        serial_port,
+        "transmitter: {}, {} bytes dropped\r",
+        if watchdog.degraded() {
+            "degraded"
+        } else {
+            "ok"
+        },
+        watchdog.dropped_bytes()
+    );
+    let _ = writeln!(CrLf(&mut *serial_port), "{state:?}");
+}
+
+/// Adapts a [`Write`] implementation to terminate lines with CR LF, as serial terminals expect.
+struct CrLf<W: Write>(W);
+
+impl<W: Write> Write for CrLf<W> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        for (index, line) in s.split('\n').enumerate() {
+            if index != 0 {
+                self.0.write_str("\r\n")?;
+            }
+            self.0.write_str(line)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Shows statistics about the memory map provided by the bootloader.
+fn mem(_: &str) {
+    let Some(boot_info) = boot_info() else {
+        let _ = writeln!(acquire_serial_port(), "boot information unavailable\r");
+        return;
+    };
+
+    let mut total = 0;
+    let mut usable = 0;
+    for entry in boot_info.memory_map() {
+        total += entry.size;
+        if entry.kind == MemoryKind::Usable {
+            usable += entry.size;
+        }
+    }
+
+    let mut serial_port = acquire_serial_port();
+    let _ = writeln!(
+        serial_port,
+        "memory map entries: {}\r",
+        boot_info.memory_map().len()
+    );
+    let _ = writeln!(serial_port, "total:  {} KiB\r", total / 1024);
+    let _ = writeln!(serial_port, "usable: {} KiB\r", usable / 1024);
+}
+
+/// Shows statistics about the interrupts handled by the current processor.
+fn intstats(_: &str) {
+    let counts = match interrupt_stats::counts() {
+        Ok(counts) => counts,
+        Err(error) => {
+            let _ = writeln!(acquire_serial_port(), "{error}\r");
+            return;
+        }
+    };
+
+    let mut serial_port = acquire_serial_port();
+    let mut any = false;
+    for (vector, &count) in counts.iter().enumerate() {
+        if count != 0 {
+            let _ = writeln!(serial_port, "vector {vector:3}: {count}\r");
+            any = true;
+        }
+    }
+    if !any {
+        let _ = writeln!(serial_port, "no interrupts handled\r");
+    }
+}
+
+/// Shows statistics about logging.
+fn logstats(_: &str) {
+    let stats = crate::logging::stats();
+
+    let mut serial_port = acquire_serial_port();
+    let _ = writeln!(CrLf(&mut *serial_port), "{stats}");
+
+    #[cfg(feature = "debugcon-logging")]
+    let _ = writeln!(
+        serial_port,
+        "debugcon port operations: {}\r",
+        crate::arch::logging::debugcon_port_operations()
+    );
+}
+
+/// Shows the effective value of every kernel command line option and where it came from.
+fn options(_: &str) {
+    let mut serial_port = acquire_serial_port();
+    for (decl, setting) in crate::options::settings() {
+        let _ = writeln!(
+            serial_port,
+            "{:<10} {:<8} {:<8} {}\r",
+            decl.name, setting.value, setting.source, decl.description
+        );
+    }
+}
+
+/// Triggers a kernel panic, or with `nested`, a panic whose message panics while it is formatted.
+fn panic(args: &str) {
+    /// A panic message that panics when it is formatted.
+    struct PanickingMessage;
+
+    impl core::fmt::Display for PanickingMessage {
+        fn fmt(&self, _: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+            panic!("panic while formatting a panic message");
+        }
+    }
+
+    if args == "nested" {
+        panic!("{}", PanickingMessage);
+    }
+
+    panic!("panic requested from kshell");
+}
@@ -0,0 +1,162 @@
+//! In-kernel test harness.
+//!
+//! Tests are declared with [`kernel_test!`][crate::kernel_test], which places a
+//! [`&dyn KernelTest`][KernelTest] in the `.ktest` section. When the `ktest` feature is enabled,
+//! [`kmain()`][crate::kmain] hands every registered test to [`test_runner()`] instead of
+//! continuing normal operation.
+//!
+//! Each test is logged as `test NAME ... ok` once it returns. The kernel cannot unwind, so a
+//! panicking test ends the run: the panic handler reports the test as `FAILED`, logs the summary,
+//! and exits QEMU with a failure code through [`report_panic()`].
+
+use core::{
+    ptr, slice,
+    sync::atomic::{AtomicPtr, AtomicUsize, Ordering},
+};
+
+use crate::arch::{qemu_exit, ExitCode};
+
+/// A test that runs inside the kernel.
+pub trait KernelTest: Sync {
+    /// Returns the name under which the test is reported.
+    fn name(&self) -> &'static str;
+
+    /// Runs the test, panicking if it fails.
+    fn run(&self);
+}
+
+/// A [`KernelTest`] consisting of a single function, as declared by
+/// [`kernel_test!`][crate::kernel_test].
+#[derive(Clone, Copy, Debug)]
+pub struct Test {
+    /// The name under which the test is reported.
+    pub name: &'static str,
+    /// The function that performs the test.
+    pub function: fn(),
+}
+
+impl KernelTest for Test {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn run(&self) {
+        (self.function)()
+    }
+}
+
+/// Declares one or more kernel tests, which are run by [`test_runner()`] when the `ktest`
+/// feature is enabled.
+///
+/// Each test is reported under its module path and function name.
+///
+/// ```ignore
+/// kernel_test! {
+///     fn addition_works() {
+///         assert_eq!(1 + 1, 2);
+///     }
+/// }
+/// ```
+#[macro_export]
+macro_rules! kernel_test {
+    ($($(#[$attr:meta])* fn $name:ident() $body:block)*) => {
+        $(
+            $(#[$attr])*
+            fn $name() $body
+
+            const _: () = {
+                #[used]
+                #[link_section = ".ktest"]
+                static TEST: &dyn $crate::ktest::KernelTest = &$crate::ktest::Test {
+                    name: concat!(module_path!(), "::", stringify!($name)),
+                    function: $name,
+                };
+            };
+        )*
+    };
+}
+
+/// The name of the test that is currently running, or null if no test is running.
+static CURRENT_NAME: AtomicPtr<u8> = AtomicPtr::new(ptr::null_mut());
+/// The length of the name of the test that is currently running.
+static CURRENT_NAME_LEN: AtomicUsize = AtomicUsize::new(0);
+/// The number of tests in the current run.
+static TOTAL: AtomicUsize = AtomicUsize::new(0);
+/// The number of tests in the current run that have passed.
+static PASSED: AtomicUsize = AtomicUsize::new(0);
+
+/// Returns the tests registered with [`kernel_test!`][crate::kernel_test].
+pub fn registered() -> &'static [&'static dyn KernelTest] {
+    extern "C" {
+        #[link_name = "ktest_start"]
+        static KTEST_START: core::ffi::c_void;
+        #[link_name = "ktest_end"]
+        static KTEST_END: core::ffi::c_void;
+    }
+
+    let start = ptr::addr_of!(KTEST_START).cast::<&'static dyn KernelTest>();
+    let end = ptr::addr_of!(KTEST_END).cast::<&'static dyn KernelTest>();
+    let len = (end as usize - start as usize) / core::mem::size_of::<&'static dyn KernelTest>();
+
+    // SAFETY:
+    // `kernel_test!` only places `&'static dyn KernelTest`s in the `.ktest` section, which the
+    // linker script brackets with `ktest_start` and `ktest_end`, and the section is never written.
+    unsafe { slice::from_raw_parts(start, len) }
+}
+
+/// Runs every test registered with [`kernel_test!`][crate::kernel_test] and exits QEMU with the
+/// result.
+pub fn run_all() -> ! {
+    test_runner(registered())
+}
+
+/// Runs `tests` in order, logging the outcome of each, and exits QEMU with
+/// [`ExitCode::Success`] once all of them have passed.
+pub fn test_runner(tests: &[&dyn KernelTest]) -> ! {
+    log::info!("running {} tests", tests.len());
+    TOTAL.store(tests.len(), Ordering::Relaxed);
+    PASSED.store(0, Ordering::Relaxed);
+
+    for test in tests {
+        let name = test.name();
+        CURRENT_NAME_LEN.store(name.len(), Ordering::Relaxed);
+        CURRENT_NAME.store(name.as_ptr().cast_mut(), Ordering::Release);
+
+        test.run();
+
+        CURRENT_NAME.store(ptr::null_mut(), Ordering::Release);
+        PASSED.fetch_add(1, Ordering::Relaxed);
+        log::info!("test {name} ... ok");
+    }
+
+    log::info!("test result: ok. {} passed; 0 failed", tests.len());
+    qemu_exit(ExitCode::Success)
+}
+
+/// Reports the running test as failed and exits QEMU with a failure code.
+///
+/// Returns without doing anything if no test is running, so that the panic handler can continue
+/// with its usual behavior.
+pub fn report_panic() {
+    let name = CURRENT_NAME.load(Ordering::Acquire);
+    if name.is_null() {
+        return;
+    }
+
+    // SAFETY:
+    // `test_runner()` stores the pointer and length of a `&'static str` before running a test,
+    // and the pointer is non-null only while that test is running.
+    let name = unsafe { slice::from_raw_parts(name, CURRENT_NAME_LEN.load(Ordering::Relaxed)) };
+    // SAFETY:
+    // The bytes were taken from a `&'static str`.
+    let name = unsafe { core::str::from_utf8_unchecked(name) };
+    let total = TOTAL.load(Ordering::Relaxed);
+    let passed = PASSED.load(Ordering::Relaxed);
+
+    log::error!("test {name} ... FAILED");
+    log::error!(
+        "test result: FAILED. {passed} passed; 1 failed; {} not run",
+        total - passed - 1
+    );
+    qemu_exit(ExitCode::Failed(1))
+}
@@ -0,0 +1,461 @@
+//! Kernel threads and the stacks they run on.
+//!
+//! [`spawn()`] creates a thread running a function on a stack of its own and hands it to the
+//! [`scheduler`], and [`yield_now()`] switches to the next runnable thread. The control flow that
+//! booted the kernel is itself a thread, which never finishes.
+//!
+//! Every spawned thread runs on a [`KernelStack`] allocated from the kernel's frames, with an
+//! unmapped guard page below it, so a thread that overflows its stack faults on the guard page
+//! and the fault is reported as a kernel stack overflow.
+//!
+//! A finished thread is still running on its stack until it switches away for the last time, so
+//! its slot is reclaimed later by whichever thread next passes through the [`scheduler`]. The
+//! [`scheduler`] can run in interrupt handlers, where the kernel's frames must not be touched, so
+//! a reclaimed slot keeps its stack for the next thread created in it instead of freeing it.
+
+use core::{fmt, ptr};
+
+use crate::{
+    arch::{context::Context, cpu, kernel_memory, KernelStack, Page, StackError},
+    scheduler,
+    spinlock::IrqSpinlock,
+};
+
+/// The maximum number of threads, including the boot and idle threads, that can exist at once.
+pub const MAX_THREADS: usize = 16;
+
+/// The size, in bytes, of the stack of a spawned thread, excluding its guard page.
+pub const STACK_SIZE: usize = 16 * 1024;
+
+/// The slot of the boot thread, which runs on the stack the kernel was entered on.
+pub(crate) const BOOT_SLOT: usize = 0;
+
+/// Every thread in existence.
+pub(crate) static THREADS: IrqSpinlock<ThreadTable> = IrqSpinlock::new(ThreadTable::new());
+
+/// Creates a runnable thread named `name` that runs `entry` once the [`scheduler`] switches to
+/// it.
+///
+/// # Errors
+/// - [`SpawnError::TooManyThreads`] if [`MAX_THREADS`] threads already exist.
+/// - [`SpawnError::Stack`] if a stack for the thread could not be allocated.
+pub fn spawn(entry: fn(), name: &'static str) -> Result<ThreadId, SpawnError> {
+    let (id, slot) = create(entry, name)?;
+    scheduler::enqueue(slot);
+
+    Ok(id)
+}
+
+/// Creates a thread named `name` that runs `entry`, returning its [`ThreadId`] and slot without
+/// making it runnable.
+///
+/// A stack kept by a reclaimed slot is reused if there is one. Otherwise, a new [`KernelStack`]
+/// is allocated without holding [`THREADS`], since allocating it takes the lock of the kernel's
+/// frames.
+///
+/// # Errors
+/// - [`SpawnError::TooManyThreads`] if [`MAX_THREADS`] threads already exist.
+/// - [`SpawnError::Stack`] if a stack for the thread could not be allocated.
+pub(crate) fn create(entry: fn(), name: &'static str) -> Result<(ThreadId, usize), SpawnError> {
+    let current = scheduler::current_slot();
+    let spare = {
+        let mut threads = THREADS.lock();
+        threads.reap(current);
+        threads.free_slot().ok_or(SpawnError::TooManyThreads)?;
+        threads.take_spare_stack()
+    };
+    let stack = match spare {
+        Some(stack) => stack,
+        None => kernel_memory::allocate_stack(STACK_SIZE / Page::PAGE_SIZE)
+            .map_err(SpawnError::Stack)?,
+    };
+
+    let mut threads = THREADS.lock();
+    // Another thread may have taken the last free slot while the stack was allocated.
+    let Some(slot) = threads.free_slot() else {
+        let excess = threads.keep_spare_stack(stack);
+        drop(threads);
+        free_excess_stack(excess);
+        return Err(SpawnError::TooManyThreads);
+    };
+
+    // SAFETY:
+    // The stack was just allocated or kept by a free slot, so no thread is using it, and the top
+    // of a stack is page aligned.
+    let context = unsafe { Context::seed(stack.top(), thread_main, slot) };
+    let displaced = threads.stacks[slot].replace(stack);
+    let id = threads.add(slot, entry, name, context);
+
+    let excess = displaced.and_then(|stack| threads.keep_spare_stack(stack));
+    drop(threads);
+    free_excess_stack(excess);
+
+    #[cfg(feature = "logging")]
+    log::debug!("Created thread {id} ({name})");
+    Ok((id, slot))
+}
+
+/// Returns `stack`, which no free slot had room to keep, to the kernel's frames.
+fn free_excess_stack(stack: Option<KernelStack>) {
+    if let Some(stack) = stack {
+        // SAFETY:
+        // Only free slots keep spare stacks, so no thread is using it, and every stack in the
+        // thread table came from `kernel_memory::allocate_stack()`.
+        unsafe { kernel_memory::free_stack(stack) };
+    }
+}
+
+/// Switches to the next runnable thread, returning once this thread is switched back to.
+///
+/// Returns immediately if no other thread is runnable.
+pub fn yield_now() {
+    scheduler::yield_now();
+}
+
+/// Returns the [`ThreadId`] of the current thread.
+pub fn current() -> ThreadId {
+    let current = scheduler::current_slot();
+    THREADS.lock().threads[current]
+        .as_ref()
+        .map(|thread| thread.id)
+        .expect("current thread slot is empty")
+}
+
+/// Returns the name of the thread `id`, or [`None`] if it no longer exists.
+pub fn name(id: ThreadId) -> Option<&'static str> {
+    let threads = THREADS.lock();
+    let slot = threads.slot_of(id)?;
+    threads.threads[slot].as_ref().map(|thread| thread.name)
+}
+
+/// Returns `true` if the thread `id` has finished or no longer exists.
+pub fn is_finished(id: ThreadId) -> bool {
+    let threads = THREADS.lock();
+    threads
+        .slot_of(id)
+        .is_none_or(|slot| threads.state(slot) == Some(ThreadState::Finished))
+}
+
+/// The first function run by a spawned thread, which calls its entry function and finishes the
+/// thread.
+extern "C" fn thread_main(slot: usize) -> ! {
+    let entry = THREADS.lock().threads[slot]
+        .as_mut()
+        .and_then(|thread| thread.entry.take())
+        .expect("spawned thread has no entry function");
+
+    // A thread first switched to from an interrupt handler starts with interrupts disabled.
+    if scheduler::is_preemptive() {
+        // SAFETY:
+        // The timer interrupt handler only touches state guarded against interrupts, and no lock
+        // is held here.
+        unsafe { cpu::enable_interrupts() }
+    }
+
+    entry();
+
+    scheduler::schedule(ThreadState::Finished);
+    unreachable!("finished thread was switched back to")
+}
+
+/// The bookkeeping of every thread.
+pub(crate) struct ThreadTable {
+    /// The threads, indexed by slot. Slot [`BOOT_SLOT`] belongs to the boot thread.
+    threads: [Option<Thread>; MAX_THREADS],
+    /// The stack of the thread in each slot, or a spare stack kept by a free slot. The boot
+    /// thread runs on the stack the kernel was entered on, so [`BOOT_SLOT`] has none.
+    stacks: [Option<KernelStack>; MAX_THREADS],
+    /// The [`ThreadId`] given to the next created thread.
+    next_id: u64,
+}
+
+impl ThreadTable {
+    /// Creates a [`ThreadTable`] in which only the boot thread exists.
+    pub(crate) const fn new() -> Self {
+        let mut threads = [const { None }; MAX_THREADS];
+        threads[BOOT_SLOT] = Some(Thread {
+            id: ThreadId(0),
+            name: "boot",
+            state: ThreadState::Running,
+            entry: None,
+            context: Context {
+                rbx: 0,
+                rbp: 0,
+                r12: 0,
+                r13: 0,
+                r14: 0,
+                r15: 0,
+                rsp: 0,
+            },
+        });
+
+        Self {
+            threads,
+            stacks: [const { None }; MAX_THREADS],
+            next_id: 1,
+        }
+    }
+
+    /// Places a new runnable thread named `name`, which runs `entry` once switched to `context`,
+    /// in the free `slot`.
+    ///
+    /// # Panics
+    /// Panics if the slot is not free.
+    pub(crate) fn add(
+        &mut self,
+        slot: usize,
+        entry: fn(),
+        name: &'static str,
+        context: Context,
+    ) -> ThreadId {
+        assert!(self.threads[slot].is_none(), "thread slot is occupied");
+
+        let id = ThreadId(self.next_id);
+        self.next_id += 1;
+        self.threads[slot] = Some(Thread {
+            id,
+            name,
+            state: ThreadState::Runnable,
+            entry: Some(entry),
+            context,
+        });
+
+        id
+    }
+
+    /// Returns the slot of the thread `id`, or [`None`] if it does not exist.
+    pub(crate) fn slot_of(&self, id: ThreadId) -> Option<usize> {
+        self.threads
+            .iter()
+            .position(|thread| thread.as_ref().is_some_and(|thread| thread.id == id))
+    }
+
+    /// Returns the state of the thread in `slot`, or [`None`] if the slot is free.
+    pub(crate) fn state(&self, slot: usize) -> Option<ThreadState> {
+        self.threads[slot].as_ref().map(|thread| thread.state)
+    }
+
+    /// Sets the state of the thread in `slot`.
+    ///
+    /// # Panics
+    /// Panics if the slot is free.
+    pub(crate) fn set_state(&mut self, slot: usize, state: ThreadState) {
+        self.thread_mut(slot).state = state;
+    }
+
+    /// Returns a pointer to the saved [`Context`] of the thread in `slot`.
+    ///
+    /// # Panics
+    /// Panics if the slot is free.
+    pub(crate) fn context(&self, slot: usize) -> *const Context {
+        let thread = self.threads[slot].as_ref().expect("thread slot is free");
+        ptr::addr_of!(thread.context)
+    }
+
+    /// Returns a mutable pointer to the saved [`Context`] of the thread in `slot`.
+    ///
+    /// # Panics
+    /// Panics if the slot is free.
+    pub(crate) fn context_mut(&mut self, slot: usize) -> *mut Context {
+        ptr::addr_of_mut!(self.thread_mut(slot).context)
+    }
+
+    /// Frees the slots of every finished thread but the one in `current`.
+    ///
+    /// Each freed slot keeps the stack of its thread as a spare.
+    pub(crate) fn reap(&mut self, current: usize) {
+        for slot in 0..MAX_THREADS {
+            if slot == current || self.state(slot) != Some(ThreadState::Finished) {
+                continue;
+            }
+
+            self.threads[slot] = None;
+        }
+    }
+
+    /// Returns the first free slot, or [`None`] if [`MAX_THREADS`] threads exist.
+    fn free_slot(&self) -> Option<usize> {
+        self.threads.iter().position(Option::is_none)
+    }
+
+    /// Takes a spare stack kept by a free slot, if there is one.
+    fn take_spare_stack(&mut self) -> Option<KernelStack> {
+        (0..MAX_THREADS)
+            .filter(|&slot| self.threads[slot].is_none())
+            .find_map(|slot| self.stacks[slot].take())
+    }
+
+    /// Keeps `stack` as the spare stack of a free slot, returning it if no free slot is without
+    /// one.
+    fn keep_spare_stack(&mut self, stack: KernelStack) -> Option<KernelStack> {
+        let slot = (0..MAX_THREADS).find(|&slot| {
+            slot != BOOT_SLOT && self.threads[slot].is_none() && self.stacks[slot].is_none()
+        });
+
+        match slot {
+            Some(slot) => {
+                self.stacks[slot] = Some(stack);
+                None
+            }
+            None => Some(stack),
+        }
+    }
+
+    /// Returns the thread in `slot`.
+    ///
+    /// # Panics
+    /// Panics if the slot is free.
+    fn thread_mut(&mut self, slot: usize) -> &mut Thread {
+        self.threads[slot].as_mut().expect("thread slot is free")
+    }
+}
+
+/// A kernel thread.
+struct Thread {
+    /// The identifier of the thread.
+    id: ThreadId,
+    /// The name of the thread, used in diagnostics.
+    name: &'static str,
+    /// The scheduling state of the thread.
+    state: ThreadState,
+    /// The function the thread runs, taken once it starts.
+    entry: Option<fn()>,
+    /// The saved registers of the thread while it is not running.
+    context: Context,
+}
+
+/// The unique identifier of a kernel thread.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ThreadId(u64);
+
+impl ThreadId {
+    /// Returns the numeric value of this [`ThreadId`].
+    pub const fn value(self) -> u64 {
+        self.0
+    }
+}
+
+impl fmt::Display for ThreadId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{}", self.0)
+    }
+}
+
+/// The scheduling states of a kernel thread.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum ThreadState {
+    /// The thread is waiting to be switched to.
+    Runnable,
+    /// The thread is running.
+    Running,
+    /// The thread is waiting for [`scheduler::unblock()`].
+    Blocked,
+    /// The thread's entry function has returned.
+    Finished,
+}
+
+/// Various errors that can occur while spawning a kernel thread.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum SpawnError {
+    /// [`MAX_THREADS`] threads already exist.
+    TooManyThreads,
+    /// A stack for the thread could not be allocated.
+    Stack(StackError),
+}
+
+impl fmt::Display for SpawnError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TooManyThreads => write!(f, "the limit of {MAX_THREADS} threads was reached"),
+            Self::Stack(error) => write!(f, "failed to allocate a thread stack: {error}"),
+        }
+    }
+}
+
+#[cfg(feature = "ktest")]
+crate::kernel_test! {
+    fn threads_ping_pong() {
+        use core::sync::atomic::{AtomicUsize, Ordering};
+
+        const ROUNDS: usize = 100;
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+        /// Advances the counter from even to odd values.
+        fn ping() {
+            for _ in 0..ROUNDS {
+                while !COUNTER.load(Ordering::Relaxed).is_multiple_of(2) {
+                    yield_now();
+                }
+                COUNTER.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        /// Advances the counter from odd to even values.
+        fn pong() {
+            for _ in 0..ROUNDS {
+                while COUNTER.load(Ordering::Relaxed).is_multiple_of(2) {
+                    yield_now();
+                }
+                COUNTER.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        COUNTER.store(0, Ordering::Relaxed);
+        let boot = current();
+        assert_eq!(name(boot), Some("boot"));
+        let ping = spawn(ping, "ping").unwrap();
+        let pong = spawn(pong, "pong").unwrap();
+        assert_ne!(ping, pong);
+
+        while !(is_finished(ping) && is_finished(pong)) {
+            yield_now();
+        }
+        assert_eq!(current(), boot);
+        assert_eq!(COUNTER.load(Ordering::Relaxed), 2 * ROUNDS);
+
+        yield_now();
+        let threads = THREADS.lock();
+        assert!(threads
+            .threads
+            .iter()
+            .flatten()
+            .all(|thread| thread.id != ping && thread.id != pong));
+    }
+
+    fn threads_run_on_guarded_stacks() {
+        use core::sync::atomic::{AtomicUsize, Ordering};
+
+        static LOCAL_ADDRESS: AtomicUsize = AtomicUsize::new(0);
+
+        /// Records the address of one of its locals.
+        fn record() {
+            let local = 0u8;
+            LOCAL_ADDRESS.store(ptr::addr_of!(local) as usize, Ordering::Relaxed);
+        }
+
+        let first = spawn(record, "record").unwrap();
+        while !is_finished(first) {
+            yield_now();
+        }
+        let address = LOCAL_ADDRESS.load(Ordering::Relaxed);
+
+        let threads = THREADS.lock();
+        let stack = threads
+            .stacks
+            .iter()
+            .flatten()
+            .find(|stack| (stack.bottom().value()..stack.top().value()).contains(&address))
+            .expect("thread did not run on a kernel stack");
+        assert_eq!(stack.pages().size_in_bytes(), STACK_SIZE);
+        let bottom = stack.bottom();
+        drop(threads);
+
+        // The slot of the finished thread keeps its stack, so the next thread reuses it.
+        let second = spawn(record, "record").unwrap();
+        while !is_finished(second) {
+            yield_now();
+        }
+        let address = LOCAL_ADDRESS.load(Ordering::Relaxed);
+        assert!((bottom.value()..bottom.value() + STACK_SIZE).contains(&address));
+    }
+}
@@ -0,0 +1,329 @@
+//! Per-module filtering of log records.
+//!
+//! A [`ModuleFilter`] maps module path prefixes to [`LevelFilter`]s. Prefixes are shortened by
+//! [`short_target()`], the same as the targets shown in log output, and then match any module path
+//! that contains them starting and ending on a `::` boundary, so both `boot` and
+//! `kernel::arch::x86_64::boot` match `kernel::arch::x86_64::boot::limine`. When several prefixes
+//! match, the longest one wins.
+
+use core::fmt;
+
+use log::LevelFilter;
+
+use super::short_target;
+
+/// The maximum number of module prefixes a [`ModuleFilter`] can hold.
+pub const MAX_MODULE_FILTERS: usize = 16;
+/// The maximum length, in bytes, of a module prefix.
+pub const MAX_PREFIX_LEN: usize = 64;
+
+/// A fixed-capacity table of per-module [`LevelFilter`]s with a global default.
+#[derive(Debug)]
+pub struct ModuleFilter {
+    /// The level used for module paths that match no entry.
+    default: LevelFilter,
+    /// The entries of the table, of which the first `count` are in use.
+    entries: [Entry; MAX_MODULE_FILTERS],
+    /// The number of entries in use.
+    count: usize,
+}
+
+impl ModuleFilter {
+    /// Creates a new [`ModuleFilter`] without any module prefixes.
+    pub const fn new(default: LevelFilter) -> Self {
+        Self {
+            default,
+            entries: [Entry::EMPTY; MAX_MODULE_FILTERS],
+            count: 0,
+        }
+    }
+
+    /// Returns the level used for module paths that match no prefix.
+    pub const fn default_level(&self) -> LevelFilter {
+        self.default
+    }
+
+    /// Sets the level used for module paths that match no prefix.
+    pub fn set_default_level(&mut self, level: LevelFilter) {
+        self.default = level;
+    }
+
+    /// Sets the level of module paths matching `prefix`, replacing any level previously set for
+    /// `prefix`.
+    ///
+    /// # Errors
+    /// - [`ModuleFilterError::EmptyPrefix`]: `prefix` is empty.
+    /// - [`ModuleFilterError::PrefixTooLong`]: `prefix` is longer than [`MAX_PREFIX_LEN`].
+    /// - [`ModuleFilterError::TableFull`]: [`MAX_MODULE_FILTERS`] prefixes are already set.
+    pub fn set(&mut self, prefix: &str, level: LevelFilter) -> Result<(), ModuleFilterError> {
+        let prefix = short_target(prefix);
+        if prefix.is_empty() {
+            return Err(ModuleFilterError::EmptyPrefix);
+        } else if prefix.len() > MAX_PREFIX_LEN {
+            return Err(ModuleFilterError::PrefixTooLong);
+        }
+
+        if let Some(entry) = self.entries[..self.count]
+            .iter_mut()
+            .find(|entry| entry.prefix() == prefix)
+        {
+            entry.level = level;
+            return Ok(());
+        }
+
+        let entry = self
+            .entries
+            .get_mut(self.count)
+            .ok_or(ModuleFilterError::TableFull)?;
+        entry.prefix[..prefix.len()].copy_from_slice(prefix.as_bytes());
+        entry.len = prefix.len();
+        entry.level = level;
+        self.count += 1;
+
+        Ok(())
+    }
+
+    /// Returns the level of the longest prefix matching `module_path`, or the default level if no
+    /// prefix matches.
+    pub fn level_for(&self, module_path: &str) -> LevelFilter {
+        self.entries[..self.count]
+            .iter()
+            .filter(|entry| prefix_matches(module_path, entry.prefix()))
+            .max_by_key(|entry| entry.len)
+            .map_or(self.default, |entry| entry.level)
+    }
+
+    /// Returns the most verbose level of the default and every prefix.
+    pub fn max_level(&self) -> LevelFilter {
+        self.entries[..self.count]
+            .iter()
+            .map(|entry| entry.level)
+            .fold(self.default, Ord::max)
+    }
+}
+
+/// Returns `true` if `prefix` occurs in `module_path` starting and ending on a `::` boundary.
+fn prefix_matches(module_path: &str, prefix: &str) -> bool {
+    let mut rest = module_path;
+    loop {
+        if let Some(after) = rest.strip_prefix(prefix) {
+            if after.is_empty() || after.starts_with("::") {
+                return true;
+            }
+        }
+
+        match rest.split_once("::") {
+            Some((_, next)) => rest = next,
+            None => return false,
+        }
+    }
+}
+
+/// A module prefix and its level.
+#[derive(Clone, Copy, Debug)]
+struct Entry {
+    /// The bytes of the prefix, of which the first `len` are in use.
+    prefix: [u8; MAX_PREFIX_LEN],
+    /// The length of the prefix.
+    len: usize,
+    /// The level of module paths matching the prefix.
+    level: LevelFilter,
+}
+
+impl Entry {
+    /// An unused [`Entry`].
+    const EMPTY: Self = Self {
+        prefix: [0; MAX_PREFIX_LEN],
+        len: 0,
+        level: LevelFilter::Off,
+    };
+
+    /// Returns the prefix of this [`Entry`].
+    fn prefix(&self) -> &str {
+        // Entries are only ever filled from a `&str` of exactly `len` bytes.
+        core::str::from_utf8(&self.prefix[..self.len]).unwrap_or("")
+    }
+}
+
+/// Various errors that can occur when setting the level of a module prefix.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum ModuleFilterError {
+    /// The prefix was empty.
+    EmptyPrefix,
+    /// The prefix was longer than [`MAX_PREFIX_LEN`].
+    PrefixTooLong,
+    /// The table already contains [`MAX_MODULE_FILTERS`] prefixes.
+    TableFull,
+}
+
+impl fmt::Display for ModuleFilterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::EmptyPrefix => f.write_str("empty module prefix"),
+            Self::PrefixTooLong => {
+                write!(f, "module prefix longer than {MAX_PREFIX_LEN} bytes")
+            }
+            Self::TableFull => write!(f, "more than {MAX_MODULE_FILTERS} module filters"),
+        }
+    }
+}
+
+/// Returns an [`Iterator`] over the `(prefix, level)` pairs of a `log` kernel command line value
+/// such as `boot=warn,memory=trace`.
+///
+/// Entries whose level is missing or invalid are yielded as [`Err`] containing the entry.
+pub fn parse_module_levels(value: &str) -> impl Iterator<Item = Result<(&str, LevelFilter), &str>> {
+    value
+        .split(',')
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            entry
+                .split_once('=')
+                .and_then(|(prefix, level)| Some((prefix, super::parse_level(level)?)))
+                .ok_or(entry)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn module_levels_are_parsed() {
+        let entries =
+            parse_module_levels("boot=warn,memory=trace,,logging::sink=off").collect::<Vec<_>>();
+        assert_eq!(
+            entries,
+            [
+                Ok(("boot", LevelFilter::Warn)),
+                Ok(("memory", LevelFilter::Trace)),
+                Err("logging::sink=off"),
+            ]
+        );
+
+        assert_eq!(parse_module_levels("").count(), 0);
+        assert_eq!(parse_module_levels(",,").count(), 0);
+    }
+
+    #[test]
+    fn malformed_module_levels_are_rejected() {
+        for value in [
+            "boot",
+            "boot=",
+            "boot=verbose",
+            "boot=WARN",
+            "boot=warn=x",
+            "boot:warn",
+        ] {
+            assert_eq!(
+                parse_module_levels(value).collect::<Vec<_>>(),
+                [Err(value)],
+                "{value}"
+            );
+        }
+
+        // An empty prefix parses, but is rejected by the filter.
+        let entries = parse_module_levels("=warn").collect::<Vec<_>>();
+        assert_eq!(entries, [Ok(("", LevelFilter::Warn))]);
+        assert_eq!(
+            ModuleFilter::new(LevelFilter::Info).set("", LevelFilter::Warn),
+            Err(ModuleFilterError::EmptyPrefix)
+        );
+    }
+
+    #[test]
+    fn longest_matching_prefix_wins() {
+        let mut filter = ModuleFilter::new(LevelFilter::Info);
+        filter.set("boot", LevelFilter::Warn).unwrap();
+        filter.set("boot::limine", LevelFilter::Trace).unwrap();
+        filter.set("memory", LevelFilter::Error).unwrap();
+
+        let level_for = |module_path| filter.level_for(module_path);
+        assert_eq!(level_for("kernel::arch::x86_64::boot"), LevelFilter::Warn);
+        assert_eq!(
+            level_for("kernel::arch::x86_64::boot::info"),
+            LevelFilter::Warn
+        );
+        assert_eq!(
+            level_for("kernel::arch::x86_64::boot::limine"),
+            LevelFilter::Trace
+        );
+        assert_eq!(
+            level_for("kernel::arch::x86_64::boot::limine::request"),
+            LevelFilter::Trace
+        );
+        assert_eq!(
+            level_for("kernel::arch::x86_64::memory::heap"),
+            LevelFilter::Error
+        );
+
+        // Prefixes only match on `::` boundaries.
+        assert_eq!(level_for("kernel::bootstrap"), LevelFilter::Info);
+        assert_eq!(level_for("kernel::reboot"), LevelFilter::Info);
+        assert_eq!(level_for("kernel::logging"), LevelFilter::Info);
+
+        assert_eq!(filter.max_level(), LevelFilter::Trace);
+    }
+
+    #[test]
+    fn prefixes_are_shortened_and_replaced() {
+        let mut filter = ModuleFilter::new(LevelFilter::Info);
+        filter.set("boot", LevelFilter::Warn).unwrap();
+        filter
+            .set("kernel::arch::x86_64::boot", LevelFilter::Debug)
+            .unwrap();
+
+        assert_eq!(filter.count, 1);
+        assert_eq!(
+            filter.level_for("kernel::arch::x86_64::boot::info"),
+            LevelFilter::Debug
+        );
+
+        filter.set_default_level(LevelFilter::Off);
+        assert_eq!(filter.default_level(), LevelFilter::Off);
+        assert_eq!(filter.level_for("kernel::logging"), LevelFilter::Off);
+        assert_eq!(filter.max_level(), LevelFilter::Debug);
+    }
+
+    #[test]
+    fn filter_capacity_is_enforced() {
+        let mut filter = ModuleFilter::new(LevelFilter::Info);
+        assert_eq!(
+            filter.set(&"a".repeat(MAX_PREFIX_LEN + 1), LevelFilter::Warn),
+            Err(ModuleFilterError::PrefixTooLong)
+        );
+        filter
+            .set(&"a".repeat(MAX_PREFIX_LEN), LevelFilter::Warn)
+            .unwrap();
+
+        for index in 1..MAX_MODULE_FILTERS {
+            filter
+                .set(&format!("module{index}"), LevelFilter::Debug)
+                .unwrap();
+        }
+        assert_eq!(
+            filter.set("overflow", LevelFilter::Trace),
+            Err(ModuleFilterError::TableFull)
+        );
+
+        // Replacing an existing prefix needs no new entry.
+        filter.set("module1", LevelFilter::Trace).unwrap();
+        assert_eq!(filter.level_for("kernel::module1"), LevelFilter::Trace);
+    }
+
+    #[test]
+    fn filter_errors_display() {
+        assert_eq!(
+            ModuleFilterError::EmptyPrefix.to_string(),
+            "empty module prefix"
+        );
+        assert_eq!(
+            ModuleFilterError::PrefixTooLong.to_string(),
+            "module prefix longer than 64 bytes"
+        );
+        assert_eq!(
+            ModuleFilterError::TableFull.to_string(),
+            "more than 16 module filters"
+        );
+    }
+}
@@ -0,0 +1,226 @@
+//! Logging of memory regions as hexadecimal dumps.
+//!
+//! Each line of a dump covers [`BYTES_PER_LINE`] bytes and is logged as its own record, in the
+//! classic layout of an address column, the bytes in hexadecimal grouped in eights, and an ASCII
+//! gutter:
+//!
+//! ```text
+//! ffff800000001000  7f 45 4c 46 02 01 01 00  00 00 00 00 00 00 00 00  |.ELF............|
+//! ```
+
+use core::fmt;
+
+use crate::arch::{direct_map, PhysicalAddress, VirtualAddress};
+
+/// The number of bytes shown on each line of a dump.
+pub const BYTES_PER_LINE: usize = 16;
+/// The number of bytes in each group of hexadecimal bytes.
+const GROUP_SIZE: usize = 8;
+/// The maximum number of bytes dumped by [`hexdump()`]; the remainder is elided.
+pub const MAX_HEXDUMP_BYTES: usize = 4096;
+/// The character shown in the ASCII gutter in place of bytes that are not printable ASCII.
+const NON_PRINTABLE: char = '.';
+
+/// Logs `bytes`, which are located at `address`, as a hexadecimal dump labelled with `label`.
+///
+/// Each line is logged as a separate record at [`log::Level::Debug`]. At most
+/// [`MAX_HEXDUMP_BYTES`] bytes are dumped, followed by a line stating how many were elided.
+pub fn hexdump(label: &str, address: VirtualAddress, bytes: &[u8]) {
+    if !log::log_enabled!(log::Level::Debug) {
+        return;
+    }
+
+    write_hexdump(label, address.value(), bytes, |args| log::debug!("{args}"));
+}
+
+/// Passes each line of the hexadecimal dump of `bytes`, which are located at `address`, to
+/// `emit`, in the format logged by [`hexdump()`].
+fn write_hexdump(label: &str, address: usize, bytes: &[u8], mut emit: impl FnMut(fmt::Arguments)) {
+    emit(format_args!(
+        "{label}: {} bytes at {address:#x}",
+        bytes.len()
+    ));
+
+    let shown = &bytes[..bytes.len().min(MAX_HEXDUMP_BYTES)];
+    for (index, line) in shown.chunks(BYTES_PER_LINE).enumerate() {
+        let line_address = address.wrapping_add(index * BYTES_PER_LINE);
+        emit(format_args!(
+            "{label}: {}",
+            HexdumpLine::new(line_address, line)
+        ));
+    }
+
+    if shown.len() != bytes.len() {
+        emit(format_args!(
+            "{label}: ... {} bytes elided",
+            bytes.len() - shown.len()
+        ));
+    }
+}
+
+/// Logs the `len` bytes of physical memory starting at `address` as a hexadecimal dump labelled
+/// with `label`, reading them through the direct map.
+///
+/// Nothing is dumped, and a warning is logged, if the direct map has not been initialized.
+///
+/// # Safety
+/// The `len` bytes starting at `address` must be mapped by the direct map and readable without
+/// side effects.
+pub unsafe fn hexdump_physical(label: &str, address: PhysicalAddress, len: usize) {
+    if direct_map::offset().is_none() {
+        log::warn!("{label}: cannot dump {address:?} without the direct map");
+        return;
+    }
+
+    let virtual_address = direct_map::phys_to_virt(address);
+    // SAFETY:
+    // The caller guarantees that `len` bytes at `address` are mapped at `virtual_address` by the
+    // direct map and can be read.
+    let bytes = unsafe { core::slice::from_raw_parts(virtual_address.value() as *const u8, len) };
+    hexdump(label, virtual_address, bytes);
+}
+
+/// A single line of a hexadecimal dump, formatted without allocating.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub struct HexdumpLine<'bytes> {
+    /// The address of the first byte of the line.
+    address: usize,
+    /// The bytes shown on the line, at most [`BYTES_PER_LINE`] of them.
+    bytes: &'bytes [u8],
+}
+
+impl<'bytes> HexdumpLine<'bytes> {
+    /// Creates a [`HexdumpLine`] showing `bytes`, the first of which is located at `address`.
+    ///
+    /// Only the first [`BYTES_PER_LINE`] bytes are shown. A shorter line is padded so that its
+    /// ASCII gutter lines up with those of full lines.
+    pub fn new(address: usize, bytes: &'bytes [u8]) -> Self {
+        Self {
+            address,
+            bytes: &bytes[..bytes.len().min(BYTES_PER_LINE)],
+        }
+    }
+}
+
+impl fmt::Display for HexdumpLine<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:016x} ", self.address)?;
+
+        for index in 0..BYTES_PER_LINE {
+            if index % GROUP_SIZE == 0 {
+                f.write_str(" ")?;
+            }
+
+            match self.bytes.get(index) {
+                Some(byte) => write!(f, "{byte:02x} ")?,
+                None => f.write_str("   ")?,
+            }
+        }
+
+        f.write_str(" |")?;
+        for &byte in self.bytes {
+            let c = if byte.is_ascii_graphic() || byte == b' ' {
+                byte as char
+            } else {
+                NON_PRINTABLE
+            };
+            fmt::Write::write_char(f, c)?;
+        }
+        f.write_str("|")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{string::String, vec::Vec};
+
+    use super::*;
+
+    /// Returns the lines that [`hexdump()`] logs for `bytes` located at `address`.
+    fn dumped(label: &str, address: usize, bytes: &[u8]) -> Vec<String> {
+        let mut lines = Vec::new();
+        write_hexdump(label, address, bytes, |args| {
+            lines.push(std::format!("{args}"))
+        });
+        lines
+    }
+
+    #[test]
+    fn full_line_groups_bytes_in_eights() {
+        let bytes = [
+            0x7f, 0x45, 0x4c, 0x46, 0x02, 0x01, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00,
+        ];
+
+        assert_eq!(
+            std::format!("{}", HexdumpLine::new(0xffff_8000_0000_1000, &bytes)),
+            "ffff800000001000  7f 45 4c 46 02 01 01 00  00 00 00 00 00 00 00 00  |.ELF............|"
+        );
+    }
+
+    #[test]
+    fn partial_line_is_padded_to_align_the_gutter() {
+        let full = std::format!("{}", HexdumpLine::new(0, &[b'a'; BYTES_PER_LINE]));
+        let partial = std::format!("{}", HexdumpLine::new(0, b"Hello, world"));
+
+        assert_eq!(
+            partial,
+            "0000000000000000  48 65 6c 6c 6f 2c 20 77  6f 72 6c 64              |Hello, world|"
+        );
+        assert_eq!(partial.find('|'), full.find('|'));
+
+        let empty = std::format!("{}", HexdumpLine::new(0, &[]));
+        assert_eq!(empty.find('|'), full.find('|'));
+        assert!(empty.ends_with("||"));
+    }
+
+    #[test]
+    fn non_printable_bytes_are_replaced_in_the_gutter() {
+        let line = std::format!(
+            "{}",
+            HexdumpLine::new(0, &[0x00, b' ', b'~', 0x7f, 0x80, 0xff, b'\n'])
+        );
+
+        assert!(line.ends_with("|. ~....|"), "{line}");
+    }
+
+    #[test]
+    fn line_shows_at_most_one_line_of_bytes() {
+        let bytes = [0x11; BYTES_PER_LINE + 4];
+
+        assert_eq!(
+            HexdumpLine::new(0, &bytes),
+            HexdumpLine::new(0, &bytes[..BYTES_PER_LINE])
+        );
+    }
+
+    #[test]
+    fn dump_addresses_each_line() {
+        let bytes: Vec<u8> = (0..20).collect();
+        let lines = dumped("test", 0x1000, &bytes);
+
+        assert_eq!(
+            lines,
+            [
+                "test: 20 bytes at 0x1000",
+                "test: 0000000000001000  00 01 02 03 04 05 06 07  08 09 0a 0b 0c 0d 0e 0f  |................|",
+                "test: 0000000000001010  10 11 12 13                                       |....|",
+            ]
+        );
+    }
+
+    #[test]
+    fn capped_dump_reports_elided_bytes() {
+        let bytes = [b'x'; MAX_HEXDUMP_BYTES + 10];
+        let lines = dumped("big", 0, &bytes);
+
+        assert_eq!(lines.len(), 1 + MAX_HEXDUMP_BYTES / BYTES_PER_LINE + 1);
+        assert_eq!(lines[0], std::format!("big: {} bytes at 0x0", bytes.len()));
+        assert!(lines[lines.len() - 2].starts_with("big: 0000000000000ff0 "));
+        assert_eq!(lines[lines.len() - 1], "big: ... 10 bytes elided");
+
+        // A dump of exactly the maximum size elides nothing.
+        let lines = dumped("big", 0, &bytes[..MAX_HEXDUMP_BYTES]);
+        assert!(!lines.last().unwrap().contains("elided"));
+    }
+}
@@ -0,0 +1,545 @@
+//! Driver for the logging capabilities of kernel.
+
+use core::{
+    fmt,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+
+use crate::{
+    arch::logging::init_arch_logger,
+    spinlock::{Acquisition, IrqSpinlock},
+};
+
+pub use hexdump::{hexdump, hexdump_physical};
+
+use filter::{parse_module_levels, ModuleFilter, ModuleFilterError};
+use ring::RingBuffer;
+use sink::{parse_sink_levels, FormattedRecord, Sink, SinkError, SinkRegistry};
+use stats::{LogCounters, LogStats};
+
+pub mod filter;
+pub mod hexdump;
+pub mod ring;
+pub mod sink;
+pub mod stats;
+
+/// The size, in bytes, of the in-memory log history.
+pub const RING_BUFFER_SIZE: usize = 64 * 1024;
+
+// Records may be logged from interrupt handlers, so every lock on the logging path disables
+// interrupts while it is held. Sinks must not enable interrupts or block while writing a record.
+static SINKS: IrqSpinlock<SinkRegistry> = IrqSpinlock::new(SinkRegistry::new());
+static FILTER: IrqSpinlock<ModuleFilter> = IrqSpinlock::new(ModuleFilter::new(DEFAULT_LEVEL));
+/// The in-memory log history.
+static RING: IrqSpinlock<RingBuffer<RING_BUFFER_SIZE>> = IrqSpinlock::new(RingBuffer::new());
+
+// Lock-free copies of the filtering state, consulted by `may_be_enabled()` so that records
+// nothing will accept are rejected without acquiring `SINKS` or `FILTER`. Levels are stored as
+// `LevelFilter as usize`.
+/// The most verbose level of any registered [`Sink`].
+static SINK_MAX_LEVEL: AtomicUsize = AtomicUsize::new(log::LevelFilter::Off as usize);
+/// The default level of [`FILTER`].
+static DEFAULT_FILTER_LEVEL: AtomicUsize = AtomicUsize::new(DEFAULT_LEVEL as usize);
+/// Whether any per-module level has been set in [`FILTER`].
+static MODULE_FILTERS_SET: AtomicBool = AtomicBool::new(false);
+/// The statistics of the logging subsystem.
+static COUNTERS: LogCounters = LogCounters::new();
+/// Whether [`init_logging()`] has been called.
+static INITIALIZED: AtomicBool = AtomicBool::new(false);
+
+/// The maximum level of messages that are logged when the kernel command line does not specify
+/// one.
+pub const DEFAULT_LEVEL: log::LevelFilter = log::LevelFilter::Info;
+
+/// Initializes kernel logging, applying the logging options in `cmdline`.
+///
+/// `loglevel=<level>` sets the default level, while `log=<prefix>=<level>,...` sets the level of
+/// individual modules. `log.<sink>=<level>` sets the level of an individual [`Sink`], and
+/// `logtarget=off` omits the module path from formatted records.
+///
+/// # Errors
+/// Returns [`LoggingInitError::AlreadyInitialized`] without doing anything if logging has already
+/// been initialized, or [`LoggingInitError::LoggerAlreadySet`] if another logger was installed.
+pub fn init_logging(cmdline: Option<&str>) -> Result<(), LoggingInitError> {
+    if INITIALIZED.swap(true, Ordering::AcqRel) {
+        return Err(LoggingInitError::AlreadyInitialized);
+    }
+
+    let level = cmdline.and_then(|cmdline| crate::cmdline::get_str(cmdline, "loglevel"));
+    let parsed_level = level.map(parse_level);
+
+    log::set_logger(&Logger {}).map_err(|_| LoggingInitError::LoggerAlreadySet)?;
+    set_level(parsed_level.flatten().unwrap_or(DEFAULT_LEVEL));
+
+    let report = {
+        let mut sinks = SINKS.lock();
+        // The registry is empty, so registering the first sink cannot fail.
+        let _ = sinks.register(&RING_SINK);
+        let report = init_arch_logger(&mut sinks, cmdline);
+        update_sink_max_level(&sinks);
+        report
+    };
+
+    log::info!("{}", crate::build_info::summary());
+    report.log();
+    // Invalid values of `loglevel` and `logtarget` are reported by the option registry.
+    if let Some(Some(level)) = parsed_level {
+        if level > log::STATIC_MAX_LEVEL {
+            log::warn!(
+                "loglevel {level} exceeds the compiled-in maximum; using {}",
+                log::STATIC_MAX_LEVEL
+            );
+        }
+    }
+
+    let module_levels = cmdline.and_then(|cmdline| crate::cmdline::get_str(cmdline, "log"));
+    for entry in module_levels.into_iter().flat_map(parse_module_levels) {
+        match entry {
+            Ok((prefix, level)) => {
+                if let Err(error) = set_module_level(prefix, level) {
+                    log::warn!("Ignoring log filter `{prefix}`: {error}");
+                }
+            }
+            Err(entry) => log::warn!("Invalid log filter `{entry}`"),
+        }
+    }
+
+    apply_sink_levels(None, cmdline);
+
+    if cmdline.and_then(|cmdline| crate::cmdline::get_str(cmdline, "logtarget")) == Some("off") {
+        sink::set_show_target(false);
+    }
+
+    Ok(())
+}
+
+/// Various errors that can occur while initializing logging.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum LoggingInitError {
+    /// [`init_logging()`] has already been called.
+    AlreadyInitialized,
+    /// A logger other than the kernel's was already installed.
+    LoggerAlreadySet,
+}
+
+impl fmt::Display for LoggingInitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::AlreadyInitialized => f.write_str("logging already initialized"),
+            Self::LoggerAlreadySet => f.write_str("another logger is already installed"),
+        }
+    }
+}
+
+/// Registers `sink`, applying any `log.<sink>=<level>` option for it in `cmdline`.
+///
+/// # Errors
+/// Returns a [`SinkError`] if `sink` could not be registered.
+pub fn register_sink(sink: &'static dyn Sink, cmdline: Option<&str>) -> Result<(), SinkError> {
+    {
+        let mut sinks = SINKS.lock();
+        sinks.register(sink)?;
+        update_sink_max_level(&sinks);
+    }
+    apply_sink_levels(Some(sink.name()), cmdline);
+    Ok(())
+}
+
+/// Sets the most verbose level written to the [`Sink`] called `name`.
+///
+/// # Errors
+/// Returns [`SinkError::UnknownSink`] if no sink called `name` is registered.
+pub fn set_sink_level(name: &str, level: log::LevelFilter) -> Result<(), SinkError> {
+    let mut sinks = SINKS.lock();
+    sinks.set_level(name, level)?;
+    update_sink_max_level(&sinks);
+    Ok(())
+}
+
+/// Publishes the most verbose level of any sink in `sinks` for [`may_be_enabled()`].
+fn update_sink_max_level(sinks: &SinkRegistry) {
+    SINK_MAX_LEVEL.store(sinks.max_level() as usize, Ordering::Relaxed);
+}
+
+/// Applies the `log.<sink>=<level>` options in `cmdline` for the sink called `only`, or for every
+/// sink if `only` is [`None`], warning about invalid options.
+fn apply_sink_levels(only: Option<&str>, cmdline: Option<&str>) {
+    for option in cmdline.into_iter().flat_map(parse_sink_levels) {
+        match option {
+            // Sinks that are not registered yet apply their options when they are registered.
+            Ok((name, level)) if only.is_none_or(|only| only == name) => {
+                let _ = set_sink_level(name, level);
+            }
+            Err(option) if only.is_none() => log::warn!("Invalid log sink level `{option}`"),
+            _ => {}
+        }
+    }
+}
+
+/// Sets the maximum level of messages that are logged from modules without a more specific level.
+///
+/// Levels are clamped to [`log::STATIC_MAX_LEVEL`], since messages above the level selected by the
+/// `log-level-*` features are removed at compile time.
+pub fn set_level(level: log::LevelFilter) {
+    let mut filter = FILTER.lock();
+    filter.set_default_level(level);
+    DEFAULT_FILTER_LEVEL.store(level as usize, Ordering::Relaxed);
+    update_max_level(&filter);
+}
+
+/// Sets the maximum level of messages that are logged from modules whose path matches `prefix`.
+///
+/// See [`filter`] for how prefixes are matched.
+///
+/// # Errors
+/// Returns a [`ModuleFilterError`] if `prefix` is invalid or too many prefixes have been set.
+pub fn set_module_level(prefix: &str, level: log::LevelFilter) -> Result<(), ModuleFilterError> {
+    let mut filter = FILTER.lock();
+    filter.set(prefix, level)?;
+    MODULE_FILTERS_SET.store(true, Ordering::Relaxed);
+    update_max_level(&filter);
+    Ok(())
+}
+
+/// Raises the level checked by the `log` macros to the most verbose level in `filter`, so that
+/// records which pass a per-module level reach the [`Logger`].
+fn update_max_level(filter: &ModuleFilter) {
+    log::set_max_level(filter.max_level().min(log::STATIC_MAX_LEVEL));
+}
+
+/// Returns `false` if no [`Sink`] would accept a record with `metadata`, without acquiring any
+/// lock.
+///
+/// This may return `true` for records that the per-module levels reject, since those are only
+/// checked exactly by [`passes_filter()`].
+fn may_be_enabled(metadata: &log::Metadata) -> bool {
+    let level = metadata.level() as usize;
+    if level > SINK_MAX_LEVEL.load(Ordering::Relaxed) {
+        return false;
+    }
+
+    if MODULE_FILTERS_SET.load(Ordering::Relaxed) {
+        // `log::max_level()` is the most verbose level of any module.
+        level <= log::max_level() as usize
+    } else {
+        level <= DEFAULT_FILTER_LEVEL.load(Ordering::Relaxed)
+    }
+}
+
+/// Returns a snapshot of the statistics of the logging subsystem.
+pub fn stats() -> LogStats {
+    let mut stats = COUNTERS.snapshot();
+    SINKS.lock().fill_stats(&mut stats);
+    stats
+}
+
+/// Returns `true` if `metadata` passes the level of the module it was logged from.
+fn passes_filter(metadata: &log::Metadata) -> bool {
+    metadata.level() <= FILTER.lock().level_for(metadata.target())
+}
+
+/// Writes the in-memory log history to `writer`, oldest record first.
+///
+/// # Errors
+/// Returns [`fmt::Error`] if writing to `writer` fails.
+pub fn dump_ring_buffer(writer: &mut impl fmt::Write) -> fmt::Result {
+    dump_ring_buffer_tail(writer, RING_BUFFER_SIZE)
+}
+
+/// Writes the records contained in the last `max_bytes` of the in-memory log history to
+/// `writer`, oldest record first.
+///
+/// # Errors
+/// Returns [`fmt::Error`] if writing to `writer` fails.
+pub fn dump_ring_buffer_tail(writer: &mut impl fmt::Write, max_bytes: usize) -> fmt::Result {
+    RING.lock().dump(writer, max_bytes)
+}
+
+/// The number of attempts made to acquire a logging lock during a panic before it is bypassed.
+const PANIC_LOCK_ATTEMPTS: usize = 100_000;
+
+/// Whether the panic handler has started, after which records are no longer logged.
+static PANICKING: AtomicBool = AtomicBool::new(false);
+
+/// Returns `true` if the panic handler has started.
+pub fn panicking() -> bool {
+    PANICKING.load(Ordering::Relaxed)
+}
+
+/// Writes a single framed block describing a panic to every [`Sink`] from the panic handler.
+///
+/// The block contains `message`, `location`, `registers`, `backtrace`, the logging [`stats()`],
+/// and the records contained in the last `replay_bytes` of the in-memory log history, delimited by
+/// `=== PANIC BEGIN ===` and `=== PANIC END ===`. Once this is called, other processors and
+/// interrupt handlers stop logging, so the block is not interleaved with other output.
+///
+/// Each lock involved is acquired with a bounded number of attempts and then forcibly bypassed, so
+/// that a panic raised while a logging lock is held cannot deadlock. If any lock was bypassed, the
+/// block says so.
+///
+/// Returns without writing anything if a panic is already being reported, such as when the panic
+/// handler itself panics.
+pub fn panic_block(
+    message: &dyn fmt::Display,
+    location: Option<&core::panic::Location<'_>>,
+    registers: &dyn fmt::Display,
+    backtrace: Option<&dyn fmt::Display>,
+    replay_bytes: usize,
+) {
+    if PANICKING.swap(true, Ordering::Relaxed) {
+        return;
+    }
+
+    // SAFETY:
+    // The panic handler never returns, so the context holding a bypassed lock on this processor
+    // never uses its guard again.
+    let (sinks, acquisition) = unsafe { SINKS.lock_or_bypass(PANIC_LOCK_ATTEMPTS) };
+    // SAFETY:
+    // Same as above.
+    let (ring, ring_acquisition) = unsafe { RING.lock_or_bypass(PANIC_LOCK_ATTEMPTS) };
+
+    let mut bypasses = [acquisition, ring_acquisition]
+        .into_iter()
+        .filter(|&acquisition| acquisition == Acquisition::Bypassed)
+        .count();
+    for sink in sinks.sinks() {
+        // SAFETY:
+        // Same as above.
+        if unsafe { sink.panic_write("", PANIC_LOCK_ATTEMPTS) } == Acquisition::Bypassed {
+            bypasses += 1;
+        }
+    }
+    for _ in 0..bypasses {
+        COUNTERS.lock_bypass();
+    }
+
+    let mut stats = COUNTERS.snapshot();
+    sinks.fill_stats(&mut stats);
+
+    let block = PanicBlock {
+        message,
+        location,
+        registers,
+        backtrace,
+        stats: &stats,
+        bypassed: bypasses != 0,
+    };
+    let _ = block.write(&mut PanicWriter(&sinks), &ring, replay_bytes);
+}
+
+/// The contents of the block written by [`panic_block()`].
+pub struct PanicBlock<'a> {
+    /// The panic message.
+    pub message: &'a dyn fmt::Display,
+    /// The location at which the panic occurred, if known.
+    pub location: Option<&'a core::panic::Location<'a>>,
+    /// The state of the processor when the panic occurred.
+    pub registers: &'a dyn fmt::Display,
+    /// The stack trace, or [`None`] if stack traces are disabled.
+    pub backtrace: Option<&'a dyn fmt::Display>,
+    /// The statistics of the logging subsystem.
+    pub stats: &'a LogStats,
+    /// Whether any lock had to be bypassed to write the block.
+    pub bypassed: bool,
+}
+
+impl PanicBlock<'_> {
+    /// Writes this [`PanicBlock`] to `writer`, followed by the records contained in the last
+    /// `replay_bytes` of `ring`.
+    ///
+    /// # Errors
+    /// Returns [`fmt::Error`] if writing to `writer` fails.
+    pub fn write<const N: usize>(
+        &self,
+        writer: &mut impl fmt::Write,
+        ring: &RingBuffer<N>,
+        replay_bytes: usize,
+    ) -> fmt::Result {
+        writeln!(writer, "=== PANIC BEGIN ===")?;
+        if self.bypassed {
+            writeln!(writer, "[lock bypassed]")?;
+        }
+        writeln!(writer, "build: {}", crate::build_info::summary())?;
+        writeln!(writer, "panic: {}", self.message)?;
+        match self.location {
+            Some(location) => writeln!(writer, "location: {location}")?,
+            None => writeln!(writer, "location: unknown")?,
+        }
+        writeln!(writer, "registers:\n{}", self.registers)?;
+        match self.backtrace {
+            Some(backtrace) => writeln!(writer, "backtrace:\n{backtrace}")?,
+            None => writeln!(writer, "backtrace: disabled")?,
+        }
+        writeln!(writer, "logging statistics:\n{}", self.stats)?;
+        writeln!(writer, "recent log records:")?;
+        ring.dump(writer, replay_bytes)?;
+        writeln!(writer, "=== PANIC END ===")
+    }
+}
+
+/// A [`fmt::Write`] implementation that writes to every [`Sink`] from the panic handler.
+struct PanicWriter<'registry>(&'registry SinkRegistry);
+
+impl fmt::Write for PanicWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for sink in self.0.sinks() {
+            // SAFETY:
+            // `PanicWriter` is only used by the panic handler, which never returns, so the
+            // context holding a bypassed lock on this processor never uses its guard again.
+            unsafe { sink.panic_write(s, PANIC_LOCK_ATTEMPTS) };
+        }
+
+        Ok(())
+    }
+}
+
+/// The [`Sink`] recording every record into the in-memory log history.
+static RING_SINK: RingSink = RingSink;
+
+/// A [`Sink`] recording into the in-memory log history.
+struct RingSink;
+
+impl Sink for RingSink {
+    fn name(&self) -> &'static str {
+        "ring"
+    }
+
+    fn write_record(&self, record: &log::Record) -> usize {
+        RING.lock()
+            .push_record(format_args!("{}", FormattedRecord(record)))
+    }
+
+    unsafe fn panic_write(&self, _: &str, _: usize) -> Acquisition {
+        // Panic output is not recorded, since the history is replayed by the panic handler itself.
+        Acquisition::Acquired
+    }
+}
+
+/// The module path prefixes removed by [`short_target()`], in the order they are tried.
+const STRIPPED_TARGET_PREFIXES: &[&str] = &["kernel::arch::x86_64::", "kernel::"];
+
+/// Shortens the target of a record, normally its module path, by removing the crate name and the
+/// architecture module.
+///
+/// For example, `kernel::arch::x86_64::boot::limine` becomes `boot::limine` and
+/// `kernel::logging` becomes `logging`. This is used both when formatting records and when
+/// matching per-module levels, so both see the same names.
+pub fn short_target(target: &str) -> &str {
+    STRIPPED_TARGET_PREFIXES
+        .iter()
+        .find_map(|prefix| target.strip_prefix(prefix))
+        .filter(|rest| !rest.is_empty())
+        .unwrap_or(target)
+}
+
+/// Parses a log level name, as accepted by the `loglevel` kernel command line option.
+///
+/// Returns [`None`] if `value` is not one of `error`, `warn`, `info`, `debug`, or `trace`.
+pub fn parse_level(value: &str) -> Option<log::LevelFilter> {
+    let level = match value {
+        "error" => log::LevelFilter::Error,
+        "warn" => log::LevelFilter::Warn,
+        "info" => log::LevelFilter::Info,
+        "debug" => log::LevelFilter::Debug,
+        "trace" => log::LevelFilter::Trace,
+        _ => return None,
+    };
+
+    Some(level)
+}
+
+struct Logger {}
+
+impl log::Log for Logger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        may_be_enabled(metadata)
+    }
+
+    fn log(&self, record: &log::Record) {
+        // Output is left to the panic handler, so that its block is not interleaved with records.
+        if panicking() {
+            return;
+        }
+
+        if !may_be_enabled(record.metadata()) {
+            COUNTERS.skipped_record();
+            return;
+        }
+
+        if !passes_filter(record.metadata()) {
+            COUNTERS.skipped_record();
+            return;
+        }
+
+        COUNTERS.record(record.level());
+        let warning = {
+            let sinks = SINKS.lock();
+            sinks.dispatch(record, &COUNTERS);
+            sinks.take_warning()
+        };
+
+        // The warning is logged once the lock is released, since logging it re-enters `log()`.
+        if let Some((name, warning)) = warning {
+            log::warn!("log sink `{name}`: {warning}");
+        }
+    }
+
+    fn flush(&self) {
+        SINKS.lock().flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A logger that discards every record, standing in for one installed by other code.
+    struct StubLogger;
+
+    impl log::Log for StubLogger {
+        fn enabled(&self, _: &log::Metadata) -> bool {
+            false
+        }
+
+        fn log(&self, _: &log::Record) {}
+
+        fn flush(&self) {}
+    }
+
+    // Both errors depend on process-wide state, so they are checked in order by a single test.
+    #[test]
+    fn init_logging_only_succeeds_once() {
+        static STUB: StubLogger = StubLogger;
+
+        log::set_logger(&STUB).expect("no other test installs a logger");
+        assert!(!INITIALIZED.load(Ordering::Acquire));
+
+        // Another logger was installed first, so the kernel's is not, and no sink is probed.
+        assert_eq!(
+            init_logging(Some("loglevel=trace")),
+            Err(LoggingInitError::LoggerAlreadySet)
+        );
+        assert_eq!(
+            SINK_MAX_LEVEL.load(Ordering::Relaxed),
+            log::LevelFilter::Off as usize
+        );
+
+        // Initialization is not retried, even though the first attempt failed.
+        assert_eq!(
+            init_logging(None),
+            Err(LoggingInitError::AlreadyInitialized)
+        );
+    }
+
+    #[test]
+    fn init_errors_display() {
+        assert_eq!(
+            std::format!("{}", LoggingInitError::AlreadyInitialized),
+            "logging already initialized"
+        );
+        assert_eq!(
+            std::format!("{}", LoggingInitError::LoggerAlreadySet),
+            "another logger is already installed"
+        );
+    }
+}
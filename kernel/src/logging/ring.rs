@@ -0,0 +1,336 @@
+//! In-memory history of formatted log records.
+//!
+//! Every record is appended to a [`RingBuffer`] as plain text terminated by a newline, so the
+//! history survives even when no console was attached and can be replayed later. Once full, the
+//! oldest bytes are overwritten; partially overwritten records are skipped when dumping.
+
+use core::fmt::{self, Write};
+
+/// The maximum number of bytes of a single record, excluding its terminating newline.
+pub const MAX_RECORD_LEN: usize = 1024;
+/// The marker appended to records that were truncated to [`MAX_RECORD_LEN`].
+const TRUNCATION_MARKER: &str = "...";
+
+/// A fixed-size buffer holding the most recent `N` bytes of log records.
+pub struct RingBuffer<const N: usize> {
+    /// The stored bytes.
+    data: [u8; N],
+    /// The index in `data` at which the next byte is written.
+    head: usize,
+    /// The number of valid bytes, ending just before `head`.
+    len: usize,
+    /// Whether any byte has been overwritten, in which case the oldest record may be partial.
+    wrapped: bool,
+}
+
+impl<const N: usize> RingBuffer<N> {
+    /// Creates a new, empty [`RingBuffer`].
+    pub const fn new() -> Self {
+        Self {
+            data: [0; N],
+            head: 0,
+            len: 0,
+            wrapped: false,
+        }
+    }
+
+    /// Returns the number of bytes currently stored.
+    pub const fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if no bytes are stored.
+    pub const fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Appends a record formatted from `args`, truncating it to [`MAX_RECORD_LEN`] bytes and
+    /// terminating it with a newline.
+    ///
+    /// Returns the number of bytes appended.
+    pub fn push_record(&mut self, args: fmt::Arguments) -> usize {
+        let mut writer = RecordWriter {
+            buffer: self,
+            written: 0,
+            truncated: false,
+        };
+        let _ = writer.write_fmt(args);
+        let (mut written, truncated) = (writer.written, writer.truncated);
+        if truncated {
+            self.push_bytes(TRUNCATION_MARKER.as_bytes());
+            written += TRUNCATION_MARKER.len();
+        }
+        self.push_bytes(b"\n");
+
+        written + 1
+    }
+
+    /// Writes at most the last `max_bytes` stored bytes to `writer`, starting at a record
+    /// boundary.
+    ///
+    /// # Errors
+    /// Returns [`fmt::Error`] if writing to `writer` fails.
+    pub fn dump(&self, writer: &mut impl Write, max_bytes: usize) -> fmt::Result {
+        let take = self.len.min(max_bytes);
+        let mut start = self.len - take;
+
+        // Skip the remainder of a record whose beginning is not included.
+        if start != 0 || self.wrapped {
+            let at_boundary = start != 0 && self.byte(start - 1) == b'\n';
+            if !at_boundary {
+                match (start..self.len).find(|&index| self.byte(index) == b'\n') {
+                    Some(newline) => start = newline + 1,
+                    None => return Ok(()),
+                }
+            }
+        }
+
+        let mut chunk = [0u8; 128];
+        let mut chunk_len = 0;
+        for index in start..self.len {
+            chunk[chunk_len] = self.byte(index);
+            chunk_len += 1;
+            if chunk_len == chunk.len() {
+                chunk_len = write_utf8(writer, &mut chunk, chunk_len)?;
+            }
+        }
+
+        while chunk_len != 0 {
+            let remaining = write_utf8(writer, &mut chunk, chunk_len)?;
+            if remaining == chunk_len {
+                // Only an incomplete character is left, which can never become valid.
+                writer.write_char(char::REPLACEMENT_CHARACTER)?;
+                break;
+            }
+            chunk_len = remaining;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the byte at `index` counting from the oldest stored byte.
+    fn byte(&self, index: usize) -> u8 {
+        self.data[(self.head + N - self.len + index) % N]
+    }
+
+    /// Appends `bytes`, overwriting the oldest bytes if necessary.
+    fn push_bytes(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.data[self.head] = byte;
+            self.head = (self.head + 1) % N;
+            if self.len == N {
+                self.wrapped = true;
+            } else {
+                self.len += 1;
+            }
+        }
+    }
+}
+
+impl<const N: usize> Default for RingBuffer<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Writes the valid UTF-8 prefix of `chunk[..len]` to `writer`, replacing invalid sequences, and
+/// moves any trailing incomplete character to the start of `chunk`.
+///
+/// Returns the number of bytes left in `chunk`.
+fn write_utf8(writer: &mut impl Write, chunk: &mut [u8], len: usize) -> Result<usize, fmt::Error> {
+    let mut bytes = &chunk[..len];
+    loop {
+        match core::str::from_utf8(bytes) {
+            Ok(text) => {
+                writer.write_str(text)?;
+                return Ok(0);
+            }
+            Err(error) => {
+                let (valid, rest) = bytes.split_at(error.valid_up_to());
+                // SAFETY:
+                // `valid_up_to` bytes were verified to be valid UTF-8.
+                writer.write_str(unsafe { core::str::from_utf8_unchecked(valid) })?;
+
+                match error.error_len() {
+                    Some(invalid) => {
+                        writer.write_char(char::REPLACEMENT_CHARACTER)?;
+                        bytes = &rest[invalid..];
+                    }
+                    None => {
+                        let remaining = rest.len();
+                        let offset = len - remaining;
+                        chunk.copy_within(offset..len, 0);
+                        return Ok(remaining);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// A [`Write`] implementation that appends to a [`RingBuffer`], stopping after
+/// [`MAX_RECORD_LEN`] bytes.
+struct RecordWriter<'buffer, const N: usize> {
+    /// The buffer being written to.
+    buffer: &'buffer mut RingBuffer<N>,
+    /// The number of bytes of the record written so far.
+    written: usize,
+    /// Whether part of the record was discarded.
+    truncated: bool,
+}
+
+impl<const N: usize> Write for RecordWriter<'_, N> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let available = MAX_RECORD_LEN - self.written;
+        let mut len = s.len().min(available);
+        while !s.is_char_boundary(len) {
+            len -= 1;
+        }
+        if len < s.len() {
+            self.truncated = true;
+        }
+
+        self.buffer.push_bytes(&s.as_bytes()[..len]);
+        self.written += len;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{string::String, vec::Vec};
+
+    use super::*;
+
+    /// Returns everything that [`RingBuffer::dump()`] writes with a limit of `max_bytes`.
+    fn dumped<const N: usize>(buffer: &RingBuffer<N>, max_bytes: usize) -> String {
+        let mut output = String::new();
+        buffer.dump(&mut output, max_bytes).unwrap();
+        output
+    }
+
+    #[test]
+    fn exact_fit_record_is_kept_whole() {
+        let mut buffer = RingBuffer::<8>::new();
+        assert!(buffer.is_empty());
+
+        assert_eq!(buffer.push_record(format_args!("abcdefg")), 8);
+        assert_eq!(buffer.len(), 8);
+        assert!(!buffer.wrapped);
+        assert_eq!(dumped(&buffer, usize::MAX), "abcdefg\n");
+    }
+
+    #[test]
+    fn wrapped_record_is_read_across_the_end() {
+        let mut buffer = RingBuffer::<16>::new();
+        buffer.push_record(format_args!("aaaa"));
+        buffer.push_record(format_args!("bbbbbb"));
+        buffer.push_record(format_args!("cccccc"));
+
+        assert!(buffer.wrapped);
+        assert_eq!(buffer.len(), 16);
+        // The last record starts before the end of the storage and finishes at its start.
+        assert_eq!(buffer.head, 3);
+        assert_eq!(dumped(&buffer, usize::MAX), "bbbbbb\ncccccc\n");
+    }
+
+    #[test]
+    fn partially_overwritten_record_is_skipped() {
+        let mut buffer = RingBuffer::<8>::new();
+        buffer.push_record(format_args!("abcdefg"));
+        buffer.push_record(format_args!("xy"));
+
+        assert_eq!(dumped(&buffer, usize::MAX), "xy\n");
+
+        // Once a single record spans the whole buffer, nothing is left to dump.
+        buffer.push_record(format_args!("0123456789"));
+        assert_eq!(dumped(&buffer, usize::MAX), "");
+    }
+
+    #[test]
+    fn oversized_record_is_truncated() {
+        let mut buffer = RingBuffer::<2048>::new();
+        let record = "x".repeat(MAX_RECORD_LEN + 100);
+
+        let written = buffer.push_record(format_args!("{record}"));
+        assert_eq!(written, MAX_RECORD_LEN + TRUNCATION_MARKER.len() + 1);
+        assert_eq!(buffer.len(), written);
+
+        let expected = std::format!("{}{TRUNCATION_MARKER}\n", &record[..MAX_RECORD_LEN]);
+        assert_eq!(dumped(&buffer, usize::MAX), expected);
+    }
+
+    #[test]
+    fn truncation_keeps_whole_characters() {
+        let mut buffer = RingBuffer::<2048>::new();
+        let record = std::format!("{}é", "a".repeat(MAX_RECORD_LEN - 1));
+
+        let written = buffer.push_record(format_args!("{record}"));
+        assert_eq!(written, MAX_RECORD_LEN - 1 + TRUNCATION_MARKER.len() + 1);
+
+        let expected = std::format!("{}{TRUNCATION_MARKER}\n", "a".repeat(MAX_RECORD_LEN - 1));
+        assert_eq!(dumped(&buffer, usize::MAX), expected);
+    }
+
+    #[test]
+    fn dump_limit_starts_at_a_record_boundary() {
+        let mut buffer = RingBuffer::<64>::new();
+        buffer.push_record(format_args!("first"));
+        buffer.push_record(format_args!("second"));
+        buffer.push_record(format_args!("third"));
+
+        assert_eq!(dumped(&buffer, usize::MAX), "first\nsecond\nthird\n");
+        // A limit that falls exactly between records keeps every record after it.
+        assert_eq!(dumped(&buffer, "second\nthird\n".len()), "second\nthird\n");
+        // A limit that falls within a record drops the part of it that fits.
+        assert_eq!(dumped(&buffer, "nd\nthird\n".len()), "third\n");
+        assert_eq!(dumped(&buffer, "ird\n".len()), "");
+        assert_eq!(dumped(&buffer, 0), "");
+    }
+
+    #[test]
+    fn dump_keeps_characters_split_across_chunks() {
+        let mut buffer = RingBuffer::<512>::new();
+        // The euro sign straddles the end of the first 128-byte chunk.
+        let record = std::format!("{}€{}", "a".repeat(127), "b".repeat(200));
+        buffer.push_record(format_args!("{record}"));
+
+        assert_eq!(dumped(&buffer, usize::MAX), std::format!("{record}\n"));
+    }
+
+    #[test]
+    fn write_utf8_holds_back_incomplete_characters() {
+        let mut output = String::new();
+        let mut chunk = [0u8; 8];
+        chunk[..3].copy_from_slice(&[b'a', 0xE2, 0x82]);
+
+        assert_eq!(write_utf8(&mut output, &mut chunk, 3), Ok(2));
+        assert_eq!(output, "a");
+        assert_eq!(chunk[..2], [0xE2, 0x82]);
+
+        chunk[2] = 0xAC;
+        assert_eq!(write_utf8(&mut output, &mut chunk, 3), Ok(0));
+        assert_eq!(output, "a€");
+    }
+
+    #[test]
+    fn write_utf8_replaces_invalid_sequences() {
+        let mut output = String::new();
+        let mut chunk = [b'a', 0xFF, b'b', 0xC3, b'c', b'd'];
+
+        assert_eq!(write_utf8(&mut output, &mut chunk, 6), Ok(0));
+        assert_eq!(output, "a\u{FFFD}b\u{FFFD}cd");
+    }
+
+    #[test]
+    fn dump_replaces_a_trailing_incomplete_character() {
+        let mut buffer = RingBuffer::<16>::new();
+        buffer.push_bytes(&[b'a', b'\n', b'b', 0xE2, 0x82]);
+
+        let output = dumped(&buffer, usize::MAX);
+        assert_eq!(
+            output.chars().collect::<Vec<_>>(),
+            ['a', '\n', 'b', '\u{FFFD}']
+        );
+    }
+}
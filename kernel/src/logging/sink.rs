@@ -0,0 +1,455 @@
+//! Destinations of log records.
+//!
+//! Every [`Sink`] is registered in a [`SinkRegistry`] together with its own [`LevelFilter`], which
+//! further restricts the records that pass the global and per-module levels. Sink levels can be
+//! set on the kernel command line with `log.<sink>=<level>`, such as `log.serial=info`.
+
+use core::{
+    fmt,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use log::LevelFilter;
+
+use crate::spinlock::Acquisition;
+
+use super::stats::{LogCounters, LogStats};
+
+/// The maximum number of sinks a [`SinkRegistry`] can hold.
+pub const MAX_SINKS: usize = 8;
+
+/// A destination for log records.
+pub trait Sink: Sync {
+    /// Returns the name used to refer to this [`Sink`], such as on the kernel command line.
+    fn name(&self) -> &'static str;
+
+    /// Writes `record` to this [`Sink`], returning the number of bytes written.
+    fn write_record(&self, record: &log::Record) -> usize;
+
+    /// Flushes any output buffered by this [`Sink`].
+    fn flush(&self) {}
+
+    /// Returns the number of bytes this [`Sink`] dropped because its device was not ready.
+    fn dropped_bytes(&self) -> u64 {
+        0
+    }
+
+    /// Returns a warning about this [`Sink`] that should be logged through the other sinks, such
+    /// as that it has started dropping output.
+    ///
+    /// Each warning is returned only once.
+    fn take_warning(&self) -> Option<&'static str> {
+        None
+    }
+
+    /// Writes `text` to this [`Sink`] from the panic handler, forcibly unlocking any lock that
+    /// cannot be acquired after `max_attempts` attempts.
+    ///
+    /// # Safety
+    /// If a lock is bypassed, the same requirements as
+    /// [`Spinlock::force_unlock()`][crate::spinlock::Spinlock::force_unlock] apply.
+    unsafe fn panic_write(&self, text: &str, max_attempts: usize) -> Acquisition;
+}
+
+/// Whether [`FormattedRecord`] includes the target of the record.
+static SHOW_TARGET: AtomicBool = AtomicBool::new(true);
+
+/// Sets whether formatted records include their target.
+///
+/// Omitting the target keeps output stable when modules are moved or renamed.
+pub fn set_show_target(show: bool) {
+    SHOW_TARGET.store(show, Ordering::Relaxed);
+}
+
+/// The textual form of a [`log::Record`] written by every [`Sink`], without a trailing newline.
+///
+/// This is `[LEVEL target] message`, with the target shortened by
+/// [`short_target()`][super::short_target], or `[LEVEL] message` if targets are hidden.
+pub struct FormattedRecord<'record, 'args>(pub &'record log::Record<'args>);
+
+impl fmt::Display for FormattedRecord<'_, '_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if SHOW_TARGET.load(Ordering::Relaxed) {
+            write!(
+                f,
+                "[{:?} {}] {}",
+                self.0.level(),
+                super::short_target(self.0.target()),
+                self.0.args()
+            )
+        } else {
+            write!(f, "[{:?}] {}", self.0.level(), self.0.args())
+        }
+    }
+}
+
+/// Writes `record` to `writer` as a [`FormattedRecord`] followed by a newline, returning the number
+/// of bytes written.
+pub fn write_line(writer: &mut impl fmt::Write, record: &log::Record) -> usize {
+    let mut writer = CountingWriter { writer, written: 0 };
+    let _ = fmt::Write::write_fmt(&mut writer, format_args!("{}\n", FormattedRecord(record)));
+    writer.written
+}
+
+/// A [`fmt::Write`] implementation that counts the bytes successfully written to `writer`.
+struct CountingWriter<'writer, W: fmt::Write> {
+    /// The writer being written to.
+    writer: &'writer mut W,
+    /// The number of bytes written so far.
+    written: usize,
+}
+
+impl<W: fmt::Write> fmt::Write for CountingWriter<'_, W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.writer.write_str(s)?;
+        self.written += s.len();
+        Ok(())
+    }
+}
+
+/// A registered [`Sink`] and its level.
+#[derive(Clone, Copy)]
+struct Entry {
+    /// The registered sink.
+    sink: &'static dyn Sink,
+    /// The most verbose level written to `sink`.
+    level: LevelFilter,
+}
+
+/// A fixed-capacity table of [`Sink`]s, each with its own [`LevelFilter`].
+pub struct SinkRegistry {
+    /// The registered sinks, in registration order.
+    entries: [Option<Entry>; MAX_SINKS],
+}
+
+impl SinkRegistry {
+    /// Creates a new, empty [`SinkRegistry`].
+    pub const fn new() -> Self {
+        Self {
+            entries: [None; MAX_SINKS],
+        }
+    }
+
+    /// Registers `sink`, initially receiving every record that reaches the registry.
+    ///
+    /// # Errors
+    /// - [`SinkError::DuplicateName`]: a sink with the same name is already registered.
+    /// - [`SinkError::RegistryFull`]: [`MAX_SINKS`] sinks are already registered.
+    pub fn register(&mut self, sink: &'static dyn Sink) -> Result<(), SinkError> {
+        if self.entry(sink.name()).is_some() {
+            return Err(SinkError::DuplicateName);
+        }
+
+        let slot = self
+            .entries
+            .iter_mut()
+            .find(|entry| entry.is_none())
+            .ok_or(SinkError::RegistryFull)?;
+        *slot = Some(Entry {
+            sink,
+            level: LevelFilter::Trace,
+        });
+
+        Ok(())
+    }
+
+    /// Sets the most verbose level written to the sink called `name`.
+    ///
+    /// # Errors
+    /// Returns [`SinkError::UnknownSink`] if no sink called `name` is registered.
+    pub fn set_level(&mut self, name: &str, level: LevelFilter) -> Result<(), SinkError> {
+        self.entries
+            .iter_mut()
+            .flatten()
+            .find(|entry| entry.sink.name() == name)
+            .ok_or(SinkError::UnknownSink)?
+            .level = level;
+
+        Ok(())
+    }
+
+    /// Returns the level of the sink called `name`, or [`None`] if no such sink is registered.
+    pub fn level(&self, name: &str) -> Option<LevelFilter> {
+        self.entry(name).map(|entry| entry.level)
+    }
+
+    /// Returns `true` if any sink accepts records at `level`.
+    pub fn enabled(&self, level: log::Level) -> bool {
+        self.entries
+            .iter()
+            .flatten()
+            .any(|entry| level <= entry.level)
+    }
+
+    /// Returns the most verbose level of any sink, or [`LevelFilter::Off`] if no sink is
+    /// registered.
+    pub fn max_level(&self) -> LevelFilter {
+        self.entries
+            .iter()
+            .flatten()
+            .map(|entry| entry.level)
+            .fold(LevelFilter::Off, Ord::max)
+    }
+
+    /// Writes `record` to every sink whose level accepts it, counting the bytes written to each
+    /// in `counters`.
+    ///
+    /// Each sink formats the record itself, so sinks whose level rejects it never format it.
+    pub fn dispatch(&self, record: &log::Record, counters: &LogCounters) {
+        for (slot, entry) in self.entries.iter().enumerate() {
+            let Some(entry) = entry else { continue };
+            if record.level() <= entry.level {
+                counters.sink_bytes(slot, entry.sink.write_record(record));
+            }
+        }
+    }
+
+    /// Fills in the name and dropped bytes of every registered sink in `stats`.
+    pub fn fill_stats(&self, stats: &mut LogStats) {
+        for (sink_stats, entry) in stats.sinks.iter_mut().zip(&self.entries) {
+            if let Some(entry) = entry {
+                sink_stats.name = Some(entry.sink.name());
+                sink_stats.bytes_dropped = entry.sink.dropped_bytes();
+            }
+        }
+    }
+
+    /// Returns the name of a sink with a pending warning and that warning, if any.
+    ///
+    /// See [`Sink::take_warning()`].
+    pub fn take_warning(&self) -> Option<(&'static str, &'static str)> {
+        self.entries
+            .iter()
+            .flatten()
+            .find_map(|entry| Some((entry.sink.name(), entry.sink.take_warning()?)))
+    }
+
+    /// Flushes every sink.
+    pub fn flush(&self) {
+        for entry in self.entries.iter().flatten() {
+            entry.sink.flush();
+        }
+    }
+
+    /// Returns an [`Iterator`] over the registered sinks, in registration order.
+    pub fn sinks(&self) -> impl Iterator<Item = &'static dyn Sink> + '_ {
+        self.entries.iter().flatten().map(|entry| entry.sink)
+    }
+
+    /// Returns the [`Entry`] of the sink called `name`.
+    fn entry(&self, name: &str) -> Option<&Entry> {
+        self.entries
+            .iter()
+            .flatten()
+            .find(|entry| entry.sink.name() == name)
+    }
+}
+
+impl Default for SinkRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Various errors that can occur while managing a [`SinkRegistry`].
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum SinkError {
+    /// [`MAX_SINKS`] sinks are already registered.
+    RegistryFull,
+    /// A sink with the same name is already registered.
+    DuplicateName,
+    /// No sink with the given name is registered.
+    UnknownSink,
+}
+
+impl fmt::Display for SinkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::RegistryFull => write!(f, "more than {MAX_SINKS} log sinks"),
+            Self::DuplicateName => f.write_str("log sink already registered"),
+            Self::UnknownSink => f.write_str("unknown log sink"),
+        }
+    }
+}
+
+/// Returns an [`Iterator`] over the `(sink, level)` pairs set by `log.<sink>=<level>` options in
+/// `cmdline`.
+///
+/// Several options may be joined with commas, as in `log.serial=info,log.debugcon=trace`. Options
+/// whose level is invalid are yielded as [`Err`] containing the option.
+pub fn parse_sink_levels(cmdline: &str) -> impl Iterator<Item = Result<(&str, LevelFilter), &str>> {
+    cmdline
+        .split_ascii_whitespace()
+        .flat_map(|option| option.split(','))
+        .filter_map(|option| {
+            let (key, level) = option.split_once('=')?;
+            let name = key.strip_prefix("log.")?;
+            Some(
+                super::parse_level(level)
+                    .map(|level| (name, level))
+                    .ok_or(option),
+            )
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{boxed::Box, vec::Vec};
+
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    /// A [`Sink`] that counts the records written to it.
+    struct CountingSink {
+        /// The name of the sink.
+        name: &'static str,
+        /// The number of records written to the sink.
+        records: AtomicUsize,
+    }
+
+    impl Sink for CountingSink {
+        fn name(&self) -> &'static str {
+            self.name
+        }
+
+        fn write_record(&self, record: &log::Record) -> usize {
+            self.records.fetch_add(1, Ordering::Relaxed);
+            record.level().as_str().len()
+        }
+
+        unsafe fn panic_write(&self, _: &str, _: usize) -> Acquisition {
+            Acquisition::Acquired
+        }
+    }
+
+    /// Returns a leaked [`CountingSink`] called `name`.
+    fn sink(name: &'static str) -> &'static CountingSink {
+        Box::leak(Box::new(CountingSink {
+            name,
+            records: AtomicUsize::new(0),
+        }))
+    }
+
+    /// Dispatches a record at `level` through `registry`.
+    fn dispatch(registry: &SinkRegistry, counters: &LogCounters, level: log::Level) {
+        registry.dispatch(
+            &log::Record::builder()
+                .level(level)
+                .args(format_args!("message"))
+                .build(),
+            counters,
+        );
+    }
+
+    #[test]
+    fn registry_fills_up() {
+        const NAMES: [&str; MAX_SINKS] = ["a", "b", "c", "d", "e", "f", "g", "h"];
+
+        let mut registry = SinkRegistry::new();
+        for name in NAMES {
+            assert_eq!(registry.register(sink(name)), Ok(()));
+        }
+
+        assert_eq!(
+            registry.register(sink("overflow")),
+            Err(SinkError::RegistryFull)
+        );
+        assert_eq!(registry.level("overflow"), None);
+        assert_eq!(
+            registry.sinks().map(|sink| sink.name()).collect::<Vec<_>>(),
+            NAMES
+        );
+    }
+
+    #[test]
+    fn duplicate_names_are_rejected() {
+        let mut registry = SinkRegistry::new();
+        let first = sink("serial");
+        assert_eq!(registry.register(first), Ok(()));
+        assert_eq!(
+            registry.register(sink("serial")),
+            Err(SinkError::DuplicateName)
+        );
+        assert_eq!(registry.sinks().count(), 1);
+
+        // A duplicate is rejected even when the registry is full.
+        for name in ["a", "b", "c", "d", "e", "f", "g"] {
+            registry.register(sink(name)).unwrap();
+        }
+        assert_eq!(
+            registry.register(sink("serial")),
+            Err(SinkError::DuplicateName)
+        );
+    }
+
+    #[test]
+    fn sink_levels_are_overridden_per_sink() {
+        let mut registry = SinkRegistry::new();
+        assert_eq!(registry.max_level(), LevelFilter::Off);
+        assert!(!registry.enabled(log::Level::Error));
+
+        let serial = sink("serial");
+        let debugcon = sink("debugcon");
+        registry.register(serial).unwrap();
+        registry.register(debugcon).unwrap();
+        assert_eq!(registry.level("serial"), Some(LevelFilter::Trace));
+        assert_eq!(registry.max_level(), LevelFilter::Trace);
+
+        assert_eq!(registry.set_level("serial", LevelFilter::Warn), Ok(()));
+        assert_eq!(registry.set_level("debugcon", LevelFilter::Info), Ok(()));
+        assert_eq!(
+            registry.set_level("framebuffer", LevelFilter::Off),
+            Err(SinkError::UnknownSink)
+        );
+        assert_eq!(registry.level("serial"), Some(LevelFilter::Warn));
+        assert_eq!(registry.level("debugcon"), Some(LevelFilter::Info));
+        assert_eq!(registry.max_level(), LevelFilter::Info);
+        assert!(registry.enabled(log::Level::Info));
+        assert!(!registry.enabled(log::Level::Debug));
+
+        let counters = LogCounters::new();
+        dispatch(&registry, &counters, log::Level::Error);
+        dispatch(&registry, &counters, log::Level::Info);
+        dispatch(&registry, &counters, log::Level::Debug);
+        assert_eq!(serial.records.load(Ordering::Relaxed), 1);
+        assert_eq!(debugcon.records.load(Ordering::Relaxed), 2);
+
+        let stats = counters.snapshot();
+        assert_eq!(stats.sinks[0].bytes_written, "ERROR".len() as u64);
+        assert_eq!(
+            stats.sinks[1].bytes_written,
+            ("ERROR".len() + "INFO".len()) as u64
+        );
+
+        registry.set_level("serial", LevelFilter::Off).unwrap();
+        registry.set_level("debugcon", LevelFilter::Off).unwrap();
+        assert_eq!(registry.max_level(), LevelFilter::Off);
+        assert!(!registry.enabled(log::Level::Error));
+    }
+
+    #[test]
+    fn sink_levels_are_parsed() {
+        let levels =
+            parse_sink_levels("quiet log.serial=info,log.debugcon=trace log=warn log.fb=loud")
+                .collect::<Vec<_>>();
+        assert_eq!(
+            levels,
+            [
+                Ok(("serial", LevelFilter::Info)),
+                Ok(("debugcon", LevelFilter::Trace)),
+                Err("log.fb=loud"),
+            ]
+        );
+    }
+
+    #[test]
+    fn sink_error_display() {
+        assert_eq!(SinkError::RegistryFull.to_string(), "more than 8 log sinks");
+        assert_eq!(
+            SinkError::DuplicateName.to_string(),
+            "log sink already registered"
+        );
+        assert_eq!(SinkError::UnknownSink.to_string(), "unknown log sink");
+    }
+}
@@ -0,0 +1,338 @@
+//! Statistics about the operation of the logging subsystem itself.
+//!
+//! Counters are updated with relaxed atomic operations on the logging path and wrap around on
+//! overflow, so differences between two [`LogStats`] snapshots are computed with wrapping
+//! arithmetic.
+
+use core::{
+    fmt,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use super::sink::MAX_SINKS;
+
+/// The number of distinct [`log::Level`]s.
+pub const LEVEL_COUNT: usize = 5;
+
+/// A monotonically increasing counter that wraps around on overflow.
+#[derive(Debug, Default)]
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    /// Creates a new [`Counter`] starting at zero.
+    pub const fn new() -> Self {
+        Self(AtomicU64::new(0))
+    }
+
+    /// Adds `amount` to this [`Counter`], wrapping around on overflow.
+    pub fn add(&self, amount: u64) {
+        self.0.fetch_add(amount, Ordering::Relaxed);
+    }
+
+    /// Returns the current value of this [`Counter`].
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// The counters maintained by the logging subsystem.
+#[derive(Debug)]
+pub struct LogCounters {
+    /// The number of records written to the sinks, indexed by [`level_index()`].
+    records: [Counter; LEVEL_COUNT],
+    /// The number of bytes written to each sink, indexed by its slot in the
+    /// [`SinkRegistry`][super::sink::SinkRegistry].
+    sink_bytes: [Counter; MAX_SINKS],
+    /// The number of records rejected before being formatted.
+    skipped_records: Counter,
+    /// The number of locks bypassed while reporting a panic.
+    lock_bypasses: Counter,
+}
+
+impl LogCounters {
+    /// Creates a new [`LogCounters`] with every counter at zero.
+    pub const fn new() -> Self {
+        Self {
+            records: [const { Counter::new() }; LEVEL_COUNT],
+            sink_bytes: [const { Counter::new() }; MAX_SINKS],
+            skipped_records: Counter::new(),
+            lock_bypasses: Counter::new(),
+        }
+    }
+
+    /// Counts a record at `level` that was written to the sinks.
+    pub fn record(&self, level: log::Level) {
+        self.records[level_index(level)].add(1);
+    }
+
+    /// Counts `bytes` written to the sink in `slot`.
+    pub fn sink_bytes(&self, slot: usize, bytes: usize) {
+        if let Some(counter) = self.sink_bytes.get(slot) {
+            counter.add(bytes as u64);
+        }
+    }
+
+    /// Counts a record rejected before being formatted.
+    pub fn skipped_record(&self) {
+        self.skipped_records.add(1);
+    }
+
+    /// Counts a lock bypassed while reporting a panic.
+    pub fn lock_bypass(&self) {
+        self.lock_bypasses.add(1);
+    }
+
+    /// Returns the current values of every counter, leaving the sink names and dropped bytes of
+    /// the returned [`LogStats`] empty.
+    pub fn snapshot(&self) -> LogStats {
+        let mut stats = LogStats::new();
+        for (count, counter) in stats.records.iter_mut().zip(&self.records) {
+            *count = counter.get();
+        }
+        for (sink, counter) in stats.sinks.iter_mut().zip(&self.sink_bytes) {
+            sink.bytes_written = counter.get();
+        }
+        stats.skipped_records = self.skipped_records.get();
+        stats.lock_bypasses = self.lock_bypasses.get();
+
+        stats
+    }
+}
+
+impl Default for LogCounters {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Returns the index of `level` in [`LogStats::records`], from [`log::Level::Error`] at 0 to
+/// [`log::Level::Trace`] at 4.
+pub const fn level_index(level: log::Level) -> usize {
+    level as usize - 1
+}
+
+/// The statistics of a single sink.
+#[derive(Clone, Copy, Debug, Default, Hash, PartialEq, Eq)]
+pub struct SinkStats {
+    /// The name of the sink, or [`None`] if the slot is unused.
+    pub name: Option<&'static str>,
+    /// The number of bytes written to the sink.
+    pub bytes_written: u64,
+    /// The number of bytes the sink dropped because its device was not ready.
+    pub bytes_dropped: u64,
+}
+
+/// A snapshot of the statistics of the logging subsystem, as returned by
+/// [`stats()`][super::stats].
+#[derive(Clone, Copy, Debug, Default, Hash, PartialEq, Eq)]
+pub struct LogStats {
+    /// The number of records written to the sinks, indexed by [`level_index()`].
+    pub records: [u64; LEVEL_COUNT],
+    /// The statistics of each sink, indexed by its slot in the
+    /// [`SinkRegistry`][super::sink::SinkRegistry].
+    pub sinks: [SinkStats; MAX_SINKS],
+    /// The number of records rejected before being formatted.
+    pub skipped_records: u64,
+    /// The number of locks bypassed while reporting a panic.
+    pub lock_bypasses: u64,
+}
+
+impl LogStats {
+    /// Creates a new [`LogStats`] with every statistic at zero.
+    pub const fn new() -> Self {
+        Self {
+            records: [0; LEVEL_COUNT],
+            sinks: [SinkStats {
+                name: None,
+                bytes_written: 0,
+                bytes_dropped: 0,
+            }; MAX_SINKS],
+            skipped_records: 0,
+            lock_bypasses: 0,
+        }
+    }
+
+    /// Returns the number of records written to the sinks at `level`.
+    pub const fn records_at(&self, level: log::Level) -> u64 {
+        self.records[level_index(level)]
+    }
+
+    /// Returns the statistics accumulated between `earlier` and this [`LogStats`], accounting for
+    /// counters that wrapped around in between.
+    ///
+    /// Sink names are taken from this [`LogStats`].
+    pub fn since(&self, earlier: &Self) -> Self {
+        let mut delta = *self;
+        for (count, earlier) in delta.records.iter_mut().zip(&earlier.records) {
+            *count = count.wrapping_sub(*earlier);
+        }
+        for (sink, earlier) in delta.sinks.iter_mut().zip(&earlier.sinks) {
+            sink.bytes_written = sink.bytes_written.wrapping_sub(earlier.bytes_written);
+            sink.bytes_dropped = sink.bytes_dropped.wrapping_sub(earlier.bytes_dropped);
+        }
+        delta.skipped_records = delta.skipped_records.wrapping_sub(earlier.skipped_records);
+        delta.lock_bypasses = delta.lock_bypasses.wrapping_sub(earlier.lock_bypasses);
+
+        delta
+    }
+
+    /// Adds the statistics in `other` to this [`LogStats`], wrapping around on overflow.
+    pub fn accumulate(&mut self, other: &Self) {
+        for (count, other) in self.records.iter_mut().zip(&other.records) {
+            *count = count.wrapping_add(*other);
+        }
+        for (sink, other) in self.sinks.iter_mut().zip(&other.sinks) {
+            sink.name = sink.name.or(other.name);
+            sink.bytes_written = sink.bytes_written.wrapping_add(other.bytes_written);
+            sink.bytes_dropped = sink.bytes_dropped.wrapping_add(other.bytes_dropped);
+        }
+        self.skipped_records = self.skipped_records.wrapping_add(other.skipped_records);
+        self.lock_bypasses = self.lock_bypasses.wrapping_add(other.lock_bypasses);
+    }
+}
+
+impl fmt::Display for LogStats {
+    /// Formats the statistics one per line, without a trailing newline.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "records: error={} warn={} info={} debug={} trace={}",
+            self.records[0], self.records[1], self.records[2], self.records[3], self.records[4]
+        )?;
+        write!(f, "\nskipped records: {}", self.skipped_records)?;
+        write!(f, "\npanic lock bypasses: {}", self.lock_bypasses)?;
+        for sink in &self.sinks {
+            if let Some(name) = sink.name {
+                write!(
+                    f,
+                    "\nsink {name}: {} bytes written, {} bytes dropped",
+                    sink.bytes_written, sink.bytes_dropped
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counter_wraps_around() {
+        let counter = Counter::new();
+        counter.add(u64::MAX - 1);
+        assert_eq!(counter.get(), u64::MAX - 1);
+
+        counter.add(3);
+        assert_eq!(counter.get(), 1);
+    }
+
+    #[test]
+    fn snapshot_reports_every_counter() {
+        let counters = LogCounters::new();
+        assert_eq!(counters.snapshot(), LogStats::new());
+
+        counters.record(log::Level::Error);
+        counters.record(log::Level::Trace);
+        counters.record(log::Level::Trace);
+        counters.sink_bytes(0, 10);
+        counters.sink_bytes(MAX_SINKS - 1, 5);
+        counters.sink_bytes(MAX_SINKS, 100);
+        counters.skipped_record();
+        counters.lock_bypass();
+        counters.lock_bypass();
+
+        let stats = counters.snapshot();
+        assert_eq!(stats.records, [1, 0, 0, 0, 2]);
+        assert_eq!(stats.records_at(log::Level::Trace), 2);
+        assert_eq!(stats.sinks[0].bytes_written, 10);
+        assert_eq!(stats.sinks[MAX_SINKS - 1].bytes_written, 5);
+        assert!(stats.sinks.iter().all(|sink| sink.name.is_none()));
+        assert!(stats.sinks.iter().all(|sink| sink.bytes_dropped == 0));
+        assert_eq!(stats.skipped_records, 1);
+        assert_eq!(stats.lock_bypasses, 2);
+    }
+
+    #[test]
+    fn level_indexes_follow_severity() {
+        assert_eq!(level_index(log::Level::Error), 0);
+        assert_eq!(level_index(log::Level::Warn), 1);
+        assert_eq!(level_index(log::Level::Info), 2);
+        assert_eq!(level_index(log::Level::Debug), 3);
+        assert_eq!(level_index(log::Level::Trace), LEVEL_COUNT - 1);
+    }
+
+    #[test]
+    fn since_accounts_for_wrapped_counters() {
+        let counters = LogCounters::new();
+        counters.skipped_records.add(u64::MAX - 1);
+        counters.sink_bytes(0, usize::MAX - 1);
+        let earlier = counters.snapshot();
+
+        counters.skipped_record();
+        counters.skipped_record();
+        counters.skipped_record();
+        counters.sink_bytes(0, 4);
+        counters.record(log::Level::Info);
+        let later = counters.snapshot();
+
+        assert!(later.skipped_records < earlier.skipped_records);
+        let delta = later.since(&earlier);
+        assert_eq!(delta.skipped_records, 3);
+        assert_eq!(delta.sinks[0].bytes_written, 4);
+        assert_eq!(delta.records_at(log::Level::Info), 1);
+        assert_eq!(delta.lock_bypasses, 0);
+    }
+
+    #[test]
+    fn accumulate_adds_and_keeps_names() {
+        let mut total = LogStats::new();
+        total.sinks[0].name = Some("serial");
+        total.records[2] = 4;
+        total.lock_bypasses = u64::MAX;
+
+        let mut other = LogStats::new();
+        other.sinks[0].name = Some("ignored");
+        other.sinks[0].bytes_written = 7;
+        other.sinks[1].name = Some("debugcon");
+        other.sinks[1].bytes_dropped = 3;
+        other.records[2] = 6;
+        other.skipped_records = 2;
+        other.lock_bypasses = 2;
+
+        total.accumulate(&other);
+        assert_eq!(total.sinks[0].name, Some("serial"));
+        assert_eq!(total.sinks[0].bytes_written, 7);
+        assert_eq!(total.sinks[1].name, Some("debugcon"));
+        assert_eq!(total.sinks[1].bytes_dropped, 3);
+        assert_eq!(total.records[2], 10);
+        assert_eq!(total.skipped_records, 2);
+        assert_eq!(total.lock_bypasses, 1);
+
+        // Accumulating the deltas between snapshots reproduces the latest snapshot.
+        let mut accumulated = LogStats::new();
+        accumulated.accumulate(&other.since(&LogStats::new()));
+        assert_eq!(accumulated, other);
+    }
+
+    #[test]
+    fn display_lists_named_sinks() {
+        let mut stats = LogStats::new();
+        stats.records = [1, 2, 3, 4, 5];
+        stats.skipped_records = 6;
+        stats.lock_bypasses = 7;
+        stats.sinks[1].name = Some("serial");
+        stats.sinks[1].bytes_written = 8;
+        stats.sinks[1].bytes_dropped = 9;
+
+        assert_eq!(
+            std::format!("{stats}"),
+            "records: error=1 warn=2 info=3 debug=4 trace=5\n\
+             skipped records: 6\n\
+             panic lock bypasses: 7\n\
+             sink serial: 8 bytes written, 9 bytes dropped"
+        );
+    }
+}
@@ -1,32 +1,123 @@
 //! Capability based microkernel.
+//!
+//! The kernel's pure modules can also be compiled for the host with `cargo test`, in which case
+//! the standard library is linked and the boot entry points are not exported.
 
-#![no_std]
-#![no_main]
+#![cfg_attr(not(test), no_std)]
+#![cfg_attr(not(test), no_main)]
 #![feature(abi_x86_interrupt)]
 
+extern crate alloc;
+
+pub mod acpi;
 pub mod arch;
+pub mod boot_progress;
+pub mod build_info;
 pub mod cells;
+pub mod cmdline;
+#[cfg(feature = "framebuffer-logging")]
+pub mod console;
+pub mod heap;
+pub mod keyboard;
+#[cfg(feature = "debug-shell")]
+pub mod kshell;
+#[cfg(feature = "ktest")]
+pub mod ktest;
+pub mod kthread;
 #[cfg(feature = "logging")]
 pub mod logging;
+pub mod options;
+pub mod panic_guard;
+pub mod scheduler;
 pub mod spinlock;
+pub mod symbols;
+pub mod sync;
 
 /// The architecture independent kernel entry point for the primary CPU.
 ///
 /// This is called by the architecture dependent entry code.
 pub fn kmain() -> ! {
-    loop {}
+    boot_progress::reach(boot_progress::BootPhase::KmainReached);
+
+    #[cfg(feature = "ktest")]
+    ktest::run_all();
+
+    #[cfg(all(feature = "debug-shell", not(feature = "ktest")))]
+    kshell::run();
+
+    // Reaching this point is all that `xtask test` checks for when no tests are compiled in.
+    #[cfg(all(
+        feature = "qemu-exit",
+        not(any(feature = "debug-shell", feature = "ktest"))
+    ))]
+    arch::qemu_exit(arch::ExitCode::Success);
+
+    #[cfg(not(any(feature = "debug-shell", feature = "ktest", feature = "qemu-exit")))]
+    loop {
+        arch::wait_for_interrupt();
+    }
 }
 
+/// The number of bytes of log history replayed by the panic handler.
+#[cfg(feature = "logging")]
+const PANIC_REPLAY_BYTES: usize = 4096;
+
 /// Handler of all panics.
+///
+/// A panic raised while this is running only writes [`panic_guard::NESTED_PANIC_MARKER`] before
+/// halting, and a third one halts immediately, so that the first panic's report survives a bug in
+/// the reporting path.
 #[cfg_attr(not(test), panic_handler)]
 fn panic_handler(info: &core::panic::PanicInfo) -> ! {
+    arch::cpu::disable_interrupts();
+
+    match panic_guard::enter() {
+        panic_guard::PanicLevel::First => {}
+        panic_guard::PanicLevel::Nested => {
+            #[cfg(feature = "logging")]
+            arch::logging::emergency_write(panic_guard::NESTED_PANIC_MARKER);
+
+            #[cfg(feature = "qemu-exit")]
+            arch::qemu::qemu_exit_unflushed(arch::ExitCode::Failed(1));
+
+            #[cfg(not(feature = "qemu-exit"))]
+            arch::cpu::halt_forever()
+        }
+        panic_guard::PanicLevel::Fatal => arch::cpu::halt_forever(),
+    }
+
+    arch::apic::halt_other_processors();
+
     #[cfg(feature = "logging")]
-    log::error!("PANIC OCCURRED: {info}");
+    {
+        let registers = arch::cpu::RegisterSnapshot::capture();
+        let backtrace =
+            arch::backtrace::enabled().then(|| arch::backtrace::Backtrace::capture(&registers));
+
+        logging::panic_block(
+            &info.message(),
+            info.location(),
+            &registers,
+            backtrace
+                .as_ref()
+                .map(|backtrace| backtrace as &dyn core::fmt::Display),
+            PANIC_REPLAY_BYTES,
+        );
+
+        // The panic output paths above release every lock they bypassed, so flushing through the
+        // logger cannot deadlock.
+        log::logger().flush();
+    }
 
     #[cfg(not(feature = "logging"))]
     core::hint::black_box(info);
 
-    loop {
-        core::hint::spin_loop()
-    }
+    #[cfg(feature = "ktest")]
+    ktest::report_panic();
+
+    #[cfg(feature = "qemu-exit")]
+    arch::qemu_exit(arch::ExitCode::Failed(1));
+
+    #[cfg(not(feature = "qemu-exit"))]
+    arch::cpu::halt_forever()
 }
@@ -0,0 +1,588 @@
+//! Registry of the options accepted on the kernel command line.
+//!
+//! Every option is declared in [`OPTIONS`] with its type, default, and description. [`init()`]
+//! parses the command line against the table once the boot information has been captured,
+//! warning about unknown options, values of the wrong type, and options given more than once, in
+//! which case the last occurrence wins. Afterwards, [`get()`] returns the effective value of an
+//! option, and [`settings()`] lists every option along with where its value came from.
+//!
+//! Options consumed before the boot information is captured, such as those of the logging
+//! subsystem, are still read by their subsystems with
+//! [`cmdline::get_str()`][crate::cmdline::get_str], and are declared here so that they are
+//! validated and listed.
+
+use core::fmt;
+
+use crate::{cmdline, sync::Once};
+
+/// The options accepted on the kernel command line.
+pub static OPTIONS: [OptionDecl; OPTION_COUNT] = [
+    OptionDecl {
+        name: "loglevel",
+        kind: OptionKind::Enum(&["error", "warn", "info", "debug", "trace"]),
+        default: OptionValue::Str("info"),
+        description: "the maximum level of log records",
+    },
+    OptionDecl {
+        name: "log",
+        kind: OptionKind::Str,
+        default: OptionValue::Str(""),
+        description: "per-module log levels, as `prefix=level` pairs joined by commas",
+    },
+    OptionDecl {
+        name: "log.",
+        kind: OptionKind::Str,
+        default: OptionValue::Str(""),
+        description: "the maximum level of log records written to the named sink",
+    },
+    OptionDecl {
+        name: "logtarget",
+        kind: OptionKind::Enum(&["on", "off"]),
+        default: OptionValue::Str("on"),
+        description: "whether log records show the module that emitted them",
+    },
+    OptionDecl {
+        name: "serial",
+        kind: OptionKind::Str,
+        default: OptionValue::Str("com1"),
+        description: "the serial port used for logging: `auto`, `com1`-`com4`, or an I/O port",
+    },
+    OptionDecl {
+        name: "backtrace",
+        kind: OptionKind::Bool,
+        default: OptionValue::Bool(true),
+        description: "whether the panic handler prints a stack trace",
+    },
+    OptionDecl {
+        name: "boot_watchdog_ms",
+        kind: OptionKind::U64,
+        default: OptionValue::U64(5000),
+        description: "how long each boot phase may take once the timer runs, or 0 to disable",
+    },
+];
+/// The number of options in [`OPTIONS`].
+pub const OPTION_COUNT: usize = 7;
+
+/// The effective settings of [`OPTIONS`], once the command line has been parsed.
+static SETTINGS: Once<[Setting; OPTION_COUNT]> = Once::new();
+
+/// Parses `cmdline` against [`OPTIONS`], logging a warning for every problem found.
+///
+/// Only the first call has any effect.
+pub fn init(cmdline: &'static str) {
+    SETTINGS.call_once(|| {
+        parse(cmdline, &OPTIONS, |diagnostic| {
+            #[cfg(feature = "logging")]
+            log::warn!("{diagnostic}");
+            #[cfg(not(feature = "logging"))]
+            let _ = diagnostic;
+        })
+    });
+}
+
+/// Returns the effective value of the option `name`, or [`None`] if no such option is declared or
+/// its value is not a `T`.
+///
+/// Before [`init()`] is called, the default value is returned.
+pub fn get<T: FromOptionValue>(name: &str) -> Option<T> {
+    let index = OPTIONS.iter().position(|decl| decl.name == name)?;
+    let value = SETTINGS
+        .get()
+        .map_or(OPTIONS[index].default, |settings| settings[index].value);
+
+    T::from_option_value(value)
+}
+
+/// Returns an [`Iterator`] over every declared option and its effective [`Setting`].
+pub fn settings() -> impl Iterator<Item = (&'static OptionDecl, Setting)> {
+    OPTIONS.iter().enumerate().map(|(index, decl)| {
+        let setting = SETTINGS
+            .get()
+            .map_or(Setting::default_of(decl), |settings| settings[index]);
+        (decl, setting)
+    })
+}
+
+/// Parses `cmdline` against `decls`, returning the effective [`Setting`] of each declaration and
+/// passing every problem found to `report`.
+///
+/// A declaration whose name ends in `.` matches every option starting with that name. Such
+/// families keep the value of their last member.
+pub fn parse<const N: usize>(
+    cmdline: &'static str,
+    decls: &[OptionDecl; N],
+    mut report: impl FnMut(Diagnostic),
+) -> [Setting; N] {
+    let mut settings = decls.each_ref().map(Setting::default_of);
+    let mut seen = [false; N];
+
+    for (key, value) in cmdline::options(cmdline) {
+        let Some(index) = decls.iter().position(|decl| decl.matches(key)) else {
+            report(Diagnostic::UnknownOption(key));
+            continue;
+        };
+        let decl = &decls[index];
+
+        let Some(value) = decl.kind.parse(value) else {
+            report(Diagnostic::InvalidValue {
+                name: decl.name,
+                value,
+                kind: decl.kind,
+            });
+            continue;
+        };
+
+        if seen[index] && !decl.is_family() {
+            report(Diagnostic::Duplicate(decl.name));
+        }
+        seen[index] = true;
+        settings[index] = Setting {
+            value,
+            source: Source::Cmdline,
+        };
+    }
+
+    settings
+}
+
+/// The declaration of a command line option.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub struct OptionDecl {
+    /// The name of the option, or the common prefix of a family of options if it ends in `.`.
+    pub name: &'static str,
+    /// The type of the value of the option.
+    pub kind: OptionKind,
+    /// The value of the option if it is not given on the command line.
+    pub default: OptionValue,
+    /// A short description of the option.
+    pub description: &'static str,
+}
+
+impl OptionDecl {
+    /// Returns `true` if this [`OptionDecl`] declares a family of options sharing a prefix.
+    pub fn is_family(&self) -> bool {
+        self.name.ends_with('.')
+    }
+
+    /// Returns `true` if the option `key` is declared by this [`OptionDecl`].
+    pub fn matches(&self, key: &str) -> bool {
+        if self.is_family() {
+            key.len() > self.name.len() && key.starts_with(self.name)
+        } else {
+            key == self.name
+        }
+    }
+}
+
+/// The type of the value of a command line option.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum OptionKind {
+    /// A boolean, written as `1`, `true`, `on`, or `yes`, or as `0`, `false`, `off`, or `no`. A
+    /// bare option is `true`.
+    Bool,
+    /// An unsigned integer, in decimal or in hexadecimal prefixed with `0x`.
+    U64,
+    /// Any string.
+    Str,
+    /// One of the listed strings.
+    Enum(&'static [&'static str]),
+}
+
+impl OptionKind {
+    /// Parses `value` as a value of this [`OptionKind`], returning [`None`] if it is not one.
+    pub fn parse(self, value: &'static str) -> Option<OptionValue> {
+        let value = match self {
+            Self::Bool => OptionValue::Bool(match value {
+                "" | "1" | "true" | "on" | "yes" => true,
+                "0" | "false" | "off" | "no" => false,
+                _ => return None,
+            }),
+            Self::U64 => OptionValue::U64(match value.strip_prefix("0x") {
+                Some(digits) => u64::from_str_radix(digits, 16).ok()?,
+                None => value.parse().ok()?,
+            }),
+            Self::Str => OptionValue::Str(value),
+            Self::Enum(variants) => {
+                OptionValue::Str(variants.iter().find(|&&variant| variant == value)?)
+            }
+        };
+
+        Some(value)
+    }
+}
+
+impl fmt::Display for OptionKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Bool => f.write_str("a boolean"),
+            Self::U64 => f.write_str("an unsigned integer"),
+            Self::Str => f.write_str("a string"),
+            Self::Enum(variants) => {
+                f.write_str("one of")?;
+                for (index, variant) in variants.iter().enumerate() {
+                    let separator = if index == 0 { " " } else { ", " };
+                    write!(f, "{separator}`{variant}`")?;
+                }
+
+                Ok(())
+            }
+        }
+    }
+}
+
+/// The value of a command line option.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum OptionValue {
+    /// The value of an [`OptionKind::Bool`] option.
+    Bool(bool),
+    /// The value of an [`OptionKind::U64`] option.
+    U64(u64),
+    /// The value of an [`OptionKind::Str`] or [`OptionKind::Enum`] option.
+    Str(&'static str),
+}
+
+impl fmt::Display for OptionValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Bool(value) => write!(f, "{value}"),
+            Self::U64(value) => write!(f, "{value}"),
+            Self::Str(value) => write!(f, "{value:?}"),
+        }
+    }
+}
+
+/// A type that the value of a command line option can be converted to by [`get()`].
+pub trait FromOptionValue: Sized {
+    /// Converts `value` into [`Self`], returning [`None`] if it is of another type.
+    fn from_option_value(value: OptionValue) -> Option<Self>;
+}
+
+impl FromOptionValue for bool {
+    fn from_option_value(value: OptionValue) -> Option<Self> {
+        match value {
+            OptionValue::Bool(value) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+impl FromOptionValue for u64 {
+    fn from_option_value(value: OptionValue) -> Option<Self> {
+        match value {
+            OptionValue::U64(value) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+impl FromOptionValue for &'static str {
+    fn from_option_value(value: OptionValue) -> Option<Self> {
+        match value {
+            OptionValue::Str(value) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+/// The effective value of a command line option and where it came from.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub struct Setting {
+    /// The effective value of the option.
+    pub value: OptionValue,
+    /// Where [`Setting::value`] came from.
+    pub source: Source,
+}
+
+impl Setting {
+    /// Returns the [`Setting`] of `decl` when it is not given on the command line.
+    pub fn default_of(decl: &OptionDecl) -> Self {
+        Self {
+            value: decl.default,
+            source: Source::Default,
+        }
+    }
+}
+
+/// Where the effective value of a command line option came from.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum Source {
+    /// The option was not given, so its default value is used.
+    Default,
+    /// The option was given on the command line.
+    Cmdline,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Default => f.pad("default"),
+            Self::Cmdline => f.pad("cmdline"),
+        }
+    }
+}
+
+/// A problem found while parsing the command line by [`parse()`].
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum Diagnostic {
+    /// The option is not declared.
+    UnknownOption(&'static str),
+    /// The value given for an option is not of its type, so the option was ignored.
+    InvalidValue {
+        /// The name of the option.
+        name: &'static str,
+        /// The value given for the option.
+        value: &'static str,
+        /// The type of the option.
+        kind: OptionKind,
+    },
+    /// The option was given more than once, and the last occurrence was used.
+    Duplicate(&'static str),
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownOption(key) => write!(f, "Ignoring unknown command line option `{key}`"),
+            Self::InvalidValue { name, value, kind } => write!(
+                f,
+                "Ignoring invalid value `{value}` for command line option `{name}`; expected {kind}"
+            ),
+            Self::Duplicate(name) => write!(
+                f,
+                "Command line option `{name}` given more than once; using the last occurrence"
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{string::ToString, vec::Vec};
+
+    use super::*;
+
+    /// A table of options covering every [`OptionKind`] and a family.
+    const DECLS: [OptionDecl; 5] = [
+        OptionDecl {
+            name: "flag",
+            kind: OptionKind::Bool,
+            default: OptionValue::Bool(false),
+            description: "a boolean",
+        },
+        OptionDecl {
+            name: "count",
+            kind: OptionKind::U64,
+            default: OptionValue::U64(7),
+            description: "an integer",
+        },
+        OptionDecl {
+            name: "name",
+            kind: OptionKind::Str,
+            default: OptionValue::Str("none"),
+            description: "a string",
+        },
+        OptionDecl {
+            name: "mode",
+            kind: OptionKind::Enum(&["fast", "slow"]),
+            default: OptionValue::Str("slow"),
+            description: "an enumeration",
+        },
+        OptionDecl {
+            name: "sink.",
+            kind: OptionKind::Str,
+            default: OptionValue::Str(""),
+            description: "a family",
+        },
+    ];
+
+    /// Parses `cmdline` against [`DECLS`], returning the settings and every diagnostic reported.
+    fn parse_all(cmdline: &'static str) -> ([Setting; 5], Vec<Diagnostic>) {
+        let mut diagnostics = Vec::new();
+        let settings = parse(cmdline, &DECLS, |diagnostic| diagnostics.push(diagnostic));
+        (settings, diagnostics)
+    }
+
+    /// Returns the [`Setting`] of an option given on the command line.
+    const fn given(value: OptionValue) -> Setting {
+        Setting {
+            value,
+            source: Source::Cmdline,
+        }
+    }
+
+    #[test]
+    fn empty_command_line_keeps_defaults() {
+        let (settings, diagnostics) = parse_all("");
+        assert!(diagnostics.is_empty());
+        for (setting, decl) in settings.iter().zip(&DECLS) {
+            assert_eq!(*setting, Setting::default_of(decl));
+        }
+    }
+
+    #[test]
+    fn every_kind_is_parsed() {
+        let (settings, diagnostics) = parse_all("flag count=0x10 name=kernel mode=fast");
+        assert!(diagnostics.is_empty());
+        assert_eq!(settings[0], given(OptionValue::Bool(true)));
+        assert_eq!(settings[1], given(OptionValue::U64(16)));
+        assert_eq!(settings[2], given(OptionValue::Str("kernel")));
+        assert_eq!(settings[3], given(OptionValue::Str("fast")));
+        assert_eq!(settings[4], Setting::default_of(&DECLS[4]));
+    }
+
+    #[test]
+    fn booleans_accept_every_spelling() {
+        for value in ["", "1", "true", "on", "yes"] {
+            assert_eq!(OptionKind::Bool.parse(value), Some(OptionValue::Bool(true)));
+        }
+        for value in ["0", "false", "off", "no"] {
+            assert_eq!(
+                OptionKind::Bool.parse(value),
+                Some(OptionValue::Bool(false))
+            );
+        }
+        for value in ["2", "True", "enabled", " "] {
+            assert_eq!(OptionKind::Bool.parse(value), None);
+        }
+    }
+
+    #[test]
+    fn integers_accept_decimal_and_hexadecimal() {
+        assert_eq!(OptionKind::U64.parse("0"), Some(OptionValue::U64(0)));
+        assert_eq!(OptionKind::U64.parse("5000"), Some(OptionValue::U64(5000)));
+        assert_eq!(
+            OptionKind::U64.parse("0x3F8"),
+            Some(OptionValue::U64(0x3F8))
+        );
+        assert_eq!(
+            OptionKind::U64.parse("18446744073709551615"),
+            Some(OptionValue::U64(u64::MAX))
+        );
+        for value in ["", "0x", "-1", "1.5", "0X10", "0xG", "18446744073709551616"] {
+            assert_eq!(OptionKind::U64.parse(value), None, "{value:?}");
+        }
+    }
+
+    #[test]
+    fn enumerations_only_accept_their_variants() {
+        let kind = OptionKind::Enum(&["on", "off"]);
+        assert_eq!(kind.parse("off"), Some(OptionValue::Str("off")));
+        assert_eq!(kind.parse("OFF"), None);
+        assert_eq!(kind.parse(""), None);
+    }
+
+    #[test]
+    fn unknown_and_invalid_options_are_reported_and_ignored() {
+        let (settings, diagnostics) = parse_all("verbose count=lots mode=medium sink.=x");
+        assert_eq!(
+            diagnostics,
+            [
+                Diagnostic::UnknownOption("verbose"),
+                Diagnostic::InvalidValue {
+                    name: "count",
+                    value: "lots",
+                    kind: OptionKind::U64,
+                },
+                Diagnostic::InvalidValue {
+                    name: "mode",
+                    value: "medium",
+                    kind: DECLS[3].kind,
+                },
+                Diagnostic::UnknownOption("sink."),
+            ]
+        );
+        for (setting, decl) in settings.iter().zip(&DECLS) {
+            assert_eq!(*setting, Setting::default_of(decl));
+        }
+    }
+
+    #[test]
+    fn duplicates_are_reported_and_the_last_wins() {
+        let (settings, diagnostics) = parse_all("count=1 count=2 count=bad count=3");
+        assert_eq!(
+            diagnostics,
+            [
+                Diagnostic::Duplicate("count"),
+                Diagnostic::InvalidValue {
+                    name: "count",
+                    value: "bad",
+                    kind: OptionKind::U64,
+                },
+                Diagnostic::Duplicate("count"),
+            ]
+        );
+        assert_eq!(settings[1], given(OptionValue::U64(3)));
+    }
+
+    #[test]
+    fn families_match_their_members_without_duplicates() {
+        let family = &DECLS[4];
+        assert!(family.is_family());
+        assert!(family.matches("sink.serial"));
+        assert!(!family.matches("sink."));
+        assert!(!family.matches("sink"));
+        assert!(!DECLS[0].is_family());
+        assert!(DECLS[0].matches("flag"));
+        assert!(!DECLS[0].matches("flags"));
+
+        let (settings, diagnostics) = parse_all("sink.serial=debug sink.debugcon=warn");
+        assert!(diagnostics.is_empty());
+        assert_eq!(settings[4], given(OptionValue::Str("warn")));
+    }
+
+    #[test]
+    fn declared_options_are_consistent() {
+        let mut names = OPTIONS.iter().map(|decl| decl.name).collect::<Vec<_>>();
+        names.sort_unstable();
+        names.dedup();
+        assert_eq!(names.len(), OPTION_COUNT);
+
+        for decl in &OPTIONS {
+            let default = match decl.default {
+                OptionValue::Bool(value) => value.to_string(),
+                OptionValue::U64(value) => value.to_string(),
+                OptionValue::Str(value) => value.to_string(),
+            };
+            let default = std::boxed::Box::leak(default.into_boxed_str());
+            assert_eq!(
+                decl.kind.parse(default),
+                Some(decl.default),
+                "{}",
+                decl.name
+            );
+        }
+    }
+
+    #[test]
+    fn kinds_values_and_diagnostics_display() {
+        assert_eq!(OptionKind::Bool.to_string(), "a boolean");
+        assert_eq!(OptionKind::U64.to_string(), "an unsigned integer");
+        assert_eq!(OptionKind::Str.to_string(), "a string");
+        assert_eq!(DECLS[3].kind.to_string(), "one of `fast`, `slow`");
+
+        assert_eq!(OptionValue::Bool(true).to_string(), "true");
+        assert_eq!(OptionValue::U64(42).to_string(), "42");
+        assert_eq!(OptionValue::Str("com1").to_string(), "\"com1\"");
+        assert_eq!(std::format!("[{:>8}]", Source::Cmdline), "[ cmdline]");
+        assert_eq!(Source::Default.to_string(), "default");
+
+        assert_eq!(
+            Diagnostic::UnknownOption("verbose").to_string(),
+            "Ignoring unknown command line option `verbose`"
+        );
+        assert_eq!(
+            Diagnostic::InvalidValue {
+                name: "mode",
+                value: "medium",
+                kind: DECLS[3].kind,
+            }
+            .to_string(),
+            "Ignoring invalid value `medium` for command line option `mode`; expected one of \
+             `fast`, `slow`"
+        );
+        assert_eq!(
+            Diagnostic::Duplicate("count").to_string(),
+            "Command line option `count` given more than once; using the last occurrence"
+        );
+    }
+}
@@ -0,0 +1,126 @@
+//! Escalation of panics raised while the panic handler is already running.
+//!
+//! The panic handler formats the panic message and walks the logging sinks, either of which can
+//! panic again. Without a guard, that recursion continues until the stack overflows and the
+//! machine triple faults, taking the original diagnostic with it. Each processor instead counts
+//! how deeply it is nested in the panic handler, and [`enter()`] returns the [`PanicLevel`] that
+//! decides how much the handler may still attempt.
+
+use core::{
+    fmt,
+    sync::atomic::{AtomicBool, AtomicU32, Ordering},
+};
+
+crate::per_cpu! {
+    /// The panic nesting of the current processor.
+    static NESTING: PanicNesting = PanicNesting::new();
+}
+
+/// The panic nesting depth of the bootstrap processor before per-CPU areas are initialized.
+static EARLY_DEPTH: AtomicU32 = AtomicU32::new(0);
+
+/// Whether any processor has entered the panic handler.
+static IN_PROGRESS: AtomicBool = AtomicBool::new(false);
+
+/// The bytes written without locks when a panic is raised while handling another.
+pub const NESTED_PANIC_MARKER: &[u8] = b"\r\n!!! panic while panicking, halting !!!\r\n";
+
+/// Records that the current processor has entered the panic handler, returning how deeply it is
+/// now nested.
+pub fn enter() -> PanicLevel {
+    IN_PROGRESS.store(true, Ordering::Release);
+
+    NESTING
+        .try_with(PanicNesting::enter)
+        .unwrap_or_else(|_| PanicLevel::from_depth(EARLY_DEPTH.fetch_add(1, Ordering::Relaxed)))
+}
+
+/// Returns `true` if any processor has entered the panic handler.
+pub fn in_progress() -> bool {
+    IN_PROGRESS.load(Ordering::Acquire)
+}
+
+/// How deeply a processor is nested in the panic handler, which determines what the handler may
+/// still do.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum PanicLevel {
+    /// The first panic on this processor, which is reported in full.
+    First,
+    /// A panic raised while reporting the first one. Only a fixed marker is written, without
+    /// taking any lock, before the processor halts.
+    Nested,
+    /// A panic raised while handling a nested panic. The processor halts without doing anything
+    /// else.
+    Fatal,
+}
+
+impl PanicLevel {
+    /// Returns the [`PanicLevel`] of a panic raised at nesting depth `depth`, where zero means
+    /// that the panic handler was not running.
+    pub const fn from_depth(depth: u32) -> Self {
+        match depth {
+            0 => Self::First,
+            1 => Self::Nested,
+            _ => Self::Fatal,
+        }
+    }
+}
+
+impl fmt::Display for PanicLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::First => f.pad("first"),
+            Self::Nested => f.pad("nested"),
+            Self::Fatal => f.pad("fatal"),
+        }
+    }
+}
+
+/// The number of times a processor has entered the panic handler.
+///
+/// The kernel cannot unwind, so the handler never returns and the depth only grows.
+#[derive(Clone, Copy, Debug, Default, Hash, PartialEq, Eq)]
+pub struct PanicNesting {
+    /// The number of panics entered.
+    depth: u32,
+}
+
+impl PanicNesting {
+    /// Creates a [`PanicNesting`] for a processor that has not panicked.
+    pub const fn new() -> Self {
+        Self { depth: 0 }
+    }
+
+    /// Records a panic, returning the [`PanicLevel`] it is handled at.
+    pub const fn enter(&mut self) -> PanicLevel {
+        let level = PanicLevel::from_depth(self.depth);
+        self.depth = self.depth.saturating_add(1);
+        level
+    }
+
+    /// Returns the number of panics entered.
+    pub const fn depth(&self) -> u32 {
+        self.depth
+    }
+}
+
+#[cfg(feature = "ktest")]
+crate::kernel_test! {
+    fn panic_nesting_escalates() {
+        let mut nesting = PanicNesting::new();
+        assert_eq!(nesting.enter(), PanicLevel::First);
+        assert_eq!(nesting.enter(), PanicLevel::Nested);
+        assert_eq!(nesting.enter(), PanicLevel::Fatal);
+        assert_eq!(nesting.enter(), PanicLevel::Fatal);
+        assert_eq!(nesting.depth(), 4);
+
+        let mut nesting = PanicNesting { depth: u32::MAX };
+        assert_eq!(nesting.enter(), PanicLevel::Fatal);
+        assert_eq!(nesting.depth(), u32::MAX);
+    }
+
+    fn panic_is_not_in_progress() {
+        assert!(!in_progress());
+        assert_eq!(NESTING.with(|nesting| nesting.depth()), 0);
+    }
+}
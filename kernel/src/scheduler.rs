@@ -0,0 +1,650 @@
+//! Round robin scheduling of kernel threads, preempted by the timer tick.
+//!
+//! Every processor has its own run queue of runnable threads, the thread it is running, and an
+//! idle thread that it runs when its run queue is empty. [`on_tick()`] charges the running thread
+//! for each timer tick and requests a reschedule once its time slice of [`TIME_SLICE_TICKS`] is
+//! used up, which [`preempt_if_needed()`] carries out on the way back from the interrupt.
+//!
+//! The per-CPU state is only accessed with interrupts disabled, and is always accessed before the
+//! thread table of [`kthread`] is locked, never while it is held. Neither is touched by anything
+//! that allocates, so scheduling is safe from interrupt handlers.
+//!
+//! Threads only ever run on the processor that spawned them, since application processors do not
+//! run threads yet.
+
+use core::{
+    fmt,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use crate::{
+    arch::{context::switch_context, cpu},
+    kthread::{self, ThreadId, ThreadState, ThreadTable, BOOT_SLOT, MAX_THREADS, THREADS},
+};
+
+/// The number of timer ticks a thread runs for before it is preempted, if other threads are
+/// runnable.
+pub const TIME_SLICE_TICKS: u32 = 5;
+
+/// Whether a timer tick drives [`on_tick()`], so that threads can be preempted.
+static PREEMPTIVE: AtomicBool = AtomicBool::new(false);
+
+crate::per_cpu! {
+    /// The scheduling state of the current processor.
+    static CPU: CpuScheduler = CpuScheduler::new(BOOT_SLOT);
+}
+
+/// Creates the idle thread of the current processor.
+///
+/// # Errors
+/// Returns [`SchedulerError::Spawn`] if the idle thread could not be created.
+pub fn init_cpu() -> Result<ThreadId, SchedulerError> {
+    let (id, slot) = kthread::create(idle, "idle").map_err(SchedulerError::Spawn)?;
+    CPU.with(|cpu| cpu.idle = Some(slot));
+
+    Ok(id)
+}
+
+/// Declares that a timer tick now calls [`on_tick()`], so that threads are preempted, and that
+/// spawned threads should start with interrupts enabled.
+pub fn enable_preemption() {
+    PREEMPTIVE.store(true, Ordering::Release);
+}
+
+/// Returns `true` if threads are preempted by a timer tick.
+pub fn is_preemptive() -> bool {
+    PREEMPTIVE.load(Ordering::Acquire)
+}
+
+/// Charges the current thread for a timer tick, requesting a reschedule if its time slice is used
+/// up or the processor is idle while threads are runnable.
+///
+/// This is called from the timer interrupt handler.
+pub fn on_tick() {
+    let _ = CPU.try_with(CpuScheduler::tick);
+}
+
+/// Switches to the next runnable thread if a reschedule was requested.
+///
+/// This is called on the return path of the timer interrupt handler and by the idle thread.
+pub fn preempt_if_needed() {
+    if CPU.try_with(|cpu| cpu.need_resched) == Ok(true) {
+        schedule(ThreadState::Runnable);
+    }
+}
+
+/// Switches to the next runnable thread, leaving the current thread runnable.
+pub fn yield_now() {
+    schedule(ThreadState::Runnable);
+}
+
+/// Blocks the current thread until another thread calls [`unblock()`] on it.
+///
+/// # Panics
+/// Panics if no other thread is runnable and the idle thread has not been created.
+pub fn block() {
+    schedule(ThreadState::Blocked);
+}
+
+/// Makes the blocked thread `id` runnable again.
+///
+/// # Errors
+/// - [`SchedulerError::NoSuchThread`] if no thread `id` exists.
+/// - [`SchedulerError::NotBlocked`] if the thread is not blocked.
+pub fn unblock(id: ThreadId) -> Result<(), SchedulerError> {
+    cpu::without_interrupts(|| CPU.with(|cpu| cpu.unblock(&mut THREADS.lock(), id)))
+}
+
+/// Returns the slot of the thread running on the current processor.
+pub(crate) fn current_slot() -> usize {
+    CPU.with(|cpu| cpu.current)
+}
+
+/// Makes the thread in `slot` runnable on the current processor.
+pub(crate) fn enqueue(slot: usize) {
+    CPU.with(|cpu| cpu.queue.push_back(slot))
+        .expect("run queue holds every thread");
+}
+
+/// Switches from the current thread, which is left in the `outgoing` state, to the next runnable
+/// thread, or to the idle thread if there is none.
+///
+/// A runnable current thread keeps running if no other thread is runnable.
+///
+/// # Panics
+/// Panics if the current thread is not left runnable, no other thread is runnable, and the idle
+/// thread has not been created.
+pub(crate) fn schedule(outgoing: ThreadState) {
+    cpu::without_interrupts(|| {
+        let switch = CPU.with(|cpu| {
+            let mut threads = THREADS.lock();
+            let (old, new) = cpu.switch(&mut threads, outgoing)?;
+
+            Some((threads.context_mut(old), threads.context(new)))
+        });
+
+        if let Some((old, new)) = switch {
+            // SAFETY:
+            // Both contexts live in the thread table, whose slots are only reclaimed once their
+            // thread is finished and not current. Interrupts are disabled and threads only run on
+            // this processor, so nothing else accesses either context until the switch completes,
+            // and `new` was saved by `switch_context()` or seeded by `kthread::create()`.
+            unsafe { switch_context(old, new) }
+        }
+    });
+}
+
+/// The body of the idle thread of each processor.
+fn idle() {
+    loop {
+        preempt_if_needed();
+        crate::arch::wait_for_interrupt();
+    }
+}
+
+/// The scheduling state of a processor.
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+pub struct CpuScheduler {
+    /// The runnable threads waiting for this processor.
+    queue: RunQueue,
+    /// The slot of the thread running on this processor.
+    current: usize,
+    /// The slot of the idle thread of this processor, once it has been created.
+    idle: Option<usize>,
+    /// The number of ticks left in the time slice of the current thread.
+    slice_remaining: u32,
+    /// Whether the current thread should be switched away from.
+    need_resched: bool,
+}
+
+impl CpuScheduler {
+    /// Creates the [`CpuScheduler`] of a processor that is running the thread in `current`.
+    pub const fn new(current: usize) -> Self {
+        Self {
+            queue: RunQueue::new(),
+            current,
+            idle: None,
+            slice_remaining: TIME_SLICE_TICKS,
+            need_resched: false,
+        }
+    }
+
+    /// Charges the current thread for a timer tick.
+    pub fn tick(&mut self) {
+        if self.idle == Some(self.current) {
+            self.need_resched = !self.queue.is_empty();
+            return;
+        }
+
+        self.slice_remaining = self.slice_remaining.saturating_sub(1);
+        if self.slice_remaining == 0 && !self.queue.is_empty() {
+            self.need_resched = true;
+        }
+    }
+
+    /// Returns `true` if the current thread should be switched away from.
+    pub const fn need_resched(&self) -> bool {
+        self.need_resched
+    }
+
+    /// Makes the current thread `outgoing` and the next runnable thread in `threads`, or the idle
+    /// thread if there is none, current, returning the slots of the old and new current threads.
+    ///
+    /// Returns [`None`] if the current thread is left runnable and no other thread is runnable,
+    /// in which case it keeps running.
+    ///
+    /// # Panics
+    /// Panics if the current thread is not left runnable, no other thread is runnable, and the
+    /// idle thread has not been created.
+    fn switch(
+        &mut self,
+        threads: &mut ThreadTable,
+        outgoing: ThreadState,
+    ) -> Option<(usize, usize)> {
+        threads.reap(self.current);
+
+        let old = self.current;
+        let is_idle = self.idle == Some(old);
+        let new = match self.queue.pop_front() {
+            Some(slot) => slot,
+            None if outgoing == ThreadState::Runnable => {
+                self.reset_slice();
+                return None;
+            }
+            None => self.idle.expect("no runnable thread and no idle thread"),
+        };
+
+        if outgoing == ThreadState::Runnable && !is_idle {
+            self.queue
+                .push_back(old)
+                .expect("run queue holds every thread");
+        }
+        threads.set_state(old, outgoing);
+        threads.set_state(new, ThreadState::Running);
+        self.current = new;
+        self.reset_slice();
+
+        Some((old, new))
+    }
+
+    /// Makes the blocked thread `id` in `threads` runnable on this processor.
+    ///
+    /// # Errors
+    /// - [`SchedulerError::NoSuchThread`] if no thread `id` exists.
+    /// - [`SchedulerError::NotBlocked`] if the thread is not blocked.
+    fn unblock(&mut self, threads: &mut ThreadTable, id: ThreadId) -> Result<(), SchedulerError> {
+        let slot = threads.slot_of(id).ok_or(SchedulerError::NoSuchThread)?;
+        if threads.state(slot) != Some(ThreadState::Blocked) {
+            return Err(SchedulerError::NotBlocked);
+        }
+
+        self.queue
+            .push_back(slot)
+            .expect("run queue holds every thread");
+        threads.set_state(slot, ThreadState::Runnable);
+        Ok(())
+    }
+
+    /// Gives the current thread a fresh time slice and clears any reschedule request.
+    fn reset_slice(&mut self) {
+        self.slice_remaining = TIME_SLICE_TICKS;
+        self.need_resched = false;
+    }
+}
+
+/// A first in, first out queue of thread slots that never allocates.
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+pub struct RunQueue {
+    /// The slots, stored as a ring starting at `head`.
+    slots: [usize; MAX_THREADS],
+    /// The index in `slots` of the front of the queue.
+    head: usize,
+    /// The number of slots in the queue.
+    len: usize,
+}
+
+impl RunQueue {
+    /// Creates an empty [`RunQueue`].
+    pub const fn new() -> Self {
+        Self {
+            slots: [0; MAX_THREADS],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    /// Returns the number of slots in this [`RunQueue`].
+    pub const fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if this [`RunQueue`] is empty.
+    pub const fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Appends `slot` to the back of this [`RunQueue`].
+    ///
+    /// # Errors
+    /// Returns [`SchedulerError::QueueFull`] if this [`RunQueue`] already holds [`MAX_THREADS`]
+    /// slots.
+    pub fn push_back(&mut self, slot: usize) -> Result<(), SchedulerError> {
+        if self.len == MAX_THREADS {
+            return Err(SchedulerError::QueueFull);
+        }
+
+        self.slots[(self.head + self.len) % MAX_THREADS] = slot;
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Removes and returns the slot at the front of this [`RunQueue`].
+    pub fn pop_front(&mut self) -> Option<usize> {
+        if self.len == 0 {
+            return None;
+        }
+
+        let slot = self.slots[self.head];
+        self.head = (self.head + 1) % MAX_THREADS;
+        self.len -= 1;
+        Some(slot)
+    }
+}
+
+impl Default for RunQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Various errors that can occur while scheduling kernel threads.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum SchedulerError {
+    /// The run queue already holds [`MAX_THREADS`] threads.
+    QueueFull,
+    /// The thread does not exist.
+    NoSuchThread,
+    /// The thread is not blocked.
+    NotBlocked,
+    /// A thread could not be created.
+    Spawn(kthread::SpawnError),
+}
+
+impl fmt::Display for SchedulerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::QueueFull => f.write_str("run queue is full"),
+            Self::NoSuchThread => f.write_str("thread does not exist"),
+            Self::NotBlocked => f.write_str("thread is not blocked"),
+            Self::Spawn(error) => write!(f, "failed to create thread: {error}"),
+        }
+    }
+}
+
+#[cfg(feature = "ktest")]
+crate::kernel_test! {
+    fn blocked_threads_wait_for_unblock() {
+        use core::sync::atomic::AtomicUsize;
+
+        static STAGE: AtomicUsize = AtomicUsize::new(0);
+
+        /// Blocks once, recording its progress before and after.
+        fn sleeper() {
+            STAGE.store(1, Ordering::Relaxed);
+            block();
+            STAGE.store(2, Ordering::Relaxed);
+        }
+
+        STAGE.store(0, Ordering::Relaxed);
+        let sleeper = kthread::spawn(sleeper, "sleeper").unwrap();
+        while STAGE.load(Ordering::Relaxed) == 0 {
+            yield_now();
+        }
+
+        for _ in 0..10 {
+            yield_now();
+        }
+        assert_eq!(STAGE.load(Ordering::Relaxed), 1, "blocked thread ran");
+        assert_eq!(unblock(kthread::current()), Err(SchedulerError::NotBlocked));
+
+        unblock(sleeper).unwrap();
+        while !kthread::is_finished(sleeper) {
+            yield_now();
+        }
+        assert_eq!(STAGE.load(Ordering::Relaxed), 2);
+        assert_eq!(unblock(sleeper), Err(SchedulerError::NoSuchThread));
+    }
+
+    fn busy_threads_are_preempted() {
+        use core::sync::atomic::AtomicU64;
+
+        use crate::arch::apic;
+
+        static STOP: AtomicBool = AtomicBool::new(false);
+        static PROGRESS: [AtomicU64; 2] = [AtomicU64::new(0), AtomicU64::new(0)];
+
+        /// Spins on the first counter without yielding.
+        fn first() {
+            while !STOP.load(Ordering::Relaxed) {
+                PROGRESS[0].fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        /// Spins on the second counter without yielding.
+        fn second() {
+            while !STOP.load(Ordering::Relaxed) {
+                PROGRESS[1].fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        if !is_preemptive() {
+            #[cfg(feature = "logging")]
+            log::warn!("preemption is disabled, skipping");
+            return;
+        }
+
+        STOP.store(false, Ordering::Relaxed);
+        for progress in &PROGRESS {
+            progress.store(0, Ordering::Relaxed);
+        }
+        let threads = [
+            kthread::spawn(first, "busy-1").unwrap(),
+            kthread::spawn(second, "busy-2").unwrap(),
+        ];
+
+        let deadline = apic::ticks() + 20 * u64::from(TIME_SLICE_TICKS);
+        while PROGRESS.iter().any(|progress| progress.load(Ordering::Relaxed) == 0) {
+            assert!(apic::ticks() < deadline, "busy threads did not both make progress");
+            core::hint::spin_loop();
+        }
+
+        STOP.store(true, Ordering::Relaxed);
+        while !threads.iter().all(|&thread| kthread::is_finished(thread)) {
+            core::hint::spin_loop();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::vec::Vec;
+
+    use crate::arch::context::Context;
+
+    use super::*;
+
+    /// Returns a [`ThreadTable`] holding the boot thread and a runnable thread in each of the
+    /// first `count` slots after it, along with their [`ThreadId`]s.
+    fn table(count: usize) -> (ThreadTable, Vec<ThreadId>) {
+        let mut threads = ThreadTable::new();
+        let ids = (1..=count)
+            .map(|slot| threads.add(slot, || {}, "test", Context::default()))
+            .collect();
+
+        (threads, ids)
+    }
+
+    #[test]
+    fn run_queue_is_fifo_and_bounded() {
+        let mut queue = RunQueue::new();
+        assert_eq!(queue.pop_front(), None);
+
+        for round in 0..3 {
+            for slot in 0..MAX_THREADS {
+                queue.push_back(slot + round).unwrap();
+            }
+            assert_eq!(queue.push_back(0), Err(SchedulerError::QueueFull));
+            assert_eq!(queue.len(), MAX_THREADS);
+
+            for slot in 0..MAX_THREADS {
+                assert_eq!(queue.pop_front(), Some(slot + round));
+            }
+            assert!(queue.is_empty());
+        }
+
+        queue.push_back(7).unwrap();
+        assert_eq!(queue.pop_front(), Some(7));
+        queue.push_back(8).unwrap();
+        queue.push_back(9).unwrap();
+        assert_eq!(queue.pop_front(), Some(8));
+        assert_eq!(queue.pop_front(), Some(9));
+        assert_eq!(queue.pop_front(), None);
+    }
+
+    #[test]
+    fn time_slices_expire_only_with_waiting_threads() {
+        let mut cpu = CpuScheduler::new(1);
+        cpu.idle = Some(2);
+
+        for _ in 0..2 * TIME_SLICE_TICKS {
+            cpu.tick();
+        }
+        assert!(!cpu.need_resched(), "a lone thread was preempted");
+
+        cpu.queue.push_back(3).unwrap();
+        cpu.tick();
+        assert!(
+            cpu.need_resched(),
+            "an expired slice did not request a reschedule"
+        );
+
+        cpu.reset_slice();
+        for _ in 0..TIME_SLICE_TICKS - 1 {
+            cpu.tick();
+            assert!(!cpu.need_resched(), "a slice expired early");
+        }
+        cpu.tick();
+        assert!(cpu.need_resched());
+
+        cpu.current = 2;
+        cpu.reset_slice();
+        cpu.tick();
+        assert!(
+            cpu.need_resched(),
+            "an idle processor ignored a runnable thread"
+        );
+        cpu.queue.pop_front();
+        cpu.tick();
+        assert!(!cpu.need_resched());
+    }
+
+    #[test]
+    fn runnable_threads_take_turns() {
+        let (mut threads, _) = table(2);
+        let mut cpu = CpuScheduler::new(BOOT_SLOT);
+        cpu.queue.push_back(1).unwrap();
+        cpu.queue.push_back(2).unwrap();
+
+        assert_eq!(
+            cpu.switch(&mut threads, ThreadState::Runnable),
+            Some((0, 1))
+        );
+        assert_eq!(threads.state(0), Some(ThreadState::Runnable));
+        assert_eq!(threads.state(1), Some(ThreadState::Running));
+        assert_eq!(
+            cpu.switch(&mut threads, ThreadState::Runnable),
+            Some((1, 2))
+        );
+        assert_eq!(
+            cpu.switch(&mut threads, ThreadState::Runnable),
+            Some((2, 0))
+        );
+        assert_eq!(
+            cpu.switch(&mut threads, ThreadState::Runnable),
+            Some((0, 1))
+        );
+        assert_eq!(threads.state(0), Some(ThreadState::Runnable));
+        assert_eq!(threads.state(2), Some(ThreadState::Runnable));
+    }
+
+    #[test]
+    fn lone_runnable_thread_keeps_running() {
+        let (mut threads, _) = table(0);
+        let mut cpu = CpuScheduler::new(BOOT_SLOT);
+        cpu.need_resched = true;
+
+        assert_eq!(cpu.switch(&mut threads, ThreadState::Runnable), None);
+        assert_eq!(threads.state(BOOT_SLOT), Some(ThreadState::Running));
+        assert!(!cpu.need_resched());
+        assert!(cpu.queue.is_empty());
+    }
+
+    #[test]
+    fn blocked_thread_waits_for_unblock() {
+        let (mut threads, ids) = table(3);
+        let mut cpu = CpuScheduler::new(1);
+        cpu.idle = Some(3);
+        threads.set_state(1, ThreadState::Running);
+        cpu.queue.push_back(2).unwrap();
+
+        assert_eq!(cpu.switch(&mut threads, ThreadState::Blocked), Some((1, 2)));
+        assert_eq!(threads.state(1), Some(ThreadState::Blocked));
+        assert!(cpu.queue.is_empty());
+
+        // With nothing runnable, blocking switches to the idle thread, which is never queued.
+        assert_eq!(cpu.switch(&mut threads, ThreadState::Blocked), Some((2, 3)));
+        assert_eq!(cpu.switch(&mut threads, ThreadState::Runnable), None);
+        assert!(cpu.queue.is_empty());
+
+        cpu.unblock(&mut threads, ids[0]).unwrap();
+        assert_eq!(threads.state(1), Some(ThreadState::Runnable));
+        assert_eq!(cpu.queue.len(), 1);
+        assert_eq!(
+            cpu.unblock(&mut threads, ids[0]),
+            Err(SchedulerError::NotBlocked)
+        );
+        assert_eq!(cpu.queue.len(), 1);
+
+        assert_eq!(
+            cpu.switch(&mut threads, ThreadState::Runnable),
+            Some((3, 1))
+        );
+        assert_eq!(threads.state(1), Some(ThreadState::Running));
+        assert_eq!(threads.state(2), Some(ThreadState::Blocked));
+        assert!(cpu.queue.is_empty());
+    }
+
+    #[test]
+    fn unblock_rejects_threads_that_are_not_blocked() {
+        let (mut threads, ids) = table(2);
+        let mut cpu = CpuScheduler::new(1);
+        threads.set_state(1, ThreadState::Running);
+        cpu.queue.push_back(2).unwrap();
+
+        assert_eq!(
+            cpu.unblock(&mut threads, ids[1]),
+            Err(SchedulerError::NotBlocked),
+            "unblocked a runnable thread"
+        );
+        assert_eq!(
+            cpu.unblock(&mut threads, ids[0]),
+            Err(SchedulerError::NotBlocked),
+            "unblocked a running thread"
+        );
+
+        // A finished thread is not reaped while it is still current.
+        assert_eq!(
+            cpu.switch(&mut threads, ThreadState::Finished),
+            Some((1, 2))
+        );
+        assert_eq!(threads.state(1), Some(ThreadState::Finished));
+        assert_eq!(
+            cpu.unblock(&mut threads, ids[0]),
+            Err(SchedulerError::NotBlocked),
+            "unblocked a finished thread"
+        );
+        assert!(cpu.queue.is_empty());
+    }
+
+    #[test]
+    fn finished_thread_is_reaped_after_switching_away() {
+        let (mut threads, ids) = table(1);
+        let mut cpu = CpuScheduler::new(1);
+        threads.set_state(1, ThreadState::Running);
+        cpu.queue.push_back(BOOT_SLOT).unwrap();
+
+        assert_eq!(
+            cpu.switch(&mut threads, ThreadState::Finished),
+            Some((1, 0))
+        );
+        assert_eq!(threads.slot_of(ids[0]), Some(1));
+
+        // The next pass through the scheduler frees the slot of the finished thread.
+        assert_eq!(cpu.switch(&mut threads, ThreadState::Runnable), None);
+        assert_eq!(threads.state(1), None);
+        assert_eq!(threads.slot_of(ids[0]), None);
+        assert_eq!(
+            cpu.unblock(&mut threads, ids[0]),
+            Err(SchedulerError::NoSuchThread)
+        );
+    }
+
+    #[test]
+    #[should_panic = "no runnable thread and no idle thread"]
+    fn blocking_without_idle_thread_panics() {
+        let (mut threads, _) = table(0);
+        let mut cpu = CpuScheduler::new(BOOT_SLOT);
+
+        cpu.switch(&mut threads, ThreadState::Blocked);
+    }
+}
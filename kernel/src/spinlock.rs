@@ -1,10 +1,18 @@
 //! Simple spinlock implementation.
+//!
+//! With the `debug-locks` feature, every lock records the processor holding it and where it was
+//! acquired. Acquiring a lock already held by the current processor panics immediately, since it
+//! can never be released, and a waiter that spins for more than [`DEADLOCK_SPIN_BUDGET`]
+//! iterations reports a possible deadlock. Without the feature, none of this bookkeeping exists.
 
 use core::{
     cell::UnsafeCell,
     error, fmt,
+    marker::PhantomData,
+    mem::ManuallyDrop,
     ops::{Deref, DerefMut},
-    sync::atomic::{AtomicBool, Ordering},
+    ptr::NonNull,
+    sync::atomic::{AtomicBool, AtomicU32, Ordering},
 };
 
 /// The locking component of a [`Spinlock`].
@@ -12,6 +20,9 @@ use core::{
 pub struct RawSpinlock {
     /// The lock.
     lock: AtomicBool,
+    /// The context holding the lock.
+    #[cfg(feature = "debug-locks")]
+    holder: debug::Holder,
 }
 
 impl RawSpinlock {
@@ -19,28 +30,54 @@ impl RawSpinlock {
     pub const fn new() -> Self {
         Self {
             lock: AtomicBool::new(false),
+            #[cfg(feature = "debug-locks")]
+            holder: debug::Holder::new(),
         }
     }
 
     /// Locks the [`RawSpinlock`], spinning until the lock is acquired.
     ///
     /// This function does not return until the lock has been acquired.
+    ///
+    /// Waiters only read the lock while it is held, so that they share its cache line instead of
+    /// repeatedly taking ownership of it away from the holder, and only attempt to acquire it once
+    /// it looks free. A waiter that loses the race for a free lock backs off before trying again.
+    ///
+    /// # Panics
+    /// With the `debug-locks` feature, panics if the current processor already holds the lock.
+    #[cfg_attr(feature = "debug-locks", track_caller)]
     pub fn lock(&self) {
-        let mut was_locked = self.lock.load(Ordering::Relaxed);
+        #[cfg(feature = "debug-locks")]
+        let mut spins = 0;
+        let mut backoff = Backoff::new();
 
         loop {
-            if !was_locked {
-                match self
-                    .lock
-                    .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
-                {
-                    Ok(_) => break,
-                    Err(state) => was_locked = state,
-                }
+            if self
+                .lock
+                .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok()
+            {
+                break;
+            }
+
+            if !self.lock.load(Ordering::Relaxed) {
+                // The lock was free but another context took it first, or the exchange failed
+                // spuriously.
+                backoff.spin();
+                continue;
             }
 
-            core::hint::spin_loop();
+            while self.lock.load(Ordering::Relaxed) {
+                #[cfg(feature = "debug-locks")]
+                self.holder
+                    .check_wait(&mut spins, core::panic::Location::caller());
+
+                relax();
+            }
         }
+
+        #[cfg(feature = "debug-locks")]
+        self.holder.set(core::panic::Location::caller());
     }
 
     /// Attempts to lock the [`RawSpinlock`].
@@ -49,6 +86,7 @@ impl RawSpinlock {
     ///
     /// # Errors
     /// If the [`RawSpinlock`] was already locked, then this calll will return an [`Err`].
+    #[cfg_attr(feature = "debug-locks", track_caller)]
     pub fn try_lock(&self) -> Result<(), SpinlockAcquisitionError> {
         if !self.lock.load(Ordering::Relaxed)
             && self
@@ -56,6 +94,9 @@ impl RawSpinlock {
                 .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
                 .is_ok()
         {
+            #[cfg(feature = "debug-locks")]
+            self.holder.set(core::panic::Location::caller());
+
             Ok(())
         } else {
             Err(SpinlockAcquisitionError)
@@ -64,6 +105,9 @@ impl RawSpinlock {
 
     /// Unlocks the [`RawSpinlock`].
     pub fn unlock(&self) {
+        #[cfg(feature = "debug-locks")]
+        self.holder.clear();
+
         self.lock.store(false, Ordering::Release);
     }
 }
@@ -74,6 +118,53 @@ impl Default for RawSpinlock {
     }
 }
 
+/// The number of pause iterations after the first failed attempt to acquire a free lock.
+const BACKOFF_INITIAL_SPINS: u32 = 1;
+/// The maximum number of pause iterations between attempts to acquire a free lock.
+///
+/// A pause takes on the order of a hundred cycles on recent processors, so this caps the delay at
+/// a few microseconds. The backoff only applies after losing the race for a free lock, never while
+/// waiting for the holder, so a lone processor, which never loses such a race, is never delayed.
+const BACKOFF_MAX_SPINS: u32 = 64;
+
+/// Bounded exponential backoff between attempts to acquire a contended lock.
+#[derive(Debug)]
+struct Backoff {
+    /// The number of pause iterations before the next attempt.
+    spins: u32,
+}
+
+impl Backoff {
+    /// Creates a new [`Backoff`] starting at [`BACKOFF_INITIAL_SPINS`].
+    const fn new() -> Self {
+        Self {
+            spins: BACKOFF_INITIAL_SPINS,
+        }
+    }
+
+    /// Pauses for the current number of iterations, then doubles it up to [`BACKOFF_MAX_SPINS`].
+    fn spin(&mut self) {
+        for _ in 0..self.spins {
+            relax();
+        }
+
+        self.spins = (self.spins * 2).min(BACKOFF_MAX_SPINS);
+    }
+}
+
+/// Tells the processor that the caller is spinning while waiting for another context, such as
+/// the holder of a lock.
+///
+/// Host tests run more threads than there are processors, so a spinning thread yields to the
+/// scheduler instead, letting the context it waits for run.
+#[inline]
+pub(crate) fn relax() {
+    #[cfg(not(test))]
+    core::hint::spin_loop();
+    #[cfg(test)]
+    std::thread::yield_now();
+}
+
 /// A mutual exclusion primitive useful for protecting shared data.
 pub struct Spinlock<T: ?Sized> {
     /// The lock.
@@ -113,6 +204,10 @@ impl<T: ?Sized> Spinlock<T> {
     /// This function will spin until the lock is available. Upon returning, this context is the
     /// only context with the lock held. A RAII guard is returned to allow for scoped unlock of the
     /// [`Spinlock`].
+    ///
+    /// # Panics
+    /// With the `debug-locks` feature, panics if the current processor already holds the lock.
+    #[cfg_attr(feature = "debug-locks", track_caller)]
     pub fn lock(&self) -> SpinlockGuard<T> {
         self.lock.lock();
 
@@ -132,6 +227,7 @@ impl<T: ?Sized> Spinlock<T> {
     /// # Errors
     /// If the [`Spinlock`] could not be acquire because it is already locked, then this call will
     /// return an [`Err`].
+    #[cfg_attr(feature = "debug-locks", track_caller)]
     pub fn try_lock(&self) -> Result<SpinlockGuard<T>, SpinlockAcquisitionError> {
         self.lock.try_lock().map(|()| SpinlockGuard {
             lock: &self.lock,
@@ -139,9 +235,32 @@ impl<T: ?Sized> Spinlock<T> {
         })
     }
 
+    /// Forcibly unlocks this [`Spinlock`], regardless of which context holds it.
+    ///
+    /// # Safety
+    /// The [`SpinlockGuard`] currently holding this [`Spinlock`], if any, must never be used again,
+    /// as happens when its holder was interrupted by a panic.
+    pub unsafe fn force_unlock(&self) {
+        self.lock.unlock()
+    }
+
+    /// Acquires this [`Spinlock`], making up to `max_attempts` attempts before forcibly unlocking
+    /// it.
+    ///
+    /// # Safety
+    /// If the lock is bypassed, the same requirements as [`Spinlock::force_unlock()`] apply.
+    pub unsafe fn lock_or_bypass(
+        &self,
+        max_attempts: usize,
+    ) -> (SpinlockGuard<'_, T>, Acquisition) {
+        // SAFETY:
+        // The invariants of `acquire_or_bypass()` are upheld by the caller.
+        unsafe { acquire_or_bypass(self, max_attempts) }
+    }
+
     /// Method that makes unlocking a mutex more explicit.
     pub fn unlock(guard: SpinlockGuard<T>) {
-        guard.lock.unlock()
+        drop(guard)
     }
 
     /// Returns a mutable reference to the underlying data.
@@ -175,6 +294,28 @@ impl<'a, T: ?Sized> SpinlockGuard<'a, T> {
     pub unsafe fn new(lock: &'a RawSpinlock, value: &'a UnsafeCell<T>) -> Self {
         Self { lock, value }
     }
+
+    /// Narrows this [`SpinlockGuard`] to the part of the protected value returned by `f`.
+    ///
+    /// The lock stays held until the returned [`MappedSpinlockGuard`] is dropped. If `f` panics,
+    /// the lock is never released.
+    pub fn map<U: ?Sized + 'a>(
+        self,
+        f: impl FnOnce(&mut T) -> &mut U,
+    ) -> MappedSpinlockGuard<'a, U> {
+        let this = ManuallyDrop::new(self);
+
+        // SAFETY:
+        // `this` holds the lock for `'a` and is never dropped, so the value is exclusively borrowed
+        // until the returned guard releases the lock.
+        let value = unsafe { &mut *this.value.get() };
+
+        MappedSpinlockGuard {
+            lock: this.lock,
+            value: NonNull::from(f(value)),
+            borrow: PhantomData,
+        }
+    }
 }
 
 impl<T: ?Sized> Deref for SpinlockGuard<'_, T> {
@@ -205,6 +346,577 @@ impl<T: ?Sized> Drop for SpinlockGuard<'_, T> {
     }
 }
 
+/// A RAII guard for part of the value protected by a [`Spinlock`], created by
+/// [`SpinlockGuard::map()`]. When this structure is dropped, the [`Spinlock`] will be unlocked.
+///
+/// The lock can only be released by dropping this guard, never through the [`Spinlock`] it was
+/// mapped from.
+pub struct MappedSpinlockGuard<'a, T: ?Sized> {
+    lock: &'a RawSpinlock,
+    value: NonNull<T>,
+    /// The exclusive borrow of the value for as long as the lock is held.
+    borrow: PhantomData<&'a mut T>,
+}
+
+impl<'a, T: ?Sized> MappedSpinlockGuard<'a, T> {
+    /// Narrows this [`MappedSpinlockGuard`] further to the part of the value returned by `f`.
+    ///
+    /// The lock stays held until the returned [`MappedSpinlockGuard`] is dropped. If `f` panics,
+    /// the lock is never released.
+    pub fn map<U: ?Sized + 'a>(
+        self,
+        f: impl FnOnce(&mut T) -> &mut U,
+    ) -> MappedSpinlockGuard<'a, U> {
+        let mut this = ManuallyDrop::new(self);
+
+        // SAFETY:
+        // `this` holds the lock for `'a` and is never dropped, so the value is exclusively borrowed
+        // until the returned guard releases the lock.
+        let value = unsafe { this.value.as_mut() };
+
+        MappedSpinlockGuard {
+            lock: this.lock,
+            value: NonNull::from(f(value)),
+            borrow: PhantomData,
+        }
+    }
+}
+
+impl<T: ?Sized> Deref for MappedSpinlockGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        // SAFETY:
+        // We have exclusive access to the value pointed to by `self.value`.
+        unsafe { self.value.as_ref() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for MappedSpinlockGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        // SAFETY:
+        // We have exclusive access to the value pointed to by `self.value`.
+        unsafe { self.value.as_mut() }
+    }
+}
+
+impl<T: ?Sized> Drop for MappedSpinlockGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.unlock();
+    }
+}
+
+/// A fair mutual exclusion primitive that grants the lock in the order it was requested.
+///
+/// Under contention, a [`Spinlock`] is granted to whichever waiter happens to win the race, so a
+/// waiter can lose indefinitely. A [`TicketSpinlock`] hands out tickets instead and serves them
+/// in order. Both counters wrap around, and tickets are only ever compared for equality, so
+/// wrapping is harmless as long as fewer than `2^32` contexts wait at once.
+pub struct TicketSpinlock<T: ?Sized> {
+    /// The ticket handed to the next context that requests the lock.
+    next: AtomicU32,
+    /// The ticket of the context currently allowed to hold the lock.
+    serving: AtomicU32,
+    /// The context holding the lock.
+    #[cfg(feature = "debug-locks")]
+    holder: debug::Holder,
+    /// The value protected by the [`TicketSpinlock`].
+    value: UnsafeCell<T>,
+}
+
+// SAFETY:
+// Nothing about `TicketSpinlock<T>` changes whether it is safe to send `T` across threads.
+unsafe impl<T: ?Sized + Send> Send for TicketSpinlock<T> {}
+
+// SAFETY:
+// If `T` is safe to send across threads, then `TicketSpinlock<T>` makes it safe to access from
+// multiple threads simultaneously.
+unsafe impl<T: ?Sized + Send> Sync for TicketSpinlock<T> {}
+
+impl<T> TicketSpinlock<T> {
+    /// Creates a new [`TicketSpinlock`] in an unlocked state ready for use.
+    pub const fn new(value: T) -> Self {
+        Self {
+            next: AtomicU32::new(0),
+            serving: AtomicU32::new(0),
+            #[cfg(feature = "debug-locks")]
+            holder: debug::Holder::new(),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    /// Consumes this [`TicketSpinlock`], returning the underlying data.
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+}
+
+impl<T: Default> Default for TicketSpinlock<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T: ?Sized> TicketSpinlock<T> {
+    /// Acquires the [`TicketSpinlock`], spinning until every context that requested it earlier
+    /// has released it.
+    ///
+    /// # Panics
+    /// With the `debug-locks` feature, panics if the current processor already holds the lock.
+    /// The panic happens before a ticket is taken, so the lock keeps working once it is released.
+    #[cfg_attr(feature = "debug-locks", track_caller)]
+    pub fn lock(&self) -> TicketSpinlockGuard<'_, T> {
+        #[cfg(feature = "debug-locks")]
+        self.holder.check_not_held(core::panic::Location::caller());
+        #[cfg(feature = "debug-locks")]
+        let mut spins = 0;
+
+        let ticket = self.next.fetch_add(1, Ordering::Relaxed);
+        while self.serving.load(Ordering::Acquire) != ticket {
+            #[cfg(feature = "debug-locks")]
+            self.holder
+                .check_wait(&mut spins, core::panic::Location::caller());
+
+            relax();
+        }
+
+        #[cfg(feature = "debug-locks")]
+        self.holder.set(core::panic::Location::caller());
+
+        TicketSpinlockGuard { lock: self }
+    }
+
+    /// Attempts to acquire this [`TicketSpinlock`] without waiting.
+    ///
+    /// # Errors
+    /// If the [`TicketSpinlock`] is held or other contexts are waiting for it, then this call will
+    /// return an [`Err`].
+    #[cfg_attr(feature = "debug-locks", track_caller)]
+    pub fn try_lock(&self) -> Result<TicketSpinlockGuard<'_, T>, SpinlockAcquisitionError> {
+        let serving = self.serving.load(Ordering::Relaxed);
+        self.next
+            .compare_exchange(
+                serving,
+                serving.wrapping_add(1),
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            )
+            .map_err(|_| SpinlockAcquisitionError)?;
+
+        #[cfg(feature = "debug-locks")]
+        self.holder.set(core::panic::Location::caller());
+
+        Ok(TicketSpinlockGuard { lock: self })
+    }
+
+    /// Returns `true` if this [`TicketSpinlock`] is held or requested by any context.
+    pub fn is_locked(&self) -> bool {
+        self.next.load(Ordering::Relaxed) != self.serving.load(Ordering::Relaxed)
+    }
+
+    /// Method that makes unlocking a [`TicketSpinlock`] more explicit.
+    pub fn unlock(guard: TicketSpinlockGuard<T>) {
+        drop(guard)
+    }
+
+    /// Returns a mutable reference to the underlying data.
+    ///
+    /// Since this call borrows the [`TicketSpinlock`] mutably, no actual locking needs to take
+    /// place: the mutable borrow statically guarantees no locks exist.
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
+}
+
+/// A RAII guard for a [`TicketSpinlock`]. When this structure is dropped, the lock is passed to
+/// the next waiting context.
+///
+/// This structure is created by the [`TicketSpinlock::lock()`] and [`TicketSpinlock::try_lock()`]
+/// methods.
+pub struct TicketSpinlockGuard<'a, T: ?Sized> {
+    /// The lock being held.
+    lock: &'a TicketSpinlock<T>,
+}
+
+impl<T: ?Sized> Deref for TicketSpinlockGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        let value_ptr = self.lock.value.get();
+
+        // SAFETY:
+        // We have exclusive access to the value pointed to by `value_ptr`.
+        unsafe { &*value_ptr }
+    }
+}
+
+impl<T: ?Sized> DerefMut for TicketSpinlockGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        let value_ptr = self.lock.value.get();
+
+        // SAFETY:
+        // We have exclusive access to the value pointed to by `value_ptr`.
+        unsafe { &mut *value_ptr }
+    }
+}
+
+impl<T: ?Sized> Drop for TicketSpinlockGuard<'_, T> {
+    fn drop(&mut self) {
+        #[cfg(feature = "debug-locks")]
+        self.lock.holder.clear();
+
+        self.lock.serving.fetch_add(1, Ordering::Release);
+    }
+}
+
+/// Control over whether the current processor accepts maskable interrupts, as used by
+/// [`IrqSpinlock`].
+pub trait InterruptControl {
+    /// Returns `true` if maskable interrupts are enabled.
+    fn interrupts_enabled() -> bool;
+
+    /// Disables maskable interrupts.
+    fn disable_interrupts();
+
+    /// Enables maskable interrupts.
+    ///
+    /// # Safety
+    /// Interrupt handlers must be able to run without deadlocking or observing broken invariants.
+    unsafe fn enable_interrupts();
+}
+
+/// A [`Spinlock`] that disables interrupts while it is held.
+///
+/// A [`Spinlock`] taken by both a thread and an interrupt handler deadlocks if the handler
+/// interrupts the thread while it holds the lock. An [`IrqSpinlock`] disables interrupts before
+/// acquiring the lock and restores their previous state after releasing it, so such a handler can
+/// never run while the lock is held on the same processor. Nested guards restore the state in
+/// effect when each was acquired.
+///
+/// Code holding an [`IrqSpinlock`] must neither enable interrupts nor wait for anything that
+/// requires interrupts to make progress. Enabling interrupts is caught by a debug assertion when
+/// the guard is dropped.
+pub struct IrqSpinlock<T: ?Sized, I: InterruptControl = crate::arch::cpu::Interrupts> {
+    /// The source of the interrupt state.
+    interrupts: PhantomData<I>,
+    /// The lock protecting the value.
+    lock: Spinlock<T>,
+}
+
+impl<T, I: InterruptControl> IrqSpinlock<T, I> {
+    /// Creates a new [`IrqSpinlock`] in an unlocked state ready for use.
+    pub const fn new(value: T) -> Self {
+        Self {
+            interrupts: PhantomData,
+            lock: Spinlock::new(value),
+        }
+    }
+}
+
+impl<T: ?Sized, I: InterruptControl> IrqSpinlock<T, I> {
+    /// Disables interrupts and acquires the [`IrqSpinlock`], spinning until the lock is
+    /// available.
+    ///
+    /// Interrupts are restored to their previous state when the returned guard is dropped.
+    ///
+    /// # Panics
+    /// With the `debug-locks` feature, panics if the current processor already holds the lock.
+    #[cfg_attr(feature = "debug-locks", track_caller)]
+    pub fn lock(&self) -> IrqSpinlockGuard<'_, T, I> {
+        let restore = Self::save_and_disable();
+
+        IrqSpinlockGuard::new(self.lock.lock(), restore)
+    }
+
+    /// Attempts to acquire this [`IrqSpinlock`] without spinning.
+    ///
+    /// # Errors
+    /// If the [`IrqSpinlock`] is already locked, then this call will return an [`Err`] and leave
+    /// the interrupt state unchanged.
+    #[cfg_attr(feature = "debug-locks", track_caller)]
+    pub fn try_lock(&self) -> Result<IrqSpinlockGuard<'_, T, I>, SpinlockAcquisitionError> {
+        let restore = Self::save_and_disable();
+
+        match self.lock.try_lock() {
+            Ok(guard) => Ok(IrqSpinlockGuard::new(guard, restore)),
+            Err(error) => {
+                if restore {
+                    // SAFETY:
+                    // Interrupts were enabled on entry, so this restores the previous state.
+                    unsafe { I::enable_interrupts() }
+                }
+
+                Err(error)
+            }
+        }
+    }
+
+    /// Forcibly unlocks this [`IrqSpinlock`], regardless of which context holds it.
+    ///
+    /// # Safety
+    /// See [`Spinlock::force_unlock()`].
+    pub unsafe fn force_unlock(&self) {
+        // SAFETY:
+        // The invariants of `Spinlock::force_unlock()` are upheld by the caller.
+        unsafe { self.lock.force_unlock() }
+    }
+
+    /// Acquires this [`IrqSpinlock`], making up to `max_attempts` attempts before forcibly
+    /// unlocking it.
+    ///
+    /// # Safety
+    /// If the lock is bypassed, the same requirements as [`Spinlock::force_unlock()`] apply.
+    pub unsafe fn lock_or_bypass(
+        &self,
+        max_attempts: usize,
+    ) -> (IrqSpinlockGuard<'_, T, I>, Acquisition) {
+        // SAFETY:
+        // The invariants of `acquire_or_bypass()` are upheld by the caller.
+        unsafe { acquire_or_bypass(self, max_attempts) }
+    }
+
+    /// Disables interrupts, returning `true` if they were enabled.
+    fn save_and_disable() -> bool {
+        let enabled = I::interrupts_enabled();
+        if enabled {
+            I::disable_interrupts();
+        }
+
+        enabled
+    }
+}
+
+/// A RAII guard for an [`IrqSpinlock`]. When this structure is dropped, the lock is released and
+/// interrupts are restored to the state they were in when the lock was acquired.
+pub struct IrqSpinlockGuard<'a, T: ?Sized, I: InterruptControl = crate::arch::cpu::Interrupts> {
+    /// The guard of the underlying [`Spinlock`].
+    guard: ManuallyDrop<SpinlockGuard<'a, T>>,
+    /// Whether interrupts must be enabled once the lock is released.
+    restore: bool,
+    /// The source of the interrupt state.
+    interrupts: PhantomData<I>,
+}
+
+impl<'a, T: ?Sized, I: InterruptControl> IrqSpinlockGuard<'a, T, I> {
+    /// Wraps `guard`, which was acquired with interrupts disabled.
+    fn new(guard: SpinlockGuard<'a, T>, restore: bool) -> Self {
+        Self {
+            guard: ManuallyDrop::new(guard),
+            restore,
+            interrupts: PhantomData,
+        }
+    }
+
+    /// Narrows this [`IrqSpinlockGuard`] to the part of the protected value returned by `f`.
+    ///
+    /// The lock stays held, and interrupts stay disabled, until the returned
+    /// [`MappedIrqSpinlockGuard`] is dropped.
+    pub fn map<U: ?Sized + 'a>(
+        self,
+        f: impl FnOnce(&mut T) -> &mut U,
+    ) -> MappedIrqSpinlockGuard<'a, U, I> {
+        let mut this = ManuallyDrop::new(self);
+        let restore = this.restore;
+
+        // SAFETY:
+        // `this` is never dropped, so `this.guard` is moved out exactly once.
+        let guard = unsafe { ManuallyDrop::take(&mut this.guard) };
+
+        MappedIrqSpinlockGuard {
+            guard: ManuallyDrop::new(guard.map(f)),
+            restore,
+            interrupts: PhantomData,
+        }
+    }
+}
+
+impl<T: ?Sized, I: InterruptControl> Deref for IrqSpinlockGuard<'_, T, I> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.guard
+    }
+}
+
+impl<T: ?Sized, I: InterruptControl> DerefMut for IrqSpinlockGuard<'_, T, I> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.guard
+    }
+}
+
+impl<T: ?Sized, I: InterruptControl> Drop for IrqSpinlockGuard<'_, T, I> {
+    fn drop(&mut self) {
+        debug_assert!(
+            !I::interrupts_enabled(),
+            "interrupts were enabled while an IrqSpinlock was held"
+        );
+
+        // SAFETY:
+        // `self.guard` is never used again.
+        unsafe { ManuallyDrop::drop(&mut self.guard) };
+
+        if self.restore {
+            // SAFETY:
+            // Interrupts were enabled when the lock was acquired and the lock has been released,
+            // so this restores the previous state.
+            unsafe { I::enable_interrupts() }
+        }
+    }
+}
+
+/// A RAII guard for part of the value protected by an [`IrqSpinlock`], created by
+/// [`IrqSpinlockGuard::map()`]. When this structure is dropped, the lock is released and
+/// interrupts are restored to the state they were in when the lock was acquired.
+pub struct MappedIrqSpinlockGuard<'a, T: ?Sized, I: InterruptControl = crate::arch::cpu::Interrupts>
+{
+    /// The guard of the underlying [`Spinlock`].
+    guard: ManuallyDrop<MappedSpinlockGuard<'a, T>>,
+    /// Whether interrupts must be enabled once the lock is released.
+    restore: bool,
+    /// The source of the interrupt state.
+    interrupts: PhantomData<I>,
+}
+
+impl<'a, T: ?Sized, I: InterruptControl> MappedIrqSpinlockGuard<'a, T, I> {
+    /// Narrows this [`MappedIrqSpinlockGuard`] further to the part of the value returned by `f`.
+    ///
+    /// The lock stays held, and interrupts stay disabled, until the returned
+    /// [`MappedIrqSpinlockGuard`] is dropped.
+    pub fn map<U: ?Sized + 'a>(
+        self,
+        f: impl FnOnce(&mut T) -> &mut U,
+    ) -> MappedIrqSpinlockGuard<'a, U, I> {
+        let mut this = ManuallyDrop::new(self);
+        let restore = this.restore;
+
+        // SAFETY:
+        // `this` is never dropped, so `this.guard` is moved out exactly once.
+        let guard = unsafe { ManuallyDrop::take(&mut this.guard) };
+
+        MappedIrqSpinlockGuard {
+            guard: ManuallyDrop::new(guard.map(f)),
+            restore,
+            interrupts: PhantomData,
+        }
+    }
+}
+
+impl<T: ?Sized, I: InterruptControl> Deref for MappedIrqSpinlockGuard<'_, T, I> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.guard
+    }
+}
+
+impl<T: ?Sized, I: InterruptControl> DerefMut for MappedIrqSpinlockGuard<'_, T, I> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.guard
+    }
+}
+
+impl<T: ?Sized, I: InterruptControl> Drop for MappedIrqSpinlockGuard<'_, T, I> {
+    fn drop(&mut self) {
+        debug_assert!(
+            !I::interrupts_enabled(),
+            "interrupts were enabled while an IrqSpinlock was held"
+        );
+
+        // SAFETY:
+        // `self.guard` is never used again.
+        unsafe { ManuallyDrop::drop(&mut self.guard) };
+
+        if self.restore {
+            // SAFETY:
+            // Interrupts were enabled when the lock was acquired and the lock has been released,
+            // so this restores the previous state.
+            unsafe { I::enable_interrupts() }
+        }
+    }
+}
+
+/// How a lock was obtained by [`acquire_or_bypass()`].
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum Acquisition {
+    /// The lock was acquired normally.
+    Acquired,
+    /// The lock could not be acquired in time and was forcibly unlocked.
+    Bypassed,
+}
+
+/// A lock that can be forcibly unlocked, for use by [`acquire_or_bypass()`].
+pub trait BypassableLock {
+    /// The guard returned when the lock is acquired.
+    type Guard;
+
+    /// Attempts to acquire the lock without spinning.
+    fn try_acquire(&self) -> Option<Self::Guard>;
+
+    /// Forcibly unlocks the lock.
+    ///
+    /// # Safety
+    /// The guard currently holding the lock, if any, must never be used again.
+    unsafe fn force_unlock(&self);
+}
+
+impl<'lock, T: ?Sized> BypassableLock for &'lock Spinlock<T> {
+    type Guard = SpinlockGuard<'lock, T>;
+
+    fn try_acquire(&self) -> Option<Self::Guard> {
+        self.try_lock().ok()
+    }
+
+    unsafe fn force_unlock(&self) {
+        // SAFETY:
+        // The invariants of `Spinlock::force_unlock()` are upheld by the caller.
+        unsafe { Spinlock::force_unlock(self) }
+    }
+}
+
+impl<'lock, T: ?Sized, I: InterruptControl> BypassableLock for &'lock IrqSpinlock<T, I> {
+    type Guard = IrqSpinlockGuard<'lock, T, I>;
+
+    fn try_acquire(&self) -> Option<Self::Guard> {
+        self.try_lock().ok()
+    }
+
+    unsafe fn force_unlock(&self) {
+        // SAFETY:
+        // The invariants of `IrqSpinlock::force_unlock()` are upheld by the caller.
+        unsafe { IrqSpinlock::force_unlock(self) }
+    }
+}
+
+/// Acquires `lock`, making up to `max_attempts` attempts before forcibly unlocking it.
+///
+/// This is intended for paths such as the panic handler, which must make progress even if the
+/// context holding `lock` will never release it.
+///
+/// # Safety
+/// If the lock is bypassed, the same requirements as [`BypassableLock::force_unlock()`] apply.
+pub unsafe fn acquire_or_bypass<L: BypassableLock>(
+    lock: L,
+    max_attempts: usize,
+) -> (L::Guard, Acquisition) {
+    for _ in 0..max_attempts {
+        if let Some(guard) = lock.try_acquire() {
+            return (guard, Acquisition::Acquired);
+        }
+
+        core::hint::spin_loop();
+    }
+
+    loop {
+        // SAFETY:
+        // The invariants of `BypassableLock::force_unlock()` are upheld by the caller.
+        unsafe { lock.force_unlock() };
+
+        if let Some(guard) = lock.try_acquire() {
+            return (guard, Acquisition::Bypassed);
+        }
+    }
+}
+
 /// Represents the failure to acquire a [`Spinlock`].
 #[derive(Clone, Copy, Debug, Default, Hash, PartialEq, Eq, PartialOrd, Ord)]
 pub struct SpinlockAcquisitionError;
@@ -216,3 +928,861 @@ impl fmt::Display for SpinlockAcquisitionError {
 }
 
 impl error::Error for SpinlockAcquisitionError {}
+
+/// The number of iterations a waiter spins before reporting a possible deadlock, with the
+/// `debug-locks` feature.
+///
+/// Legitimate hold times, such as writing a long record to a slow serial port, are orders of
+/// magnitude shorter, so a wait this long almost certainly means the holder is stuck.
+#[cfg(feature = "debug-locks")]
+pub const DEADLOCK_SPIN_BUDGET: u64 = 100_000_000;
+
+/// Bookkeeping of lock holders for the `debug-locks` feature.
+#[cfg(feature = "debug-locks")]
+mod debug {
+    use core::{
+        fmt,
+        panic::Location,
+        ptr,
+        sync::atomic::{AtomicBool, AtomicPtr, AtomicU32, Ordering},
+    };
+
+    use super::DEADLOCK_SPIN_BUDGET;
+
+    /// Whether a possible deadlock is being reported, in which case further reports are
+    /// suppressed so that reporting cannot recurse.
+    static REPORTING: AtomicBool = AtomicBool::new(false);
+
+    /// The processor holding a lock and where it acquired the lock.
+    #[derive(Debug)]
+    pub struct Holder {
+        /// The processor holding the lock, or [`Holder::NONE`].
+        cpu: AtomicU32,
+        /// Where the lock was acquired, or null if it is not held.
+        location: AtomicPtr<Location<'static>>,
+    }
+
+    impl Holder {
+        /// The value of [`Holder::cpu`] when the lock is not held.
+        const NONE: u32 = u32::MAX;
+
+        /// Creates a [`Holder`] for a lock that is not held.
+        pub const fn new() -> Self {
+            Self {
+                cpu: AtomicU32::new(Self::NONE),
+                location: AtomicPtr::new(ptr::null_mut()),
+            }
+        }
+
+        /// Records that the current processor acquired the lock at `location`.
+        pub fn set(&self, location: &'static Location<'static>) {
+            self.location
+                .store(ptr::from_ref(location).cast_mut(), Ordering::Relaxed);
+            self.cpu.store(current_cpu(), Ordering::Relaxed);
+        }
+
+        /// Records that the lock was released.
+        pub fn clear(&self) {
+            self.cpu.store(Self::NONE, Ordering::Relaxed);
+            self.location.store(ptr::null_mut(), Ordering::Relaxed);
+        }
+
+        /// Returns the processor holding the lock and where it acquired the lock, as far as they
+        /// are known.
+        fn get(&self) -> (Option<u32>, Option<&'static Location<'static>>) {
+            let cpu = self.cpu.load(Ordering::Relaxed);
+            let location = self.location.load(Ordering::Relaxed);
+
+            // SAFETY:
+            // Only references to `'static` locations are ever stored.
+            let location = unsafe { location.as_ref() };
+            ((cpu != Self::NONE).then_some(cpu), location)
+        }
+
+        /// Checks that the current processor, about to wait for the lock at `waiter`, does not
+        /// hold it.
+        ///
+        /// # Panics
+        /// Panics if the current processor holds the lock, since it can never be released.
+        pub fn check_not_held(&self, waiter: &'static Location<'static>) {
+            if let (Some(cpu), acquired_at) = self.get() {
+                if cpu == current_cpu() {
+                    panic!(
+                        "{}",
+                        RecursiveAcquisition {
+                            acquired_at,
+                            waiter
+                        }
+                    );
+                }
+            }
+        }
+
+        /// Checks a waiter at `waiter` that has spun `spins` times, then counts another spin.
+        ///
+        /// # Panics
+        /// Panics if the current processor holds the lock, since it can never be released.
+        pub fn check_wait(&self, spins: &mut u64, waiter: &'static Location<'static>) {
+            if *spins == 0 {
+                self.check_not_held(waiter);
+            }
+
+            *spins += 1;
+            if *spins == DEADLOCK_SPIN_BUDGET {
+                let (holder_cpu, acquired_at) = self.get();
+                report(&LongWait {
+                    holder_cpu,
+                    acquired_at,
+                    spins: *spins,
+                });
+            }
+        }
+    }
+
+    /// Returns the identifier of the current processor.
+    #[cfg(not(test))]
+    fn current_cpu() -> u32 {
+        crate::arch::cpu::apic_id()
+    }
+
+    /// Returns an identifier unique to the current thread, which stands in for the processor in
+    /// host tests, where threads are not pinned to processors.
+    #[cfg(test)]
+    fn current_cpu() -> u32 {
+        static NEXT_ID: AtomicU32 = AtomicU32::new(0);
+        std::thread_local! {
+            static ID: u32 = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        }
+
+        ID.with(|id| *id)
+    }
+
+    /// Reports `wait` through [`emergency_write()`][crate::arch::logging::emergency_write],
+    /// unless a report is already in progress.
+    ///
+    /// The logger is never used, since the lock being waited for may be one of its own, or its
+    /// holder may be the context that is stuck.
+    fn report(wait: &LongWait) {
+        if REPORTING.swap(true, Ordering::Acquire) {
+            return;
+        }
+
+        #[cfg(feature = "logging")]
+        {
+            use core::fmt::Write;
+            let _ = write!(EmergencyWriter, "{wait}\r\n");
+        }
+        #[cfg(not(feature = "logging"))]
+        let _ = wait;
+
+        REPORTING.store(false, Ordering::Release);
+    }
+
+    /// A [`fmt::Write`] implementation writing through
+    /// [`emergency_write()`][crate::arch::logging::emergency_write], which takes no locks.
+    #[cfg(feature = "logging")]
+    struct EmergencyWriter;
+
+    #[cfg(feature = "logging")]
+    impl fmt::Write for EmergencyWriter {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            crate::arch::logging::emergency_write(s.as_bytes());
+            Ok(())
+        }
+    }
+
+    /// A lock acquired by a processor that already holds it.
+    #[derive(Clone, Copy, Debug)]
+    pub struct RecursiveAcquisition {
+        /// Where the lock was first acquired, if known.
+        pub acquired_at: Option<&'static Location<'static>>,
+        /// Where the lock was acquired again.
+        pub waiter: &'static Location<'static>,
+    }
+
+    impl fmt::Display for RecursiveAcquisition {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "recursive lock acquisition at {}", self.waiter)?;
+            match self.acquired_at {
+                Some(location) => write!(f, "; already held since {location}"),
+                None => f.write_str("; already held"),
+            }
+        }
+    }
+
+    /// A waiter that has spun for [`DEADLOCK_SPIN_BUDGET`] iterations.
+    #[derive(Clone, Copy, Debug)]
+    pub struct LongWait {
+        /// The processor holding the lock, if known.
+        pub holder_cpu: Option<u32>,
+        /// Where the holder acquired the lock, if known.
+        pub acquired_at: Option<&'static Location<'static>>,
+        /// The number of times the waiter has spun.
+        pub spins: u64,
+    }
+
+    impl fmt::Display for LongWait {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str("possible deadlock: lock held by ")?;
+            match self.holder_cpu {
+                Some(cpu) => write!(f, "CPU {cpu}")?,
+                None => f.write_str("an unknown CPU")?,
+            }
+            match self.acquired_at {
+                Some(location) => {
+                    write!(f, " acquired at {}:{}", location.file(), location.line())?
+                }
+                None => f.write_str(" acquired at an unknown location")?,
+            }
+            write!(f, ", waited {} spins", self.spins)
+        }
+    }
+}
+
+#[cfg(feature = "ktest")]
+crate::kernel_test! {
+    fn spinlock_excludes_second_acquisition() {
+        let lock = Spinlock::new(0u32);
+
+        let mut guard = lock.lock();
+        *guard += 1;
+        assert!(lock.try_lock().is_err());
+        drop(guard);
+
+        let guard = lock.try_lock().expect("lock was not released");
+        assert_eq!(*guard, 1);
+    }
+
+    fn mapped_spinlock_guard_holds_lock() {
+        let lock = Spinlock::new((0u32, 0u32));
+
+        let mut second = lock.lock().map(|pair| &mut pair.1);
+        *second = 7;
+        assert!(lock.try_lock().is_err());
+        drop(second);
+
+        assert_eq!(*lock.lock(), (0, 7));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        thread,
+        time::{Duration, Instant},
+        vec::Vec,
+    };
+
+    use super::*;
+
+    #[test]
+    fn ticket_spinlock_excludes_second_acquisition() {
+        let lock = TicketSpinlock::new(0u32);
+        assert!(!lock.is_locked());
+
+        let mut guard = lock.lock();
+        *guard += 1;
+        assert!(lock.is_locked());
+        assert!(lock.try_lock().is_err());
+        TicketSpinlock::unlock(guard);
+
+        let guard = lock.try_lock().expect("lock was not released");
+        assert_eq!(*guard, 1);
+        drop(guard);
+        assert!(!lock.is_locked());
+        assert_eq!(lock.into_inner(), 1);
+    }
+
+    #[test]
+    fn ticket_spinlock_counters_wrap() {
+        let lock = TicketSpinlock::new(());
+        lock.next.store(u32::MAX, Ordering::Relaxed);
+        lock.serving.store(u32::MAX, Ordering::Relaxed);
+
+        let guard = lock.lock();
+        assert_eq!(lock.next.load(Ordering::Relaxed), 0);
+        assert!(lock.try_lock().is_err());
+        drop(guard);
+        assert_eq!(lock.serving.load(Ordering::Relaxed), 0);
+
+        let guard = lock
+            .try_lock()
+            .expect("lock was not released across the wrap");
+        drop(guard);
+        assert!(!lock.is_locked());
+    }
+
+    #[test]
+    fn ticket_spinlock_serves_waiters_in_order() {
+        const WAITERS: usize = 8;
+
+        let lock = Arc::new(TicketSpinlock::new(Vec::new()));
+        let guard = lock.lock();
+
+        // Start the waiters one at a time, each only once the previous one holds its ticket, so
+        // that the tickets are taken in the order of the waiters' indices.
+        let waiters = (0..WAITERS)
+            .map(|index| {
+                let waiter = {
+                    let lock = Arc::clone(&lock);
+                    thread::spawn(move || lock.lock().push(index))
+                };
+                while lock.next.load(Ordering::Relaxed) as usize != index + 2 {
+                    thread::yield_now();
+                }
+
+                waiter
+            })
+            .collect::<Vec<_>>();
+
+        drop(guard);
+        for waiter in waiters {
+            waiter.join().unwrap();
+        }
+
+        assert_eq!(*lock.lock(), (0..WAITERS).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn ticket_spinlock_takes_turns_under_full_contention() {
+        const THREADS: usize = 4;
+        const ROUNDS: usize = 50;
+
+        let lock = Arc::new(TicketSpinlock::new(Vec::new()));
+        let running = Arc::new(AtomicUsize::new(THREADS));
+        // The number of contexts waiting for the lock, excluding its holder.
+        let waiting = |lock: &TicketSpinlock<_>| {
+            lock.next
+                .load(Ordering::Relaxed)
+                .wrapping_sub(lock.serving.load(Ordering::Relaxed))
+                .wrapping_sub(1) as usize
+        };
+
+        let threads = (0..THREADS)
+            .map(|index| {
+                let lock = Arc::clone(&lock);
+                let running = Arc::clone(&running);
+                thread::spawn(move || {
+                    for _ in 0..ROUNDS {
+                        let mut guard = lock.lock();
+                        guard.push(index);
+
+                        // Hold the lock until every other running thread waits for it, so that
+                        // each release hands the lock over under full contention.
+                        while waiting(&lock) + 1 < running.load(Ordering::Relaxed) {
+                            thread::yield_now();
+                        }
+                    }
+                    running.fetch_sub(1, Ordering::Relaxed);
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        // Every waiter is served before a releasing thread gets the lock back, so the threads
+        // take turns in the same order throughout.
+        let order = lock.lock().clone();
+        assert_eq!(order.len(), THREADS * ROUNDS);
+        let (first_round, _) = order.split_at(THREADS);
+        for round in order.chunks(THREADS) {
+            assert_eq!(round, first_round, "acquisitions: {order:?}");
+        }
+    }
+
+    #[test]
+    fn ticket_spinlock_loses_no_updates_under_contention() {
+        const THREADS: usize = 4;
+        const ROUNDS: usize = 10_000;
+
+        let lock = Arc::new(TicketSpinlock::new(0usize));
+        let inside = Arc::new(AtomicUsize::new(0));
+        let threads = (0..THREADS)
+            .map(|_| {
+                let lock = Arc::clone(&lock);
+                let inside = Arc::clone(&inside);
+                thread::spawn(move || {
+                    for _ in 0..ROUNDS {
+                        let mut guard = lock.lock();
+                        assert_eq!(inside.fetch_add(1, Ordering::Relaxed), 0);
+                        *guard += 1;
+                        inside.fetch_sub(1, Ordering::Relaxed);
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        assert_eq!(*lock.lock(), THREADS * ROUNDS);
+        assert!(!lock.is_locked());
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_its_bound() {
+        let mut backoff = Backoff::new();
+        let mut spins = Vec::new();
+        for _ in 0..9 {
+            spins.push(backoff.spins);
+            backoff.spin();
+        }
+
+        assert_eq!(spins, [1, 2, 4, 8, 16, 32, 64, 64, 64]);
+        assert_eq!(BACKOFF_INITIAL_SPINS, spins[0]);
+        assert_eq!(BACKOFF_MAX_SPINS, spins[8]);
+    }
+
+    #[test]
+    fn raw_spinlock_wakes_waiters_that_found_it_held() {
+        const WAITERS: usize = 4;
+
+        let lock = Arc::new(RawSpinlock::new());
+        let arrived = Arc::new(AtomicUsize::new(0));
+        let acquired = Arc::new(AtomicUsize::new(0));
+        lock.lock();
+
+        let waiters = (0..WAITERS)
+            .map(|_| {
+                let lock = Arc::clone(&lock);
+                let arrived = Arc::clone(&arrived);
+                let acquired = Arc::clone(&acquired);
+                thread::spawn(move || {
+                    arrived.fetch_add(1, Ordering::Relaxed);
+                    lock.lock();
+                    acquired.fetch_add(1, Ordering::Relaxed);
+                    lock.unlock();
+                })
+            })
+            .collect::<Vec<_>>();
+
+        // Give every waiter time to find the lock held and start waiting for it.
+        while arrived.load(Ordering::Relaxed) != WAITERS {
+            thread::yield_now();
+        }
+        thread::sleep(Duration::from_millis(10));
+        assert_eq!(acquired.load(Ordering::Relaxed), 0);
+
+        lock.unlock();
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while acquired.load(Ordering::Relaxed) != WAITERS {
+            assert!(
+                Instant::now() < deadline,
+                "a waiter missed the release of the lock"
+            );
+            thread::yield_now();
+        }
+
+        for waiter in waiters {
+            waiter.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn spinlock_loses_no_updates_under_contention() {
+        const THREADS: usize = 4;
+        const ROUNDS: usize = 10_000;
+
+        let lock = Arc::new(Spinlock::new(0usize));
+        let inside = Arc::new(AtomicUsize::new(0));
+        let threads = (0..THREADS)
+            .map(|index| {
+                let lock = Arc::clone(&lock);
+                let inside = Arc::clone(&inside);
+                thread::spawn(move || {
+                    for round in 0..ROUNDS {
+                        // Mix in attempts that may fail, so that waiters also race against
+                        // acquisitions that never spin.
+                        let mut guard = if (index + round) % 3 == 0 {
+                            match lock.try_lock() {
+                                Ok(guard) => guard,
+                                Err(_) => lock.lock(),
+                            }
+                        } else {
+                            lock.lock()
+                        };
+
+                        assert_eq!(inside.fetch_add(1, Ordering::Relaxed), 0);
+                        *guard += 1;
+                        inside.fetch_sub(1, Ordering::Relaxed);
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        assert_eq!(*lock.lock(), THREADS * ROUNDS);
+        assert!(lock.try_lock().is_ok());
+    }
+
+    /// A lock whose acquisition and release can be measured by [`spinlock_benchmark()`].
+    trait BenchmarkLock: Default + Send + Sync + 'static {
+        /// The name of the lock in the benchmark's report.
+        const NAME: &'static str;
+
+        /// Acquires the lock.
+        fn acquire(&self);
+
+        /// Releases the lock.
+        fn release(&self);
+    }
+
+    impl BenchmarkLock for RawSpinlock {
+        const NAME: &'static str = "test-and-test-and-set with backoff";
+
+        fn acquire(&self) {
+            self.lock();
+        }
+
+        fn release(&self) {
+            self.unlock();
+        }
+    }
+
+    impl BenchmarkLock for TicketSpinlock<()> {
+        const NAME: &'static str = "ticket";
+
+        fn acquire(&self) {
+            core::mem::forget(self.lock());
+        }
+
+        fn release(&self) {
+            drop(TicketSpinlockGuard { lock: self });
+        }
+    }
+
+    /// A lock that attempts the exchange on every iteration, as [`RawSpinlock::lock`] did
+    /// before it read the lock while waiting.
+    ///
+    /// The original loop never re-read the lock once it found it held, so it could not be
+    /// measured at all. This one re-reads it from every failed exchange instead.
+    #[derive(Default)]
+    struct CompareExchangeLock(AtomicBool);
+
+    impl BenchmarkLock for CompareExchangeLock {
+        const NAME: &'static str = "compare-exchange loop";
+
+        fn acquire(&self) {
+            while self
+                .0
+                .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+                .is_err()
+            {
+                relax();
+            }
+        }
+
+        fn release(&self) {
+            self.0.store(false, Ordering::Release);
+        }
+    }
+
+    /// A plain test-and-set lock, which swaps the lock on every iteration.
+    #[derive(Default)]
+    struct TestAndSetLock(AtomicBool);
+
+    impl BenchmarkLock for TestAndSetLock {
+        const NAME: &'static str = "test-and-set";
+
+        fn acquire(&self) {
+            while self.0.swap(true, Ordering::Acquire) {
+                relax();
+            }
+        }
+
+        fn release(&self) {
+            self.0.store(false, Ordering::Release);
+        }
+    }
+
+    /// Runs `threads` threads that repeatedly acquire and release an `L` for `duration`,
+    /// returning the number of acquisitions made by each thread.
+    fn run_benchmark<L: BenchmarkLock>(threads: usize, duration: Duration) -> Vec<usize> {
+        let lock = Arc::new(L::default());
+        let stop = Arc::new(AtomicBool::new(false));
+        let threads = (0..threads)
+            .map(|_| {
+                let lock = Arc::clone(&lock);
+                let stop = Arc::clone(&stop);
+                thread::spawn(move || {
+                    let mut acquisitions = 0;
+                    while !stop.load(Ordering::Relaxed) {
+                        lock.acquire();
+                        acquisitions += 1;
+                        core::hint::black_box(&acquisitions);
+                        lock.release();
+                    }
+
+                    acquisitions
+                })
+            })
+            .collect::<Vec<_>>();
+
+        thread::sleep(duration);
+        stop.store(true, Ordering::Relaxed);
+        threads
+            .into_iter()
+            .map(|thread| thread.join().unwrap())
+            .collect()
+    }
+
+    /// Reports the throughput and fairness of `L` with `threads` contending threads.
+    fn report_benchmark<L: BenchmarkLock>(threads: usize, duration: Duration) {
+        let acquisitions = run_benchmark::<L>(threads, duration);
+        let total = acquisitions.iter().sum::<usize>();
+        let fewest = acquisitions.iter().copied().min().unwrap_or(0);
+        let most = acquisitions.iter().copied().max().unwrap_or(0);
+
+        std::println!(
+            "{:<36} {threads:>2} threads {:>12.0} acquisitions/s, fewest/most per thread {:.3}",
+            L::NAME,
+            total as f64 / duration.as_secs_f64(),
+            fewest as f64 / most.max(1) as f64,
+        );
+    }
+
+    /// Compares the throughput and fairness of [`RawSpinlock`] and [`TicketSpinlock`] against the
+    /// loops the former replaced.
+    ///
+    /// Run with `cargo test spinlock_benchmark -- --ignored --nocapture`. Waiters yield instead
+    /// of pausing on the host, so the numbers compare how the locks share the lock's cache line
+    /// rather than the exact timing of the kernel's wait loops.
+    #[test]
+    #[ignore = "benchmark"]
+    fn spinlock_benchmark() {
+        let duration = Duration::from_millis(500);
+        for threads in [1, 2, 4, 8] {
+            report_benchmark::<RawSpinlock>(threads, duration);
+            report_benchmark::<TicketSpinlock<()>>(threads, duration);
+            report_benchmark::<CompareExchangeLock>(threads, duration);
+            report_benchmark::<TestAndSetLock>(threads, duration);
+        }
+    }
+
+    #[cfg(feature = "debug-locks")]
+    #[test]
+    fn recursive_acquisition_panics_with_both_locations() {
+        use std::{panic, string::String};
+
+        let lock = Spinlock::new(());
+        let guard = lock.lock();
+
+        let payload = panic::catch_unwind(panic::AssertUnwindSafe(|| drop(lock.lock())))
+            .expect_err("recursive acquisition was not detected");
+        let message = payload
+            .downcast_ref::<String>()
+            .expect("panic message was not formatted");
+        assert!(message.starts_with(concat!("recursive lock acquisition at ", file!(), ":")));
+        assert!(message.contains(concat!("; already held since ", file!(), ":")));
+
+        drop(guard);
+        drop(lock.try_lock().expect("lock was not released"));
+    }
+
+    #[cfg(feature = "debug-locks")]
+    #[test]
+    fn recursive_ticket_acquisition_panics_without_taking_a_ticket() {
+        use std::{panic, string::String};
+
+        let lock = TicketSpinlock::new(());
+        let guard = lock.lock();
+
+        let payload = panic::catch_unwind(panic::AssertUnwindSafe(|| drop(lock.lock())))
+            .expect_err("recursive acquisition was not detected");
+        let message = payload
+            .downcast_ref::<String>()
+            .expect("panic message was not formatted");
+        assert!(message.starts_with(concat!("recursive lock acquisition at ", file!(), ":")));
+        assert!(message.contains(concat!("; already held since ", file!(), ":")));
+
+        // The abandoned acquisition must not have queued behind the holder.
+        drop(guard);
+        assert!(!lock.is_locked());
+        let guard = lock.try_lock().expect("lock was not released");
+
+        // Locks acquired without waiting are recorded too.
+        panic::catch_unwind(panic::AssertUnwindSafe(|| drop(lock.lock())))
+            .expect_err("recursive acquisition after try_lock was not detected");
+        drop(guard);
+        drop(lock.lock());
+    }
+
+    #[cfg(feature = "debug-locks")]
+    #[test]
+    fn recursive_acquisition_display() {
+        use core::panic::Location;
+
+        let acquired_at = Location::caller();
+        let waiter = Location::caller();
+        let recursive = debug::RecursiveAcquisition {
+            acquired_at: Some(acquired_at),
+            waiter,
+        };
+        assert_eq!(
+            recursive.to_string(),
+            format!("recursive lock acquisition at {waiter}; already held since {acquired_at}")
+        );
+
+        let recursive = debug::RecursiveAcquisition {
+            acquired_at: None,
+            waiter,
+        };
+        assert_eq!(
+            recursive.to_string(),
+            format!("recursive lock acquisition at {waiter}; already held")
+        );
+    }
+
+    #[cfg(feature = "debug-locks")]
+    #[test]
+    fn waiting_on_another_holder_does_not_panic() {
+        use core::panic::Location;
+
+        let holder = debug::Holder::new();
+        thread::scope(|scope| {
+            scope.spawn(|| holder.set(Location::caller()));
+        });
+
+        let mut spins = 0;
+        for _ in 0..3 {
+            holder.check_wait(&mut spins, Location::caller());
+        }
+        assert_eq!(spins, 3);
+
+        holder.clear();
+        holder.set(Location::caller());
+        holder.clear();
+        let mut spins = 0;
+        holder.check_wait(&mut spins, Location::caller());
+        assert_eq!(spins, 1);
+    }
+
+    #[cfg(feature = "debug-locks")]
+    #[test]
+    fn long_wait_display() {
+        use core::panic::Location;
+
+        let acquired_at = Location::caller();
+        let wait = debug::LongWait {
+            holder_cpu: Some(3),
+            acquired_at: Some(acquired_at),
+            spins: DEADLOCK_SPIN_BUDGET,
+        };
+        assert_eq!(
+            wait.to_string(),
+            format!(
+                "possible deadlock: lock held by CPU 3 acquired at {}:{}, waited 100000000 spins",
+                file!(),
+                acquired_at.line()
+            )
+        );
+
+        let wait = debug::LongWait {
+            holder_cpu: None,
+            acquired_at: None,
+            spins: 7,
+        };
+        assert_eq!(
+            wait.to_string(),
+            "possible deadlock: lock held by an unknown CPU acquired at an unknown location, \
+            waited 7 spins"
+        );
+    }
+
+    std::thread_local! {
+        /// Whether interrupts are enabled on the pretend processor of [`MockInterrupts`].
+        static INTERRUPTS_ENABLED: core::cell::Cell<bool> = const { core::cell::Cell::new(true) };
+    }
+
+    /// An [`InterruptControl`] whose interrupt flag is a per-thread variable.
+    struct MockInterrupts;
+
+    impl InterruptControl for MockInterrupts {
+        fn interrupts_enabled() -> bool {
+            INTERRUPTS_ENABLED.get()
+        }
+
+        fn disable_interrupts() {
+            INTERRUPTS_ENABLED.set(false);
+        }
+
+        unsafe fn enable_interrupts() {
+            INTERRUPTS_ENABLED.set(true);
+        }
+    }
+
+    #[test]
+    fn mapped_guard_releases_the_lock_when_dropped() {
+        let lock = Spinlock::new((0u32, [0u8; 4]));
+
+        let mut bytes = lock.lock().map(|value| &mut value.1);
+        assert!(lock.try_lock().is_err(), "mapping released the lock");
+        bytes[2] = 7;
+
+        let mut middle = bytes.map(|bytes| &mut bytes[1..3]);
+        assert!(lock.try_lock().is_err(), "mapping again released the lock");
+        assert_eq!(*middle, [0, 7]);
+        middle[0] = 5;
+        drop(middle);
+
+        let guard = lock
+            .try_lock()
+            .expect("dropping the mapped guard kept the lock");
+        assert_eq!(*guard, (0, [0, 5, 7, 0]));
+    }
+
+    #[test]
+    fn get_mut_and_into_inner_bypass_the_lock() {
+        let mut lock = Spinlock::new(Vec::new());
+        lock.get_mut().push(1);
+        lock.lock().push(2);
+        lock.get_mut().push(3);
+        assert_eq!(lock.into_inner(), [1, 2, 3]);
+
+        let mut lock = TicketSpinlock::new(1);
+        *lock.get_mut() += 1;
+        assert_eq!(lock.into_inner(), 2);
+    }
+
+    #[test]
+    fn mapped_irq_guard_restores_interrupts_when_dropped() {
+        let lock = IrqSpinlock::<_, MockInterrupts>::new([1u32, 2, 3]);
+        assert!(MockInterrupts::interrupts_enabled());
+
+        let guard = lock.lock();
+        assert!(!MockInterrupts::interrupts_enabled());
+        let mut last = guard.map(|values| &mut values[2]).map(|value| value);
+        assert!(!MockInterrupts::interrupts_enabled());
+        assert!(lock.try_lock().is_err());
+        assert!(!MockInterrupts::interrupts_enabled());
+        *last = 4;
+        drop(last);
+
+        assert!(MockInterrupts::interrupts_enabled());
+        assert_eq!(*lock.lock(), [1, 2, 4]);
+        assert!(MockInterrupts::interrupts_enabled());
+    }
+
+    #[test]
+    fn nested_irq_guards_restore_interrupts_in_order() {
+        let outer = IrqSpinlock::<_, MockInterrupts>::new(0);
+        let inner = IrqSpinlock::<_, MockInterrupts>::new(0);
+
+        let outer_guard = outer.lock();
+        let inner_guard = inner.lock().map(|value| value);
+        drop(inner_guard);
+        assert!(!MockInterrupts::interrupts_enabled());
+        drop(outer_guard);
+        assert!(MockInterrupts::interrupts_enabled());
+    }
+}
@@ -0,0 +1,374 @@
+//! The format of the embedded symbol table.
+//!
+//! This file is also compiled into `xtask`, which encodes the table, so it must only depend on
+//! `core`. All integers are little-endian.
+//!
+//! | Offset | Size             | Contents                                                  |
+//! |--------|------------------|-----------------------------------------------------------|
+//! | 0      | 4                | [`MAGIC`]                                                 |
+//! | 4      | 2                | [`VERSION`]                                               |
+//! | 6      | 1                | The length of the build ID                                |
+//! | 7      | 1                | Reserved, zero                                            |
+//! | 8      | 4                | The number of symbols                                     |
+//! | 12     | 4                | The size of the name area, in bytes                       |
+//! | 16     | 8                | The link address every symbol offset is relative to       |
+//! | 24     | 8                | The link address of the table itself                      |
+//! | 32     | 32               | The build ID of the image, padded with zeros              |
+//! | 64     | 12 per symbol    | The symbols, sorted by offset                             |
+//! | ...    | name area size   | Each name as a 16-bit length followed by its UTF-8 bytes  |
+//!
+//! Each symbol is its 32-bit offset from the base, its 32-bit size, and the 32-bit offset of its
+//! name in the name area.
+
+use core::{fmt, str};
+
+/// The bytes that begin an encoded symbol table.
+pub const MAGIC: [u8; 4] = *b"KSYM";
+/// The version of the format described by this module.
+pub const VERSION: u16 = 1;
+/// The size, in bytes, of the header.
+pub const HEADER_SIZE: usize = 64;
+/// The size, in bytes, of each symbol.
+pub const ENTRY_SIZE: usize = 12;
+/// The maximum length, in bytes, of a build ID.
+pub const MAX_BUILD_ID_LEN: usize = 32;
+/// The maximum length, in bytes, of a name. Longer names are truncated.
+pub const MAX_NAME_LEN: usize = u16::MAX as usize;
+
+/// A symbol to be encoded by [`encode()`].
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub struct SymbolInput<'a> {
+    /// The link address of the symbol.
+    pub address: u64,
+    /// The size of the symbol, in bytes, or zero if unknown.
+    pub size: u32,
+    /// The name of the symbol.
+    pub name: &'a str,
+}
+
+/// Returns the number of bytes [`encode()`] needs for `symbols`.
+pub fn encoded_size(symbols: &[SymbolInput]) -> usize {
+    symbols.iter().fold(HEADER_SIZE, |size, symbol| {
+        size + ENTRY_SIZE + 2 + truncate_name(symbol.name).len()
+    })
+}
+
+/// Encodes `symbols`, which must be sorted by address, into the start of `out`, returning the
+/// number of bytes written.
+///
+/// `table_address` is the link address at which the table will be loaded and `build_id` is the
+/// build ID of the image it describes.
+///
+/// # Errors
+/// - [`EncodeError::BuildIdTooLong`] if `build_id` is longer than [`MAX_BUILD_ID_LEN`] bytes.
+/// - [`EncodeError::Unsorted`] if `symbols` are not sorted by address.
+/// - [`EncodeError::OutOfRange`] if the symbols span more than 4 GiB, or there are too many.
+/// - [`EncodeError::TooSmall`] if `out` cannot hold the table.
+pub fn encode(
+    out: &mut [u8],
+    table_address: u64,
+    build_id: &[u8],
+    symbols: &[SymbolInput],
+) -> Result<usize, EncodeError> {
+    if build_id.len() > MAX_BUILD_ID_LEN {
+        return Err(EncodeError::BuildIdTooLong);
+    }
+    if symbols
+        .windows(2)
+        .any(|pair| pair[0].address > pair[1].address)
+    {
+        return Err(EncodeError::Unsorted);
+    }
+
+    let required = encoded_size(symbols);
+    if out.len() < required {
+        return Err(EncodeError::TooSmall { required });
+    }
+
+    let base = symbols.first().map_or(0, |symbol| symbol.address);
+    let count = u32::try_from(symbols.len()).map_err(|_| EncodeError::OutOfRange)?;
+    let entries_end = HEADER_SIZE + symbols.len() * ENTRY_SIZE;
+    let names_len = u32::try_from(required - entries_end).map_err(|_| EncodeError::OutOfRange)?;
+
+    let mut name_offset = 0usize;
+    for (index, symbol) in symbols.iter().enumerate() {
+        let offset = u32::try_from(symbol.address - base).map_err(|_| EncodeError::OutOfRange)?;
+        let name = truncate_name(symbol.name);
+
+        let entry = HEADER_SIZE + index * ENTRY_SIZE;
+        out[entry..entry + 4].copy_from_slice(&offset.to_le_bytes());
+        out[entry + 4..entry + 8].copy_from_slice(&symbol.size.to_le_bytes());
+        out[entry + 8..entry + 12].copy_from_slice(&(name_offset as u32).to_le_bytes());
+
+        let name_start = entries_end + name_offset;
+        out[name_start..name_start + 2].copy_from_slice(&(name.len() as u16).to_le_bytes());
+        out[name_start + 2..name_start + 2 + name.len()].copy_from_slice(name.as_bytes());
+        name_offset += 2 + name.len();
+    }
+
+    out[..HEADER_SIZE].fill(0);
+    out[0..4].copy_from_slice(&MAGIC);
+    out[4..6].copy_from_slice(&VERSION.to_le_bytes());
+    out[6] = build_id.len() as u8;
+    out[8..12].copy_from_slice(&count.to_le_bytes());
+    out[12..16].copy_from_slice(&names_len.to_le_bytes());
+    out[16..24].copy_from_slice(&base.to_le_bytes());
+    out[24..32].copy_from_slice(&table_address.to_le_bytes());
+    out[32..32 + build_id.len()].copy_from_slice(build_id);
+
+    Ok(required)
+}
+
+/// Returns the longest prefix of `name` that fits in [`MAX_NAME_LEN`] bytes and ends on a
+/// character boundary.
+fn truncate_name(name: &str) -> &str {
+    let mut len = name.len().min(MAX_NAME_LEN);
+    while !name.is_char_boundary(len) {
+        len -= 1;
+    }
+
+    &name[..len]
+}
+
+/// Various errors that can occur while encoding a symbol table.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum EncodeError {
+    /// The build ID is longer than [`MAX_BUILD_ID_LEN`] bytes.
+    BuildIdTooLong,
+    /// The symbols are not sorted by address.
+    Unsorted,
+    /// The symbols span more than 4 GiB, or there are too many of them.
+    OutOfRange,
+    /// The output buffer is too small.
+    TooSmall {
+        /// The number of bytes required.
+        required: usize,
+    },
+}
+
+impl fmt::Display for EncodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BuildIdTooLong => {
+                write!(f, "build ID is longer than {MAX_BUILD_ID_LEN} bytes")
+            }
+            Self::Unsorted => f.write_str("symbols are not sorted by address"),
+            Self::OutOfRange => f.write_str("symbols do not fit in 32-bit offsets"),
+            Self::TooSmall { required } => {
+                write!(f, "symbol table needs {required} bytes")
+            }
+        }
+    }
+}
+
+/// A decoded view of an encoded symbol table.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub struct SymbolTable<'a> {
+    /// The link address every symbol offset is relative to.
+    base: u64,
+    /// The link address of the table itself.
+    table_address: u64,
+    /// The build ID of the image the table describes.
+    build_id: &'a [u8],
+    /// The encoded symbols.
+    entries: &'a [u8],
+    /// The encoded names.
+    names: &'a [u8],
+}
+
+impl<'a> SymbolTable<'a> {
+    /// Parses the symbol table at the start of `bytes`.
+    ///
+    /// # Errors
+    /// - [`DecodeError::Missing`] if `bytes` are all zero, as they are before a table is
+    ///   embedded.
+    /// - [`DecodeError::BadMagic`] if `bytes` do not begin with [`MAGIC`].
+    /// - [`DecodeError::UnsupportedVersion`] if the table is not of version [`VERSION`].
+    /// - [`DecodeError::Truncated`] if the table does not fit in `bytes`.
+    /// - [`DecodeError::Unsorted`] if the symbols are not sorted by offset.
+    pub fn parse(bytes: &'a [u8]) -> Result<Self, DecodeError> {
+        let header = bytes.get(..HEADER_SIZE).ok_or(DecodeError::Truncated)?;
+        if header[..4] != MAGIC {
+            return if header.iter().all(|&byte| byte == 0) {
+                Err(DecodeError::Missing)
+            } else {
+                Err(DecodeError::BadMagic)
+            };
+        }
+
+        let version = u16::from_le_bytes([header[4], header[5]]);
+        if version != VERSION {
+            return Err(DecodeError::UnsupportedVersion(version));
+        }
+
+        let build_id_len = usize::from(header[6]);
+        let count = read_u32(header, 8) as usize;
+        let names_len = read_u32(header, 12) as usize;
+        if build_id_len > MAX_BUILD_ID_LEN {
+            return Err(DecodeError::Truncated);
+        }
+
+        let entries_end = count
+            .checked_mul(ENTRY_SIZE)
+            .and_then(|size| size.checked_add(HEADER_SIZE))
+            .ok_or(DecodeError::Truncated)?;
+        let names_end = entries_end
+            .checked_add(names_len)
+            .ok_or(DecodeError::Truncated)?;
+        let entries = bytes
+            .get(HEADER_SIZE..entries_end)
+            .ok_or(DecodeError::Truncated)?;
+        let names = bytes
+            .get(entries_end..names_end)
+            .ok_or(DecodeError::Truncated)?;
+
+        let table = Self {
+            base: read_u64(header, 16),
+            table_address: read_u64(header, 24),
+            build_id: &header[32..32 + build_id_len],
+            entries,
+            names,
+        };
+        if (1..table.len()).any(|index| table.offset(index - 1) > table.offset(index)) {
+            return Err(DecodeError::Unsorted);
+        }
+
+        Ok(table)
+    }
+
+    /// Returns the link address of the table itself, against which the runtime address of the
+    /// table gives the distance the image was relocated by.
+    pub const fn table_address(&self) -> u64 {
+        self.table_address
+    }
+
+    /// Returns the build ID of the image the table describes.
+    pub const fn build_id(&self) -> &'a [u8] {
+        self.build_id
+    }
+
+    /// Returns the number of symbols in the table.
+    pub const fn len(&self) -> usize {
+        self.entries.len() / ENTRY_SIZE
+    }
+
+    /// Returns the number of bytes the encoded table occupies.
+    pub const fn encoded_len(&self) -> usize {
+        HEADER_SIZE + self.entries.len() + self.names.len()
+    }
+
+    /// Returns `true` if the table holds no symbols.
+    pub const fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the symbol at `index`, or [`None`] if there is none or its name is malformed.
+    pub fn get(&self, index: usize) -> Option<Symbol<'a>> {
+        if index >= self.len() {
+            return None;
+        }
+
+        let entry = index * ENTRY_SIZE;
+        let name_offset = read_u32(self.entries, entry + 8) as usize;
+        let name_len = u16::from_le_bytes(
+            *self
+                .names
+                .get(name_offset..name_offset.checked_add(2)?)?
+                .first_chunk()?,
+        );
+        let name_start = name_offset + 2;
+        let name = self
+            .names
+            .get(name_start..name_start + usize::from(name_len))?;
+
+        Some(Symbol {
+            address: self.base + u64::from(self.offset(index)),
+            size: read_u32(self.entries, entry + 4),
+            name: str::from_utf8(name).ok()?,
+        })
+    }
+
+    /// Returns the symbol containing the link address `address`, or [`None`] if no symbol does.
+    ///
+    /// Symbols of unknown size are taken to extend up to the next symbol.
+    pub fn lookup(&self, address: u64) -> Option<Symbol<'a>> {
+        let offset = u32::try_from(address.checked_sub(self.base)?).ok()?;
+
+        // The number of symbols starting at or below `offset`.
+        let (mut low, mut high) = (0, self.len());
+        while low < high {
+            let middle = low + (high - low) / 2;
+            if self.offset(middle) <= offset {
+                low = middle + 1;
+            } else {
+                high = middle;
+            }
+        }
+
+        let symbol = self.get(low.checked_sub(1)?)?;
+        if symbol.size != 0 && address - symbol.address >= u64::from(symbol.size) {
+            return None;
+        }
+
+        Some(symbol)
+    }
+
+    /// Returns the offset from the base of the symbol at `index`, which must be in bounds.
+    fn offset(&self, index: usize) -> u32 {
+        read_u32(self.entries, index * ENTRY_SIZE)
+    }
+}
+
+/// Returns the little-endian `u32` at `offset` in `bytes`, which must be in bounds.
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    let mut value = [0; 4];
+    value.copy_from_slice(&bytes[offset..offset + 4]);
+    u32::from_le_bytes(value)
+}
+
+/// Returns the little-endian `u64` at `offset` in `bytes`, which must be in bounds.
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    let mut value = [0; 8];
+    value.copy_from_slice(&bytes[offset..offset + 8]);
+    u64::from_le_bytes(value)
+}
+
+/// A symbol decoded from a [`SymbolTable`].
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub struct Symbol<'a> {
+    /// The link address of the symbol.
+    pub address: u64,
+    /// The size of the symbol, in bytes, or zero if unknown.
+    pub size: u32,
+    /// The name of the symbol.
+    pub name: &'a str,
+}
+
+/// Various errors that can occur while decoding a symbol table.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum DecodeError {
+    /// No table was embedded.
+    Missing,
+    /// The table does not begin with [`MAGIC`].
+    BadMagic,
+    /// The table is of an unsupported version.
+    UnsupportedVersion(u16),
+    /// The table extends past the end of its bytes.
+    Truncated,
+    /// The symbols are not sorted by offset.
+    Unsorted,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Missing => f.write_str("no symbol table was embedded"),
+            Self::BadMagic => f.write_str("symbol table has a bad magic number"),
+            Self::UnsupportedVersion(version) => {
+                write!(f, "symbol table has unsupported version {version}")
+            }
+            Self::Truncated => f.write_str("symbol table is truncated"),
+            Self::Unsorted => f.write_str("symbol table is not sorted"),
+        }
+    }
+}
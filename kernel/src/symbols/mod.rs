@@ -0,0 +1,175 @@
+//! Resolution of kernel addresses to function names through an embedded symbol table.
+//!
+//! The kernel reserves [`CAPACITY`] bytes in its `.symbols` section. Once the kernel has been
+//! linked, `xtask` extracts the function symbols from the ELF image and writes them into that
+//! section in the format described by [`format`]. The table records the link address of the
+//! section, so the distance the image was relocated by is the difference between that and the
+//! section's runtime address.
+//!
+//! A kernel built without `xtask` has an empty section, and a table whose build ID differs from
+//! the running image's is stale. Either way, [`resolve()`] finds nothing and backtraces fall back
+//! to raw addresses.
+
+pub mod format;
+
+use core::{fmt, ptr};
+
+use format::{DecodeError, SymbolTable};
+
+use crate::{arch::VirtualAddress, sync::Once};
+
+/// The number of bytes reserved for the symbol table.
+pub const CAPACITY: usize = 512 * 1024;
+
+/// The space in the `.symbols` section into which `xtask` writes the symbol table.
+#[used]
+#[link_section = ".symbols"]
+static RESERVED: [u8; CAPACITY] = [0; CAPACITY];
+
+/// The embedded symbol table and the distance the image was relocated by, or the reason it is
+/// unusable.
+static TABLE: Once<Result<(SymbolTable<'static>, u64), TableError>> = Once::new();
+
+/// Returns the name of the function containing `address` and the offset of `address` within it,
+/// or [`None`] if no usable symbol table is embedded or no function contains `address`.
+pub fn resolve(address: VirtualAddress) -> Option<(&'static str, usize)> {
+    let (table, slide) = table().ok()?;
+    let symbol = table.lookup((address.value() as u64).wrapping_sub(slide))?;
+    let offset = (address.value() as u64).wrapping_sub(symbol.address.wrapping_add(slide));
+
+    Some((symbol.name, offset as usize))
+}
+
+/// Returns the embedded symbol table and the distance the image was relocated by.
+///
+/// # Errors
+/// Returns [`TableError`] if no symbol table is embedded, it cannot be decoded, or it describes a
+/// different image.
+pub fn table() -> Result<(SymbolTable<'static>, u64), TableError> {
+    *TABLE.call_once(load)
+}
+
+/// Decodes the embedded symbol table and checks that it describes the running image.
+fn load() -> Result<(SymbolTable<'static>, u64), TableError> {
+    extern "C" {
+        #[link_name = "symbols_start"]
+        static SYMBOLS_START: core::ffi::c_void;
+        #[link_name = "symbols_end"]
+        static SYMBOLS_END: core::ffi::c_void;
+    }
+
+    let start = ptr::addr_of!(SYMBOLS_START).cast::<u8>();
+    let end = ptr::addr_of!(SYMBOLS_END).cast::<u8>();
+    // SAFETY:
+    // The linker script places the `.symbols` section between `symbols_start` and `symbols_end`,
+    // and it is never written at runtime.
+    let bytes = unsafe { core::slice::from_raw_parts(start, end as usize - start as usize) };
+
+    let table = SymbolTable::parse(bytes).map_err(TableError::Decode)?;
+    if crate::arch::backtrace::build_id() != Some(table.build_id()) {
+        return Err(TableError::Stale);
+    }
+
+    Ok((table, (start as u64).wrapping_sub(table.table_address())))
+}
+
+/// Various reasons the embedded symbol table cannot be used.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum TableError {
+    /// The table is missing or malformed.
+    Decode(DecodeError),
+    /// The table was built for a different image.
+    Stale,
+}
+
+impl fmt::Display for TableError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Decode(error) => fmt::Display::fmt(error, f),
+            Self::Stale => f.write_str("symbol table was built for a different image"),
+        }
+    }
+}
+
+#[cfg(feature = "ktest")]
+crate::kernel_test! {
+    fn symbol_table_round_trips() {
+        use format::{encode, encoded_size, Symbol, SymbolInput};
+
+        const BUILD_ID: &[u8] = &[0xAB; 20];
+        const SYMBOLS: &[SymbolInput] = &[
+            SymbolInput { address: 0x1000, size: 0x20, name: "kernel::kmain" },
+            SymbolInput { address: 0x1020, size: 0, name: "kernel::panic_handler" },
+            SymbolInput { address: 0x1080, size: 0x10, name: "<T as core::fmt::Display>::fmt" },
+            SymbolInput { address: 0x2000, size: 0x8, name: "" },
+        ];
+
+        let mut buffer = [0u8; 512];
+        let len = encode(&mut buffer, 0x400, BUILD_ID, SYMBOLS).unwrap();
+        assert_eq!(len, encoded_size(SYMBOLS));
+
+        let table = SymbolTable::parse(&buffer[..len]).unwrap();
+        assert_eq!(table.table_address(), 0x400);
+        assert_eq!(table.build_id(), BUILD_ID);
+        assert_eq!(table.len(), SYMBOLS.len());
+        for (index, input) in SYMBOLS.iter().enumerate() {
+            let symbol = table.get(index).unwrap();
+            assert_eq!(
+                (symbol.address, symbol.size, symbol.name),
+                (input.address, input.size, input.name)
+            );
+        }
+
+        let kmain = Symbol { address: 0x1000, size: 0x20, name: "kernel::kmain" };
+        assert_eq!(table.lookup(0xFFF), None);
+        assert_eq!(table.lookup(0x1000), Some(kmain));
+        assert_eq!(table.lookup(0x101F), Some(kmain));
+        // Symbols of unknown size extend to the next symbol.
+        assert_eq!(table.lookup(0x107F).map(|symbol| symbol.name), Some("kernel::panic_handler"));
+        assert_eq!(table.lookup(0x1090), None);
+        assert_eq!(table.lookup(0x2007).map(|symbol| symbol.name), Some(""));
+        assert_eq!(table.lookup(0x2008), None);
+    }
+
+    fn symbol_table_rejects_bad_input() {
+        use format::{encode, DecodeError, EncodeError, SymbolInput};
+
+        let symbols = [
+            SymbolInput { address: 0x2000, size: 0, name: "b" },
+            SymbolInput { address: 0x1000, size: 0, name: "a" },
+        ];
+        let mut buffer = [0u8; 256];
+        assert_eq!(encode(&mut buffer, 0, &[], &symbols), Err(EncodeError::Unsorted));
+        assert_eq!(
+            encode(&mut buffer[..70], 0, &[], &symbols[1..]),
+            Err(EncodeError::TooSmall { required: 79 })
+        );
+        assert_eq!(encode(&mut buffer, 0, &[0; 33], &[]), Err(EncodeError::BuildIdTooLong));
+        let far = [
+            SymbolInput { address: 0, size: 0, name: "a" },
+            SymbolInput { address: 1 << 32, size: 0, name: "b" },
+        ];
+        assert_eq!(encode(&mut buffer, 0, &[], &far), Err(EncodeError::OutOfRange));
+
+        assert_eq!(SymbolTable::parse(&[0; 128]), Err(DecodeError::Missing));
+        assert_eq!(SymbolTable::parse(&[0xFF; 128]), Err(DecodeError::BadMagic));
+        assert_eq!(SymbolTable::parse(&[0; 10]), Err(DecodeError::Truncated));
+
+        let len = encode(&mut buffer, 0, &[], &symbols[1..]).unwrap();
+        assert_eq!(SymbolTable::parse(&buffer[..len - 1]), Err(DecodeError::Truncated));
+        buffer[4] = 2;
+        assert_eq!(SymbolTable::parse(&buffer[..len]), Err(DecodeError::UnsupportedVersion(2)));
+    }
+
+    fn symbol_table_resolves_running_kernel() {
+        match table() {
+            Ok(_) => {
+                let address = VirtualAddress::new_canonical(crate::kmain as *const () as usize);
+                let (name, offset) = resolve(address).expect("kmain is not in the symbol table");
+                assert!(name.ends_with("kmain"), "kmain resolved to {name}");
+                assert_eq!(offset, 0);
+            }
+            Err(_error) => log::warn!("{_error}, skipping"),
+        }
+    }
+}
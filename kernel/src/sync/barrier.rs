@@ -0,0 +1,192 @@
+//! Spin-based barrier for a fixed number of participants.
+
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use crate::spinlock::relax;
+
+/// A barrier that releases its participants once all of them have arrived.
+///
+/// The barrier can be reused: a generation counter distinguishes each round, so a participant
+/// that arrives for the next round cannot be confused with one from the current round.
+///
+/// Everything a participant wrote before calling [`Barrier::wait()`] is visible to every other
+/// participant once its call returns. Arrivals are counted with acquire-release read-modify-write
+/// operations, so the last participant to arrive observes the writes of all the others, and it
+/// publishes them by advancing the generation with a release store that the waiting participants
+/// read with acquire loads.
+#[derive(Debug)]
+pub struct Barrier {
+    /// The number of participants.
+    count: u32,
+    /// The number of participants that have arrived in the current generation.
+    arrived: AtomicU32,
+    /// The current generation, advanced every time all participants have arrived.
+    generation: AtomicU32,
+}
+
+impl Barrier {
+    /// Creates a new [`Barrier`] for `count` participants.
+    ///
+    /// # Panics
+    /// Panics if `count` is zero.
+    pub const fn new(count: u32) -> Self {
+        assert!(count != 0, "a barrier needs at least one participant");
+
+        Self {
+            count,
+            arrived: AtomicU32::new(0),
+            generation: AtomicU32::new(0),
+        }
+    }
+
+    /// Returns the number of participants.
+    pub const fn count(&self) -> u32 {
+        self.count
+    }
+
+    /// Spins until all participants of the current generation have arrived.
+    ///
+    /// Exactly one participant per generation, the last to arrive, is told that it is the leader.
+    pub fn wait(&self) -> BarrierWaitResult {
+        // This must be read before arriving, since the generation can only advance once every
+        // participant, including this one, has arrived.
+        let generation = self.generation.load(Ordering::Acquire);
+
+        let arrived = self.arrived.fetch_add(1, Ordering::AcqRel) + 1;
+        debug_assert!(
+            arrived <= self.count,
+            "more than {} participants arrived at a barrier",
+            self.count
+        );
+
+        if arrived == self.count {
+            // Nobody can arrive for the next generation until the generation advances, so the
+            // count can be reset first.
+            self.arrived.store(0, Ordering::Relaxed);
+            self.generation
+                .store(generation.wrapping_add(1), Ordering::Release);
+
+            return BarrierWaitResult { is_leader: true };
+        }
+
+        while self.generation.load(Ordering::Acquire) == generation {
+            relax();
+        }
+
+        BarrierWaitResult { is_leader: false }
+    }
+}
+
+/// The result of [`Barrier::wait()`].
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub struct BarrierWaitResult {
+    /// Whether this participant was the last to arrive.
+    is_leader: bool,
+}
+
+impl BarrierWaitResult {
+    /// Returns `true` if this participant was the last to arrive in its generation.
+    pub const fn is_leader(&self) -> bool {
+        self.is_leader
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicU32, Ordering},
+            Arc,
+        },
+        thread,
+        vec::Vec,
+    };
+
+    use super::*;
+
+    #[test]
+    fn lone_participant_leads_every_generation() {
+        let barrier = Barrier::new(1);
+        for generation in 0..3 {
+            assert_eq!(barrier.generation.load(Ordering::Relaxed), generation);
+            assert!(barrier.wait().is_leader());
+        }
+
+        assert_eq!(barrier.arrived.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    #[should_panic = "a barrier needs at least one participant"]
+    fn zero_participants_are_rejected() {
+        Barrier::new(core::hint::black_box(0));
+    }
+
+    #[test]
+    fn barrier_is_reused_across_generations() {
+        const THREADS: u32 = 4;
+        const GENERATIONS: u32 = 200;
+
+        let barrier = Arc::new(Barrier::new(THREADS));
+        let progress = Arc::new([const { AtomicU32::new(0) }; THREADS as usize]);
+        let leaders = Arc::new([const { AtomicU32::new(0) }; GENERATIONS as usize]);
+        let threads = (0..THREADS as usize)
+            .map(|index| {
+                let barrier = Arc::clone(&barrier);
+                let progress = Arc::clone(&progress);
+                let leaders = Arc::clone(&leaders);
+                thread::spawn(move || {
+                    for generation in 0..GENERATIONS {
+                        progress[index].store(generation + 1, Ordering::Relaxed);
+
+                        if barrier.wait().is_leader() {
+                            leaders[generation as usize].fetch_add(1, Ordering::Relaxed);
+                        }
+
+                        // Every participant arrived for this generation before any left it, and
+                        // none can arrive for the generation after next before this one does.
+                        for other in progress.iter() {
+                            let other = other.load(Ordering::Relaxed);
+                            assert!(
+                                (generation + 1..=generation + 2).contains(&other),
+                                "a participant at {other} was seen in generation {generation}"
+                            );
+                        }
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        assert!(leaders
+            .iter()
+            .all(|leader| leader.load(Ordering::Relaxed) == 1));
+        assert_eq!(barrier.generation.load(Ordering::Relaxed), GENERATIONS);
+        assert_eq!(barrier.arrived.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn no_participant_leaves_before_all_arrive() {
+        const THREADS: usize = 4;
+
+        let barrier = Arc::new(Barrier::new(THREADS as u32));
+        let values = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let threads = (0..THREADS)
+            .map(|index| {
+                let barrier = Arc::clone(&barrier);
+                let values = Arc::clone(&values);
+                thread::spawn(move || {
+                    values.lock().unwrap().push(index);
+                    barrier.wait();
+                    values.lock().unwrap().len()
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for thread in threads {
+            assert_eq!(thread.join().unwrap(), THREADS);
+        }
+    }
+}
@@ -0,0 +1,7 @@
+//! Synchronization primitives beyond plain locks.
+
+mod barrier;
+mod once;
+
+pub use barrier::{Barrier, BarrierWaitResult};
+pub use once::{GlobalOnce, Lazy, Once};
@@ -0,0 +1,529 @@
+//! Write-once cells for kernel statics.
+//!
+//! A [`Once`] moves through three states: uninitialized, initializing, and initialized. The
+//! context that wins the race from uninitialized to initializing runs the initializer, while every
+//! other context spins until the value is published. Publishing uses a release store that pairs
+//! with the acquire load of every reader, so a reader that observes the initialized state also
+//! observes the fully written value.
+//!
+//! If an initializer unwinds, the [`Once`] is poisoned and every later access panics instead of
+//! spinning forever. The kernel aborts on panic, so this only matters for code that can unwind.
+
+use core::{
+    cell::UnsafeCell,
+    fmt,
+    mem::{self, MaybeUninit},
+    ops::Deref,
+    panic::Location,
+    sync::atomic::{AtomicU8, Ordering},
+};
+
+use crate::spinlock::relax;
+
+/// The value has not been initialized.
+const UNINITIALIZED: u8 = 0;
+/// The value is being initialized.
+const INITIALIZING: u8 = 1;
+/// The value has been initialized.
+const INITIALIZED: u8 = 2;
+/// The initializer panicked.
+const POISONED: u8 = 3;
+
+/// A cell that is written exactly once and can then be read from any context.
+pub struct Once<T> {
+    /// The state of the value.
+    state: AtomicU8,
+    /// The value, which is initialized once `state` is [`INITIALIZED`].
+    value: UnsafeCell<MaybeUninit<T>>,
+    /// Where the value was initialized, which is only written before `state` becomes
+    /// [`INITIALIZED`].
+    #[cfg(debug_assertions)]
+    initialized_at: UnsafeCell<Option<&'static Location<'static>>>,
+}
+
+// SAFETY:
+// The value is written by a single context before it is published, after which it is only shared
+// immutably, so `T` must be safe to share across threads as well as to send to the context that
+// drops it.
+unsafe impl<T: Send + Sync> Sync for Once<T> {}
+
+// SAFETY:
+// Sending a `Once<T>` sends the contained `T`.
+unsafe impl<T: Send> Send for Once<T> {}
+
+impl<T> Once<T> {
+    /// Creates a new, uninitialized [`Once`].
+    pub const fn new() -> Self {
+        Self {
+            state: AtomicU8::new(UNINITIALIZED),
+            value: UnsafeCell::new(MaybeUninit::uninit()),
+            #[cfg(debug_assertions)]
+            initialized_at: UnsafeCell::new(None),
+        }
+    }
+
+    /// Returns the value, or [`None`] if it has not been initialized yet.
+    pub fn get(&self) -> Option<&T> {
+        if self.state.load(Ordering::Acquire) != INITIALIZED {
+            return None;
+        }
+
+        // SAFETY:
+        // The acquire load observed the release store that published the value.
+        Some(unsafe { self.get_unchecked() })
+    }
+
+    /// Returns the value, initializing it with `f` if this is the first call.
+    ///
+    /// If another context is initializing the value, this spins until it is done.
+    ///
+    /// # Panics
+    /// Panics if an initializer panicked.
+    #[track_caller]
+    pub fn call_once(&self, f: impl FnOnce() -> T) -> &T {
+        if let Some(value) = self.get() {
+            return value;
+        }
+
+        if self.begin() {
+            self.finish(f)
+        } else {
+            self.wait()
+        }
+    }
+
+    /// Initializes the value with `value`, returning a reference to it.
+    ///
+    /// # Panics
+    /// Panics if the value has already been initialized. In debug builds, the message includes
+    /// where it was initialized.
+    #[track_caller]
+    pub fn init(&self, value: T) -> &T {
+        if !self.begin() {
+            self.already_initialized();
+        }
+
+        self.finish(|| value)
+    }
+
+    /// Returns the value, spinning until another context initializes it.
+    ///
+    /// # Panics
+    /// Panics if an initializer panicked.
+    pub fn wait(&self) -> &T {
+        loop {
+            match self.state.load(Ordering::Acquire) {
+                // SAFETY:
+                // The acquire load observed the release store that published the value.
+                INITIALIZED => return unsafe { self.get_unchecked() },
+                POISONED => panic!("Once poisoned by a panicking initializer"),
+                _ => relax(),
+            }
+        }
+    }
+
+    /// Returns `true` if the value has been initialized.
+    pub fn is_initialized(&self) -> bool {
+        self.state.load(Ordering::Acquire) == INITIALIZED
+    }
+
+    /// Claims the right to initialize the value, returning `false` if another context already
+    /// claimed it.
+    fn begin(&self) -> bool {
+        self.state
+            .compare_exchange(
+                UNINITIALIZED,
+                INITIALIZING,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            )
+            .is_ok()
+    }
+
+    /// Initializes the value with `f` and publishes it.
+    ///
+    /// This must only be called by the context whose call to [`Once::begin()`] succeeded.
+    #[track_caller]
+    fn finish(&self, f: impl FnOnce() -> T) -> &T {
+        let poison = PoisonOnUnwind(&self.state);
+        let value = f();
+        mem::forget(poison);
+
+        // SAFETY:
+        // Only the context that claimed initialization writes to the cell, and no reader accesses
+        // it until it is published below.
+        unsafe { (*self.value.get()).write(value) };
+        #[cfg(debug_assertions)]
+        {
+            // SAFETY:
+            // Only the context that claimed initialization writes the location, before it is
+            // published below.
+            unsafe { *self.initialized_at.get() = Some(Location::caller()) };
+        }
+
+        self.state.store(INITIALIZED, Ordering::Release);
+
+        // SAFETY:
+        // The value was just initialized.
+        unsafe { self.get_unchecked() }
+    }
+
+    /// Panics because the value was initialized a second time.
+    #[track_caller]
+    fn already_initialized(&self) -> ! {
+        #[cfg(debug_assertions)]
+        if self.state.load(Ordering::Acquire) == INITIALIZED {
+            // SAFETY:
+            // The location is written before the value is published and never again.
+            if let Some(location) = unsafe { *self.initialized_at.get() } {
+                panic!("Once initialized again; first initialized at {location}");
+            }
+        }
+
+        panic!("Once initialized again")
+    }
+
+    /// Returns the value without checking that it has been initialized.
+    ///
+    /// # Safety
+    /// The value must have been initialized and published.
+    unsafe fn get_unchecked(&self) -> &T {
+        // SAFETY:
+        // The caller guarantees that the value has been published, after which it is never
+        // mutated, so no mutable reference to it exists.
+        let value = unsafe { &*self.value.get() };
+        // SAFETY:
+        // The caller guarantees that the value is initialized.
+        unsafe { value.assume_init_ref() }
+    }
+}
+
+impl<T> Default for Once<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Drop for Once<T> {
+    fn drop(&mut self) {
+        if *self.state.get_mut() == INITIALIZED {
+            // SAFETY:
+            // The value is initialized and, since `self` is borrowed mutably, no references to it
+            // remain.
+            unsafe { self.value.get_mut().assume_init_drop() }
+        }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Once<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut debug_tuple = f.debug_tuple("Once");
+
+        match self.get() {
+            Some(value) => debug_tuple.field(value),
+            None => debug_tuple.field(&format_args!("<uninit>")),
+        };
+
+        debug_tuple.finish()
+    }
+}
+
+/// A step that runs exactly once system-wide, which other processors can wait for.
+///
+/// Completing the step publishes everything written by it with a release store, which
+/// [`GlobalOnce::wait()`] observes with an acquire load, so a processor that returns from
+/// [`GlobalOnce::wait()`] observes every effect of the step.
+#[derive(Debug, Default)]
+pub struct GlobalOnce(Once<()>);
+
+impl GlobalOnce {
+    /// Creates a new [`GlobalOnce`] whose step has not run.
+    pub const fn new() -> Self {
+        Self(Once::new())
+    }
+
+    /// Runs `f` if no processor has run the step yet, otherwise waits until the step has
+    /// completed.
+    ///
+    /// # Panics
+    /// Panics if the step panicked.
+    #[track_caller]
+    pub fn call_once(&self, f: impl FnOnce()) {
+        self.0.call_once(f);
+    }
+
+    /// Spins until the step has completed on some processor.
+    ///
+    /// # Panics
+    /// Panics if the step panicked.
+    pub fn wait(&self) {
+        self.0.wait();
+    }
+
+    /// Returns `true` if the step has completed.
+    pub fn is_completed(&self) -> bool {
+        self.0.is_initialized()
+    }
+}
+
+/// Poisons a [`Once`] if dropped, which only happens if its initializer unwinds.
+struct PoisonOnUnwind<'a>(&'a AtomicU8);
+
+impl Drop for PoisonOnUnwind<'_> {
+    fn drop(&mut self) {
+        self.0.store(POISONED, Ordering::Release);
+    }
+}
+
+/// A value that is initialized by `F` on first access.
+pub struct Lazy<T, F = fn() -> T> {
+    /// The value.
+    once: Once<T>,
+    /// The initializer, which is taken by the context that initializes `once`.
+    init: UnsafeCell<Option<F>>,
+}
+
+// SAFETY:
+// The initializer is only accessed by the single context that initializes `once`, and `T` is
+// shared as in `Once<T>`.
+unsafe impl<T: Send + Sync, F: Send> Sync for Lazy<T, F> {}
+
+impl<T, F: FnOnce() -> T> Lazy<T, F> {
+    /// Creates a new [`Lazy`] that is initialized by `init` on first access.
+    pub const fn new(init: F) -> Self {
+        Self {
+            once: Once::new(),
+            init: UnsafeCell::new(Some(init)),
+        }
+    }
+
+    /// Returns the value, initializing it if this is the first access.
+    ///
+    /// # Panics
+    /// Panics if the initializer panicked.
+    pub fn force(this: &Self) -> &T {
+        this.once.call_once(|| {
+            // SAFETY:
+            // Only the context that initializes `this.once` runs this closure, and it does so at
+            // most once.
+            let init = unsafe { (*this.init.get()).take() };
+            match init {
+                Some(init) => init(),
+                None => unreachable!("Lazy initializer already taken"),
+            }
+        })
+    }
+
+    /// Returns the value, or [`None`] if it has not been initialized yet.
+    pub fn get(this: &Self) -> Option<&T> {
+        this.once.get()
+    }
+}
+
+impl<T, F: FnOnce() -> T> Deref for Lazy<T, F> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        Self::force(self)
+    }
+}
+
+impl<T: fmt::Debug, F> fmt::Debug for Lazy<T, F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut debug_struct = f.debug_struct("Lazy");
+
+        debug_struct.field("once", &self.once);
+
+        debug_struct.finish()
+    }
+}
+
+#[cfg(feature = "ktest")]
+crate::kernel_test! {
+    fn once_runs_initializer_once() {
+        let once = Once::new();
+        let mut calls = 0;
+
+        let mut init = |value| {
+            calls += 1;
+            value
+        };
+        assert_eq!(*once.call_once(|| init(5)), 5);
+        assert_eq!(*once.call_once(|| init(6)), 5);
+        assert_eq!(calls, 1);
+        assert_eq!(once.get(), Some(&5));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        panic::{self, AssertUnwindSafe},
+        string::String,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc, Barrier,
+        },
+        thread,
+        time::Duration,
+        vec::Vec,
+    };
+
+    use super::*;
+
+    /// Returns the message of the panic raised by `f`.
+    fn panic_message(f: impl FnOnce()) -> String {
+        let payload = panic::catch_unwind(AssertUnwindSafe(f)).expect_err("no panic occurred");
+        match payload.downcast::<&'static str>() {
+            Ok(message) => (*message).into(),
+            Err(payload) => *payload.downcast().expect("panic message was not a string"),
+        }
+    }
+
+    #[test]
+    fn racing_call_once_runs_one_initializer() {
+        const THREADS: usize = 8;
+
+        let once = Arc::new(Once::new());
+        let calls = Arc::new(AtomicUsize::new(0));
+        let barrier = Arc::new(Barrier::new(THREADS));
+        let threads = (0..THREADS)
+            .map(|index| {
+                let once = Arc::clone(&once);
+                let calls = Arc::clone(&calls);
+                let barrier = Arc::clone(&barrier);
+                thread::spawn(move || {
+                    barrier.wait();
+                    *once.call_once(|| {
+                        calls.fetch_add(1, Ordering::Relaxed);
+                        // Give the other threads time to find the value being initialized.
+                        thread::sleep(Duration::from_millis(10));
+                        index
+                    })
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let values = threads
+            .into_iter()
+            .map(|thread| thread.join().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(calls.load(Ordering::Relaxed), 1);
+        assert!(values.iter().all(|&value| value == values[0]));
+        assert_eq!(once.get(), Some(&values[0]));
+    }
+
+    #[test]
+    fn wait_blocks_until_initialized() {
+        let once = Arc::new(Once::new());
+        let waiter = {
+            let once = Arc::clone(&once);
+            thread::spawn(move || *once.wait())
+        };
+
+        thread::sleep(Duration::from_millis(20));
+        assert!(!waiter.is_finished());
+        assert!(once.get().is_none());
+
+        once.init(7);
+        assert_eq!(waiter.join().unwrap(), 7);
+        assert!(once.is_initialized());
+    }
+
+    #[test]
+    fn init_twice_panics() {
+        let once = Once::new();
+        once.init(1);
+
+        let message = panic_message(|| {
+            once.init(2);
+        });
+        assert!(message.starts_with("Once initialized again"), "{message}");
+        assert_eq!(once.get(), Some(&1));
+    }
+
+    #[test]
+    fn panicking_initializer_poisons() {
+        let once = Arc::new(Once::<u32>::new());
+        let mut waiter = None;
+
+        let message = panic_message(|| {
+            once.call_once(|| {
+                // Start a waiter while the value is being initialized, which must observe the
+                // poisoning rather than spin forever.
+                let waiter_once = Arc::clone(&once);
+                waiter = Some(thread::spawn(move || {
+                    panic_message(|| {
+                        waiter_once.wait();
+                    })
+                }));
+                thread::sleep(Duration::from_millis(10));
+                panic!("initializer failed")
+            });
+        });
+        assert_eq!(message, "initializer failed");
+
+        let poisoned = "Once poisoned by a panicking initializer";
+        assert_eq!(waiter.unwrap().join().unwrap(), poisoned);
+        assert_eq!(
+            panic_message(|| {
+                once.call_once(|| 1);
+            }),
+            poisoned
+        );
+        assert_eq!(
+            panic_message(|| {
+                once.wait();
+            }),
+            poisoned
+        );
+        assert!(once.get().is_none());
+        assert!(!once.is_initialized());
+    }
+
+    #[test]
+    fn lazy_initializes_on_first_access() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let lazy = {
+            let calls = Arc::clone(&calls);
+            Arc::new(Lazy::new(move || {
+                calls.fetch_add(1, Ordering::Relaxed);
+                42
+            }))
+        };
+        assert!(Lazy::get(&lazy).is_none());
+        assert_eq!(calls.load(Ordering::Relaxed), 0);
+
+        let threads = (0..4)
+            .map(|_| {
+                let lazy = Arc::clone(&lazy);
+                thread::spawn(move || **lazy)
+            })
+            .collect::<Vec<_>>();
+        for thread in threads {
+            assert_eq!(thread.join().unwrap(), 42);
+        }
+
+        assert_eq!(*Lazy::force(&lazy), 42);
+        assert_eq!(Lazy::get(&lazy), Some(&42));
+        assert_eq!(calls.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn panicking_lazy_initializer_poisons() {
+        let lazy = Lazy::<u32>::new(|| panic!("initializer failed"));
+
+        assert_eq!(
+            panic_message(|| {
+                Lazy::force(&lazy);
+            }),
+            "initializer failed"
+        );
+        assert_eq!(
+            panic_message(|| {
+                Lazy::force(&lazy);
+            }),
+            "Once poisoned by a panicking initializer"
+        );
+    }
+}
@@ -0,0 +1,97 @@
+//! Selection of the accelerator QEMU runs the kernel with.
+//!
+//! Hardware accelerators only run guests of the host's own architecture, and each needs support
+//! from the host's operating system, so [`Accelerator::Auto`] probes for one and falls back to
+//! emulation when none is usable.
+
+use std::fmt;
+
+use crate::cli::{Accelerator, Arch};
+
+/// Resolves `requested` into the accelerator QEMU runs a guest for `arch` with.
+///
+/// [`Accelerator::Auto`] picks KVM or HVF if usable and otherwise falls back to TCG with a
+/// warning. WHPX has no reliable probe, so it is only used when requested.
+///
+/// # Errors
+/// Returns [`AccelError`] if a specific hardware accelerator was requested but cannot be used.
+pub fn resolve(requested: Accelerator, arch: Arch) -> Result<Accelerator, AccelError> {
+    match requested {
+        Accelerator::Auto => {
+            let usable = [Accelerator::Kvm, Accelerator::Hvf]
+                .into_iter()
+                .find(|&accelerator| check(accelerator, arch).is_ok());
+            Ok(usable.unwrap_or_else(|| {
+                eprintln!("warning: no hardware accelerator is usable, falling back to TCG");
+                Accelerator::Tcg
+            }))
+        }
+        accelerator => check(accelerator, arch).map(|()| accelerator),
+    }
+}
+
+/// Checks that `accelerator` can run a guest for `arch` on this host.
+fn check(accelerator: Accelerator, arch: Arch) -> Result<(), AccelError> {
+    let unavailable = |reason| AccelError {
+        accelerator,
+        reason,
+    };
+
+    let required_os = match accelerator {
+        Accelerator::Auto | Accelerator::Tcg => return Ok(()),
+        Accelerator::Kvm => "linux",
+        Accelerator::Hvf => "macos",
+        Accelerator::Whpx => "windows",
+    };
+    if std::env::consts::OS != required_os {
+        return Err(unavailable("it is not supported by this operating system"));
+    }
+    if std::env::consts::ARCH != arch.as_str() {
+        return Err(unavailable(
+            "the guest's architecture differs from the host's",
+        ));
+    }
+
+    match accelerator {
+        Accelerator::Kvm => {
+            // Opening the device is what QEMU does, so this also catches missing permissions.
+            std::fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open("/dev/kvm")
+                .map_err(|_| unavailable("\"/dev/kvm\" does not exist or is not writable"))?;
+        }
+        Accelerator::Hvf => {
+            let supported = std::process::Command::new("sysctl")
+                .args(["-n", "kern.hv_support"])
+                .output()
+                .is_ok_and(|output| output.stdout.trim_ascii() == b"1");
+            if !supported {
+                return Err(unavailable("the Hypervisor Framework is not supported"));
+            }
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+/// The requested accelerator cannot be used.
+#[derive(Debug)]
+pub struct AccelError {
+    /// The accelerator that was requested.
+    pub accelerator: Accelerator,
+    /// Why it cannot be used.
+    pub reason: &'static str,
+}
+
+impl fmt::Display for AccelError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "accelerator `{}` is unavailable: {}",
+            self.accelerator.as_str(),
+            self.reason
+        )
+    }
+}
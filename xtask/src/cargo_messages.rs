@@ -0,0 +1,467 @@
+//! Parsing of the JSON messages `cargo` emits with `--message-format=json`.
+//!
+//! Only enough of JSON is understood to read `compiler-artifact` messages, which name the files
+//! each build produced, so that `xtask` never has to guess where `cargo` put them.
+
+use std::path::{Path, PathBuf};
+
+/// Returns the path of the last executable that `messages` report being built from the package
+/// whose manifest path ends with `manifest`, or [`None`] if there is none.
+///
+/// `messages` holds one JSON message per line, and lines that are not JSON are skipped.
+pub fn executable(messages: &str, manifest: &Path) -> Option<PathBuf> {
+    messages
+        .lines()
+        .rev()
+        .filter_map(|line| Parser::new(line).parse())
+        .filter(|message| {
+            message.get("reason").and_then(Value::as_str) == Some("compiler-artifact")
+        })
+        .filter(|message| {
+            message
+                .get("manifest_path")
+                .and_then(Value::as_str)
+                .is_some_and(|path| Path::new(path).ends_with(manifest))
+        })
+        .filter(|message| {
+            // The package's build script is compiled too, but is not the executable wanted.
+            message
+                .get("target")
+                .and_then(|target| target.get("kind"))
+                .and_then(Value::as_array)
+                .is_some_and(|kinds| kinds.iter().any(|kind| kind.as_str() == Some("bin")))
+        })
+        .find_map(|message| message.get("executable")?.as_str().map(PathBuf::from))
+}
+
+/// A JSON value.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    /// A number, `true`, `false`, or `null`, whose value no message field used here needs.
+    Scalar,
+    /// A string, with its escapes resolved.
+    String(String),
+    /// An array of values.
+    Array(Vec<Value>),
+    /// An object, as its members in the order they appeared.
+    Object(Vec<(String, Value)>),
+}
+
+impl Value {
+    /// Returns the value of the member `key` if this is an object that has one.
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Self::Object(members) => members
+                .iter()
+                .find(|(name, _)| name == key)
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    /// Returns the contents of this value if it is a string.
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::String(string) => Some(string),
+            _ => None,
+        }
+    }
+
+    /// Returns the elements of this value if it is an array.
+    pub fn as_array(&self) -> Option<&[Value]> {
+        match self {
+            Self::Array(elements) => Some(elements),
+            _ => None,
+        }
+    }
+}
+
+/// A parser of a single JSON document.
+pub struct Parser<'input> {
+    /// The document being parsed.
+    input: &'input str,
+    /// The offset of the next byte to parse.
+    position: usize,
+}
+
+impl<'input> Parser<'input> {
+    /// Creates a parser of `input`.
+    pub fn new(input: &'input str) -> Self {
+        Self { input, position: 0 }
+    }
+
+    /// Parses the whole document, returning [`None`] if it is not a single JSON value.
+    pub fn parse(mut self) -> Option<Value> {
+        let value = self.value()?;
+        self.skip_whitespace();
+        (self.position == self.input.len()).then_some(value)
+    }
+
+    /// Parses the value at the current position.
+    fn value(&mut self) -> Option<Value> {
+        self.skip_whitespace();
+        match self.peek()? {
+            b'{' => self.object(),
+            b'[' => self.array(),
+            b'"' => self.string().map(Value::String),
+            b't' => self.literal("true"),
+            b'f' => self.literal("false"),
+            b'n' => self.literal("null"),
+            b'-' | b'0'..=b'9' => self.number(),
+            _ => None,
+        }
+    }
+
+    /// Parses the object at the current position.
+    fn object(&mut self) -> Option<Value> {
+        self.expect(b'{')?;
+        let mut members = Vec::new();
+
+        self.skip_whitespace();
+        if self.eat(b'}') {
+            return Some(Value::Object(members));
+        }
+
+        loop {
+            self.skip_whitespace();
+            let name = self.string()?;
+            self.skip_whitespace();
+            self.expect(b':')?;
+            members.push((name, self.value()?));
+
+            self.skip_whitespace();
+            if self.eat(b'}') {
+                return Some(Value::Object(members));
+            }
+            self.expect(b',')?;
+        }
+    }
+
+    /// Parses the array at the current position.
+    fn array(&mut self) -> Option<Value> {
+        self.expect(b'[')?;
+        let mut elements = Vec::new();
+
+        self.skip_whitespace();
+        if self.eat(b']') {
+            return Some(Value::Array(elements));
+        }
+
+        loop {
+            elements.push(self.value()?);
+
+            self.skip_whitespace();
+            if self.eat(b']') {
+                return Some(Value::Array(elements));
+            }
+            self.expect(b',')?;
+        }
+    }
+
+    /// Parses the string at the current position, resolving its escapes.
+    fn string(&mut self) -> Option<String> {
+        self.expect(b'"')?;
+        let mut string = String::new();
+
+        loop {
+            let rest = &self.input[self.position..];
+            let end = rest.find(['"', '\\'])?;
+            string.push_str(&rest[..end]);
+            self.position += end;
+
+            if self.eat(b'"') {
+                return Some(string);
+            }
+
+            self.expect(b'\\')?;
+            let escaped = match self.next()? {
+                b'"' => '"',
+                b'\\' => '\\',
+                b'/' => '/',
+                b'b' => '\u{8}',
+                b'f' => '\u{c}',
+                b'n' => '\n',
+                b'r' => '\r',
+                b't' => '\t',
+                b'u' => self.unicode_escape()?,
+                _ => return None,
+            };
+            string.push(escaped);
+        }
+    }
+
+    /// Parses the digits of a `\u` escape, including the second half of a surrogate pair.
+    fn unicode_escape(&mut self) -> Option<char> {
+        let high = self.hex_digits()?;
+        if !(0xD800..0xDC00).contains(&high) {
+            return char::from_u32(high);
+        }
+
+        self.expect(b'\\')?;
+        self.expect(b'u')?;
+        let low = self.hex_digits()?;
+        if !(0xDC00..0xE000).contains(&low) {
+            return None;
+        }
+
+        char::from_u32(0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00))
+    }
+
+    /// Parses four hexadecimal digits.
+    fn hex_digits(&mut self) -> Option<u32> {
+        let digits = self.input.get(self.position..self.position + 4)?;
+        let value = u32::from_str_radix(digits, 16).ok()?;
+        self.position += 4;
+        Some(value)
+    }
+
+    /// Parses the number at the current position.
+    fn number(&mut self) -> Option<Value> {
+        let rest = &self.input[self.position..];
+        let length = rest
+            .find(|c: char| !matches!(c, '0'..='9' | '-' | '+' | '.' | 'e' | 'E'))
+            .unwrap_or(rest.len());
+        self.position += length;
+        Some(Value::Scalar)
+    }
+
+    /// Parses `literal`.
+    fn literal(&mut self, literal: &str) -> Option<Value> {
+        if !self.input[self.position..].starts_with(literal) {
+            return None;
+        }
+
+        self.position += literal.len();
+        Some(Value::Scalar)
+    }
+
+    /// Skips any whitespace at the current position.
+    fn skip_whitespace(&mut self) {
+        while self
+            .peek()
+            .is_some_and(|byte| matches!(byte, b' ' | b'\t' | b'\n' | b'\r'))
+        {
+            self.position += 1;
+        }
+    }
+
+    /// Consumes `byte` if it is next, returning whether it was.
+    fn eat(&mut self, byte: u8) -> bool {
+        let matches = self.peek() == Some(byte);
+        if matches {
+            self.position += 1;
+        }
+        matches
+    }
+
+    /// Consumes `byte`, returning [`None`] if it is not next.
+    fn expect(&mut self, byte: u8) -> Option<()> {
+        self.eat(byte).then_some(())
+    }
+
+    /// Consumes and returns the next byte.
+    fn next(&mut self) -> Option<u8> {
+        let byte = self.peek()?;
+        self.position += 1;
+        Some(byte)
+    }
+
+    /// Returns the next byte without consuming it.
+    fn peek(&self) -> Option<u8> {
+        self.input.as_bytes().get(self.position).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The manifest path that the kernel's artifacts are looked up by.
+    const KERNEL_MANIFEST: &str = "kernel/Cargo.toml";
+
+    /// Returns a `compiler-artifact` message, as `cargo` prints it, for a target of `kind` in the
+    /// package at `manifest_path`.
+    fn artifact(manifest_path: &str, kind: &str, executable: Option<&str>) -> String {
+        let executable = match executable {
+            Some(path) => format!("\"{path}\""),
+            None => "null".to_owned(),
+        };
+
+        format!(
+            "{{\"reason\":\"compiler-artifact\",\"package_id\":\"path+file:///work/kernel#0.1.0\",\
+             \"manifest_path\":\"{manifest_path}\",\"target\":{{\"kind\":[\"{kind}\"],\
+             \"crate_types\":[\"{kind}\"],\"name\":\"kernel\",\"src_path\":\"/work/kernel/src/main.rs\",\
+             \"edition\":\"2021\",\"doc\":true,\"doctest\":false,\"test\":true}},\"profile\":\
+             {{\"opt_level\":\"0\",\"debuginfo\":2,\"debug_assertions\":true,\"overflow_checks\":true,\
+             \"test\":false}},\"features\":[\"limine-boot-api\"],\"filenames\":[\"/work/target/out\"],\
+             \"executable\":{executable},\"fresh\":false}}"
+        )
+    }
+
+    /// Returns the kernel executable that `lines` report.
+    fn kernel_executable(lines: &[&str]) -> Option<PathBuf> {
+        executable(&lines.join("\n"), Path::new(KERNEL_MANIFEST))
+    }
+
+    #[test]
+    fn finds_the_kernel_executable() {
+        let bin = artifact(
+            "/work/kernel/Cargo.toml",
+            "bin",
+            Some("/work/target/x86_64-unknown-none/debug/kernel"),
+        );
+        let finished = r#"{"reason":"build-finished","success":true}"#;
+
+        assert_eq!(
+            kernel_executable(&[&bin, finished]),
+            Some(PathBuf::from(
+                "/work/target/x86_64-unknown-none/debug/kernel"
+            ))
+        );
+        assert_eq!(kernel_executable(&[finished]), None);
+        assert_eq!(kernel_executable(&[]), None);
+    }
+
+    #[test]
+    fn skips_build_script_artifacts() {
+        let build_script = artifact("/work/kernel/Cargo.toml", "custom-build", None);
+        let executed = r#"{"reason":"build-script-executed","package_id":"path+file:///work/kernel#0.1.0","linked_libs":[],"linked_paths":[],"cfgs":[],"env":[],"out_dir":"/work/target/debug/build/kernel-1/out"}"#;
+        let bin = artifact(
+            "/work/kernel/Cargo.toml",
+            "bin",
+            Some("/work/target/kernel"),
+        );
+
+        assert_eq!(kernel_executable(&[&build_script, executed]), None);
+        // Even a build script reported with an executable is not the kernel.
+        let runnable_build_script = artifact(
+            "/work/kernel/Cargo.toml",
+            "custom-build",
+            Some("/work/target/debug/build/kernel-1/build-script-build"),
+        );
+        assert_eq!(
+            kernel_executable(&[&bin, &runnable_build_script, executed]),
+            Some(PathBuf::from("/work/target/kernel"))
+        );
+    }
+
+    #[test]
+    fn skips_library_artifacts() {
+        let lib = artifact("/work/kernel/Cargo.toml", "lib", None);
+        let dependency = artifact(
+            "/home/user/.cargo/registry/src/log-0.4.22/Cargo.toml",
+            "lib",
+            None,
+        );
+
+        assert_eq!(kernel_executable(&[&dependency, &lib]), None);
+    }
+
+    #[test]
+    fn last_kernel_executable_wins() {
+        let first = artifact("/work/kernel/Cargo.toml", "bin", Some("/work/target/first"));
+        let last = artifact("/work/kernel/Cargo.toml", "bin", Some("/work/target/last"));
+        let other_package = artifact(
+            "/work/xtask/Cargo.toml",
+            "bin",
+            Some("/work/target/debug/xtask"),
+        );
+
+        assert_eq!(
+            kernel_executable(&[&first, &last, &other_package]),
+            Some(PathBuf::from("/work/target/last"))
+        );
+        // A manifest merely ending in the same characters belongs to another package.
+        let lookalike = artifact(
+            "/work/not-kernel/Cargo.toml",
+            "bin",
+            Some("/work/target/not-kernel"),
+        );
+        assert_eq!(
+            kernel_executable(&[&first, &lookalike]),
+            Some(PathBuf::from("/work/target/first"))
+        );
+    }
+
+    #[test]
+    fn resolves_escaped_paths() {
+        let windows = artifact(
+            r"C:\\work\\kernel\\Cargo.toml",
+            "bin",
+            Some(r"C:\\work\\target\\kernel.exe"),
+        );
+        assert_eq!(
+            executable(&windows, Path::new(r"C:\work\kernel\Cargo.toml")),
+            Some(PathBuf::from(r"C:\work\target\kernel.exe"))
+        );
+
+        let escaped = artifact(
+            "/work/kernel/Cargo.toml",
+            "bin",
+            Some(r#"/tmp/caf\u00e9 \ud83e\udd80\/\"quoted\"\tkernel"#),
+        );
+        assert_eq!(
+            kernel_executable(&[&escaped]),
+            Some(PathBuf::from("/tmp/café 🦀/\"quoted\"\tkernel"))
+        );
+    }
+
+    #[test]
+    fn skips_lines_that_are_not_json() {
+        let bin = artifact(
+            "/work/kernel/Cargo.toml",
+            "bin",
+            Some("/work/target/kernel"),
+        );
+        let truncated = &bin[..bin.len() - 1];
+
+        assert_eq!(
+            kernel_executable(&[
+                "   Compiling kernel v0.1.0 (/work/kernel)",
+                &bin,
+                "",
+                truncated,
+                "warning: unused variable: `x`",
+                r#"{"reason":"compiler-artifact"} trailing"#,
+            ]),
+            Some(PathBuf::from("/work/target/kernel"))
+        );
+    }
+
+    #[test]
+    fn parses_json_values() {
+        let parse = |input: &str| Parser::new(input).parse();
+
+        assert_eq!(parse(" null "), Some(Value::Scalar));
+        assert_eq!(parse("-1.5e+3"), Some(Value::Scalar));
+        assert_eq!(parse(r#""a\nb""#), Some(Value::String("a\nb".to_owned())));
+        assert_eq!(
+            parse(r#"{ "a" : [ true , {} , [] ] , "b" : "c" }"#),
+            Some(Value::Object(vec![
+                (
+                    "a".to_owned(),
+                    Value::Array(vec![
+                        Value::Scalar,
+                        Value::Object(Vec::new()),
+                        Value::Array(Vec::new())
+                    ])
+                ),
+                ("b".to_owned(), Value::String("c".to_owned())),
+            ]))
+        );
+
+        for invalid in [
+            "",
+            "{",
+            "[1,]",
+            r#"{"a" 1}"#,
+            r#""\x""#,
+            r#""\ud83e""#,
+            r#""\u12""#,
+            "tru",
+            "1 2",
+        ] {
+            assert_eq!(parse(invalid), None, "{invalid:?} parsed");
+        }
+    }
+}
@@ -1,24 +1,72 @@
 //! Command line parsing and command construction.
 
 use std::{
-    ops::{BitAnd, BitOr},
-    path::PathBuf,
+    collections::BTreeMap, ffi::OsString, fmt, io, path::PathBuf, sync::OnceLock, time::Duration,
 };
 
 use clap::ArgAction;
 
+/// The parsed command line.
+pub struct Arguments {
+    /// How much is reported about the commands that are run.
+    pub verbosity: Verbosity,
+    /// The action to carry out.
+    pub action: Action,
+}
+
+/// How much is reported about the commands that are run.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum Verbosity {
+    /// Commands are not announced, and their output is only shown if they fail.
+    Quiet,
+    /// Commands are announced, and the output of those that talk to the user is shown.
+    Normal,
+    /// Commands are announced, and the output of all of them is shown.
+    Verbose,
+}
+
 /// The action to carry out.
 pub enum Action {
     /// Build the Capora kernel.
-    Build(BuildArguments),
+    Build {
+        /// Arguments necessary to build the Capora kernel.
+        build_arguments: BuildArguments,
+        /// How the result of the build is reported.
+        output_format: OutputFormat,
+    },
     /// Build and run the Capora kernel using Limine.
     RunLimine {
         /// Arguments necessary to build the Capora kernel.
         build_arguments: BuildArguments,
         /// Arguments necessary to run the Capora kernel.
         run_arguments: RunArguments,
-        /// The path to the Limine bootloader.
-        limine_path: PathBuf,
+        /// The path of a prebuilt kernel to run instead of building one.
+        kernel: Option<PathBuf>,
+        /// The path to the Limine bootloader, or [`None`] to fetch it.
+        limine_path: Option<PathBuf>,
+        /// Whether the kernel is booted from an ISO built by [`Action::Iso`] instead of a FAT
+        /// drive.
+        from_iso: bool,
+    },
+    /// Build a bootable disk image for the Capora kernel.
+    Image {
+        /// Arguments necessary to build the Capora kernel.
+        build_arguments: BuildArguments,
+        /// The path to the Limine bootloader, or [`None`] to boot using `capora-boot-stub`.
+        limine_path: Option<PathBuf>,
+        /// The path the disk image is written to.
+        output: PathBuf,
+    },
+    /// Build a bootable ISO for the Capora kernel, which boots it using Limine.
+    Iso {
+        /// Arguments necessary to build the Capora kernel.
+        build_arguments: BuildArguments,
+        /// The path to the Limine bootloader, or [`None`] to fetch it.
+        limine_path: Option<PathBuf>,
+        /// Whether fetching Limine is disabled.
+        offline: bool,
+        /// The path the ISO is written to.
+        output: PathBuf,
     },
     /// Build and run the Capora kernel using `capora-boot-stub`.
     RunBootStub {
@@ -26,81 +74,535 @@ pub enum Action {
         build_arguments: BuildArguments,
         /// Argument necessary to run the Capora kernel.
         run_arguments: RunArguments,
+        /// The path of a prebuilt kernel to run instead of building one.
+        kernel: Option<PathBuf>,
+        /// Files that `capora-boot-stub` loads alongside the kernel.
+        modules: Vec<BootModule>,
+    },
+    /// Run `cargo clippy` on the Capora kernel for several feature sets.
+    Check {
+        /// Arguments necessary to build the Capora kernel, whose features are enabled in every
+        /// feature set.
+        build_arguments: BuildArguments,
+        /// The feature sets to check.
+        feature_sets: Vec<Features>,
+    },
+    /// Build the Capora kernel with every combination of boot APIs and logging backends.
+    Matrix {
+        /// Arguments necessary to build the Capora kernel, whose features are enabled in every
+        /// combination.
+        build_arguments: BuildArguments,
+        /// Whether to stop at the first combination that fails to build.
+        fail_fast: bool,
+    },
+    /// Build the Capora kernel and report the size of the resulting binary.
+    Size {
+        /// Arguments necessary to build the Capora kernel.
+        build_arguments: BuildArguments,
+        /// The features of a second build whose size should be compared against.
+        baseline_features: Option<Features>,
+        /// Strings that must not appear in the resulting binary.
+        forbidden_strings: Vec<String>,
+    },
+    /// Build the Capora kernel with the `qemu-exit` feature, run it using `capora-boot-stub`, and
+    /// report whether it exited successfully.
+    Test {
+        /// Arguments necessary to build the Capora kernel.
+        build_arguments: BuildArguments,
+        /// Arguments necessary to run the Capora kernel.
+        run_arguments: RunArguments,
+    },
+    /// Run the Capora kernel like [`Action::Test`], and compare its normalized serial output
+    /// against a snapshot.
+    Snapshot {
+        /// Arguments necessary to build the Capora kernel.
+        build_arguments: BuildArguments,
+        /// Arguments necessary to run the Capora kernel.
+        run_arguments: RunArguments,
+        /// Whether the snapshot is replaced by the serial output instead of compared against it.
+        update: bool,
+    },
+    /// Run the tests of the Capora kernel's pure modules on the host.
+    HostTest {
+        /// The features that the kernel should have enabled.
+        features: Features,
+    },
+    /// Remove the files generated by running the Capora kernel.
+    Clean {
+        /// The architecture whose files should be removed, or [`None`] for all of them.
+        arch: Option<Arch>,
+        /// Whether the kernel's build artifacts should be removed too.
+        all: bool,
     },
 }
 
 /// Arguments necessary to determine how to build the kernel.
-#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
 pub struct BuildArguments {
     /// THe architecture for which the kernel should be built.
     pub arch: Arch,
-    /// Whether the kernel should be built in release mode.
-    pub release: bool,
+    /// The cargo profile the kernel should be built with, or [`None`] for cargo's default `dev`
+    /// profile.
+    pub profile: Option<String>,
     /// The features that the kernel should have enabled.
     pub features: Features,
 }
 
 /// Arguments necessary to determine how to run the kernel.
 pub struct RunArguments {
-    /// The path to the OVMF code file used to run UEFI.
-    pub ovmf_code: PathBuf,
-    /// The path to the OVMF vars file used to run UEFI.
-    pub ovmf_vars: PathBuf,
+    /// The path to the OVMF code file used to run UEFI, or [`None`] to locate it automatically.
+    pub ovmf_code: Option<PathBuf>,
+    /// The path to the OVMF vars file used to run UEFI, or [`None`] to locate it automatically.
+    pub ovmf_vars: Option<PathBuf>,
+    /// Whether downloading missing firmware and bootloaders is disabled.
+    pub offline: bool,
+    /// How QEMU is driven.
+    pub mode: RunMode,
+    /// The time after which QEMU is killed, if any.
+    pub timeout: Option<Duration>,
+    /// The TCP port on which QEMU waits for GDB to attach before starting the kernel, if any.
+    pub gdb_port: Option<u16>,
+    /// The path of a disk image to build and boot from, instead of a virtual FAT drive.
+    pub image: Option<PathBuf>,
+    /// The amount of memory given to the guest, in QEMU's `-m` syntax, or [`None`] for the
+    /// default.
+    pub memory: Option<String>,
+    /// The number of processors given to the guest, or [`None`] for QEMU's default of one.
+    pub cpus: Option<u32>,
+    /// Arguments appended to the QEMU command line.
+    pub extra_qemu_args: Vec<OsString>,
+    /// The path the kernel's serial output is written to, or [`None`] for the default.
+    pub serial_log: Option<PathBuf>,
+    /// Whether QEMU runs without a display, printing the kernel's serial and debugcon output in
+    /// the terminal instead.
+    pub headless: bool,
+    /// The firmware QEMU boots the kernel with.
+    pub firmware: BootFirmware,
+    /// The accelerator QEMU runs the kernel with.
+    pub accelerator: Accelerator,
+}
+
+/// The accelerator QEMU runs the kernel with.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum Accelerator {
+    /// The first available hardware accelerator, or [`Accelerator::Tcg`] if there is none.
+    Auto,
+    /// The Linux Kernel-based Virtual Machine.
+    Kvm,
+    /// The macOS Hypervisor Framework.
+    Hvf,
+    /// The Windows Hypervisor Platform.
+    Whpx,
+    /// QEMU's Tiny Code Generator, which emulates the guest without hardware support.
+    Tcg,
+}
+
+impl Accelerator {
+    /// Returns the [`Accelerator`] as its textual representation, which is also its name in
+    /// QEMU's `-accel` option.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Auto => "auto",
+            Self::Kvm => "kvm",
+            Self::Hvf => "hvf",
+            Self::Whpx => "whpx",
+            Self::Tcg => "tcg",
+        }
+    }
 }
 
-/// Parses arguments to construct an [`Action`].
-pub fn parse_arguments() -> Action {
+impl clap::ValueEnum for Accelerator {
+    fn value_variants<'a>() -> &'a [Self] {
+        static ACCELERATORS: &[Accelerator] = &[
+            Accelerator::Auto,
+            Accelerator::Kvm,
+            Accelerator::Hvf,
+            Accelerator::Whpx,
+            Accelerator::Tcg,
+        ];
+
+        ACCELERATORS
+    }
+
+    fn to_possible_value(&self) -> Option<clap::builder::PossibleValue> {
+        Some(clap::builder::PossibleValue::new(self.as_str()))
+    }
+}
+
+/// The firmware QEMU boots the kernel with.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum BootFirmware {
+    /// UEFI, provided by OVMF.
+    Uefi,
+    /// Legacy BIOS, provided by QEMU's default SeaBIOS.
+    Bios,
+}
+
+impl BootFirmware {
+    /// Returns the [`BootFirmware`] as its textual representation.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Uefi => "uefi",
+            Self::Bios => "bios",
+        }
+    }
+}
+
+impl clap::ValueEnum for BootFirmware {
+    fn value_variants<'a>() -> &'a [Self] {
+        static FIRMWARES: &[BootFirmware] = &[BootFirmware::Uefi, BootFirmware::Bios];
+
+        FIRMWARES
+    }
+
+    fn to_possible_value(&self) -> Option<clap::builder::PossibleValue> {
+        Some(clap::builder::PossibleValue::new(self.as_str()))
+    }
+}
+
+/// How the result of an action is reported.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Messages meant to be read by people.
+    Human,
+    /// A single line JSON object on stdout, with every other message moved to stderr.
+    Json,
+}
+
+impl OutputFormat {
+    /// Returns the [`OutputFormat`] as its textual representation.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Human => "human",
+            Self::Json => "json",
+        }
+    }
+}
+
+impl clap::ValueEnum for OutputFormat {
+    fn value_variants<'a>() -> &'a [Self] {
+        static FORMATS: &[OutputFormat] = &[OutputFormat::Human, OutputFormat::Json];
+
+        FORMATS
+    }
+
+    fn to_possible_value(&self) -> Option<clap::builder::PossibleValue> {
+        Some(clap::builder::PossibleValue::new(self.as_str()))
+    }
+}
+
+/// How QEMU is driven while running the kernel.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum RunMode {
+    /// QEMU shows a display and its monitor is attached to the terminal.
+    Interactive,
+    /// QEMU runs without a display or monitor, and the kernel must report its result through the
+    /// `isa-debug-exit` device.
+    Test,
+}
+
+/// Parses arguments to construct an [`Action`] and the [`Verbosity`] it is carried out with.
+///
+/// # Errors
+/// Returns [`FeatureError`] if the requested features are not supported by the kernel or cannot
+/// be combined, including with the features the action enables itself.
+pub fn parse_arguments() -> Result<Arguments, FeatureError> {
     let mut matches = command_parser().get_matches();
+    let verbosity = if matches.remove_one::<bool>("verbose").unwrap_or(false) {
+        Verbosity::Verbose
+    } else if matches.remove_one::<bool>("quiet").unwrap_or(false) {
+        Verbosity::Quiet
+    } else {
+        Verbosity::Normal
+    };
+
     let (subcommand_name, mut subcommand_matches) =
         matches.remove_subcommand().expect("subcommand required");
-    match subcommand_name.as_str() {
-        "build" => Action::Build(parse_build_arguments(&mut subcommand_matches)),
-        "run-limine" => Action::RunLimine {
-            build_arguments: parse_build_arguments(&mut subcommand_matches),
-            run_arguments: parse_run_arguments(&mut subcommand_matches),
-            limine_path: subcommand_matches
-                .remove_one("limine")
-                .expect("limine is required"),
+    let action = match subcommand_name.as_str() {
+        "build" => {
+            let build_arguments = parse_build_arguments(&mut subcommand_matches)?;
+            build_arguments.features.require_boot_api()?;
+
+            Action::Build {
+                build_arguments,
+                output_format: subcommand_matches
+                    .remove_one::<OutputFormat>("output-format")
+                    .unwrap_or(OutputFormat::Human),
+            }
+        }
+        "run-limine" => {
+            let mut build_arguments = parse_build_arguments(&mut subcommand_matches)?;
+            imply(&mut build_arguments, &[Features::LIMINE_BOOT_API])?;
+            let mut run_arguments = parse_run_arguments(&mut subcommand_matches);
+            run_arguments.firmware = subcommand_matches
+                .remove_one::<BootFirmware>("firmware")
+                .unwrap_or(BootFirmware::Uefi);
+
+            Action::RunLimine {
+                build_arguments,
+                run_arguments,
+                kernel: subcommand_matches.remove_one("kernel"),
+                limine_path: subcommand_matches.remove_one("limine"),
+                from_iso: subcommand_matches
+                    .remove_one::<bool>("from-iso")
+                    .unwrap_or(false),
+            }
+        }
+        "run-boot-stub" => {
+            let mut build_arguments = parse_build_arguments(&mut subcommand_matches)?;
+            imply(&mut build_arguments, &[Features::CAPORA_BOOT_API])?;
+
+            Action::RunBootStub {
+                build_arguments,
+                run_arguments: parse_run_arguments(&mut subcommand_matches),
+                kernel: subcommand_matches.remove_one("kernel"),
+                modules: subcommand_matches
+                    .remove_many("module")
+                    .into_iter()
+                    .flatten()
+                    .collect(),
+            }
+        }
+        "image" => {
+            let mut build_arguments = parse_build_arguments(&mut subcommand_matches)?;
+            let limine_path = subcommand_matches.remove_one::<PathBuf>("limine");
+            let boot_api = match limine_path {
+                Some(_) => Features::LIMINE_BOOT_API,
+                None => Features::CAPORA_BOOT_API,
+            };
+            imply(&mut build_arguments, &[boot_api])?;
+
+            let output = subcommand_matches.remove_one("output").unwrap_or_else(|| {
+                ["run", build_arguments.arch.as_str(), "capora.img"]
+                    .iter()
+                    .collect()
+            });
+
+            Action::Image {
+                build_arguments,
+                limine_path,
+                output,
+            }
+        }
+        "iso" => {
+            let mut build_arguments = parse_build_arguments(&mut subcommand_matches)?;
+            imply(&mut build_arguments, &[Features::LIMINE_BOOT_API])?;
+
+            let output = subcommand_matches.remove_one("output").unwrap_or_else(|| {
+                ["run", build_arguments.arch.as_str(), "capora.iso"]
+                    .iter()
+                    .collect()
+            });
+
+            Action::Iso {
+                build_arguments,
+                limine_path: subcommand_matches.remove_one("limine"),
+                offline: subcommand_matches
+                    .remove_one::<bool>("offline")
+                    .unwrap_or(false),
+                output,
+            }
+        }
+        "check" => {
+            let build_arguments = parse_build_arguments(&mut subcommand_matches)?;
+            let feature_sets = match subcommand_matches.get_many::<String>("feature-set") {
+                Some(sets) => sets
+                    .map(|set| parse_features(core::iter::once(set.as_str())))
+                    .collect::<Result<Vec<_>, _>>()?,
+                None => DEFAULT_CHECK_FEATURE_SETS
+                    .iter()
+                    .map(|&set| parse_features(core::iter::once(set)))
+                    .collect::<Result<Vec<_>, _>>()?,
+            };
+            for feature_set in &feature_sets {
+                let mut features = build_arguments.features.clone();
+                features.extend(feature_set);
+                features.validate(build_arguments.arch)?;
+                features.require_boot_api()?;
+            }
+
+            Action::Check {
+                build_arguments,
+                feature_sets,
+            }
+        }
+        "matrix" => Action::Matrix {
+            build_arguments: parse_build_arguments(&mut subcommand_matches)?,
+            fail_fast: subcommand_matches
+                .remove_one::<bool>("fail-fast")
+                .unwrap_or(false),
         },
-        "run-boot-stub" => Action::RunBootStub {
-            build_arguments: parse_build_arguments(&mut subcommand_matches),
-            run_arguments: parse_run_arguments(&mut subcommand_matches),
+        "size" => {
+            let build_arguments = parse_build_arguments(&mut subcommand_matches)?;
+            build_arguments.features.require_boot_api()?;
+            let mut baseline_features = subcommand_matches
+                .get_many::<String>("baseline-features")
+                .map(|features| parse_features(features.map(String::as_str)))
+                .transpose()?;
+            if let Some(baseline_features) = &mut baseline_features {
+                if !build_arguments.features.default_features() {
+                    baseline_features.disable_default_features();
+                }
+                baseline_features.validate(build_arguments.arch)?;
+                baseline_features.require_boot_api()?;
+            }
+
+            Action::Size {
+                build_arguments,
+                baseline_features,
+                forbidden_strings: subcommand_matches
+                    .remove_many("forbid")
+                    .into_iter()
+                    .flatten()
+                    .collect(),
+            }
+        }
+        "test" => {
+            let mut build_arguments = parse_build_arguments(&mut subcommand_matches)?;
+            imply(
+                &mut build_arguments,
+                &[Features::CAPORA_BOOT_API, Features::QEMU_EXIT],
+            )?;
+            let mut run_arguments = parse_run_arguments(&mut subcommand_matches);
+            run_arguments.mode = RunMode::Test;
+            // A debugging session should not be cut short.
+            if run_arguments.gdb_port.is_some() {
+                run_arguments.timeout = None;
+            }
+
+            Action::Test {
+                build_arguments,
+                run_arguments,
+            }
+        }
+        "snapshot" => {
+            let mut build_arguments = parse_build_arguments(&mut subcommand_matches)?;
+            imply(
+                &mut build_arguments,
+                &[
+                    Features::CAPORA_BOOT_API,
+                    Features::QEMU_EXIT,
+                    Features::SERIAL_LOGGING,
+                ],
+            )?;
+            let mut run_arguments = parse_run_arguments(&mut subcommand_matches);
+            run_arguments.mode = RunMode::Test;
+            if run_arguments.gdb_port.is_some() {
+                run_arguments.timeout = None;
+            }
+
+            Action::Snapshot {
+                build_arguments,
+                run_arguments,
+                update: subcommand_matches
+                    .remove_one::<bool>("update")
+                    .unwrap_or(false),
+            }
+        }
+        "host-test" => Action::HostTest {
+            features: parse_features(
+                subcommand_matches
+                    .get_many::<String>("features")
+                    .into_iter()
+                    .flatten()
+                    .map(String::as_str),
+            )?,
+        },
+        "clean" => Action::Clean {
+            arch: subcommand_matches.remove_one("arch"),
+            all: subcommand_matches
+                .remove_one::<bool>("all")
+                .unwrap_or(false),
         },
         name => unreachable!("unexpected subcommand {name:?}"),
-    }
+    };
+
+    Ok(Arguments { verbosity, action })
 }
 
 /// Parses subcommand arguments for the [`Action::Build`] subcommand.
-pub fn parse_build_arguments(matches: &mut clap::ArgMatches) -> BuildArguments {
+///
+/// # Errors
+/// Returns [`FeatureError`] if the requested features are not supported by the kernel or cannot
+/// be combined.
+pub fn parse_build_arguments(
+    matches: &mut clap::ArgMatches,
+) -> Result<BuildArguments, FeatureError> {
     let arch = matches
         .remove_one::<Arch>("arch")
         .expect("arch is a required argument");
-    let release = matches.remove_one::<bool>("release").unwrap_or(false);
-
-    let mut features = Features::default();
-    for feature in matches
-        .get_many::<String>("features")
-        .into_iter()
-        .flatten()
-        .map(String::as_str)
-        .flat_map(|s| parse_feature(&s))
+    let profile = if matches.remove_one::<bool>("release").unwrap_or(false) {
+        Some("release".to_owned())
+    } else {
+        matches.remove_one::<String>("profile")
+    };
+    let mut features = parse_features(
+        matches
+            .get_many::<String>("features")
+            .into_iter()
+            .flatten()
+            .map(String::as_str),
+    )?;
+    if matches
+        .remove_one::<bool>("no-default-features")
+        .unwrap_or(false)
     {
-        let new_feature = match Features::str_to_feature(feature) {
-            Some(feature) => feature,
-            None => {
-                eprintln!("unsupported feature `{feature}`");
-                std::process::exit(1);
-            }
-        };
-
-        features = features | new_feature;
+        features.disable_default_features();
     }
+    features.validate(arch)?;
 
-    BuildArguments {
+    Ok(BuildArguments {
         arch,
-        release,
+        profile,
         features,
+    })
+}
+
+/// Adds `implied`, which the action enables itself, to the features of `build_arguments`, and
+/// checks that the kernel can be built with the result.
+fn imply(build_arguments: &mut BuildArguments, implied: &[&str]) -> Result<(), FeatureError> {
+    for feature in implied {
+        build_arguments.features.insert(feature);
     }
+
+    build_arguments.features.validate(build_arguments.arch)
+}
+
+/// The feature sets checked by the `check` subcommand when none are given: each boot protocol on
+/// its own and with each logging backend.
+const DEFAULT_CHECK_FEATURE_SETS: &[&str] = &[
+    "limine-boot-api",
+    "limine-boot-api,debugcon-logging",
+    "limine-boot-api,serial-logging",
+    "limine-boot-api,framebuffer-logging",
+    "capora-boot-api",
+    "capora-boot-api,debugcon-logging",
+    "capora-boot-api,serial-logging",
+    "capora-boot-api,framebuffer-logging",
+    "multiboot2-boot-api",
+    "multiboot2-boot-api,debugcon-logging",
+    "multiboot2-boot-api,serial-logging",
+    "multiboot2-boot-api,framebuffer-logging",
+];
+
+/// Parses a list of comma or whitespace separated feature lists into [`Features`].
+///
+/// # Errors
+/// Returns [`FeatureError`] if the kernel's manifest cannot be read or does not declare one of
+/// the features.
+fn parse_features<'str>(lists: impl Iterator<Item = &'str str>) -> Result<Features, FeatureError> {
+    let kernel_features = kernel_features()?;
+
+    let mut features = Features::default();
+    for feature in lists.flat_map(parse_feature) {
+        if !kernel_features.contains_key(feature) {
+            return Err(FeatureError::Unknown(feature.to_owned()));
+        }
+
+        features.insert(feature);
+    }
+
+    Ok(features)
 }
 
 fn parse_feature<'str>(feature: &'str str) -> impl Iterator<Item = &'str str> + 'str {
@@ -110,18 +612,100 @@ fn parse_feature<'str>(feature: &'str str) -> impl Iterator<Item = &'str str> +
         .filter(|s| !s.is_empty())
 }
 
+/// The TCP port on which QEMU waits for GDB if `--gdb` is given without `--gdb-port`.
+pub const DEFAULT_GDB_PORT: u16 = 1234;
+
 /// Parses subcommand arguments for the [`Action::Run`] subcommand.
 pub fn parse_run_arguments(matches: &mut clap::ArgMatches) -> RunArguments {
-    let ovmf_code = matches
-        .remove_one("ovmf-code")
-        .expect("ovmf-code is required");
-    let ovmf_vars = matches
-        .remove_one("ovmf-vars")
-        .expect("ovmf-vars is required");
+    let ovmf_code = matches.remove_one("ovmf-code");
+    let ovmf_vars = matches.remove_one("ovmf-vars");
+    let offline = matches.remove_one::<bool>("offline").unwrap_or(false);
+
+    let gdb = matches.remove_one::<bool>("gdb").unwrap_or(false);
+    let gdb_port = matches.remove_one::<u16>("gdb-port");
+    let gdb_port = match gdb_port {
+        Some(port) => Some(port),
+        None if gdb => Some(DEFAULT_GDB_PORT),
+        None => None,
+    };
 
     RunArguments {
         ovmf_code,
         ovmf_vars,
+        offline,
+        mode: RunMode::Interactive,
+        timeout: matches
+            .remove_one::<u64>("timeout")
+            .map(Duration::from_secs),
+        gdb_port,
+        image: matches.remove_one("image"),
+        memory: matches.remove_one("memory"),
+        cpus: matches.remove_one("smp"),
+        serial_log: matches.remove_one("serial-log"),
+        headless: matches.remove_one::<bool>("headless").unwrap_or(false),
+        firmware: BootFirmware::Uefi,
+        accelerator: matches
+            .remove_one::<Accelerator>("accel")
+            .unwrap_or(Accelerator::Auto),
+        extra_qemu_args: matches
+            .remove_many("qemu-args")
+            .into_iter()
+            .flatten()
+            .collect(),
+    }
+}
+
+/// Parses the path of a prebuilt kernel, which must be a regular file.
+fn parse_kernel_path(path: &str) -> Result<PathBuf, String> {
+    let path = PathBuf::from(path);
+    match std::fs::metadata(&path) {
+        Ok(metadata) if metadata.is_file() => Ok(path),
+        Ok(_) => Err("not a regular file".to_owned()),
+        Err(error) => Err(error.to_string()),
+    }
+}
+
+/// A file that `capora-boot-stub` loads alongside the kernel.
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+pub struct BootModule {
+    /// The name the module is known by.
+    pub name: String,
+    /// The path of the file holding the module.
+    pub path: PathBuf,
+}
+
+/// Parses a module in the form `<name>:<path>`.
+fn parse_boot_module(module: &str) -> Result<BootModule, String> {
+    let Some((name, path)) = module.split_once(':') else {
+        return Err("expected `<name>:<path>`".to_owned());
+    };
+    if name.is_empty() {
+        return Err("module name must not be empty".to_owned());
+    }
+    if path.is_empty() {
+        return Err("module path must not be empty".to_owned());
+    }
+
+    Ok(BootModule {
+        name: name.to_owned(),
+        path: PathBuf::from(path),
+    })
+}
+
+/// Parses a memory size in QEMU's `-m` syntax: a non-zero number of mebibytes, optionally
+/// followed by a `K`, `M`, `G`, or `T` suffix.
+fn parse_memory_size(size: &str) -> Result<String, String> {
+    let digits = size.trim_end_matches(|c: char| "kKmMgGtT".contains(c));
+    if size.len() - digits.len() > 1 {
+        return Err("expected at most one of the suffixes `K`, `M`, `G`, or `T`".to_owned());
+    }
+
+    match digits.parse::<u64>() {
+        Ok(0) => Err("memory size must not be zero".to_owned()),
+        Ok(_) => Ok(size.to_owned()),
+        Err(_) => {
+            Err("expected a number followed by `K`, `M`, `G`, or `T`, such as `512M`".to_owned())
+        }
     }
 }
 
@@ -138,12 +722,23 @@ pub fn command_parser() -> clap::Command {
         .short('r')
         .action(clap::ArgAction::SetTrue);
 
+    let profile_arg = clap::Arg::new("profile")
+        .help("build the Capora kernel with the named cargo profile")
+        .long("profile")
+        .value_name("PROFILE")
+        .conflicts_with("release");
+
     let features_arg = clap::Arg::new("features")
         .help("List of features to activate")
         .long("features")
         .short('F')
         .action(ArgAction::Append);
 
+    let no_default_features_arg = clap::Arg::new("no-default-features")
+        .help("Do not activate the kernel's default features")
+        .long("no-default-features")
+        .action(ArgAction::SetTrue);
+
     let build_subcommand = clap::Command::new("build")
         .about("build the Capora kernel")
         .arg(
@@ -152,19 +747,102 @@ pub fn command_parser() -> clap::Command {
                 .help("The architecture for which the kernel should be built"),
         )
         .arg(release_arg.clone())
-        .arg(features_arg.clone());
+        .arg(profile_arg.clone())
+        .arg(no_default_features_arg.clone())
+        .arg(features_arg.clone())
+        .arg(
+            clap::Arg::new("output-format")
+                .help("How the result of the build is reported")
+                .long("output-format")
+                .value_parser(clap::builder::EnumValueParser::<OutputFormat>::new())
+                .default_value("human"),
+        );
 
     let ovmf_code_arg = clap::Arg::new("ovmf-code")
+        .help("The OVMF code file, located or downloaded automatically if not given")
         .long("ovmf-code")
         .short('c')
-        .value_parser(clap::builder::PathBufValueParser::new())
-        .required(true);
+        .value_parser(clap::builder::PathBufValueParser::new());
 
     let ovmf_vars_arg = clap::Arg::new("ovmf-vars")
+        .help("The OVMF vars template, located or downloaded automatically if not given")
         .long("ovmf-vars")
         .short('v')
-        .value_parser(clap::builder::PathBufValueParser::new())
-        .required(true);
+        .value_parser(clap::builder::PathBufValueParser::new());
+
+    let offline_arg = clap::Arg::new("offline")
+        .help("Never download missing firmware or bootloaders")
+        .long("offline")
+        .action(ArgAction::SetTrue);
+
+    let gdb_arg = clap::Arg::new("gdb")
+        .help("Start QEMU paused, waiting for GDB to attach on port 1234")
+        .long("gdb")
+        .action(ArgAction::SetTrue);
+
+    let gdb_port_arg = clap::Arg::new("gdb-port")
+        .help("Start QEMU paused, waiting for GDB to attach on the given port")
+        .long("gdb-port")
+        .value_parser(clap::value_parser!(u16));
+
+    let image_arg = clap::Arg::new("image")
+        .help("Build a disk image at the given path and boot from it")
+        .long("image")
+        .short('i')
+        .value_parser(clap::builder::PathBufValueParser::new());
+
+    let kernel_arg = clap::Arg::new("kernel")
+        .help(
+            "A prebuilt kernel to run instead of building one; `--features` should name the \
+                features it was built with, which decide how QEMU is set up",
+        )
+        .long("kernel")
+        .value_parser(parse_kernel_path)
+        .conflicts_with_all(["release", "profile"]);
+
+    let limine_arg = clap::Arg::new("limine")
+        .long("limine")
+        .short('l')
+        .value_parser(clap::builder::PathBufValueParser::new());
+
+    let memory_arg = clap::Arg::new("memory")
+        .help("The amount of memory given to the guest, such as `512M` or `2G` [default: 256M]")
+        .long("memory")
+        .short('m')
+        .value_parser(parse_memory_size);
+
+    let smp_arg = clap::Arg::new("smp")
+        .help("The number of processors given to the guest [default: 1]")
+        .long("smp")
+        .value_parser(clap::value_parser!(u32).range(1..));
+
+    let serial_log_arg = clap::Arg::new("serial-log")
+        .help("Write the kernel's serial output to the given file instead of the terminal")
+        .long("serial-log")
+        .value_parser(clap::builder::PathBufValueParser::new());
+
+    let accel_arg = clap::Arg::new("accel")
+        .help("The accelerator QEMU runs the kernel with")
+        .long("accel")
+        .value_parser(clap::builder::EnumValueParser::<Accelerator>::new())
+        .default_value("auto");
+
+    let headless_arg = clap::Arg::new("headless")
+        .help("Run without a display, printing serial and debugcon output in the terminal")
+        .long("headless")
+        .action(ArgAction::SetTrue);
+
+    let timeout_arg = clap::Arg::new("timeout")
+        .help("The number of seconds after which QEMU is killed and the run fails")
+        .long("timeout")
+        .short('t')
+        .value_parser(clap::value_parser!(u64));
+
+    let qemu_args_arg = clap::Arg::new("qemu-args")
+        .help("Arguments passed through to QEMU")
+        .num_args(1..)
+        .last(true)
+        .value_parser(clap::builder::OsStringValueParser::new());
 
     let run_limine_subcommand = clap::Command::new("run-limine")
         .about("Run the Capora kernel using the Limine bootloader")
@@ -174,30 +852,275 @@ pub fn command_parser() -> clap::Command {
                 .help("The architecture for which the kernel should be built and run"),
         )
         .arg(release_arg.clone())
+        .arg(profile_arg.clone())
+        .arg(no_default_features_arg.clone())
         .arg(features_arg.clone())
         .arg(ovmf_code_arg.clone())
         .arg(ovmf_vars_arg.clone())
+        .arg(offline_arg.clone())
+        .arg(gdb_arg.clone())
+        .arg(gdb_port_arg.clone())
+        .arg(image_arg.clone())
+        .arg(memory_arg.clone())
+        .arg(smp_arg.clone())
+        .arg(serial_log_arg.clone())
+        .arg(headless_arg.clone())
+        .arg(accel_arg.clone())
+        .arg(timeout_arg.clone())
+        .arg(qemu_args_arg.clone())
+        .arg(kernel_arg.clone())
+        .arg(limine_arg.clone().help(
+            "The Limine UEFI executable, fetched automatically if not given; BIOS files are \
+                taken from the same directory",
+        ))
         .arg(
-            clap::Arg::new("limine")
-                .long("limine")
-                .short('l')
-                .value_parser(clap::builder::PathBufValueParser::new())
-                .required(true),
+            clap::Arg::new("firmware")
+                .help("The firmware QEMU boots the kernel with")
+                .long("firmware")
+                .value_parser(clap::builder::EnumValueParser::<BootFirmware>::new())
+                .default_value("uefi"),
+        )
+        .arg(
+            clap::Arg::new("from-iso")
+                .help("Boot from an ISO at run/<arch>/capora.iso instead of a FAT drive")
+                .long("from-iso")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("image"),
         );
 
     let run_boot_stub_subcommand = clap::Command::new("run-boot-stub")
         .about("Run the capora-kernel using `capora boot stub`")
-        .arg(arch_arg.help("The architecture for which the kernel should be built and run"))
+        .arg(
+            arch_arg
+                .clone()
+                .help("The architecture for which the kernel should be built and run"),
+        )
+        .arg(release_arg.clone())
+        .arg(profile_arg.clone())
+        .arg(no_default_features_arg.clone())
+        .arg(features_arg.clone())
+        .arg(ovmf_code_arg.clone())
+        .arg(ovmf_vars_arg.clone())
+        .arg(offline_arg.clone())
+        .arg(gdb_arg.clone())
+        .arg(gdb_port_arg.clone())
+        .arg(image_arg.clone())
+        .arg(memory_arg.clone())
+        .arg(smp_arg.clone())
+        .arg(serial_log_arg.clone())
+        .arg(headless_arg.clone())
+        .arg(accel_arg.clone())
+        .arg(timeout_arg.clone())
+        .arg(qemu_args_arg.clone())
+        .arg(kernel_arg)
+        .arg(
+            clap::Arg::new("module")
+                .help("A file `capora-boot-stub` loads alongside the kernel, as `<name>:<path>`")
+                .long("module")
+                .value_parser(parse_boot_module)
+                .action(ArgAction::Append),
+        );
+
+    let test_subcommand = clap::Command::new("test")
+        .about("build the Capora kernel, run it under QEMU, and report whether it succeeded")
+        .arg(
+            arch_arg
+                .clone()
+                .help("The architecture for which the kernel should be built and run"),
+        )
+        .arg(release_arg.clone())
+        .arg(profile_arg.clone())
+        .arg(no_default_features_arg.clone())
+        .arg(features_arg.clone())
+        .arg(ovmf_code_arg)
+        .arg(ovmf_vars_arg)
+        .arg(offline_arg.clone())
+        .arg(gdb_arg)
+        .arg(gdb_port_arg)
+        .arg(image_arg)
+        .arg(memory_arg)
+        .arg(smp_arg)
+        .arg(serial_log_arg)
+        .arg(headless_arg)
+        .arg(accel_arg)
+        .arg(timeout_arg.default_value("60"))
+        .arg(qemu_args_arg);
+
+    // Snapshots boot the kernel exactly like tests, and only differ in what is checked afterwards.
+    let snapshot_subcommand = test_subcommand
+        .clone()
+        .name("snapshot")
+        .about(
+            "build and run the Capora kernel like `test`, and compare its normalized serial output \
+                against tests/snapshots/<arch>/<features>.txt",
+        )
+        .arg(
+            clap::Arg::new("update")
+                .help("Write the serial output to the snapshot instead of comparing against it")
+                .long("update")
+                .action(ArgAction::SetTrue),
+        );
+
+    let image_subcommand = clap::Command::new("image")
+        .about("build a bootable disk image for the Capora kernel and print its path")
+        .arg(
+            arch_arg
+                .clone()
+                .help("The architecture for which the kernel should be built"),
+        )
+        .arg(release_arg.clone())
+        .arg(profile_arg.clone())
+        .arg(no_default_features_arg.clone())
+        .arg(features_arg.clone())
+        .arg(limine_arg.clone().help(
+            "Boot using the Limine bootloader at the given path instead of `capora-boot-stub`",
+        ))
+        .arg(
+            clap::Arg::new("output")
+                .help("The path the disk image is written to [default: run/<arch>/capora.img]")
+                .long("output")
+                .short('o')
+                .value_parser(clap::builder::PathBufValueParser::new()),
+        );
+
+    let iso_subcommand = clap::Command::new("iso")
+        .about("build a bootable ISO that boots the Capora kernel using Limine and print its path")
+        .arg(
+            arch_arg
+                .clone()
+                .help("The architecture for which the kernel should be built"),
+        )
+        .arg(release_arg.clone())
+        .arg(profile_arg.clone())
+        .arg(no_default_features_arg.clone())
+        .arg(features_arg.clone())
+        .arg(offline_arg)
+        .arg(limine_arg.help(
+            "The Limine UEFI executable, fetched automatically if not given; BIOS and CD files \
+            are taken from the same directory",
+        ))
+        .arg(
+            clap::Arg::new("output")
+                .help("The path the ISO is written to [default: run/<arch>/capora.iso]")
+                .long("output")
+                .short('o')
+                .value_parser(clap::builder::PathBufValueParser::new()),
+        );
+
+    let check_subcommand = clap::Command::new("check")
+        .about("run `cargo clippy` on the Capora kernel for several feature sets")
+        .arg(
+            arch_arg
+                .clone()
+                .help("The architecture for which the kernel should be checked"),
+        )
+        .arg(release_arg.clone())
+        .arg(profile_arg.clone())
+        .arg(no_default_features_arg.clone())
+        .arg(
+            features_arg
+                .clone()
+                .help("List of features to activate in every feature set"),
+        )
+        .arg(
+            clap::Arg::new("feature-set")
+                .help("A list of features to check together, which may be given multiple times")
+                .long("feature-set")
+                .short('s')
+                .action(ArgAction::Append),
+        );
+
+    let matrix_subcommand = clap::Command::new("matrix")
+        .about("build the Capora kernel with every combination of boot APIs and logging backends")
+        .arg(
+            arch_arg
+                .clone()
+                .help("The architecture for which the kernel should be built"),
+        )
+        .arg(release_arg.clone())
+        .arg(profile_arg.clone())
+        .arg(no_default_features_arg.clone())
+        .arg(
+            features_arg
+                .clone()
+                .help("List of features to activate in every combination"),
+        )
+        .arg(
+            clap::Arg::new("fail-fast")
+                .help("Stop at the first combination that fails to build")
+                .long("fail-fast")
+                .action(ArgAction::SetTrue),
+        );
+
+    let clean_subcommand = clap::Command::new("clean")
+        .about("remove the files generated by running the Capora kernel, including cached firmware")
+        .arg(
+            arch_arg
+                .clone()
+                .required(false)
+                .help("The architecture whose files should be removed, instead of all of them"),
+        )
+        .arg(
+            clap::Arg::new("all")
+                .help("Also run `cargo clean` for the kernel")
+                .long("all")
+                .action(ArgAction::SetTrue),
+        );
+
+    let host_test_subcommand = clap::Command::new("host-test")
+        .about("run the tests of the Capora kernel's pure modules on the host")
+        .arg(features_arg.clone());
+
+    let size_subcommand = clap::Command::new("size")
+        .about("build the Capora kernel and report the size of the binary")
+        .arg(arch_arg.help("The architecture for which the kernel should be built"))
         .arg(release_arg)
+        .arg(profile_arg)
+        .arg(no_default_features_arg)
         .arg(features_arg)
-        .arg(ovmf_code_arg)
-        .arg(ovmf_vars_arg);
+        .arg(
+            clap::Arg::new("baseline-features")
+                .help("List of features of a baseline build to compare the size against")
+                .long("baseline-features")
+                .short('b')
+                .action(ArgAction::Append),
+        )
+        .arg(
+            clap::Arg::new("forbid")
+                .help("A string that must not appear in the kernel binary")
+                .long("forbid")
+                .action(ArgAction::Append),
+        );
 
     clap::Command::new("xtask")
         .about("Developer utility for running various tasks in capora-kernel")
         .subcommand(build_subcommand)
         .subcommand(run_limine_subcommand)
         .subcommand(run_boot_stub_subcommand)
+        .subcommand(image_subcommand)
+        .subcommand(iso_subcommand)
+        .subcommand(check_subcommand)
+        .subcommand(matrix_subcommand)
+        .subcommand(size_subcommand)
+        .subcommand(test_subcommand)
+        .subcommand(snapshot_subcommand)
+        .subcommand(host_test_subcommand)
+        .subcommand(clean_subcommand)
+        .arg(
+            clap::Arg::new("verbose")
+                .help("Show the output of every command that is run")
+                .long("verbose")
+                .global(true)
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            clap::Arg::new("quiet")
+                .help("Do not announce commands, and only show their output if they fail")
+                .long("quiet")
+                .global(true)
+                .action(ArgAction::SetTrue)
+                .conflicts_with("verbose"),
+        )
         .subcommand_required(true)
         .arg_required_else_help(true)
 }
@@ -207,6 +1130,8 @@ pub fn command_parser() -> clap::Command {
 pub enum Arch {
     /// The `x86_64` architecture.
     X86_64,
+    /// The `aarch64` architecture.
+    Aarch64,
 }
 
 impl Arch {
@@ -214,6 +1139,7 @@ impl Arch {
     pub fn as_target_triple(&self) -> &'static str {
         match self {
             Self::X86_64 => "x86_64-unknown-none",
+            Self::Aarch64 => "aarch64-unknown-none",
         }
     }
 
@@ -221,13 +1147,23 @@ impl Arch {
     pub fn as_str(&self) -> &'static str {
         match self {
             Self::X86_64 => "x86_64",
+            Self::Aarch64 => "aarch64",
+        }
+    }
+
+    /// Returns the name UEFI firmware for the [`Arch`] looks for its fallback boot loader under in
+    /// `EFI/BOOT/`.
+    pub fn uefi_boot_file_name(&self) -> &'static str {
+        match self {
+            Self::X86_64 => "BOOTX64.EFI",
+            Self::Aarch64 => "BOOTAA64.EFI",
         }
     }
 }
 
 impl clap::ValueEnum for Arch {
     fn value_variants<'a>() -> &'a [Self] {
-        static ARCHES: &[Arch] = &[Arch::X86_64];
+        static ARCHES: &[Arch] = &[Arch::X86_64, Arch::Aarch64];
 
         ARCHES
     }
@@ -237,72 +1173,306 @@ impl clap::ValueEnum for Arch {
     }
 }
 
-/// The various features that should be enabled by the kernel.
-#[derive(Clone, Copy, Debug, Default, Hash, PartialEq, Eq)]
-pub struct Features(u64);
+/// The path of the kernel's manifest, whose `[features]` table lists the features it supports.
+const KERNEL_MANIFEST: &str = "kernel/Cargo.toml";
 
-impl Features {
-    /// Enable the `limine-boot-api` feature, which enables support for booting via the Limine boot
-    /// protocol.
-    pub const LIMINE_BOOT_API: Self = Self(0x1);
-    /// Enables the `capora-boot-api` feature, which enables support for booting via the
-    /// `capora-boot-api` protocol.
-    pub const CAPORA_BOOT_API: Self = Self(0x2);
+/// The features declared by the kernel's manifest, mapped to the features each one enables.
+static KERNEL_FEATURES: OnceLock<BTreeMap<String, Vec<String>>> = OnceLock::new();
 
-    /// Enables the `debugcon` feature, which enables support for using the `debugcon` device in
-    /// the kernel.
-    pub const DEBUGCON_LOGGING: Self = Self(0x4);
-    /// Enables the `debugcon` feature, which enables support for using the `debugcon` device in
-    /// the kernel.
-    pub const SERIAL_LOGGING: Self = Self(0x8);
+/// Returns the features declared by the kernel's manifest, reading it the first time.
+fn kernel_features() -> Result<&'static BTreeMap<String, Vec<String>>, FeatureError> {
+    if let Some(features) = KERNEL_FEATURES.get() {
+        return Ok(features);
+    }
 
-    /// Enables the `logging` feature, which enables support for loggingg within the kernel.
-    pub const LOGGING: Self = Self(0x16);
+    let manifest = std::fs::read_to_string(KERNEL_MANIFEST).map_err(FeatureError::ReadManifest)?;
+    Ok(KERNEL_FEATURES.get_or_init(|| parse_manifest_features(&manifest)))
 }
 
-impl Features {
-    /// Converts a [`str`] into its corresponding [`Features`] flag, returning [`None`] if it does
-    /// not match a feature.
-    pub fn str_to_feature(s: &str) -> Option<Features> {
-        match s {
-            "limine-boot-api" => Some(Self::LIMINE_BOOT_API),
-            "capora-boot-api" => Some(Self::CAPORA_BOOT_API),
-            "debugcon-logging" => Some(Self::DEBUGCON_LOGGING),
-            "serial-logging" => Some(Self::SERIAL_LOGGING),
-            "logging" => Some(Self::LOGGING),
-            _ => None,
+/// Parses the `[features]` table of `manifest`, mapping each feature to the kernel features it
+/// enables.
+///
+/// Only the subset of TOML the kernel's manifest uses is understood: one `name = [...]` entry per
+/// feature, whose array may span several lines. Entries that enable dependencies or their
+/// features are left out.
+fn parse_manifest_features(manifest: &str) -> BTreeMap<String, Vec<String>> {
+    let mut entries: Vec<(String, String)> = Vec::new();
+    for line in manifest
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or_default().trim())
+        .skip_while(|&line| line != "[features]")
+        .skip(1)
+        .take_while(|line| !line.starts_with('['))
+    {
+        let entry = line.split_once('=').and_then(|(name, value)| {
+            let name = name.trim().trim_matches('"');
+            let is_name = !name.is_empty()
+                && name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+            is_name.then(|| (name.to_owned(), value.to_owned()))
+        });
+
+        match (entry, entries.last_mut()) {
+            (Some(entry), _) => entries.push(entry),
+            (None, Some((_, value))) => value.push_str(line),
+            (None, None) => {}
         }
     }
 
-    /// Converts [`Features`] into a comma seperated string of the features.
-    pub fn as_string(&self) -> String {
-        let features = *self;
-        let features = [
-            "limine-boot-api",
-            "capora-boot-api",
-            "debugcon-logging",
-            "serial-logging",
-            "logging",
-        ]
+    entries
         .into_iter()
-        .filter(|&f| Self::str_to_feature(f).is_some_and(|feature| features & feature == feature));
+        .map(|(name, value)| {
+            let enables = value
+                .split('"')
+                .skip(1)
+                .step_by(2)
+                .filter(|feature| !feature.starts_with("dep:") && !feature.contains('/'))
+                .map(str::to_owned)
+                .collect();
+            (name, enables)
+        })
+        .collect()
+}
+
+/// The features that should be enabled by the kernel.
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+pub struct Features {
+    /// The features that were requested, in the order they were requested.
+    requested: Vec<String>,
+    /// Whether the kernel's default features are enabled alongside the requested ones.
+    default_features: bool,
+}
 
-        features.collect::<Vec<_>>().join(",")
+impl Default for Features {
+    fn default() -> Self {
+        Self {
+            requested: Vec::new(),
+            default_features: true,
+        }
     }
 }
 
-impl BitOr for Features {
-    type Output = Self;
+impl Features {
+    /// The `limine-boot-api` feature, which enables support for booting via the Limine boot
+    /// protocol.
+    pub const LIMINE_BOOT_API: &'static str = "limine-boot-api";
+    /// The `capora-boot-api` feature, which enables support for booting via the
+    /// `capora-boot-api` protocol.
+    pub const CAPORA_BOOT_API: &'static str = "capora-boot-api";
+    /// The `multiboot2-boot-api` feature, which enables support for booting via the Multiboot2
+    /// boot protocol.
+    pub const MULTIBOOT2_BOOT_API: &'static str = "multiboot2-boot-api";
+
+    /// The `logging` feature, which enables the kernel's logging, and is enabled by each of the
+    /// logging backends.
+    pub const LOGGING: &'static str = "logging";
+    /// The `debugcon-logging` feature, which enables support for logging to the `debugcon`
+    /// device.
+    pub const DEBUGCON_LOGGING: &'static str = "debugcon-logging";
+    /// The `serial-logging` feature, which enables support for logging to the serial port.
+    pub const SERIAL_LOGGING: &'static str = "serial-logging";
+    /// The `framebuffer-logging` feature, which enables support for logging to the framebuffer.
+    pub const FRAMEBUFFER_LOGGING: &'static str = "framebuffer-logging";
+
+    /// The `qemu-exit` feature, which lets the kernel terminate QEMU through the
+    /// `isa-debug-exit` device.
+    pub const QEMU_EXIT: &'static str = "qemu-exit";
+
+    /// The `default` feature, which cargo enables unless default features are disabled.
+    const DEFAULT: &'static str = "default";
 
-    fn bitor(self, rhs: Self) -> Self::Output {
-        Self(self.0 | rhs.0)
+    /// The boot APIs, at least one of which the kernel needs for an entry point.
+    const BOOT_APIS: &'static [&'static str] = &[
+        Self::LIMINE_BOOT_API,
+        Self::CAPORA_BOOT_API,
+        Self::MULTIBOOT2_BOOT_API,
+    ];
+
+    /// Pairs of features that the kernel cannot be built with at the same time, and why.
+    const CONFLICTS: &'static [(&'static str, &'static str, &'static str)] = &[
+        (
+            Self::MULTIBOOT2_BOOT_API,
+            Self::LIMINE_BOOT_API,
+            "Multiboot2 links the kernel at a fixed address",
+        ),
+        (
+            Self::MULTIBOOT2_BOOT_API,
+            Self::CAPORA_BOOT_API,
+            "Multiboot2 links the kernel at a fixed address",
+        ),
+    ];
+
+    /// Adds `feature` to the requested features, unless it already is.
+    pub fn insert(&mut self, feature: &str) {
+        if !self.requested.iter().any(|requested| requested == feature) {
+            self.requested.push(feature.to_owned());
+        }
+    }
+
+    /// Adds every feature of `other` to the requested features.
+    pub fn extend(&mut self, other: &Features) {
+        for feature in &other.requested {
+            self.insert(feature);
+        }
+    }
+
+    /// Returns whether the kernel's default features are enabled alongside the requested ones.
+    pub fn default_features(&self) -> bool {
+        self.default_features
+    }
+
+    /// Disables the kernel's default features, so that only the requested features are enabled.
+    pub fn disable_default_features(&mut self) {
+        self.default_features = false;
+    }
+
+    /// Returns `true` if `feature` is requested, or is enabled by a requested feature or the
+    /// default features according to the kernel's manifest.
+    pub fn enables(&self, feature: &str) -> bool {
+        let mut pending: Vec<&str> = self.requested.iter().map(String::as_str).collect();
+        if self.default_features {
+            pending.push(Self::DEFAULT);
+        }
+        let mut visited = Vec::new();
+        while let Some(current) = pending.pop() {
+            if current == feature {
+                return true;
+            }
+            if visited.contains(&current) {
+                continue;
+            }
+            visited.push(current);
+
+            if let Some(enabled) = KERNEL_FEATURES.get().and_then(|map| map.get(current)) {
+                pending.extend(enabled.iter().map(String::as_str));
+            }
+        }
+
+        false
+    }
+
+    /// Checks that the kernel can be built for `arch` with these features.
+    ///
+    /// # Errors
+    /// Returns [`FeatureError`] if two of the features conflict, or one is unavailable on `arch`.
+    pub fn validate(&self, arch: Arch) -> Result<(), FeatureError> {
+        for &(first, second, reason) in Self::CONFLICTS {
+            if self.enables(first) && self.enables(second) {
+                return Err(FeatureError::Conflict {
+                    first,
+                    second,
+                    reason,
+                });
+            }
+        }
+
+        let unavailable: &[&'static str] = match arch {
+            Arch::X86_64 => &[],
+            // These rely on I/O ports or a boot protocol that only exist on `x86_64`.
+            Arch::Aarch64 => &[
+                Self::DEBUGCON_LOGGING,
+                Self::MULTIBOOT2_BOOT_API,
+                Self::QEMU_EXIT,
+            ],
+        };
+        if let Some(&feature) = unavailable.iter().find(|&&feature| self.enables(feature)) {
+            return Err(FeatureError::Unavailable { feature, arch });
+        }
+
+        Ok(())
+    }
+
+    /// Returns an iterator over the requested features, in the order they were requested.
+    pub fn iter(&self) -> impl Iterator<Item = &str> {
+        self.requested.iter().map(String::as_str)
+    }
+
+    /// Checks that these features enable at least one boot API.
+    ///
+    /// # Errors
+    /// Returns [`FeatureError::NoBootApi`] if no boot API is enabled, since the kernel would then
+    /// have no entry point.
+    pub fn require_boot_api(&self) -> Result<(), FeatureError> {
+        if Self::BOOT_APIS
+            .iter()
+            .any(|boot_api| self.enables(boot_api))
+        {
+            Ok(())
+        } else {
+            Err(FeatureError::NoBootApi)
+        }
+    }
+
+    /// Converts [`Features`] into a comma seperated string of the requested features, which does
+    /// not include the default features.
+    pub fn as_string(&self) -> String {
+        self.requested.join(",")
     }
 }
 
-impl BitAnd for Features {
-    type Output = Self;
+/// Various errors that can occur while parsing and validating the features of the kernel.
+#[derive(Debug)]
+pub enum FeatureError {
+    /// An error occurred while reading the kernel's manifest.
+    ReadManifest(io::Error),
+    /// The kernel's manifest does not declare the feature.
+    Unknown(String),
+    /// Two features cannot be enabled at the same time.
+    Conflict {
+        /// The first of the conflicting features.
+        first: &'static str,
+        /// The second of the conflicting features.
+        second: &'static str,
+        /// Why the features conflict.
+        reason: &'static str,
+    },
+    /// No boot API is enabled, so the kernel would have no entry point.
+    NoBootApi,
+    /// The feature is not available on the architecture.
+    Unavailable {
+        /// The unavailable feature.
+        feature: &'static str,
+        /// The architecture the kernel is built for.
+        arch: Arch,
+    },
+}
 
-    fn bitand(self, rhs: Self) -> Self::Output {
-        Self(self.0 & rhs.0)
+impl fmt::Display for FeatureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ReadManifest(error) => {
+                write!(
+                    f,
+                    "error occurred while reading \"{KERNEL_MANIFEST}\": {error}"
+                )
+            }
+            Self::Unknown(feature) => {
+                write!(f, "unsupported feature `{feature}`; the kernel supports ")?;
+                let supported = KERNEL_FEATURES
+                    .get()
+                    .map(|features| features.keys().map(String::as_str).collect::<Vec<_>>());
+                match supported {
+                    Some(supported) => write!(f, "`{}`", supported.join("`, `")),
+                    None => write!(f, "the features in \"{KERNEL_MANIFEST}\""),
+                }
+            }
+            Self::Conflict {
+                first,
+                second,
+                reason,
+            } => write!(f, "features `{first}` and `{second}` conflict: {reason}"),
+            Self::NoBootApi => write!(
+                f,
+                "no boot API is enabled, so the kernel would have no entry point; enable one of \
+                    `{}`",
+                Features::BOOT_APIS.join("`, `")
+            ),
+            Self::Unavailable { feature, arch } => write!(
+                f,
+                "feature `{feature}` is not available on `{}`",
+                arch.as_str()
+            ),
+        }
     }
 }
@@ -0,0 +1,573 @@
+//! Demangling of Rust symbol names for the kernel's symbol table.
+//!
+//! Both the legacy scheme (`_ZN...E`) and the v0 scheme (`_R...`) are understood. Hashes and
+//! crate disambiguators are dropped, since the table only needs to tell functions apart for a
+//! human reading a backtrace. Names that are not Rust symbols, or that use a construct this module
+//! does not know, are returned unchanged.
+
+/// The maximum depth of nested paths and types followed in a v0 symbol, which bounds the
+/// recursion on malformed input.
+const MAX_DEPTH: u32 = 64;
+
+/// Demangles the symbol name `name`, returning it unchanged if it cannot be demangled.
+pub fn demangle(name: &str) -> String {
+    let demangled = if let Some(rest) = name.strip_prefix("_ZN") {
+        demangle_legacy(rest)
+    } else if name.starts_with("_R") {
+        // LLVM appends suffixes such as `.llvm.1234` to local symbols, and v0 names never
+        // contain a period themselves.
+        let name = name.split('.').next().unwrap_or(name);
+        V0Parser::new(name).demangle()
+    } else {
+        None
+    };
+
+    demangled.unwrap_or_else(|| name.to_owned())
+}
+
+/// Demangles the legacy symbol name `rest`, which follows the `_ZN` prefix, dropping its hash.
+fn demangle_legacy(mut rest: &str) -> Option<String> {
+    let mut components = Vec::new();
+    while !rest.starts_with('E') {
+        let digits = rest.bytes().take_while(u8::is_ascii_digit).count();
+        let len = rest[..digits].parse::<usize>().ok()?;
+        components.push(rest.get(digits..digits + len)?);
+        rest = &rest[digits + len..];
+    }
+
+    if let Some(last) = components.last() {
+        if last.len() == 17
+            && last.starts_with('h')
+            && last[1..].bytes().all(|byte| byte.is_ascii_hexdigit())
+        {
+            components.pop();
+        }
+    }
+
+    let mut demangled = String::with_capacity(rest.len());
+    for (index, component) in components.iter().enumerate() {
+        if index != 0 {
+            demangled.push_str("::");
+        }
+        demangle_legacy_component(component, &mut demangled);
+    }
+
+    Some(demangled)
+}
+
+/// Appends `component` to `out`, replacing the escapes of the legacy mangling scheme.
+fn demangle_legacy_component(component: &str, out: &mut String) {
+    const ESCAPES: &[(&str, &str)] = &[
+        ("$SP$", "@"),
+        ("$BP$", "*"),
+        ("$RF$", "&"),
+        ("$LT$", "<"),
+        ("$GT$", ">"),
+        ("$LP$", "("),
+        ("$RP$", ")"),
+        ("$C$", ","),
+        ("$u7e$", "~"),
+        ("$u20$", " "),
+        ("$u27$", "'"),
+        ("$u3d$", "="),
+        ("$u5b$", "["),
+        ("$u5d$", "]"),
+        ("$u7b$", "{"),
+        ("$u7d$", "}"),
+        ("$u3b$", ";"),
+        ("$u2b$", "+"),
+        ("$u22$", "\""),
+    ];
+
+    // Components that would begin with an escape are prefixed with an underscore.
+    let mut rest = if component.starts_with("_$") {
+        &component[1..]
+    } else {
+        component
+    };
+    while !rest.is_empty() {
+        if let Some(after) = rest.strip_prefix("..") {
+            out.push_str("::");
+            rest = after;
+        } else if let Some(&(escape, replacement)) =
+            ESCAPES.iter().find(|(escape, _)| rest.starts_with(escape))
+        {
+            out.push_str(replacement);
+            rest = &rest[escape.len()..];
+        } else {
+            let character = rest.chars().next().unwrap_or_default();
+            out.push(character);
+            rest = &rest[character.len_utf8()..];
+        }
+    }
+}
+
+/// A parser of v0 symbol names that prints them as it goes.
+struct V0Parser<'a> {
+    /// The symbol name, including the `_R` prefix.
+    symbol: &'a [u8],
+    /// The position of the next byte to parse.
+    position: usize,
+    /// The current nesting depth.
+    depth: u32,
+    /// Whether a type, rather than a value path, is being printed, which decides whether generic
+    /// arguments need a turbofish.
+    in_type: bool,
+    /// The demangled name printed so far.
+    out: String,
+}
+
+impl<'a> V0Parser<'a> {
+    /// Creates a [`V0Parser`] for `symbol`, which begins with `_R`.
+    fn new(symbol: &'a str) -> Self {
+        Self {
+            symbol: symbol.as_bytes(),
+            position: 2,
+            depth: 0,
+            in_type: false,
+            out: String::with_capacity(symbol.len()),
+        }
+    }
+
+    /// Demangles the symbol, ignoring the instantiating crate that may follow its path.
+    fn demangle(mut self) -> Option<String> {
+        // The encoding version, which is absent for the current one.
+        if self.peek()?.is_ascii_digit() {
+            return None;
+        }
+
+        self.path()?;
+        Some(self.out)
+    }
+
+    /// Returns the next byte without consuming it.
+    fn peek(&self) -> Option<u8> {
+        self.symbol.get(self.position).copied()
+    }
+
+    /// Consumes and returns the next byte.
+    fn next(&mut self) -> Option<u8> {
+        let byte = self.peek()?;
+        self.position += 1;
+        Some(byte)
+    }
+
+    /// Consumes the next byte if it is `byte`, returning whether it was.
+    fn eat(&mut self, byte: u8) -> bool {
+        let eaten = self.peek() == Some(byte);
+        if eaten {
+            self.position += 1;
+        }
+        eaten
+    }
+
+    /// Parses a base-62 number terminated by `_`.
+    fn base62(&mut self) -> Option<u64> {
+        if self.eat(b'_') {
+            return Some(0);
+        }
+
+        let mut value = 0u64;
+        loop {
+            let digit = match self.next()? {
+                byte @ b'0'..=b'9' => byte - b'0',
+                byte @ b'a'..=b'z' => byte - b'a' + 10,
+                byte @ b'A'..=b'Z' => byte - b'A' + 36,
+                b'_' => return value.checked_add(1),
+                _ => return None,
+            };
+            value = value.checked_mul(62)?.checked_add(u64::from(digit))?;
+        }
+    }
+
+    /// Parses an optional disambiguator, returning its value.
+    fn disambiguator(&mut self) -> Option<u64> {
+        if self.eat(b's') {
+            self.base62()?.checked_add(1)
+        } else {
+            Some(0)
+        }
+    }
+
+    /// Parses an identifier without a disambiguator, returning its bytes.
+    fn undisambiguated_identifier(&mut self) -> Option<&'a str> {
+        // Punycode identifiers are kept encoded.
+        self.eat(b'u');
+
+        // A length of zero is a lone `0`, so no other length begins with one.
+        let start = self.position;
+        if !self.eat(b'0') {
+            while self.peek()?.is_ascii_digit() {
+                self.position += 1;
+            }
+        }
+        let len = std::str::from_utf8(&self.symbol[start..self.position])
+            .ok()?
+            .parse::<usize>()
+            .ok()?;
+        self.eat(b'_');
+
+        let bytes = self
+            .symbol
+            .get(self.position..self.position.checked_add(len)?)?;
+        self.position += len;
+        std::str::from_utf8(bytes).ok()
+    }
+
+    /// Parses and prints a path.
+    fn path(&mut self) -> Option<()> {
+        self.nested(|parser| match parser.next()? {
+            b'C' => {
+                parser.disambiguator()?;
+                let name = parser.undisambiguated_identifier()?;
+                parser.out.push_str(name);
+                Some(())
+            }
+            b'N' => {
+                let namespace = parser.next()?;
+                parser.path()?;
+                let disambiguator = parser.disambiguator()?;
+                let name = parser.undisambiguated_identifier()?;
+                parser.out.push_str("::");
+                if namespace.is_ascii_lowercase() {
+                    parser.out.push_str(name);
+                    return Some(());
+                }
+
+                // Items in special namespaces, such as closures, are printed with their index.
+                match namespace {
+                    b'C' => parser.out.push_str("{closure"),
+                    b'S' => parser.out.push_str("{shim"),
+                    _ => parser.out.push_str(&format!("{{{}", namespace as char)),
+                }
+                if !name.is_empty() {
+                    parser.out.push(':');
+                    parser.out.push_str(name);
+                }
+                parser.out.push_str(&format!("#{disambiguator}}}"));
+                Some(())
+            }
+            b'M' => {
+                parser.disambiguator()?;
+                parser.skip_path()?;
+                parser.out.push('<');
+                parser.ty()?;
+                parser.out.push('>');
+                Some(())
+            }
+            b'X' => {
+                parser.disambiguator()?;
+                parser.skip_path()?;
+                parser.out.push('<');
+                parser.ty()?;
+                parser.out.push_str(" as ");
+                parser.as_type(Self::path)?;
+                parser.out.push('>');
+                Some(())
+            }
+            b'Y' => {
+                parser.out.push('<');
+                parser.ty()?;
+                parser.out.push_str(" as ");
+                parser.as_type(Self::path)?;
+                parser.out.push('>');
+                Some(())
+            }
+            b'I' => {
+                parser.path()?;
+                parser
+                    .out
+                    .push_str(if parser.in_type { "<" } else { "::<" });
+                parser.as_type(Self::generic_args)?;
+                parser.out.push('>');
+                Some(())
+            }
+            b'B' => parser.backref(Self::path),
+            _ => None,
+        })
+    }
+
+    /// Parses a path without printing it, as is done for the paths of impl blocks.
+    fn skip_path(&mut self) -> Option<()> {
+        let len = self.out.len();
+        self.path()?;
+        self.out.truncate(len);
+        Some(())
+    }
+
+    /// Parses and prints generic arguments up to the terminating `E`.
+    fn generic_args(&mut self) -> Option<()> {
+        let mut first = true;
+        while !self.eat(b'E') {
+            if self.eat(b'L') {
+                self.base62()?;
+                continue;
+            }
+
+            if !first {
+                self.out.push_str(", ");
+            }
+            first = false;
+
+            if self.eat(b'K') {
+                self.constant()?;
+            } else {
+                self.ty()?;
+            }
+        }
+
+        Some(())
+    }
+
+    /// Parses and prints a type.
+    fn ty(&mut self) -> Option<()> {
+        self.as_type(Self::unqualified_ty)
+    }
+
+    /// Parses and prints a type, without changing whether a type is being printed.
+    fn unqualified_ty(&mut self) -> Option<()> {
+        let byte = self.peek()?;
+        if let Some(name) = basic_type(byte) {
+            self.position += 1;
+            self.out.push_str(name);
+            return Some(());
+        }
+
+        self.nested(|parser| match byte {
+            b'R' | b'Q' => {
+                parser.position += 1;
+                if parser.eat(b'L') {
+                    parser.base62()?;
+                }
+                parser
+                    .out
+                    .push_str(if byte == b'R' { "&" } else { "&mut " });
+                parser.ty()
+            }
+            b'P' | b'O' => {
+                parser.position += 1;
+                parser
+                    .out
+                    .push_str(if byte == b'P' { "*const " } else { "*mut " });
+                parser.ty()
+            }
+            b'A' => {
+                parser.position += 1;
+                parser.out.push('[');
+                parser.ty()?;
+                parser.out.push_str("; ");
+                parser.constant()?;
+                parser.out.push(']');
+                Some(())
+            }
+            b'S' => {
+                parser.position += 1;
+                parser.out.push('[');
+                parser.ty()?;
+                parser.out.push(']');
+                Some(())
+            }
+            b'T' => {
+                parser.position += 1;
+                parser.out.push('(');
+                let mut count = 0;
+                while !parser.eat(b'E') {
+                    if count != 0 {
+                        parser.out.push_str(", ");
+                    }
+                    parser.ty()?;
+                    count += 1;
+                }
+                if count == 1 {
+                    parser.out.push(',');
+                }
+                parser.out.push(')');
+                Some(())
+            }
+            b'F' => {
+                parser.position += 1;
+                parser.fn_signature()
+            }
+            b'D' => {
+                parser.position += 1;
+                parser.dyn_bounds()
+            }
+            b'B' => {
+                parser.position += 1;
+                parser.backref(Self::ty)
+            }
+            _ => parser.path(),
+        })
+    }
+
+    /// Parses and prints a function pointer type, after its `F`.
+    fn fn_signature(&mut self) -> Option<()> {
+        if self.eat(b'G') {
+            self.base62()?;
+        }
+        if self.eat(b'U') {
+            self.out.push_str("unsafe ");
+        }
+        if self.eat(b'K') {
+            if self.eat(b'C') {
+                self.out.push_str("extern \"C\" ");
+            } else {
+                let abi = self.undisambiguated_identifier()?;
+                self.out
+                    .push_str(&format!("extern \"{}\" ", abi.replace('_', "-")));
+            }
+        }
+
+        self.out.push_str("fn(");
+        let mut first = true;
+        while !self.eat(b'E') {
+            if !first {
+                self.out.push_str(", ");
+            }
+            first = false;
+            self.ty()?;
+        }
+        self.out.push(')');
+
+        if self.eat(b'u') {
+            return Some(());
+        }
+        self.out.push_str(" -> ");
+        self.ty()
+    }
+
+    /// Parses and prints the bounds of a trait object type, after its `D`.
+    fn dyn_bounds(&mut self) -> Option<()> {
+        if self.eat(b'G') {
+            self.base62()?;
+        }
+
+        self.out.push_str("dyn ");
+        let mut first = true;
+        while !self.eat(b'E') {
+            if !first {
+                self.out.push_str(" + ");
+            }
+            first = false;
+
+            self.path()?;
+            while self.eat(b'p') {
+                let name = self.undisambiguated_identifier()?;
+                self.out.push_str(&format!("<{name} = "));
+                self.ty()?;
+                self.out.push('>');
+            }
+        }
+
+        // The lifetime bound.
+        if !self.eat(b'L') {
+            return None;
+        }
+        self.base62()?;
+
+        Some(())
+    }
+
+    /// Parses and prints a constant generic argument.
+    fn constant(&mut self) -> Option<()> {
+        if self.eat(b'p') {
+            self.out.push('_');
+            return Some(());
+        }
+        if self.eat(b'B') {
+            return self.backref(Self::constant);
+        }
+
+        let ty = self.next()?;
+        let negative = self.eat(b'n');
+        let start = self.position;
+        while self.peek()? != b'_' {
+            self.position += 1;
+        }
+        let digits = std::str::from_utf8(&self.symbol[start..self.position]).ok()?;
+        self.position += 1;
+
+        let value = if digits.is_empty() {
+            0
+        } else {
+            u128::from_str_radix(digits, 16).ok()?
+        };
+        match ty {
+            b'b' => self.out.push_str(if value == 0 { "false" } else { "true" }),
+            b'c' => self.out.push(char::from_u32(u32::try_from(value).ok()?)?),
+            _ if basic_type(ty).is_some() => {
+                if negative {
+                    self.out.push('-');
+                }
+                self.out.push_str(&value.to_string());
+            }
+            _ => return None,
+        }
+
+        Some(())
+    }
+
+    /// Parses a back reference, after its `B`, and prints what it refers to with `parse`.
+    fn backref(&mut self, parse: fn(&mut Self) -> Option<()>) -> Option<()> {
+        let start = self.position - 1;
+        let target = usize::try_from(self.base62()?).ok()?.checked_add(2)?;
+        // Back references only point backwards, which guarantees progress.
+        if target >= start {
+            return None;
+        }
+
+        let resume = self.position;
+        self.position = target;
+        let result = parse(self);
+        self.position = resume;
+        result
+    }
+
+    /// Runs `parse` in the context of a type, where generic arguments need no turbofish.
+    fn as_type(&mut self, parse: fn(&mut Self) -> Option<()>) -> Option<()> {
+        let in_type = core::mem::replace(&mut self.in_type, true);
+        let result = parse(self);
+        self.in_type = in_type;
+        result
+    }
+
+    /// Runs `parse` one level deeper, failing if [`MAX_DEPTH`] is exceeded.
+    fn nested(&mut self, parse: impl FnOnce(&mut Self) -> Option<()>) -> Option<()> {
+        if self.depth == MAX_DEPTH {
+            return None;
+        }
+
+        self.depth += 1;
+        let result = parse(self);
+        self.depth -= 1;
+        result
+    }
+}
+
+/// Returns the name of the basic type encoded as `byte`, if it is one.
+fn basic_type(byte: u8) -> Option<&'static str> {
+    Some(match byte {
+        b'a' => "i8",
+        b'b' => "bool",
+        b'c' => "char",
+        b'd' => "f64",
+        b'e' => "str",
+        b'f' => "f32",
+        b'h' => "u8",
+        b'i' => "isize",
+        b'j' => "usize",
+        b'l' => "i32",
+        b'm' => "u32",
+        b'n' => "i128",
+        b'o' => "u128",
+        b'p' => "_",
+        b's' => "i16",
+        b't' => "u16",
+        b'u' => "()",
+        b'v' => "...",
+        b'x' => "i64",
+        b'y' => "u64",
+        b'z' => "!",
+        _ => return None,
+    })
+}
@@ -0,0 +1,210 @@
+//! Partitioning of disk images with a GUID Partition Table.
+//!
+//! Only the layout `xtask` needs is supported: a protective MBR, followed by a GPT with a single
+//! EFI System Partition starting at 1 MiB, and the backup GPT at the end of the disk.
+
+use std::{
+    hash::{BuildHasher, Hasher, RandomState},
+    io::{self, Seek, SeekFrom, Write},
+};
+
+/// The size, in bytes, of a sector.
+pub const SECTOR_SIZE: u64 = 512;
+/// The first sector of the EFI System Partition, which aligns it to 1 MiB.
+const PARTITION_START_LBA: u64 = 2048;
+/// The number of sectors disks and partitions are rounded to, which is 1 MiB.
+const ALIGNMENT_SECTORS: u64 = 2048;
+
+/// The number of entries in each partition entry array.
+const PARTITION_ENTRY_COUNT: u32 = 128;
+/// The size, in bytes, of a partition entry.
+const PARTITION_ENTRY_SIZE: u32 = 128;
+/// The number of sectors taken by a partition entry array.
+const PARTITION_ARRAY_SECTORS: u64 =
+    (PARTITION_ENTRY_COUNT * PARTITION_ENTRY_SIZE) as u64 / SECTOR_SIZE;
+/// The size, in bytes, of the GPT header.
+const HEADER_SIZE: u32 = 92;
+
+/// The partition type GUID of an EFI System Partition, `C12A7328-F81F-11D2-BA4B-00A0C93EC93B`,
+/// in its on-disk encoding.
+const ESP_TYPE_GUID: [u8; 16] = [
+    0x28, 0x73, 0x2A, 0xC1, 0x1F, 0xF8, 0xD2, 0x11, 0xBA, 0x4B, 0x00, 0xA0, 0xC9, 0x3E, 0xC9, 0x3B,
+];
+/// The name given to the EFI System Partition.
+const ESP_NAME: &str = "EFI System Partition";
+
+/// The layout of a disk holding a single EFI System Partition.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub struct EspDisk {
+    /// The number of sectors on the disk.
+    total_sectors: u64,
+    /// The number of sectors in the EFI System Partition.
+    partition_sectors: u64,
+    /// The GUID of the disk.
+    disk_guid: [u8; 16],
+    /// The GUID of the EFI System Partition.
+    partition_guid: [u8; 16],
+}
+
+impl EspDisk {
+    /// Creates the layout of a disk whose EFI System Partition holds at least `partition_size`
+    /// bytes, with fresh GUIDs.
+    pub fn new(partition_size: u64) -> Self {
+        let partition_sectors = partition_size
+            .div_ceil(SECTOR_SIZE)
+            .next_multiple_of(ALIGNMENT_SECTORS);
+        // The backup partition entry array and header follow the partition.
+        let total_sectors = PARTITION_START_LBA + partition_sectors + PARTITION_ARRAY_SECTORS + 1;
+
+        Self {
+            total_sectors: total_sectors.next_multiple_of(ALIGNMENT_SECTORS),
+            partition_sectors,
+            disk_guid: random_guid(),
+            partition_guid: random_guid(),
+        }
+    }
+
+    /// Returns the size of the disk, in bytes.
+    pub fn size(&self) -> u64 {
+        self.total_sectors * SECTOR_SIZE
+    }
+
+    /// Returns the offset of the EFI System Partition from the start of the disk, in bytes.
+    pub fn partition_offset(&self) -> u64 {
+        PARTITION_START_LBA * SECTOR_SIZE
+    }
+
+    /// Returns the number of sectors in the EFI System Partition.
+    pub fn partition_sectors(&self) -> u64 {
+        self.partition_sectors
+    }
+
+    /// Returns the first sector of the EFI System Partition.
+    pub fn partition_start_lba(&self) -> u64 {
+        PARTITION_START_LBA
+    }
+
+    /// Sizes `disk` to fit this layout and writes the protective MBR and both GPTs to it.
+    ///
+    /// The contents of the partition are left untouched.
+    pub fn write<W: Write + Seek>(&self, disk: &mut W) -> io::Result<()> {
+        let last_lba = self.total_sectors - 1;
+        let backup_array_lba = last_lba - PARTITION_ARRAY_SECTORS;
+
+        let entries = self.partition_entries();
+        let entries_crc = crc32(&entries);
+
+        disk.seek(SeekFrom::Start(0))?;
+        disk.write_all(&self.protective_mbr())?;
+        disk.write_all(&self.header(1, last_lba, 2, entries_crc))?;
+        disk.write_all(&entries)?;
+
+        disk.seek(SeekFrom::Start(backup_array_lba * SECTOR_SIZE))?;
+        disk.write_all(&entries)?;
+        disk.write_all(&self.header(last_lba, 1, backup_array_lba, entries_crc))?;
+
+        Ok(())
+    }
+
+    /// Returns the protective MBR, which covers the whole disk with a single partition so that
+    /// tools unaware of GPT leave it alone.
+    fn protective_mbr(&self) -> [u8; SECTOR_SIZE as usize] {
+        let mut mbr = [0; SECTOR_SIZE as usize];
+
+        let entry = &mut mbr[446..462];
+        // The CHS start address, which is cylinder 0, head 0, sector 2.
+        entry[1..4].copy_from_slice(&[0x00, 0x02, 0x00]);
+        entry[4] = 0xEE;
+        // The CHS end address, which is saturated.
+        entry[5..8].copy_from_slice(&[0xFF, 0xFF, 0xFF]);
+        entry[8..12].copy_from_slice(&1u32.to_le_bytes());
+        let sectors = u32::try_from(self.total_sectors - 1).unwrap_or(u32::MAX);
+        entry[12..16].copy_from_slice(&sectors.to_le_bytes());
+
+        mbr[510..512].copy_from_slice(&[0x55, 0xAA]);
+        mbr
+    }
+
+    /// Returns the sector holding the GPT header located at `current_lba`.
+    fn header(
+        &self,
+        current_lba: u64,
+        backup_lba: u64,
+        entries_lba: u64,
+        entries_crc: u32,
+    ) -> [u8; SECTOR_SIZE as usize] {
+        let first_usable_lba = 2 + PARTITION_ARRAY_SECTORS;
+        let last_usable_lba = self.total_sectors - 2 - PARTITION_ARRAY_SECTORS;
+
+        let mut header = [0; SECTOR_SIZE as usize];
+        header[0..8].copy_from_slice(b"EFI PART");
+        header[8..12].copy_from_slice(&0x0001_0000u32.to_le_bytes());
+        header[12..16].copy_from_slice(&HEADER_SIZE.to_le_bytes());
+        header[24..32].copy_from_slice(&current_lba.to_le_bytes());
+        header[32..40].copy_from_slice(&backup_lba.to_le_bytes());
+        header[40..48].copy_from_slice(&first_usable_lba.to_le_bytes());
+        header[48..56].copy_from_slice(&last_usable_lba.to_le_bytes());
+        header[56..72].copy_from_slice(&self.disk_guid);
+        header[72..80].copy_from_slice(&entries_lba.to_le_bytes());
+        header[80..84].copy_from_slice(&PARTITION_ENTRY_COUNT.to_le_bytes());
+        header[84..88].copy_from_slice(&PARTITION_ENTRY_SIZE.to_le_bytes());
+        header[88..92].copy_from_slice(&entries_crc.to_le_bytes());
+
+        // The checksum covers the header with the checksum field zeroed.
+        let header_crc = crc32(&header[..HEADER_SIZE as usize]);
+        header[16..20].copy_from_slice(&header_crc.to_le_bytes());
+
+        header
+    }
+
+    /// Returns the partition entry array, whose first entry is the EFI System Partition.
+    fn partition_entries(&self) -> Vec<u8> {
+        let mut entries = vec![0; (PARTITION_ENTRY_COUNT * PARTITION_ENTRY_SIZE) as usize];
+
+        let last_lba = PARTITION_START_LBA + self.partition_sectors - 1;
+        let entry = &mut entries[..PARTITION_ENTRY_SIZE as usize];
+        entry[0..16].copy_from_slice(&ESP_TYPE_GUID);
+        entry[16..32].copy_from_slice(&self.partition_guid);
+        entry[32..40].copy_from_slice(&PARTITION_START_LBA.to_le_bytes());
+        entry[40..48].copy_from_slice(&last_lba.to_le_bytes());
+        for (index, unit) in ESP_NAME.encode_utf16().enumerate() {
+            let offset = 56 + index * 2;
+            entry[offset..offset + 2].copy_from_slice(&unit.to_le_bytes());
+        }
+
+        entries
+    }
+}
+
+/// Returns a random version 4 GUID in its on-disk encoding.
+fn random_guid() -> [u8; 16] {
+    let mut guid = [0; 16];
+    for half in guid.chunks_exact_mut(8) {
+        // Each `RandomState` is seeded differently, so this needs no external source of
+        // randomness.
+        let random = RandomState::new().build_hasher().finish();
+        half.copy_from_slice(&random.to_le_bytes());
+    }
+
+    // The version lives in the high nibble of the little-endian third field.
+    guid[7] = (guid[7] & 0x0F) | 0x40;
+    guid[8] = (guid[8] & 0x3F) | 0x80;
+    guid
+}
+
+/// Returns the CRC-32 of `bytes`, as used by GPT.
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+
+    !crc
+}
@@ -0,0 +1,80 @@
+//! Interception of Ctrl-C while QEMU runs.
+//!
+//! Ctrl-C normally terminates `xtask` immediately, which can leave a QEMU process behind that
+//! nothing reaps. While an [`InterruptGuard`] is alive, the interrupt is recorded instead, so that
+//! the caller can kill QEMU, wait for it, and only then return.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Whether an interrupt arrived while an [`InterruptGuard`] was alive.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Records interrupts instead of terminating the process until it is dropped.
+pub struct InterruptGuard {
+    /// The disposition of `SIGINT` before the guard was created.
+    #[cfg(unix)]
+    previous: usize,
+}
+
+impl InterruptGuard {
+    /// Starts recording interrupts.
+    pub fn new() -> Self {
+        INTERRUPTED.store(false, Ordering::Relaxed);
+
+        #[cfg(unix)]
+        {
+            // SAFETY:
+            // `record_interrupt` only stores to an atomic, which is async-signal-safe.
+            let previous =
+                unsafe { sys::signal(sys::SIGINT, record_interrupt as *const () as usize) };
+            Self { previous }
+        }
+
+        #[cfg(not(unix))]
+        Self {}
+    }
+
+    /// Returns `true` if an interrupt arrived since the guard was created.
+    pub fn interrupted(&self) -> bool {
+        INTERRUPTED.load(Ordering::Relaxed)
+    }
+}
+
+impl Default for InterruptGuard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for InterruptGuard {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        if self.previous != sys::SIG_ERR {
+            // SAFETY:
+            // `previous` was the disposition of `SIGINT` before the guard replaced it.
+            unsafe { sys::signal(sys::SIGINT, self.previous) };
+        }
+    }
+}
+
+/// Records that an interrupt arrived.
+#[cfg(unix)]
+extern "C" fn record_interrupt(_signal: core::ffi::c_int) {
+    INTERRUPTED.store(true, Ordering::Relaxed);
+}
+
+/// The parts of the C library used to intercept interrupts.
+#[cfg(unix)]
+mod sys {
+    use core::ffi::c_int;
+
+    /// The number of the signal sent by Ctrl-C.
+    pub const SIGINT: c_int = 2;
+    /// The value returned by `signal` when it fails.
+    pub const SIG_ERR: usize = usize::MAX;
+
+    extern "C" {
+        /// Sets the disposition of `signum` to `handler`, returning the previous disposition.
+        pub fn signal(signum: c_int, handler: usize) -> usize;
+    }
+}
@@ -0,0 +1,195 @@
+//! Fetching of the Limine bootloader used by `run-limine`.
+//!
+//! The binary release of [`LIMINE_VERSION`] is cloned into `run/limine/<version>/` the first time
+//! it is needed, so bumping the version fetches the new release while older ones stay cached.
+//! Booting under BIOS additionally needs `limine-bios.sys` and the `limine` tool, which installs
+//! the boot sector into a disk image and is built from the release's source, and booting from a
+//! CD needs the El Torito boot images.
+
+use std::{
+    fmt, io,
+    path::{Path, PathBuf},
+};
+
+use crate::{cli::Arch, run_cmd, OutputMode, RunCommandError};
+
+/// The version of Limine that is fetched, which must support the kernel's base revision.
+pub const LIMINE_VERSION: &str = "v8.4.0";
+
+/// The repository the Limine binary release is cloned from.
+const LIMINE_REPOSITORY: &str = "https://github.com/limine-bootloader/limine.git";
+
+/// Returns the path to the UEFI executable of Limine [`LIMINE_VERSION`] for `arch`, cloning the
+/// binary release first if it is not cached. Nothing is cloned if `offline` is `true`.
+///
+/// # Errors
+/// Returns [`FetchLimineError`] if Limine is not cached and cannot be cloned, or the release lacks
+/// an executable for `arch`.
+pub fn fetch_limine(arch: Arch, offline: bool) -> Result<PathBuf, FetchLimineError> {
+    let release = Path::new("run").join("limine").join(LIMINE_VERSION);
+    let executable = release.join(arch.uefi_boot_file_name());
+    if executable.is_file() {
+        return Ok(executable);
+    }
+
+    if offline {
+        return Err(FetchLimineError::NotCached);
+    }
+
+    // A clone that was interrupted leaves a directory behind that `git` refuses to clone into.
+    if release.exists() {
+        std::fs::remove_dir_all(&release).map_err(FetchLimineError::RemovePartialClone)?;
+    }
+
+    let mut cmd = std::process::Command::new("git");
+    cmd.args(["clone", "--depth", "1", "--branch"]);
+    cmd.arg(format!("{LIMINE_VERSION}-binary"));
+    cmd.arg(LIMINE_REPOSITORY);
+    cmd.arg(&release);
+    run_cmd(cmd, OutputMode::Capture).map_err(FetchLimineError::CloneError)?;
+
+    if !executable.is_file() {
+        return Err(FetchLimineError::MissingExecutable(executable));
+    }
+
+    Ok(executable)
+}
+
+/// The files needed to boot a disk image using Limine under BIOS.
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+pub struct BiosFiles {
+    /// The path to `limine-bios.sys`, which must be copied into the boot partition.
+    pub stage3: PathBuf,
+    /// The path to the `limine` tool, whose `bios-install` command writes the boot sector.
+    pub tool: PathBuf,
+}
+
+/// Returns the files needed to boot using Limine under BIOS, taken from the directory holding
+/// the UEFI executable at `executable`, which is where both the binary release and distribution
+/// packages keep them.
+///
+/// The `limine` tool is built using `make` if that directory holds its source, and is otherwise
+/// expected to be installed.
+///
+/// # Errors
+/// Returns [`LimineFilesError`] if `limine-bios.sys` is missing or the tool cannot be built.
+pub fn bios_files(executable: &Path) -> Result<BiosFiles, LimineFilesError> {
+    let directory = executable.parent().unwrap_or(Path::new("."));
+
+    let stage3 = existing_file(directory.join("limine-bios.sys"))?;
+
+    if !directory.join("limine.c").is_file() {
+        return Ok(BiosFiles {
+            stage3,
+            tool: PathBuf::from("limine"),
+        });
+    }
+
+    let tool = directory.join("limine");
+    if !tool.is_file() {
+        let mut cmd = std::process::Command::new("make");
+        cmd.arg("-C").arg(directory);
+        run_cmd(cmd, OutputMode::Capture).map_err(LimineFilesError::BuildToolError)?;
+    }
+
+    Ok(BiosFiles { stage3, tool })
+}
+
+/// The El Torito boot images needed to boot an ISO using Limine.
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+pub struct CdFiles {
+    /// The path to `limine-bios-cd.bin`, which BIOS boots from the CD.
+    pub bios: PathBuf,
+    /// The path to `limine-uefi-cd.bin`, the FAT image holding the UEFI executable that UEFI
+    /// boots from the CD.
+    pub uefi: PathBuf,
+}
+
+/// Returns the El Torito boot images of Limine, taken from the directory holding the UEFI
+/// executable at `executable`.
+///
+/// # Errors
+/// Returns [`LimineFilesError`] if either image is missing.
+pub fn cd_files(executable: &Path) -> Result<CdFiles, LimineFilesError> {
+    let directory = executable.parent().unwrap_or(Path::new("."));
+
+    Ok(CdFiles {
+        bios: existing_file(directory.join("limine-bios-cd.bin"))?,
+        uefi: existing_file(directory.join("limine-uefi-cd.bin"))?,
+    })
+}
+
+/// Returns `path` if it is a file.
+fn existing_file(path: PathBuf) -> Result<PathBuf, LimineFilesError> {
+    if !path.is_file() {
+        return Err(LimineFilesError::MissingFile(path));
+    }
+
+    Ok(path)
+}
+
+/// Various errors that can occur while locating the files of Limine that are needed besides its
+/// UEFI executable.
+#[derive(Debug)]
+pub enum LimineFilesError {
+    /// The file at the given path does not exist.
+    MissingFile(PathBuf),
+    /// An error occurred while building the `limine` tool.
+    BuildToolError(RunCommandError),
+}
+
+impl fmt::Display for LimineFilesError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingFile(path) => {
+                write!(f, "Limine file \"{}\" does not exist", path.display())
+            }
+            Self::BuildToolError(error) => {
+                write!(
+                    f,
+                    "error occurred while building the `limine` tool: {error}"
+                )
+            }
+        }
+    }
+}
+
+/// Various errors that can occur while fetching Limine.
+#[derive(Debug)]
+pub enum FetchLimineError {
+    /// Limine is not cached, and fetching it was disabled.
+    NotCached,
+    /// An error occurred while removing an incomplete clone.
+    RemovePartialClone(io::Error),
+    /// An error occurred while cloning the binary release.
+    CloneError(RunCommandError),
+    /// The binary release does not contain the expected executable.
+    MissingExecutable(PathBuf),
+}
+
+impl fmt::Display for FetchLimineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotCached => write!(
+                f,
+                "Limine {LIMINE_VERSION} is not cached; pass `--limine`, or drop `--offline` to \
+                fetch it"
+            ),
+            Self::RemovePartialClone(error) => write!(
+                f,
+                "error occurred while removing incomplete clone of Limine {LIMINE_VERSION}: \
+                {error}"
+            ),
+            Self::CloneError(error) => write!(
+                f,
+                "error occurred while cloning Limine {LIMINE_VERSION} from \
+                {LIMINE_REPOSITORY}: {error}"
+            ),
+            Self::MissingExecutable(path) => write!(
+                f,
+                "Limine {LIMINE_VERSION} binary release has no \"{}\"",
+                path.display()
+            ),
+        }
+    }
+}
@@ -4,366 +4,2262 @@ use std::{
     ffi::OsString,
     fmt, io,
     path::{Path, PathBuf},
+    process::ExitStatus,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        OnceLock,
+    },
+    time::{Duration, Instant},
 };
 
-use cli::{parse_arguments, Action, Arch, BuildArguments, Features, RunArguments};
+use cli::{
+    parse_arguments, Accelerator, Action, Arch, Arguments, BootFirmware, BootModule,
+    BuildArguments, Features, OutputFormat, RunArguments, RunMode, Verbosity,
+};
 
+pub mod accel;
+pub mod cargo_messages;
 pub mod cli;
+pub mod demangle;
+pub mod gpt;
+pub mod interrupt;
+pub mod limine;
+pub mod ovmf;
+pub mod snapshot;
+pub mod symbols;
+
+/// Whether stdout is reserved for machine-readable output, in which case [`status!`] prints to
+/// stderr instead.
+static STDOUT_RESERVED: AtomicBool = AtomicBool::new(false);
+
+/// Prints a message about the progress of `xtask` like [`println!`], unless stdout is reserved
+/// for machine-readable output, in which case it is printed to stderr.
+macro_rules! status {
+    ($($arg:tt)*) => {
+        if STDOUT_RESERVED.load(Ordering::Relaxed) {
+            eprintln!($($arg)*);
+        } else {
+            println!($($arg)*);
+        }
+    };
+}
 
 fn main() {
-    match parse_arguments() {
-        Action::Build(args) => match build(args) {
+    let Arguments { verbosity, action } = match parse_arguments() {
+        Ok(arguments) => arguments,
+        Err(error) => {
+            eprintln!("{error}");
+            std::process::exit(1);
+        }
+    };
+    let _ = VERBOSITY.set(verbosity);
+
+    match action {
+        Action::Build {
+            build_arguments,
+            output_format: OutputFormat::Human,
+        } => match build(&build_arguments) {
             Ok(path) => println!("kernel located at \"{}\"", path.display()),
             Err(error) => {
-                eprintln!("{error:?}");
+                eprintln!("{error}");
+                std::process::exit(1);
             }
         },
+        Action::Build {
+            build_arguments,
+            output_format: OutputFormat::Json,
+        } => {
+            STDOUT_RESERVED.store(true, Ordering::Relaxed);
+            match build(&build_arguments) {
+                Ok(path) => println!("{}", build_json(&build_arguments, &path)),
+                Err(error) => {
+                    println!("{{\"error\":{}}}", json_string(&error.to_string()));
+                    std::process::exit(1);
+                }
+            }
+        }
         Action::RunLimine {
             build_arguments,
             run_arguments,
+            kernel,
             limine_path,
-        } => match run_limine(build_arguments, run_arguments, limine_path) {
+            from_iso,
+        } => match prebuilt_or_build(&build_arguments, kernel)
+            .map_err(RunLimineError::from)
+            .and_then(|kernel_path| {
+                run_limine(
+                    build_arguments,
+                    run_arguments,
+                    &kernel_path,
+                    limine_path,
+                    from_iso,
+                )
+            }) {
             Ok(_) => {}
             Err(error) => {
                 eprintln!("{error}");
             }
         },
+        Action::Iso {
+            build_arguments,
+            limine_path,
+            offline,
+            output,
+        } => match iso(build_arguments, limine_path, offline, &output) {
+            Ok(path) => println!("{}", path.display()),
+            Err(error) => {
+                eprintln!("{error}");
+                std::process::exit(1);
+            }
+        },
         Action::RunBootStub {
             build_arguments,
             run_arguments,
-        } => match run_boot_stub(build_arguments, run_arguments) {
+            kernel,
+            modules,
+        } => match check_boot_modules(&modules)
+            .and_then(|()| Ok(prebuilt_or_build(&build_arguments, kernel)?))
+            .and_then(|kernel_path| {
+                run_boot_stub(build_arguments, run_arguments, &kernel_path, &modules)
+            }) {
             Ok(_) => {}
             Err(error) => {
                 eprintln!("{error}");
             }
         },
+        Action::Check {
+            build_arguments,
+            feature_sets,
+        } => match check(build_arguments, &feature_sets) {
+            Ok(()) => println!("all {} feature sets passed", feature_sets.len()),
+            Err(error) => {
+                eprintln!("{error}");
+                std::process::exit(1);
+            }
+        },
+        Action::Matrix {
+            build_arguments,
+            fail_fast,
+        } => {
+            let report = matrix(&build_arguments, fail_fast);
+            println!("{report}");
+            if report.failed() {
+                std::process::exit(1);
+            }
+        }
+        Action::Size {
+            build_arguments,
+            baseline_features,
+            forbidden_strings,
+        } => match size(build_arguments, baseline_features, &forbidden_strings) {
+            Ok(report) => println!("{report}"),
+            Err(error) => {
+                eprintln!("{error}");
+                std::process::exit(1);
+            }
+        },
+        Action::Image {
+            build_arguments,
+            limine_path,
+            output,
+        } => match image(build_arguments, limine_path, &output) {
+            Ok(path) => println!("{}", path.display()),
+            Err(error) => {
+                eprintln!("{error}");
+                std::process::exit(1);
+            }
+        },
+        Action::Test {
+            build_arguments,
+            run_arguments,
+        } => {
+            let serial_log = serial_log_path(build_arguments.arch, &run_arguments);
+            match run_tests(build_arguments, run_arguments) {
+                Ok(()) => println!("kernel reported success"),
+                Err(error) => {
+                    eprintln!("{error}");
+                    // The log only exists once QEMU has been started.
+                    if let (Some(serial_log), RunBootStubError::QemuError(_)) = (serial_log, error)
+                    {
+                        eprintln!("serial log at \"{}\"", serial_log.display());
+                    }
+                    std::process::exit(1);
+                }
+            }
+        }
+        Action::Snapshot {
+            build_arguments,
+            run_arguments,
+            update,
+        } => match snapshot(build_arguments, run_arguments, update) {
+            Ok(SnapshotOutcome::Matched(path)) => {
+                println!("serial output matches \"{}\"", path.display())
+            }
+            Ok(SnapshotOutcome::Updated(path)) => println!("updated \"{}\"", path.display()),
+            Err(error) => {
+                eprintln!("{error}");
+                std::process::exit(1);
+            }
+        },
+        Action::HostTest { features } => match host_test(features) {
+            Ok(()) => {}
+            Err(error) => {
+                eprintln!("{error}");
+                std::process::exit(1);
+            }
+        },
+        Action::Clean { arch, all } => match clean(arch, all) {
+            Ok(()) => {}
+            Err(error) => {
+                eprintln!("{error}");
+                std::process::exit(1);
+            }
+        },
     };
 }
 
 /// Builds the Capora kernel.
-pub fn build(arguments: BuildArguments) -> Result<PathBuf, BuildError> {
+pub fn build(arguments: &BuildArguments) -> Result<PathBuf, BuildError> {
+    build_with(arguments, OutputMode::Stream)
+}
+
+/// Returns the path of `prebuilt` if it is given, and otherwise builds the Capora kernel.
+pub fn prebuilt_or_build(
+    arguments: &BuildArguments,
+    prebuilt: Option<PathBuf>,
+) -> Result<PathBuf, BuildError> {
+    match prebuilt {
+        Some(path) => Ok(path),
+        None => build(arguments),
+    }
+}
+
+/// Builds the Capora kernel, treating the diagnostics of `cargo` according to `mode`.
+fn build_with(arguments: &BuildArguments, mode: OutputMode) -> Result<PathBuf, BuildError> {
     let mut cmd = std::process::Command::new("cargo");
     cmd.arg("build");
     cmd.args(["--package", "kernel"]);
 
     cmd.args(["--target", arguments.arch.as_target_triple()]);
-    if arguments.release {
-        cmd.arg("--release");
+    if let Some(profile) = &arguments.profile {
+        cmd.args(["--profile", profile]);
     }
 
+    if !arguments.features.default_features() {
+        cmd.arg("--no-default-features");
+    }
     let features = arguments.features.as_string();
     if features.len() != 0 {
         cmd.arg("--features").arg(features);
     }
 
-    let mut binary_location = PathBuf::with_capacity(50);
-    binary_location.push("target");
-    binary_location.push(arguments.arch.as_target_triple());
-    if arguments.release {
-        binary_location.push("release");
-    } else {
-        binary_location.push("debug");
-    }
-    binary_location.push("kernel");
+    let binary_location = build_kernel_executable(cmd, mode)?;
+    check_embedded_commit(&binary_location);
 
-    run_cmd(cmd)?;
+    // A kernel without a symbol table still works; its backtraces just show raw addresses.
+    match symbols::embed(&binary_location) {
+        Ok(cost) => status!("embedded symbol table: {cost}"),
+        Err(error) => eprintln!("warning: symbol table not embedded: {error}"),
+    }
 
     Ok(binary_location)
 }
 
-/// Various errors that can occur while building the Capora kernel.
-#[derive(Debug)]
-pub struct BuildError(RunCommandError);
-
-impl From<RunCommandError> for BuildError {
-    fn from(value: RunCommandError) -> Self {
-        Self(value)
-    }
+/// Runs `cmd`, a `cargo build` invocation that builds the kernel, and returns the path of the
+/// kernel executable that `cargo` reports, wherever its target directory and profile put it.
+fn build_kernel_executable(
+    mut cmd: std::process::Command,
+    mode: OutputMode,
+) -> Result<PathBuf, BuildError> {
+    // Diagnostics are still rendered to the terminal, while the artifacts are reported on stdout.
+    cmd.arg("--message-format=json-render-diagnostics");
+
+    let messages = run_cmd_output(cmd, mode)?;
+    cargo_messages::executable(&messages, &Path::new("kernel").join("Cargo.toml"))
+        .ok_or(BuildError::MissingExecutable)
 }
 
-impl fmt::Display for BuildError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "error while building kernel: {}", self.0)
+/// Returns the name of the directory that `cargo` places the artifacts of `profile` in, where
+/// [`None`] stands for its default `dev` profile.
+///
+/// The built-in `dev` and `test` profiles share `debug`, and `bench` shares `release`, while
+/// every other profile gets a directory of its own name.
+fn profile_directory(profile: Option<&str>) -> &str {
+    match profile {
+        None | Some("dev" | "test") => "debug",
+        Some("bench") => "release",
+        Some(profile) => profile,
     }
 }
 
-/// Builds and runs the Capora kernel using the Limine bootloader.
-pub fn run_limine(
-    mut build_args: BuildArguments,
-    run_args: RunArguments,
-    limine_path: PathBuf,
-) -> Result<(), RunLimineError> {
-    const LIMINE_CONF: &str = "\
-        timeout: 0\n\
-        \n\
-        /Capora Kernel\n\
-            \tprotocol: limine\n\
-            \tkernel_path: boot():/kernel
-    ";
-
-    build_args.features = build_args.features | Features::LIMINE_BOOT_API;
-
-    let kernel_path = build(build_args)?;
-    let fat_directory = build_fat_directory(
-        build_args.arch,
-        limine_path,
-        &[(&kernel_path, "kernel")],
-        &[(LIMINE_CONF.as_bytes(), "limine.conf")],
-    )
-    .map_err(RunLimineError::BuildFatDirectoryError)?;
-
-    run(build_args, run_args, fat_directory)?;
+/// Warns if the kernel binary at `path` does not embed the git commit that is checked out, which
+/// the kernel reports in its build summary.
+///
+/// Nothing is checked outside of a git checkout, where the kernel embeds `unknown` instead.
+fn check_embedded_commit(path: &Path) {
+    let Ok(output) = std::process::Command::new("git")
+        .args(["rev-parse", "HEAD"])
+        .output()
+    else {
+        return;
+    };
+    if !output.status.success() {
+        return;
+    }
+    let Ok(commit) = String::from_utf8(output.stdout) else {
+        return;
+    };
+    let commit = commit.trim();
+    let Ok(binary) = std::fs::read(path) else {
+        return;
+    };
 
-    Ok(())
+    if binary
+        .windows(commit.len())
+        .any(|window| window == commit.as_bytes())
+    {
+        status!("kernel built from commit {commit}");
+    } else {
+        eprintln!("warning: kernel binary does not embed the checked out commit {commit}");
+    }
 }
 
-/// Various errors that can occur while building and running the Capora kernel using the Limine
-/// bootloader.
+/// Various errors that can occur while building the Capora kernel.
 #[derive(Debug)]
-pub enum RunLimineError {
-    /// An error occurred while building the kernel.
-    BuildError(BuildError),
-    /// An error occurred while building the fat directory.
-    BuildFatDirectoryError(std::io::Error),
-    /// An error occurred while running QEMU.
-    QemuError(QemuError),
-}
-
-impl From<BuildError> for RunLimineError {
-    fn from(value: BuildError) -> Self {
-        Self::BuildError(value)
-    }
+pub enum BuildError {
+    /// An error occurred while running `cargo`.
+    CommandError(RunCommandError),
+    /// `cargo` did not report building the kernel's executable.
+    MissingExecutable,
 }
 
-impl From<QemuError> for RunLimineError {
-    fn from(value: QemuError) -> Self {
-        Self::QemuError(value)
+impl From<RunCommandError> for BuildError {
+    fn from(value: RunCommandError) -> Self {
+        Self::CommandError(value)
     }
 }
 
-impl fmt::Display for RunLimineError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Self::BuildError(error) => fmt::Display::fmt(error, f),
-            Self::BuildFatDirectoryError(error) => {
-                writeln!(f, "error occurred while building FAT directory: {error}",)
+            Self::CommandError(error) => write!(f, "error while building kernel: {error}"),
+            Self::MissingExecutable => {
+                f.write_str("error while building kernel: cargo reported no kernel executable")
             }
-            Self::QemuError(error) => fmt::Display::fmt(error, f),
         }
     }
 }
 
-/// Builds and runs the Capora kernel using `capora-boot-stub`.
-pub fn run_boot_stub(
-    mut build_args: BuildArguments,
-    run_args: RunArguments,
-) -> Result<(), RunBootStubError> {
-    build_args.features = build_args.features | Features::CAPORA_BOOT_API;
-
-    let kernel_path = build(build_args)?;
-    let fat_directory = build_fat_directory(
-        build_args.arch,
-        PathBuf::from(env!("CARGO_BIN_FILE_BOOT_STUB_boot-stub")),
-        &[],
-        &[],
+/// Describes the kernel at `kernel_path`, built with `arguments`, as a single line JSON object.
+///
+/// The profile is reported as the [name of its directory][profile_directory], so that aliases
+/// of the same profile, such as `dev` and the default, are reported alike.
+fn build_json(arguments: &BuildArguments, kernel_path: &Path) -> String {
+    let features: Vec<String> = arguments.features.iter().map(json_string).collect();
+
+    format!(
+        "{{\"kernel\":{},\"arch\":{},\"profile\":{},\"features\":[{}]}}",
+        json_string(&kernel_path.to_string_lossy()),
+        json_string(arguments.arch.as_str()),
+        json_string(profile_directory(arguments.profile.as_deref())),
+        features.join(",")
     )
-    .map_err(RunBootStubError::BuildFatDirectoryError)?;
+}
 
-    let mut cmd = std::process::Command::new(env!("CARGO_BIN_FILE_CONFIG_capora-boot-stub-ctl"));
-    cmd.arg("configure");
+/// Returns `string` as a JSON string literal.
+fn json_string(string: &str) -> String {
+    let mut literal = String::with_capacity(string.len() + 2);
+    literal.push('"');
+    for c in string.chars() {
+        match c {
+            '"' => literal.push_str("\\\""),
+            '\\' => literal.push_str("\\\\"),
+            '\n' => literal.push_str("\\n"),
+            '\r' => literal.push_str("\\r"),
+            '\t' => literal.push_str("\\t"),
+            c if c.is_control() => literal.push_str(&format!("\\u{:04x}", c as u32)),
+            c => literal.push(c),
+        }
+    }
+    literal.push('"');
+
+    literal
+}
 
-    cmd.arg("--stub")
-        .arg(fat_directory.join("EFI").join("BOOT").join("BOOTX64.EFI"));
-    cmd.arg("--application")
-        .arg(format!("kernel:embedded:{}", kernel_path.display()));
+/// Runs the tests of the Capora kernel's pure modules on the host with `features` enabled.
+///
+/// The kernel is compiled for the host, which links the standard library and the test harness in
+/// place of the kernel's entry points.
+pub fn host_test(features: Features) -> Result<(), HostTestError> {
+    let mut cmd = std::process::Command::new("cargo");
+    cmd.arg("test");
+    cmd.args(["--package", "kernel"]);
 
-    run_cmd(cmd)?;
+    let features = features.as_string();
+    if !features.is_empty() {
+        cmd.arg("--features").arg(features);
+    }
 
-    run(build_args, run_args, fat_directory)?;
+    run_cmd(cmd, OutputMode::Stream)?;
 
     Ok(())
 }
 
-/// Various errors that can occur while building and running the Capora kernel using
-/// `capora-boot-stub`.
-pub enum RunBootStubError {
-    /// An error ocurred while building the kernel.
-    BuildError(BuildError),
-    /// An error occurred while building the fat directory.
-    BuildFatDirectoryError(std::io::Error),
-    /// An error occurred while configuring `capora-boot-stub`.
-    ConfigureError(RunCommandError),
-    /// An error occurred while running QEMU.
-    QemuError(QemuError),
-}
+/// Various errors that can occur while running the host tests of the Capora kernel.
+#[derive(Debug)]
+pub struct HostTestError(RunCommandError);
 
-impl From<BuildError> for RunBootStubError {
-    fn from(value: BuildError) -> Self {
-        Self::BuildError(value)
+impl From<RunCommandError> for HostTestError {
+    fn from(value: RunCommandError) -> Self {
+        Self(value)
     }
 }
 
-impl From<RunCommandError> for RunBootStubError {
-    fn from(value: RunCommandError) -> Self {
-        Self::ConfigureError(value)
+impl fmt::Display for HostTestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "error while running host tests: {}", self.0)
     }
 }
 
-impl From<QemuError> for RunBootStubError {
-    fn from(value: QemuError) -> Self {
-        Self::QemuError(value)
+/// Removes the files generated by running the Capora kernel for `arch`, or for every architecture
+/// and the shared Limine cache if `arch` is [`None`], reporting each directory removed.
+///
+/// If `all` is `true`, `cargo clean` is also run for the kernel.
+pub fn clean(arch: Option<Arch>, all: bool) -> Result<(), CleanError> {
+    let run_directory = match arch {
+        Some(arch) => Path::new("run").join(arch.as_str()),
+        None => PathBuf::from("run"),
+    };
+    match std::fs::remove_dir_all(&run_directory) {
+        Ok(()) => println!("removed \"{}\"", run_directory.display()),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => {
+            println!("\"{}\" does not exist", run_directory.display());
+        }
+        Err(error) => return Err(CleanError::RemoveError(run_directory, error)),
+    }
+
+    if !all {
+        return Ok(());
+    }
+
+    let arches = match arch {
+        Some(arch) => vec![arch],
+        None => <Arch as clap::ValueEnum>::value_variants().to_vec(),
+    };
+    for arch in arches {
+        let mut cmd = std::process::Command::new("cargo");
+        cmd.arg("clean");
+        cmd.args(["--package", "kernel"]);
+        cmd.args(["--target", arch.as_target_triple()]);
+        run_cmd(cmd, OutputMode::Stream).map_err(CleanError::CargoCleanError)?;
     }
+
+    Ok(())
 }
 
-impl fmt::Display for RunBootStubError {
+/// Various errors that can occur while removing generated files.
+#[derive(Debug)]
+pub enum CleanError {
+    /// An error occurred while removing the given directory.
+    RemoveError(PathBuf, io::Error),
+    /// An error occurred while running `cargo clean`.
+    CargoCleanError(RunCommandError),
+}
+
+impl fmt::Display for CleanError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Self::BuildError(error) => fmt::Display::fmt(error, f),
-            Self::BuildFatDirectoryError(error) => {
-                write!(f, "error occurred while building FAT directory: {error}",)
-            }
-            Self::ConfigureError(error) => write!(
+            Self::RemoveError(path, error) => write!(
                 f,
-                "error occurred while configuring `capora-boot-stub`: {error}"
+                "error occurred while removing \"{}\": {error}",
+                path.display()
             ),
-            Self::QemuError(error) => fmt::Display::fmt(error, f),
+            Self::CargoCleanError(error) => {
+                write!(f, "error occurred while running `cargo clean`: {error}")
+            }
         }
     }
 }
 
-/// Builds and runs the Capora kernel.
-pub fn run(
-    build_args: BuildArguments,
-    run_args: RunArguments,
-    fat_directory: PathBuf,
-) -> Result<(), QemuError> {
-    let qemu_name = match build_args.arch {
-        Arch::X86_64 => "qemu-system-x86_64",
-    };
+/// Runs `cargo clippy` on the Capora kernel once for each of `feature_sets`, each combined with
+/// the features of `arguments`.
+///
+/// Every feature set is checked even if an earlier one fails, and the failures are reported
+/// together.
+pub fn check(arguments: BuildArguments, feature_sets: &[Features]) -> Result<(), CheckError> {
+    let mut failures = Vec::new();
+    for feature_set in feature_sets {
+        let mut features = arguments.features.clone();
+        features.extend(feature_set);
+
+        let mut cmd = std::process::Command::new("cargo");
+        cmd.arg("clippy");
+        cmd.args(["--package", "kernel"]);
+        cmd.args(["--target", arguments.arch.as_target_triple()]);
+        if let Some(profile) = &arguments.profile {
+            cmd.args(["--profile", profile]);
+        }
 
-    let mut cmd = std::process::Command::new(qemu_name);
+        if !features.default_features() {
+            cmd.arg("--no-default-features");
+        }
+        let features_string = features.as_string();
+        if !features_string.is_empty() {
+            cmd.arg("--features").arg(features_string);
+        }
 
-    // Disable unnecessary devices.
-    cmd.arg("-nodefaults");
+        if let Err(error) = run_cmd(cmd, OutputMode::Stream) {
+            failures.push((features, error));
+        }
+    }
 
-    cmd.args(["-boot", "menu=on,splash-time=0"]);
-    match build_args.arch {
-        Arch::X86_64 => {
-            // Use fairly modern machine to target.
-            cmd.args(["-machine", "q35"]);
-            cmd.args(["-cpu", "host,rdrand=on"]);
+    if !failures.is_empty() {
+        return Err(CheckError {
+            checked: feature_sets.len(),
+            failures,
+        });
+    }
 
-            // Allocate some memory.
-            cmd.args(["-m", "256M"]);
+    Ok(())
+}
 
-            // Use vga graphics
-            cmd.args(["-vga", "std"]);
+/// The feature sets that failed to pass [`check()`].
+#[derive(Debug)]
+pub struct CheckError {
+    /// The number of feature sets checked.
+    pub checked: usize,
+    /// The feature sets that failed and the error each failed with.
+    pub failures: Vec<(Features, RunCommandError)>,
+}
 
-            if std::env::consts::OS == "linux" {
-                cmd.arg("-enable-kvm");
-            }
+impl fmt::Display for CheckError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} of {} feature sets failed:",
+            self.failures.len(),
+            self.checked
+        )?;
+        for (features, error) in &self.failures {
+            write!(f, "\n    `{}`: {error}", features.as_string())?;
         }
+
+        Ok(())
     }
+}
 
-    let mut ovmf_code_arg = OsString::from("if=pflash,format=raw,readonly=on,file=");
-    ovmf_code_arg.push(run_args.ovmf_code);
-    cmd.arg("-drive").arg(ovmf_code_arg);
+/// The boot APIs whose combinations [`matrix()`] builds.
+const MATRIX_BOOT_APIS: &[&str] = &[
+    Features::LIMINE_BOOT_API,
+    Features::CAPORA_BOOT_API,
+    Features::MULTIBOOT2_BOOT_API,
+];
+
+/// The logging configurations [`matrix()`] crosses with each combination of boot APIs, with no
+/// features meaning that logging is disabled.
+const MATRIX_LOGGING: &[&[&str]] = &[
+    &[],
+    &[Features::LOGGING],
+    &[Features::DEBUGCON_LOGGING],
+    &[Features::SERIAL_LOGGING],
+    &[Features::FRAMEBUFFER_LOGGING],
+];
+
+/// Builds the Capora kernel once for every combination of boot APIs crossed with every logging
+/// configuration, each combined with the features of `arguments`.
+///
+/// Combinations the kernel cannot be built with are skipped rather than built. Unless
+/// `fail_fast` is set, every combination is built even if an earlier one fails.
+pub fn matrix(arguments: &BuildArguments, fail_fast: bool) -> MatrixReport {
+    let mut entries = Vec::new();
+    for boot_apis in 0..1usize << MATRIX_BOOT_APIS.len() {
+        for logging in MATRIX_LOGGING {
+            let mut features = arguments.features.clone();
+            let selected = MATRIX_BOOT_APIS
+                .iter()
+                .enumerate()
+                .filter(|(index, _)| boot_apis & (1 << index) != 0);
+            for (_, boot_api) in selected {
+                features.insert(boot_api);
+            }
+            for feature in *logging {
+                features.insert(feature);
+            }
 
-    let mut ovmf_vars_arg = OsString::from("if=pflash,format=raw,readonly=on,file=");
-    ovmf_vars_arg.push(run_args.ovmf_vars);
-    cmd.arg("-drive").arg(ovmf_vars_arg);
+            let outcome = match matrix_skip_reason(&features, arguments.arch) {
+                Some(reason) => MatrixOutcome::Skipped(reason),
+                None => {
+                    let arguments = BuildArguments {
+                        features: features.clone(),
+                        ..arguments.clone()
+                    };
+                    match build_with(&arguments, OutputMode::Capture) {
+                        Ok(_) => MatrixOutcome::Passed,
+                        Err(error) => MatrixOutcome::Failed(error),
+                    }
+                }
+            };
+
+            let stop = fail_fast && matches!(outcome, MatrixOutcome::Failed(_));
+            entries.push((features, outcome));
+            if stop {
+                return MatrixReport { entries };
+            }
+        }
+    }
 
-    let mut fat_drive_arg = OsString::from("format=raw,file=fat:rw:");
-    fat_drive_arg.push(fat_directory);
-    cmd.arg("-drive").arg(fat_drive_arg);
+    MatrixReport { entries }
+}
 
-    cmd.args(["-debugcon", "file:run/x86_64/debugcon.txt"]);
-    cmd.args(["-serial", "file:run/x86_64/serial.txt"]);
-    cmd.args(["-D", "run/x86_64/logfile.txt"]);
+/// Returns why the kernel cannot be built for `arch` with `features`, or [`None`] if it can.
+fn matrix_skip_reason(features: &Features, arch: Arch) -> Option<String> {
+    if features.require_boot_api().is_err() {
+        return Some("no boot API selected".to_owned());
+    }
 
-    cmd.args(["-monitor", "stdio"]);
+    let backends = [
+        Features::DEBUGCON_LOGGING,
+        Features::SERIAL_LOGGING,
+        Features::FRAMEBUFFER_LOGGING,
+    ];
+    if features.enables(Features::LOGGING)
+        && !backends.iter().any(|backend| features.enables(backend))
+    {
+        return Some("logging has no output backend".to_owned());
+    }
 
-    run_cmd(cmd)?;
+    features.validate(arch).err().map(|error| error.to_string())
+}
 
-    Ok(())
+/// The outcome of building the kernel with one combination of features in [`matrix()`].
+#[derive(Debug)]
+pub enum MatrixOutcome {
+    /// The kernel was built successfully.
+    Passed,
+    /// The kernel failed to build.
+    Failed(BuildError),
+    /// The kernel cannot be built with the combination, for the given reason, so it was not
+    /// attempted.
+    Skipped(String),
 }
 
-/// Various errors that can occur while running QEMU.
+/// The outcome of every combination of features built by [`matrix()`].
 #[derive(Debug)]
-pub struct QemuError(RunCommandError);
+pub struct MatrixReport {
+    /// The combinations in the order they were built, and the outcome of each.
+    pub entries: Vec<(Features, MatrixOutcome)>,
+}
 
-impl From<RunCommandError> for QemuError {
-    fn from(value: RunCommandError) -> Self {
-        Self(value)
+impl MatrixReport {
+    /// Returns `true` if any combination failed to build.
+    pub fn failed(&self) -> bool {
+        self.entries
+            .iter()
+            .any(|(_, outcome)| matches!(outcome, MatrixOutcome::Failed(_)))
     }
 }
 
-impl fmt::Display for QemuError {
+impl fmt::Display for MatrixReport {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "error while running QEMU: {}", self.0)
+        let names: Vec<String> = self
+            .entries
+            .iter()
+            .map(|(features, _)| match features.as_string() {
+                name if name.is_empty() => "(no features)".to_owned(),
+                name => name,
+            })
+            .collect();
+        let width = names.iter().map(String::len).max().unwrap_or(0);
+
+        let (mut passed, mut failed, mut skipped) = (0, 0, 0);
+        for (name, (_, outcome)) in names.iter().zip(&self.entries) {
+            let result = match outcome {
+                MatrixOutcome::Passed => {
+                    passed += 1;
+                    "passed".to_owned()
+                }
+                MatrixOutcome::Failed(_) => {
+                    failed += 1;
+                    "FAILED".to_owned()
+                }
+                MatrixOutcome::Skipped(reason) => {
+                    skipped += 1;
+                    format!("skipped: {reason}")
+                }
+            };
+            writeln!(f, "{name:<width$}  {result}")?;
+        }
+        write!(f, "{passed} passed, {failed} failed, {skipped} skipped")?;
+
+        for (name, (_, outcome)) in names.iter().zip(&self.entries) {
+            if let MatrixOutcome::Failed(error) = outcome {
+                write!(f, "\n\n`{name}`: {error}")?;
+            }
+        }
+
+        Ok(())
     }
 }
 
-/// Sets up the FAT directory used for UEFI boot.
-pub fn build_fat_directory(
-    arch: Arch,
-    loader_path: PathBuf,
-    additional_files: &[(&Path, &str)],
-    additional_binary_files: &[(&[u8], &str)],
-) -> Result<PathBuf, std::io::Error> {
-    let mut fat_directory = PathBuf::with_capacity(50);
-    fat_directory.push("run");
-    fat_directory.push(arch.as_str());
-    fat_directory.push("fat_directory");
-
-    let mut boot_directory = fat_directory.join("EFI");
-    boot_directory.push("BOOT");
-    if !boot_directory.exists() {
+/// Builds the Capora kernel and measures the size of the resulting binary.
+///
+/// If `baseline_features` is provided, a second build with those features is measured first so
+/// that the two sizes can be compared. The binary is checked to contain none of
+/// `forbidden_strings`.
+pub fn size(
+    arguments: BuildArguments,
+    baseline_features: Option<Features>,
+    forbidden_strings: &[String],
+) -> Result<SizeReport, SizeError> {
+    let baseline_size = match baseline_features {
+        Some(features) => {
+            let path = build(&BuildArguments {
+                features,
+                ..arguments.clone()
+            })?;
+            Some(std::fs::metadata(path).map_err(SizeError::ReadError)?.len())
+        }
+        None => None,
+    };
+
+    let path = build(&arguments)?;
+    let binary = std::fs::read(path).map_err(SizeError::ReadError)?;
+
+    for string in forbidden_strings.iter().filter(|string| !string.is_empty()) {
+        if binary
+            .windows(string.len())
+            .any(|window| window == string.as_bytes())
+        {
+            return Err(SizeError::ForbiddenString(string.clone()));
+        }
+    }
+
+    Ok(SizeReport {
+        size: binary.len() as u64,
+        baseline_size,
+        symbol_table: symbols::measure(&binary),
+    })
+}
+
+/// The sizes measured by [`size()`].
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub struct SizeReport {
+    /// The size of the kernel binary, in bytes.
+    pub size: u64,
+    /// The size of the baseline kernel binary, in bytes.
+    pub baseline_size: Option<u64>,
+    /// The space taken by the embedded symbol table.
+    pub symbol_table: Option<symbols::SectionCost>,
+}
+
+impl fmt::Display for SizeReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "kernel size: {} bytes", self.size)?;
+        if let Some(baseline_size) = self.baseline_size {
+            let difference = self.size as i64 - baseline_size as i64;
+            write!(f, " (baseline {baseline_size} bytes, {difference:+} bytes)")?;
+        }
+        if let Some(symbol_table) = self.symbol_table {
+            write!(f, "\nsymbol table: {symbol_table}")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Various errors that can occur while measuring the size of the Capora kernel.
+#[derive(Debug)]
+pub enum SizeError {
+    /// An error occurred while building the kernel.
+    BuildError(BuildError),
+    /// An error occurred while reading the kernel binary.
+    ReadError(io::Error),
+    /// The kernel binary contained a forbidden string.
+    ForbiddenString(String),
+}
+
+impl From<BuildError> for SizeError {
+    fn from(value: BuildError) -> Self {
+        Self::BuildError(value)
+    }
+}
+
+impl fmt::Display for SizeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BuildError(error) => fmt::Display::fmt(error, f),
+            Self::ReadError(error) => write!(f, "error reading kernel binary: {error}"),
+            Self::ForbiddenString(string) => {
+                write!(f, "kernel binary contains forbidden string {string:?}")
+            }
+        }
+    }
+}
+
+/// The configuration Limine boots the kernel at `/kernel` with.
+const LIMINE_CONF: &str = "\
+    timeout: 0\n\
+    \n\
+    /Capora Kernel\n\
+        \tprotocol: limine\n\
+        \tkernel_path: boot():/kernel
+";
+
+/// Runs the Capora kernel at `kernel_path` using the Limine bootloader.
+///
+/// `build_args` must enable the `limine-boot-api` feature, and describe how the kernel was built.
+/// Limine is fetched if `limine_path` is [`None`]. If `from_iso` is `true`, the kernel boots from
+/// an ISO written to `run/<arch>/capora.iso`. Otherwise, booting under BIOS always boots from a
+/// disk image, which is written to `run/<arch>/limine-bios.img` unless another path is given.
+pub fn run_limine(
+    build_args: BuildArguments,
+    run_args: RunArguments,
+    kernel_path: &Path,
+    limine_path: Option<PathBuf>,
+    from_iso: bool,
+) -> Result<(), RunLimineError> {
+    if run_args.firmware == BootFirmware::Bios && build_args.arch != Arch::X86_64 {
+        return Err(RunLimineError::BiosUnsupported(build_args.arch));
+    }
+
+    let limine_path = match limine_path {
+        Some(limine_path) => limine_path,
+        None => limine::fetch_limine(build_args.arch, run_args.offline)
+            .map_err(RunLimineError::FetchLimineError)?,
+    };
+
+    if from_iso {
+        let iso_path = Path::new("run")
+            .join(build_args.arch.as_str())
+            .join("capora.iso");
+        let iso_path = stage_iso(build_args.arch, kernel_path, &limine_path, &iso_path)
+            .map_err(RunLimineError::IsoError)?;
+
+        run(
+            build_args,
+            run_args,
+            kernel_path,
+            BootMedium::Cdrom(iso_path),
+        )?;
+        return Ok(());
+    }
+
+    let bios_files = match run_args.firmware {
+        BootFirmware::Uefi => None,
+        BootFirmware::Bios => {
+            Some(limine::bios_files(&limine_path).map_err(RunLimineError::BiosFilesError)?)
+        }
+    };
+
+    let bios_stage3 = bios_files.as_ref().map(|files| files.stage3.as_path());
+    let fat_directory = stage_limine(build_args.arch, kernel_path, limine_path, bios_stage3)?;
+    let boot_medium = match (bios_files, &run_args.image) {
+        (None, Some(image_path)) => BootMedium::DiskImage(
+            build_disk_image(&fat_directory, image_path)
+                .map_err(RunLimineError::BuildDiskImageError)?,
+        ),
+        (None, None) => BootMedium::FatDirectory(fat_directory),
+        // SeaBIOS cannot boot from QEMU's virtual FAT drive.
+        (Some(bios_files), image_path) => {
+            let image_path = image_path.clone().unwrap_or_else(|| {
+                Path::new("run")
+                    .join(build_args.arch.as_str())
+                    .join("limine-bios.img")
+            });
+            let image_path = build_disk_image(&fat_directory, &image_path)
+                .map_err(RunLimineError::BuildDiskImageError)?;
+
+            let mut cmd = std::process::Command::new(&bios_files.tool);
+            cmd.arg("bios-install").arg(&image_path);
+            run_cmd(cmd, OutputMode::Capture).map_err(RunLimineError::BiosInstallError)?;
+
+            BootMedium::DiskImage(image_path)
+        }
+    };
+
+    run(build_args, run_args, kernel_path, boot_medium)?;
+
+    Ok(())
+}
+
+/// Lays out the FAT directory that boots the kernel at `kernel_path` using the Limine bootloader,
+/// returning the path of the directory.
+///
+/// If `bios_stage3` is given, it is placed in the directory too, so that the disk image built
+/// from it can also be booted by BIOS. The kernel must enable the `limine-boot-api` feature.
+fn stage_limine(
+    arch: Arch,
+    kernel_path: &Path,
+    limine_path: PathBuf,
+    bios_stage3: Option<&Path>,
+) -> Result<PathBuf, RunLimineError> {
+    let mut files = vec![(kernel_path, "kernel")];
+    if let Some(stage3) = bios_stage3 {
+        files.push((stage3, "limine-bios.sys"));
+    }
+    let fat_directory = build_fat_directory(
+        arch,
+        limine_path,
+        &files,
+        &[(LIMINE_CONF.as_bytes(), "limine.conf")],
+    )
+    .map_err(RunLimineError::BuildFatDirectoryError)?;
+    report_fat_directory(&fat_directory);
+
+    Ok(fat_directory.path)
+}
+
+/// Prints what [`build_fat_directory()`] changed, unless running quietly.
+fn report_fat_directory(fat_directory: &FatDirectory) {
+    if verbosity() != Verbosity::Quiet {
+        println!("FAT directory {fat_directory}");
+    }
+}
+
+/// Various errors that can occur while building and running the Capora kernel using the Limine
+/// bootloader.
+#[derive(Debug)]
+pub enum RunLimineError {
+    /// An error occurred while building the kernel.
+    BuildError(BuildError),
+    /// An error occurred while fetching Limine.
+    FetchLimineError(limine::FetchLimineError),
+    /// Booting under BIOS is not available for the architecture.
+    BiosUnsupported(Arch),
+    /// An error occurred while locating the files needed to boot under BIOS.
+    BiosFilesError(limine::LimineFilesError),
+    /// An error occurred while building the fat directory.
+    BuildFatDirectoryError(std::io::Error),
+    /// An error occurred while building the disk image.
+    BuildDiskImageError(DiskImageError),
+    /// An error occurred while installing Limine's BIOS boot sector into the disk image.
+    BiosInstallError(RunCommandError),
+    /// An error occurred while building the ISO.
+    IsoError(IsoError),
+    /// An error occurred while running QEMU.
+    QemuError(QemuError),
+}
+
+impl From<BuildError> for RunLimineError {
+    fn from(value: BuildError) -> Self {
+        Self::BuildError(value)
+    }
+}
+
+impl From<QemuError> for RunLimineError {
+    fn from(value: QemuError) -> Self {
+        Self::QemuError(value)
+    }
+}
+
+impl fmt::Display for RunLimineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BuildError(error) => fmt::Display::fmt(error, f),
+            Self::FetchLimineError(error) => fmt::Display::fmt(error, f),
+            Self::BiosUnsupported(arch) => {
+                write!(f, "BIOS boot is not available on `{}`", arch.as_str())
+            }
+            Self::BiosFilesError(error) => fmt::Display::fmt(error, f),
+            Self::BuildFatDirectoryError(error) => {
+                writeln!(f, "error occurred while building FAT directory: {error}",)
+            }
+            Self::BuildDiskImageError(error) => fmt::Display::fmt(error, f),
+            Self::BiosInstallError(error) => write!(
+                f,
+                "error occurred while installing Limine's BIOS boot sector: {error}"
+            ),
+            Self::IsoError(error) => fmt::Display::fmt(error, f),
+            Self::QemuError(error) => fmt::Display::fmt(error, f),
+        }
+    }
+}
+
+/// Builds an ISO at `output` that boots the Capora kernel using Limine under both BIOS and UEFI,
+/// returning its path.
+///
+/// Limine is fetched if `limine_path` is [`None`], unless `offline` is `true`.
+pub fn iso(
+    mut build_args: BuildArguments,
+    limine_path: Option<PathBuf>,
+    offline: bool,
+    output: &Path,
+) -> Result<PathBuf, IsoError> {
+    build_args.features.insert(Features::LIMINE_BOOT_API);
+
+    let limine_path = match limine_path {
+        Some(limine_path) => limine_path,
+        None => {
+            limine::fetch_limine(build_args.arch, offline).map_err(IsoError::FetchLimineError)?
+        }
+    };
+
+    let kernel_path = build(&build_args)?;
+    stage_iso(build_args.arch, &kernel_path, &limine_path, output)
+}
+
+/// Builds an ISO at `output` that boots the kernel at `kernel_path` using the Limine bootloader
+/// at `limine_path`, returning the path of the ISO.
+///
+/// The ISO's contents are laid out in `run/<arch>/iso_root/` first. The kernel must enable the
+/// `limine-boot-api` feature.
+fn stage_iso(
+    arch: Arch,
+    kernel_path: &Path,
+    limine_path: &Path,
+    output: &Path,
+) -> Result<PathBuf, IsoError> {
+    // Limine only provides El Torito boot images for `x86_64`.
+    if arch != Arch::X86_64 {
+        return Err(IsoError::UnsupportedArch(arch));
+    }
+
+    let bios_files = limine::bios_files(limine_path).map_err(IsoError::LimineFilesError)?;
+    let cd_files = limine::cd_files(limine_path).map_err(IsoError::LimineFilesError)?;
+
+    let iso_root = Path::new("run").join(arch.as_str()).join("iso_root");
+    let limine_directory = iso_root.join("boot").join("limine");
+    let boot_directory = iso_root.join("EFI").join("BOOT");
+    let lay_out = || -> Result<(), io::Error> {
+        match std::fs::remove_dir_all(&iso_root) {
+            Ok(()) => {}
+            Err(error) if error.kind() == io::ErrorKind::NotFound => {}
+            Err(error) => return Err(error),
+        }
+        std::fs::create_dir_all(&limine_directory)?;
         std::fs::create_dir_all(&boot_directory)?;
+
+        std::fs::copy(kernel_path, iso_root.join("kernel"))?;
+        std::fs::write(iso_root.join("limine.conf"), LIMINE_CONF)?;
+        std::fs::copy(&bios_files.stage3, limine_directory.join("limine-bios.sys"))?;
+        std::fs::copy(&cd_files.bios, limine_directory.join("limine-bios-cd.bin"))?;
+        std::fs::copy(&cd_files.uefi, limine_directory.join("limine-uefi-cd.bin"))?;
+        std::fs::copy(limine_path, boot_directory.join(arch.uefi_boot_file_name()))?;
+
+        if let Some(parent) = output.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        Ok(())
+    };
+    lay_out().map_err(IsoError::LayOutError)?;
+
+    let mut cmd = std::process::Command::new("xorriso");
+    cmd.args(["-as", "mkisofs", "-R", "-r", "-J"]);
+    cmd.args(["-b", "boot/limine/limine-bios-cd.bin"]);
+    cmd.args(["-no-emul-boot", "-boot-load-size", "4", "-boot-info-table"]);
+    cmd.args(["-hfsplus", "-apm-block-size", "2048"]);
+    cmd.args(["--efi-boot", "boot/limine/limine-uefi-cd.bin"]);
+    cmd.args([
+        "-efi-boot-part",
+        "--efi-boot-image",
+        "--protective-msdos-label",
+    ]);
+    cmd.arg(&iso_root);
+    cmd.arg("-o").arg(output);
+    run_cmd(cmd, OutputMode::Capture).map_err(IsoError::XorrisoError)?;
+
+    // Makes the ISO bootable under BIOS when written to a disk rather than a CD.
+    let mut cmd = std::process::Command::new(&bios_files.tool);
+    cmd.arg("bios-install").arg(output);
+    run_cmd(cmd, OutputMode::Capture).map_err(IsoError::BiosInstallError)?;
+
+    Ok(output.to_path_buf())
+}
+
+/// Various errors that can occur while building an ISO for the Capora kernel.
+#[derive(Debug)]
+pub enum IsoError {
+    /// Limine cannot boot an ISO on the architecture.
+    UnsupportedArch(Arch),
+    /// An error occurred while fetching Limine.
+    FetchLimineError(limine::FetchLimineError),
+    /// An error occurred while locating the files of Limine the ISO needs.
+    LimineFilesError(limine::LimineFilesError),
+    /// An error occurred while building the kernel.
+    BuildError(BuildError),
+    /// An error occurred while laying out the contents of the ISO.
+    LayOutError(io::Error),
+    /// An error occurred while running `xorriso`.
+    XorrisoError(RunCommandError),
+    /// An error occurred while installing Limine's BIOS boot sector into the ISO.
+    BiosInstallError(RunCommandError),
+}
+
+impl From<BuildError> for IsoError {
+    fn from(value: BuildError) -> Self {
+        Self::BuildError(value)
+    }
+}
+
+impl fmt::Display for IsoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnsupportedArch(arch) => {
+                write!(f, "Limine cannot boot an ISO on `{}`", arch.as_str())
+            }
+            Self::FetchLimineError(error) => fmt::Display::fmt(error, f),
+            Self::LimineFilesError(error) => fmt::Display::fmt(error, f),
+            Self::BuildError(error) => fmt::Display::fmt(error, f),
+            Self::LayOutError(error) => {
+                write!(f, "error occurred while laying out the ISO: {error}")
+            }
+            Self::XorrisoError(error) => {
+                write!(f, "error occurred while building the ISO: {error}")
+            }
+            Self::BiosInstallError(error) => write!(
+                f,
+                "error occurred while installing Limine's BIOS boot sector: {error}"
+            ),
+        }
+    }
+}
+
+/// Runs the Capora kernel at `kernel_path` using `capora-boot-stub`, which loads `modules`
+/// alongside it.
+///
+/// `build_args` must enable the `capora-boot-api` feature, and describe how the kernel was built.
+/// `modules` must have passed [`check_boot_modules()`], which should happen before the kernel is
+/// built, so that a mistyped module does not cost a build.
+pub fn run_boot_stub(
+    build_args: BuildArguments,
+    run_args: RunArguments,
+    kernel_path: &Path,
+    modules: &[BootModule],
+) -> Result<(), RunBootStubError> {
+    let fat_directory = stage_boot_stub(build_args.arch, kernel_path, modules)?;
+    let boot_medium = match &run_args.image {
+        Some(image_path) => BootMedium::DiskImage(
+            build_disk_image(&fat_directory, image_path)
+                .map_err(RunBootStubError::BuildDiskImageError)?,
+        ),
+        None => BootMedium::FatDirectory(fat_directory),
+    };
+
+    run(build_args, run_args, kernel_path, boot_medium)?;
+
+    Ok(())
+}
+
+/// The name of the kernel among the applications `capora-boot-stub` loads.
+const BOOT_STUB_KERNEL_NAME: &str = "kernel";
+
+/// Checks that every module in `modules` exists and has a unique name.
+fn check_boot_modules(modules: &[BootModule]) -> Result<(), RunBootStubError> {
+    for (index, module) in modules.iter().enumerate() {
+        let duplicate = module.name == BOOT_STUB_KERNEL_NAME
+            || modules[..index]
+                .iter()
+                .any(|earlier| earlier.name == module.name);
+        if duplicate {
+            return Err(RunBootStubError::DuplicateModule(module.name.clone()));
+        }
+
+        if !module.path.is_file() {
+            return Err(RunBootStubError::MissingModule(module.clone()));
+        }
+    }
+
+    Ok(())
+}
+
+/// Lays out the FAT directory that boots the kernel at `kernel_path` using `capora-boot-stub`,
+/// which loads `modules` alongside it, returning the path of the directory.
+///
+/// The kernel must enable the `capora-boot-api` feature, and `modules` must have passed
+/// [`check_boot_modules()`].
+fn stage_boot_stub(
+    arch: Arch,
+    kernel_path: &Path,
+    modules: &[BootModule],
+) -> Result<PathBuf, RunBootStubError> {
+    // `capora-boot-stub` is only built for `x86_64` UEFI.
+    if arch != Arch::X86_64 {
+        return Err(RunBootStubError::UnsupportedArch(arch));
+    }
+
+    let fat_directory = build_fat_directory(
+        arch,
+        PathBuf::from(env!("CARGO_BIN_FILE_BOOT_STUB_boot-stub")),
+        &[],
+        &[],
+    )
+    .map_err(RunBootStubError::BuildFatDirectoryError)?;
+    report_fat_directory(&fat_directory);
+    let fat_directory = fat_directory.path;
+
+    let mut cmd = std::process::Command::new(env!("CARGO_BIN_FILE_CONFIG_capora-boot-stub-ctl"));
+    cmd.arg("configure");
+
+    cmd.arg("--stub").arg(
+        fat_directory
+            .join("EFI")
+            .join("BOOT")
+            .join(arch.uefi_boot_file_name()),
+    );
+    cmd.arg("--application").arg(format!(
+        "{BOOT_STUB_KERNEL_NAME}:embedded:{}",
+        kernel_path.display()
+    ));
+    for module in modules {
+        cmd.arg("--module").arg(format!(
+            "{}:embedded:{}",
+            module.name,
+            module.path.display()
+        ));
+    }
+
+    run_cmd(cmd, OutputMode::Capture)?;
+
+    Ok(fat_directory)
+}
+
+/// Various errors that can occur while building and running the Capora kernel using
+/// `capora-boot-stub`.
+pub enum RunBootStubError {
+    /// `capora-boot-stub` is not available for the architecture.
+    UnsupportedArch(Arch),
+    /// More than one module, or a module and the kernel, have the given name.
+    DuplicateModule(String),
+    /// The file of the module does not exist.
+    MissingModule(BootModule),
+    /// An error ocurred while building the kernel.
+    BuildError(BuildError),
+    /// An error occurred while building the fat directory.
+    BuildFatDirectoryError(std::io::Error),
+    /// An error occurred while configuring `capora-boot-stub`.
+    ConfigureError(RunCommandError),
+    /// An error occurred while building the disk image.
+    BuildDiskImageError(DiskImageError),
+    /// An error occurred while running QEMU.
+    QemuError(QemuError),
+}
+
+impl From<BuildError> for RunBootStubError {
+    fn from(value: BuildError) -> Self {
+        Self::BuildError(value)
+    }
+}
+
+impl From<RunCommandError> for RunBootStubError {
+    fn from(value: RunCommandError) -> Self {
+        Self::ConfigureError(value)
+    }
+}
+
+impl From<QemuError> for RunBootStubError {
+    fn from(value: QemuError) -> Self {
+        Self::QemuError(value)
+    }
+}
+
+impl fmt::Display for RunBootStubError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnsupportedArch(arch) => write!(
+                f,
+                "`capora-boot-stub` is not available on `{}`; use `run-limine` instead",
+                arch.as_str()
+            ),
+            Self::DuplicateModule(name) if name == BOOT_STUB_KERNEL_NAME => write!(
+                f,
+                "module name `{name}` is taken by the kernel; choose another name"
+            ),
+            Self::DuplicateModule(name) => write!(f, "module `{name}` is given more than once"),
+            Self::MissingModule(module) => write!(
+                f,
+                "file \"{}\" of module `{}` does not exist",
+                module.path.display(),
+                module.name
+            ),
+            Self::BuildError(error) => fmt::Display::fmt(error, f),
+            Self::BuildFatDirectoryError(error) => {
+                write!(f, "error occurred while building FAT directory: {error}",)
+            }
+            Self::ConfigureError(error) => write!(
+                f,
+                "error occurred while configuring `capora-boot-stub`: {error}"
+            ),
+            Self::BuildDiskImageError(error) => fmt::Display::fmt(error, f),
+            Self::QemuError(error) => fmt::Display::fmt(error, f),
+        }
+    }
+}
+
+/// Builds the Capora kernel with the `qemu-exit` feature, runs it using `capora-boot-stub`, and
+/// checks that it reports success through the `isa-debug-exit` device.
+///
+/// Without the `ktest` feature, the kernel reports success once it reaches `kmain`.
+pub fn run_tests(
+    mut build_args: BuildArguments,
+    run_args: RunArguments,
+) -> Result<(), RunBootStubError> {
+    build_args.features.insert(Features::QEMU_EXIT);
+
+    let kernel_path = build(&build_args)?;
+    run_boot_stub(build_args, run_args, &kernel_path, &[])
+}
+
+/// The directory holding the snapshots compared against by [`snapshot()`].
+const SNAPSHOT_DIRECTORY: &str = "tests/snapshots";
+
+/// The file holding the substitutions that normalize serial output before it is compared against a
+/// snapshot, in the format read by [`snapshot::parse_substitutions()`].
+const SNAPSHOT_SUBSTITUTIONS: &str = "tests/snapshots/normalize.txt";
+
+/// Runs the Capora kernel like [`run_tests()`], and compares its serial output, normalized by the
+/// substitutions in [`SNAPSHOT_SUBSTITUTIONS`], against its snapshot, or replaces the snapshot with
+/// it if `update` is set.
+///
+/// Each architecture and set of features has its own snapshot, at
+/// `tests/snapshots/<arch>/<features>.txt`. `build_args` must enable the `serial-logging` and
+/// `qemu-exit` features.
+pub fn snapshot(
+    build_args: BuildArguments,
+    run_args: RunArguments,
+    update: bool,
+) -> Result<SnapshotOutcome, SnapshotError> {
+    // Read first, so that a mistake in them does not cost a build and a boot.
+    let substitutions = match std::fs::read_to_string(SNAPSHOT_SUBSTITUTIONS) {
+        Ok(text) => snapshot::parse_substitutions(&text).map_err(SnapshotError::Substitutions)?,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Vec::new(),
+        Err(error) => return Err(SnapshotError::Io(error)),
+    };
+
+    // Sorted, so that the order features are requested in does not matter.
+    let mut features: Vec<&str> = build_args.features.iter().collect();
+    features.sort_unstable();
+    let snapshot_path = Path::new(SNAPSHOT_DIRECTORY)
+        .join(build_args.arch.as_str())
+        .join(format!("{}.txt", features.join(",")));
+
+    let serial_log = serial_log_path(build_args.arch, &run_args)
+        .expect("test runs always write the serial output to a file");
+    run_tests(build_args, run_args).map_err(SnapshotError::Run)?;
+
+    let output = std::fs::read(&serial_log).map_err(SnapshotError::Io)?;
+    let actual = snapshot::normalize(&String::from_utf8_lossy(&output), &substitutions);
+
+    if update {
+        if let Some(parent) = snapshot_path.parent() {
+            std::fs::create_dir_all(parent).map_err(SnapshotError::Io)?;
+        }
+        std::fs::write(&snapshot_path, actual).map_err(SnapshotError::Io)?;
+        return Ok(SnapshotOutcome::Updated(snapshot_path));
+    }
+
+    let expected = match std::fs::read_to_string(&snapshot_path) {
+        Ok(expected) => expected,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => {
+            return Err(SnapshotError::Missing(snapshot_path))
+        }
+        Err(error) => return Err(SnapshotError::Io(error)),
+    };
+
+    let expected_name = snapshot_path.display().to_string();
+    let actual_name = format!("{} (normalized)", serial_log.display());
+    match snapshot::unified_diff(&expected, &actual, &expected_name, &actual_name) {
+        None => Ok(SnapshotOutcome::Matched(snapshot_path)),
+        Some(diff) => Err(SnapshotError::Mismatch {
+            path: snapshot_path,
+            diff,
+        }),
+    }
+}
+
+/// The result of a successful [`snapshot()`].
+pub enum SnapshotOutcome {
+    /// The serial output matched the snapshot at the given path.
+    Matched(PathBuf),
+    /// The snapshot at the given path was replaced by the serial output.
+    Updated(PathBuf),
+}
+
+/// Various errors that can occur while comparing the serial output of the Capora kernel against
+/// its snapshot.
+pub enum SnapshotError {
+    /// The substitutions that normalize serial output are invalid.
+    Substitutions(snapshot::SubstitutionError),
+    /// An error occurred while building or running the kernel.
+    Run(RunBootStubError),
+    /// There is no snapshot at the given path.
+    Missing(PathBuf),
+    /// The serial output differs from the snapshot at `path`.
+    Mismatch {
+        /// The path of the snapshot.
+        path: PathBuf,
+        /// The differences, as a unified diff from the snapshot to the serial output.
+        diff: String,
+    },
+    /// An error occurred while reading or writing the serial output, a snapshot, or the
+    /// substitutions.
+    Io(io::Error),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Substitutions(error) => {
+                write!(
+                    f,
+                    "invalid substitution in \"{SNAPSHOT_SUBSTITUTIONS}\", {error}"
+                )
+            }
+            Self::Run(error) => fmt::Display::fmt(error, f),
+            Self::Missing(path) => write!(
+                f,
+                "no snapshot at \"{}\"; rerun with `--update` to create it",
+                path.display()
+            ),
+            Self::Mismatch { path, diff } => write!(
+                f,
+                "serial output differs from \"{}\"; rerun with `--update` to accept it:\n{diff}",
+                path.display()
+            ),
+            Self::Io(error) => write!(f, "error occurred while handling snapshot: {error}"),
+        }
+    }
+}
+
+/// Builds a disk image for the Capora kernel, booting it using Limine if `limine_path` is given
+/// and `capora-boot-stub` otherwise, and writes it to `output`.
+pub fn image(
+    mut build_args: BuildArguments,
+    limine_path: Option<PathBuf>,
+    output: &Path,
+) -> Result<PathBuf, ImageError> {
+    let fat_directory = match limine_path {
+        Some(limine_path) => {
+            build_args.features.insert(Features::LIMINE_BOOT_API);
+            let kernel_path =
+                build(&build_args).map_err(|error| ImageError::Limine(error.into()))?;
+            stage_limine(build_args.arch, &kernel_path, limine_path, None)
+                .map_err(ImageError::Limine)?
+        }
+        None => {
+            build_args.features.insert(Features::CAPORA_BOOT_API);
+            let kernel_path =
+                build(&build_args).map_err(|error| ImageError::BootStub(error.into()))?;
+            stage_boot_stub(build_args.arch, &kernel_path, &[]).map_err(ImageError::BootStub)?
+        }
+    };
+
+    build_disk_image(&fat_directory, output).map_err(ImageError::DiskImage)
+}
+
+/// Various errors that can occur while building a disk image for the Capora kernel.
+pub enum ImageError {
+    /// An error occurred while preparing the kernel to boot using Limine.
+    Limine(RunLimineError),
+    /// An error occurred while preparing the kernel to boot using `capora-boot-stub`.
+    BootStub(RunBootStubError),
+    /// An error occurred while building the disk image.
+    DiskImage(DiskImageError),
+}
+
+impl fmt::Display for ImageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Limine(error) => fmt::Display::fmt(error, f),
+            Self::BootStub(error) => fmt::Display::fmt(error, f),
+            Self::DiskImage(error) => fmt::Display::fmt(error, f),
+        }
+    }
+}
+
+/// The medium QEMU boots the Capora kernel from.
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+pub enum BootMedium {
+    /// A directory exposed to the guest as a virtual FAT drive.
+    FatDirectory(PathBuf),
+    /// A raw disk image built by [`build_disk_image()`].
+    DiskImage(PathBuf),
+    /// An ISO built by [`iso()`], inserted as a CD.
+    Cdrom(PathBuf),
+}
+
+/// Runs the Capora kernel at `kernel_path`, booting from `boot_medium`.
+pub fn run(
+    build_args: BuildArguments,
+    run_args: RunArguments,
+    kernel_path: &Path,
+    boot_medium: BootMedium,
+) -> Result<(), QemuError> {
+    let qemu_name = match build_args.arch {
+        Arch::X86_64 => "qemu-system-x86_64",
+        Arch::Aarch64 => "qemu-system-aarch64",
+    };
+    let run_directory = Path::new("run").join(build_args.arch.as_str());
+
+    let serial_log = serial_log_path(build_args.arch, &run_args);
+
+    let mut cmd = std::process::Command::new(qemu_name);
+
+    // Disable unnecessary devices.
+    cmd.arg("-nodefaults");
+
+    // Nobody can pick from the boot menu without a display.
+    if run_args.headless || run_args.mode == RunMode::Test {
+        cmd.args(["-boot", "menu=off"]);
+    } else {
+        cmd.args(["-boot", "menu=on,splash-time=0"]);
+    }
+
+    let accelerator = accel::resolve(run_args.accelerator, build_args.arch)?;
+    cmd.args(["-accel", accelerator.as_str()]);
+    // Only hardware accelerators can pass the host's processor through.
+    let host_cpu = matches!(accelerator, Accelerator::Kvm | Accelerator::Hvf);
+
+    match build_args.arch {
+        Arch::X86_64 => {
+            // Use fairly modern machine to target.
+            cmd.args(["-machine", "q35"]);
+            if host_cpu {
+                cmd.args(["-cpu", "host,rdrand=on"]);
+            } else {
+                cmd.args(["-cpu", "max"]);
+            }
+
+            // Allocate some memory.
+            cmd.arg("-m")
+                .arg(run_args.memory.as_deref().unwrap_or("256M"));
+
+            // Use vga graphics
+            if !run_args.headless {
+                cmd.args(["-vga", "std"]);
+            }
+        }
+        Arch::Aarch64 => {
+            cmd.args(["-machine", "virt"]);
+            cmd.args(["-cpu", if host_cpu { "host" } else { "cortex-a72" }]);
+
+            cmd.arg("-m")
+                .arg(run_args.memory.as_deref().unwrap_or("256M"));
+
+            // The `virt` machine has no display of its own, and `-nodefaults` drops its input
+            // devices.
+            if !run_args.headless {
+                cmd.args(["-device", "ramfb"]);
+                cmd.args(["-device", "qemu-xhci"]);
+                cmd.args(["-device", "usb-kbd"]);
+            }
+        }
+    }
+
+    if let Some(cpus) = run_args.cpus {
+        cmd.arg("-smp").arg(cpus.to_string());
+    }
+
+    // Without flash drives, QEMU falls back to SeaBIOS.
+    if run_args.firmware == BootFirmware::Uefi {
+        let firmware = ovmf::resolve(
+            build_args.arch,
+            run_args.ovmf_code,
+            run_args.ovmf_vars,
+            run_args.offline,
+        )?;
+
+        let mut ovmf_code_arg = OsString::from("if=pflash,format=raw,readonly=on,file=");
+        ovmf_code_arg.push(firmware.code);
+        cmd.arg("-drive").arg(ovmf_code_arg);
+
+        // The variables are writable so that UEFI variables persist across runs.
+        let mut ovmf_vars_arg = OsString::from("if=pflash,format=raw,file=");
+        ovmf_vars_arg.push(firmware.vars);
+        cmd.arg("-drive").arg(ovmf_vars_arg);
+    }
+
+    match boot_medium {
+        BootMedium::FatDirectory(fat_directory) => {
+            let mut drive_arg = OsString::from("format=raw,file=fat:rw:");
+            drive_arg.push(fat_directory);
+            cmd.arg("-drive").arg(drive_arg);
+        }
+        BootMedium::DiskImage(image_path) => {
+            let mut drive_arg = OsString::from("format=raw,file=");
+            drive_arg.push(image_path);
+            cmd.arg("-drive").arg(drive_arg);
+        }
+        BootMedium::Cdrom(iso_path) => {
+            cmd.arg("-cdrom").arg(iso_path);
+        }
+    }
+
+    cmd.arg("-D").arg(run_directory.join("logfile.txt"));
+
+    // `debug-shell` enables `serial-logging`.
+    let serial_logging = build_args.features.enables(Features::SERIAL_LOGGING);
+    if let Some(serial_log) = &serial_log {
+        if !serial_logging {
+            eprintln!("warning: kernel built without `serial-logging`, so the serial log is empty");
+        }
+
+        if let Some(parent) = serial_log.parent() {
+            std::fs::create_dir_all(parent).map_err(QemuError::SerialLogError)?;
+        }
+        std::fs::File::create(serial_log).map_err(QemuError::SerialLogError)?;
+
+        let mut serial_arg = OsString::from("file:");
+        serial_arg.push(serial_log);
+        cmd.arg("-serial").arg(serial_arg);
+    }
+
+    // Stop instead of rebooting after a triple fault, so that the failure is visible.
+    cmd.arg("-no-reboot");
+
+    if let Some(port) = run_args.gdb_port {
+        // Start paused so that early boot can be debugged.
+        cmd.arg("-gdb").arg(format!("tcp::{port}"));
+        cmd.arg("-S");
+
+        println!("QEMU is waiting for GDB, attach with:");
+        println!(
+            "    gdb -ex \"target remote :{port}\" -ex \"symbol-file {}\"",
+            kernel_path.display()
+        );
+    }
+
+    // Only `x86_64` has the I/O port based debugcon device.
+    let has_debugcon = build_args.arch == Arch::X86_64;
+    let debugcon_logging = has_debugcon && build_args.features.enables(Features::DEBUGCON_LOGGING);
+    let debugcon_log = run_directory.join("debugcon.txt");
+
+    let interactive = run_args.mode == RunMode::Interactive;
+    let serial_in_terminal = interactive && serial_log.is_none();
+    // The kernel writes each line to every logging backend, so showing both in the terminal would
+    // print everything twice.
+    let debugcon_in_terminal =
+        debugcon_logging && interactive && !(serial_logging && serial_in_terminal);
+
+    match run_args.mode {
+        RunMode::Interactive => {
+            if run_args.headless {
+                cmd.args(["-display", "none"]);
+            }
+
+            if serial_in_terminal || debugcon_in_terminal {
+                // The monitor shares the terminal and is reached with `Ctrl-A c`.
+                cmd.args(["-chardev", "stdio,id=console,mux=on"]);
+                cmd.args(["-mon", "chardev=console"]);
+                if serial_in_terminal {
+                    cmd.args(["-serial", "chardev:console"]);
+                }
+            } else {
+                cmd.args(["-monitor", "stdio"]);
+            }
+        }
+        RunMode::Test => {
+            cmd.args(["-display", "none"]);
+            cmd.args(["-monitor", "none"]);
+        }
+    }
+
+    if debugcon_in_terminal {
+        cmd.args(["-debugcon", "chardev:console"]);
+    } else if has_debugcon {
+        let mut debugcon_file_arg = OsString::from("file:");
+        debugcon_file_arg.push(&debugcon_log);
+        cmd.arg("-debugcon").arg(debugcon_file_arg);
+    }
+
+    if interactive {
+        let serial = match &serial_log {
+            Some(serial_log) => format!("\"{}\"", serial_log.display()),
+            None => "the terminal".to_owned(),
+        };
+        let debugcon = if debugcon_in_terminal {
+            "the terminal".to_owned()
+        } else {
+            format!("\"{}\"", debugcon_log.display())
+        };
+        match (serial_logging, debugcon_logging) {
+            (true, true) => println!("serial log goes to {serial}, debugcon log to {debugcon}"),
+            (true, false) => println!("serial log goes to {serial}"),
+            (false, true) => println!("debugcon log goes to {debugcon}"),
+            (false, false) => {}
+        }
+    }
+
+    let qemu_exit = build_args.features.enables(Features::QEMU_EXIT);
+    if qemu_exit {
+        cmd.args(["-device", "isa-debug-exit,iobase=0xf4,iosize=0x04"]);
+    }
+
+    // Passed last so that they can override anything above.
+    cmd.args(&run_args.extra_qemu_args);
+
+    let status = run_qemu(cmd, run_args.timeout);
+    if let (Some(serial_log), RunMode::Interactive) = (&serial_log, run_args.mode) {
+        println!("serial log written to \"{}\"", serial_log.display());
+    }
+
+    let status = status?;
+    if !qemu_exit {
+        if !status.success() {
+            return Err(RunCommandError::CommandFailed {
+                code: status.code(),
+                stderr: None,
+            }
+            .into());
+        }
+
+        return Ok(());
     }
 
-    let boot_file_name = match arch {
-        Arch::X86_64 => "BOOTX64.EFI",
+    match (decode_guest_exit(status.code()), run_args.mode) {
+        (GuestExit::Success, _) => Ok(()),
+        (GuestExit::Failed(code), _) => Err(QemuError::GuestFailed(code)),
+        (GuestExit::Other(code), RunMode::Test) => Err(QemuError::NoGuestExit(code)),
+        (GuestExit::Other(_), RunMode::Interactive) if status.success() => Ok(()),
+        (GuestExit::Other(code), RunMode::Interactive) => {
+            Err(RunCommandError::CommandFailed { code, stderr: None }.into())
+        }
+    }
+}
+
+/// Returns the path the serial output of a run with `run_args` is written to, or [`None`] if it is
+/// shown in the terminal.
+///
+/// Test runs have no terminal, so their output goes to `run/<arch>/serial.txt` by default.
+pub fn serial_log_path(arch: Arch, run_args: &RunArguments) -> Option<PathBuf> {
+    match (&run_args.serial_log, run_args.mode) {
+        (Some(serial_log), _) => Some(serial_log.clone()),
+        (None, RunMode::Interactive) => None,
+        (None, RunMode::Test) => Some(Path::new("run").join(arch.as_str()).join("serial.txt")),
+    }
+}
+
+/// The interval at which a running QEMU process is checked for having exited.
+const QEMU_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Runs the QEMU command `cmd`, killing QEMU if it is still running after `timeout` or Ctrl-C is
+/// pressed.
+fn run_qemu(
+    mut cmd: std::process::Command,
+    timeout: Option<Duration>,
+) -> Result<ExitStatus, QemuError> {
+    announce(&cmd);
+
+    let interrupt_guard = interrupt::InterruptGuard::new();
+    let mut child = cmd.spawn().map_err(|error| launch_error(&cmd, error))?;
+
+    let start = Instant::now();
+    loop {
+        if let Some(status) = child.try_wait().map_err(RunCommandError::from)? {
+            return Ok(status);
+        }
+
+        let error = if interrupt_guard.interrupted() {
+            QemuError::Interrupted
+        } else if let Some(timeout) = timeout.filter(|&timeout| start.elapsed() >= timeout) {
+            QemuError::TimedOut {
+                seconds: timeout.as_secs(),
+            }
+        } else {
+            std::thread::sleep(QEMU_POLL_INTERVAL);
+            continue;
+        };
+
+        // QEMU may exit on its own after the check above, which makes killing it fail.
+        let _ = child.kill();
+        child.wait().map_err(RunCommandError::from)?;
+        return Err(error);
+    }
+}
+
+/// The port value the kernel writes to the `isa-debug-exit` device to report success.
+const GUEST_SUCCESS_VALUE: i32 = 0x10;
+/// The port value the kernel writes to the `isa-debug-exit` device to report a failure code of
+/// zero.
+const GUEST_FAILURE_BASE_VALUE: i32 = 0x11;
+
+/// How the kernel terminated QEMU, as decoded by [`decode_guest_exit()`].
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum GuestExit {
+    /// The kernel reported success.
+    Success,
+    /// The kernel reported a failure with the given code.
+    Failed(u8),
+    /// QEMU exited without the kernel writing to the `isa-debug-exit` device, with the given exit
+    /// status.
+    Other(Option<i32>),
+}
+
+/// Decodes the exit status of a QEMU process run with the `qemu-exit` feature.
+///
+/// QEMU exits with status `(value << 1) | 1` when the kernel writes `value` to the
+/// `isa-debug-exit` device. The kernel writes `0x10` for success, giving status 33, and
+/// `0x11 + code` for failure `code`, giving status `35 + 2 * code`. Failure codes are limited to
+/// `0x6e` so that the status fits in the 8 bits the host preserves. This must be kept in sync with
+/// `ExitCode::port_value()` in the kernel's `arch::x86_64::qemu` module.
+pub fn decode_guest_exit(status: Option<i32>) -> GuestExit {
+    let Some(status) = status else {
+        return GuestExit::Other(None);
     };
+    if status & 1 == 0 {
+        return GuestExit::Other(Some(status));
+    }
+
+    match status >> 1 {
+        GUEST_SUCCESS_VALUE => GuestExit::Success,
+        value @ GUEST_FAILURE_BASE_VALUE..=0x7f => {
+            GuestExit::Failed((value - GUEST_FAILURE_BASE_VALUE) as u8)
+        }
+        _ => GuestExit::Other(Some(status)),
+    }
+}
 
-    std::fs::copy(loader_path, boot_directory.join(boot_file_name))?;
+/// Various errors that can occur while running QEMU.
+#[derive(Debug)]
+pub enum QemuError {
+    /// An error occurred while running the QEMU command.
+    CommandError(RunCommandError),
+    /// An error occurred while resolving the OVMF firmware.
+    FirmwareError(ovmf::OvmfError),
+    /// The requested accelerator cannot be used.
+    AccelError(accel::AccelError),
+    /// An error occurred while creating the serial log.
+    SerialLogError(io::Error),
+    /// The kernel reported a failure through the `isa-debug-exit` device.
+    GuestFailed(u8),
+    /// QEMU exited with the given status without the kernel reporting a result through the
+    /// `isa-debug-exit` device.
+    NoGuestExit(Option<i32>),
+    /// QEMU was killed after running for longer than its timeout.
+    TimedOut {
+        /// The timeout, in seconds.
+        seconds: u64,
+    },
+    /// QEMU was killed because Ctrl-C was pressed.
+    Interrupted,
+}
 
-    for &(file, name) in additional_files {
-        std::fs::copy(file, fat_directory.join(name))?;
+impl From<RunCommandError> for QemuError {
+    fn from(value: RunCommandError) -> Self {
+        Self::CommandError(value)
     }
+}
+
+impl From<ovmf::OvmfError> for QemuError {
+    fn from(value: ovmf::OvmfError) -> Self {
+        Self::FirmwareError(value)
+    }
+}
+
+impl From<accel::AccelError> for QemuError {
+    fn from(value: accel::AccelError) -> Self {
+        Self::AccelError(value)
+    }
+}
+
+impl fmt::Display for QemuError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::CommandError(error) => write!(f, "error while running QEMU: {error}"),
+            Self::FirmwareError(error) => fmt::Display::fmt(error, f),
+            Self::AccelError(error) => fmt::Display::fmt(error, f),
+            Self::SerialLogError(error) => {
+                write!(f, "error occurred while creating serial log: {error}")
+            }
+            Self::GuestFailed(code) => write!(f, "kernel exited QEMU with failure code {code}"),
+            Self::NoGuestExit(Some(status)) => write!(
+                f,
+                "QEMU exited with status {status} without the kernel reporting a result"
+            ),
+            Self::NoGuestExit(None) => {
+                f.write_str("QEMU was terminated by a signal before the kernel reported a result")
+            }
+            Self::TimedOut { seconds } => {
+                write!(f, "QEMU was killed after running for {seconds} seconds")
+            }
+            Self::Interrupted => f.write_str("QEMU was killed by Ctrl-C"),
+        }
+    }
+}
 
+/// Sets up the FAT directory used for UEFI boot, returning what was changed.
+///
+/// Anything in the directory that is not one of the given files is removed, so that files from
+/// earlier runs, such as an old kernel, cannot end up being booted. Files whose size and
+/// modification time already match their source are not copied again.
+pub fn build_fat_directory(
+    arch: Arch,
+    loader_path: PathBuf,
+    additional_files: &[(&Path, &str)],
+    additional_binary_files: &[(&[u8], &str)],
+) -> Result<FatDirectory, std::io::Error> {
+    let loader_name = ["EFI", "BOOT", arch.uefi_boot_file_name()].iter().collect();
+    let mut expected = vec![(loader_name, FatSource::File(&loader_path))];
+    for &(file, name) in additional_files {
+        expected.push((PathBuf::from(name), FatSource::File(file)));
+    }
     for &(bytes, name) in additional_binary_files {
-        std::fs::write(fat_directory.join(name), bytes)?;
+        expected.push((PathBuf::from(name), FatSource::Bytes(bytes)));
+    }
+
+    sync_fat_directory(
+        ["run", arch.as_str(), "fat_directory"].iter().collect(),
+        expected,
+    )
+}
+
+/// Makes the directory at `path` hold exactly the `expected` files, named relative to it, as
+/// described by [`build_fat_directory()`].
+fn sync_fat_directory(
+    path: PathBuf,
+    expected: Vec<(PathBuf, FatSource)>,
+) -> Result<FatDirectory, io::Error> {
+    let mut fat_directory = FatDirectory {
+        path,
+        copied: Vec::new(),
+        unchanged: Vec::new(),
+        removed: Vec::new(),
+    };
+
+    std::fs::create_dir_all(&fat_directory.path)?;
+    remove_unexpected(
+        &fat_directory.path,
+        Path::new(""),
+        &expected,
+        &mut fat_directory.removed,
+    )?;
+
+    for (name, source) in expected {
+        let destination = fat_directory.path.join(&name);
+        if let Some(parent) = destination.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let up_to_date = match source {
+            FatSource::File(source) => {
+                let source_metadata = std::fs::metadata(source)?;
+                let up_to_date = match std::fs::metadata(&destination) {
+                    Ok(metadata) => {
+                        metadata.is_file()
+                            && metadata.len() == source_metadata.len()
+                            && metadata.modified()? == source_metadata.modified()?
+                    }
+                    Err(error) if error.kind() == io::ErrorKind::NotFound => false,
+                    Err(error) => return Err(error),
+                };
+
+                if !up_to_date {
+                    std::fs::copy(source, &destination)?;
+                    // The copy keeps the source's modification time, so that the next run can
+                    // tell it is unchanged.
+                    std::fs::File::options()
+                        .write(true)
+                        .open(&destination)?
+                        .set_modified(source_metadata.modified()?)?;
+                }
+                up_to_date
+            }
+            FatSource::Bytes(bytes) => {
+                let up_to_date = std::fs::read(&destination).is_ok_and(|current| current == bytes);
+                if !up_to_date {
+                    std::fs::write(&destination, bytes)?;
+                }
+                up_to_date
+            }
+        };
+
+        if up_to_date {
+            fat_directory.unchanged.push(name);
+        } else {
+            fat_directory.copied.push(name);
+        }
     }
 
     Ok(fat_directory)
 }
 
-/// Runs a [`Command`][c], handling non-zero exit codes and other failures.
+/// Removes everything in `root.join(relative)` that is not, and does not contain, one of the
+/// `expected` files, recording what was removed relative to `root` in `removed`.
+fn remove_unexpected(
+    root: &Path,
+    relative: &Path,
+    expected: &[(PathBuf, FatSource)],
+    removed: &mut Vec<PathBuf>,
+) -> Result<(), io::Error> {
+    for entry in std::fs::read_dir(root.join(relative))? {
+        let entry = entry?;
+        let name = relative.join(entry.file_name());
+
+        if entry.file_type()?.is_dir() {
+            let contains_expected = expected
+                .iter()
+                .any(|(file, _)| *file != name && file.starts_with(&name));
+            if contains_expected {
+                remove_unexpected(root, &name, expected, removed)?;
+            } else {
+                std::fs::remove_dir_all(entry.path())?;
+                removed.push(name);
+            }
+        } else if !expected.iter().any(|(file, _)| *file == name) {
+            std::fs::remove_file(entry.path())?;
+            removed.push(name);
+        }
+    }
+
+    Ok(())
+}
+
+/// Where the contents of a file in the FAT directory come from.
+enum FatSource<'a> {
+    /// The file is copied from the given path.
+    File(&'a Path),
+    /// The file is written with the given bytes.
+    Bytes(&'a [u8]),
+}
+
+/// The FAT directory set up by [`build_fat_directory()`], and what was changed to set it up.
+#[derive(Debug)]
+pub struct FatDirectory {
+    /// The path of the FAT directory.
+    pub path: PathBuf,
+    /// The files that were copied or written, relative to the directory.
+    pub copied: Vec<PathBuf>,
+    /// The files that already matched their source, relative to the directory.
+    pub unchanged: Vec<PathBuf>,
+    /// The files and directories left over from earlier runs that were removed, relative to the
+    /// directory.
+    pub removed: Vec<PathBuf>,
+}
+
+impl fmt::Display for FatDirectory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "\"{}\": {} copied, {} unchanged, {} removed",
+            self.path.display(),
+            self.copied.len(),
+            self.unchanged.len(),
+            self.removed.len()
+        )?;
+        for removed in &self.removed {
+            write!(f, "\n    removed \"{}\"", removed.display())?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The smallest EFI System Partition built by [`build_disk_image()`], which leaves FAT32 enough
+/// clusters.
+const MIN_ESP_SIZE: u64 = 64 * 1024 * 1024;
+/// The space left free in the EFI System Partition beyond the files copied into it.
+const ESP_SLACK: u64 = 16 * 1024 * 1024;
+
+/// Builds a raw disk image at `image_path` with a GPT and a single FAT32 EFI System Partition
+/// holding the contents of `fat_directory`.
+///
+/// The partition grows with the files copied into it. It is formatted and filled using `mformat`
+/// and `mcopy` from mtools.
+pub fn build_disk_image(
+    fat_directory: &Path,
+    image_path: &Path,
+) -> Result<PathBuf, DiskImageError> {
+    let contents_size = directory_size(fat_directory).map_err(DiskImageError::Io)?;
+    // FAT32 needs room for its own structures, which the quarter on top of the contents covers.
+    let partition_size = (contents_size + contents_size / 4 + ESP_SLACK).max(MIN_ESP_SIZE);
+    let disk = gpt::EspDisk::new(partition_size);
+
+    if let Some(parent) = image_path.parent() {
+        std::fs::create_dir_all(parent).map_err(DiskImageError::Io)?;
+    }
+    let mut file = std::fs::File::create(image_path).map_err(DiskImageError::Io)?;
+    disk.write(&mut file).map_err(DiskImageError::Io)?;
+    drop(file);
+
+    // mtools addresses the partition as an offset into the image.
+    let mut partition = image_path.as_os_str().to_owned();
+    partition.push(format!("@@{}", disk.partition_offset()));
+
+    let mut cmd = std::process::Command::new("mformat");
+    cmd.env("MTOOLS_SKIP_CHECK", "1");
+    cmd.arg("-i").arg(&partition);
+    cmd.args(["-F", "-v", "CAPORA", "-h", "64", "-s", "32"]);
+    cmd.arg("-T").arg(disk.partition_sectors().to_string());
+    cmd.arg("-H").arg(disk.partition_start_lba().to_string());
+    cmd.arg("::");
+    run_cmd(cmd, OutputMode::Capture).map_err(DiskImageError::Format)?;
+
+    let mut cmd = std::process::Command::new("mcopy");
+    cmd.env("MTOOLS_SKIP_CHECK", "1");
+    cmd.arg("-i").arg(&partition);
+    cmd.args(["-s", "-Q"]);
+    for entry in std::fs::read_dir(fat_directory).map_err(DiskImageError::Io)? {
+        cmd.arg(entry.map_err(DiskImageError::Io)?.path());
+    }
+    cmd.arg("::/");
+    run_cmd(cmd, OutputMode::Capture).map_err(DiskImageError::Copy)?;
+
+    Ok(image_path.to_path_buf())
+}
+
+/// Returns the total size of the files in `directory` and its subdirectories.
+fn directory_size(directory: &Path) -> Result<u64, io::Error> {
+    let mut size = 0;
+    for entry in std::fs::read_dir(directory)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        size += if metadata.is_dir() {
+            directory_size(&entry.path())?
+        } else {
+            metadata.len()
+        };
+    }
+
+    Ok(size)
+}
+
+/// Various errors that can occur while building a disk image.
+#[derive(Debug)]
+pub enum DiskImageError {
+    /// An error occurred while reading the FAT directory or writing the image.
+    Io(io::Error),
+    /// An error occurred while formatting the EFI System Partition.
+    Format(RunCommandError),
+    /// An error occurred while copying files into the EFI System Partition.
+    Copy(RunCommandError),
+}
+
+impl fmt::Display for DiskImageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(error) => write!(f, "error occurred while building disk image: {error}"),
+            Self::Format(error) => write!(
+                f,
+                "error occurred while formatting disk image with `mformat`: {error}"
+            ),
+            Self::Copy(error) => write!(
+                f,
+                "error occurred while copying files into disk image with `mcopy`: {error}"
+            ),
+        }
+    }
+}
+
+/// How much `xtask` reports about the commands it runs, set once by [`main()`].
+static VERBOSITY: OnceLock<Verbosity> = OnceLock::new();
+
+/// Returns how much `xtask` reports about the commands it runs.
+fn verbosity() -> Verbosity {
+    VERBOSITY.get().copied().unwrap_or(Verbosity::Normal)
+}
+
+/// Prints that `cmd` is about to run, unless running quietly.
+fn announce(cmd: &std::process::Command) {
+    if verbosity() != Verbosity::Quiet {
+        status!("Running command: {cmd:?}");
+    }
+}
+
+/// What is done with the output of a command run by [`run_cmd()`].
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum OutputMode {
+    /// The output is shown as it is written, or captured like [`OutputMode::Capture`] when
+    /// running quietly.
+    Stream,
+    /// The output is hidden, and stderr is reported if the command fails, unless running
+    /// verbosely, in which case it is streamed.
+    Capture,
+}
+
+impl OutputMode {
+    /// Returns whether the output is captured rather than shown.
+    fn captures(self) -> bool {
+        match (self, verbosity()) {
+            (_, Verbosity::Verbose) => false,
+            (_, Verbosity::Quiet) => true,
+            (Self::Stream, Verbosity::Normal) => false,
+            (Self::Capture, Verbosity::Normal) => true,
+        }
+    }
+}
+
+/// Runs a [`Command`][c], handling non-zero exit codes and other failures, and treating its
+/// output according to `mode`.
 ///
 /// [c]: std::process::Command
-pub fn run_cmd(mut cmd: std::process::Command) -> Result<(), RunCommandError> {
-    println!("Running command: {cmd:?}");
+pub fn run_cmd(mut cmd: std::process::Command, mode: OutputMode) -> Result<(), RunCommandError> {
+    announce(&cmd);
+
+    if !mode.captures() {
+        let status = cmd.status().map_err(|error| launch_error(&cmd, error))?;
+        if !status.success() {
+            return Err(RunCommandError::CommandFailed {
+                code: status.code(),
+                stderr: None,
+            });
+        }
 
-    let status = cmd.status()?;
-    if !status.success() {
-        return Err(RunCommandError::CommandFailed {
-            code: status.code(),
-        });
+        return Ok(());
     }
 
-    Ok(())
+    cmd.stdout(std::process::Stdio::piped());
+    cmd.stderr(std::process::Stdio::piped());
+    let output = cmd.output().map_err(|error| launch_error(&cmd, error))?;
+    check_output(&output)
+}
+
+/// Runs `cmd`, returning what it wrote to stdout, which is not shown.
+///
+/// Its stderr is treated according to `mode`, and its stdout is decoded lossily if it is not
+/// UTF-8.
+pub fn run_cmd_output(
+    mut cmd: std::process::Command,
+    mode: OutputMode,
+) -> Result<String, RunCommandError> {
+    announce(&cmd);
+
+    cmd.stdout(std::process::Stdio::piped());
+    if mode.captures() {
+        cmd.stderr(std::process::Stdio::piped());
+    } else {
+        cmd.stderr(std::process::Stdio::inherit());
+    }
+    let output = cmd.output().map_err(|error| launch_error(&cmd, error))?;
+    check_output(&output)?;
+
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Returns an error carrying the captured stderr of `output` if its command failed.
+fn check_output(output: &std::process::Output) -> Result<(), RunCommandError> {
+    if output.status.success() {
+        return Ok(());
+    }
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    Err(RunCommandError::CommandFailed {
+        code: output.status.code(),
+        stderr: Some(stderr.trim_end().to_owned()).filter(|stderr| !stderr.is_empty()),
+    })
+}
+
+/// Converts `error`, which occurred while launching `cmd`, into a [`RunCommandError`] that names
+/// the program if it is not installed.
+fn launch_error(cmd: &std::process::Command, error: io::Error) -> RunCommandError {
+    if error.kind() == io::ErrorKind::NotFound {
+        RunCommandError::ToolNotFound(cmd.get_program().to_owned())
+    } else {
+        RunCommandError::ProcessError(error)
+    }
 }
 
 /// Various errors that can occur while running a command.
 #[derive(Debug)]
 pub enum RunCommandError {
+    /// The given program is not installed.
+    ToolNotFound(OsString),
     /// An error occurred while launching the command.
     ProcessError(io::Error),
     /// The command exited with a non-zero exit code.
     CommandFailed {
         /// The exit of code of the command.
         code: Option<i32>,
+        /// What the command wrote to stderr, if it was captured and is not empty.
+        stderr: Option<String>,
     },
 }
 
@@ -376,11 +2272,357 @@ impl From<io::Error> for RunCommandError {
 impl fmt::Display for RunCommandError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
+            Self::ToolNotFound(program) => write!(
+                f,
+                "`{}` was not found; install it or add it to PATH",
+                program.to_string_lossy()
+            ),
             Self::ProcessError(error) => write!(f, "error launching command: {error}"),
-            Self::CommandFailed { code: Some(code) } => {
-                write!(f, "command failed with exit status {code}")
+            Self::CommandFailed { code, stderr } => {
+                match code {
+                    Some(code) => write!(f, "command failed with exit status {code}")?,
+                    None => write!(f, "command terminated by signal")?,
+                }
+                match stderr {
+                    Some(stderr) => write!(f, ":\n{stderr}"),
+                    None => Ok(()),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A directory under the system's temporary directory that is removed when dropped.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        /// Creates an empty directory whose name includes `name`, which must be unique among the
+        /// tests.
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("xtask-{name}-{}", std::process::id()));
+            let _ = std::fs::remove_dir_all(&path);
+            std::fs::create_dir_all(&path).unwrap();
+            Self(path)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// Returns the relative path made of the `/`-separated components of `path`.
+    fn relative(path: &str) -> PathBuf {
+        path.split('/').collect()
+    }
+
+    /// Returns `paths`, sorted.
+    fn sorted(mut paths: Vec<PathBuf>) -> Vec<PathBuf> {
+        paths.sort();
+        paths
+    }
+
+    /// Returns the files in `directory` and its subdirectories, relative to it.
+    fn files(directory: &Path) -> Vec<PathBuf> {
+        let mut files = Vec::new();
+        let mut pending = vec![PathBuf::new()];
+        while let Some(subdirectory) = pending.pop() {
+            for entry in std::fs::read_dir(directory.join(&subdirectory)).unwrap() {
+                let entry = entry.unwrap();
+                let name = subdirectory.join(entry.file_name());
+                if entry.file_type().unwrap().is_dir() {
+                    pending.push(name);
+                } else {
+                    files.push(name);
+                }
             }
-            Self::CommandFailed { code: None } => write!(f, "command terminated by signal"),
         }
+
+        sorted(files)
+    }
+
+    /// Parses `json` as a single JSON document.
+    fn parse_json(json: &str) -> cargo_messages::Value {
+        cargo_messages::Parser::new(json)
+            .parse()
+            .unwrap_or_else(|| panic!("invalid JSON: {json}"))
+    }
+
+    /// Creates a package named `kernel` in `directory`, with a custom `release-lto` profile, and
+    /// returns the path of the package.
+    fn create_kernel_package(directory: &Path) -> PathBuf {
+        let package = directory.join("kernel");
+        std::fs::create_dir_all(package.join("src")).unwrap();
+        std::fs::write(
+            package.join("Cargo.toml"),
+            "[package]\nname = \"kernel\"\nversion = \"0.0.0\"\nedition = \"2021\"\n\n\
+                [workspace]\n\n[profile.release-lto]\ninherits = \"release\"\nlto = true\n",
+        )
+        .unwrap();
+        std::fs::write(package.join("src").join("main.rs"), "fn main() {}\n").unwrap();
+        package
+    }
+
+    /// Builds the kernel package at `package` with `profile`, into `target_directory` if it is
+    /// given, and returns the canonical path of the executable that was reported.
+    fn build_kernel_package(
+        package: &Path,
+        profile: Option<&str>,
+        target_directory: Option<&Path>,
+    ) -> PathBuf {
+        let mut cmd = std::process::Command::new("cargo");
+        cmd.current_dir(package).args(["build", "--offline"]);
+        if let Some(profile) = profile {
+            cmd.args(["--profile", profile]);
+        }
+        match target_directory {
+            Some(target_directory) => cmd.env("CARGO_TARGET_DIR", target_directory),
+            None => cmd.env_remove("CARGO_TARGET_DIR"),
+        };
+
+        let kernel = build_kernel_executable(cmd, OutputMode::Capture).unwrap();
+        assert!(kernel.is_file(), "{} does not exist", kernel.display());
+        kernel.canonicalize().unwrap()
+    }
+
+    /// Returns the canonical path that the kernel executable built with `profile` has in
+    /// `target_directory`.
+    fn kernel_path(target_directory: &Path, profile: Option<&str>) -> PathBuf {
+        target_directory
+            .canonicalize()
+            .unwrap()
+            .join(profile_directory(profile))
+            .join(format!("kernel{}", std::env::consts::EXE_SUFFIX))
+    }
+
+    #[test]
+    fn profile_directories_follow_cargo() {
+        assert_eq!(profile_directory(None), "debug");
+        assert_eq!(profile_directory(Some("dev")), "debug");
+        assert_eq!(profile_directory(Some("test")), "debug");
+        assert_eq!(profile_directory(Some("release")), "release");
+        assert_eq!(profile_directory(Some("bench")), "release");
+        assert_eq!(profile_directory(Some("release-lto")), "release-lto");
+    }
+
+    #[test]
+    fn kernel_executable_defaults_to_the_package_target_directory() {
+        let temp = TempDir::new("target-dir-unset");
+        let package = create_kernel_package(&temp.0);
+
+        for profile in [None, Some("release-lto")] {
+            assert_eq!(
+                build_kernel_package(&package, profile, None),
+                kernel_path(&package.join("target"), profile)
+            );
+        }
+    }
+
+    #[test]
+    fn kernel_executable_follows_cargo_target_dir() {
+        let temp = TempDir::new("target-dir-set");
+        let package = create_kernel_package(&temp.0);
+        let target_directory = temp.0.join("shared target");
+
+        for profile in [None, Some("dev"), Some("release"), Some("release-lto")] {
+            assert_eq!(
+                build_kernel_package(&package, profile, Some(&target_directory)),
+                kernel_path(&target_directory, profile)
+            );
+        }
+        assert!(!package.join("target").exists());
+    }
+
+    #[test]
+    fn json_strings_round_trip() {
+        for string in [
+            "",
+            "kernel",
+            "quote \" and backslash \\",
+            "tab\tnewline\nreturn\r",
+            "\u{1}\u{1f}\u{7f}\u{85}",
+            "café 🦀",
+        ] {
+            let literal = json_string(string);
+            assert_eq!(
+                parse_json(&literal),
+                cargo_messages::Value::String(string.to_owned()),
+                "{literal}"
+            );
+        }
+
+        let error = format!("{{\"error\":{}}}", json_string("cargo failed:\n\"x\""));
+        assert_eq!(
+            parse_json(&error)
+                .get("error")
+                .and_then(cargo_messages::Value::as_str),
+            Some("cargo failed:\n\"x\"")
+        );
+    }
+
+    #[test]
+    fn build_json_round_trips() {
+        let mut features = Features::default();
+        features.insert(Features::LIMINE_BOOT_API);
+        features.insert(Features::SERIAL_LOGGING);
+        let arguments = BuildArguments {
+            arch: Arch::X86_64,
+            profile: Some("release".to_owned()),
+            features,
+        };
+        let kernel = Path::new("/work/target \"x\"\\release/kernel");
+
+        let json = build_json(&arguments, kernel);
+        assert!(!json.contains('\n'));
+        let build = parse_json(&json);
+        let field = |name| build.get(name).and_then(cargo_messages::Value::as_str);
+        assert_eq!(field("kernel"), Some("/work/target \"x\"\\release/kernel"));
+        assert_eq!(field("arch"), Some("x86_64"));
+        assert_eq!(field("profile"), Some("release"));
+        let features: Vec<&str> = build
+            .get("features")
+            .and_then(cargo_messages::Value::as_array)
+            .unwrap()
+            .iter()
+            .map(|feature| feature.as_str().unwrap())
+            .collect();
+        assert_eq!(features, ["limine-boot-api", "serial-logging"]);
+
+        let arguments = BuildArguments {
+            profile: None,
+            features: Features::default(),
+            ..arguments
+        };
+        let build = parse_json(&build_json(&arguments, kernel));
+        assert_eq!(
+            build.get("profile").and_then(cargo_messages::Value::as_str),
+            Some("debug")
+        );
+        for (profile, reported) in [("dev", "debug"), ("bench", "release"), ("size", "size")] {
+            let arguments = BuildArguments {
+                profile: Some(profile.to_owned()),
+                ..arguments.clone()
+            };
+            let build = parse_json(&build_json(&arguments, kernel));
+            assert_eq!(
+                build.get("profile").and_then(cargo_messages::Value::as_str),
+                Some(reported)
+            );
+        }
+        assert_eq!(
+            build
+                .get("features")
+                .and_then(cargo_messages::Value::as_array),
+            Some(&[][..])
+        );
+    }
+
+    #[test]
+    fn fat_directory_sync_removes_stale_files() {
+        let temp = TempDir::new("fat-stale");
+        let loader = temp.0.join("loader.efi");
+        std::fs::write(&loader, b"loader").unwrap();
+
+        let fat = temp.0.join("fat");
+        for stale in [
+            "limine.conf",
+            "EFI/BOOT/old-kernel",
+            "EFI/OLD/loader.efi",
+            "EFI/BOOT/BOOTX64.EFI",
+        ] {
+            let stale = fat.join(relative(stale));
+            std::fs::create_dir_all(stale.parent().unwrap()).unwrap();
+            std::fs::write(stale, b"stale").unwrap();
+        }
+
+        let fat_directory = sync_fat_directory(
+            fat.clone(),
+            vec![
+                (relative("EFI/BOOT/BOOTX64.EFI"), FatSource::File(&loader)),
+                (relative("boot/config"), FatSource::Bytes(b"config")),
+            ],
+        )
+        .unwrap();
+
+        assert_eq!(
+            sorted(fat_directory.removed),
+            [
+                relative("EFI/BOOT/old-kernel"),
+                relative("EFI/OLD"),
+                relative("limine.conf")
+            ]
+        );
+        assert_eq!(
+            sorted(fat_directory.copied),
+            [relative("EFI/BOOT/BOOTX64.EFI"), relative("boot/config")]
+        );
+        assert!(fat_directory.unchanged.is_empty());
+        assert_eq!(
+            files(&fat),
+            [relative("EFI/BOOT/BOOTX64.EFI"), relative("boot/config")]
+        );
+        assert_eq!(
+            std::fs::read(fat.join(relative("EFI/BOOT/BOOTX64.EFI"))).unwrap(),
+            b"loader"
+        );
+    }
+
+    #[test]
+    fn fat_directory_sync_skips_unchanged_files() {
+        let temp = TempDir::new("fat-unchanged");
+        let loader = temp.0.join("loader.efi");
+        std::fs::write(&loader, b"loader").unwrap();
+
+        let fat = temp.0.join("fat");
+        let destination = fat.join("BOOTX64.EFI");
+        let sync = |config: &[u8]| {
+            sync_fat_directory(
+                fat.clone(),
+                vec![
+                    (relative("BOOTX64.EFI"), FatSource::File(&loader)),
+                    (relative("config"), FatSource::Bytes(config)),
+                ],
+            )
+            .unwrap()
+        };
+
+        let first = sync(b"config");
+        assert_eq!(first.copied.len(), 2);
+        assert!(first.unchanged.is_empty());
+
+        let second = sync(b"config");
+        assert!(second.copied.is_empty());
+        assert_eq!(
+            sorted(second.unchanged),
+            [relative("BOOTX64.EFI"), relative("config")]
+        );
+        assert!(second.removed.is_empty());
+
+        // A copy whose size and modification time match its source is trusted without being read.
+        let modified = std::fs::metadata(&loader).unwrap().modified().unwrap();
+        std::fs::write(&destination, b"LOADER").unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(&destination)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+        let third = sync(b"config");
+        assert!(third.copied.is_empty());
+        assert_eq!(std::fs::read(&destination).unwrap(), b"LOADER");
+
+        std::fs::write(&loader, b"new loader").unwrap();
+        let fourth = sync(b"new config");
+        assert_eq!(
+            sorted(fourth.copied),
+            [relative("BOOTX64.EFI"), relative("config")]
+        );
+        assert_eq!(std::fs::read(&destination).unwrap(), b"new loader");
+        assert_eq!(std::fs::read(fat.join("config")).unwrap(), b"new config");
     }
 }
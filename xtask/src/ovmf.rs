@@ -0,0 +1,302 @@
+//! Location of the OVMF firmware used to boot the kernel under UEFI, or its `aarch64` build,
+//! AAVMF.
+//!
+//! Paths given on the command line are used as they are. Otherwise, the firmware is looked for in
+//! the locations distributions install it to, and failing that, a prebuilt copy is downloaded into
+//! `run/<arch>/ovmf/` and reused by later runs.
+
+use std::{
+    fmt, io,
+    path::{Path, PathBuf},
+};
+
+use crate::{cli::Arch, run_cmd, OutputMode, RunCommandError};
+
+/// The release of the prebuilt firmware that is downloaded.
+const PREBUILT_RELEASE: &str = "edk2-stable202408-r1";
+
+/// The locations distributions install the firmware's code and variable template to, as pairs
+/// that were built together.
+const SYSTEM_LOCATIONS_X86_64: &[(&str, &str)] = &[
+    // Debian and Ubuntu.
+    (
+        "/usr/share/OVMF/OVMF_CODE_4M.fd",
+        "/usr/share/OVMF/OVMF_VARS_4M.fd",
+    ),
+    (
+        "/usr/share/OVMF/OVMF_CODE.fd",
+        "/usr/share/OVMF/OVMF_VARS.fd",
+    ),
+    // Arch Linux.
+    (
+        "/usr/share/edk2/x64/OVMF_CODE.4m.fd",
+        "/usr/share/edk2/x64/OVMF_VARS.4m.fd",
+    ),
+    (
+        "/usr/share/edk2/x64/OVMF_CODE.fd",
+        "/usr/share/edk2/x64/OVMF_VARS.fd",
+    ),
+    // Fedora.
+    (
+        "/usr/share/edk2/ovmf/OVMF_CODE.fd",
+        "/usr/share/edk2/ovmf/OVMF_VARS.fd",
+    ),
+    // Gentoo.
+    (
+        "/usr/share/edk2-ovmf/x64/OVMF_CODE.fd",
+        "/usr/share/edk2-ovmf/x64/OVMF_VARS.fd",
+    ),
+    // openSUSE.
+    (
+        "/usr/share/qemu/ovmf-x86_64-code.bin",
+        "/usr/share/qemu/ovmf-x86_64-vars.bin",
+    ),
+    // Homebrew.
+    (
+        "/opt/homebrew/share/qemu/edk2-x86_64-code.fd",
+        "/opt/homebrew/share/qemu/edk2-i386-vars.fd",
+    ),
+    (
+        "/usr/local/share/qemu/edk2-x86_64-code.fd",
+        "/usr/local/share/qemu/edk2-i386-vars.fd",
+    ),
+];
+
+/// The locations distributions install the `aarch64` firmware's code and variable template to, as
+/// pairs that were built together.
+///
+/// QEMU's `virt` machine requires both to be padded to [`AARCH64_FLASH_SIZE`], which these
+/// packages already do.
+const SYSTEM_LOCATIONS_AARCH64: &[(&str, &str)] = &[
+    // Debian and Ubuntu.
+    (
+        "/usr/share/AAVMF/AAVMF_CODE.fd",
+        "/usr/share/AAVMF/AAVMF_VARS.fd",
+    ),
+    // Arch Linux.
+    (
+        "/usr/share/edk2/aarch64/QEMU_CODE.fd",
+        "/usr/share/edk2/aarch64/QEMU_VARS.fd",
+    ),
+    // Fedora.
+    (
+        "/usr/share/edk2/aarch64/QEMU_EFI-pflash.raw",
+        "/usr/share/edk2/aarch64/vars-template-pflash.raw",
+    ),
+    // Homebrew.
+    (
+        "/opt/homebrew/share/qemu/edk2-aarch64-code.fd",
+        "/opt/homebrew/share/qemu/edk2-arm-vars.fd",
+    ),
+    (
+        "/usr/local/share/qemu/edk2-aarch64-code.fd",
+        "/usr/local/share/qemu/edk2-arm-vars.fd",
+    ),
+];
+
+/// The size, in bytes, that QEMU's `aarch64` `virt` machine requires each flash device to be.
+const AARCH64_FLASH_SIZE: u64 = 64 * 1024 * 1024;
+
+/// The firmware QEMU runs the kernel with.
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+pub struct Firmware {
+    /// The path to the firmware's code, which is mapped read-only.
+    pub code: PathBuf,
+    /// The path to the writable copy of the firmware's variables.
+    pub vars: PathBuf,
+}
+
+/// Resolves the OVMF firmware for `arch`, preferring `code` and `vars` when they are given.
+///
+/// The variables are copied to `run/<arch>/ovmf_vars.fd`, which UEFI writes its variables to.
+/// That copy persists across runs, unless `vars` is given, in which case it is replaced. Nothing
+/// is downloaded if `offline` is `true`.
+///
+/// # Errors
+/// Returns [`OvmfError`] if the firmware cannot be found or downloaded, or its variables cannot be
+/// copied.
+pub fn resolve(
+    arch: Arch,
+    code: Option<PathBuf>,
+    vars: Option<PathBuf>,
+    offline: bool,
+) -> Result<Firmware, OvmfError> {
+    let writable_vars = Path::new("run").join(arch.as_str()).join("ovmf_vars.fd");
+
+    let (code, vars_template) = match (code, vars) {
+        (Some(code), Some(vars)) => (code, Some(vars)),
+        (code, vars) => {
+            let (found_code, found_vars) = find(arch, offline)?;
+            let fresh_vars = vars.is_some();
+            let vars = vars.unwrap_or(found_vars);
+
+            // A template found automatically only seeds the copy, so that variables persist.
+            let vars_template = (fresh_vars || !writable_vars.exists()).then_some(vars);
+            (code.unwrap_or(found_code), vars_template)
+        }
+    };
+
+    if let Some(vars_template) = vars_template {
+        copy_vars(&vars_template, &writable_vars).map_err(OvmfError::CopyVars)?;
+    }
+
+    let code = match arch {
+        Arch::X86_64 => code,
+        Arch::Aarch64 => {
+            let padded_code = Path::new("run").join(arch.as_str()).join("aavmf_code.fd");
+            pad_flash(&code, &padded_code, &writable_vars).map_err(OvmfError::PadFlash)?
+        }
+    };
+
+    Ok(Firmware {
+        code,
+        vars: writable_vars,
+    })
+}
+
+/// Returns the paths to the firmware's code and variable template, looking in the system
+/// locations, then in the download cache, and finally downloading it unless `offline` is `true`.
+fn find(arch: Arch, offline: bool) -> Result<(PathBuf, PathBuf), OvmfError> {
+    let system_locations = match arch {
+        Arch::X86_64 => SYSTEM_LOCATIONS_X86_64,
+        Arch::Aarch64 => SYSTEM_LOCATIONS_AARCH64,
+    };
+    if let Some(&(code, vars)) = system_locations
+        .iter()
+        .find(|(code, vars)| Path::new(code).is_file() && Path::new(vars).is_file())
+    {
+        return Ok((PathBuf::from(code), PathBuf::from(vars)));
+    }
+
+    let cache = Path::new("run").join(arch.as_str()).join("ovmf");
+    let release = cache.join(format!("{PREBUILT_RELEASE}-bin"));
+    let arch_directory = release.join(match arch {
+        Arch::X86_64 => "x64",
+        Arch::Aarch64 => "aarch64",
+    });
+    let (code, vars) = (
+        arch_directory.join("code.fd"),
+        arch_directory.join("vars.fd"),
+    );
+    if code.is_file() && vars.is_file() {
+        return Ok((code, vars));
+    }
+
+    if offline {
+        return Err(OvmfError::NotFound);
+    }
+
+    download(&cache)?;
+    if code.is_file() && vars.is_file() {
+        Ok((code, vars))
+    } else {
+        Err(OvmfError::MissingFromArchive)
+    }
+}
+
+/// Downloads and unpacks the prebuilt firmware into `cache`.
+fn download(cache: &Path) -> Result<(), OvmfError> {
+    std::fs::create_dir_all(cache).map_err(OvmfError::CreateCache)?;
+
+    let archive = cache.join(format!("{PREBUILT_RELEASE}-bin.tar.xz"));
+    let mut cmd = std::process::Command::new("curl");
+    cmd.args(["--location", "--fail", "--silent", "--show-error"]);
+    cmd.arg("--output").arg(&archive);
+    cmd.arg(format!(
+        "https://github.com/rust-osdev/ovmf-prebuilt/releases/download/{PREBUILT_RELEASE}/\
+        {PREBUILT_RELEASE}-bin.tar.xz"
+    ));
+    run_cmd(cmd, OutputMode::Capture).map_err(OvmfError::Download)?;
+
+    let mut cmd = std::process::Command::new("tar");
+    cmd.arg("-xJf").arg(&archive);
+    cmd.arg("-C").arg(cache);
+    run_cmd(cmd, OutputMode::Capture).map_err(OvmfError::Unpack)?;
+
+    // The archive is no longer needed once unpacked.
+    let _ = std::fs::remove_file(archive);
+
+    Ok(())
+}
+
+/// Copies the firmware file at `template` to `destination`, making the copy writable.
+fn copy_vars(template: &Path, destination: &Path) -> Result<(), io::Error> {
+    if let Some(parent) = destination.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    std::fs::copy(template, destination)?;
+
+    // Distribution packages install the template read-only, which `copy` preserves.
+    let mut permissions = std::fs::metadata(destination)?.permissions();
+    #[allow(clippy::permissions_set_readonly_false)]
+    permissions.set_readonly(false);
+    std::fs::set_permissions(destination, permissions)
+}
+
+/// Pads the firmware's code at `code` and the writable variables at `vars` to
+/// [`AARCH64_FLASH_SIZE`], returning the path of the code to use.
+///
+/// Code that is too small is copied to `padded_code` first, since it may be read-only.
+fn pad_flash(code: &Path, padded_code: &Path, vars: &Path) -> Result<PathBuf, io::Error> {
+    let vars = std::fs::OpenOptions::new().write(true).open(vars)?;
+    if vars.metadata()?.len() < AARCH64_FLASH_SIZE {
+        vars.set_len(AARCH64_FLASH_SIZE)?;
+    }
+
+    if std::fs::metadata(code)?.len() >= AARCH64_FLASH_SIZE {
+        return Ok(code.to_path_buf());
+    }
+
+    copy_vars(code, padded_code)?;
+    std::fs::OpenOptions::new()
+        .write(true)
+        .open(padded_code)?
+        .set_len(AARCH64_FLASH_SIZE)?;
+    Ok(padded_code.to_path_buf())
+}
+
+/// Various errors that can occur while resolving the OVMF firmware.
+#[derive(Debug)]
+pub enum OvmfError {
+    /// The firmware was not found, and downloading it was disabled.
+    NotFound,
+    /// The downloaded archive did not contain the firmware.
+    MissingFromArchive,
+    /// An error occurred while creating the download cache.
+    CreateCache(io::Error),
+    /// An error occurred while downloading the firmware.
+    Download(RunCommandError),
+    /// An error occurred while unpacking the downloaded firmware.
+    Unpack(RunCommandError),
+    /// An error occurred while copying the firmware's variables to a writable location.
+    CopyVars(io::Error),
+    /// An error occurred while padding the firmware to the size of a flash device.
+    PadFlash(io::Error),
+}
+
+impl fmt::Display for OvmfError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotFound => f.write_str(
+                "OVMF firmware not found in the system locations or the download cache; pass \
+                `--ovmf-code` and `--ovmf-vars`, or drop `--offline` to download it",
+            ),
+            Self::MissingFromArchive => {
+                f.write_str("downloaded OVMF archive does not contain the expected firmware")
+            }
+            Self::CreateCache(error) => {
+                write!(
+                    f,
+                    "error occurred while creating OVMF download cache: {error}"
+                )
+            }
+            Self::Download(error) => write!(f, "error occurred while downloading OVMF: {error}"),
+            Self::Unpack(error) => write!(f, "error occurred while unpacking OVMF: {error}"),
+            Self::CopyVars(error) => {
+                write!(f, "error occurred while copying OVMF variables: {error}")
+            }
+            Self::PadFlash(error) => write!(f, "error occurred while padding AAVMF: {error}"),
+        }
+    }
+}
@@ -0,0 +1,788 @@
+//! Normalization and comparison of the kernel's serial output for `xtask snapshot`.
+//!
+//! Serial output contains data that changes between otherwise identical boots, such as addresses.
+//! Before it is compared against an expected snapshot, every line is rewritten by a list of
+//! [`Substitution`]s, whose patterns use a small subset of regular expression syntax:
+//! - `.` matches any character, and `[...]` or `[^...]` a character class, which may contain
+//!   ranges such as `a-f`.
+//! - `\d`, `\w`, and `\s` match digits, word characters, and whitespace, and `\` followed by any
+//!   other character matches that character.
+//! - `*`, `+`, and `?` repeat the preceding item, matching as much as possible.
+//! - `^` and `$` at the start and end of a pattern anchor it to the start and end of the line.
+//!
+//! Groups and alternation are not supported.
+
+use std::fmt;
+
+/// A pattern that matches text within a single line.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Pattern {
+    /// The items of the pattern, which must match one after another.
+    items: Vec<Item>,
+    /// Whether the pattern only matches at the start of a line.
+    anchored_start: bool,
+    /// Whether the pattern only matches at the end of a line.
+    anchored_end: bool,
+}
+
+impl Pattern {
+    /// Parses `pattern`.
+    ///
+    /// # Errors
+    /// Returns [`PatternError`] if `pattern` is not valid.
+    pub fn parse(pattern: &str) -> Result<Self, PatternError> {
+        let mut chars = pattern.chars().peekable();
+        let anchored_start = chars.next_if_eq(&'^').is_some();
+
+        let mut items = Vec::new();
+        let mut anchored_end = false;
+        while let Some(c) = chars.next() {
+            let atom = match c {
+                '$' if chars.peek().is_none() => {
+                    anchored_end = true;
+                    break;
+                }
+                '.' => Atom::Any,
+                '[' => parse_class(&mut chars)?,
+                '\\' => match chars.next() {
+                    Some(escaped) => escape_atom(escaped),
+                    None => return Err(PatternError::TrailingBackslash),
+                },
+                '*' | '+' | '?' => return Err(PatternError::NothingToRepeat(c)),
+                c => Atom::Char(c),
+            };
+
+            let repeat = match chars.next_if(|c| matches!(c, '*' | '+' | '?')) {
+                Some('*') => Repeat::ZeroOrMore,
+                Some('+') => Repeat::OneOrMore,
+                Some(_) => Repeat::ZeroOrOne,
+                None => Repeat::One,
+            };
+            items.push(Item { atom, repeat });
+        }
+
+        Ok(Self {
+            items,
+            anchored_start,
+            anchored_end,
+        })
+    }
+
+    /// Returns `line` with every non-overlapping, non-empty match of the pattern replaced by
+    /// `replacement`, searching from left to right.
+    pub fn replace_all(&self, line: &str, replacement: &str) -> String {
+        let chars: Vec<char> = line.chars().collect();
+        let mut replaced = String::with_capacity(line.len());
+
+        let mut position = 0;
+        while position < chars.len() {
+            let matched = if self.anchored_start && position != 0 {
+                None
+            } else {
+                match_items(&self.items, &chars, position, self.anchored_end)
+                    .filter(|&end| end > position)
+            };
+
+            match matched {
+                Some(end) => {
+                    replaced.push_str(replacement);
+                    position = end;
+                }
+                None => {
+                    replaced.push(chars[position]);
+                    position += 1;
+                }
+            }
+        }
+
+        replaced
+    }
+}
+
+/// Parses the rest of a character class, after its opening `[`.
+fn parse_class(chars: &mut std::iter::Peekable<std::str::Chars<'_>>) -> Result<Atom, PatternError> {
+    let negated = chars.next_if_eq(&'^').is_some();
+
+    let mut ranges = Vec::new();
+    loop {
+        let start = match chars.next() {
+            Some(']') if !ranges.is_empty() => break,
+            Some('\\') => match chars.next() {
+                Some(escaped) => match escape_atom(escaped) {
+                    Atom::Class {
+                        ranges: escaped, ..
+                    } => {
+                        ranges.extend(escaped);
+                        continue;
+                    }
+                    _ => escaped,
+                },
+                None => return Err(PatternError::TrailingBackslash),
+            },
+            Some(c) => c,
+            None => return Err(PatternError::UnclosedClass),
+        };
+
+        // A `-` right before the closing `]` is taken literally.
+        let end = if chars.peek() == Some(&'-') {
+            chars.next();
+            match chars.next() {
+                Some(']') => {
+                    ranges.push((start, start));
+                    ranges.push(('-', '-'));
+                    break;
+                }
+                Some('\\') => chars.next().ok_or(PatternError::TrailingBackslash)?,
+                Some(end) => end,
+                None => return Err(PatternError::UnclosedClass),
+            }
+        } else {
+            start
+        };
+
+        if end < start {
+            return Err(PatternError::InvalidRange(start, end));
+        }
+        ranges.push((start, end));
+    }
+
+    Ok(Atom::Class { negated, ranges })
+}
+
+/// Returns the atom that `\` followed by `c` stands for.
+fn escape_atom(c: char) -> Atom {
+    let ranges = match c {
+        'd' => vec![('0', '9')],
+        'w' => vec![('a', 'z'), ('A', 'Z'), ('0', '9'), ('_', '_')],
+        's' => vec![(' ', ' '), ('\t', '\t'), ('\n', '\n'), ('\r', '\r')],
+        c => return Atom::Char(c),
+    };
+
+    Atom::Class {
+        negated: false,
+        ranges,
+    }
+}
+
+/// Returns the position at which `items` stop matching if they match `text` starting at
+/// `position`, preferring the longest repetitions, or [`None`] if they do not match.
+fn match_items(
+    items: &[Item],
+    text: &[char],
+    position: usize,
+    anchored_end: bool,
+) -> Option<usize> {
+    let Some((item, rest)) = items.split_first() else {
+        return (!anchored_end || position == text.len()).then_some(position);
+    };
+
+    let (min, max) = match item.repeat {
+        Repeat::One => (1, 1),
+        Repeat::ZeroOrOne => (0, 1),
+        Repeat::ZeroOrMore => (0, usize::MAX),
+        Repeat::OneOrMore => (1, usize::MAX),
+    };
+    let available = text[position..]
+        .iter()
+        .take(max)
+        .take_while(|&&c| item.atom.matches(c))
+        .count();
+    if available < min {
+        return None;
+    }
+
+    (min..=available)
+        .rev()
+        .find_map(|taken| match_items(rest, text, position + taken, anchored_end))
+}
+
+/// An element of a [`Pattern`] and how often it repeats.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Item {
+    /// What a single repetition matches.
+    atom: Atom,
+    /// How often the atom repeats.
+    repeat: Repeat,
+}
+
+/// Something that matches a single character.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Atom {
+    /// Matches the given character.
+    Char(char),
+    /// Matches any character.
+    Any,
+    /// Matches any character in one of `ranges`, or any character outside of them if `negated`.
+    Class {
+        /// Whether the class matches the characters outside of `ranges` instead.
+        negated: bool,
+        /// Inclusive ranges of characters.
+        ranges: Vec<(char, char)>,
+    },
+}
+
+impl Atom {
+    /// Returns `true` if the atom matches `c`.
+    fn matches(&self, c: char) -> bool {
+        match self {
+            Self::Char(expected) => c == *expected,
+            Self::Any => true,
+            Self::Class { negated, ranges } => {
+                ranges
+                    .iter()
+                    .any(|&(start, end)| (start..=end).contains(&c))
+                    != *negated
+            }
+        }
+    }
+}
+
+/// How often an [`Atom`] repeats.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Repeat {
+    /// Exactly once.
+    One,
+    /// Zero times or once, from `?`.
+    ZeroOrOne,
+    /// Any number of times, from `*`.
+    ZeroOrMore,
+    /// At least once, from `+`.
+    OneOrMore,
+}
+
+/// Various errors that can occur while parsing a [`Pattern`].
+#[derive(Debug, PartialEq, Eq)]
+pub enum PatternError {
+    /// The pattern ends with a `\` that escapes nothing.
+    TrailingBackslash,
+    /// A `[` is never closed.
+    UnclosedClass,
+    /// A range in a character class ends before it starts.
+    InvalidRange(char, char),
+    /// A repetition operator follows nothing that can be repeated.
+    NothingToRepeat(char),
+}
+
+impl fmt::Display for PatternError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TrailingBackslash => f.write_str("pattern ends with an unescaped `\\`"),
+            Self::UnclosedClass => f.write_str("character class is missing its closing `]`"),
+            Self::InvalidRange(start, end) => {
+                write!(f, "character range `{start}-{end}` ends before it starts")
+            }
+            Self::NothingToRepeat(operator) => write!(f, "`{operator}` follows nothing to repeat"),
+        }
+    }
+}
+
+/// A rewrite of the parts of a line that match a [`Pattern`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Substitution {
+    /// The pattern whose matches are replaced.
+    pub pattern: Pattern,
+    /// The text that each match is replaced with.
+    pub replacement: String,
+}
+
+/// Parses a list of substitutions, one per line in the form `<pattern> => <replacement>`.
+///
+/// Blank lines and lines starting with `#` are ignored.
+///
+/// # Errors
+/// Returns [`SubstitutionError`] naming the first line that is not a valid substitution.
+pub fn parse_substitutions(text: &str) -> Result<Vec<Substitution>, SubstitutionError> {
+    let mut substitutions = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let error = |kind| SubstitutionError {
+            line: index + 1,
+            kind,
+        };
+        let (pattern, replacement) = line
+            .split_once(" => ")
+            .ok_or_else(|| error(SubstitutionErrorKind::MissingArrow))?;
+        let pattern = Pattern::parse(pattern.trim_end())
+            .map_err(|pattern_error| error(SubstitutionErrorKind::Pattern(pattern_error)))?;
+
+        substitutions.push(Substitution {
+            pattern,
+            replacement: replacement.trim_start().to_owned(),
+        });
+    }
+
+    Ok(substitutions)
+}
+
+/// An invalid line in a list of substitutions.
+#[derive(Debug, PartialEq, Eq)]
+pub struct SubstitutionError {
+    /// The number of the line, starting from one.
+    pub line: usize,
+    /// What is wrong with the line.
+    pub kind: SubstitutionErrorKind,
+}
+
+/// What is wrong with a line in a list of substitutions.
+#[derive(Debug, PartialEq, Eq)]
+pub enum SubstitutionErrorKind {
+    /// The line does not separate the pattern from the replacement with ` => `.
+    MissingArrow,
+    /// The pattern is not valid.
+    Pattern(PatternError),
+}
+
+impl fmt::Display for SubstitutionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            SubstitutionErrorKind::MissingArrow => write!(
+                f,
+                "line {}: expected `<pattern> => <replacement>`",
+                self.line
+            ),
+            SubstitutionErrorKind::Pattern(error) => write!(f, "line {}: {error}", self.line),
+        }
+    }
+}
+
+/// Returns `text` with every line rewritten by each of `substitutions` in turn.
+///
+/// Line endings are normalized to `\n`, and the result ends with one unless it is empty.
+pub fn normalize(text: &str, substitutions: &[Substitution]) -> String {
+    let mut normalized = String::with_capacity(text.len());
+    for line in text.lines() {
+        let mut line = line.to_owned();
+        for substitution in substitutions {
+            line = substitution
+                .pattern
+                .replace_all(&line, &substitution.replacement);
+        }
+
+        normalized.push_str(&line);
+        normalized.push('\n');
+    }
+
+    normalized
+}
+
+/// The number of unchanged lines shown around each change by [`unified_diff()`].
+const DIFF_CONTEXT: usize = 3;
+
+/// Returns the lines that turn `expected` into `actual` as a unified diff, labelling the two sides
+/// with `expected_name` and `actual_name`, or [`None`] if they are equal.
+pub fn unified_diff(
+    expected: &str,
+    actual: &str,
+    expected_name: &str,
+    actual_name: &str,
+) -> Option<String> {
+    if expected == actual {
+        return None;
+    }
+
+    let old: Vec<&str> = expected.lines().collect();
+    let new: Vec<&str> = actual.lines().collect();
+    let edits = diff_lines(&old, &new);
+
+    let changes: Vec<usize> = edits
+        .iter()
+        .enumerate()
+        .filter(|(_, edit)| !matches!(edit, Edit::Keep))
+        .map(|(index, _)| index)
+        .collect();
+
+    // Changes whose context would touch or overlap share a hunk.
+    let mut hunks: Vec<(usize, usize)> = Vec::new();
+    for &change in &changes {
+        let start = change.saturating_sub(DIFF_CONTEXT);
+        let end = (change + DIFF_CONTEXT + 1).min(edits.len());
+        match hunks.last_mut() {
+            Some((_, last_end)) if start <= *last_end => *last_end = end,
+            _ => hunks.push((start, end)),
+        }
+    }
+
+    let mut diff = format!("--- {expected_name}\n+++ {actual_name}\n");
+    // When the contents only differ in their final line ending, every line is kept.
+    if hunks.is_empty() {
+        diff.push_str("(the files differ only in their final line ending)\n");
+        return Some(diff);
+    }
+
+    let (mut old_line, mut new_line) = (0, 0);
+    let mut position = 0;
+    for (start, end) in hunks {
+        for edit in &edits[position..start] {
+            old_line += usize::from(!matches!(edit, Edit::Insert));
+            new_line += usize::from(!matches!(edit, Edit::Remove));
+        }
+        position = end;
+
+        let hunk = &edits[start..end];
+        let old_count = hunk
+            .iter()
+            .filter(|edit| !matches!(edit, Edit::Insert))
+            .count();
+        let new_count = hunk
+            .iter()
+            .filter(|edit| !matches!(edit, Edit::Remove))
+            .count();
+        // An empty range is numbered by the line before it.
+        let old_start = if old_count == 0 {
+            old_line
+        } else {
+            old_line + 1
+        };
+        let new_start = if new_count == 0 {
+            new_line
+        } else {
+            new_line + 1
+        };
+        diff.push_str(&format!(
+            "@@ -{old_start},{old_count} +{new_start},{new_count} @@\n"
+        ));
+
+        for edit in hunk {
+            match edit {
+                Edit::Keep => {
+                    diff.push_str(&format!(" {}\n", old[old_line]));
+                    old_line += 1;
+                    new_line += 1;
+                }
+                Edit::Remove => {
+                    diff.push_str(&format!("-{}\n", old[old_line]));
+                    old_line += 1;
+                }
+                Edit::Insert => {
+                    diff.push_str(&format!("+{}\n", new[new_line]));
+                    new_line += 1;
+                }
+            }
+        }
+    }
+
+    Some(diff)
+}
+
+/// A step in turning one list of lines into another.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Edit {
+    /// The next line of both lists is the same.
+    Keep,
+    /// The next line of the old list is removed.
+    Remove,
+    /// The next line of the new list is inserted.
+    Insert,
+}
+
+/// Returns the shortest list of [`Edit`]s that turns `old` into `new`, based on their longest
+/// common subsequence.
+fn diff_lines(old: &[&str], new: &[&str]) -> Vec<Edit> {
+    // `common[i][j]` is the length of the longest common subsequence of `old[i..]` and `new[j..]`.
+    let mut common = vec![vec![0u32; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            common[i][j] = if old[i] == new[j] {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+
+    let mut edits = Vec::with_capacity(old.len().max(new.len()));
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            edits.push(Edit::Keep);
+            i += 1;
+            j += 1;
+        } else if i < old.len() && (j == new.len() || common[i + 1][j] >= common[i][j + 1]) {
+            // Removals come first, so that a changed line reads as `-old` then `+new`.
+            edits.push(Edit::Remove);
+            i += 1;
+        } else {
+            edits.push(Edit::Insert);
+            j += 1;
+        }
+    }
+
+    edits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns an [`Item`] that matches `atom` with the given `repeat`.
+    fn item(atom: Atom, repeat: Repeat) -> Item {
+        Item { atom, repeat }
+    }
+
+    /// Returns `line` with every match of `pattern` replaced by `<>`.
+    fn replace(pattern: &str, line: &str) -> String {
+        Pattern::parse(pattern).unwrap().replace_all(line, "<>")
+    }
+
+    #[test]
+    fn parses_atoms_and_repetitions() {
+        assert_eq!(
+            Pattern::parse("^a.b?$"),
+            Ok(Pattern {
+                items: vec![
+                    item(Atom::Char('a'), Repeat::One),
+                    item(Atom::Any, Repeat::One),
+                    item(Atom::Char('b'), Repeat::ZeroOrOne),
+                ],
+                anchored_start: true,
+                anchored_end: true,
+            })
+        );
+        assert_eq!(
+            Pattern::parse(r"\d+[^a-f_]*\.$x").unwrap().items,
+            [
+                item(
+                    Atom::Class {
+                        negated: false,
+                        ranges: vec![('0', '9')]
+                    },
+                    Repeat::OneOrMore
+                ),
+                item(
+                    Atom::Class {
+                        negated: true,
+                        ranges: vec![('a', 'f'), ('_', '_')]
+                    },
+                    Repeat::ZeroOrMore
+                ),
+                item(Atom::Char('.'), Repeat::One),
+                // `$` is only an anchor at the end of the pattern.
+                item(Atom::Char('$'), Repeat::One),
+                item(Atom::Char('x'), Repeat::One),
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_character_classes() {
+        let ranges = |pattern: &str| match Pattern::parse(pattern).unwrap().items.as_slice() {
+            [Item {
+                atom: Atom::Class { ranges, .. },
+                ..
+            }] => ranges.clone(),
+            items => panic!("{pattern} parsed to {items:?}"),
+        };
+
+        assert_eq!(ranges(r"[\d_]"), [('0', '9'), ('_', '_')]);
+        assert_eq!(ranges("[a-]"), [('a', 'a'), ('-', '-')]);
+        assert_eq!(ranges("[]a]"), [(']', ']'), ('a', 'a')]);
+        assert_eq!(ranges(r"[\]-\^]"), [(']', '^')]);
+        assert_eq!(ranges("[0-9a-fA-F]"), [('0', '9'), ('a', 'f'), ('A', 'F')]);
+    }
+
+    #[test]
+    fn rejects_invalid_patterns() {
+        assert_eq!(Pattern::parse("a\\"), Err(PatternError::TrailingBackslash));
+        assert_eq!(Pattern::parse("[a\\"), Err(PatternError::TrailingBackslash));
+        assert_eq!(Pattern::parse("[a"), Err(PatternError::UnclosedClass));
+        assert_eq!(Pattern::parse("[a-"), Err(PatternError::UnclosedClass));
+        assert_eq!(
+            Pattern::parse("[z-a]"),
+            Err(PatternError::InvalidRange('z', 'a'))
+        );
+        assert_eq!(
+            Pattern::parse("*a"),
+            Err(PatternError::NothingToRepeat('*'))
+        );
+        assert_eq!(
+            Pattern::parse("^+"),
+            Err(PatternError::NothingToRepeat('+'))
+        );
+        assert_eq!(
+            Pattern::parse("a*?"),
+            Err(PatternError::NothingToRepeat('?'))
+        );
+
+        assert_eq!(
+            PatternError::InvalidRange('z', 'a').to_string(),
+            "character range `z-a` ends before it starts"
+        );
+    }
+
+    #[test]
+    fn repetitions_match_greedily_and_backtrack() {
+        assert_eq!(replace("a.*b", "xaxbxbx"), "x<>x");
+        assert_eq!(replace(r"\d+", "12 ab 345"), "<> ab <>");
+        assert_eq!(replace("colou?r", "color colour colouur"), "<> <> colouur");
+        assert_eq!(replace(r"\w+=\d*;", "a=; b=12; =3;"), "<> <> =3;");
+        assert_eq!(replace("[^0-9]+", "ab12cd"), "<>12<>");
+        assert_eq!(replace(".", "é🦀"), "<><>");
+        assert_eq!(replace(r"\.\*", "a.*b"), "a<>b");
+    }
+
+    #[test]
+    fn anchors_match_at_the_ends_of_the_line() {
+        assert_eq!(replace(r"^\s+", "  a  "), "<>a  ");
+        assert_eq!(replace(r"\s+$", "  a  "), "  a<>");
+        assert_eq!(replace("^a$", "a"), "<>");
+        assert_eq!(replace("^a$", "aa"), "aa");
+        assert_eq!(replace("^a", "aaa"), "<>aa");
+    }
+
+    #[test]
+    fn replacements_are_non_empty_and_non_overlapping() {
+        assert_eq!(replace("x*", "abc"), "abc");
+        assert_eq!(replace("x*", "axxb"), "a<>b");
+        assert_eq!(replace("aa", "aaa"), "<>a");
+        assert_eq!(replace("a", ""), "");
+        assert_eq!(
+            replace("0x[0-9a-fA-F]+", "at 0xFFFF_8000 and 0x12"),
+            "at <>_8000 and <>"
+        );
+    }
+
+    #[test]
+    fn parses_substitution_lists() {
+        let substitutions = parse_substitutions(
+            "# comment\n\n  0x[0-9a-f]+   =>   0x<hex>  \nticks \\d+ => ticks <n> of <m>\n",
+        )
+        .unwrap();
+        assert_eq!(substitutions.len(), 2);
+        assert_eq!(
+            substitutions[0].pattern,
+            Pattern::parse("0x[0-9a-f]+").unwrap()
+        );
+        assert_eq!(substitutions[0].replacement, "0x<hex>");
+        assert_eq!(substitutions[1].replacement, "ticks <n> of <m>");
+
+        let error = parse_substitutions("# comment\na => b\nno arrow\n").unwrap_err();
+        assert_eq!(error.line, 3);
+        assert_eq!(error.kind, SubstitutionErrorKind::MissingArrow);
+        assert_eq!(
+            error.to_string(),
+            "line 3: expected `<pattern> => <replacement>`"
+        );
+
+        let error = parse_substitutions("[a => b").unwrap_err();
+        assert_eq!(
+            error.kind,
+            SubstitutionErrorKind::Pattern(PatternError::UnclosedClass)
+        );
+        assert_eq!(
+            error.to_string(),
+            "line 1: character class is missing its closing `]`"
+        );
+    }
+
+    #[test]
+    fn checked_in_substitutions_are_valid() {
+        let substitutions =
+            parse_substitutions(include_str!("../../tests/snapshots/normalize.txt")).unwrap();
+        assert_eq!(
+            normalize(
+                "[INFO] I/O APIC 0 at 0xfec00000, GSI base 0\n",
+                &substitutions
+            ),
+            "[INFO] I/O APIC 0 at 0x<hex>, GSI base 0\n"
+        );
+        assert_eq!(
+            normalize(
+                "[INFO] Local APIC timer ticking at 100 Hz (initial count 62500)\n",
+                &substitutions
+            ),
+            "[INFO] Local APIC timer ticking at 100 Hz (initial count <count>)\n"
+        );
+    }
+
+    #[test]
+    fn normalizes_every_line() {
+        let substitutions = parse_substitutions("\\d+ => N\nN N => N").unwrap();
+        assert_eq!(normalize("a 1 2\r\nb 3", &substitutions), "a N\nb N\n");
+        assert_eq!(normalize("", &substitutions), "");
+        assert_eq!(normalize("\n\n", &[]), "\n\n");
+    }
+
+    #[test]
+    fn diffs_lines_by_longest_common_subsequence() {
+        assert_eq!(
+            diff_lines(&["a", "b", "c"], &["a", "x", "c"]),
+            [Edit::Keep, Edit::Remove, Edit::Insert, Edit::Keep]
+        );
+        assert_eq!(
+            diff_lines(&["a", "b"], &["b", "c"]),
+            [Edit::Remove, Edit::Keep, Edit::Insert]
+        );
+        assert_eq!(diff_lines(&[], &["a"]), [Edit::Insert]);
+        assert_eq!(diff_lines(&["a"], &[]), [Edit::Remove]);
+    }
+
+    /// Returns the lines `1` to `count`, each followed by a newline, with the lines numbered in
+    /// `changed` replaced by their number followed by `!`.
+    fn numbered_lines(count: usize, changed: &[usize]) -> String {
+        (1..=count)
+            .map(|line| match changed.contains(&line) {
+                true => format!("{line}!\n"),
+                false => format!("{line}\n"),
+            })
+            .collect()
+    }
+
+    /// Returns the hunk headers of `diff`.
+    fn hunk_headers(diff: &str) -> Vec<&str> {
+        diff.lines().filter(|line| line.starts_with("@@")).collect()
+    }
+
+    #[test]
+    fn unified_diff_shows_context_around_changes() {
+        let expected = numbered_lines(10, &[]);
+        assert_eq!(unified_diff(&expected, &expected, "old", "new"), None);
+
+        assert_eq!(
+            unified_diff(&expected, &numbered_lines(10, &[5]), "old", "new").unwrap(),
+            "--- old\n+++ new\n@@ -2,7 +2,7 @@\n 2\n 3\n 4\n-5\n+5!\n 6\n 7\n 8\n"
+        );
+    }
+
+    #[test]
+    fn unified_diff_splits_and_merges_hunks() {
+        let expected = numbered_lines(20, &[]);
+
+        let distant = unified_diff(&expected, &numbered_lines(20, &[2, 18]), "old", "new");
+        assert_eq!(
+            hunk_headers(&distant.unwrap()),
+            ["@@ -1,5 +1,5 @@", "@@ -15,6 +15,6 @@"]
+        );
+
+        // Changes whose context would overlap share a hunk.
+        let close = unified_diff(&expected, &numbered_lines(20, &[5, 11]), "old", "new");
+        assert_eq!(hunk_headers(&close.unwrap()), ["@@ -2,13 +2,13 @@"]);
+    }
+
+    #[test]
+    fn unified_diff_numbers_empty_ranges_by_the_line_before() {
+        assert_eq!(
+            unified_diff("", "a\n", "old", "new").unwrap(),
+            "--- old\n+++ new\n@@ -0,0 +1,1 @@\n+a\n"
+        );
+        assert_eq!(
+            unified_diff("a\nb\n", "", "old", "new").unwrap(),
+            "--- old\n+++ new\n@@ -1,2 +0,0 @@\n-a\n-b\n"
+        );
+        assert_eq!(
+            hunk_headers(&unified_diff("a\nb\n", "a\nb\nc\n", "old", "new").unwrap()),
+            ["@@ -1,2 +1,3 @@"]
+        );
+    }
+
+    #[test]
+    fn unified_diff_reports_a_missing_final_newline() {
+        assert_eq!(
+            unified_diff("a\nb", "a\nb\n", "old", "new").unwrap(),
+            "--- old\n+++ new\n(the files differ only in their final line ending)\n"
+        );
+    }
+}
@@ -0,0 +1,292 @@
+//! Embedding of the kernel's symbol table.
+//!
+//! The kernel reserves a `.symbols` section, which is zero when it is linked. Once it has been
+//! built, the function symbols of the ELF image are demangled, sorted, encoded in the format of
+//! the kernel's `symbols::format` module, and written over that section in place.
+
+use std::{fmt, io, path::Path};
+
+use crate::demangle::demangle;
+
+// The decoding half is only used to measure an embedded table.
+#[allow(dead_code)]
+#[path = "../../kernel/src/symbols/format.rs"]
+mod format;
+
+use format::{encode, EncodeError, SymbolInput, SymbolTable};
+
+/// The name of the section the symbol table is written into.
+const SYMBOLS_SECTION: &str = ".symbols";
+/// The name of the section holding the build ID note.
+const BUILD_ID_SECTION: &str = ".note.gnu.build-id";
+
+/// The section type of a symbol table.
+const SHT_SYMTAB: u32 = 2;
+/// The symbol type of a function.
+const STT_FUNC: u8 = 2;
+/// The size, in bytes, of a section header.
+const SECTION_HEADER_SIZE: usize = 64;
+/// The size, in bytes, of a symbol table entry.
+const SYMBOL_SIZE: usize = 24;
+/// The type of an ELF note holding the build ID.
+const NT_GNU_BUILD_ID: u32 = 3;
+
+/// Writes the symbol table of the kernel image at `path` into its `.symbols` section.
+///
+/// # Errors
+/// Returns [`EmbedError`] if the image cannot be read or written, is not a suitable ELF file, or
+/// its symbols do not fit in the section. The image is left unchanged in that case.
+pub fn embed(path: &Path) -> Result<SectionCost, EmbedError> {
+    let mut image = std::fs::read(path).map_err(EmbedError::Io)?;
+    let elf = Elf::parse(&image).ok_or(EmbedError::NotElf)?;
+
+    let symbols_section = elf
+        .section(SYMBOLS_SECTION)
+        .ok_or(EmbedError::MissingSection(SYMBOLS_SECTION))?;
+    let build_id = elf
+        .section(BUILD_ID_SECTION)
+        .and_then(|section| parse_build_id_note(elf.contents(&section)?))
+        .ok_or(EmbedError::MissingSection(BUILD_ID_SECTION))?;
+
+    let names = elf
+        .function_symbols()
+        .ok_or(EmbedError::MissingSection(".symtab"))?
+        .into_iter()
+        .map(|(address, size, name)| (address, size, demangle(name)))
+        .collect::<Vec<_>>();
+    let mut symbols = names
+        .iter()
+        .map(|(address, size, name)| SymbolInput {
+            address: *address,
+            size: u32::try_from(*size).unwrap_or(u32::MAX),
+            name,
+        })
+        .collect::<Vec<_>>();
+    symbols.sort_by(|a, b| a.address.cmp(&b.address).then(a.name.cmp(b.name)));
+    symbols.dedup_by_key(|symbol| symbol.address);
+
+    let mut table = vec![0; symbols_section.size as usize];
+    let used = encode(&mut table, symbols_section.address, build_id, &symbols)
+        .map_err(EmbedError::Encode)?;
+
+    let start = symbols_section.offset as usize;
+    image[start..start + table.len()].copy_from_slice(&table);
+    std::fs::write(path, image).map_err(EmbedError::Io)?;
+
+    Ok(SectionCost {
+        capacity: symbols_section.size,
+        used: used as u64,
+        symbols: symbols.len(),
+    })
+}
+
+/// Returns the cost of the `.symbols` section of the kernel image `image`, or [`None`] if it has
+/// no such section.
+pub fn measure(image: &[u8]) -> Option<SectionCost> {
+    let elf = Elf::parse(image)?;
+    let section = elf.section(SYMBOLS_SECTION)?;
+    let table = elf
+        .contents(&section)
+        .and_then(|bytes| SymbolTable::parse(bytes).ok());
+
+    Some(SectionCost {
+        capacity: section.size,
+        used: table.map_or(0, |table| table.encoded_len() as u64),
+        symbols: table.map_or(0, |table| table.len()),
+    })
+}
+
+/// The space taken by the symbol table in a kernel image.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub struct SectionCost {
+    /// The size of the `.symbols` section, in bytes.
+    pub capacity: u64,
+    /// The number of bytes of the section used by the table.
+    pub used: u64,
+    /// The number of symbols in the table.
+    pub symbols: usize,
+}
+
+impl fmt::Display for SectionCost {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} symbols in {} of {} bytes",
+            self.symbols, self.used, self.capacity
+        )
+    }
+}
+
+/// Various errors that can occur while embedding the symbol table.
+#[derive(Debug)]
+pub enum EmbedError {
+    /// An error occurred while reading or writing the kernel image.
+    Io(io::Error),
+    /// The kernel image is not a 64-bit little-endian ELF file.
+    NotElf,
+    /// The kernel image lacks the given section.
+    MissingSection(&'static str),
+    /// The symbol table could not be encoded.
+    Encode(EncodeError),
+}
+
+impl fmt::Display for EmbedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(error) => write!(f, "error accessing kernel image: {error}"),
+            Self::NotElf => f.write_str("kernel image is not a 64-bit little-endian ELF file"),
+            Self::MissingSection(name) => write!(f, "kernel image has no `{name}` section"),
+            Self::Encode(EncodeError::TooSmall { required }) => write!(
+                f,
+                "symbol table needs {required} bytes, more than the `{SYMBOLS_SECTION}` section \
+                holds; raise `symbols::CAPACITY`"
+            ),
+            Self::Encode(error) => write!(f, "error encoding symbol table: {error}"),
+        }
+    }
+}
+
+/// A section of an [`Elf`] file.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+struct Section {
+    /// The type of the section.
+    kind: u32,
+    /// The link address of the section.
+    address: u64,
+    /// The offset of the section in the file.
+    offset: u64,
+    /// The size of the section, in bytes.
+    size: u64,
+    /// The index of the section associated with this one.
+    link: u32,
+}
+
+/// A 64-bit little-endian ELF file.
+struct Elf<'a> {
+    /// The contents of the file.
+    bytes: &'a [u8],
+    /// The section headers.
+    sections: Vec<Section>,
+    /// The offsets of the names of the sections in `names`.
+    section_names: Vec<u32>,
+    /// The section name string table.
+    names: &'a [u8],
+}
+
+impl<'a> Elf<'a> {
+    /// Parses the section headers of the ELF file `bytes`.
+    fn parse(bytes: &'a [u8]) -> Option<Self> {
+        if bytes.get(..6)? != b"\x7fELF\x02\x01" {
+            return None;
+        }
+
+        let section_offset = read_u64(bytes, 0x28)? as usize;
+        let section_count = usize::from(read_u16(bytes, 0x3C)?);
+        let names_index = usize::from(read_u16(bytes, 0x3E)?);
+
+        let mut sections = Vec::with_capacity(section_count);
+        let mut section_names = Vec::with_capacity(section_count);
+        for index in 0..section_count {
+            let header = section_offset + index * SECTION_HEADER_SIZE;
+            section_names.push(read_u32(bytes, header)?);
+            sections.push(Section {
+                kind: read_u32(bytes, header + 0x04)?,
+                address: read_u64(bytes, header + 0x10)?,
+                offset: read_u64(bytes, header + 0x18)?,
+                size: read_u64(bytes, header + 0x20)?,
+                link: read_u32(bytes, header + 0x28)?,
+            });
+        }
+
+        let mut elf = Self {
+            bytes,
+            sections,
+            section_names,
+            names: &[],
+        };
+        elf.names = elf.contents(elf.sections.get(names_index)?)?;
+
+        Some(elf)
+    }
+
+    /// Returns the section named `name`.
+    fn section(&self, name: &str) -> Option<Section> {
+        self.section_names
+            .iter()
+            .position(|&offset| read_str(self.names, offset as usize) == Some(name))
+            .map(|index| self.sections[index])
+    }
+
+    /// Returns the contents of `section`.
+    fn contents(&self, section: &Section) -> Option<&'a [u8]> {
+        let start = usize::try_from(section.offset).ok()?;
+        let end = start.checked_add(usize::try_from(section.size).ok()?)?;
+        self.bytes.get(start..end)
+    }
+
+    /// Returns the address, size, and name of every defined function in the symbol table.
+    fn function_symbols(&self) -> Option<Vec<(u64, u64, &'a str)>> {
+        let symtab = self
+            .sections
+            .iter()
+            .find(|section| section.kind == SHT_SYMTAB)?;
+        let symbols = self.contents(symtab)?;
+        let strings = self.contents(self.sections.get(symtab.link as usize)?)?;
+
+        Some(
+            symbols
+                .chunks_exact(SYMBOL_SIZE)
+                .filter(|symbol| symbol[4] & 0xF == STT_FUNC)
+                .filter_map(|symbol| {
+                    let section_index = read_u16(symbol, 6)?;
+                    if section_index == 0 {
+                        return None;
+                    }
+
+                    let name = read_str(strings, read_u32(symbol, 0)? as usize)?;
+                    Some((read_u64(symbol, 8)?, read_u64(symbol, 16)?, name))
+                })
+                .collect(),
+        )
+    }
+}
+
+/// Returns the descriptor of `note` if it is a GNU build ID note.
+fn parse_build_id_note(note: &[u8]) -> Option<&[u8]> {
+    let name_size = read_u32(note, 0)? as usize;
+    let descriptor_size = read_u32(note, 4)? as usize;
+    if read_u32(note, 8)? != NT_GNU_BUILD_ID || note.get(12..12 + name_size)? != b"GNU\0" {
+        return None;
+    }
+
+    let descriptor_start = 12 + name_size.next_multiple_of(4);
+    note.get(descriptor_start..descriptor_start + descriptor_size)
+}
+
+/// Returns the NUL-terminated string at `offset` in `bytes`.
+fn read_str(bytes: &[u8], offset: usize) -> Option<&str> {
+    let bytes = bytes.get(offset..)?;
+    let len = bytes.iter().position(|&byte| byte == 0)?;
+    std::str::from_utf8(&bytes[..len]).ok()
+}
+
+/// Returns the little-endian `u16` at `offset` in `bytes`.
+fn read_u16(bytes: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(
+        *bytes.get(offset..offset + 2)?.first_chunk()?,
+    ))
+}
+
+/// Returns the little-endian `u32` at `offset` in `bytes`.
+fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        *bytes.get(offset..offset + 4)?.first_chunk()?,
+    ))
+}
+
+/// Returns the little-endian `u64` at `offset` in `bytes`.
+fn read_u64(bytes: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(
+        *bytes.get(offset..offset + 8)?.first_chunk()?,
+    ))
+}